@@ -3,8 +3,11 @@ use std::env;
 
 #[cfg(feature = "production")]
 pub const OWNER_KEY: &str = env!("PROTOCOL_OWNER_FEE_ADDRESS");
-#[cfg(not(feature = "production"))]
+#[cfg(all(not(feature = "production"), not(test)))]
 pub const OWNER_KEY: &str = "change me";
+/// `[3u8; 32]`, a valid key so tests can own fee token accounts
+#[cfg(all(not(feature = "production"), test))]
+pub const OWNER_KEY: &str = "CktRuQ2mttgRGkXJtyksdKHjUdc2C4TgDzyB98oEzy8";
 
 // pub const BASE_SEED: [u8; 32] = [
 //   49, 97, 50, 98, 51, 99, 52, 100, 111, 110, 101, 115, 111, 108, 95, 97, 117, 116, 104, 111, 114,
@@ -6,11 +6,18 @@ use solana_program::{
   msg,
   program_error::{PrintProgramError, ProgramError},
 };
+use std::convert::TryFrom;
 use thiserror::Error;
 
 /// OneSolResult
 pub type ProtocolResult<T = ()> = Result<T, ProtocolError>;
 
+/// `ProgramError::Custom` codes from this one on are errors of one leg of a
+/// multi-leg instruction, `LEG_ERROR_BASE + leg * LEG_ERROR_STRIDE + code`.
+pub const LEG_ERROR_BASE: u32 = 0x1_0000;
+/// Room for the codes of every `ProtocolError` variant
+pub const LEG_ERROR_STRIDE: u32 = 0x100;
+
 #[macro_export]
 macro_rules! check_unreachable {
   () => {{
@@ -21,7 +28,7 @@ macro_rules! check_unreachable {
 // pub(crate) use check_unreachable;
 
 /// Errors that may be returned by the OneSol program.
-#[derive(Clone, Debug, Eq, Error, FromPrimitive, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Error, FromPrimitive, PartialEq)]
 pub enum ProtocolError {
   /// Unknown error.
   #[error("Unknown error")]
@@ -221,10 +228,203 @@ pub enum ProtocolError {
 
   #[error("overflow")]
   Overflow,
+
+  /// User source/destination mints don't match the pool mints in either orientation
+  #[error("user mints do not match pool mints")]
+  MintRoutingMismatch,
+
+  #[error("raydium pool is disabled")]
+  RaydiumPoolDisabled,
+
+  #[error("crema pool is paused")]
+  CremaPoolDisabled,
+
+  #[error("pool is blocked")]
+  PoolBlocked,
+
+  #[error("blocked pool list is full")]
+  BlockedPoolListFull,
+
+  #[error("invalid meteora damm pool account")]
+  InvalidMeteoraDammPoolAccount,
+
+  #[error("meteora damm pool disabled")]
+  MeteoraDammPoolDisabled,
+
+  /// Fee reference mint not set
+  #[error("Fee reference mint not set")]
+  FeeReferenceMintNotSet,
+
+  /// No fallback candidate can fill the swap
+  #[error("No fallback candidate can fill the swap")]
+  NoValidFallbackCandidate,
+
+  /// Token account is frozen
+  #[error("Token account is frozen")]
+  AccountFrozen,
+
+  /// Invalid saber add-decimals wrapper account
+  #[error("Invalid saber add-decimals wrapper account")]
+  InvalidSaberWrapperAccount,
+
+  /// Min notional list is full
+  #[error("Min notional list is full")]
+  MinNotionalListFull,
+
+  /// Exchanger is disabled in the protocol config
+  #[error("Exchanger is disabled")]
+  ExchangerDisabled,
+
+  /// Open orders account still holds funds
+  #[error("Open orders account still holds funds")]
+  OpenOrdersNotEmpty,
+
+  /// SwapInfo is too recent or in the middle of a route
+  #[error("SwapInfo is not stale")]
+  SwapInfoNotStale,
+
+  /// Transient intermediate account not emptied by the Out leg
+  #[error("Transient intermediate account not emptied by the Out leg")]
+  TransientAccountNotEmpty,
+
+  /// Treasury token account missing or not the config one
+  #[error("Invalid treasury token account")]
+  InvalidTreasuryAccount,
+
+  /// Mint fee cap list is full
+  #[error("Mint fee cap list is full")]
+  MintFeeCapListFull,
+
+  /// Pool price drifted too far from the client reference price
+  #[error("Pool price drifted too far from the reference price")]
+  PriceDeviationTooHigh,
+
+  /// Host fee account missing or not a protocol pool token account
+  #[error("Invalid host fee account")]
+  InvalidHostFeeAccount,
+
+  /// Exchanger program account not executable or not a known program id
+  #[error("Invalid dex program id")]
+  InvalidDexProgramId,
+
+  /// Rebate account not of the intermediate mint or not owned by the
+  /// program authority
+  #[error("Invalid rebate account")]
+  InvalidRebateAccount,
+
+  /// Invalid raydium cp-swap pool or amm config account
+  #[error("Invalid raydium cp-swap pool account")]
+  InvalidRaydiumCpmmPoolAccount,
+
+  /// Swaps are disabled on the raydium cp-swap pool
+  #[error("Raydium cp-swap pool disabled")]
+  RaydiumCpmmPoolDisabled,
+
+  /// The owner can't fund the wrapped amount and the WSOL account rent
+  #[error("Insufficient lamports")]
+  InsufficientLamports,
+
+  /// The config has no room for another fee-exempt owner
+  #[error("fee exempt owner list is full")]
+  FeeExemptOwnerListFull,
+
+  /// The expected output oracle is not the config signer or did not sign
+  #[error("invalid expect amount oracle")]
+  InvalidExpectOracle,
+
+  /// The exchanger stats account is missing, or is not the counter of the
+  /// swap's exchanger
+  #[error("invalid exchanger stats account")]
+  InvalidExchangerStatsAccount,
+
+  /// The pool registry is not the program's registry account
+  #[error("invalid pool registry account")]
+  InvalidPoolRegistryAccount,
+
+  /// The pool registry has no room for another pool
+  #[error("pool registry is full")]
+  PoolRegistryFull,
+
+  /// The pool accounts passed differ from the registered ones
+  #[error("pool accounts do not match the registry")]
+  PoolRegistryMismatch,
+
+  /// The lamport fee collector is not the fee owner wallet
+  #[error("invalid lamport fee collector")]
+  InvalidFeeCollector,
+
+  /// The fee accrual is not the one of the swap owner and fee mint
+  #[error("invalid fee accrual account")]
+  InvalidFeeAccrualAccount,
+
+  /// The swap left less than `min_source_remaining` in the source account
+  #[error("insufficient funds left in the source account")]
+  InsufficientFunds,
+
+  /// The mint decimals differ from the ones the pool or quote expects
+  #[error("invalid mint decimals")]
+  InvalidMintDecimals,
+
+  /// The pool creator isn't in the trusted pool creators list
+  #[error("untrusted pool creator")]
+  UntrustedPoolCreator,
+
+  /// The trusted pool creators account isn't the one of the program
+  #[error("invalid trusted pool creators account")]
+  InvalidTrustedPoolCreatorsAccount,
+
+  /// The trusted pool creators list is full
+  #[error("trusted pool creator list full")]
+  TrustedPoolCreatorListFull,
+
+  #[error("invalid cropper swap account data")]
+  InvalidCropperSwapAccountData,
+
+  /// The reference mint fee swap can only be priced by the swap source mint
+  #[error("Fee reference mint is not the swap source mint")]
+  FeeReferenceMintNotSource,
+
+  /// The expect oracle mint list is full
+  #[error("expect oracle mint list full")]
+  ExpectOracleMintListFull,
 }
+impl ProtocolError {
+  /// Code carried by `ProgramError::Custom`. Codes follow the variant order
+  /// and are stable, new variants are only appended.
+  pub const fn code(&self) -> u32 {
+    *self as u32
+  }
+
+  /// Maps a `ProgramError::Custom` code of a failed transaction back to the
+  /// error.
+  pub fn from_code(code: u32) -> Option<Self> {
+    num_traits::FromPrimitive::from_u32(code)
+  }
+
+  /// Tags the error of leg `leg` of a multi-leg instruction with the leg
+  /// index. Errors not raised by this program are returned as is.
+  pub fn in_leg(leg: u8, error: ProgramError) -> ProgramError {
+    msg!("leg {} failed: {}", leg, error);
+    match error {
+      ProgramError::Custom(code) if Self::from_code(code).is_some() => {
+        ProgramError::Custom(LEG_ERROR_BASE + leg as u32 * LEG_ERROR_STRIDE + code)
+      }
+      error => error,
+    }
+  }
+
+  /// Maps a `ProgramError::Custom` code tagged by `in_leg` back to the leg
+  /// index and the error.
+  pub fn from_leg_code(code: u32) -> Option<(u8, Self)> {
+    let offset = code.checked_sub(LEG_ERROR_BASE)?;
+    let leg = u8::try_from(offset / LEG_ERROR_STRIDE).ok()?;
+    Some((leg, Self::from_code(offset % LEG_ERROR_STRIDE)?))
+  }
+}
+
 impl From<ProtocolError> for ProgramError {
   fn from(e: ProtocolError) -> Self {
-    ProgramError::Custom(e as u32)
+    ProgramError::Custom(e.code())
   }
 }
 impl<T> DecodeError<T> for ProtocolError {
@@ -335,6 +535,171 @@ impl PrintProgramError for ProtocolError {
       ProtocolError::Overflow => {
         msg!("Error: Overflow")
       }
+      ProtocolError::MintRoutingMismatch => {
+        msg!("Error: MintRoutingMismatch")
+      }
+      ProtocolError::RaydiumPoolDisabled => {
+        msg!("Error: RaydiumPoolDisabled")
+      }
+      ProtocolError::CremaPoolDisabled => {
+        msg!("Error: CremaPoolDisabled")
+      }
+      ProtocolError::PoolBlocked => {
+        msg!("Error: PoolBlocked")
+      }
+      ProtocolError::BlockedPoolListFull => {
+        msg!("Error: BlockedPoolListFull")
+      }
+      ProtocolError::InvalidMeteoraDammPoolAccount => {
+        msg!("Error: InvalidMeteoraDammPoolAccount")
+      }
+      ProtocolError::MeteoraDammPoolDisabled => {
+        msg!("Error: MeteoraDammPoolDisabled")
+      }
+      ProtocolError::FeeReferenceMintNotSet => {
+        msg!("Error: FeeReferenceMintNotSet")
+      }
+      ProtocolError::NoValidFallbackCandidate => {
+        msg!("Error: NoValidFallbackCandidate")
+      }
+      ProtocolError::AccountFrozen => {
+        msg!("Error: AccountFrozen")
+      }
+      ProtocolError::InvalidSaberWrapperAccount => {
+        msg!("Error: InvalidSaberWrapperAccount")
+      }
+      ProtocolError::MinNotionalListFull => {
+        msg!("Error: MinNotionalListFull")
+      }
+      ProtocolError::ExchangerDisabled => {
+        msg!("Error: ExchangerDisabled")
+      }
+      ProtocolError::OpenOrdersNotEmpty => {
+        msg!("Error: OpenOrdersNotEmpty")
+      }
+      ProtocolError::SwapInfoNotStale => {
+        msg!("Error: SwapInfoNotStale")
+      }
+      ProtocolError::TransientAccountNotEmpty => {
+        msg!("Error: TransientAccountNotEmpty")
+      }
+      ProtocolError::InvalidTreasuryAccount => {
+        msg!("Error: InvalidTreasuryAccount")
+      }
+      ProtocolError::MintFeeCapListFull => {
+        msg!("Error: MintFeeCapListFull")
+      }
+      ProtocolError::PriceDeviationTooHigh => {
+        msg!("Error: PriceDeviationTooHigh")
+      }
+      ProtocolError::InvalidHostFeeAccount => {
+        msg!("Error: InvalidHostFeeAccount")
+      }
+      ProtocolError::InvalidDexProgramId => {
+        msg!("Error: InvalidDexProgramId")
+      }
+      ProtocolError::InvalidRebateAccount => {
+        msg!("Error: InvalidRebateAccount")
+      }
+      ProtocolError::InvalidRaydiumCpmmPoolAccount => {
+        msg!("Error: InvalidRaydiumCpmmPoolAccount")
+      }
+      ProtocolError::RaydiumCpmmPoolDisabled => {
+        msg!("Error: RaydiumCpmmPoolDisabled")
+      }
+      ProtocolError::InsufficientLamports => {
+        msg!("Error: InsufficientLamports")
+      }
+      ProtocolError::FeeExemptOwnerListFull => {
+        msg!("Error: FeeExemptOwnerListFull")
+      }
+      ProtocolError::InvalidExpectOracle => {
+        msg!("Error: InvalidExpectOracle")
+      }
+      ProtocolError::InvalidExchangerStatsAccount => {
+        msg!("Error: InvalidExchangerStatsAccount")
+      }
+      ProtocolError::InvalidPoolRegistryAccount => {
+        msg!("Error: InvalidPoolRegistryAccount")
+      }
+      ProtocolError::PoolRegistryFull => {
+        msg!("Error: PoolRegistryFull")
+      }
+      ProtocolError::PoolRegistryMismatch => {
+        msg!("Error: PoolRegistryMismatch")
+      }
+      ProtocolError::InvalidFeeCollector => {
+        msg!("Error: InvalidFeeCollector")
+      }
+      ProtocolError::InvalidFeeAccrualAccount => {
+        msg!("Error: InvalidFeeAccrualAccount")
+      }
+      ProtocolError::InsufficientFunds => msg!("Error: InsufficientFunds"),
+      ProtocolError::InvalidMintDecimals => msg!("Error: InvalidMintDecimals"),
+      ProtocolError::UntrustedPoolCreator => msg!("Error: UntrustedPoolCreator"),
+      ProtocolError::InvalidTrustedPoolCreatorsAccount => {
+        msg!("Error: InvalidTrustedPoolCreatorsAccount")
+      }
+      ProtocolError::TrustedPoolCreatorListFull => msg!("Error: TrustedPoolCreatorListFull"),
+      ProtocolError::InvalidCropperSwapAccountData => {
+        msg!("Error: InvalidCropperSwapAccountData")
+      }
+      ProtocolError::FeeReferenceMintNotSource => {
+        msg!("Error: FeeReferenceMintNotSource")
+      }
+      ProtocolError::ExpectOracleMintListFull => msg!("Error: ExpectOracleMintListFull"),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_error_code_round_trip() {
+    let mut code = 0;
+    while let Some(e) = ProtocolError::from_code(code) {
+      assert_eq!(e.code(), code);
+      assert_eq!(ProgramError::from(e), ProgramError::Custom(code));
+      code += 1;
+    }
+    assert!(code > ProtocolError::AccountFrozen.code());
+    assert_eq!(ProtocolError::from_code(code), None);
+
+    // codes already seen by clients
+    assert_eq!(ProtocolError::Unknown.code(), 0);
+    assert_eq!(ProtocolError::ExceededSlippage.code(), 1);
+    assert_eq!(ProtocolError::InvalidInput.code(), 4);
+    assert!(code <= LEG_ERROR_STRIDE);
+  }
+
+  #[test]
+  fn test_leg_error_code() {
+    let error = ProtocolError::in_leg(1, ProtocolError::ExceededSlippage.into());
+    assert_eq!(error, ProgramError::Custom(LEG_ERROR_BASE + 0x101));
+    assert_eq!(
+      ProtocolError::from_leg_code(LEG_ERROR_BASE + 0x101),
+      Some((1, ProtocolError::ExceededSlippage))
+    );
+    assert_eq!(
+      ProtocolError::from_leg_code(LEG_ERROR_BASE + 255 * LEG_ERROR_STRIDE),
+      Some((255, ProtocolError::Unknown))
+    );
+    // not a leg code
+    assert_eq!(ProtocolError::from_leg_code(1), None);
+    assert_eq!(
+      ProtocolError::from_leg_code(LEG_ERROR_BASE + 256 * LEG_ERROR_STRIDE),
+      None
+    );
+    // errors of other programs are kept
+    assert_eq!(
+      ProtocolError::in_leg(0, ProgramError::InvalidArgument),
+      ProgramError::InvalidArgument
+    );
+    assert_eq!(
+      ProtocolError::in_leg(0, ProgramError::Custom(LEG_ERROR_STRIDE)),
+      ProgramError::Custom(LEG_ERROR_STRIDE)
+    );
+  }
+}
@@ -0,0 +1,86 @@
+use std::mem::size_of;
+
+use solana_program::{
+  instruction::{AccountMeta, Instruction},
+  program_error::ProgramError,
+  pubkey::Pubkey,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+struct Swap {
+  /// SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+  pub amount_in: u64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum SwapInstrution {
+  Swap(Swap),
+}
+
+impl SwapInstrution {
+  pub fn pack(&self) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(size_of::<Self>());
+    match &*self {
+      Self::Swap(Swap {
+        amount_in,
+        minimum_amount_out,
+      }) => {
+        buf.extend_from_slice(&[248, 198, 158, 145, 225, 117, 135, 200]);
+        buf.extend_from_slice(&amount_in.to_le_bytes());
+        buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+      }
+    };
+    buf
+  }
+}
+
+/// Creates a DAMM v2 `swap` instruction, both pool tokens must use `token_program_id`.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_instruction(
+  program_id: &Pubkey,
+  pool_authority: &Pubkey,
+  pool_key: &Pubkey,
+  user_source_token_account: &Pubkey,
+  user_destination_token_account: &Pubkey,
+  token_a_vault: &Pubkey,
+  token_b_vault: &Pubkey,
+  token_a_mint: &Pubkey,
+  token_b_mint: &Pubkey,
+  user_authority: &Pubkey,
+  token_program_id: &Pubkey,
+  event_authority: &Pubkey,
+  amount_in: u64,
+  minimum_amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+  let data = SwapInstrution::Swap(Swap {
+    amount_in,
+    minimum_amount_out,
+  })
+  .pack();
+
+  let accounts = vec![
+    AccountMeta::new_readonly(*pool_authority, false),
+    AccountMeta::new(*pool_key, false),
+    AccountMeta::new(*user_source_token_account, false),
+    AccountMeta::new(*user_destination_token_account, false),
+    AccountMeta::new(*token_a_vault, false),
+    AccountMeta::new(*token_b_vault, false),
+    AccountMeta::new_readonly(*token_a_mint, false),
+    AccountMeta::new_readonly(*token_b_mint, false),
+    AccountMeta::new_readonly(*user_authority, true),
+    AccountMeta::new_readonly(*token_program_id, false),
+    AccountMeta::new_readonly(*token_program_id, false),
+    // no referral token account, anchor reads the program id as `None`
+    AccountMeta::new_readonly(*program_id, false),
+    AccountMeta::new_readonly(*event_authority, false),
+    AccountMeta::new_readonly(*program_id, false),
+  ];
+
+  Ok(Instruction {
+    program_id: *program_id,
+    accounts,
+    data,
+  })
+}
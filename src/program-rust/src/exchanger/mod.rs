@@ -1,7 +1,10 @@
 pub mod aldrin;
 pub mod crema;
 pub mod cropper;
+pub mod meteora_damm;
 pub mod raydium;
+pub mod raydium_cpmm;
+pub mod saber_wrapper;
 pub mod serum_dex;
 pub mod spl_token_swap;
 pub mod stable_swap;
@@ -44,7 +44,7 @@ pub enum AmmInstruction {
   ///   1. `[writable]` amm Account
   ///   2. `[]` $authority
   ///   3. `[writable]` amm open_orders Account
-  ///   4. `[writable]` amm target_orders Account
+  ///   4. `[writable]` amm target_orders Account, omitted by slim callers
   ///   5. `[writable]` pool_token_coin Amm Account to swap FROM or To,
   ///   6. `[writable]` pool_token_pc Amm Account to swap FROM or To,
   ///   7. `[]` serum dex program id
@@ -58,8 +58,6 @@ pub enum AmmInstruction {
   ///   15. `[writable]` user source token Account. user Account to swap from.
   ///   16. `[writable]` user destination token Account. user Account to swap to.
   ///   17. `[singer]` user owner Account
-  SwapSlim(SwapInstruction),
-
   Swap(SwapInstruction),
 }
 
@@ -68,14 +66,6 @@ impl AmmInstruction {
   pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
     let mut buf = Vec::with_capacity(size_of::<Self>());
     match &*self {
-      Self::SwapSlim(SwapInstruction {
-        amount_in,
-        minimum_amount_out,
-      }) => {
-        buf.push(9);
-        buf.extend_from_slice(&amount_in.to_le_bytes());
-        buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
-      }
       Self::Swap(SwapInstruction {
         amount_in,
         minimum_amount_out,
@@ -89,72 +79,14 @@ impl AmmInstruction {
   }
 }
 
-/// Creates a 'swap base in' instruction.
-pub fn swap_slim(
-  program_id: &Pubkey,
-  amm_id: &Pubkey,
-  amm_authority: &Pubkey,
-  amm_open_orders: &Pubkey,
-  pool_coin_token_account: &Pubkey,
-  pool_pc_token_account: &Pubkey,
-  serum_program_id: &Pubkey,
-  serum_market: &Pubkey,
-  serum_bids: &Pubkey,
-  serum_asks: &Pubkey,
-  serum_event_queue: &Pubkey,
-  serum_coin_vault_account: &Pubkey,
-  serum_pc_vault_account: &Pubkey,
-  serum_vault_signer: &Pubkey,
-  uer_source_token_account: &Pubkey,
-  uer_destination_token_account: &Pubkey,
-  user_source_owner: &Pubkey,
-  amount_in: u64,
-  minimum_amount_out: u64,
-) -> Result<Instruction, ProgramError> {
-  let data = AmmInstruction::SwapSlim(SwapInstruction {
-    amount_in,
-    minimum_amount_out,
-  })
-  .pack()?;
-
-  let accounts = vec![
-    // spl token
-    AccountMeta::new_readonly(spl_token::id(), false),
-    // amm
-    AccountMeta::new(*amm_id, false),
-    AccountMeta::new_readonly(*amm_authority, false),
-    AccountMeta::new(*amm_open_orders, false),
-    AccountMeta::new(*pool_coin_token_account, false),
-    AccountMeta::new(*pool_pc_token_account, false),
-    // serum
-    AccountMeta::new_readonly(*serum_program_id, false),
-    AccountMeta::new(*serum_market, false),
-    AccountMeta::new(*serum_bids, false),
-    AccountMeta::new(*serum_asks, false),
-    AccountMeta::new(*serum_event_queue, false),
-    AccountMeta::new(*serum_coin_vault_account, false),
-    AccountMeta::new(*serum_pc_vault_account, false),
-    AccountMeta::new_readonly(*serum_vault_signer, false),
-    // user
-    AccountMeta::new(*uer_source_token_account, false),
-    AccountMeta::new(*uer_destination_token_account, false),
-    AccountMeta::new_readonly(*user_source_owner, true),
-  ];
-
-  Ok(Instruction {
-    program_id: *program_id,
-    accounts,
-    data,
-  })
-}
-
-/// Creates a 'swap in' instruction.
+/// Creates a 'swap base in' instruction, `amm_target_orders` is left out of
+/// the accounts when `None`.
 pub fn swap(
   program_id: &Pubkey,
   amm_id: &Pubkey,
   amm_authority: &Pubkey,
   amm_open_orders: &Pubkey,
-  amm_target_orders: &Pubkey,
+  amm_target_orders: Option<&Pubkey>,
   pool_coin_token_account: &Pubkey,
   pool_pc_token_account: &Pubkey,
   serum_program_id: &Pubkey,
@@ -177,14 +109,18 @@ pub fn swap(
   })
   .pack()?;
 
-  let accounts = vec![
+  let mut accounts = vec![
     // spl token
     AccountMeta::new_readonly(spl_token::id(), false),
     // amm
     AccountMeta::new(*amm_id, false),
     AccountMeta::new_readonly(*amm_authority, false),
     AccountMeta::new(*amm_open_orders, false),
-    AccountMeta::new(*amm_target_orders, false),
+  ];
+  if let Some(amm_target_orders) = amm_target_orders {
+    accounts.push(AccountMeta::new(*amm_target_orders, false));
+  }
+  accounts.extend_from_slice(&[
     AccountMeta::new(*pool_coin_token_account, false),
     AccountMeta::new(*pool_pc_token_account, false),
     // serum
@@ -200,7 +136,7 @@ pub fn swap(
     AccountMeta::new(*user_source_token_account, false),
     AccountMeta::new(*user_destination_token_account, false),
     AccountMeta::new_readonly(*user_source_owner, true),
-  ];
+  ]);
 
   Ok(Instruction {
     program_id: *program_id,
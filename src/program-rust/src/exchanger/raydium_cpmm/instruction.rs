@@ -0,0 +1,87 @@
+use std::mem::size_of;
+
+use solana_program::{
+  instruction::{AccountMeta, Instruction},
+  program_error::ProgramError,
+  pubkey::Pubkey,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+struct SwapBaseInput {
+  /// SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+  pub amount_in: u64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum SwapInstruction {
+  SwapBaseInput(SwapBaseInput),
+}
+
+impl SwapInstruction {
+  pub fn pack(&self) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(size_of::<Self>());
+    match &*self {
+      Self::SwapBaseInput(SwapBaseInput {
+        amount_in,
+        minimum_amount_out,
+      }) => {
+        buf.extend_from_slice(&[143, 190, 90, 218, 196, 30, 51, 222]);
+        buf.extend_from_slice(&amount_in.to_le_bytes());
+        buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+      }
+    };
+    buf
+  }
+}
+
+/// Creates a CP-Swap `swap_base_input` instruction. Each side takes the token
+/// program of its mint.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_base_input(
+  program_id: &Pubkey,
+  payer: &Pubkey,
+  authority: &Pubkey,
+  amm_config: &Pubkey,
+  pool_state: &Pubkey,
+  input_token_account: &Pubkey,
+  output_token_account: &Pubkey,
+  input_vault: &Pubkey,
+  output_vault: &Pubkey,
+  input_token_program: &Pubkey,
+  output_token_program: &Pubkey,
+  input_token_mint: &Pubkey,
+  output_token_mint: &Pubkey,
+  observation_state: &Pubkey,
+  amount_in: u64,
+  minimum_amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+  let data = SwapInstruction::SwapBaseInput(SwapBaseInput {
+    amount_in,
+    minimum_amount_out,
+  })
+  .pack();
+
+  let accounts = vec![
+    AccountMeta::new_readonly(*payer, true),
+    AccountMeta::new_readonly(*authority, false),
+    AccountMeta::new_readonly(*amm_config, false),
+    AccountMeta::new(*pool_state, false),
+    AccountMeta::new(*input_token_account, false),
+    AccountMeta::new(*output_token_account, false),
+    AccountMeta::new(*input_vault, false),
+    AccountMeta::new(*output_vault, false),
+    AccountMeta::new_readonly(*input_token_program, false),
+    AccountMeta::new_readonly(*output_token_program, false),
+    AccountMeta::new_readonly(*input_token_mint, false),
+    AccountMeta::new_readonly(*output_token_mint, false),
+    AccountMeta::new(*observation_state, false),
+  ];
+
+  Ok(Instruction {
+    program_id: *program_id,
+    accounts,
+    data,
+  })
+}
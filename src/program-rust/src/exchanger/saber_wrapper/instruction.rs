@@ -0,0 +1,91 @@
+use solana_program::{
+  instruction::{AccountMeta, Instruction},
+  program_error::ProgramError,
+  pubkey::Pubkey,
+};
+
+const DEPOSIT_DISCRIMINATOR: [u8; 8] = [242, 35, 198, 137, 82, 225, 242, 182];
+const WITHDRAW_DISCRIMINATOR: [u8; 8] = [183, 18, 70, 156, 148, 109, 161, 34];
+
+fn user_stake_accounts(
+  wrapper: &Pubkey,
+  wrapper_mint: &Pubkey,
+  wrapper_underlying_tokens: &Pubkey,
+  owner: &Pubkey,
+  user_underlying_tokens: &Pubkey,
+  user_wrapped_tokens: &Pubkey,
+  token_program_id: &Pubkey,
+) -> Vec<AccountMeta> {
+  vec![
+    AccountMeta::new_readonly(*wrapper, false),
+    AccountMeta::new(*wrapper_mint, false),
+    AccountMeta::new(*wrapper_underlying_tokens, false),
+    AccountMeta::new_readonly(*owner, true),
+    AccountMeta::new(*user_underlying_tokens, false),
+    AccountMeta::new(*user_wrapped_tokens, false),
+    AccountMeta::new_readonly(*token_program_id, false),
+  ]
+}
+
+/// Creates an add-decimals `deposit` instruction, wraps `amount` underlying
+/// tokens into `amount * multiplier` wrapped tokens.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit(
+  program_id: &Pubkey,
+  wrapper: &Pubkey,
+  wrapper_mint: &Pubkey,
+  wrapper_underlying_tokens: &Pubkey,
+  owner: &Pubkey,
+  user_underlying_tokens: &Pubkey,
+  user_wrapped_tokens: &Pubkey,
+  token_program_id: &Pubkey,
+  amount: u64,
+) -> Result<Instruction, ProgramError> {
+  let mut data = DEPOSIT_DISCRIMINATOR.to_vec();
+  data.extend_from_slice(&amount.to_le_bytes());
+  Ok(Instruction {
+    program_id: *program_id,
+    accounts: user_stake_accounts(
+      wrapper,
+      wrapper_mint,
+      wrapper_underlying_tokens,
+      owner,
+      user_underlying_tokens,
+      user_wrapped_tokens,
+      token_program_id,
+    ),
+    data,
+  })
+}
+
+/// Creates an add-decimals `withdraw` instruction, burns at most
+/// `max_burn_amount` wrapped tokens, rounded down to a multiple of the
+/// multiplier.
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw(
+  program_id: &Pubkey,
+  wrapper: &Pubkey,
+  wrapper_mint: &Pubkey,
+  wrapper_underlying_tokens: &Pubkey,
+  owner: &Pubkey,
+  user_underlying_tokens: &Pubkey,
+  user_wrapped_tokens: &Pubkey,
+  token_program_id: &Pubkey,
+  max_burn_amount: u64,
+) -> Result<Instruction, ProgramError> {
+  let mut data = WITHDRAW_DISCRIMINATOR.to_vec();
+  data.extend_from_slice(&max_burn_amount.to_le_bytes());
+  Ok(Instruction {
+    program_id: *program_id,
+    accounts: user_stake_accounts(
+      wrapper,
+      wrapper_mint,
+      wrapper_underlying_tokens,
+      owner,
+      user_underlying_tokens,
+      user_wrapped_tokens,
+      token_program_id,
+    ),
+    data,
+  })
+}
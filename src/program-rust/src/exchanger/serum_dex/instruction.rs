@@ -31,6 +31,12 @@ pub struct NewOrderInstructionV3 {
   pub limit: u16,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CancelOrderInstructionV2 {
+  pub side: Side,
+  pub order_id: u128,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum MarketInstruction {
   /// 0. `[writable]` market
@@ -58,6 +64,13 @@ pub enum MarketInstruction {
   /// 11. `[]` the rent sysvar
   /// 12. `[]` (optional) the (M)SRM account used for fee discounts
   NewOrderV3(NewOrderInstructionV3),
+  /// 0. `[writable]` market
+  /// 1. `[writable]` bids
+  /// 2. `[writable]` asks
+  /// 3. `[writable]` OpenOrders
+  /// 4. `[signer]` the OpenOrders owner
+  /// 5. `[writable]` event_q
+  CancelOrderV2(CancelOrderInstructionV2),
   /// 0. `[writable]` OpenOrders
   /// 1. `[signer]` the OpenOrders owner
   /// 2. `[writable]` the destination account to send rent exemption SOL to
@@ -102,6 +115,12 @@ impl MarketInstruction {
         buf.extend_from_slice(&client_order_id.to_le_bytes());
         buf.extend_from_slice(&limit.to_le_bytes());
       }
+      Self::CancelOrderV2(CancelOrderInstructionV2 { side, order_id }) => {
+        buf.extend_from_slice(&11u32.to_le_bytes());
+        let side_value: u8 = Side::into(*side);
+        buf.extend_from_slice(&(side_value as u32).to_le_bytes());
+        buf.extend_from_slice(&order_id.to_le_bytes());
+      }
       Self::CloseOpenOrders => {
         buf.extend_from_slice(&14u32.to_le_bytes());
       }
@@ -209,7 +228,34 @@ pub fn settle_funds(
   })
 }
 
-#[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_order(
+  program_id: &Pubkey,
+  market: &Pubkey,
+  market_bids: &Pubkey,
+  market_asks: &Pubkey,
+  open_orders_account: &Pubkey,
+  open_orders_account_owner: &Pubkey,
+  event_queue: &Pubkey,
+  side: Side,
+  order_id: u128,
+) -> Result<Instruction, ProtocolError> {
+  let data = MarketInstruction::CancelOrderV2(CancelOrderInstructionV2 { side, order_id }).pack();
+  let accounts: Vec<AccountMeta> = vec![
+    AccountMeta::new(*market, false),
+    AccountMeta::new(*market_bids, false),
+    AccountMeta::new(*market_asks, false),
+    AccountMeta::new(*open_orders_account, false),
+    AccountMeta::new_readonly(*open_orders_account_owner, true),
+    AccountMeta::new(*event_queue, false),
+  ];
+  Ok(Instruction {
+    program_id: *program_id,
+    data,
+    accounts,
+  })
+}
+
 pub fn close_open_orders(
   program_id: &Pubkey,
   open_orders: &Pubkey,
@@ -314,4 +360,62 @@ mod tests {
 
     assert!(mi.pack() == mi2.pack());
   }
+
+  #[test]
+  pub fn test_new_order_client_order_id() {
+    let key = Pubkey::new_unique();
+    for client_order_id in [0, 42, u64::MAX] {
+      let instruction = new_order(
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        &key,
+        None,
+        &key,
+        Side::Bid,
+        NonZeroU64::new(100).unwrap(),
+        NonZeroU64::new(20).unwrap(),
+        OrderType::ImmediateOrCancel,
+        client_order_id,
+        SelfTradeBehavior::DecrementTake,
+        10,
+        NonZeroU64::new(109).unwrap(),
+      )
+      .unwrap();
+      match serum_dex::instruction::MarketInstruction::unpack(&instruction.data) {
+        Some(serum_dex::instruction::MarketInstruction::NewOrderV3(order)) => {
+          assert_eq!(order.client_order_id, client_order_id)
+        }
+        other => panic!("unexpected instruction {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  pub fn test_pack_market_instruction_cancel_order() {
+    for (side, serum_side) in [
+      (Side::Bid, serum_dex::matching::Side::Bid),
+      (Side::Ask, serum_dex::matching::Side::Ask),
+    ] {
+      let mi = MarketInstruction::CancelOrderV2(CancelOrderInstructionV2 {
+        side,
+        order_id: 0x1234_5678_9abc_def0_0fed_cba9_8765_4321,
+      });
+      let mi2 = serum_dex::instruction::MarketInstruction::CancelOrderV2(
+        serum_dex::instruction::CancelOrderInstructionV2 {
+          side: serum_side,
+          order_id: 0x1234_5678_9abc_def0_0fed_cba9_8765_4321,
+        },
+      );
+      assert!(mi.pack() == mi2.pack());
+    }
+  }
 }
@@ -3,17 +3,48 @@ use crate::error::ProtocolError;
 use solana_program::{
   account_info::AccountInfo,
   entrypoint::ProgramResult,
+  instruction::Instruction,
   program::{invoke, invoke_signed},
   pubkey::Pubkey,
 };
-use std::num::NonZeroU64;
+use std::{convert::TryFrom, num::NonZeroU64};
 
 use super::{
-  instruction::{self, init_open_orders, SelfTradeBehavior},
+  instruction::{self, close_open_orders, init_open_orders, SelfTradeBehavior},
   matching::{OrderType, Side},
   state::MarketState,
 };
 
+// Size of a serum-dex open orders account, including padding.
+pub const OPEN_ORDERS_LEN: usize = 3228;
+pub const OPEN_ORDERS_SEED: &[u8] = b"open_orders";
+pub const OPEN_ORDERS_AUTHORITY_SEED: &[u8] = b"open_orders_authority";
+
+// Program-owned open orders account of `owner` on `market`.
+pub fn find_open_orders_address(
+  market: &Pubkey,
+  owner: &Pubkey,
+  program_id: &Pubkey,
+) -> (Pubkey, u8) {
+  Pubkey::find_program_address(
+    &[OPEN_ORDERS_SEED, market.as_ref(), owner.as_ref()],
+    program_id,
+  )
+}
+
+// PDA set as the owner of the account from `find_open_orders_address`, it
+// signs orders and settles on behalf of `owner`.
+pub fn find_open_orders_authority(
+  market: &Pubkey,
+  owner: &Pubkey,
+  program_id: &Pubkey,
+) -> (Pubkey, u8) {
+  Pubkey::find_program_address(
+    &[OPEN_ORDERS_AUTHORITY_SEED, market.as_ref(), owner.as_ref()],
+    program_id,
+  )
+}
+
 // An exchange rate for swapping *from* one token *to* another.
 #[derive(Clone, Debug, PartialEq, Eq, Copy)]
 pub struct ExchangeRate {
@@ -47,6 +78,26 @@ pub struct ExchangeRate {
   pub strict: bool,
 }
 
+// Account layout used for the settle funds CPI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettleLayout {
+  // market, open_orders, owner, vaults, wallets, vault_signer, token program.
+  Minimal,
+  // Minimal layout plus the referrer pc wallet.
+  WithReferral,
+}
+
+impl SettleLayout {
+  // The referral slot is only kept when it is a wallet of the market's quote
+  // mint, otherwise the dex rejects the settle and aborts the whole swap.
+  pub fn resolve(pc_mint: &Pubkey, referral_mint: Option<Pubkey>) -> Self {
+    match referral_mint {
+      Some(mint) if mint == *pc_mint => SettleLayout::WithReferral,
+      _ => SettleLayout::Minimal,
+    }
+  }
+}
+
 // Market accounts are the accounts used to place orders against the dex minus
 // common accounts, i.e., program ids, sysvars, and the `pc_wallet`.
 #[derive(Clone)]
@@ -74,6 +125,8 @@ pub struct MarketAccounts<'a, 'info: 'a> {
 pub struct OrderbookClient<'a, 'info: 'a> {
   pub market: MarketAccounts<'a, 'info>,
   pub open_order_authority: &'a AccountInfo<'info>,
+  // Signer seeds when `open_order_authority` is a PDA of this program.
+  pub open_order_authority_seeds: Option<&'a [&'a [u8]]>,
   pub pc_wallet: &'a AccountInfo<'info>,
   pub dex_program: &'a AccountInfo<'info>,
   pub token_program: &'a AccountInfo<'info>,
@@ -82,20 +135,26 @@ pub struct OrderbookClient<'a, 'info: 'a> {
 
 impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
   // Executes the sell order portion of the swap, purchasing as much of the
-  // quote currency as possible for the given `base_amount`.
+  // quote currency as possible for the given `base_amount`, at a price no
+  // lower than the one paying `minimum_pc_out` for the whole order.
   //
   // `base_amount` is the "native" amount of the base currency, i.e., token
   // amount including decimals.
   pub fn sell(
     &self,
     base_amount: u64,
+    minimum_pc_out: u64,
+    client_order_id: u64,
     srm_msrm_discount: Option<AccountInfo<'info>>,
   ) -> ProgramResult {
-    let limit_price = 1;
-    let max_coin_qty = {
+    let (limit_price, max_coin_qty) = {
       // The loaded market must be dropped before CPI.
       let market = MarketState::unpack_from_slice(&self.market.market.try_borrow_data()?)?;
-      coin_lots(&market, base_amount)
+      let max_coin_qty =
+        coin_lots(&market, base_amount).ok_or(ProtocolError::InvalidSerumDexMarketAccount)?;
+      let limit_price = sell_limit_price(&market, max_coin_qty, minimum_pc_out)
+        .ok_or(ProtocolError::InvalidSerumDexMarketAccount)?;
+      (limit_price, max_coin_qty)
     };
     let max_native_pc_qty = u64::MAX;
     self.order_cpi(
@@ -103,21 +162,30 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
       max_coin_qty,
       max_native_pc_qty,
       Side::Ask,
+      client_order_id,
       srm_msrm_discount,
     )
   }
 
   // Executes the buy order portion of the swap, purchasing as much of the
-  // base currency as possible, for the given `quote_amount`.
+  // base currency as possible, for the given `quote_amount`, at a price no
+  // higher than the one buying `minimum_coin_out` with all of it.
   //
   // `quote_amount` is the "native" amount of the quote currency, i.e., token
   // amount including decimals.
   pub fn buy(
     &self,
     quote_amount: u64,
+    minimum_coin_out: u64,
+    client_order_id: u64,
     srm_msrm_discount: Option<AccountInfo<'info>>,
   ) -> ProgramResult {
-    let limit_price = u64::MAX;
+    let limit_price = {
+      // The loaded market must be dropped before CPI.
+      let market = MarketState::unpack_from_slice(&self.market.market.try_borrow_data()?)?;
+      buy_limit_price(&market, quote_amount, minimum_coin_out)
+        .ok_or(ProtocolError::InvalidSerumDexMarketAccount)?
+    };
     let max_coin_qty = u64::MAX;
     let max_native_pc_qty = quote_amount;
     self.order_cpi(
@@ -125,6 +193,7 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
       max_coin_qty,
       max_native_pc_qty,
       Side::Bid,
+      client_order_id,
       srm_msrm_discount,
     )
   }
@@ -136,6 +205,8 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
   // * `max_native_pc_qty` - the max number of quote currency in native token
   //                         units (includes decimals).
   // * `side` - bid or ask, i.e. the type of order.
+  // * `client_order_id` - the caller's id, reported by the dex on the fill
+  //                       events so the order can be reconciled off chain.
   // * `referral` - referral account, earning a fee.
   fn order_cpi(
     &self,
@@ -143,14 +214,35 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
     max_coin_qty: u64,
     max_native_pc_qty: u64,
     side: Side,
+    client_order_id: u64,
     srm_msrm_discount: Option<AccountInfo<'info>>,
   ) -> ProgramResult {
-    // Client order id is only used for cancels. Not used here so hardcode.
-    let client_order_id = 0;
+    let (instruction, accounts) = self.new_order_instruction(
+      limit_price,
+      max_coin_qty,
+      max_native_pc_qty,
+      side,
+      client_order_id,
+      srm_msrm_discount,
+    )?;
+    self.invoke_as_authority(&instruction, &accounts[..])
+  }
+
+  // Builds the new order instruction of `order_cpi` and the accounts handed
+  // to its invoke.
+  pub fn new_order_instruction(
+    &self,
+    limit_price: u64,
+    max_coin_qty: u64,
+    max_native_pc_qty: u64,
+    side: Side,
+    client_order_id: u64,
+    srm_msrm_discount: Option<AccountInfo<'info>>,
+  ) -> Result<(Instruction, Vec<AccountInfo<'info>>), ProtocolError> {
     // Limit is the dex's custom compute budge parameter, setting an upper
     // bound on the number of matching cycles the program can perform
     // before giving up and posting the remaining unmatched order.
-    let limit = 65535;
+    let limit = matching_limit(remaining_compute_units());
 
     // let srm_msrm_discount_key = match srm_msrm_discount {
     //   Some(srm_msrm_discount) => Some(srm_msrm_discount.key),
@@ -160,31 +252,8 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
     // if let Some(srm_msrm_discount) = srm_msrm_discount {
     //     ctx = ctx.with_remaining_accounts(vec![srm_msrm_discount]);
     // }
-    let mut accounts = vec![
-      self.market.market.clone(),
-      self.market.open_orders.clone(),
-      self.market.request_queue.clone(),
-      self.market.event_queue.clone(),
-      self.market.bids.clone(),
-      self.market.asks.clone(),
-      self.market.order_payer_authority.clone(),
-      self.open_order_authority.clone(),
-      self.market.coin_vault.clone(),
-      self.market.pc_vault.clone(),
-      self.token_program.clone(),
-      self.rent.clone(),
-    ];
-    if let Some(account) = srm_msrm_discount.clone() {
-      accounts.push(account)
-    };
-    let srm_msrm_discount_key = match srm_msrm_discount {
-      Some(acc) => {
-        accounts.push(acc.clone());
-        Some(acc.key)
-      }
-      None => None,
-    };
-    accounts.push(self.dex_program.clone());
+    let srm_msrm_discount_key = srm_msrm_discount.as_ref().map(|acc| acc.key);
+    let accounts = self.new_order_accounts(srm_msrm_discount);
 
     let instruction = instruction::new_order(
       self.market.market.key,
@@ -202,21 +271,132 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
       srm_msrm_discount_key,
       self.dex_program.key,
       side,
-      NonZeroU64::new(limit_price).unwrap(),
-      NonZeroU64::new(max_coin_qty).unwrap(),
+      NonZeroU64::new(limit_price).ok_or(ProtocolError::InvalidInput)?,
+      NonZeroU64::new(max_coin_qty).ok_or(ProtocolError::InvalidInput)?,
       OrderType::ImmediateOrCancel,
       client_order_id,
       SelfTradeBehavior::DecrementTake,
       limit,
-      NonZeroU64::new(max_native_pc_qty).unwrap(),
+      NonZeroU64::new(max_native_pc_qty).ok_or(ProtocolError::InvalidInput)?,
     )
     .map_err(|_| ProtocolError::InvalidDelegate)?;
 
-    invoke(&instruction, &accounts[..])?;
-    Ok(())
+    Ok((instruction, accounts))
+  }
+
+  // Accounts handed to the new order invoke: the instruction metas, the
+  // optional discount account among them, then the dex program.
+  fn new_order_accounts(
+    &self,
+    srm_msrm_discount: Option<AccountInfo<'info>>,
+  ) -> Vec<AccountInfo<'info>> {
+    let mut accounts = vec![
+      self.market.market.clone(),
+      self.market.open_orders.clone(),
+      self.market.request_queue.clone(),
+      self.market.event_queue.clone(),
+      self.market.bids.clone(),
+      self.market.asks.clone(),
+      self.market.order_payer_authority.clone(),
+      self.open_order_authority.clone(),
+      self.market.coin_vault.clone(),
+      self.market.pc_vault.clone(),
+      self.token_program.clone(),
+      self.rent.clone(),
+    ];
+    if let Some(account) = srm_msrm_discount {
+      accounts.push(account);
+    }
+    accounts.push(self.dex_program.clone());
+    accounts
+  }
+
+  // Cancels an order resting on the book, its funds are released to the
+  // open orders account and returned by the next settle.
+  pub fn cancel_order(&self, side: Side, order_id: u128) -> ProgramResult {
+    let (instruction, accounts) = self.cancel_order_instruction(side, order_id)?;
+    self.invoke_as_authority(&instruction, &accounts[..])
+  }
+
+  // Builds the instruction of `cancel_order` and the accounts handed to its
+  // invoke.
+  pub fn cancel_order_instruction(
+    &self,
+    side: Side,
+    order_id: u128,
+  ) -> Result<(Instruction, Vec<AccountInfo<'info>>), ProtocolError> {
+    let accounts = vec![
+      self.market.market.clone(),
+      self.market.bids.clone(),
+      self.market.asks.clone(),
+      self.market.open_orders.clone(),
+      self.open_order_authority.clone(),
+      self.market.event_queue.clone(),
+      self.dex_program.clone(),
+    ];
+    let instruction = instruction::cancel_order(
+      self.dex_program.key,
+      self.market.market.key,
+      self.market.bids.key,
+      self.market.asks.key,
+      self.market.open_orders.key,
+      self.open_order_authority.key,
+      self.market.event_queue.key,
+      side,
+      order_id,
+    )?;
+    Ok((instruction, accounts))
+  }
+
+  fn invoke_as_authority(
+    &self,
+    instruction: &Instruction,
+    accounts: &[AccountInfo<'info>],
+  ) -> ProgramResult {
+    match self.open_order_authority_seeds {
+      Some(seeds) => invoke_signed(instruction, accounts, &[seeds]),
+      None => invoke(instruction, accounts),
+    }
+  }
+
+  // Pre-validates the settle accounts, since a failed CPI can't be caught
+  // and retried. Returns the layout the market accepts for `referral_mint`.
+  pub fn settle_layout(
+    &self,
+    referral_mint: Option<Pubkey>,
+  ) -> Result<SettleLayout, ProtocolError> {
+    let market = MarketState::unpack_from_slice(
+      &self
+        .market
+        .market
+        .try_borrow_data()
+        .map_err(|_| ProtocolError::BorrowAccountDataError)?,
+    )
+    .map_err(|_| ProtocolError::InvalidSerumDexMarketAccount)?;
+    let vault_signer = Pubkey::create_program_address(
+      &[
+        self.market.market.key.as_ref(),
+        &market.vault_signer_nonce.to_le_bytes(),
+      ],
+      self.dex_program.key,
+    )
+    .map_err(|_| ProtocolError::DexSwapError)?;
+    if vault_signer != *self.market.vault_signer.key {
+      return Err(ProtocolError::DexSwapError);
+    }
+    Ok(SettleLayout::resolve(&market.pc_mint, referral_mint))
   }
 
   pub fn settle(&self, referral: Option<AccountInfo<'info>>) -> ProgramResult {
+    let (instruction, accounts) = self.settle_instruction(referral)?;
+    self.invoke_as_authority(&instruction, &accounts[..])
+  }
+
+  // Builds the instruction of `settle` and the accounts handed to its invoke.
+  pub fn settle_instruction(
+    &self,
+    referral: Option<AccountInfo<'info>>,
+  ) -> Result<(Instruction, Vec<AccountInfo<'info>>), ProtocolError> {
     let mut accounts = vec![
       self.market.market.clone(),
       self.market.open_orders.clone(),
@@ -249,28 +429,79 @@ impl<'a, 'info: 'a> OrderbookClient<'a, 'info> {
       referral_key,
       self.market.vault_signer.key,
     )?;
-    invoke(&instruction, &accounts[..])?;
-    Ok(())
+    Ok((instruction, accounts))
   }
 }
 
-// Returns the amount of lots for the base currency of a trade with `size`.
-fn coin_lots(market: &MarketState, size: u64) -> u64 {
-  size.checked_div(market.coin_lot_size).unwrap()
+// Compute kept for the new_order overhead and the rest of the swap (settle,
+// fee transfer) when sizing the matching limit.
+const ORDER_RESERVED_COMPUTE_UNITS: u64 = 60_000;
+// Rough upper bound of the compute used by one serum matching cycle.
+const MATCHING_CYCLE_COMPUTE_UNITS: u64 = 2_000;
+const MAX_MATCHING_LIMIT: u16 = 65535;
+
+// `sol_remaining_compute_units` is not exposed by this solana-program
+// version, the limit falls back to `MAX_MATCHING_LIMIT`.
+fn remaining_compute_units() -> Option<u64> {
+  None
+}
+
+// Number of matching cycles that fit in the `remaining` compute units, so the
+// IOC order stops matching instead of exhausting the budget. At least one
+// cycle is always allowed.
+fn matching_limit(remaining: Option<u64>) -> u16 {
+  match remaining {
+    Some(remaining) => {
+      let cycles =
+        remaining.saturating_sub(ORDER_RESERVED_COMPUTE_UNITS) / MATCHING_CYCLE_COMPUTE_UNITS;
+      cycles.clamp(1, MAX_MATCHING_LIMIT as u64) as u16
+    }
+    None => MAX_MATCHING_LIMIT,
+  }
+}
+
+// Returns the amount of lots for the base currency of a trade with `size`,
+// None for a market without lot size.
+fn coin_lots(market: &MarketState, size: u64) -> Option<u64> {
+  size.checked_div(market.coin_lot_size)
+}
+
+// Lowest price, in quote lots per base lot, at which `coin_lots` sold still
+// return `minimum_pc_out` native quote before fees. Rounded down so a fill
+// right at the minimum isn't cut, at least 1. None for a market without lot
+// size or an empty order.
+fn sell_limit_price(market: &MarketState, coin_lots: u64, minimum_pc_out: u64) -> Option<u64> {
+  let lots_value = (coin_lots as u128).checked_mul(market.pc_lot_size as u128)?;
+  let price = (minimum_pc_out as u128).checked_div(lots_value)?;
+  Some(u64::try_from(price).ok()?.max(1))
+}
+
+// Highest price, in quote lots per base lot, at which `quote_amount` native
+// quote still buys `minimum_coin_out` native base. Rounded up so a fill right
+// at the minimum isn't cut, u64::MAX without a minimum. None for a market
+// without lot size.
+fn buy_limit_price(market: &MarketState, quote_amount: u64, minimum_coin_out: u64) -> Option<u64> {
+  if market.coin_lot_size == 0 || market.pc_lot_size == 0 {
+    return None;
+  }
+  if minimum_coin_out == 0 {
+    return Some(u64::MAX);
+  }
+  let spent = quote_amount as u128 * market.coin_lot_size as u128;
+  let received = minimum_coin_out as u128 * market.pc_lot_size as u128;
+  let price = (spent + received - 1) / received;
+  Some(u64::try_from(price).unwrap_or(u64::MAX).max(1))
 }
 
-#[allow(dead_code)]
 pub fn invoke_init_open_orders<'a>(
-  base_seed: &[u8],
+  authority_seeds: &[&[u8]],
   program_id: &Pubkey,
   open_orders: &AccountInfo<'a>,
   authority: &AccountInfo<'a>,
   market: &AccountInfo<'a>,
   rent: &AccountInfo<'a>,
-  nonce: u8,
 ) -> Result<(), ProtocolError> {
-  let authority_signature_seeds = [base_seed, &[nonce]];
-  let signers = &[&authority_signature_seeds[..]];
+  let signers = &[authority_seeds];
 
   let ix = init_open_orders(program_id, open_orders.key, authority.key, market.key, None)
     .map_err(|_| ProtocolError::InitOpenOrdersInstructionError)?;
@@ -286,3 +517,204 @@ pub fn invoke_init_open_orders<'a>(
   )
   .map_err(|_| ProtocolError::InvokeError)
 }
+
+pub fn invoke_close_open_orders<'a>(
+  authority_seeds: &[&[u8]],
+  program_id: &Pubkey,
+  open_orders: &AccountInfo<'a>,
+  authority: &AccountInfo<'a>,
+  destination: &AccountInfo<'a>,
+  market: &AccountInfo<'a>,
+) -> Result<(), ProtocolError> {
+  let signers = &[authority_seeds];
+
+  let ix = close_open_orders(
+    program_id,
+    open_orders.key,
+    authority.key,
+    destination.key,
+    market.key,
+  )?;
+  invoke_signed(
+    &ix,
+    &[
+      open_orders.clone(),
+      authority.clone(),
+      destination.clone(),
+      market.clone(),
+    ],
+    signers,
+  )
+  .map_err(|_| ProtocolError::InvokeError)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  pub fn test_matching_limit() {
+    assert_eq!(matching_limit(None), MAX_MATCHING_LIMIT);
+    assert_eq!(matching_limit(Some(1_400_000)), 670);
+    assert_eq!(matching_limit(Some(u64::MAX)), MAX_MATCHING_LIMIT);
+    // low budget, fewer cycles
+    assert_eq!(
+      matching_limit(Some(
+        ORDER_RESERVED_COMPUTE_UNITS + 10 * MATCHING_CYCLE_COMPUTE_UNITS
+      )),
+      10
+    );
+    // nothing left, still try one cycle
+    assert_eq!(matching_limit(Some(ORDER_RESERVED_COMPUTE_UNITS)), 1);
+    assert_eq!(matching_limit(Some(0)), 1);
+  }
+
+  #[test]
+  pub fn test_coin_lots() {
+    let mut data = vec![0u8; 388];
+    data[..5].copy_from_slice(b"serum");
+    data[381..].copy_from_slice(b"padding");
+    // coin_lot_size
+    data[5 + 344..5 + 352].copy_from_slice(&100u64.to_le_bytes());
+    let market = MarketState::unpack_from_slice(&data).unwrap();
+    assert_eq!(coin_lots(&market, 1_099), Some(10));
+    assert_eq!(coin_lots(&market, 99), Some(0));
+    let mut market = market;
+    market.coin_lot_size = 0;
+    assert_eq!(coin_lots(&market, 1_000), None);
+  }
+
+  #[test]
+  pub fn test_limit_price() {
+    let mut data = vec![0u8; 388];
+    data[..5].copy_from_slice(b"serum");
+    data[381..].copy_from_slice(b"padding");
+    data[5 + 344..5 + 352].copy_from_slice(&100u64.to_le_bytes());
+    data[5 + 352..5 + 360].copy_from_slice(&10u64.to_le_bytes());
+    let mut market = MarketState::unpack_from_slice(&data).unwrap();
+
+    // 10 lots of 100 sold for at least 5_000, 500 quote lots of 10
+    assert_eq!(sell_limit_price(&market, 10, 5_000), Some(50));
+    assert_eq!(sell_limit_price(&market, 10, 5_099), Some(50));
+    assert_eq!(sell_limit_price(&market, 10, 0), Some(1));
+    assert_eq!(sell_limit_price(&market, 0, 5_000), None);
+
+    // 5_000 quote buying at least 1_000 base
+    assert_eq!(buy_limit_price(&market, 5_000, 1_000), Some(50));
+    assert_eq!(buy_limit_price(&market, 5_000, 999), Some(51));
+    assert_eq!(buy_limit_price(&market, 5_000, 0), Some(u64::MAX));
+    assert_eq!(buy_limit_price(&market, u64::MAX, 1), Some(u64::MAX));
+    assert_eq!(buy_limit_price(&market, 1, u64::MAX), Some(1));
+
+    market.pc_lot_size = 0;
+    assert_eq!(sell_limit_price(&market, 10, 5_000), None);
+    assert_eq!(buy_limit_price(&market, 5_000, 1_000), None);
+  }
+
+  #[test]
+  pub fn test_new_order_accounts() {
+    let keys = (0..17).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    let mut lamports = vec![0u64; keys.len()];
+    let mut data = vec![Vec::<u8>::new(); keys.len()];
+    let owner = Pubkey::new_unique();
+    let infos = keys
+      .iter()
+      .zip(lamports.iter_mut())
+      .zip(data.iter_mut())
+      .map(|((key, lamports), data)| {
+        AccountInfo::new(key, false, false, lamports, data, &owner, false, 0)
+      })
+      .collect::<Vec<_>>();
+    let client = OrderbookClient {
+      market: MarketAccounts {
+        market: &infos[0],
+        open_orders: &infos[1],
+        request_queue: &infos[2],
+        event_queue: &infos[3],
+        bids: &infos[4],
+        asks: &infos[5],
+        order_payer_authority: &infos[6],
+        coin_vault: &infos[7],
+        pc_vault: &infos[8],
+        vault_signer: &infos[9],
+        coin_wallet: &infos[10],
+      },
+      open_order_authority: &infos[11],
+      open_order_authority_seeds: None,
+      pc_wallet: &infos[12],
+      dex_program: &infos[13],
+      token_program: &infos[14],
+      rent: &infos[15],
+    };
+
+    let without = client
+      .new_order_accounts(None)
+      .iter()
+      .map(|account| *account.key)
+      .collect::<Vec<_>>();
+    assert_eq!(without.len(), 13);
+    assert_eq!(without[12], keys[13]);
+    // the discount account is passed once, right before the dex program
+    let with = client
+      .new_order_accounts(Some(infos[16].clone()))
+      .iter()
+      .map(|account| *account.key)
+      .collect::<Vec<_>>();
+    assert_eq!(with.len(), 14);
+    assert_eq!(with[..12], without[..12]);
+    assert_eq!(with[12], keys[16]);
+    assert_eq!(with[13], keys[13]);
+  }
+
+  #[test]
+  pub fn test_open_orders_pda() {
+    let program_id = Pubkey::new_unique();
+    let market = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let (open_orders, _) = find_open_orders_address(&market, &owner, &program_id);
+    let (authority, bump) = find_open_orders_authority(&market, &owner, &program_id);
+    assert_ne!(open_orders, authority);
+    assert_eq!(
+      find_open_orders_address(&market, &owner, &program_id).0,
+      open_orders
+    );
+    assert_eq!(
+      Pubkey::create_program_address(
+        &[
+          OPEN_ORDERS_AUTHORITY_SEED,
+          market.as_ref(),
+          owner.as_ref(),
+          &[bump]
+        ],
+        &program_id
+      )
+      .unwrap(),
+      authority
+    );
+    // one open orders account per market and user
+    let other_owner = Pubkey::new_unique();
+    let other_market = Pubkey::new_unique();
+    assert_ne!(
+      find_open_orders_address(&market, &other_owner, &program_id).0,
+      open_orders
+    );
+    assert_ne!(
+      find_open_orders_address(&other_market, &owner, &program_id).0,
+      open_orders
+    );
+  }
+
+  #[test]
+  pub fn test_settle_layout_resolve() {
+    let pc_mint = Pubkey::new_unique();
+    assert_eq!(SettleLayout::resolve(&pc_mint, None), SettleLayout::Minimal);
+    assert_eq!(
+      SettleLayout::resolve(&pc_mint, Some(Pubkey::new_unique())),
+      SettleLayout::Minimal
+    );
+    assert_eq!(
+      SettleLayout::resolve(&pc_mint, Some(pc_mint)),
+      SettleLayout::WithReferral
+    );
+  }
+}
@@ -0,0 +1,13 @@
+//! Swap calculations
+
+/// The direction to round.  Used for pool token to trading token conversions to
+/// avoid losing value on any deposit or withdrawal.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundDirection {
+  /// Floor the value, ie. 1.9 => 1, 1.1 => 1, 1.5 => 1
+  Floor,
+  /// Ceiling the value, ie. 1.9 => 2, 1.1 => 2, 1.5 => 2
+  #[allow(dead_code)]
+  Ceiling,
+}
@@ -0,0 +1,349 @@
+//! Stable swap invariant and fee rounding helpers for stable swap quotes
+
+use crate::exchanger::spl_token_swap::calculator::RoundDirection;
+use std::convert::TryFrom;
+
+/// Computes `amount * numerator / denominator` rounded in `round_direction`.
+pub fn mul_div(
+  amount: u64,
+  numerator: u64,
+  denominator: u64,
+  round_direction: RoundDirection,
+) -> Option<u64> {
+  if denominator == 0 {
+    return None;
+  }
+  let product = (amount as u128).checked_mul(numerator as u128)?;
+  let quotient = product.checked_div(denominator as u128)?;
+  let result = match round_direction {
+    RoundDirection::Floor => quotient,
+    RoundDirection::Ceiling => {
+      if product.checked_rem(denominator as u128)? > 0 {
+        quotient.checked_add(1)?
+      } else {
+        quotient
+      }
+    }
+  };
+  u64::try_from(result).ok()
+}
+
+/// Output left after the pool's trade fee, rounded down in favor of the pool.
+pub fn output_after_fee(amount_out: u64, fee_numerator: u64, fee_denominator: u64) -> Option<u64> {
+  if fee_numerator > fee_denominator {
+    return None;
+  }
+  mul_div(
+    amount_out,
+    fee_denominator.checked_sub(fee_numerator)?,
+    fee_denominator,
+    RoundDirection::Floor,
+  )
+}
+
+/// Basis points denominator used by `minimum_out_from_bps`.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Minimum output derived from `amount_in` rescaled to the destination decimals
+/// and multiplied by `bps / 10000`, rounded down.
+pub fn minimum_out_from_bps(
+  amount_in: u64,
+  source_decimals: u8,
+  destination_decimals: u8,
+  bps: u16,
+) -> Option<u64> {
+  if bps as u64 > BPS_DENOMINATOR {
+    return None;
+  }
+  let numerator = (amount_in as u128)
+    .checked_mul(bps as u128)?
+    .checked_mul(10u128.checked_pow(destination_decimals as u32)?)?;
+  let denominator =
+    (BPS_DENOMINATOR as u128).checked_mul(10u128.checked_pow(source_decimals as u32)?)?;
+  u64::try_from(numerator.checked_div(denominator)?).ok()
+}
+
+/// Number of tokens in a saber pool.
+const N_COINS: u128 = 2;
+/// Newton iterations before the last approximation is returned.
+const MAX_ITERATIONS: usize = 256;
+
+/// Current amplification coefficient of a pool ramping linearly from
+/// `initial_amp_factor` at `start_ramp_ts` to `target_amp_factor` at
+/// `stop_ramp_ts`, the same way saber computes it. `None` before the ramp
+/// started, as saber fails then too.
+pub fn compute_amp_factor(
+  initial_amp_factor: u64,
+  target_amp_factor: u64,
+  start_ramp_ts: i64,
+  stop_ramp_ts: i64,
+  current_ts: i64,
+) -> Option<u64> {
+  if current_ts >= stop_ramp_ts {
+    return Some(target_amp_factor);
+  }
+  let time_range = u128::try_from(stop_ramp_ts.checked_sub(start_ramp_ts)?).ok()?;
+  let time_delta = u128::try_from(current_ts.checked_sub(start_ramp_ts)?).ok()?;
+  if target_amp_factor >= initial_amp_factor {
+    let amp_range = (target_amp_factor - initial_amp_factor) as u128;
+    let amp_delta = amp_range.checked_mul(time_delta)?.checked_div(time_range)?;
+    initial_amp_factor.checked_add(u64::try_from(amp_delta).ok()?)
+  } else {
+    let amp_range = (initial_amp_factor - target_amp_factor) as u128;
+    let amp_delta = amp_range.checked_mul(time_delta)?.checked_div(time_range)?;
+    initial_amp_factor.checked_sub(u64::try_from(amp_delta).ok()?)
+  }
+}
+
+/// Saber's two coins StableSwap invariant. Intermediate values are kept in
+/// u128, pools whose invariant cubed does not fit fail to quote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StableCurve {
+  /// amplification coefficient, see `compute_amp_factor`
+  pub amp_factor: u64,
+}
+
+impl StableCurve {
+  /// Invariant D of the balances `amount_a` and `amount_b`.
+  pub fn compute_d(&self, amount_a: u64, amount_b: u64) -> Option<u128> {
+    let sum_x = (amount_a as u128).checked_add(amount_b as u128)?;
+    if sum_x == 0 {
+      return Some(0);
+    }
+    let ann = (self.amp_factor as u128).checked_mul(N_COINS)?;
+    let amount_a_times_coins = (amount_a as u128).checked_mul(N_COINS)?;
+    let amount_b_times_coins = (amount_b as u128).checked_mul(N_COINS)?;
+    let mut d = sum_x;
+    for _ in 0..MAX_ITERATIONS {
+      let d_prod = d
+        .checked_mul(d)?
+        .checked_div(amount_a_times_coins)?
+        .checked_mul(d)?
+        .checked_div(amount_b_times_coins)?;
+      let d_prev = d;
+      // d = (ann * sum_x + d_prod * n) * d / ((ann - 1) * d + (n + 1) * d_prod)
+      let numerator = d.checked_mul(
+        d_prod
+          .checked_mul(N_COINS)?
+          .checked_add(ann.checked_mul(sum_x)?)?,
+      )?;
+      let denominator = d
+        .checked_mul(ann.checked_sub(1)?)?
+        .checked_add(d_prod.checked_mul(N_COINS + 1)?)?;
+      d = numerator.checked_div(denominator)?;
+      if abs_diff(d, d_prev) <= 1 {
+        break;
+      }
+    }
+    Some(d)
+  }
+
+  /// Balance of the other coin once one coin balance is `x` at invariant `d`.
+  pub fn compute_y(&self, x: u64, d: u128) -> Option<u64> {
+    let ann = (self.amp_factor as u128).checked_mul(N_COINS)?;
+    // c = d ** (n + 1) / (n ** (2 * n) * x * amp)
+    let c = d
+      .checked_mul(d)?
+      .checked_div((x as u128).checked_mul(N_COINS)?)?
+      .checked_mul(d)?
+      .checked_div(ann.checked_mul(N_COINS)?)?;
+    // b = x + d / ann, d is subtracted in the denominator below
+    let b = d.checked_div(ann)?.checked_add(x as u128)?;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+      let y_prev = y;
+      // y = (y * y + c) / (2 * y + b - d)
+      let numerator = y.checked_mul(y)?.checked_add(c)?;
+      let denominator = y.checked_mul(2)?.checked_add(b)?.checked_sub(d)?;
+      y = numerator.checked_div(denominator)?;
+      if abs_diff(y, y_prev) <= 1 {
+        break;
+      }
+    }
+    u64::try_from(y).ok()
+  }
+
+  /// Output of swapping `source_amount` into a pool holding
+  /// `swap_source_amount` and `swap_destination_amount`, after the trade fee
+  /// saber takes out of it. Rounded down, so the quote is at most one unit
+  /// under what the pool pays and never above it.
+  pub fn swap_to(
+    &self,
+    source_amount: u64,
+    swap_source_amount: u64,
+    swap_destination_amount: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+  ) -> Option<u64> {
+    let d = self.compute_d(swap_source_amount, swap_destination_amount)?;
+    let y = self.compute_y(swap_source_amount.checked_add(source_amount)?, d)?;
+    let dy = swap_destination_amount.checked_sub(y)?;
+    if fee_numerator == 0 || fee_denominator == 0 {
+      return Some(dy);
+    }
+    output_after_fee(dy, fee_numerator, fee_denominator)
+  }
+}
+
+fn abs_diff(a: u128, b: u128) -> u128 {
+  if a > b {
+    a - b
+  } else {
+    b - a
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  const FEES: [(u64, u64); 4] = [(0, 10_000), (4, 10_000), (30, 10_000), (1, 3)];
+
+  #[test]
+  fn test_mul_div_round_direction() {
+    assert_eq!(mul_div(10, 1, 3, RoundDirection::Floor), Some(3));
+    assert_eq!(mul_div(10, 1, 3, RoundDirection::Ceiling), Some(4));
+    assert_eq!(mul_div(9, 1, 3, RoundDirection::Ceiling), Some(3));
+    assert_eq!(mul_div(1, 1, 0, RoundDirection::Floor), None);
+    assert_eq!(mul_div(u64::MAX, 2, 1, RoundDirection::Floor), None);
+  }
+
+  #[test]
+  fn test_output_after_fee_never_favors_user() {
+    for &(numerator, denominator) in FEES.iter() {
+      for amount in (1u64..100_000).step_by(97).chain([u64::MAX / 10_000]) {
+        let out = output_after_fee(amount, numerator, denominator).unwrap();
+        // out <= exact value and exact - out < 1 unit
+        let exact_scaled = amount as u128 * (denominator - numerator) as u128;
+        let out_scaled = out as u128 * denominator as u128;
+        assert!(out_scaled <= exact_scaled);
+        assert!(exact_scaled - out_scaled < denominator as u128);
+      }
+    }
+  }
+
+  proptest! {
+    #[test]
+    fn proptest_output_after_fee_within_one_unit(
+      amount in 0..u64::MAX / 10_000,
+      fee_numerator in 0u64..=10_000,
+      fee_denominator in 1u64..=10_000,
+    ) {
+      prop_assume!(fee_numerator <= fee_denominator);
+      let out = output_after_fee(amount, fee_numerator, fee_denominator).unwrap();
+      // out <= exact value and exact - out < 1 unit
+      let exact_scaled = amount as u128 * (fee_denominator - fee_numerator) as u128;
+      let out_scaled = out as u128 * fee_denominator as u128;
+      prop_assert!(out_scaled <= exact_scaled);
+      prop_assert!(exact_scaled - out_scaled < fee_denominator as u128);
+    }
+
+    #[test]
+    fn proptest_swap_to_never_above_pool_output(
+      source_amount in 1u64..1_000_000_000,
+      swap_source_amount in 1_000_000u64..1_000_000_000_000,
+      swap_destination_amount in 1_000_000u64..1_000_000_000_000,
+      amp_factor in 1u64..1_000,
+      fee_numerator in 0u64..100,
+    ) {
+      let curve = StableCurve { amp_factor };
+      let dy = curve.swap_to(source_amount, swap_source_amount, swap_destination_amount, 0, 10_000);
+      prop_assume!(dy.is_some());
+      let dy = dy.unwrap();
+      let quote = curve
+        .swap_to(
+          source_amount,
+          swap_source_amount,
+          swap_destination_amount,
+          fee_numerator,
+          10_000,
+        )
+        .unwrap();
+      // saber pays `dy` less its fee rounded down, the quote is never above
+      // it and at most one unit under it
+      let paid = dy - dy * fee_numerator / 10_000;
+      prop_assert!(quote <= paid);
+      prop_assert!(paid - quote <= 1);
+    }
+  }
+
+  #[test]
+  fn test_minimum_out_from_bps_equal_decimals() {
+    assert_eq!(minimum_out_from_bps(1_000_000, 6, 6, 9_990), Some(999_000));
+    assert_eq!(
+      minimum_out_from_bps(1_000_000, 6, 6, 10_000),
+      Some(1_000_000)
+    );
+    assert_eq!(minimum_out_from_bps(1_001, 6, 6, 9_990), Some(999));
+    assert_eq!(minimum_out_from_bps(u64::MAX, 9, 9, 10_000), Some(u64::MAX));
+    assert_eq!(minimum_out_from_bps(1_000_000, 6, 6, 10_001), None);
+  }
+
+  #[test]
+  fn test_minimum_out_from_bps_differing_decimals() {
+    // 1.0 (6 decimals) -> 0.999 (9 decimals)
+    assert_eq!(
+      minimum_out_from_bps(1_000_000, 6, 9, 9_990),
+      Some(999_000_000)
+    );
+    // 1.0 (9 decimals) -> 0.999 (6 decimals)
+    assert_eq!(
+      minimum_out_from_bps(1_000_000_000, 9, 6, 9_990),
+      Some(999_000)
+    );
+    // dust below one destination unit rounds down to zero
+    assert_eq!(minimum_out_from_bps(999, 9, 6, 10_000), Some(0));
+    // rescaling past u64 fails instead of wrapping
+    assert_eq!(minimum_out_from_bps(u64::MAX, 0, 9, 10_000), None);
+  }
+
+  #[test]
+  fn test_compute_amp_factor_ramp() {
+    // ramp up from 10 to 100 between 1_000 and 2_000
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 1_000), Some(10));
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 1_500), Some(55));
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 1_999), Some(99));
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 2_000), Some(100));
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 9_000), Some(100));
+    // ramp down
+    assert_eq!(compute_amp_factor(100, 10, 1_000, 2_000, 1_500), Some(55));
+    assert_eq!(compute_amp_factor(100, 10, 1_000, 2_000, 1_999), Some(11));
+    // no ramp
+    assert_eq!(compute_amp_factor(100, 100, 0, 0, 1_500), Some(100));
+    assert_eq!(compute_amp_factor(10, 100, 1_000, 2_000, 999), None);
+  }
+
+  #[test]
+  fn test_stable_curve_swap_to() {
+    let curve = StableCurve { amp_factor: 100 };
+    // the invariant of a balanced pool is its sum
+    assert_eq!(curve.compute_d(1_000_000, 1_000_000), Some(2_000_000));
+    assert_eq!(curve.compute_d(0, 0), Some(0));
+
+    let reserve = 1_000_000_000_000;
+    let out = curve
+      .swap_to(1_000_000, reserve, reserve, 0, 10_000)
+      .unwrap();
+    assert!(out < 1_000_000 && out > 999_990, "{}", out);
+    // a flatter curve moves the price more
+    let flat = StableCurve { amp_factor: 1 }
+      .swap_to(1_000_000_000, reserve, reserve, 0, 10_000)
+      .unwrap();
+    let steep = curve
+      .swap_to(1_000_000_000, reserve, reserve, 0, 10_000)
+      .unwrap();
+    assert!(flat < steep && steep < 1_000_000_000);
+    // the trade fee is taken out of the output, rounded in favor of the pool
+    assert_eq!(
+      curve.swap_to(1_000_000, reserve, reserve, 4, 10_000),
+      Some(out - (out * 4 + 9_999) / 10_000)
+    );
+    // imbalanced pool: the scarce coin is worth more
+    let scarce = curve
+      .swap_to(1_000_000, reserve, reserve / 10, 0, 10_000)
+      .unwrap();
+    assert!(scarce < out);
+    assert_eq!(curve.swap_to(u64::MAX, reserve, reserve, 0, 10_000), None);
+  }
+}
@@ -1 +1,2 @@
+pub mod curve;
 pub mod instruction;
@@ -1,8 +1,15 @@
 //! Instruction types
 
 use crate::error::ProtocolError;
+use crate::spl_token;
+use crate::state::derive_config_address;
 use arrayref::{array_ref, array_refs};
-use solana_program::program_error::ProgramError;
+use solana_program::{
+  instruction::{AccountMeta, Instruction},
+  program_error::ProgramError,
+  program_option::COption,
+  pubkey::Pubkey,
+};
 use std::num::NonZeroU64;
 
 /// ExchangerType
@@ -24,8 +31,16 @@ pub enum ExchangerType {
   AldrinExchange,
   /// CropperFinance
   CropperFinance,
+  /// Meteora DAMM v2
+  MeteoraDamm,
+  /// Raydium CP-Swap (CPMM)
+  RaydiumCpmm,
+  /// Orca legacy constant product pools, an spl-token-swap fork
+  OrcaV1,
 }
 
+/// Discriminants used by `ExchangerType::from` and `ExchangerType::to_u8`.
+/// They are encoded on the wire, never renumber an existing variant.
 impl ExchangerType {
   pub fn from(value: u8) -> Option<Self> {
     match value {
@@ -37,9 +52,108 @@ impl ExchangerType {
       5 => Some(ExchangerType::CremaFinance),
       6 => Some(ExchangerType::AldrinExchange),
       7 => Some(ExchangerType::CropperFinance),
+      8 => Some(ExchangerType::MeteoraDamm),
+      9 => Some(ExchangerType::RaydiumCpmm),
+      10 => Some(ExchangerType::OrcaV1),
       _ => None,
     }
   }
+
+  pub fn to_u8(&self) -> u8 {
+    match self {
+      ExchangerType::SplTokenSwap => 0,
+      ExchangerType::SerumDex => 1,
+      ExchangerType::StableSwap => 2,
+      ExchangerType::RaydiumSwap => 3,
+      ExchangerType::RaydiumSwapSlim => 4,
+      ExchangerType::CremaFinance => 5,
+      ExchangerType::AldrinExchange => 6,
+      ExchangerType::CropperFinance => 7,
+      ExchangerType::MeteoraDamm => 8,
+      ExchangerType::RaydiumCpmm => 9,
+      ExchangerType::OrcaV1 => 10,
+    }
+  }
+
+  /// Every exchanger, in discriminant order
+  pub const ALL: [ExchangerType; 11] = [
+    ExchangerType::SplTokenSwap,
+    ExchangerType::SerumDex,
+    ExchangerType::StableSwap,
+    ExchangerType::RaydiumSwap,
+    ExchangerType::RaydiumSwapSlim,
+    ExchangerType::CremaFinance,
+    ExchangerType::AldrinExchange,
+    ExchangerType::CropperFinance,
+    ExchangerType::MeteoraDamm,
+    ExchangerType::RaydiumCpmm,
+    ExchangerType::OrcaV1,
+  ];
+
+  /// Pool accounts and swap instructions of the exchanger, also returned
+  /// on chain by `GetExchangerSpec`.
+  pub const fn spec(self) -> ExchangerSpec {
+    const ALL_MODES: u8 = EXCHANGER_MODE_DIRECT | EXCHANGER_MODE_IN | EXCHANGER_MODE_OUT;
+    let (accounts_len, max_accounts_len, modes) = match self {
+      // optional host fee account
+      ExchangerType::SplTokenSwap => (7, 8, ALL_MODES),
+      // optional open orders authority and system program, then referral
+      ExchangerType::SerumDex => (11, 14, ALL_MODES),
+      // optional clock, then up to two 5 account add-decimals wrappers
+      ExchangerType::StableSwap => (6, 17, ALL_MODES),
+      // optional coin and pc mints
+      ExchangerType::RaydiumSwap => (15, 17, ALL_MODES),
+      ExchangerType::RaydiumSwapSlim => (14, 16, EXCHANGER_MODE_IN | EXCHANGER_MODE_OUT_SLIM),
+      ExchangerType::CremaFinance => (6, 6, ALL_MODES),
+      // optional mints of both sides
+      ExchangerType::AldrinExchange => (8, 10, ALL_MODES),
+      ExchangerType::CropperFinance => (8, 10, ALL_MODES),
+      ExchangerType::MeteoraDamm => (10, 10, ALL_MODES),
+      ExchangerType::RaydiumCpmm => (9, 9, ALL_MODES),
+      // orca pools take no host fee account
+      ExchangerType::OrcaV1 => (7, 7, ALL_MODES),
+    };
+    ExchangerSpec {
+      accounts_len,
+      max_accounts_len,
+      modes,
+    }
+  }
+}
+
+/// Pool accounts an exchanger takes after the accounts of the protocol in a
+/// swap, and the swap instructions it can be used with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangerSpec {
+  /// pool accounts without any of the optional ones
+  pub accounts_len: u8,
+  /// pool accounts with every optional one
+  pub max_accounts_len: u8,
+  /// `EXCHANGER_MODE_*` bits
+  pub modes: u8,
+}
+
+/// Direct single step swap
+pub const EXCHANGER_MODE_DIRECT: u8 = 1;
+/// First leg of a route, with a `SwapInInstruction`
+pub const EXCHANGER_MODE_IN: u8 = 1 << 1;
+/// Last leg of a route, with a `SwapOutInstruction`
+pub const EXCHANGER_MODE_OUT: u8 = 1 << 2;
+/// Last leg of a route, with a `SwapOutSlimInstruction`
+pub const EXCHANGER_MODE_OUT_SLIM: u8 = 1 << 3;
+
+impl ExchangerSpec {
+  pub const LEN: usize = 4;
+
+  /// `[exchanger][accounts_len][max_accounts_len][modes]`
+  pub fn pack(&self, exchanger: ExchangerType) -> [u8; Self::LEN] {
+    [
+      exchanger.to_u8(),
+      self.accounts_len,
+      self.max_accounts_len,
+      self.modes,
+    ]
+  }
 }
 
 /// Initialize instruction data
@@ -58,15 +172,153 @@ pub struct SwapInstruction {
   pub expect_amount_out: NonZeroU64,
   /// Minimum amount of DESTINATION token to output, prevents excessive slippage
   pub minimum_amount_out: NonZeroU64,
+  /// `SWAP_FLAG_*` bits, optional trailing byte
+  pub flags: u8,
+  /// smallest `amount_in` after clamping to the source balance, optional
+  /// trailing u64 after `flags`. A config entry for the source mint wins.
+  pub min_notional_in: u64,
+  /// largest price impact against the pool spot price, in bps, optional
+  /// trailing u16 after `min_notional_in`. 0 disables the check, only
+  /// spl-token-swap and aldrin steps enforce it.
+  pub max_price_impact_bps: u16,
+  /// side the router expects to take, 0 bid or 1 ask, optional trailing byte
+  /// after `max_price_impact_bps`. Only serum and aldrin steps check it.
+  pub expected_side: Option<u8>,
+  /// opaque id echoed back in the return data with the amount received, in
+  /// place of the `FeeBreakdown`, optional trailing u64 after
+  /// `expected_side`. Serum steps also place it as the order's
+  /// `client_order_id`, 0 when absent. Pack the side as
+  /// `NO_EXPECTED_SIDE` to pass it without a side check.
+  pub client_route_id: Option<u64>,
+  /// pool spot price the client quoted against, optional trailing
+  /// `[num][den][max_drift_bps]` after `client_route_id`. Only spl-token-swap,
+  /// aldrin and cropper steps check it.
+  pub reference_price: Option<ReferencePrice>,
+  /// `SWAP_EXTRA_FLAG_*` bits, optional trailing byte after
+  /// `reference_price`. Pack the price as zeros to pass it without a check.
+  pub extra_flags: u8,
+  /// pool the router quoted, optional trailing pubkey after `extra_flags`.
+  /// The swap fails with `InvalidProgramAddress` when the primary pool
+  /// account (the market of a serum step) is another one.
+  pub expected_pool: Option<Pubkey>,
+  /// smallest source balance left after the swap, optional trailing u64
+  /// after `expected_pool`. Pack the pool as zeros to pass it without a
+  /// pool check, 0 disables it.
+  pub min_source_remaining: u64,
+}
+
+/// Spot price of a pool, `num / den` destination units per source unit, and
+/// how far the pool may have moved from it before the swap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferencePrice {
+  pub num: u64,
+  pub den: u64,
+  /// largest drift of the pool spot price from `num / den`, in bps
+  pub max_drift_bps: u16,
 }
 
+/// `expected_side` byte leaving the side unchecked, so that
+/// `client_route_id` can follow it.
+pub const NO_EXPECTED_SIDE: u8 = 0xff;
+
+/// `client_route_id` value echoing nothing, so that `reference_price` can
+/// follow it.
+pub const NO_CLIENT_ROUTE_ID: u64 = u64::MAX;
+
+/// Cancel the order resting in slot 0 of the serum open orders account
+/// before placing the new one.
+pub const SWAP_FLAG_SERUM_CANCEL_RESTING_ORDER: u8 = 1;
+/// Fail with `NotRentExempt` when the destination token account is not rent
+/// exempt.
+pub const SWAP_FLAG_REQUIRE_RENT_EXEMPT_DESTINATION: u8 = 1 << 1;
+/// Take the fee in the config `fee_reference_mint` by swapping it through an
+/// extra spl-token-swap pool passed after the exchanger accounts. The
+/// reference mint must be the source or destination mint, the fee swap may
+/// not pay much less than the price the swap itself got.
+pub const SWAP_FLAG_FEE_IN_REFERENCE_MINT: u8 = 1 << 2;
+/// Fail with `InvalidInput` when `amount_in` exceeds the source mint supply,
+/// the source mint is passed as the last account.
+pub const SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY: u8 = 1 << 3;
+/// Take a flat fee on `amount_in` in the source mint before the swap instead
+/// of a share of the output surplus, the fee token account holds the source
+/// mint. Exclusive with `SWAP_FLAG_FEE_IN_REFERENCE_MINT`.
+pub const SWAP_FLAG_FEE_ON_INPUT: u8 = 1 << 4;
+/// Apply the config holder discount to the fee, the owner's token account of
+/// the config `fee_discount_mint` is passed after the fee pool accounts.
+pub const SWAP_FLAG_FEE_DISCOUNT: u8 = 1 << 5;
+/// The config treasury token account is passed after the fee pool accounts,
+/// required once the config has a treasury.
+pub const SWAP_FLAG_TREASURY: u8 = 1 << 6;
+/// Check a serum step's settle against the market's fee rate, failing with
+/// `DexSwapError` when the market took more than its fee on the fill.
+pub const SWAP_FLAG_SERUM_CHECK_SETTLE: u8 = 1 << 7;
+/// Fail with `InvalidOwner` when the destination token account is not owned
+/// by the signer, swapping into a third party account is allowed by default.
+pub const SWAP_EXTRA_FLAG_DESTINATION_OWNED_BY_SIGNER: u8 = 1;
+/// The config `expect_oracle`, co-signing the transaction, is passed as the
+/// last account to attest `expect_amount_out`. Required once
+/// `expect_amount_out` reaches the config threshold of the DESTINATION mint,
+/// the swap fails with `InvalidExpectOracle` without it.
+pub const SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT: u8 = 1 << 1;
+/// Fail with `InvalidExpectAmountOut` when `expect_amount_out` is over the
+/// config `max_expect_out_ratio` times `amount_in`. The source and
+/// destination mints are passed as the last two accounts, before the oracle.
+pub const SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO: u8 = 1 << 2;
+/// The `ExchangerStats` account of the swap's exchanger is passed before the
+/// expect out ratio mints, required once the config collects exchanger stats.
+pub const SWAP_EXTRA_FLAG_EXCHANGER_STATS: u8 = 1 << 3;
+/// Fail with `InvalidSourceBalance` when the source account can't spend
+/// `amount_in`, instead of clamping `amount_in` to what it holds.
+pub const SWAP_EXTRA_FLAG_NO_CLAMP: u8 = 1 << 4;
+/// Record the output fee in the owner's `FeeAccrual` of the destination mint
+/// instead of transferring it, to be paid later by `SettleAccruedFee`. The
+/// accrual account is passed before the exchanger stats account. Not with
+/// `SWAP_FLAG_FEE_ON_INPUT` or `SWAP_FLAG_FEE_IN_REFERENCE_MINT`.
+pub const SWAP_EXTRA_FLAG_ACCRUE_FEE: u8 = 1 << 5;
+/// Parse and check the accounts, check the pool trades the swap mints and
+/// quotes at least `minimum_amount_out` from its reserves, then return
+/// without any transfer or CPI. Nothing is written.
+pub const SWAP_EXTRA_FLAG_VALIDATE_ONLY: u8 = 1 << 7;
+const SWAP_EXTRA_FLAGS_ALL: u8 = SWAP_EXTRA_FLAG_DESTINATION_OWNED_BY_SIGNER
+  | SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT
+  | SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO
+  | SWAP_EXTRA_FLAG_EXCHANGER_STATS
+  | SWAP_EXTRA_FLAG_NO_CLAMP
+  | SWAP_EXTRA_FLAG_ACCRUE_FEE
+  | SWAP_EXTRA_FLAG_VALIDATE_ONLY;
+const SWAP_FLAGS_ALL: u8 = SWAP_FLAG_SERUM_CANCEL_RESTING_ORDER
+  | SWAP_FLAG_REQUIRE_RENT_EXEMPT_DESTINATION
+  | SWAP_FLAG_FEE_IN_REFERENCE_MINT
+  | SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY
+  | SWAP_FLAG_FEE_ON_INPUT
+  | SWAP_FLAG_FEE_DISCOUNT
+  | SWAP_FLAG_TREASURY
+  | SWAP_FLAG_SERUM_CHECK_SETTLE;
+
 /// Swap instruction data
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SwapInInstruction {
   /// amount of tokens to swap
   pub amount_in: NonZeroU64,
+  /// `SWAP_IN_FLAG_*` bits, optional trailing byte
+  pub flags: u8,
+  /// bump of the DESTINATION mint rebate vault, trailing byte after
+  /// `flags`, only with `SWAP_IN_FLAG_REBATE`
+  pub rebate_nonce: u8,
+  /// index of the pool in the pool registry, trailing byte after
+  /// `rebate_nonce`, only with `SWAP_IN_FLAG_REBATE`
+  pub rebate_pool_index: u8,
 }
 
+/// Credit the config rebate share of the output to the intermediate account,
+/// out of the DESTINATION mint rebate vault, capped per owner and slot by the
+/// vault. Only swaps through the registered pool `rebate_pool_index` are
+/// rebated, a pool of the caller would hand the rebate back with its fees.
+/// The pool registry, the writable vault and its token account are passed as
+/// the last three accounts.
+pub const SWAP_IN_FLAG_REBATE: u8 = 1;
+const SWAP_IN_FLAGS_ALL: u8 = SWAP_IN_FLAG_REBATE;
+
 /// Swap instruction data
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SwapOutInstruction {
@@ -74,8 +326,50 @@ pub struct SwapOutInstruction {
   pub expect_amount_out: NonZeroU64,
   /// Minimum amount of DESTINATION token to output, prevents excessive slippage
   pub minimum_amount_out: NonZeroU64,
+  /// `SWAP_OUT_FLAG_*` bits, optional trailing byte
+  pub flags: u8,
+  /// opaque id echoed back in the return data with the amount received, in
+  /// place of the `FeeBreakdown`, optional trailing u64 after `flags`. Serum
+  /// steps also place it as the order's `client_order_id`, 0 when absent.
+  pub client_route_id: Option<u64>,
+  /// final minimum given in the route input, trailing `[amount_in u64]
+  /// [min_out_bps_of_in u16]` after `client_route_id`, present with
+  /// `SWAP_OUT_FLAG_MINIMUM_FROM_INPUT`
+  pub input_minimum: Option<InputMinimum>,
 }
 
+/// Minimum of a swap out leg expressed in the input of the route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputMinimum {
+  /// amount of the route input token
+  pub amount_in: NonZeroU64,
+  /// minimum output in bps of `amount_in` rescaled to the destination
+  /// decimals, at most 10000
+  pub min_out_bps_of_in: u16,
+}
+
+/// Send the part of the intermediate amount the exchanger did not take to the
+/// token account passed as the last account.
+pub const SWAP_OUT_FLAG_REFUND_RESIDUAL: u8 = 1;
+/// Apply the config holder discount to the fee, the owner's token account of
+/// the config `fee_discount_mint` is passed before the refund account.
+pub const SWAP_OUT_FLAG_FEE_DISCOUNT: u8 = 1 << 1;
+/// The config treasury token account is passed before the discount account,
+/// required once the config has a treasury.
+pub const SWAP_OUT_FLAG_TREASURY: u8 = 1 << 2;
+/// Raise the minimum to `input_minimum` rescaled with the decimals of the
+/// route input mint and the destination mint, both passed before the
+/// treasury account.
+pub const SWAP_OUT_FLAG_MINIMUM_FROM_INPUT: u8 = 1 << 3;
+/// Close the SwapInfo once the leg succeeded, its lamports go to the owner,
+/// which saves the separate CloseSwapInfo transaction.
+pub const SWAP_OUT_FLAG_CLOSE_ON_COMPLETE: u8 = 1 << 4;
+const SWAP_OUT_FLAGS_ALL: u8 = SWAP_OUT_FLAG_REFUND_RESIDUAL
+  | SWAP_OUT_FLAG_FEE_DISCOUNT
+  | SWAP_OUT_FLAG_TREASURY
+  | SWAP_OUT_FLAG_MINIMUM_FROM_INPUT
+  | SWAP_OUT_FLAG_CLOSE_ON_COMPLETE;
+
 /// Swap instruction data
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SwapOutSlimInstruction {
@@ -83,7 +377,384 @@ pub struct SwapOutSlimInstruction {
   pub minimum_amount_out: NonZeroU64,
 }
 
+/// StableSwap guard instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwapStableSwapGuardInstruction {
+  /// amount of tokens to swap
+  pub amount_in: NonZeroU64,
+  /// expect amount of tokens to swap
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount out in basis points of `amount_in`, after adjusting for decimals
+  pub min_out_bps_of_in: u16,
+}
+
+/// AddBlockedPool/RemoveBlockedPool instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockedPoolInstruction {
+  /// pool account address
+  pub pool: Pubkey,
+}
+
+/// AddFeeExemptOwner/RemoveFeeExemptOwner instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeExemptOwnerInstruction {
+  /// wallet whose swaps are not charged a fee
+  pub owner: Pubkey,
+}
+
+/// Max number of candidates of a `SwapWithFallback`
+pub const MAX_FALLBACK_CANDIDATES: usize = 4;
+
+/// SwapWithFallback instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapWithFallbackInstruction {
+  /// amount of tokens to swap
+  pub amount_in: NonZeroU64,
+  /// expect amount of tokens to swap
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: NonZeroU64,
+  /// exchanger and number of accounts of each candidate, in order
+  pub candidates: Vec<(ExchangerType, u8)>,
+}
+
+/// SwapWithBackup instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapWithBackupInstruction {
+  /// amount of tokens to swap
+  pub amount_in: NonZeroU64,
+  /// expect amount of tokens to swap
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: NonZeroU64,
+  /// exchanger and number of accounts of the primary pool
+  pub primary: (ExchangerType, u8),
+}
+
+/// Max number of legs of a `QuoteRoute`
+pub const MAX_QUOTE_ROUTE_LEGS: usize = 4;
+
+/// QuoteRoute instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuoteRouteInstruction {
+  /// amount of SOURCE tokens sent to the first leg
+  pub amount_in: NonZeroU64,
+  /// expect amount of DESTINATION tokens, the output fee is taken above it
+  pub expect_amount_out: NonZeroU64,
+  /// exchanger and number of pool accounts of each leg, in order
+  pub legs: Vec<(ExchangerType, u8)>,
+}
+
+/// EstimateComputeUnits instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct EstimateComputeUnitsInstruction {
+  /// exchanger of the swap step
+  pub exchanger: ExchangerType,
+  /// swap from token b (pc) to token a (coin)
+  pub reverse: bool,
+}
+
+/// InitializeSwapInfo instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitializeSwapInfoInstruction {
+  /// nonce of the account at `derive_swap_info_address`, optional trailing
+  /// byte. None for an account the client created.
+  pub nonce: Option<u8>,
+}
+
+/// VerifyAuthority instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyAuthorityInstruction {
+  /// nonce (bump) the pool uses to derive its authority
+  pub nonce: u8,
+}
+
+/// SetMinNotional instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MinNotionalInstruction {
+  /// source mint
+  pub mint: Pubkey,
+  /// minimum `amount_in` in `mint` units, zero removes the entry
+  pub amount: u64,
+}
+
+/// SetMintFeeCap instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MintFeeCapInstruction {
+  /// mint the fee is taken in
+  pub mint: Pubkey,
+  /// largest fee in `mint` raw units, zero removes the entry
+  pub max_fee: u64,
+}
+
+/// SetRebate instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebateInstruction {
+  /// share of an In leg output rebated, in bps, 0 turns the rebate off
+  pub rebate_bps: u16,
+}
+
+/// SetRebateVault instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebateVaultInstruction {
+  /// largest rebate paid out of the vault to one owner in a slot, in raw
+  /// units of the vault mint
+  pub max_rebate: u64,
+}
+
+/// SetMaxRouteAccounts instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaxRouteAccountsInstruction {
+  /// most distinct accounts of a multi-leg swap, 0 restores the default
+  pub max_route_accounts: u8,
+}
+
+/// SetCollectHostFee instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollectHostFeeInstruction {
+  /// pass the protocol host fee account to spl-token-swap pools
+  pub enabled: bool,
+}
+
+/// SetExchangerEnabled instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExchangerEnabledInstruction {
+  /// exchanger to enable or disable
+  pub exchanger: ExchangerType,
+  /// false blocks new swaps through `exchanger`
+  pub enabled: bool,
+}
+
+/// SetMaxSurplus instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaxSurplusInstruction {
+  /// largest surplus the output fee is taken on, in bps of
+  /// `expect_amount_out`, 0 disables the bound
+  pub max_surplus_bps: u16,
+}
+
+/// SetSwapInfoTtl instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwapInfoTtlInstruction {
+  /// slots after which a SwapInfo may be closed by anyone, 0 disables it
+  pub ttl_slots: u64,
+}
+
+/// SetFeeGranularity instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeGranularityInstruction {
+  /// raw units the fee is rounded down to a multiple of, 0 disables it
+  pub granularity: u64,
+}
+
+/// StartRoute instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartRouteInstruction {
+  /// exchanger of the In leg
+  pub exchanger: ExchangerType,
+  /// amount of tokens to swap
+  pub amount_in: NonZeroU64,
+}
+
+/// SwapTransientRoute instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransientRouteInstruction {
+  /// exchanger of the In leg
+  pub exchanger_in: ExchangerType,
+  /// exchanger of the Out leg
+  pub exchanger_out: ExchangerType,
+  /// number of In leg exchanger accounts, the Out leg ones follow them
+  pub in_accounts_len: u8,
+  /// amount of tokens to swap
+  pub amount_in: NonZeroU64,
+  /// expect amount of DESTINATION token, the fee is a share of the surplus
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: NonZeroU64,
+  /// `TRANSIENT_ROUTE_FLAG_*` bits, optional trailing byte
+  pub flags: u8,
+}
+
+/// Apply the config holder discount to the fee, the owner's token account of
+/// the config `fee_discount_mint` is passed after the Out leg accounts.
+pub const TRANSIENT_ROUTE_FLAG_FEE_DISCOUNT: u8 = 1;
+const TRANSIENT_ROUTE_FLAGS_ALL: u8 = TRANSIENT_ROUTE_FLAG_FEE_DISCOUNT;
+
+/// NativeSwap instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct NativeSwapInstruction {
+  /// exchanger of the swap
+  pub exchanger: ExchangerType,
+  /// swap the user's tokens into SOL rather than SOL into them
+  pub unwrap: bool,
+  /// lamports to swap, or tokens when unwrapping
+  pub amount_in: NonZeroU64,
+  /// expect amount of DESTINATION token, the fee is a share of the surplus
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: NonZeroU64,
+  /// charge the fee of an unwrapping swap in lamports from the owner to the
+  /// fee collector passed last, optional trailing byte
+  pub lamport_fee: bool,
+}
+
+/// SetMaxExpectOutRatio instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaxExpectOutRatioInstruction {
+  /// largest `expect_amount_out` as a multiple of `amount_in` in whole
+  /// tokens, 0 disables the bound
+  pub max_expect_out_ratio: u16,
+}
+
+/// AddTrustedPoolCreator/RemoveTrustedPoolCreator instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustedPoolCreatorInstruction {
+  /// creator, or owner, of the pools swaps may go through
+  pub creator: Pubkey,
+}
+
+/// SetCheckPoolCreators instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckPoolCreatorsInstruction {
+  /// swaps passing the trusted list only go through pools of its creators
+  pub enabled: bool,
+}
+
+/// InitializeExchangerStats instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExchangerStatsInstruction {
+  /// exchanger the counter is created for
+  pub exchanger: ExchangerType,
+}
+
+/// RegisterPool instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegisterPoolInstruction {
+  /// exchanger of the registered pool
+  pub exchanger: ExchangerType,
+}
+
+/// SwapByIndex instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapByIndexInstruction {
+  /// index of the pool in the pool registry
+  pub pool_index: u8,
+  pub swap: SwapInstruction,
+}
+
+/// One output of a MultiOutputSwap
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputLeg {
+  /// exchanger of the leg
+  pub exchanger: ExchangerType,
+  /// number of exchanger accounts of the leg
+  pub accounts_len: u8,
+  /// part of the source amount the leg swaps
+  pub amount_in: NonZeroU64,
+  /// expect amount of the leg DESTINATION token, the fee is a share of the
+  /// surplus
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of the leg DESTINATION token to output
+  pub minimum_amount_out: NonZeroU64,
+}
+
+/// SwapPercent instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapPercentInstruction {
+  /// exchanger of the swap
+  pub exchanger: ExchangerType,
+  /// part of the source balance to swap, in bps
+  pub percent_bps: u16,
+  /// expect amount of DESTINATION token, the fee is a share of the surplus
+  pub expect_amount_out: NonZeroU64,
+  /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+  pub minimum_amount_out: NonZeroU64,
+}
+
+/// InitializeFeeAccrual instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeAccrualInstruction {
+  /// swap owner allowed to accrue its fees
+  pub owner: Pubkey,
+  /// mint of the accrued fees
+  pub mint: Pubkey,
+}
+
+/// MultiOutputSwap instruction data
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiOutputSwapInstruction {
+  /// most of the source token both legs spend together
+  pub amount_in: NonZeroU64,
+  pub legs: [OutputLeg; 2],
+}
+
+/// SetCollectExchangerStats instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollectExchangerStatsInstruction {
+  /// direct swaps increment the counter of their exchanger
+  pub enabled: bool,
+}
+
+/// SetExpectOracle instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectOracleInstruction {
+  /// oracle signer, `None` when packed as the default pubkey
+  pub oracle: COption<Pubkey>,
+}
+
+/// SetExpectOracleThreshold instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectOracleThresholdInstruction {
+  /// DESTINATION mint of the attested swaps
+  pub mint: Pubkey,
+  /// `expect_amount_out` in `mint` raw units from which the oracle must
+  /// attest, zero removes the entry
+  pub threshold: u64,
+}
+
+/// SetFeeReferenceMint instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeReferenceMintInstruction {
+  /// reference mint, `None` when packed as the default pubkey
+  pub mint: COption<Pubkey>,
+}
+
+/// SetFeeDiscount instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeDiscountInstruction {
+  /// discount mint, `None` when packed as the default pubkey
+  pub mint: COption<Pubkey>,
+  /// minimum balance of `mint` to get the discount
+  pub threshold: u64,
+  /// discount on the fee, in bps of the fee
+  pub discount_bps: u16,
+}
+
+/// SetTreasury instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreasuryInstruction {
+  /// treasury token account, `None` when packed as the default pubkey
+  pub token_account: COption<Pubkey>,
+  /// share of the fee sent to the treasury, in bps of the fee
+  pub treasury_bps: u16,
+}
+
+/// RecoverFees instruction data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoverFeesInstruction {
+  /// amount of tokens to recover
+  pub amount: NonZeroU64,
+  /// nonce used to create the program authority address
+  pub nonce: u8,
+}
+
 // Instructions supported by the 1sol protocol program
+// Swaps take the protocol config at `state::derive_config_address` as their
+// last account and fail without it.
+// The `TrustedPoolCreators` list may be passed right before it, the swap then
+// only goes through pools of trusted creators while the config checks them.
+// Passing the list is up to the caller: it lets an integrator keep its own
+// swaps off unvetted pools, it doesn't stop anyone else from using them.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum ProtocolInstruction {
@@ -123,6 +794,10 @@ pub enum ProtocolInstruction {
   ///     13. `[]`  serum-dex vault_signer for settleFunds
   ///     14. `[]`  serum-dex rent_sysvar
   ///     15. `[]`  serum-dex serum_dex_program_id
+  ///     16. `[optional]`  open orders authority PDA, when user market open_orders is the
+  ///         program-owned PDA. It is created on first use, paid by the (writable) OWNER.
+  ///     17. `[optional]`  system program, required with the open orders authority PDA
+  ///     18. `[optional, writable]`  serum-dex referrer pc wallet for settleFunds
   SwapSerumDex(SwapInstruction),
 
   /// Swap tokens through Saber StableSwap
@@ -139,6 +814,10 @@ pub enum ProtocolInstruction {
   ///     10. `[writable]` StableSwap admin fee account. Must have same mint as User DESTINATION token account.
   ///     11. `[]` StableSwap clock id.
   ///     12. `[]` StableSwap program id.
+  ///     13.. `[optional]` up to two saber add-decimals wrappers, when a user token is the
+  ///         underlying of a pool token: wrapper, `[writable]` wrapper mint, `[writable]`
+  ///         wrapper underlying tokens, `[writable]` user wrapped token account, add-decimals
+  ///         program id. The clock account is then required.
   SwapStableSwap(SwapInstruction),
 
   /// Swap tokens through Raydium-Swap
@@ -163,14 +842,19 @@ pub enum ProtocolInstruction {
   ///     18. `[writable]` raydium pc_vault account.
   ///     19. `[]` raydium vault_signer account.
   ///     20. `[]` raydium program id.
+  ///     21. `[]` optional raydium coin mint, with the pc mint.
+  ///     22. `[]` optional raydium pc mint, their decimals must match the amm.
   SwapRaydiumSwap(SwapInstruction),
 
-  /// Initialize a new swap info account
-  ///   1. `[writable, signer]` The swapInfo account for initializing
-  ///   2. `[signer]` User account
-  InitializeSwapInfo,
+  /// Initialize a new swap info account, created by the client or, with a
+  /// `nonce`, created here at `derive_swap_info_address(user, nonce)`
+  ///   1. `[writable, signer]` The swapInfo account for initializing, not a
+  ///      signer when derived
+  ///   2. `[signer]` User account, pays for a derived account
+  ///   3. `[]` System program, only with a `nonce`
+  InitializeSwapInfo(InitializeSwapInfoInstruction),
 
-  /// Setup SwapInfo account
+  /// Setup SwapInfo account, records the slot it was set up at
   ///   1. `[writable]` The swapInfo account for setup
   ///   2. `[]` TokenAccount to set
   SetupSwapInfo,
@@ -214,6 +898,10 @@ pub enum ProtocolInstruction {
   ///     13. `[]`  serum-dex vault_signer for settleFunds
   ///     14. `[]`  serum-dex rent_sysvar
   ///     15. `[]`  serum-dex serum_dex_program_id
+  ///     16. `[optional]`  open orders authority PDA, when user market open_orders is the
+  ///         program-owned PDA. It is created on first use, paid by the (writable) OWNER.
+  ///     17. `[optional]`  system program, required with the open orders authority PDA
+  ///     18. `[optional, writable]`  serum-dex referrer pc wallet for settleFunds
   SwapSerumDexIn(SwapInInstruction),
 
   /// Swap tokens through Saber StableSwap
@@ -292,6 +980,10 @@ pub enum ProtocolInstruction {
   ///     14. `[]`  serum-dex vault_signer for settleFunds
   ///     15. `[]`  serum-dex rent_sysvar
   ///     16. `[]`  serum-dex serum_dex_program_id
+  ///     17. `[optional]`  open orders authority PDA, when user market open_orders is the
+  ///         program-owned PDA. It is created on first use, paid by the (writable) OWNER.
+  ///     18. `[optional]`  system program, required with the open orders authority PDA
+  ///     19. `[optional, writable]`  serum-dex referrer pc wallet for settleFunds
   SwapSerumDexOut(SwapOutInstruction),
 
   /// Swap tokens through Saber StableSwap
@@ -535,128 +1227,1904 @@ pub enum ProtocolInstruction {
   ///   12. `[writable]` AldrinExchange Pool fee account.
   ///   13. '[]` AldrinExchange program id.
   SwapCropperFinanceOut(SwapOutInstruction),
-}
 
-impl ProtocolInstruction {
-  /// Unpacks a byte buffer into a [OneSolInstruction](enum.OneSolInstruction.html).
-  pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    let (&tag, rest) = input.split_first().ok_or(ProtocolError::InvalidInput)?;
-    Ok(match tag {
-      3 => Self::SwapSplTokenSwap(SwapInstruction::unpack(rest)?),
-      4 => Self::SwapSerumDex(SwapInstruction::unpack(rest)?),
-      5 => return Err(ProtocolError::InvalidInstruction.into()),
-      6 => Self::SwapStableSwap(SwapInstruction::unpack(rest)?),
-      8 => return Err(ProtocolError::InvalidInstruction.into()),
-      9 => Self::SwapRaydiumSwap(SwapInstruction::unpack(rest)?),
-      10 => Self::InitializeSwapInfo,
-      11 => Self::SetupSwapInfo,
-      12 => Self::SwapSplTokenSwapIn(SwapInInstruction::unpack(rest)?),
-      13 => Self::SwapSplTokenSwapOut(SwapOutInstruction::unpack(rest)?),
-      14 => Self::SwapSerumDexIn(SwapInInstruction::unpack(rest)?),
-      15 => Self::SwapSerumDexOut(SwapOutInstruction::unpack(rest)?),
-      16 => Self::SwapStableSwapIn(SwapInInstruction::unpack(rest)?),
-      17 => Self::SwapStableSwapOut(SwapOutInstruction::unpack(rest)?),
-      18 => Self::SwapRaydiumIn(SwapInInstruction::unpack(rest)?),
-      19 => Self::SwapRaydiumOut(SwapOutInstruction::unpack(rest)?),
-      20 => Self::SwapRaydiumIn2(SwapInInstruction::unpack(rest)?),
-      21 => Self::SwapRaydiumOut2(SwapOutSlimInstruction::unpack(rest)?),
-      22 => Self::SwapCremaFinance(SwapInstruction::unpack(rest)?),
-      23 => Self::SwapCremaFinanceIn(SwapInInstruction::unpack(rest)?),
-      24 => Self::SwapCremaFinanceOut(SwapOutInstruction::unpack(rest)?),
-      25 => Self::SwapAldrinExchange(SwapInstruction::unpack(rest)?),
-      26 => Self::SwapAldrinExchangeIn(SwapInInstruction::unpack(rest)?),
-      27 => Self::SwapAldrinExchangeOut(SwapOutInstruction::unpack(rest)?),
-      28 => Self::SwapCropperFinance(SwapInstruction::unpack(rest)?),
-      29 => Self::SwapCropperFinanceIn(SwapInInstruction::unpack(rest)?),
-      30 => Self::SwapCropperFinanceOut(SwapOutInstruction::unpack(rest)?),
-      31 => Self::CloseSwapInfo,
-      _ => return Err(ProtocolError::InvalidInstruction.into()),
-    })
-  }
-}
+  /// Recover tokens held by a program authority token account
+  ///
+  ///   0. `[signer]` config owner account.
+  ///   1. `[writable]` source token account, owned by the program authority.
+  ///   2. `[]` program authority.
+  ///   3. `[writable]` destination token account. Must have same mint as source.
+  ///   4. '[]` Token program id.
+  RecoverFees(RecoverFeesInstruction),
 
-impl SwapInstruction {
-  const DATA_LEN: usize = 24;
+  /// Swap tokens through Saber StableSwap with a minimum out relative to amount_in
+  ///
+  ///     0. `[]` User token SOURCE mint.
+  ///     1. `[]` User token DESTINATION mint.
+  ///     2.. accounts of `SwapStableSwap`, the config treasury token account
+  ///       passed as with `SWAP_FLAG_TREASURY` once the config has a treasury.
+  SwapStableSwapGuard(SwapStableSwapGuardInstruction),
 
-  // size = 1 or 3
-  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
-  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    if input.len() < SwapInstruction::DATA_LEN {
-      return Err(ProtocolError::InvalidInput.into());
-    }
-    let arr_data = array_ref![input, 0, SwapInstruction::DATA_LEN];
-    let (&amount_in_arr, &expect_amount_out_arr, &minimum_amount_out_arr) =
-      array_refs![arr_data, 8, 8, 8];
-    let amount_in =
-      NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
-    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
-      .ok_or(ProtocolError::InvalidInput)?;
-    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
-      .ok_or(ProtocolError::InvalidInput)?;
-    if expect_amount_out.get() < minimum_amount_out.get() || expect_amount_out.get() == 0 {
-      return Err(ProtocolError::InvalidExpectAmountOut.into());
-    }
-    Ok(SwapInstruction {
-      amount_in,
-      expect_amount_out,
-      minimum_amount_out,
-    })
-  }
-}
+  /// Initialize the protocol config account at `state::derive_config_address`
+  ///   0. `[writable]` The protocol config account for initializing
+  ///   1. `[writable, signer]` owner account, pays for the config
+  ///   2. `[]` System program id
+  InitializeConfig,
 
-impl SwapInInstruction {
-  const DATA_LEN: usize = 8;
+  /// Add a pool to the protocol config blocklist
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  AddBlockedPool(BlockedPoolInstruction),
 
-  // size = 1 or 3
-  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
-  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    if input.len() < SwapInInstruction::DATA_LEN {
-      return Err(ProtocolError::InvalidInput.into());
+  /// Remove a pool from the protocol config blocklist
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  RemoveBlockedPool(BlockedPoolInstruction),
+
+  /// Reset a completed SwapInfo account so it can be setup for a new route,
+  /// records the slot it was reset at
+  ///   0. `[writable]` The swapInfo account to reset
+  ///   1. `[signer]` owner account
+  ResetSwapInfo,
+
+  /// Swap tokens through Meteora DAMM v2
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[]` Token program id
+  ///   4. `[writable]` fee token account
+  ///
+  ///   5. `[writable]` MeteoraDamm pool account.
+  ///   6. `[]` MeteoraDamm config account.
+  ///   7. `[]` MeteoraDamm pool authority.
+  ///   8. `[writable]` MeteoraDamm token_a vault.
+  ///   9. `[writable]` MeteoraDamm token_b vault.
+  ///   10. `[]` MeteoraDamm token_a mint.
+  ///   11. `[]` MeteoraDamm token_b mint.
+  ///   12. `[]` Clock sysvar.
+  ///   13. `[]` MeteoraDamm event authority.
+  ///   14. `[]` MeteoraDamm program id.
+  SwapMeteoraDamm(SwapInstruction),
+
+  /// SwapIn by Meteora DAMM v2
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` Protocol SwapInfo account
+  ///   4. '[]` Token program id.
+  ///
+  ///   5. `[writable]` MeteoraDamm pool account.
+  ///   6. `[]` MeteoraDamm config account.
+  ///   7. `[]` MeteoraDamm pool authority.
+  ///   8. `[writable]` MeteoraDamm token_a vault.
+  ///   9. `[writable]` MeteoraDamm token_b vault.
+  ///   10. `[]` MeteoraDamm token_a mint.
+  ///   11. `[]` MeteoraDamm token_b mint.
+  ///   12. `[]` Clock sysvar.
+  ///   13. `[]` MeteoraDamm event authority.
+  ///   14. `[]` MeteoraDamm program id.
+  SwapMeteoraDammIn(SwapInInstruction),
+
+  /// SwapOut by Meteora DAMM v2
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` SwapInfo account
+  ///   4. '[]` Token program id.
+  ///   5. `[writable]` fee token account.
+  ///
+  ///   6. `[writable]` MeteoraDamm pool account.
+  ///   7. `[]` MeteoraDamm config account.
+  ///   8. `[]` MeteoraDamm pool authority.
+  ///   9. `[writable]` MeteoraDamm token_a vault.
+  ///   10. `[writable]` MeteoraDamm token_b vault.
+  ///   11. `[]` MeteoraDamm token_a mint.
+  ///   12. `[]` MeteoraDamm token_b mint.
+  ///   13. `[]` Clock sysvar.
+  ///   14. `[]` MeteoraDamm event authority.
+  ///   15. `[]` MeteoraDamm program id.
+  SwapMeteoraDammOut(SwapOutInstruction),
+
+  /// Set or clear the protocol config fee reference mint
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetFeeReferenceMint(FeeReferenceMintInstruction),
+
+  /// Reserved for a compute unit price hint, does nothing. The priority fee
+  /// itself is set client side with the compute budget program; this lets
+  /// wallets put the hint in the aggregator instruction list.
+  /// Any instruction data after the tag is ignored, no accounts are read.
+  SetComputeHint,
+
+  /// Swap through the best quoting of several candidate pools. Candidates
+  /// that fail to parse, are blocked or quote below `minimum_amount_out`
+  /// are skipped. Returns `[exchanger, candidate index]` as return data.
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[]` Token program id
+  ///   4. `[writable]` fee token account
+  ///   5.. accounts of each candidate, in order, as for its single step swap,
+  ///       then the config treasury token account when one is set.
+  SwapWithFallback(SwapWithFallbackInstruction),
+
+  /// Returns the estimated compute units of one swap step through an
+  /// exchanger as a little endian u32 in the return data. No accounts.
+  EstimateComputeUnits(EstimateComputeUnitsInstruction),
+
+  /// Checks that the authority is `create_program_address([pool, nonce], program)`,
+  /// returns 1 when it matches and 0 otherwise in the return data.
+  ///
+  ///   0. `[]` pool account.
+  ///   1. `[]` claimed pool authority.
+  ///   2. `[]` pool program id.
+  VerifyAuthority(VerifyAuthorityInstruction),
+
+  /// Set or clear the minimum swap `amount_in` of a source mint
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetMinNotional(MinNotionalInstruction),
+
+  /// Settle the free balances of a serum open orders account to the owner's
+  /// wallets, without placing an order.
+  ///
+  ///   0. `[signer]` open orders owner.
+  ///   1. `[writable]` serum-dex open_orders, owned by the owner or by the
+  ///      open orders authority PDA of the owner.
+  ///   2. `[writable]` serum-dex market
+  ///   3. `[writable]` serum-dex coin_vault
+  ///   4. `[writable]` serum-dex pc_vault
+  ///   5. `[writable]` owner coin wallet
+  ///   6. `[writable]` owner pc wallet
+  ///   7. `[]` serum-dex vault_signer
+  ///   8. `[]` Token program id
+  ///   9. `[]` serum-dex program id
+  ///   10. `[optional]` open orders authority PDA, when open_orders is the
+  ///       program-owned account.
+  SettleOpenOrders,
+
+  /// Enable or disable swaps through an exchanger
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetExchangerEnabled(ExchangerEnabledInstruction),
+
+  /// Initialize a SwapInfo, bind it to the intermediate token account and
+  /// swap the In leg of a route through `exchanger`. Same accounts as the In
+  /// swap of the exchanger, the SwapInfo must not be initialized yet.
+  ///     0. `[writable]` User token SOURCE Account
+  ///     1. `[writable]` User intermediate token account, owned by the user.
+  ///     2. `[signer]` User token SOURCE account OWNER, owner of the SwapInfo.
+  ///     3. `[writable]` SwapInfo account, rent exempt and owned by the program.
+  ///     4. `[]` Token program id
+  ///     5. ..`[]` exchanger accounts
+  StartRoute(StartRouteInstruction),
+
+  /// Set or clear the protocol config fee discount of the holders of a mint
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetFeeDiscount(FeeDiscountInstruction),
+
+  /// Close the program-owned serum open orders account of the owner and
+  /// return its rent to the owner. The account must be settled and hold no
+  /// orders.
+  ///
+  ///   0. `[writable, signer]` open orders owner, receives the rent.
+  ///   1. `[writable]` serum-dex open_orders, the open orders PDA of the owner.
+  ///   2. `[writable]` serum-dex market
+  ///   3. `[]` open orders authority PDA
+  ///   4. `[]` serum-dex program id
+  CloseSerumOpenOrders,
+
+  /// Set the largest surplus over `expect_amount_out` the output fee is
+  /// taken on
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetMaxSurplus(MaxSurplusInstruction),
+
+  /// Close a SwapInfo created, set up or reset more than the config
+  /// `swap_info_ttl_slots` ago and not between the In and the Out leg of a
+  /// route, the rent goes to the SwapInfo owner. Anyone may call it. Legacy
+  /// SwapInfos with no recorded slot are never stale.
+  ///
+  ///   0. `[writable]` SwapInfo account
+  ///   1. `[writable]` SwapInfo owner, receives the rent.
+  ///   2. `[]` The protocol config account
+  CloseStaleSwapInfo,
+
+  /// Set the slots after which anyone may close a SwapInfo
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetSwapInfoTtl(SwapInfoTtlInstruction),
+
+  /// Swap a two legs route through a transient intermediate token account,
+  /// without SwapInfo. The account is created at the PDA of
+  /// `["transient", owner, intermediate mint]`, owned by the user, and closed
+  /// once the Out leg emptied it.
+  ///
+  ///   0. `[writable]` User token SOURCE Account
+  ///   1. `[writable]` User token DESTINATION Account
+  ///   2. `[writable, signer]` User token SOURCE account OWNER, pays the
+  ///      transient account rent and gets it back.
+  ///   3. `[writable]` transient intermediate token account, not created yet.
+  ///   4. `[]` intermediate mint
+  ///   5. `[]` Token program id
+  ///   6. `[]` System program id
+  ///   7. `[writable]` fee token account, holds the DESTINATION mint.
+  ///   8. ..`[]` In leg exchanger accounts, then Out leg exchanger accounts,
+  ///      then the discount token account with
+  ///      `TRANSIENT_ROUTE_FLAG_FEE_DISCOUNT`, then the config treasury token
+  ///      account when one is set.
+  SwapTransientRoute(TransientRouteInstruction),
+
+  /// Set the raw units the protocol fee is rounded down to a multiple of
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetFeeGranularity(FeeGranularityInstruction),
+
+  /// Set or clear the DAO treasury token account and its share of the fee
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetTreasury(TreasuryInstruction),
+
+  /// Return the live fee rate, owner and paused flag of the protocol config,
+  /// encoded as a `state::ConfigView` in the return data
+  ///   0. `[]` The protocol config account
+  GetConfig,
+
+  /// Set or remove the absolute fee cap of a mint
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetMintFeeCap(MintFeeCapInstruction),
+
+  /// Turn on or off collecting the spl-token-swap host fee. When on, direct
+  /// spl-token-swap swaps on pools with a host fee must pass a pool token
+  /// account owned by the fee owner as the host fee account.
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetCollectHostFee(CollectHostFeeInstruction),
+
+  /// Quote a route leg by leg from the pool states, without any CPI, then
+  /// take the protocol output fee. Returns the net DESTINATION amount (u64)
+  /// as return data. Legs without a quote (serum) fail the whole route.
+  ///   0. `[]` The protocol config account
+  ///   1. `[]` route mints, one more than the legs: SOURCE, intermediates, DESTINATION
+  ///   2.. pool accounts of each leg, in order, as for its single step swap.
+  QuoteRoute(QuoteRouteInstruction),
+
+  /// Set the In leg rebate share, the cap is set per mint on its rebate vault
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetRebate(RebateInstruction),
+
+  /// Set the most distinct accounts `SwapWithFallback` and
+  /// `SwapTransientRoute` may take, routes over it fail early with
+  /// `InvalidAccountsLength`
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetMaxRouteAccounts(MaxRouteAccountsInstruction),
+
+  /// Swap tokens through Raydium CP-Swap
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[]` Token program id
+  ///   4. `[writable]` fee token account
+  ///
+  ///   5. `[writable]` RaydiumCpmm pool state account.
+  ///   6. `[]` RaydiumCpmm amm config account.
+  ///   7. `[]` RaydiumCpmm vault and lp mint authority.
+  ///   8. `[writable]` RaydiumCpmm token_0 vault.
+  ///   9. `[writable]` RaydiumCpmm token_1 vault.
+  ///   10. `[]` RaydiumCpmm token_0 mint.
+  ///   11. `[]` RaydiumCpmm token_1 mint.
+  ///   12. `[writable]` RaydiumCpmm observation state account.
+  ///   13. `[]` RaydiumCpmm program id.
+  SwapRaydiumCpmm(SwapInstruction),
+
+  /// SwapIn by Raydium CP-Swap
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` Protocol SwapInfo account
+  ///   4. '[]` Token program id.
+  ///
+  ///   5. `[writable]` RaydiumCpmm pool state account.
+  ///   6. `[]` RaydiumCpmm amm config account.
+  ///   7. `[]` RaydiumCpmm vault and lp mint authority.
+  ///   8. `[writable]` RaydiumCpmm token_0 vault.
+  ///   9. `[writable]` RaydiumCpmm token_1 vault.
+  ///   10. `[]` RaydiumCpmm token_0 mint.
+  ///   11. `[]` RaydiumCpmm token_1 mint.
+  ///   12. `[writable]` RaydiumCpmm observation state account.
+  ///   13. `[]` RaydiumCpmm program id.
+  SwapRaydiumCpmmIn(SwapInInstruction),
+
+  /// SwapOut by Raydium CP-Swap
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` SwapInfo account
+  ///   4. '[]` Token program id.
+  ///   5. `[writable]` fee token account.
+  ///
+  ///   6. `[writable]` RaydiumCpmm pool state account.
+  ///   7. `[]` RaydiumCpmm amm config account.
+  ///   8. `[]` RaydiumCpmm vault and lp mint authority.
+  ///   9. `[writable]` RaydiumCpmm token_0 vault.
+  ///   10. `[writable]` RaydiumCpmm token_1 vault.
+  ///   11. `[]` RaydiumCpmm token_0 mint.
+  ///   12. `[]` RaydiumCpmm token_1 mint.
+  ///   13. `[writable]` RaydiumCpmm observation state account.
+  ///   14. `[]` RaydiumCpmm program id.
+  SwapRaydiumCpmmOut(SwapOutInstruction),
+
+  /// Builds the CPI of every exchanger with dummy accounts and checks the
+  /// accounts handed to each invoke match its instruction. Debug builds only,
+  /// a no-op in release builds. No accounts.
+  SelfTest,
+
+  /// Swap through a primary pool, or through a backup spl-token-swap pool of
+  /// the same pair when the primary fails to parse, is blocked or quotes
+  /// below `minimum_amount_out`. The pool is picked before any CPI. Returns
+  /// `[exchanger, 0 primary or 1 backup]` as return data.
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[]` Token program id
+  ///   4. `[writable]` fee token account
+  ///   5.. accounts of the primary pool, as for its single step swap, then
+  ///       the accounts of the backup spl-token-swap pool, then the config
+  ///       treasury token account when one is set.
+  SwapWithBackup(SwapWithBackupInstruction),
+
+  /// Swap native SOL through a transient WSOL account at the PDA of
+  /// `["transient", owner, native mint]`. The owner funds it with
+  /// `amount_in` lamports and its rent, or only the rent when unwrapping, and
+  /// gets every lamport left back once it is closed after the swap.
+  ///
+  ///   0. `[writable]` User token DESTINATION account, or SOURCE account when
+  ///      unwrapping.
+  ///   1. `[writable, signer]` User system account, owner of the token account.
+  ///   2. `[writable]` transient WSOL account, not created yet.
+  ///   3. `[]` native mint
+  ///   4. `[]` Token program id
+  ///   5. `[]` System program id
+  ///   6. `[writable]` fee token account, holds the DESTINATION mint. Unused
+  ///      with `lamport_fee`.
+  ///   7. ..`[]` exchanger accounts, then the config treasury token account
+  ///      when one is set.
+  ///   8. `[writable]` fee owner system account, only with `lamport_fee`.
+  NativeSwap(NativeSwapInstruction),
+
+  /// Add a protocol owned wallet to the config fee-exempt owners, its swaps
+  /// skip the protocol fee
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  AddFeeExemptOwner(FeeExemptOwnerInstruction),
+
+  /// Remove a wallet from the config fee-exempt owners
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  RemoveFeeExemptOwner(FeeExemptOwnerInstruction),
+
+  /// Set or clear the protocol config signer attesting `expect_amount_out`
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetExpectOracle(ExpectOracleInstruction),
+
+  /// Set the largest `expect_amount_out` of a swap, as a multiple of
+  /// `amount_in`, checked by swaps with `SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO`
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetMaxExpectOutRatio(MaxExpectOutRatioInstruction),
+
+  /// Create the `ExchangerStats` counter of an exchanger, at
+  /// `state::derive_exchanger_stats_address`
+  ///   0. `[writable]` exchanger stats account, not created yet
+  ///   1. `[writable, signer]` payer
+  ///   2. `[]` System program id
+  InitializeExchangerStats(ExchangerStatsInstruction),
+
+  /// Turn on or off counting direct swaps per exchanger. When on, direct
+  /// swaps must pass the `ExchangerStats` account of their exchanger with
+  /// `SWAP_EXTRA_FLAG_EXCHANGER_STATS`, which serializes them per exchanger.
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetCollectExchangerStats(CollectExchangerStatsInstruction),
+
+  /// Return `[exchanger][swap_count]` (u8, u64) of every passed counter, in
+  /// account order
+  ///   0. ..`[]` exchanger stats accounts
+  GetExchangerStats,
+
+  /// Append a pool to the pool registry, created at
+  /// `state::derive_pool_registry_address` by the first call. The index of
+  /// the pool (u8) is set as the return data.
+  ///   0. `[]` The protocol config account
+  ///   1. `[writable, signer]` config owner account, pays for the registry
+  ///   2. `[writable]` pool registry account
+  ///   3. `[]` System program id
+  ///   4. ..`[]` exchanger accounts of the pool, as passed to a direct swap
+  RegisterPool(RegisterPoolInstruction),
+
+  /// Direct swap through a registered pool, the exchanger comes from the
+  /// registry and the pool accounts must match the registered ones
+  ///   0-5. accounts of a direct swap
+  ///   6. `[]` pool registry account
+  ///   7. ..`[]` the registered pool accounts, then the accounts of the
+  ///      `SWAP_FLAG_*` and `SWAP_EXTRA_FLAG_*` set
+  SwapByIndex(SwapByIndexInstruction),
+
+  /// Swap one source token into two destinations, each leg a direct swap of
+  /// its own part of the source with its own slippage check and fee. Once
+  /// the config has a treasury it takes its share of the fee of each leg, a
+  /// leg charging a fee must then output the treasury mint.
+  ///   0. `[writable]` User token SOURCE account
+  ///   1. `[signer]` User token SOURCE account OWNER
+  ///   2. `[]` Token program id
+  ///   3. `[writable]` User token DESTINATION account of the first leg
+  ///   4. `[writable]` fee token account of the first leg DESTINATION mint
+  ///   5. `[writable]` User token DESTINATION account of the second leg
+  ///   6. `[writable]` fee token account of the second leg DESTINATION mint
+  ///   7. ..`[]` exchanger accounts of the first leg, then of the second,
+  ///      then the config treasury token account when one is set.
+  MultiOutputSwap(MultiOutputSwapInstruction),
+
+  /// Return the packed `ExchangerSpec` of every exchanger, 4 bytes each in
+  /// discriminant order, so clients need not hardcode the account counts.
+  /// Takes no account.
+  GetExchangerSpec,
+
+  /// Create the `FeeAccrual` of an owner in a mint, at
+  /// `state::derive_fee_accrual_address`. Only owners given one can swap with
+  /// `SWAP_EXTRA_FLAG_ACCRUE_FEE`.
+  ///   0. `[]` The protocol config account
+  ///   1. `[writable, signer]` config owner account, pays for the accrual
+  ///   2. `[writable]` fee accrual account, not created yet
+  ///   3. `[]` System program id
+  InitializeFeeAccrual(FeeAccrualInstruction),
+
+  /// Pay the fees owed in a `FeeAccrual` to the fee token account and reset
+  /// it. The paid amount (u64) is set as the return data.
+  ///   0. `[writable]` fee accrual account
+  ///   1. `[signer]` owner of the accrual
+  ///   2. `[writable]` owner token account of the accrual mint
+  ///   3. `[writable]` fee token account of the accrual mint
+  ///   4. `[]` Token program id
+  SettleAccruedFee,
+
+  /// Direct swap of `percent_bps` of the source account balance, read when
+  /// the instruction runs.
+  ///   0..5. as for the single step swap of `exchanger`
+  ///   6.. exchanger accounts, the config treasury token account passed as
+  ///       with `SWAP_FLAG_TREASURY` once the config has a treasury.
+  SwapPercent(SwapPercentInstruction),
+
+  /// Swap tokens through an Orca v1 pool
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[]` Token program id
+  ///   4. `[writable]` fee token account
+  ///
+  ///   5. `[]` OrcaV1 swap_info account
+  ///   6. `[]` OrcaV1 swap_info authority
+  ///   7. `[writable]` OrcaV1 token_A Account.
+  ///   8. `[writable]` OrcaV1 token_B Account.
+  ///   9. `[writable]` OrcaV1 Pool token mint, to generate trading fees
+  ///   10. `[writable]` OrcaV1 Fee account, to receive trading fees
+  ///   11. '[]` OrcaV1 program id
+  SwapOrcaV1(SwapInstruction),
+
+  /// SwapIn by Orca v1
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` Protocol SwapInfo account
+  ///   4. '[]` Token program id.
+  ///
+  ///   5.. OrcaV1 accounts, as for `SwapOrcaV1`.
+  SwapOrcaV1In(SwapInInstruction),
+
+  /// SwapOut by Orca v1
+  ///   0. `[writable]` User token SOURCE Account, (coin_wallet).
+  ///   1. `[writable]` User token DESTINATION Account to swap INTO. Must be the DESTINATION token.
+  ///   2. `[signer]` User token SOURCE account OWNER (or Authority) account.
+  ///   3. '[writable]` SwapInfo account
+  ///   4. '[]` Token program id.
+  ///   5. `[writable]` fee token account.
+  ///
+  ///   6.. OrcaV1 accounts, as for `SwapOrcaV1`.
+  SwapOrcaV1Out(SwapOutInstruction),
+
+  /// Add a pool creator to the `TrustedPoolCreators` list, created at
+  /// `state::derive_trusted_pool_creators_address` on first use
+  ///   0. `[]` The protocol config account
+  ///   1. `[writable, signer]` config owner account, pays for the list
+  ///   2. `[writable]` trusted pool creators account
+  ///   3. `[]` System program id
+  AddTrustedPoolCreator(TrustedPoolCreatorInstruction),
+
+  /// Remove a pool creator from the `TrustedPoolCreators` list
+  ///   0. `[]` The protocol config account
+  ///   1. `[signer]` config owner account
+  ///   2. `[writable]` trusted pool creators account
+  RemoveTrustedPoolCreator(TrustedPoolCreatorInstruction),
+
+  /// Turn on or off checking pool creators. When on, swaps passing the
+  /// `TrustedPoolCreators` account before the config account fail with
+  /// `UntrustedPoolCreator` on pools whose creator isn't listed, or can't
+  /// be read from the pool accounts. Other swaps are not checked, so this is
+  /// a filter integrators opt into per swap, not a protocol wide restriction.
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetCheckPoolCreators(CheckPoolCreatorsInstruction),
+
+  /// Create, or update the cap of, the rebate vault of a mint. The vault
+  /// PDA owns the token account In leg rebates of that mint are paid from,
+  /// apart from the fees `RecoverFees` drains.
+  ///   0. `[]` The protocol config account
+  ///   1. `[writable, signer]` config owner account, pays for the vault
+  ///   2. `[writable]` rebate vault, PDA of `[REBATE_VAULT_SEED, mint]`
+  ///   3. `[]` vault token account, owned by the rebate vault
+  ///   4. `[]` System program id
+  SetRebateVault(RebateVaultInstruction),
+
+  /// Set or remove the `expect_amount_out` of a DESTINATION mint from which
+  /// swaps must be attested by the config `expect_oracle`. Swaps that can't
+  /// pass the oracle fail over the threshold.
+  ///   0. `[writable]` The protocol config account
+  ///   1. `[signer]` config owner account
+  SetExpectOracleThreshold(ExpectOracleThresholdInstruction),
+}
+
+impl ProtocolInstruction {
+  /// Unpacks a byte buffer into a [OneSolInstruction](enum.OneSolInstruction.html).
+  pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    let (&tag, rest) = input.split_first().ok_or(ProtocolError::InvalidInput)?;
+    Ok(match tag {
+      3 => Self::SwapSplTokenSwap(SwapInstruction::unpack(rest)?),
+      4 => Self::SwapSerumDex(SwapInstruction::unpack(rest)?),
+      5 => return Err(ProtocolError::InvalidInstruction.into()),
+      6 => Self::SwapStableSwap(SwapInstruction::unpack(rest)?),
+      8 => return Err(ProtocolError::InvalidInstruction.into()),
+      9 => Self::SwapRaydiumSwap(SwapInstruction::unpack(rest)?),
+      10 => Self::InitializeSwapInfo(InitializeSwapInfoInstruction::unpack(rest)?),
+      11 => Self::SetupSwapInfo,
+      12 => Self::SwapSplTokenSwapIn(SwapInInstruction::unpack(rest)?),
+      13 => Self::SwapSplTokenSwapOut(SwapOutInstruction::unpack(rest)?),
+      14 => Self::SwapSerumDexIn(SwapInInstruction::unpack(rest)?),
+      15 => Self::SwapSerumDexOut(SwapOutInstruction::unpack(rest)?),
+      16 => Self::SwapStableSwapIn(SwapInInstruction::unpack(rest)?),
+      17 => Self::SwapStableSwapOut(SwapOutInstruction::unpack(rest)?),
+      18 => Self::SwapRaydiumIn(SwapInInstruction::unpack(rest)?),
+      19 => Self::SwapRaydiumOut(SwapOutInstruction::unpack(rest)?),
+      20 => Self::SwapRaydiumIn2(SwapInInstruction::unpack(rest)?),
+      21 => Self::SwapRaydiumOut2(SwapOutSlimInstruction::unpack(rest)?),
+      22 => Self::SwapCremaFinance(SwapInstruction::unpack(rest)?),
+      23 => Self::SwapCremaFinanceIn(SwapInInstruction::unpack(rest)?),
+      24 => Self::SwapCremaFinanceOut(SwapOutInstruction::unpack(rest)?),
+      25 => Self::SwapAldrinExchange(SwapInstruction::unpack(rest)?),
+      26 => Self::SwapAldrinExchangeIn(SwapInInstruction::unpack(rest)?),
+      27 => Self::SwapAldrinExchangeOut(SwapOutInstruction::unpack(rest)?),
+      28 => Self::SwapCropperFinance(SwapInstruction::unpack(rest)?),
+      29 => Self::SwapCropperFinanceIn(SwapInInstruction::unpack(rest)?),
+      30 => Self::SwapCropperFinanceOut(SwapOutInstruction::unpack(rest)?),
+      31 => Self::CloseSwapInfo,
+      32 => Self::RecoverFees(RecoverFeesInstruction::unpack(rest)?),
+      33 => Self::SwapStableSwapGuard(SwapStableSwapGuardInstruction::unpack(rest)?),
+      34 => Self::InitializeConfig,
+      35 => Self::AddBlockedPool(BlockedPoolInstruction::unpack(rest)?),
+      36 => Self::RemoveBlockedPool(BlockedPoolInstruction::unpack(rest)?),
+      37 => Self::ResetSwapInfo,
+      38 => Self::SwapMeteoraDamm(SwapInstruction::unpack(rest)?),
+      39 => Self::SwapMeteoraDammIn(SwapInInstruction::unpack(rest)?),
+      40 => Self::SwapMeteoraDammOut(SwapOutInstruction::unpack(rest)?),
+      41 => Self::SetFeeReferenceMint(FeeReferenceMintInstruction::unpack(rest)?),
+      42 => Self::SetComputeHint,
+      43 => Self::SwapWithFallback(SwapWithFallbackInstruction::unpack(rest)?),
+      44 => Self::EstimateComputeUnits(EstimateComputeUnitsInstruction::unpack(rest)?),
+      45 => Self::VerifyAuthority(VerifyAuthorityInstruction::unpack(rest)?),
+      46 => Self::SetMinNotional(MinNotionalInstruction::unpack(rest)?),
+      47 => Self::SettleOpenOrders,
+      48 => Self::SetExchangerEnabled(ExchangerEnabledInstruction::unpack(rest)?),
+      49 => Self::StartRoute(StartRouteInstruction::unpack(rest)?),
+      50 => Self::SetFeeDiscount(FeeDiscountInstruction::unpack(rest)?),
+      51 => Self::CloseSerumOpenOrders,
+      52 => Self::SetMaxSurplus(MaxSurplusInstruction::unpack(rest)?),
+      53 => Self::CloseStaleSwapInfo,
+      54 => Self::SetSwapInfoTtl(SwapInfoTtlInstruction::unpack(rest)?),
+      55 => Self::SwapTransientRoute(TransientRouteInstruction::unpack(rest)?),
+      56 => Self::SetFeeGranularity(FeeGranularityInstruction::unpack(rest)?),
+      57 => Self::SetTreasury(TreasuryInstruction::unpack(rest)?),
+      58 => Self::GetConfig,
+      59 => Self::SetMintFeeCap(MintFeeCapInstruction::unpack(rest)?),
+      60 => Self::SetCollectHostFee(CollectHostFeeInstruction::unpack(rest)?),
+      61 => Self::QuoteRoute(QuoteRouteInstruction::unpack(rest)?),
+      62 => Self::SetRebate(RebateInstruction::unpack(rest)?),
+      63 => Self::SetMaxRouteAccounts(MaxRouteAccountsInstruction::unpack(rest)?),
+      64 => Self::SwapRaydiumCpmm(SwapInstruction::unpack(rest)?),
+      65 => Self::SwapRaydiumCpmmIn(SwapInInstruction::unpack(rest)?),
+      66 => Self::SwapRaydiumCpmmOut(SwapOutInstruction::unpack(rest)?),
+      67 => Self::SelfTest,
+      68 => Self::SwapWithBackup(SwapWithBackupInstruction::unpack(rest)?),
+      69 => Self::NativeSwap(NativeSwapInstruction::unpack(rest)?),
+      70 => Self::AddFeeExemptOwner(FeeExemptOwnerInstruction::unpack(rest)?),
+      71 => Self::RemoveFeeExemptOwner(FeeExemptOwnerInstruction::unpack(rest)?),
+      72 => Self::SetExpectOracle(ExpectOracleInstruction::unpack(rest)?),
+      73 => Self::SetMaxExpectOutRatio(MaxExpectOutRatioInstruction::unpack(rest)?),
+      74 => Self::InitializeExchangerStats(ExchangerStatsInstruction::unpack(rest)?),
+      75 => Self::SetCollectExchangerStats(CollectExchangerStatsInstruction::unpack(rest)?),
+      76 => Self::GetExchangerStats,
+      77 => Self::RegisterPool(RegisterPoolInstruction::unpack(rest)?),
+      78 => Self::SwapByIndex(SwapByIndexInstruction::unpack(rest)?),
+      79 => Self::MultiOutputSwap(MultiOutputSwapInstruction::unpack(rest)?),
+      80 => Self::GetExchangerSpec,
+      81 => Self::InitializeFeeAccrual(FeeAccrualInstruction::unpack(rest)?),
+      82 => Self::SettleAccruedFee,
+      83 => Self::SwapPercent(SwapPercentInstruction::unpack(rest)?),
+      84 => Self::SwapOrcaV1(SwapInstruction::unpack(rest)?),
+      85 => Self::SwapOrcaV1In(SwapInInstruction::unpack(rest)?),
+      86 => Self::SwapOrcaV1Out(SwapOutInstruction::unpack(rest)?),
+      87 => Self::AddTrustedPoolCreator(TrustedPoolCreatorInstruction::unpack(rest)?),
+      88 => Self::RemoveTrustedPoolCreator(TrustedPoolCreatorInstruction::unpack(rest)?),
+      89 => Self::SetCheckPoolCreators(CheckPoolCreatorsInstruction::unpack(rest)?),
+      90 => Self::SetRebateVault(RebateVaultInstruction::unpack(rest)?),
+      91 => Self::SetExpectOracleThreshold(ExpectOracleThresholdInstruction::unpack(rest)?),
+      _ => return Err(ProtocolError::InvalidInstruction.into()),
+    })
+  }
+}
+
+impl SwapInstruction {
+  const DATA_LEN: usize = 24;
+
+  // size = 1 or 3
+  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, SwapInstruction::DATA_LEN];
+    let (&amount_in_arr, &expect_amount_out_arr, &minimum_amount_out_arr) =
+      array_refs![arr_data, 8, 8, 8];
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
+    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    if expect_amount_out.get() < minimum_amount_out.get() || expect_amount_out.get() == 0 {
+      return Err(ProtocolError::InvalidExpectAmountOut.into());
+    }
+    let flags = input
+      .get(SwapInstruction::DATA_LEN)
+      .copied()
+      .unwrap_or_default();
+    if flags & !SWAP_FLAGS_ALL != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let fee_flags = SWAP_FLAG_FEE_ON_INPUT | SWAP_FLAG_FEE_IN_REFERENCE_MINT;
+    if flags & fee_flags == fee_flags {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let min_notional_in = if input.len() >= SwapInstruction::DATA_LEN + 9 {
+      u64::from_le_bytes(*array_ref![input, SwapInstruction::DATA_LEN + 1, 8])
+    } else {
+      0
+    };
+    let max_price_impact_bps = if input.len() >= SwapInstruction::DATA_LEN + 11 {
+      u16::from_le_bytes(*array_ref![input, SwapInstruction::DATA_LEN + 9, 2])
+    } else {
+      0
+    };
+    let expected_side = match input.get(SwapInstruction::DATA_LEN + 11).copied() {
+      None | Some(NO_EXPECTED_SIDE) => None,
+      Some(side) if side > 1 => return Err(ProtocolError::InvalidInput.into()),
+      side => side,
+    };
+    let client_route_id = if input.len() >= SwapInstruction::DATA_LEN + 20 {
+      Some(u64::from_le_bytes(*array_ref![
+        input,
+        SwapInstruction::DATA_LEN + 12,
+        8
+      ]))
+      .filter(|id| *id != NO_CLIENT_ROUTE_ID)
+    } else {
+      None
+    };
+    let reference_price = if input.len() >= SwapInstruction::DATA_LEN + 38 {
+      let (&num, &den, &max_drift_bps) = array_refs![
+        array_ref![input, SwapInstruction::DATA_LEN + 20, 18],
+        8,
+        8,
+        2
+      ];
+      let (num, den) = (u64::from_le_bytes(num), u64::from_le_bytes(den));
+      match (num, den) {
+        (0, 0) => None,
+        (0, _) | (_, 0) => return Err(ProtocolError::InvalidInput.into()),
+        _ => Some(ReferencePrice {
+          num,
+          den,
+          max_drift_bps: u16::from_le_bytes(max_drift_bps),
+        }),
+      }
+    } else {
+      None
+    };
+    let extra_flags = input
+      .get(SwapInstruction::DATA_LEN + 38)
+      .copied()
+      .unwrap_or_default();
+    if extra_flags & !SWAP_EXTRA_FLAGS_ALL != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    // only an output fee is accrued, in the destination mint
+    if extra_flags & SWAP_EXTRA_FLAG_ACCRUE_FEE != 0 && flags & fee_flags != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let expected_pool = if input.len() >= SwapInstruction::DATA_LEN + 71 {
+      Some(Pubkey::new_from_array(*array_ref![
+        input,
+        SwapInstruction::DATA_LEN + 39,
+        32
+      ]))
+      .filter(|pool| *pool != Pubkey::default())
+    } else {
+      None
+    };
+    let min_source_remaining = if input.len() >= SwapInstruction::DATA_LEN + 79 {
+      u64::from_le_bytes(*array_ref![input, SwapInstruction::DATA_LEN + 71, 8])
+    } else {
+      0
+    };
+    Ok(SwapInstruction {
+      amount_in,
+      expect_amount_out,
+      minimum_amount_out,
+      flags,
+      min_notional_in,
+      max_price_impact_bps,
+      expected_side,
+      client_route_id,
+      reference_price,
+      extra_flags,
+      expected_pool,
+      min_source_remaining,
+    })
+  }
+
+  fn pack_into(&self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&self.amount_in.get().to_le_bytes());
+    buf.extend_from_slice(&self.expect_amount_out.get().to_le_bytes());
+    buf.extend_from_slice(&self.minimum_amount_out.get().to_le_bytes());
+    buf.push(self.flags);
+    buf.extend_from_slice(&self.min_notional_in.to_le_bytes());
+    buf.extend_from_slice(&self.max_price_impact_bps.to_le_bytes());
+    let has_extra_flags =
+      self.extra_flags != 0 || self.expected_pool.is_some() || self.min_source_remaining != 0;
+    if self.expected_side.is_some()
+      || self.client_route_id.is_some()
+      || self.reference_price.is_some()
+      || has_extra_flags
+    {
+      buf.push(self.expected_side.unwrap_or(NO_EXPECTED_SIDE));
+    }
+    if self.client_route_id.is_some() || self.reference_price.is_some() || has_extra_flags {
+      let client_route_id = self.client_route_id.unwrap_or(NO_CLIENT_ROUTE_ID);
+      buf.extend_from_slice(&client_route_id.to_le_bytes());
+    }
+    if self.reference_price.is_some() || has_extra_flags {
+      let reference_price = self.reference_price.unwrap_or(ReferencePrice {
+        num: 0,
+        den: 0,
+        max_drift_bps: 0,
+      });
+      buf.extend_from_slice(&reference_price.num.to_le_bytes());
+      buf.extend_from_slice(&reference_price.den.to_le_bytes());
+      buf.extend_from_slice(&reference_price.max_drift_bps.to_le_bytes());
+    }
+    if has_extra_flags {
+      buf.push(self.extra_flags);
+    }
+    if self.expected_pool.is_some() || self.min_source_remaining != 0 {
+      buf.extend_from_slice(self.expected_pool.unwrap_or_default().as_ref());
+    }
+    if self.min_source_remaining != 0 {
+      buf.extend_from_slice(&self.min_source_remaining.to_le_bytes());
+    }
+  }
+
+  pub fn cancel_resting_order(&self) -> bool {
+    self.flags & SWAP_FLAG_SERUM_CANCEL_RESTING_ORDER != 0
+  }
+
+  pub fn require_rent_exempt_destination(&self) -> bool {
+    self.flags & SWAP_FLAG_REQUIRE_RENT_EXEMPT_DESTINATION != 0
+  }
+
+  pub fn fee_in_reference_mint(&self) -> bool {
+    self.flags & SWAP_FLAG_FEE_IN_REFERENCE_MINT != 0
+  }
+
+  pub fn check_amount_in_supply(&self) -> bool {
+    self.flags & SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY != 0
+  }
+
+  pub fn fee_on_input(&self) -> bool {
+    self.flags & SWAP_FLAG_FEE_ON_INPUT != 0
+  }
+
+  pub fn fee_discount(&self) -> bool {
+    self.flags & SWAP_FLAG_FEE_DISCOUNT != 0
+  }
+
+  pub fn treasury(&self) -> bool {
+    self.flags & SWAP_FLAG_TREASURY != 0
+  }
+
+  pub fn check_serum_settle(&self) -> bool {
+    self.flags & SWAP_FLAG_SERUM_CHECK_SETTLE != 0
+  }
+
+  pub fn require_destination_owned_by_signer(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_DESTINATION_OWNED_BY_SIGNER != 0
+  }
+
+  pub fn oracle_expect_amount_out(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT != 0
+  }
+
+  pub fn check_expect_out_ratio(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO != 0
+  }
+
+  pub fn exchanger_stats(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_EXCHANGER_STATS != 0
+  }
+
+  pub fn no_clamp(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_NO_CLAMP != 0
+  }
+
+  pub fn accrue_fee(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_ACCRUE_FEE != 0
+  }
+
+  pub fn validate_only(&self) -> bool {
+    self.extra_flags & SWAP_EXTRA_FLAG_VALIDATE_ONLY != 0
+  }
+}
+
+impl SwapInInstruction {
+  const DATA_LEN: usize = 8;
+
+  // size = 1 or 3
+  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapInInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
     }
     let &amount_in_arr = array_ref![input, 0, SwapInInstruction::DATA_LEN];
     let amount_in =
       NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
-    Ok(Self { amount_in })
+    let flags = input
+      .get(SwapInInstruction::DATA_LEN)
+      .copied()
+      .unwrap_or_default();
+    if flags & !SWAP_IN_FLAGS_ALL != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let (rebate_nonce, rebate_pool_index) = if flags & SWAP_IN_FLAG_REBATE != 0 {
+      match input.get(SwapInInstruction::DATA_LEN + 1..SwapInInstruction::DATA_LEN + 3) {
+        Some(&[rebate_nonce, rebate_pool_index]) => (rebate_nonce, rebate_pool_index),
+        _ => return Err(ProtocolError::InvalidInput.into()),
+      }
+    } else {
+      (0, 0)
+    };
+    Ok(Self {
+      amount_in,
+      flags,
+      rebate_nonce,
+      rebate_pool_index,
+    })
+  }
+
+  pub fn rebate(&self) -> bool {
+    self.flags & SWAP_IN_FLAG_REBATE != 0
+  }
+}
+
+impl SwapOutInstruction {
+  const DATA_LEN: usize = 16;
+
+  // size = 1 or 3
+  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapOutInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, SwapOutInstruction::DATA_LEN];
+    let (&expect_amount_out_arr, &minimum_amount_out_arr) = array_refs![arr_data, 8, 8];
+    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    if expect_amount_out.get() < minimum_amount_out.get() || expect_amount_out.get() == 0 {
+      return Err(ProtocolError::InvalidExpectAmountOut.into());
+    }
+    let flags = input
+      .get(SwapOutInstruction::DATA_LEN)
+      .copied()
+      .unwrap_or_default();
+    if flags & !SWAP_OUT_FLAGS_ALL != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let client_route_id = if input.len() >= SwapOutInstruction::DATA_LEN + 9 {
+      Some(u64::from_le_bytes(*array_ref![
+        input,
+        SwapOutInstruction::DATA_LEN + 1,
+        8
+      ]))
+    } else {
+      None
+    };
+    let input_minimum = if flags & SWAP_OUT_FLAG_MINIMUM_FROM_INPUT != 0 {
+      if input.len() < SwapOutInstruction::DATA_LEN + 19 {
+        return Err(ProtocolError::InvalidInput.into());
+      }
+      let arr_data = array_ref![input, SwapOutInstruction::DATA_LEN + 9, 10];
+      let (&amount_in_arr, &min_out_bps_of_in_arr) = array_refs![arr_data, 8, 2];
+      let amount_in =
+        NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
+      let min_out_bps_of_in = u16::from_le_bytes(min_out_bps_of_in_arr);
+      if min_out_bps_of_in == 0 || min_out_bps_of_in > 10_000 {
+        return Err(ProtocolError::InvalidInput.into());
+      }
+      Some(InputMinimum {
+        amount_in,
+        min_out_bps_of_in,
+      })
+    } else {
+      None
+    };
+    Ok(Self {
+      expect_amount_out,
+      minimum_amount_out,
+      flags,
+      client_route_id,
+      input_minimum,
+    })
+  }
+
+  pub fn refund_residual(&self) -> bool {
+    self.flags & SWAP_OUT_FLAG_REFUND_RESIDUAL != 0
+  }
+
+  pub fn fee_discount(&self) -> bool {
+    self.flags & SWAP_OUT_FLAG_FEE_DISCOUNT != 0
+  }
+
+  pub fn treasury(&self) -> bool {
+    self.flags & SWAP_OUT_FLAG_TREASURY != 0
+  }
+
+  pub fn close_on_complete(&self) -> bool {
+    self.flags & SWAP_OUT_FLAG_CLOSE_ON_COMPLETE != 0
+  }
+}
+
+impl SwapOutSlimInstruction {
+  const DATA_LEN: usize = 8;
+
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapOutSlimInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let &minimum_amount_out_arr = array_ref![input, 0, SwapOutSlimInstruction::DATA_LEN];
+    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self { minimum_amount_out })
+  }
+}
+
+impl SwapStableSwapGuardInstruction {
+  const DATA_LEN: usize = 18;
+
+  // [amount_in], [expect_amount_out], [min_out_bps_of_in]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapStableSwapGuardInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, SwapStableSwapGuardInstruction::DATA_LEN];
+    let (&amount_in_arr, &expect_amount_out_arr, &min_out_bps_of_in_arr) =
+      array_refs![arr_data, 8, 8, 2];
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
+    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    let min_out_bps_of_in = u16::from_le_bytes(min_out_bps_of_in_arr);
+    if min_out_bps_of_in == 0 || min_out_bps_of_in > 10_000 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      amount_in,
+      expect_amount_out,
+      min_out_bps_of_in,
+    })
+  }
+}
+
+impl BlockedPoolInstruction {
+  const DATA_LEN: usize = 32;
+
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < BlockedPoolInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let pool = Pubkey::new_from_array(*array_ref![input, 0, BlockedPoolInstruction::DATA_LEN]);
+    Ok(Self { pool })
+  }
+}
+
+impl FeeExemptOwnerInstruction {
+  const DATA_LEN: usize = 32;
+
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < FeeExemptOwnerInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let owner = Pubkey::new_from_array(*array_ref![input, 0, FeeExemptOwnerInstruction::DATA_LEN]);
+    Ok(Self { owner })
+  }
+}
+
+impl SwapWithFallbackInstruction {
+  const DATA_LEN: usize = 25;
+
+  // [amount_in], [expect_amount_out], [minimum_amount_out], [count],
+  // count * ([exchanger], [accounts_len])
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapWithFallbackInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let swap = SwapInstruction::unpack(&input[..SwapInstruction::DATA_LEN])?;
+    let count = input[SwapInstruction::DATA_LEN] as usize;
+    if count == 0 || count > MAX_FALLBACK_CANDIDATES {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let candidates_data = &input[SwapWithFallbackInstruction::DATA_LEN..];
+    if candidates_data.len() < count * 2 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let candidates = candidates_data
+      .chunks_exact(2)
+      .take(count)
+      .map(|c| match (ExchangerType::from(c[0]), c[1]) {
+        (Some(exchanger), accounts_len) if accounts_len > 0 => Ok((exchanger, accounts_len)),
+        _ => Err(ProtocolError::InvalidInput),
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(Self {
+      amount_in: swap.amount_in,
+      expect_amount_out: swap.expect_amount_out,
+      minimum_amount_out: swap.minimum_amount_out,
+      candidates,
+    })
+  }
+}
+
+impl SwapWithBackupInstruction {
+  const DATA_LEN: usize = 26;
+
+  // [amount_in], [expect_amount_out], [minimum_amount_out], [exchanger], [accounts_len]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapWithBackupInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let swap = SwapInstruction::unpack(&input[..SwapInstruction::DATA_LEN])?;
+    let primary = match (
+      ExchangerType::from(input[SwapInstruction::DATA_LEN]),
+      input[SwapInstruction::DATA_LEN + 1],
+    ) {
+      (Some(exchanger), accounts_len) if accounts_len > 0 => (exchanger, accounts_len),
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self {
+      amount_in: swap.amount_in,
+      expect_amount_out: swap.expect_amount_out,
+      minimum_amount_out: swap.minimum_amount_out,
+      primary,
+    })
+  }
+}
+
+impl NativeSwapInstruction {
+  const DATA_LEN: usize = 26;
+
+  // [exchanger], [unwrap], [amount_in], [expect_amount_out], [minimum_amount_out], [lamport_fee]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < NativeSwapInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let exchanger = ExchangerType::from(input[0]).ok_or(ProtocolError::InvalidInput)?;
+    let unwrap = match input[1] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    let swap = SwapInstruction::unpack(&input[2..NativeSwapInstruction::DATA_LEN])?;
+    // only a swap into SOL has its fee in SOL
+    let lamport_fee = match input.get(NativeSwapInstruction::DATA_LEN) {
+      None | Some(0) => false,
+      Some(1) if unwrap => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self {
+      exchanger,
+      unwrap,
+      amount_in: swap.amount_in,
+      expect_amount_out: swap.expect_amount_out,
+      minimum_amount_out: swap.minimum_amount_out,
+      lamport_fee,
+    })
+  }
+}
+
+impl QuoteRouteInstruction {
+  const DATA_LEN: usize = 17;
+
+  // [amount_in], [expect_amount_out], [count], count * ([exchanger], [accounts_len])
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < QuoteRouteInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let (&amount_in_arr, &expect_amount_out_arr, &[count]) = array_refs![
+      array_ref![input, 0, QuoteRouteInstruction::DATA_LEN],
+      8,
+      8,
+      1
+    ];
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in_arr)).ok_or(ProtocolError::InvalidInput)?;
+    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
+      .ok_or(ProtocolError::InvalidInput)?;
+    let count = count as usize;
+    if count == 0 || count > MAX_QUOTE_ROUTE_LEGS {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let legs_data = &input[QuoteRouteInstruction::DATA_LEN..];
+    if legs_data.len() < count * 2 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let legs = legs_data
+      .chunks_exact(2)
+      .take(count)
+      .map(|c| match (ExchangerType::from(c[0]), c[1]) {
+        (Some(exchanger), accounts_len) if accounts_len > 0 => Ok((exchanger, accounts_len)),
+        _ => Err(ProtocolError::InvalidInput),
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(Self {
+      amount_in,
+      expect_amount_out,
+      legs,
+    })
+  }
+}
+
+impl EstimateComputeUnitsInstruction {
+  const DATA_LEN: usize = 2;
+
+  // [exchanger], [reverse]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < EstimateComputeUnitsInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let exchanger = ExchangerType::from(input[0]).ok_or(ProtocolError::InvalidInput)?;
+    let reverse = match input[1] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self { exchanger, reverse })
+  }
+}
+
+impl VerifyAuthorityInstruction {
+  // [nonce]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    let nonce = *input.first().ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self { nonce })
+  }
+}
+
+impl MinNotionalInstruction {
+  const DATA_LEN: usize = 40;
+
+  // [mint], [amount]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MinNotionalInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, MinNotionalInstruction::DATA_LEN];
+    let (mint, &amount) = array_refs![arr_data, 32, 8];
+    Ok(Self {
+      mint: Pubkey::new_from_array(*mint),
+      amount: u64::from_le_bytes(amount),
+    })
+  }
+}
+
+impl MintFeeCapInstruction {
+  const DATA_LEN: usize = 40;
+
+  // [mint], [max_fee]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MintFeeCapInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, MintFeeCapInstruction::DATA_LEN];
+    let (mint, &max_fee) = array_refs![arr_data, 32, 8];
+    Ok(Self {
+      mint: Pubkey::new_from_array(*mint),
+      max_fee: u64::from_le_bytes(max_fee),
+    })
+  }
+}
+
+impl RebateInstruction {
+  const DATA_LEN: usize = 2;
+
+  // [rebate_bps]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < RebateInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let rebate_bps = u16::from_le_bytes(*array_ref![input, 0, RebateInstruction::DATA_LEN]);
+    if rebate_bps > 10_000 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self { rebate_bps })
+  }
+}
+
+impl RebateVaultInstruction {
+  const DATA_LEN: usize = 8;
+
+  // [max_rebate]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < RebateVaultInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      max_rebate: u64::from_le_bytes(*array_ref![input, 0, RebateVaultInstruction::DATA_LEN]),
+    })
+  }
+}
+
+impl ExpectOracleThresholdInstruction {
+  const DATA_LEN: usize = 40;
+
+  // [mint], [threshold]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < ExpectOracleThresholdInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, ExpectOracleThresholdInstruction::DATA_LEN];
+    let (mint, &threshold) = array_refs![arr_data, 32, 8];
+    Ok(Self {
+      mint: Pubkey::new_from_array(*mint),
+      threshold: u64::from_le_bytes(threshold),
+    })
+  }
+}
+
+impl InitializeSwapInfoInstruction {
+  // [nonce]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    Ok(Self {
+      nonce: input.first().copied(),
+    })
+  }
+}
+
+impl MaxRouteAccountsInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [max_route_accounts]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MaxRouteAccountsInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      max_route_accounts: input[0],
+    })
+  }
+}
+
+impl CollectHostFeeInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [enabled]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < CollectHostFeeInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let enabled = match input[0] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self { enabled })
+  }
+}
+
+impl ExchangerEnabledInstruction {
+  const DATA_LEN: usize = 2;
+
+  // [exchanger], [enabled]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < ExchangerEnabledInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let exchanger = ExchangerType::from(input[0]).ok_or(ProtocolError::InvalidInput)?;
+    let enabled = match input[1] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self { exchanger, enabled })
+  }
+}
+
+impl StartRouteInstruction {
+  const DATA_LEN: usize = 9;
+
+  // [exchanger], [amount_in]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < StartRouteInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, StartRouteInstruction::DATA_LEN];
+    let (&[exchanger], &amount_in) = array_refs![arr_data, 1, 8];
+    let exchanger = ExchangerType::from(exchanger).ok_or(ProtocolError::InvalidInput)?;
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in)).ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self {
+      exchanger,
+      amount_in,
+    })
+  }
+}
+
+impl FeeReferenceMintInstruction {
+  const DATA_LEN: usize = 32;
+
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < FeeReferenceMintInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let mint = Pubkey::new_from_array(*array_ref![input, 0, FeeReferenceMintInstruction::DATA_LEN]);
+    if mint == Pubkey::default() {
+      Ok(Self {
+        mint: COption::None,
+      })
+    } else {
+      Ok(Self {
+        mint: COption::Some(mint),
+      })
+    }
+  }
+}
+
+impl ExpectOracleInstruction {
+  const DATA_LEN: usize = 32;
+
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < ExpectOracleInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let oracle = Pubkey::new_from_array(*array_ref![input, 0, ExpectOracleInstruction::DATA_LEN]);
+    if oracle == Pubkey::default() {
+      Ok(Self {
+        oracle: COption::None,
+      })
+    } else {
+      Ok(Self {
+        oracle: COption::Some(oracle),
+      })
+    }
+  }
+}
+
+impl MaxSurplusInstruction {
+  const DATA_LEN: usize = 2;
+
+  // [max_surplus_bps]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MaxSurplusInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      max_surplus_bps: u16::from_le_bytes(*array_ref![input, 0, 2]),
+    })
+  }
+}
+
+impl ExchangerStatsInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [exchanger]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < ExchangerStatsInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let exchanger = ExchangerType::from(input[0]).ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self { exchanger })
+  }
+}
+
+impl RegisterPoolInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [exchanger]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < RegisterPoolInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let exchanger = ExchangerType::from(input[0]).ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self { exchanger })
+  }
+}
+
+impl SwapByIndexInstruction {
+  // [pool_index], [swap instruction data]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    let (&pool_index, rest) = input.split_first().ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self {
+      pool_index,
+      swap: SwapInstruction::unpack(rest)?,
+    })
+  }
+}
+
+impl OutputLeg {
+  const DATA_LEN: usize = 26;
+
+  // [exchanger], [accounts_len], [amount_in], [expect_amount_out], [minimum_amount_out]
+  fn unpack(input: &[u8; OutputLeg::DATA_LEN]) -> Result<Self, ProgramError> {
+    let (&[exchanger], &[accounts_len], &amount_in, &expect_amount_out, &minimum_amount_out) =
+      array_refs![input, 1, 1, 8, 8, 8];
+    let exchanger = ExchangerType::from(exchanger).ok_or(ProtocolError::InvalidInput)?;
+    if accounts_len == 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in)).ok_or(ProtocolError::InvalidInput)?;
+    let expect_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(expect_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    let minimum_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(minimum_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    if expect_amount_out.get() < minimum_amount_out.get() {
+      return Err(ProtocolError::InvalidExpectAmountOut.into());
+    }
+    Ok(Self {
+      exchanger,
+      accounts_len,
+      amount_in,
+      expect_amount_out,
+      minimum_amount_out,
+    })
+  }
+}
+
+impl MultiOutputSwapInstruction {
+  const DATA_LEN: usize = 8 + 2 * OutputLeg::DATA_LEN;
+
+  // [amount_in], 2 * [output leg]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MultiOutputSwapInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, MultiOutputSwapInstruction::DATA_LEN];
+    let (&amount_in, first, second) =
+      array_refs![arr_data, 8, OutputLeg::DATA_LEN, OutputLeg::DATA_LEN];
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in)).ok_or(ProtocolError::InvalidInput)?;
+    let legs = [OutputLeg::unpack(first)?, OutputLeg::unpack(second)?];
+    let legs_amount_in = legs[0]
+      .amount_in
+      .get()
+      .checked_add(legs[1].amount_in.get())
+      .ok_or(ProtocolError::InvalidInput)?;
+    if legs_amount_in > amount_in.get() {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self { amount_in, legs })
+  }
+}
+
+impl SwapPercentInstruction {
+  const DATA_LEN: usize = 19;
+
+  // [exchanger], [percent_bps], [expect_amount_out], [minimum_amount_out]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < SwapPercentInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, SwapPercentInstruction::DATA_LEN];
+    let (&[exchanger], &percent_bps, &expect_amount_out, &minimum_amount_out) =
+      array_refs![arr_data, 1, 2, 8, 8];
+    let exchanger = ExchangerType::from(exchanger).ok_or(ProtocolError::InvalidInput)?;
+    let percent_bps = u16::from_le_bytes(percent_bps);
+    if percent_bps == 0 || percent_bps > 10_000 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let expect_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(expect_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    let minimum_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(minimum_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    if expect_amount_out < minimum_amount_out {
+      return Err(ProtocolError::InvalidExpectAmountOut.into());
+    }
+    Ok(Self {
+      exchanger,
+      percent_bps,
+      expect_amount_out,
+      minimum_amount_out,
+    })
+  }
+}
+
+impl FeeAccrualInstruction {
+  const DATA_LEN: usize = 64;
+
+  // [owner], [mint]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < FeeAccrualInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let (&owner, &mint) = array_refs![
+      array_ref![input, 0, FeeAccrualInstruction::DATA_LEN],
+      32,
+      32
+    ];
+    Ok(Self {
+      owner: Pubkey::new_from_array(owner),
+      mint: Pubkey::new_from_array(mint),
+    })
+  }
+}
+
+impl CollectExchangerStatsInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [enabled]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < CollectExchangerStatsInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let enabled = match input[0] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self { enabled })
+  }
+}
+
+impl MaxExpectOutRatioInstruction {
+  const DATA_LEN: usize = 2;
+
+  // [max_expect_out_ratio]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < MaxExpectOutRatioInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      max_expect_out_ratio: u16::from_le_bytes(*array_ref![input, 0, 2]),
+    })
+  }
+}
+
+impl TrustedPoolCreatorInstruction {
+  const DATA_LEN: usize = 32;
+
+  // [creator]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < TrustedPoolCreatorInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      creator: Pubkey::new_from_array(*array_ref![input, 0, 32]),
+    })
+  }
+}
+
+impl CheckPoolCreatorsInstruction {
+  const DATA_LEN: usize = 1;
+
+  // [enabled]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < CheckPoolCreatorsInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let enabled = match input[0] {
+      0 => false,
+      1 => true,
+      _ => return Err(ProtocolError::InvalidInput.into()),
+    };
+    Ok(Self { enabled })
   }
 }
 
-impl SwapOutInstruction {
-  const DATA_LEN: usize = 16;
+impl TransientRouteInstruction {
+  const DATA_LEN: usize = 27;
 
-  // size = 1 or 3
-  // flag[0/1], [account_size], [amount_in], [minium_amount_out]
+  // [exchanger_in], [exchanger_out], [in_accounts_len], [amount_in],
+  // [expect_amount_out], [minimum_amount_out], optional [flags]
   fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    if input.len() < SwapOutInstruction::DATA_LEN {
+    if input.len() < TransientRouteInstruction::DATA_LEN {
       return Err(ProtocolError::InvalidInput.into());
     }
-    let arr_data = array_ref![input, 0, SwapOutInstruction::DATA_LEN];
-    let (&expect_amount_out_arr, &minimum_amount_out_arr) = array_refs![arr_data, 8, 8];
-    let expect_amount_out = NonZeroU64::new(u64::from_le_bytes(expect_amount_out_arr))
-      .ok_or(ProtocolError::InvalidInput)?;
-    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
-      .ok_or(ProtocolError::InvalidInput)?;
-    if expect_amount_out.get() < minimum_amount_out.get() || expect_amount_out.get() == 0 {
+    let arr_data = array_ref![input, 0, TransientRouteInstruction::DATA_LEN];
+    let (
+      &[exchanger_in],
+      &[exchanger_out],
+      &[in_accounts_len],
+      &amount_in,
+      &expect_amount_out,
+      &minimum_amount_out,
+    ) = array_refs![arr_data, 1, 1, 1, 8, 8, 8];
+    let exchanger_in = ExchangerType::from(exchanger_in).ok_or(ProtocolError::InvalidInput)?;
+    let exchanger_out = ExchangerType::from(exchanger_out).ok_or(ProtocolError::InvalidInput)?;
+    if in_accounts_len == 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let amount_in =
+      NonZeroU64::new(u64::from_le_bytes(amount_in)).ok_or(ProtocolError::InvalidInput)?;
+    let expect_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(expect_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    let minimum_amount_out =
+      NonZeroU64::new(u64::from_le_bytes(minimum_amount_out)).ok_or(ProtocolError::InvalidInput)?;
+    if expect_amount_out.get() < minimum_amount_out.get() {
       return Err(ProtocolError::InvalidExpectAmountOut.into());
     }
+    let flags = input
+      .get(TransientRouteInstruction::DATA_LEN)
+      .copied()
+      .unwrap_or_default();
+    if flags & !TRANSIENT_ROUTE_FLAGS_ALL != 0 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
     Ok(Self {
+      exchanger_in,
+      exchanger_out,
+      in_accounts_len,
+      amount_in,
       expect_amount_out,
       minimum_amount_out,
+      flags,
     })
   }
+
+  pub fn fee_discount(&self) -> bool {
+    self.flags & TRANSIENT_ROUTE_FLAG_FEE_DISCOUNT != 0
+  }
 }
 
-impl SwapOutSlimInstruction {
+impl SwapInfoTtlInstruction {
   const DATA_LEN: usize = 8;
 
+  // [ttl_slots]
   fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-    if input.len() < SwapOutSlimInstruction::DATA_LEN {
+    if input.len() < SwapInfoTtlInstruction::DATA_LEN {
       return Err(ProtocolError::InvalidInput.into());
     }
-    let &minimum_amount_out_arr = array_ref![input, 0, SwapOutSlimInstruction::DATA_LEN];
-    let minimum_amount_out = NonZeroU64::new(u64::from_le_bytes(minimum_amount_out_arr))
-      .ok_or(ProtocolError::InvalidInput)?;
-    Ok(Self { minimum_amount_out })
+    Ok(Self {
+      ttl_slots: u64::from_le_bytes(*array_ref![input, 0, 8]),
+    })
+  }
+}
+
+impl FeeGranularityInstruction {
+  const DATA_LEN: usize = 8;
+
+  // [granularity]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < FeeGranularityInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      granularity: u64::from_le_bytes(*array_ref![input, 0, 8]),
+    })
+  }
+}
+
+impl FeeDiscountInstruction {
+  const DATA_LEN: usize = 42;
+
+  // [mint], [threshold], [discount_bps]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < FeeDiscountInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, FeeDiscountInstruction::DATA_LEN];
+    let (mint, &threshold, &discount_bps) = array_refs![arr_data, 32, 8, 2];
+    let mint = Pubkey::new_from_array(*mint);
+    let discount_bps = u16::from_le_bytes(discount_bps);
+    if discount_bps > 10_000 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      mint: if mint == Pubkey::default() {
+        COption::None
+      } else {
+        COption::Some(mint)
+      },
+      threshold: u64::from_le_bytes(threshold),
+      discount_bps,
+    })
+  }
+}
+
+impl TreasuryInstruction {
+  const DATA_LEN: usize = 34;
+
+  // [token_account], [treasury_bps]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < TreasuryInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, TreasuryInstruction::DATA_LEN];
+    let (token_account, &treasury_bps) = array_refs![arr_data, 32, 2];
+    let token_account = Pubkey::new_from_array(*token_account);
+    let treasury_bps = u16::from_le_bytes(treasury_bps);
+    if treasury_bps > 10_000 {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Ok(Self {
+      token_account: if token_account == Pubkey::default() {
+        COption::None
+      } else {
+        COption::Some(token_account)
+      },
+      treasury_bps,
+    })
+  }
+}
+
+impl RecoverFeesInstruction {
+  const DATA_LEN: usize = 9;
+
+  // [amount], [nonce]
+  fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+    if input.len() < RecoverFeesInstruction::DATA_LEN {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let arr_data = array_ref![input, 0, RecoverFeesInstruction::DATA_LEN];
+    let (&amount_arr, &[nonce]) = array_refs![arr_data, 8, 1];
+    let amount =
+      NonZeroU64::new(u64::from_le_bytes(amount_arr)).ok_or(ProtocolError::InvalidInput)?;
+    Ok(Self { amount, nonce })
+  }
+}
+
+/// User accounts of a direct swap, the first three accounts of the
+/// instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirectSwapUserAccounts {
+  pub source: Pubkey,
+  pub destination: Pubkey,
+  /// owner of `source`, signs the transaction
+  pub owner: Pubkey,
+}
+
+/// Instruction tag of the direct swap through `exchanger`.
+fn direct_swap_tag(exchanger: ExchangerType) -> u8 {
+  match exchanger {
+    ExchangerType::SplTokenSwap => 3,
+    ExchangerType::SerumDex => 4,
+    ExchangerType::StableSwap => 6,
+    ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => 9,
+    ExchangerType::CremaFinance => 22,
+    ExchangerType::AldrinExchange => 25,
+    ExchangerType::CropperFinance => 28,
+    ExchangerType::MeteoraDamm => 38,
+    ExchangerType::RaydiumCpmm => 64,
+    ExchangerType::OrcaV1 => 84,
+  }
+}
+
+/// Whether `len` exchanger accounts are accepted by the parser of
+/// `exchanger`.
+fn direct_swap_accounts_len_valid(exchanger: ExchangerType, len: usize) -> bool {
+  match exchanger {
+    // optional host fee account
+    ExchangerType::SplTokenSwap => len == 7 || len == 8,
+    // optional open orders authority, system program and referrer
+    ExchangerType::SerumDex => (11..=14).contains(&len),
+    // up to two saber add-decimals wrappers of 5 accounts
+    ExchangerType::StableSwap => len == 7 || len == 12 || len == 17,
+    ExchangerType::RaydiumSwap => len == 15 || len == 14,
+    ExchangerType::RaydiumSwapSlim => len == 14,
+    ExchangerType::CremaFinance => len == 6,
+    // optional mints of both sides, to read their decimals
+    ExchangerType::AldrinExchange | ExchangerType::CropperFinance => len == 8 || len == 10,
+    ExchangerType::MeteoraDamm => len == 10,
+    ExchangerType::RaydiumCpmm => len == 9,
+    // orca pools take no host fee account
+    ExchangerType::OrcaV1 => len == 7,
+  }
+}
+
+/// Builds a direct swap through `exchanger` with the accounts in the order
+/// `process_single_step_swap` slices them.
+///
+/// `pool_accounts` are the exchanger accounts, followed by the fee pool
+/// accounts when `SWAP_FLAG_FEE_IN_REFERENCE_MINT` is set, by the treasury
+/// token account when `SWAP_FLAG_TREASURY` is set, by the discount token
+/// account when `SWAP_FLAG_FEE_DISCOUNT` is set and by the source mint when
+/// `SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY` is set. The protocol config at its
+/// program derived address goes last.
+pub fn build_direct_swap(
+  program_id: &Pubkey,
+  exchanger: ExchangerType,
+  user_accounts: &DirectSwapUserAccounts,
+  fee_token_account: &Pubkey,
+  pool_accounts: &[AccountMeta],
+  data: &SwapInstruction,
+) -> Result<Instruction, ProgramError> {
+  let mut trailing = 0;
+  if data.fee_in_reference_mint() {
+    // spl-token-swap pool without host fee account
+    trailing += 7;
+  }
+  if data.treasury() {
+    trailing += 1;
   }
+  if data.fee_discount() {
+    trailing += 1;
+  }
+  if data.check_amount_in_supply() {
+    trailing += 1;
+  }
+  let exchanger_accounts_len = pool_accounts.len().checked_sub(trailing);
+  if !exchanger_accounts_len.map_or(false, |len| direct_swap_accounts_len_valid(exchanger, len)) {
+    return Err(ProtocolError::InvalidAccountsLength.into());
+  }
+
+  let mut buf = Vec::with_capacity(1 + SwapInstruction::DATA_LEN + 20);
+  buf.push(direct_swap_tag(exchanger));
+  data.pack_into(&mut buf);
+
+  // the owner pays for the serum open orders PDA when it is passed
+  let owner_pays = exchanger == ExchangerType::SerumDex && exchanger_accounts_len >= Some(13);
+  let mut accounts = vec![
+    AccountMeta::new(user_accounts.source, false),
+    AccountMeta::new(user_accounts.destination, false),
+    AccountMeta {
+      pubkey: user_accounts.owner,
+      is_signer: true,
+      is_writable: owner_pays,
+    },
+    AccountMeta::new_readonly(spl_token::id(), false),
+    AccountMeta::new(*fee_token_account, false),
+  ];
+  accounts.extend_from_slice(pool_accounts);
+  accounts.push(AccountMeta::new_readonly(
+    derive_config_address(program_id).0,
+    false,
+  ));
+
+  Ok(Instruction {
+    program_id: *program_id,
+    accounts,
+    data: buf,
+  })
 }
 
 #[cfg(test)]
@@ -678,5 +3146,1322 @@ mod tests {
     assert_eq!(i.amount_in.get(), amount_in);
     assert_eq!(i.expect_amount_out.get(), expect_amount_out);
     assert_eq!(i.minimum_amount_out.get(), minimum_amount_out);
+    assert!(!i.cancel_resting_order());
+
+    buf.push(SWAP_FLAG_SERUM_CANCEL_RESTING_ORDER);
+    let i = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert!(i.cancel_resting_order());
+
+    assert!(!i.require_rent_exempt_destination());
+
+    buf[SwapInstruction::DATA_LEN] = SWAP_FLAG_REQUIRE_RENT_EXEMPT_DESTINATION;
+    let i = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert!(i.require_rent_exempt_destination());
+    assert!(!i.cancel_resting_order());
+
+    buf[SwapInstruction::DATA_LEN] = SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY;
+    let i = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert!(i.check_amount_in_supply());
+    assert!(!i.fee_in_reference_mint());
+
+    buf[SwapInstruction::DATA_LEN] = SWAP_FLAG_FEE_ON_INPUT;
+    assert!(SwapInstruction::unpack(&buf[..]).unwrap().fee_on_input());
+    buf[SwapInstruction::DATA_LEN] = SWAP_FLAG_FEE_ON_INPUT | SWAP_FLAG_FEE_IN_REFERENCE_MINT;
+    assert!(SwapInstruction::unpack(&buf[..]).is_err());
+
+    buf[SwapInstruction::DATA_LEN] = SWAP_FLAG_SERUM_CHECK_SETTLE;
+    let i = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert!(i.check_serum_settle());
+    assert!(!i.treasury());
+
+    buf[SwapInstruction::DATA_LEN] = 0;
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().min_notional_in,
+      0
+    );
+    buf.extend_from_slice(&5_000u64.to_le_bytes());
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().min_notional_in,
+      5_000
+    );
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..])
+        .unwrap()
+        .max_price_impact_bps,
+      0
+    );
+    buf.extend_from_slice(&300u16.to_le_bytes());
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..])
+        .unwrap()
+        .max_price_impact_bps,
+      300
+    );
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().expected_side,
+      None
+    );
+    buf.push(1);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().expected_side,
+      Some(1)
+    );
+    *buf.last_mut().unwrap() = 2;
+    assert!(SwapInstruction::unpack(&buf[..]).is_err());
+
+    // the route id follows the side, which may be left unchecked
+    *buf.last_mut().unwrap() = NO_EXPECTED_SIDE;
+    buf.extend_from_slice(&42u64.to_le_bytes());
+    let data = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!(data.expected_side, None);
+    assert_eq!(data.client_route_id, Some(42));
+    let mut packed = vec![];
+    data.pack_into(&mut packed);
+    assert_eq!(packed, buf);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..buf.len() - 1])
+        .unwrap()
+        .client_route_id,
+      None
+    );
+
+    // the reference price follows the route id, which may be left out
+    let route_id_at = buf.len() - 8;
+    buf[route_id_at..].copy_from_slice(&NO_CLIENT_ROUTE_ID.to_le_bytes());
+    buf.extend_from_slice(&3u64.to_le_bytes());
+    buf.extend_from_slice(&2u64.to_le_bytes());
+    buf.extend_from_slice(&50u16.to_le_bytes());
+    let data = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!(data.client_route_id, None);
+    assert_eq!(
+      data.reference_price,
+      Some(ReferencePrice {
+        num: 3,
+        den: 2,
+        max_drift_bps: 50,
+      })
+    );
+    let mut packed = vec![];
+    data.pack_into(&mut packed);
+    assert_eq!(packed, buf);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..buf.len() - 1])
+        .unwrap()
+        .reference_price,
+      None
+    );
+    // a zero price can't be drifted from
+    let den_at = buf.len() - 10;
+    buf[den_at..den_at + 8].copy_from_slice(&0u64.to_le_bytes());
+    assert!(SwapInstruction::unpack(&buf[..]).is_err());
+
+    // the extra flags follow the reference price, which may be left out
+    let price_at = buf.len() - 18;
+    buf[price_at..].copy_from_slice(&[0u8; 18]);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().reference_price,
+      None
+    );
+    buf.push(SWAP_EXTRA_FLAG_DESTINATION_OWNED_BY_SIGNER);
+    let data = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!(data.reference_price, None);
+    assert!(data.require_destination_owned_by_signer());
+    let mut packed = vec![];
+    data.pack_into(&mut packed);
+    assert_eq!(packed, buf);
+    assert!(!SwapInstruction::unpack(&buf[..buf.len() - 1])
+      .unwrap()
+      .require_destination_owned_by_signer());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT;
+    assert!(SwapInstruction::unpack(&buf[..])
+      .unwrap()
+      .oracle_expect_amount_out());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO;
+    assert!(SwapInstruction::unpack(&buf[..])
+      .unwrap()
+      .check_expect_out_ratio());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_EXCHANGER_STATS;
+    assert!(SwapInstruction::unpack(&buf[..]).unwrap().exchanger_stats());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_NO_CLAMP;
+    assert!(SwapInstruction::unpack(&buf[..]).unwrap().no_clamp());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_ACCRUE_FEE;
+    assert!(SwapInstruction::unpack(&buf[..]).unwrap().accrue_fee());
+    *buf.last_mut().unwrap() = SWAP_EXTRA_FLAG_VALIDATE_ONLY;
+    assert!(SwapInstruction::unpack(&buf[..]).unwrap().validate_only());
+    let flags = buf[SwapInstruction::DATA_LEN];
+    buf[SwapInstruction::DATA_LEN] = flags | SWAP_FLAG_FEE_ON_INPUT;
+    assert!(SwapInstruction::unpack(&buf[..]).is_err());
+    buf[SwapInstruction::DATA_LEN] = flags;
+    *buf.last_mut().unwrap() = 1 << 6;
+    assert!(SwapInstruction::unpack(&buf[..]).is_err());
+
+    // the expected pool follows the extra flags
+    *buf.last_mut().unwrap() = 0;
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..]).unwrap().expected_pool,
+      None
+    );
+    let pool = Pubkey::new_unique();
+    buf.extend_from_slice(pool.as_ref());
+    let data = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!(data.expected_pool, Some(pool));
+    let mut packed = vec![];
+    data.pack_into(&mut packed);
+    assert_eq!(packed, buf);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..buf.len() - 1])
+        .unwrap()
+        .expected_pool,
+      None
+    );
+
+    // then the source floor, after a zeroed pool when unchecked
+    let pool_start = buf.len() - 32;
+    buf[pool_start..].copy_from_slice(&[0; 32]);
+    buf.extend_from_slice(&300u64.to_le_bytes());
+    let data = SwapInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!((data.expected_pool, data.min_source_remaining), (None, 300));
+    let mut packed = vec![];
+    data.pack_into(&mut packed);
+    assert_eq!(packed, buf);
+    assert_eq!(
+      SwapInstruction::unpack(&buf[..buf.len() - 1])
+        .unwrap()
+        .min_source_remaining,
+      0
+    );
+  }
+
+  #[test]
+  fn test_unpack_swap_out_client_route_id() {
+    let mut buf = vec![];
+    buf.extend_from_slice(&2_000u64.to_le_bytes());
+    buf.extend_from_slice(&1_500u64.to_le_bytes());
+    buf.push(0);
+    assert_eq!(
+      SwapOutInstruction::unpack(&buf).unwrap().client_route_id,
+      None
+    );
+    buf.extend_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(
+      SwapOutInstruction::unpack(&buf).unwrap().client_route_id,
+      Some(u64::MAX)
+    );
+  }
+
+  #[test]
+  fn test_unpack_swap_out_input_minimum() {
+    let mut buf = vec![];
+    buf.extend_from_slice(&2_000u64.to_le_bytes());
+    buf.extend_from_slice(&1_500u64.to_le_bytes());
+    buf.push(SWAP_OUT_FLAG_MINIMUM_FROM_INPUT);
+    buf.extend_from_slice(&7u64.to_le_bytes());
+    // the flag needs the trailing input amount and bps
+    assert!(SwapOutInstruction::unpack(&buf).is_err());
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    buf.extend_from_slice(&9_950u16.to_le_bytes());
+    let data = SwapOutInstruction::unpack(&buf).unwrap();
+    assert_eq!(data.client_route_id, Some(7));
+    assert_eq!(
+      data.input_minimum,
+      Some(InputMinimum {
+        amount_in: NonZeroU64::new(1_000).unwrap(),
+        min_out_bps_of_in: 9_950,
+      })
+    );
+    buf[33..35].copy_from_slice(&10_001u16.to_le_bytes());
+    assert!(SwapOutInstruction::unpack(&buf).is_err());
+    buf[33..35].copy_from_slice(&0u16.to_le_bytes());
+    assert!(SwapOutInstruction::unpack(&buf).is_err());
+    // without the flag the trailing bytes are ignored
+    buf[16] = 0;
+    assert_eq!(
+      SwapOutInstruction::unpack(&buf).unwrap().input_minimum,
+      None
+    );
+  }
+
+  #[test]
+  fn test_unpack_set_min_notional() {
+    let mint = Pubkey::new_unique();
+    let mut buf = vec![46u8];
+    buf.extend_from_slice(mint.as_ref());
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetMinNotional(MinNotionalInstruction {
+        mint,
+        amount: 1_000
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..39]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_exchanger_enabled() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[48, 6, 0]).unwrap(),
+      ProtocolInstruction::SetExchangerEnabled(ExchangerEnabledInstruction {
+        exchanger: ExchangerType::AldrinExchange,
+        enabled: false,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[48, 6]).is_err());
+    assert!(ProtocolInstruction::unpack(&[48, 6, 2]).is_err());
+    assert!(ProtocolInstruction::unpack(&[48, 9, 1]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_start_route() {
+    let mut buf = vec![49u8, 0];
+    buf.extend_from_slice(&500u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::StartRoute(StartRouteInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        amount_in: NonZeroU64::new(500).unwrap(),
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..9]).is_err());
+    buf[1] = 9;
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+    let mut buf = vec![49u8, 0];
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_fee_discount() {
+    let mint = Pubkey::new_unique();
+    let mut buf = vec![50u8];
+    buf.extend_from_slice(mint.as_ref());
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    buf.extend_from_slice(&2_500u16.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetFeeDiscount(FeeDiscountInstruction {
+        mint: COption::Some(mint),
+        threshold: 1_000,
+        discount_bps: 2_500,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..42]).is_err());
+    buf[41..43].copy_from_slice(&10_001u16.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+
+    buf[1..33].copy_from_slice(Pubkey::default().as_ref());
+    buf[41..43].copy_from_slice(&0u16.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetFeeDiscount(FeeDiscountInstruction {
+        mint: COption::None,
+        threshold: 1_000,
+        discount_bps: 0,
+      })
+    );
+  }
+
+  #[test]
+  fn test_unpack_set_treasury() {
+    let treasury = Pubkey::new_unique();
+    let mut buf = vec![57u8];
+    buf.extend_from_slice(treasury.as_ref());
+    buf.extend_from_slice(&2_000u16.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetTreasury(TreasuryInstruction {
+        token_account: COption::Some(treasury),
+        treasury_bps: 2_000,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..34]).is_err());
+    buf[33..35].copy_from_slice(&10_001u16.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+
+    buf[1..33].copy_from_slice(Pubkey::default().as_ref());
+    buf[33..35].copy_from_slice(&0u16.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetTreasury(TreasuryInstruction {
+        token_account: COption::None,
+        treasury_bps: 0,
+      })
+    );
+    // unknown out flags are still rejected
+    let mut out = vec![0u8; 17];
+    out[0..8].copy_from_slice(&2u64.to_le_bytes());
+    out[8..16].copy_from_slice(&1u64.to_le_bytes());
+    out[16] = SWAP_OUT_FLAG_TREASURY;
+    assert!(SwapOutInstruction::unpack(&out).unwrap().treasury());
+    out[16] = SWAP_OUT_FLAG_CLOSE_ON_COMPLETE;
+    assert!(SwapOutInstruction::unpack(&out)
+      .unwrap()
+      .close_on_complete());
+    out[16] = 1 << 5;
+    assert!(SwapOutInstruction::unpack(&out).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_max_surplus() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[52, 0xf4, 0x01]).unwrap(),
+      ProtocolInstruction::SetMaxSurplus(MaxSurplusInstruction {
+        max_surplus_bps: 500
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[52, 0xf4]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_max_expect_out_ratio() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[73, 0xe8, 0x03]).unwrap(),
+      ProtocolInstruction::SetMaxExpectOutRatio(MaxExpectOutRatioInstruction {
+        max_expect_out_ratio: 1_000
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[73, 0xe8]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_trusted_pool_creators() {
+    let creator = Pubkey::new_unique();
+    let mut buf = vec![87];
+    buf.extend_from_slice(creator.as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::AddTrustedPoolCreator(TrustedPoolCreatorInstruction { creator })
+    );
+    buf[0] = 88;
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::RemoveTrustedPoolCreator(TrustedPoolCreatorInstruction { creator })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..32]).is_err());
+    assert_eq!(
+      ProtocolInstruction::unpack(&[89, 1]).unwrap(),
+      ProtocolInstruction::SetCheckPoolCreators(CheckPoolCreatorsInstruction { enabled: true })
+    );
+    assert!(ProtocolInstruction::unpack(&[89, 2]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_pool_registry() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[77, 3]).unwrap(),
+      ProtocolInstruction::RegisterPool(RegisterPoolInstruction {
+        exchanger: ExchangerType::RaydiumSwap
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[77, 0xff]).is_err());
+    assert!(ProtocolInstruction::unpack(&[77]).is_err());
+
+    let mut buf = vec![78, 5];
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    buf.extend_from_slice(&2_000u64.to_le_bytes());
+    buf.extend_from_slice(&1_900u64.to_le_bytes());
+    let swap = SwapInstruction::unpack(&buf[2..]).unwrap();
+    assert_eq!(swap.amount_in.get(), 1_000);
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::SwapByIndex(SwapByIndexInstruction {
+        pool_index: 5,
+        swap
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    assert!(ProtocolInstruction::unpack(&[78]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_multi_output_swap() {
+    let leg = |exchanger: u8, accounts_len: u8, amount_in: u64| {
+      let mut buf = vec![exchanger, accounts_len];
+      buf.extend_from_slice(&amount_in.to_le_bytes());
+      buf.extend_from_slice(&(amount_in * 2).to_le_bytes());
+      buf.extend_from_slice(&amount_in.to_le_bytes());
+      buf
+    };
+    let mut buf = vec![79];
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    buf.extend(leg(0, 7, 600));
+    buf.extend(leg(3, 19, 400));
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::MultiOutputSwap(MultiOutputSwapInstruction {
+        amount_in: NonZeroU64::new(1_000).unwrap(),
+        legs: [
+          OutputLeg {
+            exchanger: ExchangerType::SplTokenSwap,
+            accounts_len: 7,
+            amount_in: NonZeroU64::new(600).unwrap(),
+            expect_amount_out: NonZeroU64::new(1_200).unwrap(),
+            minimum_amount_out: NonZeroU64::new(600).unwrap(),
+          },
+          OutputLeg {
+            exchanger: ExchangerType::RaydiumSwap,
+            accounts_len: 19,
+            amount_in: NonZeroU64::new(400).unwrap(),
+            expect_amount_out: NonZeroU64::new(800).unwrap(),
+            minimum_amount_out: NonZeroU64::new(400).unwrap(),
+          },
+        ],
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+
+    // the legs spend more than amount_in
+    let mut bad = buf.clone();
+    bad[1..9].copy_from_slice(&999u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+    // a leg without accounts
+    let mut bad = buf.clone();
+    bad[10] = 0;
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+    // minimum above the expectation
+    let mut bad = buf.clone();
+    bad[53..61].copy_from_slice(&801u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+  }
+
+  #[test]
+  fn test_unpack_fee_accrual() {
+    let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut buf = vec![81];
+    buf.extend_from_slice(owner.as_ref());
+    buf.extend_from_slice(mint.as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::InitializeFeeAccrual(FeeAccrualInstruction { owner, mint })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..64]).is_err());
+    assert_eq!(
+      ProtocolInstruction::unpack(&[82]).unwrap(),
+      ProtocolInstruction::SettleAccruedFee
+    );
+  }
+
+  #[test]
+  fn test_unpack_swap_percent() {
+    let mut buf = vec![83, 0];
+    buf.extend_from_slice(&5_000u16.to_le_bytes());
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    buf.extend_from_slice(&900u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::SwapPercent(SwapPercentInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        percent_bps: 5_000,
+        expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+        minimum_amount_out: NonZeroU64::new(900).unwrap(),
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..19]).is_err());
+    for percent_bps in [0u16, 10_001] {
+      let mut bad = buf.clone();
+      bad[2..4].copy_from_slice(&percent_bps.to_le_bytes());
+      assert!(ProtocolInstruction::unpack(&bad).is_err());
+    }
+    // the whole balance
+    let mut all = buf.clone();
+    all[2..4].copy_from_slice(&10_000u16.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&all).is_ok());
+    // minimum above the expectation
+    let mut bad = buf.clone();
+    bad[12..20].copy_from_slice(&1_001u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+  }
+
+  #[test]
+  fn test_unpack_exchanger_stats() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[74, 1]).unwrap(),
+      ProtocolInstruction::InitializeExchangerStats(ExchangerStatsInstruction {
+        exchanger: ExchangerType::SerumDex
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[74, 0xff]).is_err());
+    assert!(ProtocolInstruction::unpack(&[74]).is_err());
+    assert_eq!(
+      ProtocolInstruction::unpack(&[75, 1]).unwrap(),
+      ProtocolInstruction::SetCollectExchangerStats(CollectExchangerStatsInstruction {
+        enabled: true
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[75, 2]).is_err());
+    assert_eq!(
+      ProtocolInstruction::unpack(&[76]).unwrap(),
+      ProtocolInstruction::GetExchangerStats
+    );
+  }
+
+  #[test]
+  fn test_exchanger_spec() {
+    use crate::parser::{
+      aldrin::AldrinPoolArgs, crema::CremaSwapV1Args, cropper::CropperArgs,
+      meteora_damm::MeteoraDammArgs, raydium::RaydiumSwapArgs, raydium_cpmm::RaydiumCpmmArgs,
+      saber_wrapper::SaberWrapperArgs, serum_dex::SerumDexArgs, spl_token_swap::SplTokenSwapArgs,
+      stable_swap::StableSwapArgs,
+    };
+    assert_eq!(
+      ProtocolInstruction::unpack(&[80]).unwrap(),
+      ProtocolInstruction::GetExchangerSpec
+    );
+    for (i, exchanger) in ExchangerType::ALL.iter().enumerate() {
+      assert_eq!(exchanger.to_u8() as usize, i);
+    }
+    let accounts_len = |exchanger: ExchangerType| {
+      let spec = exchanger.spec();
+      (spec.accounts_len as usize, spec.max_accounts_len as usize)
+    };
+    // kept in sync with the parsers
+    assert_eq!(
+      accounts_len(ExchangerType::SplTokenSwap),
+      (
+        SplTokenSwapArgs::MIN_ACCOUNTS,
+        SplTokenSwapArgs::MIN_ACCOUNTS + 1
+      )
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::SerumDex),
+      (SerumDexArgs::MIN_ACCOUNTS, SerumDexArgs::MIN_ACCOUNTS + 3)
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::StableSwap),
+      (
+        StableSwapArgs::MIN_ACCOUNTS,
+        StableSwapArgs::MIN_ACCOUNTS + 1 + 2 * SaberWrapperArgs::ACCOUNTS_LEN
+      )
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::RaydiumSwap),
+      (
+        RaydiumSwapArgs::ACCOUNTS_LEN,
+        RaydiumSwapArgs::ACCOUNTS_LEN + 2
+      )
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::RaydiumSwapSlim),
+      (
+        RaydiumSwapArgs::SLIM_ACCOUNTS_LEN,
+        RaydiumSwapArgs::SLIM_ACCOUNTS_LEN + 2
+      )
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::CremaFinance),
+      (CremaSwapV1Args::MIN_ACCOUNTS, CremaSwapV1Args::MIN_ACCOUNTS)
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::AldrinExchange),
+      (
+        AldrinPoolArgs::MIN_ACCOUNTS,
+        AldrinPoolArgs::MIN_ACCOUNTS + 2
+      )
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::CropperFinance),
+      (CropperArgs::MIN_ACCOUNTS, CropperArgs::MIN_ACCOUNTS + 2)
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::MeteoraDamm),
+      (MeteoraDammArgs::MIN_ACCOUNTS, MeteoraDammArgs::MIN_ACCOUNTS)
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::RaydiumCpmm),
+      (RaydiumCpmmArgs::MIN_ACCOUNTS, RaydiumCpmmArgs::MIN_ACCOUNTS)
+    );
+    assert_eq!(
+      accounts_len(ExchangerType::OrcaV1),
+      (
+        SplTokenSwapArgs::MIN_ACCOUNTS,
+        SplTokenSwapArgs::MIN_ACCOUNTS
+      )
+    );
+    assert_eq!(
+      ExchangerType::RaydiumSwapSlim
+        .spec()
+        .pack(ExchangerType::RaydiumSwapSlim),
+      [4, 14, 16, EXCHANGER_MODE_IN | EXCHANGER_MODE_OUT_SLIM]
+    );
+  }
+
+  #[test]
+  fn test_unpack_swap_transient_route() {
+    let mut buf = vec![55u8, 0, 6, 7];
+    buf.extend_from_slice(&500u64.to_le_bytes());
+    buf.extend_from_slice(&1_600u64.to_le_bytes());
+    buf.extend_from_slice(&1_500u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::SwapTransientRoute(TransientRouteInstruction {
+        exchanger_in: ExchangerType::SplTokenSwap,
+        exchanger_out: ExchangerType::AldrinExchange,
+        in_accounts_len: 7,
+        amount_in: NonZeroU64::new(500).unwrap(),
+        expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+        minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+        flags: 0,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..27]).is_err());
+    let mut with_flags = buf.clone();
+    with_flags.push(TRANSIENT_ROUTE_FLAG_FEE_DISCOUNT);
+    match ProtocolInstruction::unpack(&with_flags).unwrap() {
+      ProtocolInstruction::SwapTransientRoute(data) => assert!(data.fee_discount()),
+      _ => panic!("not a transient route"),
+    }
+    *with_flags.last_mut().unwrap() = 1 << 1;
+    assert!(ProtocolInstruction::unpack(&with_flags).is_err());
+    // expect below the minimum
+    let mut bad = buf.clone();
+    bad[12..20].copy_from_slice(&1_400u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+    let mut bad = buf.clone();
+    bad[3] = 0;
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+    let mut bad = buf;
+    bad[2] = 0xff;
+    assert!(ProtocolInstruction::unpack(&bad).is_err());
+  }
+
+  #[test]
+  fn test_unpack_swap_info_ttl() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[53]).unwrap(),
+      ProtocolInstruction::CloseStaleSwapInfo
+    );
+    let mut buf = vec![54u8];
+    buf.extend_from_slice(&432_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::SetSwapInfoTtl(SwapInfoTtlInstruction { ttl_slots: 432_000 })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..8]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_fee_granularity() {
+    let mut buf = vec![56u8];
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf).unwrap(),
+      ProtocolInstruction::SetFeeGranularity(FeeGranularityInstruction { granularity: 1_000 })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..8]).is_err());
+  }
+
+  #[test]
+  fn test_exchanger_type_round_trip() {
+    let all = [
+      ExchangerType::SplTokenSwap,
+      ExchangerType::SerumDex,
+      ExchangerType::StableSwap,
+      ExchangerType::RaydiumSwap,
+      ExchangerType::RaydiumSwapSlim,
+      ExchangerType::CremaFinance,
+      ExchangerType::AldrinExchange,
+      ExchangerType::CropperFinance,
+      ExchangerType::MeteoraDamm,
+      ExchangerType::RaydiumCpmm,
+      ExchangerType::OrcaV1,
+    ];
+    for (i, x) in all.iter().enumerate() {
+      assert_eq!(x.to_u8() as usize, i);
+      assert_eq!(ExchangerType::from(x.to_u8()), Some(*x));
+    }
+    assert_eq!(ExchangerType::from(all.len() as u8), None);
+  }
+
+  #[test]
+  fn test_unpack_set_fee_reference_mint() {
+    let mint = Pubkey::new_unique();
+    let mut buf = vec![41u8];
+    buf.extend_from_slice(mint.as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetFeeReferenceMint(FeeReferenceMintInstruction {
+        mint: COption::Some(mint),
+      })
+    );
+
+    let mut buf = vec![41u8];
+    buf.extend_from_slice(Pubkey::default().as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetFeeReferenceMint(FeeReferenceMintInstruction {
+        mint: COption::None,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..20]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_expect_oracle() {
+    let oracle = Pubkey::new_unique();
+    let mut buf = vec![72u8];
+    buf.extend_from_slice(oracle.as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetExpectOracle(ExpectOracleInstruction {
+        oracle: COption::Some(oracle),
+      })
+    );
+
+    let mut buf = vec![72u8];
+    buf.extend_from_slice(Pubkey::default().as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetExpectOracle(ExpectOracleInstruction {
+        oracle: COption::None,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..20]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_swap_with_fallback() {
+    let mut buf = vec![43u8];
+    buf.extend_from_slice(&1000u64.to_le_bytes());
+    buf.extend_from_slice(&990u64.to_le_bytes());
+    buf.extend_from_slice(&980u64.to_le_bytes());
+    buf.extend_from_slice(&[2, 0, 7, 8, 10]);
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SwapWithFallback(SwapWithFallbackInstruction {
+        amount_in: NonZeroU64::new(1000).unwrap(),
+        expect_amount_out: NonZeroU64::new(990).unwrap(),
+        minimum_amount_out: NonZeroU64::new(980).unwrap(),
+        candidates: vec![
+          (ExchangerType::SplTokenSwap, 7),
+          (ExchangerType::MeteoraDamm, 10)
+        ],
+      })
+    );
+
+    // missing candidate
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    // unknown exchanger
+    let mut bad = buf.clone();
+    bad[28] = 200;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    // empty account range
+    let mut bad = buf.clone();
+    bad[27] = 0;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    // too many candidates
+    let mut bad = buf.clone();
+    bad[25] = MAX_FALLBACK_CANDIDATES as u8 + 1;
+    bad.extend_from_slice(&[0, 7, 0, 7, 0, 7]);
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    bad[25] = 0;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_swap_with_backup() {
+    let mut buf = vec![68u8];
+    buf.extend_from_slice(&1000u64.to_le_bytes());
+    buf.extend_from_slice(&990u64.to_le_bytes());
+    buf.extend_from_slice(&980u64.to_le_bytes());
+    buf.extend_from_slice(&[9, 9]);
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SwapWithBackup(SwapWithBackupInstruction {
+        amount_in: NonZeroU64::new(1000).unwrap(),
+        expect_amount_out: NonZeroU64::new(990).unwrap(),
+        minimum_amount_out: NonZeroU64::new(980).unwrap(),
+        primary: (ExchangerType::RaydiumCpmm, 9),
+      })
+    );
+
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    // unknown exchanger
+    let mut bad = buf.clone();
+    bad[25] = 200;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    // empty account range
+    let mut bad = buf.clone();
+    bad[26] = 0;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_native_swap() {
+    let mut buf = vec![69u8, 0, 1];
+    buf.extend_from_slice(&1000u64.to_le_bytes());
+    buf.extend_from_slice(&990u64.to_le_bytes());
+    buf.extend_from_slice(&980u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::NativeSwap(NativeSwapInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        unwrap: true,
+        amount_in: NonZeroU64::new(1000).unwrap(),
+        expect_amount_out: NonZeroU64::new(990).unwrap(),
+        minimum_amount_out: NonZeroU64::new(980).unwrap(),
+        lamport_fee: false,
+      })
+    );
+
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    let mut bad = buf.clone();
+    bad[2] = 2;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    let mut bad = buf.clone();
+    bad[1] = 200;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    // minimum above the expectation
+    let mut bad = buf.clone();
+    bad[19..27].copy_from_slice(&995u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+
+    let mut with_lamport_fee = buf.clone();
+    with_lamport_fee.push(1);
+    match ProtocolInstruction::unpack(&with_lamport_fee[..]).unwrap() {
+      ProtocolInstruction::NativeSwap(data) => assert!(data.lamport_fee),
+      _ => panic!("not a native swap"),
+    }
+    with_lamport_fee[27] = 2;
+    assert!(ProtocolInstruction::unpack(&with_lamport_fee[..]).is_err());
+    // a swap out of SOL has its fee in the destination token
+    with_lamport_fee[2] = 0;
+    with_lamport_fee[27] = 1;
+    assert!(ProtocolInstruction::unpack(&with_lamport_fee[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_fee_exempt_owner() {
+    let owner = Pubkey::new_unique();
+    let mut buf = vec![70u8];
+    buf.extend_from_slice(owner.as_ref());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::AddFeeExemptOwner(FeeExemptOwnerInstruction { owner })
+    );
+    buf[0] = 71;
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::RemoveFeeExemptOwner(FeeExemptOwnerInstruction { owner })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_quote_route() {
+    let mut buf = vec![61u8];
+    buf.extend_from_slice(&1000u64.to_le_bytes());
+    buf.extend_from_slice(&990u64.to_le_bytes());
+    buf.extend_from_slice(&[2, 0, 7, 3, 17]);
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::QuoteRoute(QuoteRouteInstruction {
+        amount_in: NonZeroU64::new(1000).unwrap(),
+        expect_amount_out: NonZeroU64::new(990).unwrap(),
+        legs: vec![
+          (ExchangerType::SplTokenSwap, 7),
+          (ExchangerType::RaydiumSwap, 17)
+        ],
+      })
+    );
+
+    // missing leg
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    // zero amount in
+    let mut bad = buf.clone();
+    bad[1..9].copy_from_slice(&0u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    // too many legs
+    let mut bad = buf.clone();
+    bad[17] = MAX_QUOTE_ROUTE_LEGS as u8 + 1;
+    bad.extend_from_slice(&[0, 7, 0, 7, 0, 7]);
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+    bad[17] = 0;
+    assert!(ProtocolInstruction::unpack(&bad[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_swap_out_flags() {
+    let mut data = vec![13];
+    data.extend_from_slice(&10u64.to_le_bytes());
+    data.extend_from_slice(&9u64.to_le_bytes());
+    match ProtocolInstruction::unpack(&data).unwrap() {
+      ProtocolInstruction::SwapSplTokenSwapOut(i) => assert!(!i.refund_residual()),
+      _ => panic!("unexpected instruction"),
+    }
+    data.push(SWAP_OUT_FLAG_REFUND_RESIDUAL);
+    match ProtocolInstruction::unpack(&data).unwrap() {
+      ProtocolInstruction::SwapSplTokenSwapOut(i) => assert!(i.refund_residual()),
+      _ => panic!("unexpected instruction"),
+    }
+    *data.last_mut().unwrap() = 1 << 1;
+    assert!(ProtocolInstruction::unpack(&data).is_err());
+  }
+
+  #[test]
+  fn test_unpack_verify_authority() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[45, 254]).unwrap(),
+      ProtocolInstruction::VerifyAuthority(VerifyAuthorityInstruction { nonce: 254 })
+    );
+    assert!(ProtocolInstruction::unpack(&[45]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_estimate_compute_units() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[44, 1, 1]).unwrap(),
+      ProtocolInstruction::EstimateComputeUnits(EstimateComputeUnitsInstruction {
+        exchanger: ExchangerType::SerumDex,
+        reverse: true,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[44, 1]).is_err());
+    assert!(ProtocolInstruction::unpack(&[44, 1, 2]).is_err());
+    assert!(ProtocolInstruction::unpack(&[44, 200, 0]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_compute_hint() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[42]).unwrap(),
+      ProtocolInstruction::SetComputeHint
+    );
+    let mut buf = vec![42u8];
+    buf.extend_from_slice(&10_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetComputeHint
+    );
+  }
+
+  #[test]
+  fn test_unpack_get_config() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[58]).unwrap(),
+      ProtocolInstruction::GetConfig
+    );
+  }
+
+  #[test]
+  fn test_unpack_initialize_swap_info() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[10]).unwrap(),
+      ProtocolInstruction::InitializeSwapInfo(InitializeSwapInfoInstruction { nonce: None })
+    );
+    assert_eq!(
+      ProtocolInstruction::unpack(&[10, 3]).unwrap(),
+      ProtocolInstruction::InitializeSwapInfo(InitializeSwapInfoInstruction { nonce: Some(3) })
+    );
+  }
+
+  #[test]
+  fn test_unpack_self_test() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[67]).unwrap(),
+      ProtocolInstruction::SelfTest
+    );
+  }
+
+  #[test]
+  fn test_unpack_set_mint_fee_cap() {
+    let mint = Pubkey::new_unique();
+    let mut buf = vec![59u8];
+    buf.extend_from_slice(mint.as_ref());
+    buf.extend_from_slice(&5_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetMintFeeCap(MintFeeCapInstruction {
+        mint,
+        max_fee: 5_000,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..40]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_swap_in_rebate() {
+    let mut buf = vec![12u8];
+    buf.extend_from_slice(&1000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SwapSplTokenSwapIn(SwapInInstruction {
+        amount_in: NonZeroU64::new(1000).unwrap(),
+        flags: 0,
+        rebate_nonce: 0,
+        rebate_pool_index: 0,
+      })
+    );
+    buf.extend_from_slice(&[SWAP_IN_FLAG_REBATE, 254, 3]);
+    let data = match ProtocolInstruction::unpack(&buf[..]).unwrap() {
+      ProtocolInstruction::SwapSplTokenSwapIn(data) => data,
+      _ => panic!("unexpected instruction"),
+    };
+    assert!(data.rebate());
+    assert_eq!((data.rebate_nonce, data.rebate_pool_index), (254, 3));
+    // the rebate nonce and pool index are required with the flag
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 1]).is_err());
+    assert!(ProtocolInstruction::unpack(&buf[..buf.len() - 2]).is_err());
+    // unknown flag
+    buf[9] = 1 << 1;
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_rebate() {
+    let mut buf = vec![62u8];
+    buf.extend_from_slice(&25u16.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetRebate(RebateInstruction { rebate_bps: 25 })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..2]).is_err());
+    buf[1..3].copy_from_slice(&10_001u16.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_rebate_vault() {
+    let mut buf = vec![90u8];
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetRebateVault(RebateVaultInstruction { max_rebate: 1_000 })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..8]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_expect_oracle_threshold() {
+    let mint = Pubkey::new_unique();
+    let mut buf = vec![91u8];
+    buf.extend_from_slice(mint.as_ref());
+    buf.extend_from_slice(&1_000u64.to_le_bytes());
+    assert_eq!(
+      ProtocolInstruction::unpack(&buf[..]).unwrap(),
+      ProtocolInstruction::SetExpectOracleThreshold(ExpectOracleThresholdInstruction {
+        mint,
+        threshold: 1_000,
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&buf[..40]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_max_route_accounts() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[63, 48]).unwrap(),
+      ProtocolInstruction::SetMaxRouteAccounts(MaxRouteAccountsInstruction {
+        max_route_accounts: 48
+      })
+    );
+    assert!(ProtocolInstruction::unpack(&[63]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_set_collect_host_fee() {
+    assert_eq!(
+      ProtocolInstruction::unpack(&[60, 1]).unwrap(),
+      ProtocolInstruction::SetCollectHostFee(CollectHostFeeInstruction { enabled: true })
+    );
+    assert_eq!(
+      ProtocolInstruction::unpack(&[60, 0]).unwrap(),
+      ProtocolInstruction::SetCollectHostFee(CollectHostFeeInstruction { enabled: false })
+    );
+    assert!(ProtocolInstruction::unpack(&[60, 2]).is_err());
+    assert!(ProtocolInstruction::unpack(&[60]).is_err());
+  }
+
+  #[test]
+  fn test_unpack_recover_fees() {
+    let amount = 5000u64;
+    let mut buf = vec![32u8];
+    buf.extend_from_slice(&amount.to_le_bytes());
+    buf.push(254);
+
+    let i = ProtocolInstruction::unpack(&buf[..]).unwrap();
+    assert_eq!(
+      i,
+      ProtocolInstruction::RecoverFees(RecoverFeesInstruction {
+        amount: NonZeroU64::new(amount).unwrap(),
+        nonce: 254,
+      })
+    );
+    buf[1..9].copy_from_slice(&0u64.to_le_bytes());
+    assert!(ProtocolInstruction::unpack(&buf[..]).is_err());
+  }
+
+  #[test]
+  fn test_build_direct_swap() {
+    let program_id = Pubkey::new_unique();
+    let user_accounts = DirectSwapUserAccounts {
+      source: Pubkey::new_unique(),
+      destination: Pubkey::new_unique(),
+      owner: Pubkey::new_unique(),
+    };
+    let fee_token_account = Pubkey::new_unique();
+    let (protocol_config, _) = derive_config_address(&program_id);
+    let data = SwapInstruction {
+      amount_in: NonZeroU64::new(10).unwrap(),
+      expect_amount_out: NonZeroU64::new(20).unwrap(),
+      minimum_amount_out: NonZeroU64::new(15).unwrap(),
+      flags: 0,
+      min_notional_in: 3,
+      max_price_impact_bps: 100,
+      expected_side: None,
+      client_route_id: None,
+      reference_price: None,
+      extra_flags: 0,
+      expected_pool: None,
+      min_source_remaining: 0,
+    };
+    let pool_accounts = |len: usize| {
+      (0..len)
+        .map(|_| AccountMeta::new(Pubkey::new_unique(), false))
+        .collect::<Vec<_>>()
+    };
+    let cases = [
+      (ExchangerType::SplTokenSwap, 7, 9),
+      (ExchangerType::SerumDex, 11, 10),
+      (ExchangerType::StableSwap, 7, 8),
+      (ExchangerType::RaydiumSwap, 15, 13),
+      (ExchangerType::RaydiumSwapSlim, 14, 15),
+      (ExchangerType::CremaFinance, 6, 7),
+      (ExchangerType::AldrinExchange, 8, 7),
+      (ExchangerType::CropperFinance, 8, 9),
+      (ExchangerType::MeteoraDamm, 10, 11),
+      (ExchangerType::RaydiumCpmm, 9, 10),
+      (ExchangerType::OrcaV1, 7, 8),
+    ];
+    for (exchanger, len, bad_len) in cases {
+      let pool = pool_accounts(len);
+      let ix = build_direct_swap(
+        &program_id,
+        exchanger,
+        &user_accounts,
+        &fee_token_account,
+        &pool,
+        &data,
+      )
+      .unwrap();
+      assert_eq!(ix.program_id, program_id);
+      // user accounts, token program and fee account, the pool, then the config
+      assert_eq!(ix.accounts.len(), 6 + len);
+      assert_eq!(
+        ix.accounts[0],
+        AccountMeta::new(user_accounts.source, false)
+      );
+      assert_eq!(
+        ix.accounts[1],
+        AccountMeta::new(user_accounts.destination, false)
+      );
+      assert_eq!(
+        ix.accounts[2],
+        AccountMeta::new_readonly(user_accounts.owner, true)
+      );
+      assert_eq!(
+        ix.accounts[3],
+        AccountMeta::new_readonly(spl_token::id(), false)
+      );
+      assert_eq!(ix.accounts[4], AccountMeta::new(fee_token_account, false));
+      assert_eq!(ix.accounts[5..5 + len], pool[..]);
+      assert_eq!(
+        ix.accounts[5 + len],
+        AccountMeta::new_readonly(protocol_config, false)
+      );
+
+      let unpacked = match ProtocolInstruction::unpack(&ix.data).unwrap() {
+        ProtocolInstruction::SwapSplTokenSwap(d) => (ExchangerType::SplTokenSwap, d),
+        ProtocolInstruction::SwapSerumDex(d) => (ExchangerType::SerumDex, d),
+        ProtocolInstruction::SwapStableSwap(d) => (ExchangerType::StableSwap, d),
+        ProtocolInstruction::SwapRaydiumSwap(d) => (ExchangerType::RaydiumSwap, d),
+        ProtocolInstruction::SwapCremaFinance(d) => (ExchangerType::CremaFinance, d),
+        ProtocolInstruction::SwapAldrinExchange(d) => (ExchangerType::AldrinExchange, d),
+        ProtocolInstruction::SwapCropperFinance(d) => (ExchangerType::CropperFinance, d),
+        ProtocolInstruction::SwapMeteoraDamm(d) => (ExchangerType::MeteoraDamm, d),
+        ProtocolInstruction::SwapRaydiumCpmm(d) => (ExchangerType::RaydiumCpmm, d),
+        ProtocolInstruction::SwapOrcaV1(d) => (ExchangerType::OrcaV1, d),
+        _ => panic!("not a direct swap"),
+      };
+      let expected = match exchanger {
+        ExchangerType::RaydiumSwapSlim => ExchangerType::RaydiumSwap,
+        e => e,
+      };
+      assert_eq!(unpacked, (expected, data.clone()));
+
+      assert!(build_direct_swap(
+        &program_id,
+        exchanger,
+        &user_accounts,
+        &fee_token_account,
+        &pool_accounts(bad_len),
+        &data,
+      )
+      .is_err());
+    }
+
+    // fee pool and source mint follow the exchanger accounts
+    let flagged = SwapInstruction {
+      flags: SWAP_FLAG_FEE_IN_REFERENCE_MINT | SWAP_FLAG_CHECK_AMOUNT_IN_SUPPLY,
+      ..data.clone()
+    };
+    let build = |len| {
+      build_direct_swap(
+        &program_id,
+        ExchangerType::CremaFinance,
+        &user_accounts,
+        &fee_token_account,
+        &pool_accounts(len),
+        &flagged,
+      )
+    };
+    assert!(build(6).is_err());
+    assert_eq!(build(6 + 7 + 1).unwrap().accounts.len(), 6 + 14);
+
+    let discounted = SwapInstruction {
+      flags: flagged.flags | SWAP_FLAG_FEE_DISCOUNT,
+      ..data.clone()
+    };
+    assert_eq!(
+      build_direct_swap(
+        &program_id,
+        ExchangerType::CremaFinance,
+        &user_accounts,
+        &fee_token_account,
+        &pool_accounts(6 + 7 + 1 + 1),
+        &discounted,
+      )
+      .unwrap()
+      .accounts
+      .len(),
+      6 + 15
+    );
+    let with_treasury = SwapInstruction {
+      flags: discounted.flags | SWAP_FLAG_TREASURY,
+      ..data.clone()
+    };
+    let build = |len| {
+      build_direct_swap(
+        &program_id,
+        ExchangerType::CremaFinance,
+        &user_accounts,
+        &fee_token_account,
+        &pool_accounts(len),
+        &with_treasury,
+      )
+    };
+    assert!(build(6 + 7 + 1 + 1).is_err());
+    assert_eq!(build(6 + 7 + 1 + 1 + 1).unwrap().accounts.len(), 6 + 16);
+
+    // the owner pays for the serum open orders PDA
+    let ix = build_direct_swap(
+      &program_id,
+      ExchangerType::SerumDex,
+      &user_accounts,
+      &fee_token_account,
+      &pool_accounts(13),
+      &data,
+    )
+    .unwrap();
+    assert_eq!(ix.accounts[2], AccountMeta::new(user_accounts.owner, true));
   }
 }
@@ -13,4 +13,5 @@ pub mod state;
 mod entrypoint;
 
 // export
+pub use parser::detect::detect_exchanger;
 pub use solana_program;
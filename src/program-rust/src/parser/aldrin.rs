@@ -1,10 +1,16 @@
-use super::base::{validate_authority_pubkey, TokenAccount, TokenMint};
+use super::{
+  base::{
+    dex_program_ids, validate_authority_pubkey, validate_dex_program, TokenAccount, TokenMint,
+  },
+  reserves::{normalize_amount, PoolReserves},
+};
 use crate::{
-  declare_validated_account_wrapper,
+  check_unreachable, declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
   exchanger::aldrin::instruction::Side,
+  instruction::ExchangerType,
 };
-use arrayref::array_ref;
+use arrayref::{array_ref, array_refs};
 use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
 
 declare_validated_account_wrapper!(AldrinPool, |account: &AccountInfo| {
@@ -82,6 +88,32 @@ impl<'a, 'b: 'a> AldrinPool<'a, 'b> {
       .map_err(|_| ProtocolError::BorrowAccountDataError)?;
     Ok(Pubkey::new_from_array(*array_ref![data, 40, 32]))
   }
+
+  pub fn fee_base_account(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 297, 32]))
+  }
+
+  pub fn fee_quote_account(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 329, 32]))
+  }
+
+  /// Pool token account the swap fee is minted to, the `fee_account` of a
+  /// swap.
+  pub fn fee_pool_token_account(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 361, 32]))
+  }
 }
 
 #[derive(Copy, Clone)]
@@ -94,15 +126,22 @@ pub struct AldrinPoolArgs<'a, 'b: 'a> {
   pub fee_account: &'a AccountInfo<'b>,
   pub curve_key: &'a AccountInfo<'b>,
   pub program_id: &'a AccountInfo<'b>,
+  /// optional `(coin_mint, pc_mint)`, only needed to read the decimals
+  pub mints: Option<(TokenMint<'a, 'b>, TokenMint<'a, 'b>)>,
 }
 
 impl<'a, 'b: 'a> AldrinPoolArgs<'a, 'b> {
-  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 8;
+  pub const MIN_ACCOUNTS: usize = 8;
 
-    if accounts.len() != MIN_ACCOUNTS {
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if !(accounts.len() == Self::MIN_ACCOUNTS || accounts.len() == Self::MIN_ACCOUNTS + 2) {
       return Err(ProtocolError::InvalidAccountsLength);
     }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, mint_accounts): (
+      &'a [AccountInfo<'b>; Self::MIN_ACCOUNTS],
+      &'a [AccountInfo<'b>],
+    ) = array_refs![accounts, Self::MIN_ACCOUNTS; .. ;];
     let &[
       ref pool_info_acc,
       ref authority,
@@ -112,16 +151,28 @@ impl<'a, 'b: 'a> AldrinPoolArgs<'a, 'b> {
       ref fee_account,
       ref curve_key,
       ref program_id,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = fixed_accounts;
 
     let pool_info = AldrinPool::new(pool_info_acc)?;
-    if !program_id.executable || *pool_info_acc.owner != *program_id.key {
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::AldrinExchange))?;
+    if *pool_info_acc.owner != *program_id.key {
       return Err(ProtocolError::InvalidProgramAddress);
     }
 
     if *pool_mint_acc.key != pool_info.pool_mint()? {
       return Err(ProtocolError::InvalidTokenMint);
     }
+    if *fee_account.key != pool_info.fee_pool_token_account()? {
+      msg!(
+        "fee_account got {}, expect: {}",
+        fee_account.key,
+        pool_info.fee_pool_token_account()?
+      );
+      return Err(ProtocolError::InvalidFeeTokenAccount);
+    }
+    if TokenAccount::new(fee_account)?.mint()? != *pool_mint_acc.key {
+      return Err(ProtocolError::InvalidFeeTokenAccount);
+    }
     let pool_vault_1 = TokenAccount::new(pool_coin_vault_acc)?;
     let pool_vault_2 = TokenAccount::new(pool_pc_vault_acc)?;
 
@@ -162,6 +213,22 @@ impl<'a, 'b: 'a> AldrinPoolArgs<'a, 'b> {
       &pool_info_acc.key.to_bytes(),
       pool_info.nonce()?,
     )?;
+    coin_vault.check_vault_owner(authority.key)?;
+    pc_vault.check_vault_owner(authority.key)?;
+
+    let mints = match mint_accounts {
+      [] => None,
+      [ref coin_mint_acc, ref pc_mint_acc] => {
+        if *coin_mint_acc.key != coin_mint {
+          return Err(ProtocolError::InvalidCoinMint);
+        }
+        if *pc_mint_acc.key != pc_mint {
+          return Err(ProtocolError::InvalidPcMint);
+        }
+        Some((TokenMint::new(coin_mint_acc)?, TokenMint::new(pc_mint_acc)?))
+      }
+      _ => check_unreachable!()?,
+    };
 
     Ok(Self {
       pool_info,
@@ -172,9 +239,48 @@ impl<'a, 'b: 'a> AldrinPoolArgs<'a, 'b> {
       fee_account,
       curve_key,
       program_id,
+      mints,
     })
   }
 
+  /// Decimals of the coin mint, `None` without the mint accounts.
+  pub fn coin_decimals(&self) -> ProtocolResult<Option<u8>> {
+    self
+      .mints
+      .map(|(coin_mint, _)| coin_mint.decimals())
+      .transpose()
+  }
+
+  /// Decimals of the pc mint, `None` without the mint accounts.
+  pub fn pc_decimals(&self) -> ProtocolResult<Option<u8>> {
+    self
+      .mints
+      .map(|(_, pc_mint)| pc_mint.decimals())
+      .transpose()
+  }
+
+  /// `quote` rescaled from the destination mint decimals to
+  /// `target_decimals`, so quotes into mints of different decimals compare.
+  pub fn normalized_quote(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    target_decimals: u8,
+  ) -> ProtocolResult<u64> {
+    let quote = self.quote(source_mint, destination_mint, amount_in)?;
+    let decimals = if *destination_mint == self.pool_info.coin_mint()? {
+      self.coin_decimals()?
+    } else {
+      self.pc_decimals()?
+    };
+    normalize_amount(
+      quote,
+      decimals.ok_or(ProtocolError::ExpectedAccount)?,
+      target_decimals,
+    )
+  }
+
   pub fn find_side(&self, source_mint: &Pubkey) -> ProtocolResult<Side> {
     let coin_mint = self.pool_info.coin_mint()?;
     if *source_mint == coin_mint {
@@ -186,25 +292,26 @@ impl<'a, 'b: 'a> AldrinPoolArgs<'a, 'b> {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
   use super::*;
   use bs58;
   use solana_sdk::{account::Account, account_info::IntoAccountInfo};
   use std::str::FromStr;
 
-  #[test]
-  pub fn test_parse_aldrin_pool_info() {
-    let pubkey = Pubkey::from_str("HjZ2zgg4HemPREiJ7he3VWqV6bHV5yhLNkMJahwigbzz").unwrap();
-    let program_id = Pubkey::from_str("CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4").unwrap();
-    let account_data = "4VMpc88zcKRkaAdBUkKmF6xLE8umnyqnBceSPeNcGdFdcHMnVMfjmCnJWCG3dVxvE8LzuisKJDY5cRkFwCpfWM7NtKSKc3dw
+  pub(crate) const ALDRIN_POOL_DATA: &str = "4VMpc88zcKRkaAdBUkKmF6xLE8umnyqnBceSPeNcGdFdcHMnVMfjmCnJWCG3dVxvE8LzuisKJDY5cRkFwCpfWM7NtKSKc3dw
 aqfLsNsLiR9TZ5gQrptWrv3DKD4GeTc9iUv8ftfEjKARGg4zGhpMzbDWFnsZtL19VU94iCuRfTaspLKEdpAW7qp7KhA3xM4YWBA4d2iPBu1cuQFMiAMocXU4
 9YqBeEhajTLbcckBXsnYgN5KhWmcFtRwgzKSEuG3nnu8HDpx5ze8EW1PzYGg2mCsx4KnMUh7prqW2YKuXnrcwBwfe1PMDKdTxCrY17r9tPmaQ3vR4xv7RJA9
 GdLrPf1C84LpFgUkbJ72DEgL7PyiXF2tuJofzrt8PXxzWHjL3YPcbJyNtaEWEmem4HwMbw6JYing6X422pLnXAb1zeyGnE1oM4s7d7MFysZ1FMfpWgYJvaD7
 11EtACBHwDCPbbcwi588Pdgu1SCHyzCMyX8t8RnShRJGPTwrfDLizxxTQxHTAXRCSMPtJ4RnBYLUwwxCgcPUYRJiFWpV7CuFWMNTxw2rs8skuvYPFh1fj7E2
 dVmzXNyJydYDyCE8ntSuc6NQJAnmNYnpMueCof7KfJWJuxVbkZ2jKyWMe349VHLS28sd1Kon";
+
+  #[test]
+  pub fn test_parse_aldrin_pool_info() {
+    let pubkey = Pubkey::from_str("HjZ2zgg4HemPREiJ7he3VWqV6bHV5yhLNkMJahwigbzz").unwrap();
+    let program_id = Pubkey::from_str("CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4").unwrap();
     let mut test_account = Account {
       lamports: 4189920,
-      data: bs58::decode(account_data.replace('\n', ""))
+      data: bs58::decode(ALDRIN_POOL_DATA.replace('\n', ""))
         .into_vec()
         .unwrap(),
       owner: program_id,
@@ -235,5 +342,17 @@ dVmzXNyJydYDyCE8ntSuc6NQJAnmNYnpMueCof7KfJWJuxVbkZ2jKyWMe349VHLS28sd1Kon";
       "8CFVFQGtqdnuYgypdJ2YwexRuaFs9KUPSyP6Gd5LtTqL".to_string()
     );
     assert_eq!(c.nonce().unwrap(), 252);
+    assert_eq!(
+      c.fee_base_account().unwrap().to_string(),
+      "GpS3CdQSNCTfwLTDu9MFDqYXLKp3iZG8PgmNCPDd1DfZ".to_string()
+    );
+    assert_eq!(
+      c.fee_quote_account().unwrap().to_string(),
+      "8bPVz8KDuPkh4PHXdA6Gdm6aN3wvgpknFSyE7CkYu2x1".to_string()
+    );
+    assert_eq!(
+      c.fee_pool_token_account().unwrap().to_string(),
+      "5QiosfontQRdkb5FdNkJX2VA1vb6z2Kend8F8k6nHe6o".to_string()
+    );
   }
 }
@@ -1,11 +1,85 @@
 use crate::{
   declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
   spl_token,
-  state::SwapInfo,
+  state::{derive_config_address, ProtocolConfig, Status, SwapInfo},
 };
 use arrayref::{array_ref, array_refs};
-use solana_program::{account_info::AccountInfo, msg, program_pack::Pack, pubkey::Pubkey, sysvar};
+use solana_program::{
+  account_info::AccountInfo, msg, program_pack::Pack, pubkey, pubkey::Pubkey, rent::Rent, sysvar,
+};
+use std::cmp;
+
+/// spl-token-swap and the forks sharing its layout: spl, orca v1, orca v2, step.
+/// Orca v1 stays here for the routes built before `ExchangerType::OrcaV1`.
+pub const SPL_TOKEN_SWAP_PROGRAM_IDS: &[Pubkey] = &[
+  pubkey!("SwaPpA9LAaLfeLi3a68M4DjnLqgtticKg6CnyNwgAC8"),
+  pubkey!("DjVE6JNiYqPL2QXyCUUh8rNjHrbz9hXHNYt99MQ59qw1"),
+  pubkey!("9W959DqEETiGZocYWCQPaJ6sBmUzgfxXfqGeTEdp3aQP"),
+  pubkey!("SSwpMgqNDsyV7mAgN9ady4bDVu5ySjmmXejXvy2vLt1"),
+];
+/// serum v3 and openbook
+pub const SERUM_DEX_PROGRAM_IDS: &[Pubkey] = &[
+  pubkey!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"),
+  pubkey!("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX"),
+];
+pub const STABLE_SWAP_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ")];
+pub const RAYDIUM_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8")];
+pub const CREMA_PROGRAM_IDS: &[Pubkey] = &[pubkey!("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319")];
+/// aldrin v1 and v2 (curve) pools
+pub const ALDRIN_PROGRAM_IDS: &[Pubkey] = &[
+  pubkey!("AMM55ShdkoGRB5jVYPjWziwk8m5MpwyDgsMWHaMSQWH6"),
+  pubkey!("CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4"),
+];
+pub const CROPPER_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh")];
+pub const METEORA_DAMM_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("cpamdpZCGKUy5JxQXB4dcpGPiikHawvSWAd6mEn1sGG")];
+/// raydium cp-swap (cpmm) pools
+pub const RAYDIUM_CPMM_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C")];
+/// orca legacy token swap v1, without host fees
+pub const ORCA_V1_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("DjVE6JNiYqPL2QXyCUUh8rNjHrbz9hXHNYt99MQ59qw1")];
+/// saber decimal wrapper, wraps tokens in front of stable swap pools
+pub const SABER_WRAPPER_PROGRAM_IDS: &[Pubkey] =
+  &[pubkey!("DecZY86MU5Gj7kppfUCEmd4LbXXuyZH1yHaP2NTqdiZB")];
+
+/// Program ids accepted for the CPI of an exchanger.
+pub fn dex_program_ids(exchanger: ExchangerType) -> &'static [Pubkey] {
+  match exchanger {
+    ExchangerType::SplTokenSwap => SPL_TOKEN_SWAP_PROGRAM_IDS,
+    ExchangerType::SerumDex => SERUM_DEX_PROGRAM_IDS,
+    ExchangerType::StableSwap => STABLE_SWAP_PROGRAM_IDS,
+    ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => RAYDIUM_PROGRAM_IDS,
+    ExchangerType::CremaFinance => CREMA_PROGRAM_IDS,
+    ExchangerType::AldrinExchange => ALDRIN_PROGRAM_IDS,
+    ExchangerType::CropperFinance => CROPPER_PROGRAM_IDS,
+    ExchangerType::MeteoraDamm => METEORA_DAMM_PROGRAM_IDS,
+    ExchangerType::RaydiumCpmm => RAYDIUM_CPMM_PROGRAM_IDS,
+    ExchangerType::OrcaV1 => ORCA_V1_PROGRAM_IDS,
+  }
+}
+
+/// Checks the program account a parser hands to the CPI is executable and
+/// one of `program_ids`.
+pub fn validate_dex_program(
+  program_acc: &AccountInfo,
+  program_ids: &[Pubkey],
+) -> ProtocolResult<()> {
+  if !program_acc.executable || !program_ids.contains(program_acc.key) {
+    msg!(
+      "program_id: {}, executable: {}",
+      program_acc.key,
+      program_acc.executable
+    );
+    return Err(ProtocolError::InvalidDexProgramId);
+  }
+  Ok(())
+}
 
 declare_validated_account_wrapper!(SplTokenProgram, |account: &AccountInfo| {
   if *account.key != spl_token::ID {
@@ -24,11 +98,12 @@ declare_validated_account_wrapper!(TokenAccount, |account: &AccountInfo| {
   if data.len() != spl_token::ACCOUNT_LEN {
     return Err(ProtocolError::InvalidTokenAccount);
   };
-  let is_initialized = data[0x6c];
-  if is_initialized != 1u8 {
-    return Err(ProtocolError::InvalidTokenAccount);
-  };
-  Ok(())
+  // 0: Uninitialized, 1: Initialized, 2: Frozen
+  match data[0x6c] {
+    1u8 => Ok(()),
+    2u8 => Err(ProtocolError::AccountFrozen),
+    _ => Err(ProtocolError::InvalidTokenAccount),
+  }
 });
 
 #[allow(unused)]
@@ -40,6 +115,40 @@ impl<'a, 'b: 'a> TokenAccount<'a, 'b> {
       .map_err(|_| ProtocolError::BorrowAccountDataError)?;
     Ok(u64::from_le_bytes(*array_ref![data, 64, 8]))
   }
+
+  /// Reads balance and mint with a single borrow of the account data.
+  pub fn snapshot(self) -> ProtocolResult<(u64, Pubkey)> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok((
+      u64::from_le_bytes(*array_ref![data, 64, 8]),
+      Pubkey::new_from_array(*array_ref![data, 0, 32]),
+    ))
+  }
+
+  /// Token amount received since `amount_before`.
+  ///
+  /// Always reads the token `amount` field, never lamports, so that for native
+  /// (WSOL) accounts any lamport change from rent or account creation in the
+  /// same transaction is not counted as swap output. The account must already
+  /// exist before the swap step runs.
+  pub fn amount_delta(self, amount_before: u64) -> ProtocolResult<u64> {
+    self
+      .balance()?
+      .checked_sub(amount_before)
+      .ok_or(ProtocolError::Overflow)
+  }
+
+  pub fn is_native(self) -> ProtocolResult<bool> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(*array_ref![data, 109, 4] == [1, 0, 0, 0])
+  }
+
   pub fn mint(self) -> ProtocolResult<Pubkey> {
     let data = self
       .inner()
@@ -64,6 +173,28 @@ impl<'a, 'b: 'a> TokenAccount<'a, 'b> {
     unpack_coption_key(array_ref![data, 72, 36])
   }
 
+  pub fn delegated_amount(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 121, 8]))
+  }
+
+  /// Like `snapshot`, with the balance limited to what `authority` can
+  /// transfer: all of it for the owner, at most `delegated_amount` for the
+  /// delegate.
+  pub fn spendable_snapshot(self, authority: &Pubkey) -> ProtocolResult<(u64, Pubkey)> {
+    let (balance, mint) = self.snapshot()?;
+    if self.owner()? == *authority || self.delegate()? != Some(*authority) {
+      return Ok((balance, mint));
+    }
+    match self.delegated_amount()? {
+      0 => Err(ProtocolError::InvalidDelegate),
+      delegated_amount => Ok((cmp::min(balance, delegated_amount), mint)),
+    }
+  }
+
   pub fn check_owner(self, authority: &Pubkey, strict: bool) -> ProtocolResult<()> {
     let owner = self.owner()?;
     if *authority == owner {
@@ -81,6 +212,23 @@ impl<'a, 'b: 'a> TokenAccount<'a, 'b> {
     Err(ProtocolError::InvalidOwner)
   }
 
+  /// Checks a pool vault is owned by the pool `authority`, a vault with the
+  /// right mint but another owner is not the pool's.
+  pub fn check_vault_owner(self, authority: &Pubkey) -> ProtocolResult<()> {
+    if self.owner()? != *authority {
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    Ok(())
+  }
+
+  pub fn check_rent_exempt(self, rent: &Rent) -> ProtocolResult<()> {
+    let account = self.inner();
+    if !rent.is_exempt(account.lamports(), account.data_len()) {
+      return Err(ProtocolError::NotRentExempt);
+    }
+    Ok(())
+  }
+
   // pub fn check_delegate(self, authority: &Pubkey) -> ProtocolResult<()> {
   //   let delegate = self.delegate()?;
   //   match delegate {
@@ -112,6 +260,35 @@ declare_validated_account_wrapper!(TokenMint, |mint: &AccountInfo| {
   Ok(())
 });
 
+#[allow(unused)]
+impl<'a, 'b: 'a> TokenMint<'a, 'b> {
+  pub fn decimals(self) -> ProtocolResult<u8> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(data[44])
+  }
+
+  pub fn supply(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 36, 8]))
+  }
+}
+
+/// Fails with `InvalidMintDecimals` unless `mint` has `expected` decimals.
+pub fn validate_decimals(mint: TokenMint, expected: u8) -> ProtocolResult<()> {
+  let decimals = mint.decimals()?;
+  if decimals != expected {
+    msg!("mint decimals got {}, expect: {}", decimals, expected);
+    return Err(ProtocolError::InvalidMintDecimals);
+  }
+  Ok(())
+}
+
 declare_validated_account_wrapper!(SignerAccount, |account: &AccountInfo| {
   if !account.is_signer {
     return Err(ProtocolError::InvalidSignerAccount);
@@ -126,6 +303,13 @@ declare_validated_account_wrapper!(SysClockAccount, |account: &AccountInfo| {
   Ok(())
 });
 
+declare_validated_account_wrapper!(SysRentAccount, |account: &AccountInfo| {
+  if *account.key != sysvar::rent::id() {
+    return Err(ProtocolError::InvalidRentAccount);
+  }
+  Ok(())
+});
+
 #[derive(Copy, Clone)]
 pub struct UserArgs<'a, 'b: 'a> {
   pub token_source_account: TokenAccount<'a, 'b>,
@@ -161,6 +345,29 @@ impl<'a, 'b: 'a> UserArgs<'a, 'b> {
       source_account_owner,
     })
   }
+
+  /// The protocol fee account must not be one of the user's accounts,
+  /// otherwise the fee would be counted in the swap balance deltas.
+  pub fn check_fee_token_account(&self, fee_token_account: &Pubkey) -> ProtocolResult<()> {
+    if fee_token_account == self.token_source_account.pubkey()
+      || fee_token_account == self.token_destination_account.pubkey()
+    {
+      return Err(ProtocolError::InvalidFeeTokenAccount);
+    }
+    Ok(())
+  }
+
+  /// Transfers fail when the user's accounts are owned by another token
+  /// program than the one passed, e.g. token-2022 accounts with the classic
+  /// program.
+  pub fn check_token_program(&self, spl_token_program: &SplTokenProgram) -> ProtocolResult<()> {
+    if self.token_source_account.inner().owner != spl_token_program.pubkey()
+      || self.token_destination_account.inner().owner != spl_token_program.pubkey()
+    {
+      return Err(ProtocolError::IncorrectTokenProgramId);
+    }
+    Ok(())
+  }
 }
 
 #[derive(Copy, Clone)]
@@ -201,7 +408,7 @@ impl<'a, 'b: 'a> SwapInfoArgs<'a, 'b> {
       return Err(ProtocolError::InvalidOwner);
     }
     let swap_info =
-      SwapInfo::unpack(&account.data.borrow()).map_err(|_| ProtocolError::InvalidAccountData)?;
+      SwapInfo::load(&account.data.borrow()).map_err(|_| ProtocolError::InvalidAccountData)?;
     Ok(Self {
       swap_info,
       swap_info_acc: account,
@@ -209,6 +416,95 @@ impl<'a, 'b: 'a> SwapInfoArgs<'a, 'b> {
   }
 }
 
+pub struct ProtocolConfigArgs<'a, 'b: 'a> {
+  pub config: ProtocolConfig,
+  pub config_acc: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b: 'a> ProtocolConfigArgs<'a, 'b> {
+  pub fn with_parsed_args(
+    account: &'a AccountInfo<'b>,
+    program_id: &'a Pubkey,
+  ) -> ProtocolResult<Self> {
+    if *account.owner != *program_id {
+      return Err(ProtocolError::InvalidOwner);
+    }
+    if *account.key != derive_config_address(program_id).0 {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    let config = ProtocolConfig::unpack(&account.data.borrow())
+      .map_err(|_| ProtocolError::InvalidAccountData)?;
+    if Status::from_u8(config.status).ok() != Some(Status::ProtocolConfig) {
+      return Err(ProtocolError::InvalidAccountFlags);
+    }
+    Ok(Self {
+      config,
+      config_acc: account,
+    })
+  }
+
+  /// Takes the config account every swap passes last, returns the accounts
+  /// before it. A swap without it, or with another account in its place,
+  /// fails.
+  pub fn split_trailing(
+    accounts: &'a [AccountInfo<'b>],
+    program_id: &'a Pubkey,
+  ) -> ProtocolResult<(Self, &'a [AccountInfo<'b>])> {
+    let (last, rest) = accounts
+      .split_last()
+      .ok_or(ProtocolError::InvalidAccountsLength)?;
+    Ok((Self::with_parsed_args(last, program_id)?, rest))
+  }
+}
+
+pub struct RecoverFeesArgs<'a, 'b: 'a> {
+  pub owner: SignerAccount<'a, 'b>,
+  pub source_token_account: TokenAccount<'a, 'b>,
+  pub authority: &'a AccountInfo<'b>,
+  pub destination_token_account: TokenAccount<'a, 'b>,
+  pub spl_token_program: SplTokenProgram<'a, 'b>,
+}
+
+impl<'a, 'b: 'a> RecoverFeesArgs<'a, 'b> {
+  pub fn with_parsed_args(
+    accounts: &'a [AccountInfo<'b>],
+    program_id: &Pubkey,
+    nonce: u8,
+  ) -> ProtocolResult<Self> {
+    const MIN_ACCOUNTS: usize = 5;
+    if accounts.len() != MIN_ACCOUNTS {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+
+    let &[
+      ref owner_acc,
+      ref source_token_acc,
+      ref authority,
+      ref destination_token_acc,
+      ref spl_token_program_acc,
+    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+
+    let owner = SignerAccount::new(owner_acc)?;
+    validate_authority_pubkey(authority.key, program_id, program_id.as_ref(), nonce)?;
+    let source_token_account = TokenAccount::new(source_token_acc)?;
+    source_token_account.check_owner(authority.key, true)?;
+    source_token_account.check_writable()?;
+    let destination_token_account = TokenAccount::new(destination_token_acc)?;
+    destination_token_account.check_writable()?;
+    if source_token_account.mint()? != destination_token_account.mint()? {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+
+    Ok(RecoverFeesArgs {
+      owner,
+      source_token_account,
+      authority,
+      destination_token_account,
+      spl_token_program: SplTokenProgram::new(spl_token_program_acc)?,
+    })
+  }
+}
+
 #[allow(unused)]
 fn unpack_coption_key(src: &[u8; 36]) -> ProtocolResult<Option<Pubkey>> {
   let (tag, body) = array_refs![src, 4, 32];
@@ -236,3 +532,391 @@ pub fn validate_authority_pubkey(
   }
   Ok(())
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use solana_sdk::{account::Account, account_info::IntoAccountInfo};
+
+  #[test]
+  fn test_validate_dex_program() {
+    let mut program = Account {
+      lamports: 1_141_440,
+      data: vec![],
+      owner: Pubkey::default(),
+      executable: true,
+      rent_epoch: 281,
+    };
+    let known = RAYDIUM_PROGRAM_IDS[0];
+    let program_info = (&known, &mut program).into_account_info();
+    assert_eq!(
+      validate_dex_program(
+        &program_info,
+        dex_program_ids(ExchangerType::RaydiumSwapSlim)
+      ),
+      Ok(())
+    );
+    assert_eq!(
+      validate_dex_program(&program_info, dex_program_ids(ExchangerType::SerumDex)),
+      Err(ProtocolError::InvalidDexProgramId)
+    );
+
+    let unknown = Pubkey::new_unique();
+    let mut program_info = program_info.clone();
+    program_info.key = &unknown;
+    assert_eq!(
+      validate_dex_program(&program_info, RAYDIUM_PROGRAM_IDS),
+      Err(ProtocolError::InvalidDexProgramId)
+    );
+    // the known id passed as a plain, non executable account
+    program_info.key = &known;
+    program_info.executable = false;
+    assert_eq!(
+      validate_dex_program(&program_info, RAYDIUM_PROGRAM_IDS),
+      Err(ProtocolError::InvalidDexProgramId)
+    );
+  }
+
+  fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+    data[0..32].copy_from_slice(mint.as_ref());
+    data[32..64].copy_from_slice(owner.as_ref());
+    data[64..72].copy_from_slice(&amount.to_le_bytes());
+    data[0x6c] = 1;
+    Account {
+      lamports: 2039280,
+      data,
+      owner: spl_token::ID,
+      executable: false,
+      rent_epoch: 281,
+    }
+  }
+
+  #[test]
+  pub fn test_recover_fees_args_arbitrary_mint() {
+    let program_id = Pubkey::new_unique();
+    let (authority_key, nonce) = Pubkey::find_program_address(&[program_id.as_ref()], &program_id);
+    let mint = Pubkey::new_unique();
+    let owner_key = Pubkey::new_unique();
+    let source_key = Pubkey::new_unique();
+    let destination_key = Pubkey::new_unique();
+    let token_program_key = spl_token::ID;
+
+    let mut owner_account = Account::default();
+    let mut source_account = token_account(&mint, &authority_key, 42_000);
+    let mut authority_account = Account::default();
+    let mut destination_account = token_account(&mint, &owner_key, 0);
+    let mut token_program_account = Account::default();
+    let accounts = vec![
+      AccountInfo::new(
+        &owner_key,
+        true,
+        false,
+        &mut owner_account.lamports,
+        &mut owner_account.data,
+        &owner_account.owner,
+        false,
+        0,
+      ),
+      AccountInfo::new(
+        &source_key,
+        false,
+        true,
+        &mut source_account.lamports,
+        &mut source_account.data,
+        &source_account.owner,
+        false,
+        0,
+      ),
+      AccountInfo::new(
+        &authority_key,
+        false,
+        false,
+        &mut authority_account.lamports,
+        &mut authority_account.data,
+        &authority_account.owner,
+        false,
+        0,
+      ),
+      AccountInfo::new(
+        &destination_key,
+        false,
+        true,
+        &mut destination_account.lamports,
+        &mut destination_account.data,
+        &destination_account.owner,
+        false,
+        0,
+      ),
+      AccountInfo::new(
+        &token_program_key,
+        false,
+        false,
+        &mut token_program_account.lamports,
+        &mut token_program_account.data,
+        &token_program_account.owner,
+        false,
+        0,
+      ),
+    ];
+
+    let args = RecoverFeesArgs::with_parsed_args(&accounts, &program_id, nonce).unwrap();
+    assert_eq!(
+      args.source_token_account.snapshot().unwrap(),
+      (42_000, mint)
+    );
+    assert_eq!(*args.owner.pubkey(), owner_key);
+
+    assert!(RecoverFeesArgs::with_parsed_args(&accounts, &Pubkey::new_unique(), nonce).is_err());
+  }
+
+  #[test]
+  pub fn test_native_token_account_amount_delta() {
+    let pubkey = Pubkey::new_unique();
+    let native_mint = spl_token::native_mint::id();
+    let mut test_account = token_account(&native_mint, &Pubkey::new_unique(), 1_000);
+    test_account.data[109..113].copy_from_slice(&[1, 0, 0, 0]);
+    test_account.data[113..121].copy_from_slice(&2039280u64.to_le_bytes());
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let token_account = TokenAccount::new(&account_info).unwrap();
+    assert!(token_account.is_native().unwrap());
+    let (amount_before, mint) = token_account.snapshot().unwrap();
+    assert_eq!(mint, native_mint);
+
+    // rent top-up and swap output both land in lamports, only the token
+    // amount reflects the swap
+    **account_info.lamports.borrow_mut() += 5_000_000 + 500;
+    account_info.data.borrow_mut()[64..72].copy_from_slice(&1_500u64.to_le_bytes());
+    assert_eq!(token_account.amount_delta(amount_before).unwrap(), 500);
+  }
+
+  #[test]
+  pub fn test_token_account_vault_owner() {
+    let (mint, authority) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (vault_key, other_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut vault = token_account(&mint, &authority, 1_000);
+    let mut other = token_account(&mint, &Pubkey::new_unique(), 1_000);
+    let vault_info = (&vault_key, &mut vault).into_account_info();
+    let other_info = (&other_key, &mut other).into_account_info();
+    assert_eq!(
+      TokenAccount::new(&vault_info)
+        .unwrap()
+        .check_vault_owner(&authority),
+      Ok(())
+    );
+    assert_eq!(
+      TokenAccount::new(&other_info)
+        .unwrap()
+        .check_vault_owner(&authority),
+      Err(ProtocolError::InvalidTokenAccount)
+    );
+  }
+
+  #[test]
+  pub fn test_token_account_rent_exempt() {
+    let rent = Rent::default();
+    let pubkey = Pubkey::new_unique();
+    let exempt_lamports = rent.minimum_balance(spl_token::ACCOUNT_LEN);
+    let mut test_account = token_account(&Pubkey::new_unique(), &Pubkey::new_unique(), 0);
+    test_account.lamports = exempt_lamports;
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let token_account = TokenAccount::new(&account_info).unwrap();
+    assert_eq!(token_account.check_rent_exempt(&rent), Ok(()));
+
+    let mut test_account = token_account(&Pubkey::new_unique(), &Pubkey::new_unique(), 0);
+    test_account.lamports = exempt_lamports - 1;
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let token_account = TokenAccount::new(&account_info).unwrap();
+    assert_eq!(
+      token_account.check_rent_exempt(&rent),
+      Err(ProtocolError::NotRentExempt)
+    );
+  }
+
+  #[test]
+  pub fn test_user_args_frozen_account() {
+    let keys = [
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    ];
+    let mut source = token_account(&Pubkey::new_unique(), &keys[2], 100);
+    let mut destination = token_account(&Pubkey::new_unique(), &keys[2], 0);
+    destination.data[0x6c] = 2;
+    let mut owner = Account::default();
+    let accounts = [
+      (&keys[0], &mut source).into_account_info(),
+      (&keys[1], &mut destination).into_account_info(),
+      (&keys[2], &mut owner).into_account_info(),
+    ];
+    assert_eq!(
+      TokenAccount::new(&accounts[1]).err(),
+      Some(ProtocolError::AccountFrozen)
+    );
+    assert_eq!(
+      UserArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::AccountFrozen)
+    );
+
+    accounts[1].data.borrow_mut()[0x6c] = 1;
+    accounts[0].data.borrow_mut()[0x6c] = 2;
+    assert_eq!(
+      UserArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::AccountFrozen)
+    );
+    accounts[0].data.borrow_mut()[0x6c] = 1;
+    assert!(UserArgs::with_parsed_args(&accounts).is_ok());
+  }
+
+  #[test]
+  pub fn test_user_args_fee_account_alias() {
+    let keys = [
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    ];
+    let mut source = token_account(&Pubkey::new_unique(), &keys[2], 100);
+    let mut destination = token_account(&Pubkey::new_unique(), &keys[2], 0);
+    let mut owner = Account::default();
+    let accounts = [
+      (&keys[0], &mut source).into_account_info(),
+      (&keys[1], &mut destination).into_account_info(),
+      (&keys[2], &mut owner).into_account_info(),
+    ];
+    let user_args = UserArgs::with_parsed_args(&accounts).unwrap();
+    assert_eq!(
+      user_args.check_fee_token_account(&keys[0]).err(),
+      Some(ProtocolError::InvalidFeeTokenAccount)
+    );
+    assert_eq!(
+      user_args.check_fee_token_account(&keys[1]).err(),
+      Some(ProtocolError::InvalidFeeTokenAccount)
+    );
+    assert!(user_args
+      .check_fee_token_account(&Pubkey::new_unique())
+      .is_ok());
+  }
+
+  #[test]
+  pub fn test_user_args_token_program() {
+    let keys = [
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    ];
+    let mut source = token_account(&Pubkey::new_unique(), &keys[2], 100);
+    let mut destination = token_account(&Pubkey::new_unique(), &keys[2], 0);
+    let mut owner = Account::default();
+    let mut token_program = Account::default();
+    let accounts = [
+      (&keys[0], &mut source).into_account_info(),
+      (&keys[1], &mut destination).into_account_info(),
+      (&keys[2], &mut owner).into_account_info(),
+    ];
+    let token_program_info = (&spl_token::ID, &mut token_program).into_account_info();
+    let spl_token_program = SplTokenProgram::new(&token_program_info).unwrap();
+    let user_args = UserArgs::with_parsed_args(&accounts).unwrap();
+    assert!(user_args.check_token_program(&spl_token_program).is_ok());
+
+    // a destination owned by another token program
+    let token_2022 = Pubkey::new_unique();
+    accounts[1].assign(&token_2022);
+    assert_eq!(
+      user_args.check_token_program(&spl_token_program).err(),
+      Some(ProtocolError::IncorrectTokenProgramId)
+    );
+    accounts[1].assign(&spl_token::ID);
+    accounts[0].assign(&token_2022);
+    assert_eq!(
+      user_args.check_token_program(&spl_token_program).err(),
+      Some(ProtocolError::IncorrectTokenProgramId)
+    );
+  }
+
+  #[test]
+  pub fn test_token_account_snapshot() {
+    let pubkey = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+    data[0..32].copy_from_slice(mint.as_ref());
+    data[32..64].copy_from_slice(owner.as_ref());
+    data[64..72].copy_from_slice(&1_234_567u64.to_le_bytes());
+    data[0x6c] = 1;
+    let mut test_account = Account {
+      lamports: 2039280,
+      data,
+      owner: spl_token::ID,
+      executable: false,
+      rent_epoch: 281,
+    };
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let token_account = TokenAccount::new(&account_info).unwrap();
+    let (balance, snapshot_mint) = token_account.snapshot().unwrap();
+    assert_eq!(balance, token_account.balance().unwrap());
+    assert_eq!(snapshot_mint, token_account.mint().unwrap());
+    assert_eq!(balance, 1_234_567);
+    assert_eq!(snapshot_mint, mint);
+  }
+
+  #[test]
+  pub fn test_token_account_spendable_snapshot() {
+    let pubkey = Pubkey::new_unique();
+    let (mint, owner, delegate) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let mut test_account = token_account(&mint, &owner, 1_000);
+    test_account.data[72..76].copy_from_slice(&[1, 0, 0, 0]);
+    test_account.data[76..108].copy_from_slice(delegate.as_ref());
+    test_account.data[121..129].copy_from_slice(&400u64.to_le_bytes());
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let token_account = TokenAccount::new(&account_info).unwrap();
+    assert_eq!(token_account.delegated_amount(), Ok(400));
+    assert_eq!(token_account.spendable_snapshot(&owner), Ok((1_000, mint)));
+    assert_eq!(token_account.spendable_snapshot(&delegate), Ok((400, mint)));
+    // neither owner nor delegate, left to the token program to reject
+    assert_eq!(
+      token_account.spendable_snapshot(&Pubkey::new_unique()),
+      Ok((1_000, mint))
+    );
+
+    account_info.data.borrow_mut()[121..129].copy_from_slice(&5_000u64.to_le_bytes());
+    assert_eq!(
+      token_account.spendable_snapshot(&delegate),
+      Ok((1_000, mint))
+    );
+    account_info.data.borrow_mut()[121..129].copy_from_slice(&0u64.to_le_bytes());
+    assert_eq!(
+      token_account.spendable_snapshot(&delegate),
+      Err(ProtocolError::InvalidDelegate)
+    );
+  }
+
+  #[test]
+  pub fn test_validate_decimals() {
+    let pubkey = Pubkey::new_unique();
+    let mut data = vec![0u8; spl_token::MINT_LEN];
+    data[44] = 6;
+    data[0x2d] = 1;
+    let mut mint_account = Account {
+      lamports: 1_461_600,
+      data,
+      owner: spl_token::ID,
+      executable: false,
+      rent_epoch: 281,
+    };
+    let mint_info = (&pubkey, &mut mint_account).into_account_info();
+    let mint = TokenMint::new(&mint_info).unwrap();
+    assert_eq!(validate_decimals(mint, 6), Ok(()));
+    assert_eq!(
+      validate_decimals(mint, 9),
+      Err(ProtocolError::InvalidMintDecimals)
+    );
+    assert_eq!(
+      validate_decimals(mint, 0),
+      Err(ProtocolError::InvalidMintDecimals)
+    );
+  }
+}
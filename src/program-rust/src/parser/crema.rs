@@ -1,11 +1,13 @@
-use super::base::TokenAccount;
+use super::base::{dex_program_ids, validate_dex_program, TokenAccount};
 use crate::{
   declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
   parser::base::validate_authority_pubkey,
 };
 use arrayref::array_ref;
 use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
+use std::convert::TryFrom;
 
 declare_validated_account_wrapper!(SwapInfoV1, |account: &AccountInfo| {
   let account_data = account
@@ -17,6 +19,10 @@ declare_validated_account_wrapper!(SwapInfoV1, |account: &AccountInfo| {
   if account_data[34] != 1 {
     return Err(ProtocolError::InvalidCremaSwapAccountData);
   }
+  let is_paused = account_data[33];
+  if is_paused != 0 {
+    return Err(ProtocolError::CremaPoolDisabled);
+  }
   Ok(())
 });
 
@@ -62,6 +68,73 @@ impl<'a, 'b: 'a> SwapInfoV1<'a, 'b> {
       .map_err(|_| ProtocolError::BorrowAccountDataError)?;
     Ok(Pubkey::new_from_array(*array_ref![data, 260, 32]))
   }
+
+  /// Trade fee, scaled by `FEE_DENOMINATOR`. The manager fee is taken out of
+  /// it, not on top.
+  pub fn fee_numerator(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 357, 8]))
+  }
+
+  /// sqrt(token_b / token_a), scaled by `SQRT_PRICE_SCALE`.
+  pub fn current_sqrt_price(self) -> ProtocolResult<u128> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u128::from_le_bytes(*array_ref![data, 377, 16]))
+  }
+
+  pub fn current_liquidity(self) -> ProtocolResult<u128> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u128::from_le_bytes(*array_ref![data, 393, 16]))
+  }
+}
+
+pub const FEE_DENOMINATOR: u64 = 1_000_000_000_000;
+pub const SQRT_PRICE_SCALE: u128 = 1_000_000_000_000;
+
+/// Approximate output of a crema swap. Inside a tick the pool behaves as a
+/// constant product pool on the virtual reserves `L / sqrt_price` and
+/// `L * sqrt_price`, so this is exact only as long as the swap does not cross
+/// the next initialized tick. Larger trades are over-quoted.
+pub fn quote_crema(swap_info: SwapInfoV1, amount_in: u64, a_to_b: bool) -> ProtocolResult<u64> {
+  let sqrt_price = swap_info.current_sqrt_price()?;
+  let liquidity = swap_info.current_liquidity()?;
+  if sqrt_price == 0 || liquidity == 0 {
+    return Ok(0);
+  }
+  let fee = (amount_in as u128)
+    .checked_mul(swap_info.fee_numerator()? as u128)
+    .and_then(|v| v.checked_add(FEE_DENOMINATOR as u128 - 1))
+    .ok_or(ProtocolError::Overflow)?
+    / FEE_DENOMINATOR as u128;
+  let amount_in = (amount_in as u128).saturating_sub(fee);
+
+  let reserve_a = liquidity
+    .checked_mul(SQRT_PRICE_SCALE)
+    .ok_or(ProtocolError::Overflow)?
+    / sqrt_price;
+  let reserve_b = liquidity
+    .checked_mul(sqrt_price)
+    .ok_or(ProtocolError::Overflow)?
+    / SQRT_PRICE_SCALE;
+  let (source_reserve, destination_reserve) = if a_to_b {
+    (reserve_a, reserve_b)
+  } else {
+    (reserve_b, reserve_a)
+  };
+  let out = destination_reserve
+    .checked_mul(amount_in)
+    .and_then(|v| v.checked_div(source_reserve.checked_add(amount_in)?))
+    .ok_or(ProtocolError::Overflow)?;
+  u64::try_from(out).map_err(|_| ProtocolError::Overflow)
 }
 
 #[derive(Copy, Clone)]
@@ -75,10 +148,10 @@ pub struct CremaSwapV1Args<'a, 'b: 'a> {
 }
 
 impl<'a, 'b: 'a> CremaSwapV1Args<'a, 'b> {
-  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 6;
+  pub const MIN_ACCOUNTS: usize = 6;
 
-    if accounts.len() != MIN_ACCOUNTS {
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if accounts.len() != Self::MIN_ACCOUNTS {
       return Err(ProtocolError::InvalidAccountsLength);
     }
     let &[
@@ -88,17 +161,17 @@ impl<'a, 'b: 'a> CremaSwapV1Args<'a, 'b> {
       ref pool_token_b_acc,
       ref tick_dst_acc,
       ref program_id,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = array_ref![accounts, 0, Self::MIN_ACCOUNTS];
 
     if !swap_info_acc.is_writable {
       return Err(ProtocolError::ReadonlyAccount);
     }
     let swap_info = SwapInfoV1::new(swap_info_acc)?;
-    if !program_id.executable || *swap_info_acc.owner != *program_id.key {
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::CremaFinance))?;
+    if *swap_info_acc.owner != *program_id.key {
       msg!(
-        "program_id: {}, executable: {}, swap_info: {}, owner: {}",
+        "program_id: {}, swap_info: {}, owner: {}",
         program_id.key.to_string(),
-        program_id.executable,
         swap_info_acc.key.to_string(),
         swap_info_acc.owner.to_string(),
       );
@@ -151,30 +224,31 @@ impl<'a, 'b: 'a> CremaSwapV1Args<'a, 'b> {
     } else if *source_mint_key == pool_token_b_mint && *destination_mint_key == pool_token_a_mint {
       return Ok((&self.pool_token_b, &self.pool_token_a));
     }
-    Err(ProtocolError::InvalidTokenMint)
+    Err(ProtocolError::MintRoutingMismatch)
   }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
   use super::*;
   use bs58;
   use solana_sdk::{account::Account, account_info::IntoAccountInfo};
   use std::str::FromStr;
 
-  #[test]
-  pub fn test_parse_crema_swap_v1() {
-    let pubkey = Pubkey::from_str("8J3avAjuRfL2CYFKKDwhhceiRoajhrHv9kN5nUiEnuBG").unwrap();
-    let program_id = Pubkey::from_str("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319").unwrap();
-    let account_data = "GfbXvUuzWx8PEGeQR41UGuxsUTmM7kYjMA5BZZoQv9MAGkNCeEkfcusa5rLVifmCQRSPr8vPwQ8wRFAzuGSXGgH4wUKBph
+  pub(crate) const CREMA_SWAP_INFO_DATA: &str = "GfbXvUuzWx8PEGeQR41UGuxsUTmM7kYjMA5BZZoQv9MAGkNCeEkfcusa5rLVifmCQRSPr8vPwQ8wRFAzuGSXGgH4wUKBph
 CBDT9quQHAAvBLUJTqMXaSqYjNtq9s3QSZHsCZE1HA8iBHBUgZzW79KnBqHPEnpENxcsN2fAeM4ZtnptTrTYyvnNHjzkfK15jPhXeBntuYRnrubVfYs5HL8X
 WVZrUsGc2FiNmw9DxsgctR1pJUfkqqkUSvXUywbDnSVwgJpjCQUTWJYwGUCfWyKcjezWvVuRJaobis634fDApe3SmXJEFo5KiT3hgVCJWiZcRCie4wR3daiR
 YZybDHAn6bUYwVN82MRcq4EyiZrChSXgu3S67uiLfDnR3Wfmgn6nCZG2UnuYT6MiASsNDdxVP2RjMquLYkL8ZU2RHUvVLYUNfXpJArnt95ByCXA9zv4DhRUh
 SaE3zxQ9yT9m4eBR3rqsmxsjdpWv7EPezNnqiuKJjWNMrxrEb77ecX6UpsdVn6LWJWKtU67Ug6DjKYGGVcrCw4T7ZGppQr6y5pvXYQLe42RFUh77Jvm6CKqc
 WExa6Gae6euRW6eCcTw5Lf4F7y6PZxD3wek4uMrrHnURYHBkaumuCDiy1z3kbrv9R9RGsYT";
+
+  #[test]
+  pub fn test_parse_crema_swap_v1() {
+    let pubkey = Pubkey::from_str("8J3avAjuRfL2CYFKKDwhhceiRoajhrHv9kN5nUiEnuBG").unwrap();
+    let program_id = Pubkey::from_str("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319").unwrap();
     let mut test_account = Account {
       lamports: 4182960,
-      data: bs58::decode(account_data.replace('\n', ""))
+      data: bs58::decode(CREMA_SWAP_INFO_DATA.replace('\n', ""))
         .into_vec()
         .unwrap(),
       owner: program_id,
@@ -202,4 +276,49 @@ WExa6Gae6euRW6eCcTw5Lf4F7y6PZxD3wek4uMrrHnURYHBkaumuCDiy1z3kbrv9R9RGsYT";
     );
     assert_eq!(c.nonce().unwrap(), 254,);
   }
+  #[test]
+  pub fn test_crema_quote() {
+    let pubkey = Pubkey::from_str("8J3avAjuRfL2CYFKKDwhhceiRoajhrHv9kN5nUiEnuBG").unwrap();
+    let mut test_account = Account {
+      lamports: 4182960,
+      data: bs58::decode(CREMA_SWAP_INFO_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap(),
+      owner: Pubkey::from_str("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319").unwrap(),
+      executable: false,
+      rent_epoch: 281,
+    };
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    let c = SwapInfoV1::new(&account_info).unwrap();
+    assert_eq!(c.fee_numerator().unwrap(), 100_000_000);
+    assert_eq!(c.current_sqrt_price().unwrap(), 1_000_163_818_166);
+    assert_eq!(c.current_liquidity().unwrap(), 963_291_920_916_190);
+
+    // USDT -> USDC at ~1.00033 minus the 0.01% fee
+    assert_eq!(quote_crema(c, 1_000_000, true).unwrap(), 1_000_227);
+    assert_eq!(quote_crema(c, 1_000_000, false).unwrap(), 999_572);
+    assert_eq!(quote_crema(c, 0, true).unwrap(), 0);
+  }
+
+  #[test]
+  pub fn test_parse_crema_swap_v1_paused() {
+    let pubkey = Pubkey::from_str("8J3avAjuRfL2CYFKKDwhhceiRoajhrHv9kN5nUiEnuBG").unwrap();
+    let program_id = Pubkey::from_str("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319").unwrap();
+    let mut data = bs58::decode(CREMA_SWAP_INFO_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    data[33] = 1;
+    let mut test_account = Account {
+      lamports: 4182960,
+      data,
+      owner: program_id,
+      executable: false,
+      rent_epoch: 281,
+    };
+    let account_info = (&pubkey, &mut test_account).into_account_info();
+    assert_eq!(
+      SwapInfoV1::new(&account_info).err(),
+      Some(ProtocolError::CremaPoolDisabled)
+    );
+  }
 }
@@ -1,9 +1,15 @@
-use super::base::{validate_authority_pubkey, TokenAccount, TokenMint};
+use super::{
+  base::{
+    dex_program_ids, validate_authority_pubkey, validate_dex_program, TokenAccount, TokenMint,
+  },
+  reserves::{normalize_amount, PoolReserves},
+};
 use crate::{
-  declare_validated_account_wrapper,
+  check_unreachable, declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
 };
-use arrayref::array_ref;
+use arrayref::{array_ref, array_refs};
 use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
 
 declare_validated_account_wrapper!(CropperSwapV1, |account: &AccountInfo| {
@@ -11,7 +17,11 @@ declare_validated_account_wrapper!(CropperSwapV1, |account: &AccountInfo| {
     .try_borrow_data()
     .map_err(|_| ProtocolError::BorrowAccountDataError)?;
   if account_data.len() != 291 {
-    return Err(ProtocolError::InvalidCremaSwapAccountData);
+    return Err(ProtocolError::InvalidCropperSwapAccountData);
+  }
+  let is_initialized = account_data[1];
+  if is_initialized != 1 {
+    return Err(ProtocolError::InvalidCropperSwapAccountData);
   }
   Ok(())
 });
@@ -72,7 +82,7 @@ declare_validated_account_wrapper!(CropperProgramState, |account: &AccountInfo|
     .try_borrow_data()
     .map_err(|_| ProtocolError::BorrowAccountDataError)?;
   if account_data.len() != 130 {
-    return Err(ProtocolError::InvalidCremaSwapAccountData);
+    return Err(ProtocolError::InvalidCropperSwapAccountData);
   }
   Ok(())
 });
@@ -97,15 +107,22 @@ pub struct CropperArgs<'a, 'b: 'a> {
   pub pool_mint: TokenMint<'a, 'b>,
   pub fee_account: TokenAccount<'a, 'b>,
   pub program_id: &'a AccountInfo<'b>,
+  /// optional `(token_a_mint, token_b_mint)`, only needed to read the decimals
+  pub mints: Option<(TokenMint<'a, 'b>, TokenMint<'a, 'b>)>,
 }
 
 impl<'a, 'b: 'a> CropperArgs<'a, 'b> {
-  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 8;
+  pub const MIN_ACCOUNTS: usize = 8;
 
-    if accounts.len() != MIN_ACCOUNTS {
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if !(accounts.len() == Self::MIN_ACCOUNTS || accounts.len() == Self::MIN_ACCOUNTS + 2) {
       return Err(ProtocolError::InvalidAccountsLength);
     }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, mint_accounts): (
+      &'a [AccountInfo<'b>; Self::MIN_ACCOUNTS],
+      &'a [AccountInfo<'b>],
+    ) = array_refs![accounts, Self::MIN_ACCOUNTS; .. ;];
     let &[
       ref swap_info_acc,
       ref authority,
@@ -115,10 +132,11 @@ impl<'a, 'b: 'a> CropperArgs<'a, 'b> {
       ref pool_mint_acc,
       ref fee_account_acc,
       ref program_id,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = fixed_accounts;
 
     let swap_info = CropperSwapV1::new(swap_info_acc)?;
-    if !program_id.executable || *swap_info_acc.owner != *program_id.key {
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::CropperFinance))?;
+    if *swap_info_acc.owner != *program_id.key {
       return Err(ProtocolError::InvalidProgramAddress);
     }
 
@@ -149,6 +167,8 @@ impl<'a, 'b: 'a> CropperArgs<'a, 'b> {
       &swap_info_acc.key.to_bytes(),
       swap_info.nonce()?,
     )?;
+    token_a_account.check_vault_owner(authority.key)?;
+    token_b_account.check_vault_owner(authority.key)?;
 
     let program_state = CropperProgramState::new(program_state_acc)?;
     let fee_account = TokenAccount::new(fee_account_acc)?;
@@ -169,6 +189,22 @@ impl<'a, 'b: 'a> CropperArgs<'a, 'b> {
       );
     }
 
+    let mints = match mint_accounts {
+      [] => None,
+      [ref token_a_mint_acc, ref token_b_mint_acc] => {
+        if *token_a_mint_acc.key != swap_info.token_a_mint()?
+          || *token_b_mint_acc.key != swap_info.token_b_mint()?
+        {
+          return Err(ProtocolError::InvalidTokenMint);
+        }
+        Some((
+          TokenMint::new(token_a_mint_acc)?,
+          TokenMint::new(token_b_mint_acc)?,
+        ))
+      }
+      _ => check_unreachable!()?,
+    };
+
     Ok(Self {
       swap_info,
       authority,
@@ -178,28 +214,62 @@ impl<'a, 'b: 'a> CropperArgs<'a, 'b> {
       pool_mint,
       fee_account,
       program_id,
+      mints,
     })
   }
+
+  /// Decimals of the token a mint, `None` without the mint accounts.
+  pub fn token_a_decimals(&self) -> ProtocolResult<Option<u8>> {
+    self.mints.map(|(mint_a, _)| mint_a.decimals()).transpose()
+  }
+
+  /// Decimals of the token b mint, `None` without the mint accounts.
+  pub fn token_b_decimals(&self) -> ProtocolResult<Option<u8>> {
+    self.mints.map(|(_, mint_b)| mint_b.decimals()).transpose()
+  }
+
+  /// `quote` rescaled from the destination mint decimals to
+  /// `target_decimals`, see `AldrinPoolArgs::normalized_quote`.
+  pub fn normalized_quote(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    target_decimals: u8,
+  ) -> ProtocolResult<u64> {
+    let quote = self.quote(source_mint, destination_mint, amount_in)?;
+    let decimals = if *destination_mint == self.swap_info.token_a_mint()? {
+      self.token_a_decimals()?
+    } else {
+      self.token_b_decimals()?
+    };
+    normalize_amount(
+      quote,
+      decimals.ok_or(ProtocolError::ExpectedAccount)?,
+      target_decimals,
+    )
+  }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
   use super::*;
   use bs58;
   use solana_sdk::{account::Account, account_info::IntoAccountInfo};
   use std::str::FromStr;
 
+  pub(crate) const CROPPER_SWAP_INFO_DATA: &str = "2C1RW18oraJgyUDV6gjSYhkVvyAJktsiS4Hq3dn2J6HQn3EDc1L9HW8uVLZPAGvfJxLeVQBinxLGGpcySQeD1sfrUiPRYy3u
+GqmEhSz8LxYtVh2a8qBpQwPnrExV2EGqdvii6s3KUdxayiDAiEv8pUoF5xDHGQNHwYnA8r76yiFkc8RMom5pahKvqH4vBeJ2ypMBCqXos98PB4p9s7HanZQJ
+wwNsNLBhoPbzt4ETyew4TPGnb5dAuvQtmLRmHmiMrMv4hjcbn3yBYrtzyfFs774i28HRTL9n9S3DbgYsUmJPBBbJjU3TaJBxLyiWASQDrd4snbxpcWbgTo95
+WiQ3pv9mtcjZxcGchY1hw4AGj83tmHeah5EE5cRWrhqemnT9TZLoFHzoVRZBW";
+
   #[test]
   pub fn test_parse_cropper_swap_info() {
     let pubkey = Pubkey::from_str("7NWyuTfpb8gfRpgm67yv5GkdX2EM3WkefGSwHZfNVzTW").unwrap();
     let program_id = Pubkey::from_str("CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh").unwrap();
-    let account_data = "2C1RW18oraJgyUDV6gjSYhkVvyAJktsiS4Hq3dn2J6HQn3EDc1L9HW8uVLZPAGvfJxLeVQBinxLGGpcySQeD1sfrUiPRYy3u
-GqmEhSz8LxYtVh2a8qBpQwPnrExV2EGqdvii6s3KUdxayiDAiEv8pUoF5xDHGQNHwYnA8r76yiFkc8RMom5pahKvqH4vBeJ2ypMBCqXos98PB4p9s7HanZQJ
-wwNsNLBhoPbzt4ETyew4TPGnb5dAuvQtmLRmHmiMrMv4hjcbn3yBYrtzyfFs774i28HRTL9n9S3DbgYsUmJPBBbJjU3TaJBxLyiWASQDrd4snbxpcWbgTo95
-WiQ3pv9mtcjZxcGchY1hw4AGj83tmHeah5EE5cRWrhqemnT9TZLoFHzoVRZBW";
     let mut test_account = Account {
       lamports: 2916240,
-      data: bs58::decode(account_data.replace('\n', ""))
+      data: bs58::decode(CROPPER_SWAP_INFO_DATA.replace('\n', ""))
         .into_vec()
         .unwrap(),
       owner: program_id,
@@ -232,6 +302,31 @@ WiQ3pv9mtcjZxcGchY1hw4AGj83tmHeah5EE5cRWrhqemnT9TZLoFHzoVRZBW";
     assert_eq!(c.nonce().unwrap(), 253);
   }
 
+  #[test]
+  pub fn test_parse_cropper_swap_info_invalid() {
+    let pubkey = Pubkey::from_str("7NWyuTfpb8gfRpgm67yv5GkdX2EM3WkefGSwHZfNVzTW").unwrap();
+    let program_id = Pubkey::from_str("CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh").unwrap();
+    let data = bs58::decode(CROPPER_SWAP_INFO_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    let mut uninitialized = data.clone();
+    uninitialized[1] = 0;
+    for data in [uninitialized, data[..290].to_vec()] {
+      let mut test_account = Account {
+        lamports: 2916240,
+        data,
+        owner: program_id,
+        executable: false,
+        rent_epoch: 283,
+      };
+      let account_info = (&pubkey, &mut test_account).into_account_info();
+      assert_eq!(
+        CropperSwapV1::new(&account_info).err(),
+        Some(ProtocolError::InvalidCropperSwapAccountData)
+      );
+    }
+  }
+
   #[test]
   pub fn test_parse_cropper_program_state() {
     let pubkey = Pubkey::from_str("3hsU1VgsBgBgz5jWiqdw9RfGU6TpWdCmdah1oi4kF3Tq").unwrap();
@@ -0,0 +1,223 @@
+use super::{base::dex_program_ids, meteora_damm, raydium_cpmm};
+use crate::instruction::ExchangerType;
+use arrayref::array_ref;
+use solana_program::account_info::AccountInfo;
+
+const RAYDIUM_AMM_LEN: usize = 752;
+const CREMA_SWAP_INFO_LEN: usize = 473;
+const ALDRIN_POOL_LEN: usize = 474;
+const CROPPER_SWAP_INFO_LEN: usize = 291;
+const STABLE_SWAP_INFO_LEN: usize = 395;
+const SPL_TOKEN_SWAP_INFO_LEN: usize = 324;
+const SERUM_MARKET_LEN: usize = 388;
+
+/// anchor discriminator of aldrin `Pool` account, also used by meteora damm v2
+const ALDRIN_POOL_DISCRIMINATOR: [u8; 8] = [241, 154, 109, 4, 17, 177, 109, 188];
+
+/// Exchangers with a pool account to detect, orca v1 before spl-token-swap
+/// whose program ids include it.
+const DETECTED_EXCHANGERS: [ExchangerType; 10] = [
+  ExchangerType::RaydiumSwap,
+  ExchangerType::CremaFinance,
+  ExchangerType::AldrinExchange,
+  ExchangerType::MeteoraDamm,
+  ExchangerType::RaydiumCpmm,
+  ExchangerType::CropperFinance,
+  ExchangerType::StableSwap,
+  ExchangerType::OrcaV1,
+  ExchangerType::SplTokenSwap,
+  ExchangerType::SerumDex,
+];
+
+/// Classifies a pool account by its owner, then checks the owner's layout:
+/// data length and discriminator or initialized flag. Pool status (paused,
+/// disabled...) is not checked here, the exchanger parser still validates
+/// the account before swapping.
+pub fn detect_exchanger(account: &AccountInfo) -> Option<ExchangerType> {
+  let exchanger = DETECTED_EXCHANGERS
+    .iter()
+    .copied()
+    .find(|exchanger| dex_program_ids(*exchanger).contains(account.owner))?;
+  let data = account.try_borrow_data().ok()?;
+  let matches = match exchanger {
+    ExchangerType::RaydiumSwap => {
+      data.len() == RAYDIUM_AMM_LEN && u64::from_le_bytes(*array_ref![data, 0, 8]) != 0
+    }
+    ExchangerType::CremaFinance => data.len() == CREMA_SWAP_INFO_LEN && data[34] == 1,
+    ExchangerType::AldrinExchange => {
+      data.len() == ALDRIN_POOL_LEN && *array_ref![data, 0, 8] == ALDRIN_POOL_DISCRIMINATOR
+    }
+    ExchangerType::MeteoraDamm => {
+      data.len() == meteora_damm::POOL_LEN && *array_ref![data, 0, 8] == ALDRIN_POOL_DISCRIMINATOR
+    }
+    ExchangerType::RaydiumCpmm => {
+      data.len() == raydium_cpmm::POOL_LEN
+        && *array_ref![data, 0, 8] == raydium_cpmm::POOL_DISCRIMINATOR
+    }
+    ExchangerType::CropperFinance => data.len() == CROPPER_SWAP_INFO_LEN && data[1] == 1,
+    ExchangerType::StableSwap => data.len() == STABLE_SWAP_INFO_LEN && data[0] == 1,
+    ExchangerType::OrcaV1 | ExchangerType::SplTokenSwap => {
+      data.len() == SPL_TOKEN_SWAP_INFO_LEN && data[0] == 1 && data[1] == 1
+    }
+    ExchangerType::SerumDex => {
+      data.len() == SERUM_MARKET_LEN
+        && data[..5] == *b"serum"
+        && u64::from_le_bytes(*array_ref![data, 5, 8]) == 3
+    }
+    _ => false,
+  };
+  if matches {
+    Some(exchanger)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{aldrin, crema, cropper, raydium, serum_dex};
+  use solana_sdk::{account::Account, account_info::IntoAccountInfo, pubkey::Pubkey};
+  use std::str::FromStr;
+
+  fn detect(owner: &str, data: Vec<u8>) -> Option<ExchangerType> {
+    let pubkey = Pubkey::new_unique();
+    let mut account = Account {
+      lamports: 1000000,
+      data,
+      owner: Pubkey::from_str(owner).unwrap(),
+      executable: false,
+      rent_epoch: 281,
+    };
+    let account_info = (&pubkey, &mut account).into_account_info();
+    detect_exchanger(&account_info)
+  }
+
+  fn decode(data: &str) -> Vec<u8> {
+    bs58::decode(data.replace('\n', "")).into_vec().unwrap()
+  }
+
+  #[test]
+  fn test_detect_exchanger() {
+    assert_eq!(
+      detect(
+        "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8",
+        decode(raydium::tests::RAYDIUM_AMM_DATA)
+      ),
+      Some(ExchangerType::RaydiumSwap)
+    );
+    assert_eq!(
+      detect(
+        "6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319",
+        decode(crema::test::CREMA_SWAP_INFO_DATA)
+      ),
+      Some(ExchangerType::CremaFinance)
+    );
+    assert_eq!(
+      detect(
+        "CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4",
+        decode(aldrin::test::ALDRIN_POOL_DATA)
+      ),
+      Some(ExchangerType::AldrinExchange)
+    );
+    assert_eq!(
+      detect(
+        "CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh",
+        decode(cropper::test::CROPPER_SWAP_INFO_DATA)
+      ),
+      Some(ExchangerType::CropperFinance)
+    );
+    assert_eq!(
+      detect(
+        "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+        decode(serum_dex::tests::SERUM_MARKET_DATA)
+      ),
+      Some(ExchangerType::SerumDex)
+    );
+
+    let pool_key = Pubkey::new_unique();
+    assert_eq!(
+      detect(
+        "cpamdpZCGKUy5JxQXB4dcpGPiikHawvSWAd6mEn1sGG",
+        meteora_damm::test::pool_data(&pool_key, &pool_key, &pool_key, &pool_key)
+      ),
+      Some(ExchangerType::MeteoraDamm)
+    );
+    assert_eq!(
+      detect(
+        "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C",
+        raydium_cpmm::test::pool_data([&pool_key; 6], 255)
+      ),
+      Some(ExchangerType::RaydiumCpmm)
+    );
+
+    let mut stable_swap_data = vec![0u8; STABLE_SWAP_INFO_LEN];
+    stable_swap_data[0] = 1;
+    assert_eq!(
+      detect(
+        "SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ",
+        stable_swap_data
+      ),
+      Some(ExchangerType::StableSwap)
+    );
+    let mut token_swap_data = vec![0u8; SPL_TOKEN_SWAP_INFO_LEN];
+    token_swap_data[0] = 1;
+    token_swap_data[1] = 1;
+    assert_eq!(
+      detect(
+        "SwaPpA9LAaLfeLi3a68M4DjnLqgtticKg6CnyNwgAC8",
+        token_swap_data.clone()
+      ),
+      Some(ExchangerType::SplTokenSwap)
+    );
+    assert_eq!(
+      detect(
+        "DjVE6JNiYqPL2QXyCUUh8rNjHrbz9hXHNYt99MQ59qw1",
+        token_swap_data
+      ),
+      Some(ExchangerType::OrcaV1)
+    );
+  }
+
+  #[test]
+  fn test_detect_exchanger_unknown() {
+    // same length as a serum market but wrong header
+    let mut data = decode(serum_dex::tests::SERUM_MARKET_DATA);
+    data[0] = 0;
+    assert_eq!(
+      detect("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin", data),
+      None
+    );
+    // aldrin-sized account without the pool discriminator
+    assert_eq!(
+      detect(
+        "CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4",
+        vec![0u8; ALDRIN_POOL_LEN]
+      ),
+      None
+    );
+    // pool-sized data owned by the token program is never a pool
+    assert_eq!(
+      detect(
+        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+        decode(raydium::tests::RAYDIUM_AMM_DATA)
+      ),
+      None
+    );
+    // a pool layout under another dex program
+    assert_eq!(
+      detect(
+        "6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319",
+        decode(raydium::tests::RAYDIUM_AMM_DATA)
+      ),
+      None
+    );
+    assert_eq!(
+      detect(
+        &Pubkey::new_unique().to_string(),
+        decode(cropper::test::CROPPER_SWAP_INFO_DATA)
+      ),
+      None
+    );
+  }
+}
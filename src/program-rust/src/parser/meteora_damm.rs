@@ -0,0 +1,279 @@
+use super::base::{dex_program_ids, validate_dex_program, TokenAccount, TokenMint};
+use crate::{
+  declare_validated_account_wrapper,
+  error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
+};
+use arrayref::array_ref;
+use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey, sysvar};
+
+/// anchor discriminator of DAMM v2 `Pool` account
+const POOL_DISCRIMINATOR: [u8; 8] = [241, 154, 109, 4, 17, 177, 109, 188];
+/// anchor discriminator of DAMM v2 `Config` account
+const CONFIG_DISCRIMINATOR: [u8; 8] = [155, 12, 170, 224, 30, 250, 204, 130];
+pub const POOL_LEN: usize = 1112;
+
+declare_validated_account_wrapper!(MeteoraDammPool, |account: &AccountInfo| {
+  if !account.is_writable {
+    return Err(ProtocolError::ReadonlyAccount);
+  }
+  let account_data = account
+    .try_borrow_data()
+    .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+  if account_data.len() != POOL_LEN || *array_ref![account_data, 0, 8] != POOL_DISCRIMINATOR {
+    return Err(ProtocolError::InvalidMeteoraDammPoolAccount);
+  }
+  // 0: Enable, 1: Disable
+  let pool_status = account_data[481];
+  if pool_status != 0 {
+    return Err(ProtocolError::MeteoraDammPoolDisabled);
+  }
+  Ok(())
+});
+
+impl<'a, 'b: 'a> MeteoraDammPool<'a, 'b> {
+  pub fn token_a_mint(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 168, 32]))
+  }
+
+  pub fn token_b_mint(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 200, 32]))
+  }
+
+  pub fn token_a_vault(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 232, 32]))
+  }
+
+  pub fn token_b_vault(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 264, 32]))
+  }
+
+  /// Creator of the pool, after the fee and metrics fields at 568.
+  pub fn creator(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 648, 32]))
+  }
+}
+
+declare_validated_account_wrapper!(MeteoraDammConfig, |account: &AccountInfo| {
+  let account_data = account
+    .try_borrow_data()
+    .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+  if account_data.len() < 8 || *array_ref![account_data, 0, 8] != CONFIG_DISCRIMINATOR {
+    return Err(ProtocolError::InvalidMeteoraDammPoolAccount);
+  }
+  Ok(())
+});
+
+#[derive(Copy, Clone)]
+pub struct MeteoraDammArgs<'a, 'b: 'a> {
+  pub pool: MeteoraDammPool<'a, 'b>,
+  pub config: MeteoraDammConfig<'a, 'b>,
+  pub pool_authority: &'a AccountInfo<'b>,
+  pub token_a_vault: TokenAccount<'a, 'b>,
+  pub token_b_vault: TokenAccount<'a, 'b>,
+  pub token_a_mint: TokenMint<'a, 'b>,
+  pub token_b_mint: TokenMint<'a, 'b>,
+  /// the dynamic fee depends on the current slot/timestamp
+  pub clock: &'a AccountInfo<'b>,
+  pub event_authority: &'a AccountInfo<'b>,
+  pub program_id: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b: 'a> MeteoraDammArgs<'a, 'b> {
+  pub const MIN_ACCOUNTS: usize = 10;
+
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if accounts.len() != Self::MIN_ACCOUNTS {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let &[
+      ref pool_acc,
+      ref config_acc,
+      ref pool_authority,
+      ref token_a_vault_acc,
+      ref token_b_vault_acc,
+      ref token_a_mint_acc,
+      ref token_b_mint_acc,
+      ref clock,
+      ref event_authority,
+      ref program_id,
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = array_ref![accounts, 0, Self::MIN_ACCOUNTS];
+
+    let pool = MeteoraDammPool::new(pool_acc)?;
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::MeteoraDamm))?;
+    if *pool_acc.owner != *program_id.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    let config = MeteoraDammConfig::new(config_acc)?;
+    if *config_acc.owner != *program_id.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    if !sysvar::clock::check_id(clock.key) {
+      return Err(ProtocolError::InvalidClockAccount);
+    }
+
+    if *token_a_vault_acc.key != pool.token_a_vault()? {
+      msg!(
+        "token_a_vault got {}, expect: {}",
+        token_a_vault_acc.key,
+        pool.token_a_vault()?
+      );
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    if *token_b_vault_acc.key != pool.token_b_vault()? {
+      msg!(
+        "token_b_vault got {}, expect: {}",
+        token_b_vault_acc.key,
+        pool.token_b_vault()?
+      );
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    if *token_a_mint_acc.key != pool.token_a_mint()?
+      || *token_b_mint_acc.key != pool.token_b_mint()?
+    {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+
+    Ok(Self {
+      pool,
+      config,
+      pool_authority,
+      token_a_vault: TokenAccount::new(token_a_vault_acc)?,
+      token_b_vault: TokenAccount::new(token_b_vault_acc)?,
+      token_a_mint: TokenMint::new(token_a_mint_acc)?,
+      token_b_mint: TokenMint::new(token_b_mint_acc)?,
+      clock,
+      event_authority,
+      program_id,
+    })
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+  use crate::spl_token;
+  use solana_sdk::account_info::AccountInfo;
+  use std::str::FromStr;
+
+  pub(crate) fn pool_data(
+    token_a_mint: &Pubkey,
+    token_b_mint: &Pubkey,
+    token_a_vault: &Pubkey,
+    token_b_vault: &Pubkey,
+  ) -> Vec<u8> {
+    let mut data = vec![0u8; POOL_LEN];
+    data[0..8].copy_from_slice(&POOL_DISCRIMINATOR);
+    data[168..200].copy_from_slice(token_a_mint.as_ref());
+    data[200..232].copy_from_slice(token_b_mint.as_ref());
+    data[232..264].copy_from_slice(token_a_vault.as_ref());
+    data[264..296].copy_from_slice(token_b_vault.as_ref());
+    data
+  }
+
+  #[test]
+  fn test_parse_meteora_damm_args() {
+    let program_id = Pubkey::from_str("cpamdpZCGKUy5JxQXB4dcpGPiikHawvSWAd6mEn1sGG").unwrap();
+    let mut keys = (0..10).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    keys[7] = sysvar::clock::id();
+    keys[9] = program_id;
+    let (mint_a, mint_b) = (keys[5], keys[6]);
+
+    let mut config_data = vec![0u8; 1048];
+    config_data[0..8].copy_from_slice(&CONFIG_DISCRIMINATOR);
+    let vault_data = |mint: &Pubkey| {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut datas = vec![
+      pool_data(&mint_a, &mint_b, &keys[3], &keys[4]),
+      config_data,
+      vec![],
+      vault_data(&mint_a),
+      vault_data(&mint_b),
+      mint_data.clone(),
+      mint_data,
+      vec![0u8; 40],
+      vec![],
+      vec![],
+    ];
+    let mut owners = vec![program_id; 10];
+    owners[2] = Pubkey::default();
+    owners[3..7].fill(spl_token::ID);
+    owners[7] = sysvar::id();
+    owners[9] = Pubkey::from_str("BPFLoaderUpgradeab1e11111111111111111111111").unwrap();
+    let mut lamports = vec![1_000_000u64; 10];
+
+    let accounts = keys
+      .iter()
+      .zip(owners.iter())
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .enumerate()
+      .map(|(i, (((key, owner), data), lamports))| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          &mut data[..],
+          owner,
+          i == 9,
+          300,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args = MeteoraDammArgs::with_parsed_args(&accounts).unwrap();
+    assert_eq!(args.pool.token_a_mint().unwrap(), mint_a);
+    assert_eq!(args.pool.token_b_mint().unwrap(), mint_b);
+    assert_eq!(*args.token_a_vault.pubkey(), keys[3]);
+    assert_eq!(*args.token_b_vault.pubkey(), keys[4]);
+    assert_eq!(*args.clock.key, sysvar::clock::id());
+
+    // vaults in the wrong order
+    let mut swapped = accounts.clone();
+    swapped.swap(3, 4);
+    assert_eq!(
+      MeteoraDammArgs::with_parsed_args(&swapped).err(),
+      Some(ProtocolError::InvalidTokenAccount)
+    );
+    // clock sysvar is required
+    let mut no_clock = accounts.clone();
+    no_clock[7] = accounts[8].clone();
+    assert_eq!(
+      MeteoraDammArgs::with_parsed_args(&no_clock).err(),
+      Some(ProtocolError::InvalidClockAccount)
+    );
+    // disabled pool
+    accounts[0].data.borrow_mut()[481] = 1;
+    assert_eq!(
+      MeteoraDammArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::MeteoraDammPoolDisabled)
+    );
+  }
+}
@@ -2,7 +2,12 @@ pub mod aldrin;
 pub mod base;
 pub mod crema;
 pub mod cropper;
+pub mod detect;
+pub mod meteora_damm;
 pub mod raydium;
+pub mod raydium_cpmm;
+pub mod reserves;
+pub mod saber_wrapper;
 pub mod serum_dex;
 pub mod spl_token_swap;
 pub mod stable_swap;
@@ -1,12 +1,14 @@
 use crate::{
-  declare_validated_account_wrapper,
+  check_unreachable, declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
 };
 use arrayref::array_ref;
 use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+use std::convert::TryFrom;
 
 use super::{
-  base::TokenAccount,
+  base::{dex_program_ids, validate_decimals, validate_dex_program, TokenAccount, TokenMint},
   serum_dex::{SerumDexMarket, SerumDexOpenOrders},
 };
 
@@ -19,6 +21,10 @@ declare_validated_account_wrapper!(RaydiumAmmInfo, |account: &AccountInfo| {
     return Err(ProtocolError::InvalidTokenAccount);
   };
   let status = u64::from_le_bytes(*array_ref![data, 0, 8]);
+  // 2: Disabled, 3: WithdrawOnly, 4: LiquidityOnly
+  if (2u64..=4u64).contains(&status) {
+    return Err(ProtocolError::RaydiumPoolDisabled);
+  }
   if status != 1u64 {
     return Err(ProtocolError::InvalidAccountFlags);
   };
@@ -27,6 +33,45 @@ declare_validated_account_wrapper!(RaydiumAmmInfo, |account: &AccountInfo| {
 
 #[allow(dead_code)]
 impl<'a, 'b: 'a> RaydiumAmmInfo<'a, 'b> {
+  /// Decimals of the coin mint recorded by the AMM, after status, nonce,
+  /// order_num and depth, so at 32.
+  pub fn coin_decimals(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 32, 8]))
+  }
+
+  /// Decimals of the pc mint, right after `coin_decimals`.
+  pub fn pc_decimals(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 40, 8]))
+  }
+
+  /// Coin owed to the pool owner at the next take-pnl, still held in the
+  /// vaults. 16 u64 params, then the 8 u64 fees, so at 128 + 64.
+  pub fn need_take_pnl_coin(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 192, 8]))
+  }
+
+  /// Pc owed to the pool owner at the next take-pnl, right after
+  /// `need_take_pnl_coin`.
+  pub fn need_take_pnl_pc(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 200, 8]))
+  }
+
   pub fn token_coin(self) -> ProtocolResult<Pubkey> {
     let data = self
       .inner()
@@ -89,124 +134,69 @@ impl<'a, 'b: 'a> RaydiumAmmInfo<'a, 'b> {
     // 128 + 208
     Ok(Pubkey::new_from_array(*array_ref![data, 560, 32]))
   }
-}
 
-#[derive(Copy, Clone)]
-pub struct RaydiumSwapArgs<'a, 'b: 'a> {
-  pub amm_info: RaydiumAmmInfo<'a, 'b>,
-  pub authority: &'a AccountInfo<'b>,
-  pub open_orders: SerumDexOpenOrders<'a, 'b>,
-  pub target_orders: &'a AccountInfo<'b>,
-  pub pool_token_coin: TokenAccount<'a, 'b>,
-  pub pool_token_pc: TokenAccount<'a, 'b>,
-  pub serum_dex_program_id: &'a AccountInfo<'b>,
-  pub serum_market: SerumDexMarket<'a, 'b>,
-  pub bids: &'a AccountInfo<'b>,
-  pub asks: &'a AccountInfo<'b>,
-  pub event_q: &'a AccountInfo<'b>,
-  pub coin_vault: TokenAccount<'a, 'b>,
-  pub pc_vault: TokenAccount<'a, 'b>,
-  pub vault_signer: &'a AccountInfo<'b>,
-  pub program_id: &'a AccountInfo<'b>,
+  /// Owner the AMM was created by, after target_orders and 8 u64 of
+  /// padding, so at 592 + 32 + 64.
+  pub fn amm_owner(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 688, 32]))
+  }
 }
 
-impl<'a, 'b: 'a> RaydiumSwapArgs<'a, 'b> {
-  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 15;
-    if accounts.len() != MIN_ACCOUNTS {
-      return Err(ProtocolError::InvalidAccountsLength);
-    }
-    let &[
-      ref amm_info_acc,
-      ref authority,
-      ref open_orders_acc,
-      ref target_orders_acc,
-      ref pool_token_coin_acc,
-      ref pool_token_pc_acc,
-      ref serum_dex_program_id,
-      ref serum_market_acc,
-      ref bids,
-      ref asks,
-      ref event_q,
-      ref coin_vault_acc,
-      ref pc_vault_acc,
-      ref vault_signer,
-      ref program_id,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
-
-    if !amm_info_acc.is_writable {
-      return Err(ProtocolError::ReadonlyAccount);
-    }
-    let amm_info = RaydiumAmmInfo::new(amm_info_acc)?;
-
-    if amm_info.token_coin()? != *pool_token_coin_acc.key {
-      return Err(ProtocolError::InvalidTokenAccount);
-    }
-    if amm_info.token_pc()? != *pool_token_pc_acc.key {
-      return Err(ProtocolError::InvalidTokenAccount);
-    }
-    if amm_info.open_orders()? != *open_orders_acc.key {
-      return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
-    }
-    if amm_info.market()? != *serum_market_acc.key {
-      return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
-    }
-    if !open_orders_acc.is_writable {
-      return Err(ProtocolError::ReadonlyAccount);
-    }
-    if amm_info.serum_dex()? != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexProgramId);
-    }
+/// Checks the serum accounts against the ones referenced by the AMM, shared
+/// by the full and slim swap accounts.
+fn check_serum_market<'a, 'b: 'a>(
+  amm_info: RaydiumAmmInfo<'a, 'b>,
+  open_orders_acc: &'a AccountInfo<'b>,
+  serum_market_acc: &'a AccountInfo<'b>,
+  serum_dex_program_id: &'a AccountInfo<'b>,
+  market_queues: [&'a AccountInfo<'b>; 3],
+) -> ProtocolResult<SerumDexMarket<'a, 'b>> {
+  if amm_info.open_orders()? != *open_orders_acc.key {
+    return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
+  }
+  if amm_info.market()? != *serum_market_acc.key {
+    return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
+  }
+  if !open_orders_acc.is_writable {
+    return Err(ProtocolError::ReadonlyAccount);
+  }
+  if amm_info.serum_dex()? != *serum_dex_program_id.key {
+    return Err(ProtocolError::InvalidSerumDexProgramId);
+  }
+  validate_dex_program(
+    serum_dex_program_id,
+    dex_program_ids(ExchangerType::SerumDex),
+  )?;
 
-    let market = SerumDexMarket::new(serum_market_acc)?;
-    if *market.inner().owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *bids.owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *asks.owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *event_q.owner != *serum_dex_program_id.key {
+  let market = SerumDexMarket::new(serum_market_acc)?;
+  if *market.inner().owner != *serum_dex_program_id.key
+    || market.own_address()? != *serum_market_acc.key
+  {
+    return Err(ProtocolError::InvalidSerumDexMarketAccount);
+  }
+  if market.coin_mint()? != amm_info.coin_mint()? || market.pc_mint()? != amm_info.pc_mint()? {
+    return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
+  }
+  // bids, asks, event queue
+  for acc in market_queues {
+    if *acc.owner != *serum_dex_program_id.key {
       return Err(ProtocolError::InvalidSerumDexMarketAccount);
     }
-    Ok(Self {
-      amm_info,
-      authority,
-      open_orders: SerumDexOpenOrders::new(open_orders_acc)?,
-      target_orders: target_orders_acc,
-      pool_token_coin: TokenAccount::new(pool_token_coin_acc)?,
-      pool_token_pc: TokenAccount::new(pool_token_pc_acc)?,
-      serum_dex_program_id,
-      serum_market: market,
-      bids,
-      asks,
-      event_q,
-      coin_vault: TokenAccount::new(coin_vault_acc)?,
-      pc_vault: TokenAccount::new(pc_vault_acc)?,
-      vault_signer,
-      program_id,
-    })
   }
-
-  // pub fn find_token_pair(
-  //   &self,
-  //   source_token_account_mint: &Pubkey,
-  // ) -> ProtocolResult<(&TokenAccount<'a, 'b>, &TokenAccount<'a, 'b>)> {
-  //   if *source_token_account_mint == self.token_a.mint()? {
-  //     Ok((&self.token_a, &self.token_b))
-  //   } else {
-  //     Ok((&self.token_b, &self.token_a))
-  //   }
-  // }
+  Ok(market)
 }
 
 #[derive(Copy, Clone)]
-pub struct RaydiumSwapArgs2<'a, 'b: 'a> {
+pub struct RaydiumSwapArgs<'a, 'b: 'a> {
   pub amm_info: RaydiumAmmInfo<'a, 'b>,
   pub authority: &'a AccountInfo<'b>,
   pub open_orders: SerumDexOpenOrders<'a, 'b>,
+  /// absent in the slim account layout
+  pub target_orders: Option<&'a AccountInfo<'b>>,
   pub pool_token_coin: TokenAccount<'a, 'b>,
   pub pool_token_pc: TokenAccount<'a, 'b>,
   pub serum_dex_program_id: &'a AccountInfo<'b>,
@@ -218,18 +208,34 @@ pub struct RaydiumSwapArgs2<'a, 'b: 'a> {
   pub pc_vault: TokenAccount<'a, 'b>,
   pub vault_signer: &'a AccountInfo<'b>,
   pub program_id: &'a AccountInfo<'b>,
+  /// optional `(coin_mint, pc_mint)`, checked against the AMM mints and
+  /// decimals
+  pub mints: Option<(TokenMint<'a, 'b>, TokenMint<'a, 'b>)>,
 }
 
-impl<'a, 'b: 'a> RaydiumSwapArgs2<'a, 'b> {
+impl<'a, 'b: 'a> RaydiumSwapArgs<'a, 'b> {
+  pub const ACCOUNTS_LEN: usize = 15;
+  pub const SLIM_ACCOUNTS_LEN: usize = 14;
+
+  /// Parses either the full (with target_orders) or the slim account layout,
+  /// telling them apart by the number of accounts. Both may be followed by
+  /// the coin and pc mints.
   pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 14;
-    if accounts.len() != MIN_ACCOUNTS {
-      return Err(ProtocolError::InvalidAccountsLength);
-    }
+    let (accounts, mint_accounts) = match accounts.len() {
+      Self::ACCOUNTS_LEN | Self::SLIM_ACCOUNTS_LEN => (accounts, &[][..]),
+      len if len == Self::ACCOUNTS_LEN + 2 || len == Self::SLIM_ACCOUNTS_LEN + 2 => {
+        accounts.split_at(len - 2)
+      }
+      _ => return Err(ProtocolError::InvalidAccountsLength),
+    };
+    let (head, target_orders, tail) = match accounts.len() {
+      Self::ACCOUNTS_LEN => (&accounts[..3], Some(&accounts[3]), &accounts[4..]),
+      Self::SLIM_ACCOUNTS_LEN => (&accounts[..3], None, &accounts[3..]),
+      _ => return Err(ProtocolError::InvalidAccountsLength),
+    };
+    let &[ref amm_info_acc, ref authority, ref open_orders_acc]: &'a [AccountInfo<'b>; 3] =
+      array_ref![head, 0, 3];
     let &[
-      ref amm_info_acc,
-      ref authority,
-      ref open_orders_acc,
       ref pool_token_coin_acc,
       ref pool_token_pc_acc,
       ref serum_dex_program_id,
@@ -241,12 +247,16 @@ impl<'a, 'b: 'a> RaydiumSwapArgs2<'a, 'b> {
       ref pc_vault_acc,
       ref vault_signer,
       ref program_id,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+    ]: &'a[AccountInfo<'b>; 11] = array_ref![tail, 0, 11];
 
     if !amm_info_acc.is_writable {
       return Err(ProtocolError::ReadonlyAccount);
     }
     let amm_info = RaydiumAmmInfo::new(amm_info_acc)?;
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::RaydiumSwap))?;
+    if *amm_info_acc.owner != *program_id.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
 
     if amm_info.token_coin()? != *pool_token_coin_acc.key {
       return Err(ProtocolError::InvalidTokenAccount);
@@ -254,36 +264,35 @@ impl<'a, 'b: 'a> RaydiumSwapArgs2<'a, 'b> {
     if amm_info.token_pc()? != *pool_token_pc_acc.key {
       return Err(ProtocolError::InvalidTokenAccount);
     }
-    if amm_info.open_orders()? != *open_orders_acc.key {
-      return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
-    }
-    if amm_info.market()? != *serum_market_acc.key {
-      return Err(ProtocolError::InvalidRaydiumAmmInfoAccount);
-    }
-    if !open_orders_acc.is_writable {
-      return Err(ProtocolError::ReadonlyAccount);
-    }
-    if amm_info.serum_dex()? != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexProgramId);
-    }
-
-    let market = SerumDexMarket::new(serum_market_acc)?;
-    if *market.inner().owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *bids.owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *asks.owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
-    if *event_q.owner != *serum_dex_program_id.key {
-      return Err(ProtocolError::InvalidSerumDexMarketAccount);
-    }
+    let market = check_serum_market(
+      amm_info,
+      open_orders_acc,
+      serum_market_acc,
+      serum_dex_program_id,
+      [bids, asks, event_q],
+    )?;
+    let mints = match mint_accounts {
+      [] => None,
+      [ref coin_mint_acc, ref pc_mint_acc] => {
+        if *coin_mint_acc.key != amm_info.coin_mint()? || *pc_mint_acc.key != amm_info.pc_mint()? {
+          return Err(ProtocolError::InvalidTokenMint);
+        }
+        let coin_mint = TokenMint::new(coin_mint_acc)?;
+        let pc_mint = TokenMint::new(pc_mint_acc)?;
+        // same mints but other decimals, the amm was set up for another token
+        let recorded =
+          |decimals: u64| u8::try_from(decimals).map_err(|_| ProtocolError::InvalidMintDecimals);
+        validate_decimals(coin_mint, recorded(amm_info.coin_decimals()?)?)?;
+        validate_decimals(pc_mint, recorded(amm_info.pc_decimals()?)?)?;
+        Some((coin_mint, pc_mint))
+      }
+      _ => check_unreachable!()?,
+    };
     Ok(Self {
       amm_info,
       authority,
       open_orders: SerumDexOpenOrders::new(open_orders_acc)?,
+      target_orders,
       pool_token_coin: TokenAccount::new(pool_token_coin_acc)?,
       pool_token_pc: TokenAccount::new(pool_token_pc_acc)?,
       serum_dex_program_id,
@@ -295,34 +304,41 @@ impl<'a, 'b: 'a> RaydiumSwapArgs2<'a, 'b> {
       pc_vault: TokenAccount::new(pc_vault_acc)?,
       vault_signer,
       program_id,
+      mints,
     })
   }
 
-  // pub fn find_token_pair(
-  //   &self,
-  //   source_token_account_mint: &Pubkey,
-  // ) -> ProtocolResult<(&TokenAccount<'a, 'b>, &TokenAccount<'a, 'b>)> {
-  //   if *source_token_account_mint == self.token_a.mint()? {
-  //     Ok((&self.token_a, &self.token_b))
-  //   } else {
-  //     Ok((&self.token_b, &self.token_a))
-  //   }
-  // }
+  /// `(coin_lot_size, pc_lot_size)` of the serum or openbook market, both
+  /// checked to be non zero when the accounts are parsed.
+  pub fn lot_sizes(&self) -> ProtocolResult<(u64, u64)> {
+    Ok((
+      self.serum_market.coin_lot_size()?,
+      self.serum_market.pc_lot_size()?,
+    ))
+  }
+
+  /// Rounds `amount` of `mint` down to a whole number of market lots.
+  pub fn round_to_lot(&self, mint: &Pubkey, amount: u64) -> ProtocolResult<u64> {
+    let (coin_lot_size, pc_lot_size) = self.lot_sizes()?;
+    let lot_size = if *mint == self.amm_info.coin_mint()? {
+      coin_lot_size
+    } else if *mint == self.amm_info.pc_mint()? {
+      pc_lot_size
+    } else {
+      return Err(ProtocolError::InvalidTokenMint);
+    };
+    Ok(amount - amount % lot_size.max(1))
+  }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
   use super::*;
   use solana_sdk::{account_info::AccountInfo, pubkey::Pubkey};
   use std::str::FromStr;
 
-  #[test]
-  fn test_raydium_struct() {
-    let raydium_program_id =
-      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
-    let raydium_pubkey = Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap();
-    let raydium_data =
-      "Csa6r43w6Tksashc251QAkcpr6D4zyiWB4sSrw5xDZzoH9FsPfiZDXJSNMMTFHVsbKqVyDZb32anWxQN
+  pub(crate) const RAYDIUM_AMM_DATA: &str =
+    "Csa6r43w6Tksashc251QAkcpr6D4zyiWB4sSrw5xDZzoH9FsPfiZDXJSNMMTFHVsbKqVyDZb32anWxQN
 Nk9FL7bCpKPZ7qMdCe6eCkjjRbbdiYvHBV1TrhWWwQ6pKP3rNVfae2R25Hj8ttD9CwVTz2CRzcDDdu88N5T6J67xVhcBKwEmJB3i
 txbnWWnvHf95TBXbmmAZFrbfPm6153Re8mjTUVswfNCRVC2ypRV8jzZoBbohMWrbPxKW4VXZdaEE8JwVU5QrPFvKFJKkmeReiBre
 b7Huy52gGioSCu8FLWg8JYQHMzgnr31tR5sDa1WSVJVPUQ4t4rRazqcdALsdSKZHUrnZACbLTsEgiXQWn4Ncc9eVciH78oQsXgvP
@@ -333,7 +349,13 @@ b51Jijykz38oM6KPswC7rAxgTVVgiMu4JvKmVwecn7NCP4iWoM9k8vrYaa8tS3VBZtAMCkVtuwpQeYVZ
 aQukNQ7sfZSCEGj6vKv4fGJNpuDJDZiUXhveEjnbYffrm5Gnfz2kvSSdCgotWNJwcJZkfv5LsMkprfTXodEXXnLqqHj3LM8tNSFu
 CqhMRFKbuHdZt1EfvFWcyxNukAhUXZn5k4MVNQdhQZ5poqMfUa6AzgXBMVAYCoFrsKF9qHbCEHFLNcznS3J3go3xcCnigQtQEctX
 awtxg5yoJmS91iDZt2nTceatH7LN78fA5DxmJDn8kpF3F2";
-    let mut raydium_data = bs58::decode(raydium_data.replace('\n', ""))
+
+  #[test]
+  fn test_raydium_struct() {
+    let raydium_program_id =
+      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
+    let raydium_pubkey = Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap();
+    let mut raydium_data = bs58::decode(RAYDIUM_AMM_DATA.replace('\n', ""))
       .into_vec()
       .unwrap();
     let mut raydium_lamports = 6124800u64;
@@ -378,5 +400,477 @@ awtxg5yoJmS91iDZt2nTceatH7LN78fA5DxmJDn8kpF3F2";
       raydium_info.serum_dex().unwrap().to_string(),
       "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"
     );
+    assert_eq!(raydium_info.need_take_pnl_coin().unwrap(), 52_401_277_655);
+    assert_eq!(raydium_info.need_take_pnl_pc().unwrap(), 444_694_306_620);
+    // RAY and USDT
+    assert_eq!(raydium_info.coin_decimals().unwrap(), 6);
+    assert_eq!(raydium_info.pc_decimals().unwrap(), 6);
+  }
+
+  fn market_data(market: &Pubkey, coin_mint: &Pubkey, pc_mint: &Pubkey) -> Vec<u8> {
+    let mut data = vec![0u8; 388];
+    data[..5].copy_from_slice(b"serum");
+    data[5..13].copy_from_slice(&3u64.to_le_bytes());
+    data[13..45].copy_from_slice(market.as_ref());
+    data[53..85].copy_from_slice(coin_mint.as_ref());
+    data[85..117].copy_from_slice(pc_mint.as_ref());
+    // coin_lot_size, pc_lot_size
+    data[349..357].copy_from_slice(&1_000u64.to_le_bytes());
+    data[357..365].copy_from_slice(&10u64.to_le_bytes());
+    data[381..].copy_from_slice(b"padding");
+    data
+  }
+
+  #[test]
+  fn test_raydium_check_serum_market() {
+    let raydium_program_id =
+      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
+    let serum_program_id =
+      Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+    let raydium_pubkey = Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap();
+    let open_orders_pubkey =
+      Pubkey::from_str("7UF3m8hDGZ6bNnHzaT2YHrhp7A7n9qFfBj6QEpHPv5S8").unwrap();
+    let market_pubkey = Pubkey::from_str("teE55QrL4a4QSfydR9dnHF97jgCfptpuigbb53Lo95g").unwrap();
+    let coin_mint = Pubkey::from_str("4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R").unwrap();
+    let pc_mint = Pubkey::from_str("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB").unwrap();
+    let other_pubkey = Pubkey::new_unique();
+
+    let mut raydium_data = bs58::decode(RAYDIUM_AMM_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    let mut market_data_ok = market_data(&market_pubkey, &coin_mint, &pc_mint);
+    let mut market_data_flipped = market_data(&market_pubkey, &pc_mint, &coin_mint);
+    let mut lamports = [1_000_000u64; 5];
+    let mut empty = [vec![], vec![]];
+    let [l0, l1, l2, l3, l4] = &mut lamports;
+    let [e0, e1] = &mut empty;
+    let raydium_info = AccountInfo::new(
+      &raydium_pubkey,
+      false,
+      true,
+      l0,
+      &mut raydium_data[..],
+      &raydium_program_id,
+      false,
+      248,
+    );
+    let open_orders = AccountInfo::new(
+      &open_orders_pubkey,
+      false,
+      true,
+      l1,
+      &mut e0[..],
+      &serum_program_id,
+      false,
+      248,
+    );
+    let market = AccountInfo::new(
+      &market_pubkey,
+      false,
+      true,
+      l2,
+      &mut market_data_ok[..],
+      &serum_program_id,
+      false,
+      248,
+    );
+    let flipped_market = AccountInfo::new(
+      &market_pubkey,
+      false,
+      true,
+      l3,
+      &mut market_data_flipped[..],
+      &serum_program_id,
+      false,
+      248,
+    );
+    let serum_program = AccountInfo::new(
+      &serum_program_id,
+      false,
+      false,
+      l4,
+      &mut e1[..],
+      &other_pubkey,
+      true,
+      248,
+    );
+    let amm_info = RaydiumAmmInfo::new(&raydium_info).unwrap();
+    let queues = [&market, &market, &market];
+
+    assert!(check_serum_market(amm_info, &open_orders, &market, &serum_program, queues).is_ok());
+    // coin/pc mints do not match the amm
+    assert_eq!(
+      check_serum_market(
+        amm_info,
+        &open_orders,
+        &flipped_market,
+        &serum_program,
+        queues
+      )
+      .err(),
+      Some(ProtocolError::InvalidRaydiumAmmInfoAccount)
+    );
+    // market is not the one referenced by the amm
+    assert_eq!(
+      check_serum_market(
+        amm_info,
+        &open_orders,
+        &serum_program,
+        &serum_program,
+        queues
+      )
+      .err(),
+      Some(ProtocolError::InvalidRaydiumAmmInfoAccount)
+    );
+    // dex program is not the one referenced by the amm
+    assert_eq!(
+      check_serum_market(amm_info, &open_orders, &market, &raydium_info, queues).err(),
+      Some(ProtocolError::InvalidSerumDexProgramId)
+    );
+    // open orders is not the one referenced by the amm
+    assert_eq!(
+      check_serum_market(amm_info, &market, &market, &serum_program, queues).err(),
+      Some(ProtocolError::InvalidRaydiumAmmInfoAccount)
+    );
+    // the dex program id passed as a non executable account
+    let mut not_executable = serum_program.clone();
+    not_executable.executable = false;
+    assert_eq!(
+      check_serum_market(amm_info, &open_orders, &market, &not_executable, queues).err(),
+      Some(ProtocolError::InvalidDexProgramId)
+    );
+  }
+
+  #[test]
+  fn test_raydium_disabled_pool() {
+    let raydium_program_id =
+      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
+    let raydium_pubkey = Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap();
+    let mut raydium_data = bs58::decode(RAYDIUM_AMM_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    // status = Disabled
+    raydium_data[0..8].copy_from_slice(&2u64.to_le_bytes());
+    let mut raydium_lamports = 6124800u64;
+    let raydium_account_info = AccountInfo::new(
+      &raydium_pubkey,
+      false,
+      true,
+      &mut raydium_lamports,
+      &mut raydium_data[..],
+      &raydium_program_id,
+      false,
+      248,
+    );
+    assert_eq!(
+      RaydiumAmmInfo::new(&raydium_account_info).err(),
+      Some(ProtocolError::RaydiumPoolDisabled)
+    );
+  }
+
+  #[test]
+  fn test_raydium_openbook_market() {
+    let raydium_program_id =
+      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
+    let openbook_program_id =
+      Pubkey::from_str("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX").unwrap();
+    let raydium_pubkey = Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap();
+    let open_orders_pubkey =
+      Pubkey::from_str("7UF3m8hDGZ6bNnHzaT2YHrhp7A7n9qFfBj6QEpHPv5S8").unwrap();
+    let market_pubkey = Pubkey::from_str("teE55QrL4a4QSfydR9dnHF97jgCfptpuigbb53Lo95g").unwrap();
+    let coin_mint = Pubkey::from_str("4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R").unwrap();
+    let pc_mint = Pubkey::from_str("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB").unwrap();
+
+    let mut raydium_data = bs58::decode(RAYDIUM_AMM_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    raydium_data[560..592].copy_from_slice(openbook_program_id.as_ref());
+    // openbook market with a longer, permissioned layout
+    let openbook_market = |own_address: &Pubkey, pc_lot_size: u64| {
+      let mut data = market_data(own_address, &coin_mint, &pc_mint);
+      data[5..13].copy_from_slice(&(3u64 | 1 << 9).to_le_bytes());
+      data[357..365].copy_from_slice(&pc_lot_size.to_le_bytes());
+      data.splice(381..381, vec![0u8; 3 * 32]);
+      data
+    };
+    let mut market_data_ok = openbook_market(&market_pubkey, 10);
+    let mut market_data_moved = openbook_market(&Pubkey::new_unique(), 10);
+    let mut market_data_no_lot = openbook_market(&market_pubkey, 0);
+    let mut lamports = [1_000_000u64; 6];
+    let mut empty = [vec![], vec![]];
+    let [l0, l1, l2, l3, l4, l5] = &mut lamports;
+    let [e0, e1] = &mut empty;
+    let raydium_info = AccountInfo::new(
+      &raydium_pubkey,
+      false,
+      true,
+      l0,
+      &mut raydium_data[..],
+      &raydium_program_id,
+      false,
+      248,
+    );
+    let open_orders = AccountInfo::new(
+      &open_orders_pubkey,
+      false,
+      true,
+      l1,
+      &mut e0[..],
+      &openbook_program_id,
+      false,
+      248,
+    );
+    let market = AccountInfo::new(
+      &market_pubkey,
+      false,
+      true,
+      l2,
+      &mut market_data_ok[..],
+      &openbook_program_id,
+      false,
+      248,
+    );
+    let moved_market = AccountInfo::new(
+      &market_pubkey,
+      false,
+      true,
+      l3,
+      &mut market_data_moved[..],
+      &openbook_program_id,
+      false,
+      248,
+    );
+    let no_lot_market = AccountInfo::new(
+      &market_pubkey,
+      false,
+      true,
+      l4,
+      &mut market_data_no_lot[..],
+      &openbook_program_id,
+      false,
+      248,
+    );
+    let openbook_program = AccountInfo::new(
+      &openbook_program_id,
+      false,
+      false,
+      l5,
+      &mut e1[..],
+      &Pubkey::default(),
+      true,
+      248,
+    );
+    let amm_info = RaydiumAmmInfo::new(&raydium_info).unwrap();
+    let queues = [&market, &market, &market];
+
+    let serum_market =
+      check_serum_market(amm_info, &open_orders, &market, &openbook_program, queues).unwrap();
+    assert_eq!(serum_market.coin_lot_size().unwrap(), 1_000);
+    assert_eq!(serum_market.pc_lot_size().unwrap(), 10);
+    // market data belongs to another market
+    assert_eq!(
+      check_serum_market(
+        amm_info,
+        &open_orders,
+        &moved_market,
+        &openbook_program,
+        queues
+      )
+      .err(),
+      Some(ProtocolError::InvalidSerumDexMarketAccount)
+    );
+    assert_eq!(
+      check_serum_market(
+        amm_info,
+        &open_orders,
+        &no_lot_market,
+        &openbook_program,
+        queues
+      )
+      .err(),
+      Some(ProtocolError::InvalidSerumDexMarketAccount)
+    );
+  }
+
+  #[test]
+  fn test_raydium_swap_args_target_orders() {
+    let raydium_program_id =
+      Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8").unwrap();
+    let serum_program_id =
+      Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+    let coin_mint = Pubkey::from_str("4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R").unwrap();
+    let pc_mint = Pubkey::from_str("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB").unwrap();
+    let raydium_data = bs58::decode(RAYDIUM_AMM_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    let token_data = |mint: &Pubkey| {
+      let mut data = vec![0u8; crate::spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut open_orders_data = vec![0u8; 3228];
+    open_orders_data[5] = 5;
+
+    // (key, owner, data, executable) in the full layout order
+    let mut accounts = vec![
+      (
+        Pubkey::from_str("DVa7Qmb5ct9RCpaU7UTpSaf3GVMYz17vNVU67XpdCRut").unwrap(),
+        raydium_program_id,
+        raydium_data.clone(),
+        false,
+      ),
+      (Pubkey::new_unique(), raydium_program_id, vec![], false),
+      (
+        Pubkey::from_str("7UF3m8hDGZ6bNnHzaT2YHrhp7A7n9qFfBj6QEpHPv5S8").unwrap(),
+        serum_program_id,
+        open_orders_data,
+        false,
+      ),
+      (Pubkey::new_unique(), raydium_program_id, vec![], false),
+      (
+        Pubkey::new_from_array(*array_ref![raydium_data, 336, 32]),
+        crate::spl_token::ID,
+        token_data(&coin_mint),
+        false,
+      ),
+      (
+        Pubkey::new_from_array(*array_ref![raydium_data, 368, 32]),
+        crate::spl_token::ID,
+        token_data(&pc_mint),
+        false,
+      ),
+      (serum_program_id, Pubkey::default(), vec![], true),
+      (
+        Pubkey::from_str("teE55QrL4a4QSfydR9dnHF97jgCfptpuigbb53Lo95g").unwrap(),
+        serum_program_id,
+        market_data(
+          &Pubkey::from_str("teE55QrL4a4QSfydR9dnHF97jgCfptpuigbb53Lo95g").unwrap(),
+          &coin_mint,
+          &pc_mint,
+        ),
+        false,
+      ),
+      (Pubkey::new_unique(), serum_program_id, vec![], false),
+      (Pubkey::new_unique(), serum_program_id, vec![], false),
+      (Pubkey::new_unique(), serum_program_id, vec![], false),
+      (
+        Pubkey::new_unique(),
+        crate::spl_token::ID,
+        token_data(&coin_mint),
+        false,
+      ),
+      (
+        Pubkey::new_unique(),
+        crate::spl_token::ID,
+        token_data(&pc_mint),
+        false,
+      ),
+      (Pubkey::new_unique(), serum_program_id, vec![], false),
+      (raydium_program_id, Pubkey::default(), vec![], true),
+    ];
+    let mut lamports = vec![1_000_000u64; accounts.len()];
+    let infos = accounts
+      .iter_mut()
+      .zip(lamports.iter_mut())
+      .map(|((key, owner, data, executable), lamports)| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          &mut data[..],
+          owner,
+          *executable,
+          248,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args = RaydiumSwapArgs::with_parsed_args(&infos).unwrap();
+    assert_eq!(args.target_orders.map(|acc| *acc.key), Some(*infos[3].key));
+    assert_eq!(args.pool_token_coin.pubkey(), infos[4].key);
+
+    let slim = infos
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| *i != 3)
+      .map(|(_, acc)| acc.clone())
+      .collect::<Vec<_>>();
+    let args = RaydiumSwapArgs::with_parsed_args(&slim).unwrap();
+    assert!(args.target_orders.is_none());
+    assert_eq!(args.pool_token_coin.pubkey(), infos[4].key);
+    assert_eq!(args.program_id.key, infos[14].key);
+    assert_eq!(args.lot_sizes().unwrap(), (1_000, 10));
+    assert_eq!(args.round_to_lot(&coin_mint, 12_345).unwrap(), 12_000);
+    assert_eq!(args.round_to_lot(&pc_mint, 12_345).unwrap(), 12_340);
+    assert_eq!(
+      args.round_to_lot(&Pubkey::new_unique(), 12_345).err(),
+      Some(ProtocolError::InvalidTokenMint)
+    );
+
+    assert!(args.mints.is_none());
+
+    assert_eq!(
+      RaydiumSwapArgs::with_parsed_args(&infos[..13]).err(),
+      Some(ProtocolError::InvalidAccountsLength)
+    );
+
+    // trailing coin and pc mints, checked against the amm decimals
+    let mint_data = |decimals: u8| {
+      let mut data = vec![0u8; crate::spl_token::MINT_LEN];
+      data[44] = decimals;
+      data[0x2d] = 1;
+      data
+    };
+    let mut mints = vec![
+      (coin_mint, mint_data(6)),
+      (pc_mint, mint_data(6)),
+      (coin_mint, mint_data(9)),
+    ];
+    let mut mint_lamports = vec![1_000_000u64; mints.len()];
+    let mint_infos = mints
+      .iter_mut()
+      .zip(mint_lamports.iter_mut())
+      .map(|((key, data), lamports)| {
+        AccountInfo::new(
+          key,
+          false,
+          false,
+          lamports,
+          &mut data[..],
+          &crate::spl_token::ID,
+          false,
+          248,
+        )
+      })
+      .collect::<Vec<_>>();
+    let with_mints = |base: &[AccountInfo<'_>], coin: usize, pc: usize| {
+      let mut accounts = base.to_vec();
+      accounts.push(mint_infos[coin].clone());
+      accounts.push(mint_infos[pc].clone());
+      accounts
+    };
+    let full = with_mints(&infos, 0, 1);
+    let args = RaydiumSwapArgs::with_parsed_args(&full).unwrap();
+    assert_eq!(args.target_orders.map(|acc| *acc.key), Some(*infos[3].key));
+    assert_eq!(
+      args.mints.map(|(coin, pc)| (*coin.pubkey(), *pc.pubkey())),
+      Some((coin_mint, pc_mint))
+    );
+    let slim_with_mints = with_mints(&slim, 0, 1);
+    let args = RaydiumSwapArgs::with_parsed_args(&slim_with_mints).unwrap();
+    assert!(args.target_orders.is_none());
+    assert!(args.mints.is_some());
+    // mints swapped
+    assert_eq!(
+      RaydiumSwapArgs::with_parsed_args(&with_mints(&infos, 1, 0)).err(),
+      Some(ProtocolError::InvalidTokenMint)
+    );
+    // right coin mint key, decimals the amm does not record
+    assert_eq!(
+      RaydiumSwapArgs::with_parsed_args(&with_mints(&infos, 2, 1)).err(),
+      Some(ProtocolError::InvalidMintDecimals)
+    );
   }
 }
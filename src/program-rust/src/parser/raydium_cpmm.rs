@@ -0,0 +1,377 @@
+use super::base::{
+  dex_program_ids, validate_authority_pubkey, validate_dex_program, TokenAccount, TokenMint,
+};
+use crate::{
+  declare_validated_account_wrapper,
+  error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
+};
+use arrayref::array_ref;
+use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
+
+/// anchor discriminator of CP-Swap `PoolState` account
+pub const POOL_DISCRIMINATOR: [u8; 8] = [247, 237, 227, 245, 215, 195, 222, 70];
+/// anchor discriminator of CP-Swap `AmmConfig` account
+const AMM_CONFIG_DISCRIMINATOR: [u8; 8] = [218, 244, 33, 104, 203, 203, 43, 111];
+pub const POOL_LEN: usize = 637;
+/// seed of the vault and lp mint authority, bumped with `auth_bump`
+pub const AUTH_SEED: &[u8] = b"vault_and_lp_mint_auth_seed";
+/// `status` bit set when swaps are disabled
+const STATUS_SWAP_DISABLED: u8 = 1 << 2;
+
+declare_validated_account_wrapper!(RaydiumCpmmPool, |account: &AccountInfo| {
+  if !account.is_writable {
+    return Err(ProtocolError::ReadonlyAccount);
+  }
+  let account_data = account
+    .try_borrow_data()
+    .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+  if account_data.len() != POOL_LEN || *array_ref![account_data, 0, 8] != POOL_DISCRIMINATOR {
+    return Err(ProtocolError::InvalidRaydiumCpmmPoolAccount);
+  }
+  if account_data[329] & STATUS_SWAP_DISABLED != 0 {
+    return Err(ProtocolError::RaydiumCpmmPoolDisabled);
+  }
+  Ok(())
+});
+
+impl<'a, 'b: 'a> RaydiumCpmmPool<'a, 'b> {
+  fn read_pubkey(self, offset: usize) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, offset, 32]))
+  }
+
+  fn read_u64(self, offset: usize) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, offset, 8]))
+  }
+
+  pub fn amm_config(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(8)
+  }
+
+  pub fn pool_creator(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(40)
+  }
+
+  pub fn token_0_vault(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(72)
+  }
+
+  pub fn token_1_vault(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(104)
+  }
+
+  pub fn token_0_mint(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(168)
+  }
+
+  pub fn token_1_mint(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(200)
+  }
+
+  pub fn observation_key(self) -> ProtocolResult<Pubkey> {
+    self.read_pubkey(296)
+  }
+
+  pub fn auth_bump(self) -> ProtocolResult<u8> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(data[328])
+  }
+
+  /// Protocol and fund fees of token 0 accrued in its vault, not swappable.
+  pub fn fees_token_0(self) -> ProtocolResult<u64> {
+    self
+      .read_u64(341)?
+      .checked_add(self.read_u64(357)?)
+      .ok_or(ProtocolError::Overflow)
+  }
+
+  /// Protocol and fund fees of token 1 accrued in its vault, not swappable.
+  pub fn fees_token_1(self) -> ProtocolResult<u64> {
+    self
+      .read_u64(349)?
+      .checked_add(self.read_u64(365)?)
+      .ok_or(ProtocolError::Overflow)
+  }
+}
+
+declare_validated_account_wrapper!(RaydiumCpmmConfig, |account: &AccountInfo| {
+  let account_data = account
+    .try_borrow_data()
+    .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+  if account_data.len() < 8 || *array_ref![account_data, 0, 8] != AMM_CONFIG_DISCRIMINATOR {
+    return Err(ProtocolError::InvalidRaydiumCpmmPoolAccount);
+  }
+  Ok(())
+});
+
+/// Raydium CP-Swap pool accounts. No serum market is involved. Vaults and
+/// mints are validated as classic spl-token accounts, pools holding a
+/// token-2022 mint are rejected.
+#[derive(Copy, Clone)]
+pub struct RaydiumCpmmArgs<'a, 'b: 'a> {
+  pub pool: RaydiumCpmmPool<'a, 'b>,
+  pub amm_config: RaydiumCpmmConfig<'a, 'b>,
+  pub authority: &'a AccountInfo<'b>,
+  pub token_0_vault: TokenAccount<'a, 'b>,
+  pub token_1_vault: TokenAccount<'a, 'b>,
+  pub token_0_mint: TokenMint<'a, 'b>,
+  pub token_1_mint: TokenMint<'a, 'b>,
+  pub observation_state: &'a AccountInfo<'b>,
+  pub program_id: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b: 'a> RaydiumCpmmArgs<'a, 'b> {
+  pub const MIN_ACCOUNTS: usize = 9;
+
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if accounts.len() != Self::MIN_ACCOUNTS {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let &[
+      ref pool_acc,
+      ref amm_config_acc,
+      ref authority,
+      ref token_0_vault_acc,
+      ref token_1_vault_acc,
+      ref token_0_mint_acc,
+      ref token_1_mint_acc,
+      ref observation_state,
+      ref program_id,
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = array_ref![accounts, 0, Self::MIN_ACCOUNTS];
+
+    let pool = RaydiumCpmmPool::new(pool_acc)?;
+    validate_dex_program(program_id, dex_program_ids(ExchangerType::RaydiumCpmm))?;
+    if *pool_acc.owner != *program_id.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    let amm_config = RaydiumCpmmConfig::new(amm_config_acc)?;
+    if *amm_config_acc.owner != *program_id.key || *amm_config_acc.key != pool.amm_config()? {
+      return Err(ProtocolError::InvalidRaydiumCpmmPoolAccount);
+    }
+    validate_authority_pubkey(authority.key, program_id.key, AUTH_SEED, pool.auth_bump()?)?;
+
+    if *token_0_vault_acc.key != pool.token_0_vault()? {
+      msg!(
+        "token_0_vault got {}, expect: {}",
+        token_0_vault_acc.key,
+        pool.token_0_vault()?
+      );
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    if *token_1_vault_acc.key != pool.token_1_vault()? {
+      msg!(
+        "token_1_vault got {}, expect: {}",
+        token_1_vault_acc.key,
+        pool.token_1_vault()?
+      );
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    if *token_0_mint_acc.key != pool.token_0_mint()?
+      || *token_1_mint_acc.key != pool.token_1_mint()?
+    {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+    if *observation_state.key != pool.observation_key()? {
+      return Err(ProtocolError::InvalidRaydiumCpmmPoolAccount);
+    }
+
+    Ok(Self {
+      pool,
+      amm_config,
+      authority,
+      token_0_vault: TokenAccount::new(token_0_vault_acc)?,
+      token_1_vault: TokenAccount::new(token_1_vault_acc)?,
+      token_0_mint: TokenMint::new(token_0_mint_acc)?,
+      token_1_mint: TokenMint::new(token_1_mint_acc)?,
+      observation_state,
+      program_id,
+    })
+  }
+
+  /// `(input_vault, output_vault, input_mint, output_mint)` of a swap from
+  /// `source_mint` to `destination_mint`.
+  #[allow(clippy::type_complexity)]
+  pub fn find_token_pair(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(
+    TokenAccount<'a, 'b>,
+    TokenAccount<'a, 'b>,
+    TokenMint<'a, 'b>,
+    TokenMint<'a, 'b>,
+  )> {
+    let (mint_0, mint_1) = (self.token_0_mint.pubkey(), self.token_1_mint.pubkey());
+    if source_mint == mint_0 && destination_mint == mint_1 {
+      Ok((
+        self.token_0_vault,
+        self.token_1_vault,
+        self.token_0_mint,
+        self.token_1_mint,
+      ))
+    } else if source_mint == mint_1 && destination_mint == mint_0 {
+      Ok((
+        self.token_1_vault,
+        self.token_0_vault,
+        self.token_1_mint,
+        self.token_0_mint,
+      ))
+    } else {
+      Err(ProtocolError::MintRoutingMismatch)
+    }
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+  use crate::spl_token;
+  use solana_sdk::account_info::AccountInfo;
+  use std::str::FromStr;
+
+  /// Pool data with the given `keys` at the offsets read by the parser:
+  /// amm_config, token_0_vault, token_1_vault, token_0_mint, token_1_mint,
+  /// observation_state.
+  pub(crate) fn pool_data(keys: [&Pubkey; 6], auth_bump: u8) -> Vec<u8> {
+    let mut data = vec![0u8; POOL_LEN];
+    data[0..8].copy_from_slice(&POOL_DISCRIMINATOR);
+    for (key, offset) in keys.iter().zip([8, 72, 104, 168, 200, 296]) {
+      data[offset..offset + 32].copy_from_slice(key.as_ref());
+    }
+    data[328] = auth_bump;
+    data
+  }
+
+  pub(crate) fn amm_config_data() -> Vec<u8> {
+    let mut data = vec![0u8; 236];
+    data[0..8].copy_from_slice(&AMM_CONFIG_DISCRIMINATOR);
+    data
+  }
+
+  #[test]
+  fn test_parse_raydium_cpmm_args() {
+    let program_id = Pubkey::from_str("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C").unwrap();
+    let (authority, auth_bump) = Pubkey::find_program_address(&[AUTH_SEED], &program_id);
+    let token_2022 = Pubkey::new_unique();
+    let mut keys = (0..9).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    keys[2] = authority;
+    keys[8] = program_id;
+    let (mint_0, mint_1) = (keys[5], keys[6]);
+
+    let vault_data = |mint: &Pubkey| {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut pool = pool_data(
+      [&keys[1], &keys[3], &keys[4], &mint_0, &mint_1, &keys[7]],
+      auth_bump,
+    );
+    // 30 token 0 of protocol fees and 12 of fund fees
+    pool[341..349].copy_from_slice(&30u64.to_le_bytes());
+    pool[357..365].copy_from_slice(&12u64.to_le_bytes());
+    let mut datas = vec![
+      pool,
+      amm_config_data(),
+      vec![],
+      vault_data(&mint_0),
+      vault_data(&mint_1),
+      mint_data.clone(),
+      mint_data,
+      vec![0u8; 64],
+      vec![],
+    ];
+    let mut owners = vec![program_id; 9];
+    owners[2] = Pubkey::default();
+    owners[3..7].fill(spl_token::ID);
+    owners[8] = Pubkey::from_str("BPFLoaderUpgradeab1e11111111111111111111111").unwrap();
+    let mut lamports = vec![1_000_000u64; 9];
+
+    let accounts = keys
+      .iter()
+      .zip(owners.iter())
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .enumerate()
+      .map(|(i, (((key, owner), data), lamports))| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          &mut data[..],
+          owner,
+          i == 8,
+          300,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args = RaydiumCpmmArgs::with_parsed_args(&accounts).unwrap();
+    assert_eq!(*args.token_0_vault.pubkey(), keys[3]);
+    assert_eq!(*args.token_1_vault.pubkey(), keys[4]);
+    assert_eq!(*args.observation_state.key, keys[7]);
+    assert_eq!(args.pool.fees_token_0(), Ok(42));
+    assert_eq!(args.pool.fees_token_1(), Ok(0));
+    let (input_vault, output_vault, input_mint, _) =
+      args.find_token_pair(&mint_1, &mint_0).unwrap();
+    assert_eq!(
+      (*input_vault.pubkey(), *output_vault.pubkey()),
+      (keys[4], keys[3])
+    );
+    assert_eq!(*input_mint.pubkey(), mint_1);
+    assert_eq!(
+      args.find_token_pair(&mint_0, &Pubkey::new_unique()).err(),
+      Some(ProtocolError::MintRoutingMismatch)
+    );
+
+    // vaults in the wrong order
+    let mut swapped = accounts.clone();
+    swapped.swap(3, 4);
+    assert_eq!(
+      RaydiumCpmmArgs::with_parsed_args(&swapped).err(),
+      Some(ProtocolError::InvalidTokenAccount)
+    );
+    // amm config of another pool
+    let mut wrong_config = accounts.clone();
+    wrong_config[1] = accounts[7].clone();
+    assert_eq!(
+      RaydiumCpmmArgs::with_parsed_args(&wrong_config).err(),
+      Some(ProtocolError::InvalidRaydiumCpmmPoolAccount)
+    );
+    // authority not derived from the program
+    let mut wrong_authority = accounts.clone();
+    wrong_authority[2] = accounts[7].clone();
+    assert_eq!(
+      RaydiumCpmmArgs::with_parsed_args(&wrong_authority).err(),
+      Some(ProtocolError::InvalidAuthority)
+    );
+    // token-2022 mint
+    let mut mint_2022 = accounts.clone();
+    mint_2022[6].owner = &token_2022;
+    assert_eq!(
+      RaydiumCpmmArgs::with_parsed_args(&mint_2022).err(),
+      Some(ProtocolError::InvalidTokenMint)
+    );
+    // swaps disabled
+    accounts[0].data.borrow_mut()[329] = STATUS_SWAP_DISABLED;
+    assert_eq!(
+      RaydiumCpmmArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::RaydiumCpmmPoolDisabled)
+    );
+  }
+}
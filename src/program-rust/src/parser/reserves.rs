@@ -0,0 +1,615 @@
+use super::{
+  aldrin::AldrinPoolArgs, base::TokenAccount, cropper::CropperArgs, meteora_damm::MeteoraDammArgs,
+  raydium::RaydiumSwapArgs, raydium_cpmm::RaydiumCpmmArgs, spl_token_swap::SplTokenSwapArgs,
+  stable_swap::StableSwapArgs,
+};
+use crate::{
+  error::{ProtocolError, ProtocolResult},
+  exchanger::stable_swap::curve::StableCurve,
+};
+use solana_program::pubkey::Pubkey;
+
+/// Vault balances of a pool, used to quote a swap the same way for every AMM.
+pub trait PoolReserves {
+  /// Returns `(source_reserve, destination_reserve)` for a swap from
+  /// `source_mint` to `destination_mint`.
+  fn reserves(&self, source_mint: &Pubkey, destination_mint: &Pubkey)
+    -> ProtocolResult<(u64, u64)>;
+
+  /// Constant product output for `amount_in`, before pool fees. Only meant to
+  /// rank pools, curve pools (stable swap) are under-quoted.
+  fn quote(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+  ) -> ProtocolResult<u64> {
+    let (source_reserve, destination_reserve) = self.reserves(source_mint, destination_mint)?;
+    let out = (destination_reserve as u128)
+      .checked_mul(amount_in as u128)
+      .and_then(|v| v.checked_div(source_reserve as u128 + amount_in as u128))
+      .ok_or(ProtocolError::Overflow)?;
+    Ok(out as u64)
+  }
+}
+
+/// Shortfall of `amount_out` against the spot price of the reserves taken
+/// before the swap, in bps. Pool fees count as impact.
+pub fn price_impact_bps(
+  source_reserve: u64,
+  destination_reserve: u64,
+  amount_in: u64,
+  amount_out: u64,
+) -> ProtocolResult<u64> {
+  let spot_out = (amount_in as u128)
+    .checked_mul(destination_reserve as u128)
+    .and_then(|v| v.checked_div(source_reserve as u128))
+    .ok_or(ProtocolError::Overflow)?;
+  if spot_out == 0 || amount_out as u128 >= spot_out {
+    return Ok(0);
+  }
+  Ok(((spot_out - amount_out as u128) * 10_000 / spot_out) as u64)
+}
+
+/// StableSwap output for `amount_in` after the pool trade fee, at
+/// `amp_factor`, usually `StableSwapInfo::current_amp_factor`.
+pub fn quote_stableswap(
+  pool: &StableSwapArgs,
+  source_mint: &Pubkey,
+  destination_mint: &Pubkey,
+  amount_in: u64,
+  amp_factor: u64,
+) -> ProtocolResult<u64> {
+  let (source_reserve, destination_reserve) = pool.reserves(source_mint, destination_mint)?;
+  StableCurve { amp_factor }
+    .swap_to(
+      amount_in,
+      source_reserve,
+      destination_reserve,
+      pool.swap_info.trade_fee_numerator()?,
+      pool.swap_info.trade_fee_denominator()?,
+    )
+    .ok_or(ProtocolError::Overflow)
+}
+
+/// Rescales `amount` from `decimals` to `target_decimals`, rounding down.
+pub fn normalize_amount(amount: u64, decimals: u8, target_decimals: u8) -> ProtocolResult<u64> {
+  let scaled = if target_decimals >= decimals {
+    10u128
+      .checked_pow((target_decimals - decimals) as u32)
+      .and_then(|factor| (amount as u128).checked_mul(factor))
+  } else {
+    10u128
+      .checked_pow((decimals - target_decimals) as u32)
+      .map(|factor| amount as u128 / factor)
+  };
+  scaled
+    .filter(|v| *v <= u64::MAX as u128)
+    .map(|v| v as u64)
+    .ok_or(ProtocolError::Overflow)
+}
+
+fn vault_reserves(
+  vault_a: TokenAccount,
+  vault_b: TokenAccount,
+  source_mint: &Pubkey,
+  destination_mint: &Pubkey,
+) -> ProtocolResult<(u64, u64)> {
+  let mint_a = vault_a.mint()?;
+  let mint_b = vault_b.mint()?;
+  if *source_mint == mint_a && *destination_mint == mint_b {
+    Ok((vault_a.balance()?, vault_b.balance()?))
+  } else if *source_mint == mint_b && *destination_mint == mint_a {
+    Ok((vault_b.balance()?, vault_a.balance()?))
+  } else {
+    Err(ProtocolError::InvalidTokenMint)
+  }
+}
+
+impl<'a, 'b: 'a> PoolReserves for SplTokenSwapArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    vault_reserves(
+      self.token_a_account,
+      self.token_b_account,
+      source_mint,
+      destination_mint,
+    )
+  }
+}
+
+impl<'a, 'b: 'a> PoolReserves for StableSwapArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    vault_reserves(self.token_a, self.token_b, source_mint, destination_mint)
+  }
+}
+
+impl<'a, 'b: 'a> PoolReserves for AldrinPoolArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    vault_reserves(
+      self.pool_coin_vault,
+      self.pool_pc_vault,
+      source_mint,
+      destination_mint,
+    )
+  }
+}
+
+impl<'a, 'b: 'a> PoolReserves for CropperArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    vault_reserves(
+      self.token_a_account,
+      self.token_b_account,
+      source_mint,
+      destination_mint,
+    )
+  }
+}
+
+impl<'a, 'b: 'a> PoolReserves for MeteoraDammArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    vault_reserves(
+      self.token_a_vault,
+      self.token_b_vault,
+      source_mint,
+      destination_mint,
+    )
+  }
+}
+
+/// Protocol and fund fees accrue in the vaults until collected, they are not
+/// swappable and left out.
+impl<'a, 'b: 'a> PoolReserves for RaydiumCpmmArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    let (source_reserve, destination_reserve) = vault_reserves(
+      self.token_0_vault,
+      self.token_1_vault,
+      source_mint,
+      destination_mint,
+    )?;
+    let fees_0 = self.pool.fees_token_0()?;
+    let fees_1 = self.pool.fees_token_1()?;
+    let (source_fees, destination_fees) = if *source_mint == self.token_0_vault.mint()? {
+      (fees_0, fees_1)
+    } else {
+      (fees_1, fees_0)
+    };
+    Ok((
+      source_reserve.saturating_sub(source_fees),
+      destination_reserve.saturating_sub(destination_fees),
+    ))
+  }
+}
+
+/// Only the amm's own token accounts are counted, funds held in its serum
+/// open orders are not. The pnl pending take-pnl sits in the vaults but is
+/// not swappable, it is left out.
+impl<'a, 'b: 'a> PoolReserves for RaydiumSwapArgs<'a, 'b> {
+  fn reserves(
+    &self,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+  ) -> ProtocolResult<(u64, u64)> {
+    let (source_reserve, destination_reserve) = vault_reserves(
+      self.pool_token_coin,
+      self.pool_token_pc,
+      source_mint,
+      destination_mint,
+    )?;
+    let pnl_coin = self.amm_info.need_take_pnl_coin()?;
+    let pnl_pc = self.amm_info.need_take_pnl_pc()?;
+    let (source_pnl, destination_pnl) = if *source_mint == self.pool_token_coin.mint()? {
+      (pnl_coin, pnl_pc)
+    } else {
+      (pnl_pc, pnl_coin)
+    };
+    Ok((
+      source_reserve.saturating_sub(source_pnl),
+      destination_reserve.saturating_sub(destination_pnl),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    parser::{
+      aldrin::{self, AldrinPool},
+      base::TokenMint,
+      cropper::{self, CropperProgramState, CropperSwapV1},
+      raydium::{self, RaydiumAmmInfo},
+      serum_dex::{self, SerumDexMarket, SerumDexOpenOrders},
+      spl_token_swap::SplTokenSwapInfo,
+      stable_swap::StableSwapInfo,
+    },
+    spl_token,
+  };
+  use solana_sdk::{account::Account, account_info::AccountInfo};
+  use std::str::FromStr;
+
+  struct Fixture {
+    keys: Vec<Pubkey>,
+    accounts: Vec<Account>,
+  }
+
+  impl Fixture {
+    fn new() -> Self {
+      Fixture {
+        keys: vec![],
+        accounts: vec![],
+      }
+    }
+
+    fn add(&mut self, owner: Pubkey, data: Vec<u8>) -> usize {
+      self.keys.push(Pubkey::new_unique());
+      self.accounts.push(Account {
+        lamports: 1000000,
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 281,
+      });
+      self.accounts.len() - 1
+    }
+
+    fn add_blob(&mut self, owner: &str, data: &str) -> usize {
+      let data = bs58::decode(data.replace('\n', "")).into_vec().unwrap();
+      self.add(Pubkey::from_str(owner).unwrap(), data)
+    }
+
+    fn add_vault(&mut self, mint: &Pubkey, amount: u64) -> usize {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[64..72].copy_from_slice(&amount.to_le_bytes());
+      data[0x6c] = 1;
+      self.add(spl_token::ID, data)
+    }
+
+    fn add_mint(&mut self) -> usize {
+      self.add_mint_with_decimals(0)
+    }
+
+    fn add_mint_with_decimals(&mut self, decimals: u8) -> usize {
+      let mut data = vec![0u8; spl_token::MINT_LEN];
+      data[44] = decimals;
+      data[0x2d] = 1;
+      self.add(spl_token::ID, data)
+    }
+
+    fn infos(&mut self) -> Vec<AccountInfo> {
+      self
+        .keys
+        .iter()
+        .zip(self.accounts.iter_mut())
+        .map(|(key, account)| {
+          AccountInfo::new(
+            key,
+            false,
+            true,
+            &mut account.lamports,
+            &mut account.data[..],
+            &account.owner,
+            false,
+            account.rent_epoch,
+          )
+        })
+        .collect()
+    }
+  }
+
+  fn assert_reserves<T: PoolReserves>(pool: &T, mint_a: &Pubkey, mint_b: &Pubkey) {
+    assert_eq!(pool.reserves(mint_a, mint_b), Ok((1_000, 2_000)));
+    assert_eq!(pool.reserves(mint_b, mint_a), Ok((2_000, 1_000)));
+    assert_eq!(pool.quote(mint_a, mint_b, 1_000), Ok(1_000));
+    assert_eq!(pool.quote(mint_b, mint_a, 1_000), Ok(333));
+    assert_eq!(pool.quote(mint_a, mint_b, 0), Ok(0));
+    assert_eq!(
+      pool.reserves(mint_a, &Pubkey::new_unique()),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+    assert_eq!(
+      pool.reserves(mint_a, mint_a),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+  }
+
+  #[test]
+  fn test_spl_token_swap_reserves() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut swap_info_data = vec![0u8; 324];
+    swap_info_data[0] = 1;
+    swap_info_data[1] = 1;
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add(Pubkey::new_unique(), swap_info_data);
+    let vault_a = fixture.add_vault(&mint_a, 1_000);
+    let vault_b = fixture.add_vault(&mint_b, 2_000);
+    let pool_mint = fixture.add_mint();
+    let infos = fixture.infos();
+    let args = SplTokenSwapArgs {
+      swap_info: SplTokenSwapInfo::new(&infos[swap_info]).unwrap(),
+      authority_acc_info: &infos[swap_info],
+      token_a_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      token_b_account: TokenAccount::new(&infos[vault_b]).unwrap(),
+      pool_mint: TokenMint::new(&infos[pool_mint]).unwrap(),
+      fee_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      program: &infos[swap_info],
+      host_fee_account: None,
+    };
+    assert_reserves(&args, &mint_a, &mint_b);
+  }
+
+  #[test]
+  fn test_stable_swap_reserves() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut swap_info_data = vec![0u8; 395];
+    swap_info_data[0] = 1;
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add(Pubkey::new_unique(), swap_info_data);
+    let vault_a = fixture.add_vault(&mint_a, 1_000);
+    let vault_b = fixture.add_vault(&mint_b, 2_000);
+    let infos = fixture.infos();
+    let args = StableSwapArgs {
+      swap_info: StableSwapInfo::new(&infos[swap_info]).unwrap(),
+      authority_acc: &infos[swap_info],
+      token_a: TokenAccount::new(&infos[vault_a]).unwrap(),
+      token_b: TokenAccount::new(&infos[vault_b]).unwrap(),
+      admin_fee_acc: &infos[vault_a],
+      program_acc: &infos[swap_info],
+    };
+    assert_reserves(&args, &mint_a, &mint_b);
+  }
+
+  #[test]
+  fn test_quote_stableswap() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let reserve = 1_000_000_000_000;
+    // ramping from 10 to 100 over 1_000 seconds, 4 bps trade fee
+    let mut swap_info_data = vec![0u8; 395];
+    swap_info_data[0] = 1;
+    swap_info_data[3..11].copy_from_slice(&10u64.to_le_bytes());
+    swap_info_data[11..19].copy_from_slice(&100u64.to_le_bytes());
+    swap_info_data[19..27].copy_from_slice(&1_000i64.to_le_bytes());
+    swap_info_data[27..35].copy_from_slice(&2_000i64.to_le_bytes());
+    swap_info_data[363..371].copy_from_slice(&4u64.to_le_bytes());
+    swap_info_data[371..379].copy_from_slice(&10_000u64.to_le_bytes());
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add(Pubkey::new_unique(), swap_info_data);
+    let vault_a = fixture.add_vault(&mint_a, reserve);
+    let vault_b = fixture.add_vault(&mint_b, reserve);
+    let infos = fixture.infos();
+    let args = StableSwapArgs {
+      swap_info: StableSwapInfo::new(&infos[swap_info]).unwrap(),
+      authority_acc: &infos[swap_info],
+      token_a: TokenAccount::new(&infos[vault_a]).unwrap(),
+      token_b: TokenAccount::new(&infos[vault_b]).unwrap(),
+      admin_fee_acc: &infos[vault_a],
+      program_acc: &infos[swap_info],
+    };
+    assert_eq!(args.swap_info.amp_factor(1_500), Ok(55));
+    assert_eq!(args.swap_info.amp_factor(3_000), Ok(100));
+
+    let amount_in = 1_000_000_000;
+    let quote = quote_stableswap(&args, &mint_a, &mint_b, amount_in, 100).unwrap();
+    assert_eq!(
+      Some(quote),
+      StableCurve { amp_factor: 100 }.swap_to(amount_in, reserve, reserve, 4, 10_000)
+    );
+    // the curve keeps a balanced pool close to 1:1, above constant product
+    assert!(quote < amount_in && quote > amount_in * 9_990 / 10_000);
+    assert!(quote > args.quote(&mint_a, &mint_b, amount_in).unwrap());
+    // a lower amp earlier in the ramp quotes less
+    assert!(quote_stableswap(&args, &mint_a, &mint_b, amount_in, 55).unwrap() < quote);
+    assert_eq!(
+      quote_stableswap(&args, &mint_a, &Pubkey::new_unique(), amount_in, 100),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+  }
+
+  #[test]
+  fn test_aldrin_reserves() {
+    let mut fixture = Fixture::new();
+    let pool = fixture.add_blob(
+      "CURVGoZn8zycx6FXwwevgBTB2gVvdbGTEpvMJDbgs2t4",
+      aldrin::test::ALDRIN_POOL_DATA,
+    );
+    let coin_mint = Pubkey::from_str("7zhbkbKpGaUsJW7AD4yyAfGGoy53Xx2H3Ai5BKcwGKHw").unwrap();
+    let pc_mint = Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap();
+    let coin_vault = fixture.add_vault(&coin_mint, 1_000);
+    let pc_vault = fixture.add_vault(&pc_mint, 2_000);
+    let pool_mint = fixture.add_mint();
+    let infos = fixture.infos();
+    let pool_info = AldrinPool::new(&infos[pool]).unwrap();
+    assert_eq!(pool_info.coin_mint().unwrap(), coin_mint);
+    assert_eq!(pool_info.pc_mint().unwrap(), pc_mint);
+    let args = AldrinPoolArgs {
+      pool_info,
+      authority: &infos[pool],
+      pool_mint: TokenMint::new(&infos[pool_mint]).unwrap(),
+      pool_coin_vault: TokenAccount::new(&infos[coin_vault]).unwrap(),
+      pool_pc_vault: TokenAccount::new(&infos[pc_vault]).unwrap(),
+      fee_account: &infos[pool],
+      curve_key: &infos[pool],
+      program_id: &infos[pool],
+      mints: None,
+    };
+    assert_reserves(&args, &coin_mint, &pc_mint);
+  }
+
+  #[test]
+  fn test_cropper_reserves() {
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add_blob(
+      "CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh",
+      cropper::test::CROPPER_SWAP_INFO_DATA,
+    );
+    let program_state = fixture.add(Pubkey::new_unique(), vec![0u8; 130]);
+    let token_a_mint = Pubkey::from_str("So11111111111111111111111111111111111111112").unwrap();
+    let token_b_mint = Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap();
+    let vault_a = fixture.add_vault(&token_a_mint, 1_000);
+    let vault_b = fixture.add_vault(&token_b_mint, 2_000);
+    let pool_mint = fixture.add_mint();
+    let infos = fixture.infos();
+    let swap_info = CropperSwapV1::new(&infos[swap_info]).unwrap();
+    assert_eq!(swap_info.token_a_mint().unwrap(), token_a_mint);
+    assert_eq!(swap_info.token_b_mint().unwrap(), token_b_mint);
+    let args = CropperArgs {
+      swap_info,
+      authority: swap_info.inner(),
+      program_state: CropperProgramState::new(&infos[program_state]).unwrap(),
+      token_a_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      token_b_account: TokenAccount::new(&infos[vault_b]).unwrap(),
+      pool_mint: TokenMint::new(&infos[pool_mint]).unwrap(),
+      fee_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      program_id: swap_info.inner(),
+      mints: None,
+    };
+    assert_reserves(&args, &token_a_mint, &token_b_mint);
+  }
+
+  #[test]
+  fn test_normalized_quote() {
+    assert_eq!(normalize_amount(1_500_000, 6, 9), Ok(1_500_000_000));
+    assert_eq!(normalize_amount(1_500_000_999, 9, 6), Ok(1_500_000));
+    assert_eq!(normalize_amount(7, 6, 6), Ok(7));
+    assert_eq!(
+      normalize_amount(u64::MAX, 0, 1),
+      Err(ProtocolError::Overflow)
+    );
+
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add_blob(
+      "CTMAxxk34HjKWxQ3QLZK1HpaLXmBveao3ESePXbiyfzh",
+      cropper::test::CROPPER_SWAP_INFO_DATA,
+    );
+    let program_state = fixture.add(Pubkey::new_unique(), vec![0u8; 130]);
+    let sol = Pubkey::from_str("So11111111111111111111111111111111111111112").unwrap();
+    let usdc = Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap();
+    // 10 SOL against 1_000 USDC
+    let vault_a = fixture.add_vault(&sol, 10_000_000_000);
+    let vault_b = fixture.add_vault(&usdc, 1_000_000_000);
+    let pool_mint = fixture.add_mint();
+    let sol_mint = fixture.add_mint_with_decimals(9);
+    let usdc_mint = fixture.add_mint_with_decimals(6);
+    let infos = fixture.infos();
+    let swap_info = CropperSwapV1::new(&infos[swap_info]).unwrap();
+    let mut args = CropperArgs {
+      swap_info,
+      authority: swap_info.inner(),
+      program_state: CropperProgramState::new(&infos[program_state]).unwrap(),
+      token_a_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      token_b_account: TokenAccount::new(&infos[vault_b]).unwrap(),
+      pool_mint: TokenMint::new(&infos[pool_mint]).unwrap(),
+      fee_account: TokenAccount::new(&infos[vault_a]).unwrap(),
+      program_id: swap_info.inner(),
+      mints: None,
+    };
+    assert_eq!(args.quote(&sol, &usdc, 1_000_000_000), Ok(90_909_090));
+    assert_eq!(
+      args.normalized_quote(&sol, &usdc, 1_000_000_000, 9),
+      Err(ProtocolError::ExpectedAccount)
+    );
+
+    args.mints = Some((
+      TokenMint::new(&infos[sol_mint]).unwrap(),
+      TokenMint::new(&infos[usdc_mint]).unwrap(),
+    ));
+    assert_eq!(args.token_a_decimals(), Ok(Some(9)));
+    assert_eq!(args.token_b_decimals(), Ok(Some(6)));
+    // 1 SOL buys 90.909090 USDC
+    assert_eq!(
+      args.normalized_quote(&sol, &usdc, 1_000_000_000, 9),
+      Ok(90_909_090_000)
+    );
+    // 100 USDC buys 0.909090909 SOL
+    assert_eq!(
+      args.normalized_quote(&usdc, &sol, 100_000_000, 6),
+      Ok(909_090)
+    );
+  }
+
+  #[test]
+  fn test_raydium_reserves() {
+    let serum_program = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+    let mut open_orders_data = vec![0u8; 3228];
+    open_orders_data[5] = 5;
+    let mut fixture = Fixture::new();
+    let amm_info = fixture.add_blob(
+      "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8",
+      raydium::tests::RAYDIUM_AMM_DATA,
+    );
+    let market = fixture.add_blob(serum_program, serum_dex::tests::SERUM_MARKET_DATA);
+    let open_orders = fixture.add(Pubkey::from_str(serum_program).unwrap(), open_orders_data);
+    let coin_mint = Pubkey::from_str("4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R").unwrap();
+    let pc_mint = Pubkey::from_str("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB").unwrap();
+    // on top of the pnl pending take-pnl in the amm data
+    let pool_coin = fixture.add_vault(&coin_mint, 52_401_277_655 + 1_000);
+    let pool_pc = fixture.add_vault(&pc_mint, 444_694_306_620 + 2_000);
+    // serum vaults are not part of the amm reserves
+    let coin_vault = fixture.add_vault(&coin_mint, 7_000);
+    let pc_vault = fixture.add_vault(&pc_mint, 9_000);
+    let infos = fixture.infos();
+    let amm_info = RaydiumAmmInfo::new(&infos[amm_info]).unwrap();
+    assert_eq!(amm_info.coin_mint().unwrap(), coin_mint);
+    assert_eq!(amm_info.pc_mint().unwrap(), pc_mint);
+    let args = RaydiumSwapArgs {
+      amm_info,
+      authority: amm_info.inner(),
+      open_orders: SerumDexOpenOrders::new(&infos[open_orders]).unwrap(),
+      target_orders: None,
+      pool_token_coin: TokenAccount::new(&infos[pool_coin]).unwrap(),
+      pool_token_pc: TokenAccount::new(&infos[pool_pc]).unwrap(),
+      serum_dex_program_id: amm_info.inner(),
+      serum_market: SerumDexMarket::new(&infos[market]).unwrap(),
+      bids: amm_info.inner(),
+      asks: amm_info.inner(),
+      event_q: amm_info.inner(),
+      coin_vault: TokenAccount::new(&infos[coin_vault]).unwrap(),
+      pc_vault: TokenAccount::new(&infos[pc_vault]).unwrap(),
+      vault_signer: amm_info.inner(),
+      program_id: amm_info.inner(),
+      mints: None,
+    };
+    assert_reserves(&args, &coin_mint, &pc_mint);
+  }
+
+  #[test]
+  fn test_price_impact_bps() {
+    // large trade into a shallow pool: half the pool for half the spot output
+    let out = (2_000u128 * 1_000 / (1_000 + 1_000)) as u64;
+    assert_eq!(price_impact_bps(1_000, 2_000, 1_000, out), Ok(5_000));
+    // small trade into a deep pool
+    assert_eq!(
+      price_impact_bps(1_000_000_000, 2_000_000_000, 1_000, 1_999),
+      Ok(5)
+    );
+    assert_eq!(price_impact_bps(1_000, 2_000, 10, 25), Ok(0));
+    assert_eq!(
+      price_impact_bps(0, 2_000, 10, 25),
+      Err(ProtocolError::Overflow)
+    );
+  }
+}
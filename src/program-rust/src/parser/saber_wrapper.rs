@@ -0,0 +1,239 @@
+use super::base::{validate_dex_program, TokenAccount, SABER_WRAPPER_PROGRAM_IDS};
+use crate::{
+  declare_validated_account_wrapper,
+  error::{ProtocolError, ProtocolResult},
+};
+use arrayref::array_ref;
+use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
+
+/// anchor discriminator of add-decimals `WrappedToken` account
+const WRAPPED_TOKEN_DISCRIMINATOR: [u8; 8] = [28, 41, 198, 163, 189, 149, 175, 142];
+const WRAPPED_TOKEN_LEN: usize = 114;
+
+declare_validated_account_wrapper!(WrappedToken, |account: &AccountInfo| {
+  let account_data = account
+    .try_borrow_data()
+    .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+  if account_data.len() != WRAPPED_TOKEN_LEN
+    || *array_ref![account_data, 0, 8] != WRAPPED_TOKEN_DISCRIMINATOR
+  {
+    return Err(ProtocolError::InvalidSaberWrapperAccount);
+  }
+  if u64::from_le_bytes(*array_ref![account_data, 9, 8]) == 0 {
+    return Err(ProtocolError::InvalidSaberWrapperAccount);
+  }
+  Ok(())
+});
+
+impl<'a, 'b: 'a> WrappedToken<'a, 'b> {
+  /// decimals of the wrapped mint
+  #[allow(dead_code)]
+  pub fn decimals(self) -> ProtocolResult<u8> {
+    Ok(
+      self
+        .inner()
+        .try_borrow_data()
+        .map_err(|_| ProtocolError::BorrowAccountDataError)?[8],
+    )
+  }
+
+  /// 10^(wrapped decimals - underlying decimals)
+  pub fn multiplier(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 9, 8]))
+  }
+
+  pub fn underlying_mint(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 17, 32]))
+  }
+
+  pub fn underlying_tokens(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 49, 32]))
+  }
+
+  pub fn wrapper_mint(self) -> ProtocolResult<Pubkey> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(Pubkey::new_from_array(*array_ref![data, 81, 32]))
+  }
+}
+
+/// Accounts of one add-decimals wrap or unwrap leg.
+#[derive(Copy, Clone)]
+pub struct SaberWrapperArgs<'a, 'b: 'a> {
+  pub wrapper: WrappedToken<'a, 'b>,
+  pub wrapper_mint: &'a AccountInfo<'b>,
+  pub wrapper_underlying_tokens: TokenAccount<'a, 'b>,
+  /// user account of the wrapped mint, traded in the stable pool
+  pub user_wrapped_tokens: TokenAccount<'a, 'b>,
+  pub program_id: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b: 'a> SaberWrapperArgs<'a, 'b> {
+  pub const ACCOUNTS_LEN: usize = 5;
+
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    if accounts.len() != Self::ACCOUNTS_LEN {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let &[
+      ref wrapper_acc,
+      ref wrapper_mint,
+      ref wrapper_underlying_tokens_acc,
+      ref user_wrapped_tokens_acc,
+      ref program_id,
+    ]: &'a[AccountInfo<'b>; Self::ACCOUNTS_LEN] = array_ref![accounts, 0, Self::ACCOUNTS_LEN];
+
+    let wrapper = WrappedToken::new(wrapper_acc)?;
+    validate_dex_program(program_id, SABER_WRAPPER_PROGRAM_IDS)?;
+    if *wrapper_acc.owner != *program_id.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    if *wrapper_mint.key != wrapper.wrapper_mint()? {
+      msg!(
+        "wrapper_mint got {}, expect: {}",
+        wrapper_mint.key,
+        wrapper.wrapper_mint()?
+      );
+      return Err(ProtocolError::InvalidSaberWrapperAccount);
+    }
+    if *wrapper_underlying_tokens_acc.key != wrapper.underlying_tokens()? {
+      return Err(ProtocolError::InvalidSaberWrapperAccount);
+    }
+    let user_wrapped_tokens = TokenAccount::new(user_wrapped_tokens_acc)?;
+    if user_wrapped_tokens.mint()? != *wrapper_mint.key {
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+
+    Ok(Self {
+      wrapper,
+      wrapper_mint,
+      wrapper_underlying_tokens: TokenAccount::new(wrapper_underlying_tokens_acc)?,
+      user_wrapped_tokens,
+      program_id,
+    })
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+  use crate::spl_token;
+  use solana_sdk::account_info::AccountInfo;
+  use std::str::FromStr;
+
+  pub(crate) fn wrapped_token_data(
+    multiplier: u64,
+    underlying_mint: &Pubkey,
+    underlying_tokens: &Pubkey,
+    wrapper_mint: &Pubkey,
+  ) -> Vec<u8> {
+    let mut data = vec![0u8; WRAPPED_TOKEN_LEN];
+    data[0..8].copy_from_slice(&WRAPPED_TOKEN_DISCRIMINATOR);
+    data[8] = 9;
+    data[9..17].copy_from_slice(&multiplier.to_le_bytes());
+    data[17..49].copy_from_slice(underlying_mint.as_ref());
+    data[49..81].copy_from_slice(underlying_tokens.as_ref());
+    data[81..113].copy_from_slice(wrapper_mint.as_ref());
+    data[113] = 255;
+    data
+  }
+
+  #[test]
+  fn test_parse_saber_wrapper_args() {
+    let program_id = Pubkey::from_str("DecZY86MU5Gj7kppfUCEmd4LbXXuyZH1yHaP2NTqdiZB").unwrap();
+    let mut keys = (0..5).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    keys[4] = program_id;
+    let underlying_mint = Pubkey::new_unique();
+
+    let token_data = |mint: &Pubkey| {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut datas = vec![
+      wrapped_token_data(1_000, &underlying_mint, &keys[2], &keys[1]),
+      vec![0u8; spl_token::MINT_LEN],
+      token_data(&underlying_mint),
+      token_data(&keys[1]),
+      vec![],
+    ];
+    let owners = [
+      program_id,
+      spl_token::ID,
+      spl_token::ID,
+      spl_token::ID,
+      Pubkey::default(),
+    ];
+    let mut lamports = vec![1_000_000u64; 5];
+    let accounts = keys
+      .iter()
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .zip(owners.iter())
+      .enumerate()
+      .map(|(i, (((key, data), lamports), owner))| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          data.as_mut_slice(),
+          owner,
+          i == 4,
+          281,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args = SaberWrapperArgs::with_parsed_args(&accounts).unwrap();
+    assert_eq!(args.wrapper.multiplier(), Ok(1_000));
+    assert_eq!(args.wrapper.decimals(), Ok(9));
+    assert_eq!(args.wrapper.underlying_mint(), Ok(underlying_mint));
+
+    // user wrapped account of the underlying mint
+    assert_eq!(
+      SaberWrapperArgs::with_parsed_args(&[
+        accounts[0].clone(),
+        accounts[1].clone(),
+        accounts[2].clone(),
+        accounts[2].clone(),
+        accounts[4].clone(),
+      ])
+      .err(),
+      Some(ProtocolError::InvalidTokenAccount)
+    );
+    // swapped wrapper mint and underlying vault
+    assert_eq!(
+      SaberWrapperArgs::with_parsed_args(&[
+        accounts[0].clone(),
+        accounts[2].clone(),
+        accounts[1].clone(),
+        accounts[3].clone(),
+        accounts[4].clone(),
+      ])
+      .err(),
+      Some(ProtocolError::InvalidSaberWrapperAccount)
+    );
+
+    accounts[0].data.borrow_mut()[0] = 0;
+    assert_eq!(
+      SaberWrapperArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::InvalidSaberWrapperAccount)
+    );
+  }
+}
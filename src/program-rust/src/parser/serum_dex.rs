@@ -1,11 +1,14 @@
 use crate::{
-  declare_validated_account_wrapper,
+  check_unreachable, declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
   exchanger::serum_dex::matching::Side as DexSide,
-  parser::base::TokenAccount,
+  instruction::ExchangerType,
+  parser::base::{
+    dex_program_ids, validate_dex_program, SplTokenProgram, SysRentAccount, TokenAccount,
+  },
 };
 use arrayref::{array_ref, array_refs};
-use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
+use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey, system_program};
 
 declare_validated_account_wrapper!(SerumDexMarket, |account: &AccountInfo| {
   if !account.is_writable {
@@ -15,20 +18,36 @@ declare_validated_account_wrapper!(SerumDexMarket, |account: &AccountInfo| {
     .try_borrow_data()
     .map_err(|_| ProtocolError::BorrowAccountDataError)?;
   // [5,8,32,8,32,32,32,8,8,32,8,8,8,32,32,32,32,8,8,8,8,7]
+  // permissioned and openbook markets keep these offsets and append their own
+  // fields before the tail padding
   const MARKET_LEN: usize = 388;
-  if data.len() != MARKET_LEN {
+  if data.len() < MARKET_LEN {
     return Err(ProtocolError::InvalidSerumDexMarketAccount);
   }
   let flag_data = u64::from_le_bytes(*array_ref![data, 5, 8]);
   /**
    *  Initialized = 1u64 << 0,
    *  Market = 1u64 << 1,
+   *  Permissioned = 1u64 << 9,
+   *  CrankAuthorityRequired = 1u64 << 10,
    */
-  if flag_data != 3 {
+  const OPTIONAL_FLAGS: u64 = 1 << 9 | 1 << 10;
+  if flag_data & !OPTIONAL_FLAGS != 3 {
     // if flag_data != (SerumAccountFlag::Initialized | SerumAccountFlag::Market).bits() {
     msg!("flag_data: {:?}, expect: {:?}", flag_data, 3,);
     return Err(ProtocolError::InvalidSerumDexMarketAccount);
   }
+  // orders are sized in lots, a malformed market would divide by zero
+  let coin_lot_size = u64::from_le_bytes(*array_ref![data, 5 + 344, 8]);
+  let pc_lot_size = u64::from_le_bytes(*array_ref![data, 5 + 352, 8]);
+  if coin_lot_size == 0 || pc_lot_size == 0 {
+    msg!(
+      "coin_lot_size: {}, pc_lot_size: {}",
+      coin_lot_size,
+      pc_lot_size
+    );
+    return Err(ProtocolError::InvalidSerumDexMarketAccount);
+  }
   Ok(())
 });
 
@@ -62,6 +81,16 @@ declare_validated_account_wrapper!(SerumDexOpenOrders, |account: &AccountInfo| {
 
 #[allow(unused)]
 impl<'a, 'b: 'a> SerumDexMarket<'a, 'b> {
+  pub fn own_address(self) -> ProtocolResult<Pubkey> {
+    let account_data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (_, data, _) = array_refs![&account_data, 5; ..; 7];
+    Ok(Pubkey::new_from_array(*array_ref![data, 8, 32]))
+  }
+
   pub fn coin_mint(self) -> ProtocolResult<Pubkey> {
     let account_data = self
       .inner()
@@ -81,6 +110,46 @@ impl<'a, 'b: 'a> SerumDexMarket<'a, 'b> {
     let (_, data, _) = array_refs![&account_data, 5; ..; 7];
     Ok(Pubkey::new_from_array(*array_ref![data, 80, 32]))
   }
+
+  pub fn vault_signer_nonce(self) -> ProtocolResult<u64> {
+    self.read_u64(40)
+  }
+
+  pub fn pc_fees_accrued(self) -> ProtocolResult<u64> {
+    self.read_u64(200)
+  }
+
+  pub fn coin_lot_size(self) -> ProtocolResult<u64> {
+    self.read_u64(344)
+  }
+
+  pub fn pc_lot_size(self) -> ProtocolResult<u64> {
+    self.read_u64(352)
+  }
+
+  pub fn fee_rate_bps(self) -> ProtocolResult<u64> {
+    self.read_u64(360)
+  }
+
+  /// Smallest amount an order on `side` spends: one coin lot when selling,
+  /// one pc lot, the price of a coin lot at the lowest tick, when buying.
+  pub fn min_amount_in(self, side: DexSide) -> ProtocolResult<u64> {
+    match side {
+      DexSide::Bid => self.pc_lot_size(),
+      DexSide::Ask => self.coin_lot_size(),
+    }
+  }
+
+  /// `offset` is relative to the end of the 5 bytes head padding
+  fn read_u64(self, offset: usize) -> ProtocolResult<u64> {
+    let account_data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (_, data, _) = array_refs![&account_data, 5; ..; 7];
+    Ok(u64::from_le_bytes(*array_ref![data, offset, 8]))
+  }
 }
 
 #[allow(unused)]
@@ -104,11 +173,92 @@ impl<'a, 'b: 'a> SerumDexOpenOrders<'a, 'b> {
     let (_, data, _) = array_refs![&account_data, 5; ..; 7];
     Ok(Pubkey::new_from_array(*array_ref![data, 40, 32]))
   }
+
+  pub fn native_coin_free(self) -> ProtocolResult<u64> {
+    self.read_u64(72)
+  }
+
+  pub fn native_coin_total(self) -> ProtocolResult<u64> {
+    self.read_u64(80)
+  }
+
+  pub fn native_pc_free(self) -> ProtocolResult<u64> {
+    self.read_u64(88)
+  }
+
+  pub fn native_pc_total(self) -> ProtocolResult<u64> {
+    self.read_u64(96)
+  }
+
+  /// `(native_coin_free, native_pc_free)`, settled to the wallets by
+  /// `settle_funds`
+  pub fn free_balances(self) -> ProtocolResult<(u64, u64)> {
+    Ok((self.native_coin_free()?, self.native_pc_free()?))
+  }
+
+  /// `(native_coin_total, native_pc_total)`, free and locked in orders
+  pub fn total_balances(self) -> ProtocolResult<(u64, u64)> {
+    Ok((self.native_coin_total()?, self.native_pc_total()?))
+  }
+
+  /// Free balance of the side an order on `side` buys into, the coin of a
+  /// bid and the pc of an ask.
+  pub fn native_free_out(self, side: DexSide) -> ProtocolResult<u64> {
+    match side {
+      DexSide::Bid => self.native_coin_free(),
+      DexSide::Ask => self.native_pc_free(),
+    }
+  }
+
+  /// Side and order id of the order in `slot`, if one is resting there.
+  pub fn resting_order(self, slot: u8) -> ProtocolResult<Option<(DexSide, u128)>> {
+    if slot >= 128 {
+      return Err(ProtocolError::InvalidInput);
+    }
+    let account_data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (_, data, _) = array_refs![&account_data, 5; ..; 7];
+    let free_slot_bits = u128::from_le_bytes(*array_ref![data, 104, 16]);
+    if free_slot_bits & (1u128 << slot) != 0 {
+      return Ok(None);
+    }
+    let is_bid_bits = u128::from_le_bytes(*array_ref![data, 120, 16]);
+    let side = if is_bid_bits & (1u128 << slot) != 0 {
+      DexSide::Bid
+    } else {
+      DexSide::Ask
+    };
+    let order_id = u128::from_le_bytes(*array_ref![data, 136 + slot as usize * 16, 16]);
+    Ok(Some((side, order_id)))
+  }
+
+  /// `offset` is relative to the end of the 5 bytes head padding
+  fn read_u64(self, offset: usize) -> ProtocolResult<u64> {
+    let account_data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (_, data, _) = array_refs![&account_data, 5; ..; 7];
+    Ok(u64::from_le_bytes(*array_ref![data, offset, 8]))
+  }
+}
+
+/// Accounts for trading through the program-owned open orders account, see
+/// `serum_dex::order::find_open_orders_address`.
+#[derive(Copy, Clone)]
+pub struct SerumDexOpenOrdersPda<'a, 'b: 'a> {
+  pub authority_acc: &'a AccountInfo<'b>,
+  pub system_program_acc: &'a AccountInfo<'b>,
 }
 
 #[derive(Copy, Clone)]
 pub struct SerumDexArgs<'a, 'b: 'a> {
-  pub open_orders: SerumDexOpenOrders<'a, 'b>,
+  /// Uninitialized on first use of a program-owned open orders account.
+  pub open_orders: &'a AccountInfo<'b>,
   pub market: SerumDexMarket<'a, 'b>,
   pub request_queue_acc: &'a AccountInfo<'b>,
   pub event_queue_acc: &'a AccountInfo<'b>,
@@ -119,14 +269,24 @@ pub struct SerumDexArgs<'a, 'b: 'a> {
   pub vault_signer_acc: &'a AccountInfo<'b>,
   pub rent_sysvar_acc: &'a AccountInfo<'b>,
   pub program_acc: &'a AccountInfo<'b>,
+  pub open_orders_pda: Option<SerumDexOpenOrdersPda<'a, 'b>>,
+  pub referral_acc: Option<TokenAccount<'a, 'b>>,
 }
 
 impl<'a, 'b: 'a> SerumDexArgs<'a, 'b> {
+  pub const MIN_ACCOUNTS: usize = 11;
+
+  /// 11 accounts, followed by the optional open orders authority and system
+  /// program pair, followed by the optional referral account.
   pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 11;
-    if accounts.len() != MIN_ACCOUNTS {
+    if !(Self::MIN_ACCOUNTS..=Self::MIN_ACCOUNTS + 3).contains(&accounts.len()) {
       return Err(ProtocolError::InvalidAccountsLength);
     }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts): (
+      &'a [AccountInfo<'b>; Self::MIN_ACCOUNTS],
+      &'a [AccountInfo<'b>],
+    ) = array_refs![accounts, Self::MIN_ACCOUNTS; .. ;];
     let &[
       ref open_orders_acc,
       ref market_acc,
@@ -139,22 +299,47 @@ impl<'a, 'b: 'a> SerumDexArgs<'a, 'b> {
       ref vault_signer_acc,
       ref rent_sysvar_acc,
       ref serum_program_acc,
-    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+    ]: &'a[AccountInfo<'b>; Self::MIN_ACCOUNTS] = fixed_accounts;
+    let (open_orders_pda, referral_account) = match other_accounts {
+      [authority_acc, system_program_acc, rest @ ..] => {
+        if *system_program_acc.key != system_program::id() {
+          return Err(ProtocolError::InvalidProgramAddress);
+        }
+        let pda = SerumDexOpenOrdersPda {
+          authority_acc,
+          system_program_acc,
+        };
+        (Some(pda), rest)
+      }
+      _ => (None, other_accounts),
+    };
+    let referral_acc = match referral_account {
+      [] => None,
+      [ref acc] => Some(TokenAccount::new(acc)?),
+      _ => check_unreachable!()?,
+    };
 
     let market = SerumDexMarket::new(market_acc)?;
+    validate_dex_program(serum_program_acc, dex_program_ids(ExchangerType::SerumDex))?;
     if *market.inner().owner != *serum_program_acc.key {
       return Err(ProtocolError::InvalidProgramAddress);
     }
-    let open_orders = SerumDexOpenOrders::new(open_orders_acc)?;
-    if *open_orders.inner().owner != *serum_program_acc.key {
-      return Err(ProtocolError::InvalidProgramAddress);
+    // a program-owned open orders account is created on first use
+    let uninitialized = open_orders_pda.is_some()
+      && *open_orders_acc.owner == system_program::id()
+      && open_orders_acc.data_is_empty();
+    if !uninitialized {
+      let open_orders = SerumDexOpenOrders::new(open_orders_acc)?;
+      if *open_orders.inner().owner != *serum_program_acc.key {
+        return Err(ProtocolError::InvalidProgramAddress);
+      }
     }
     // if open_orders.market()? != *market.pubkey() {
     //   return Err(ProtocolError::InvalidSerumDexMarketAccount);
     // }
 
     Ok(SerumDexArgs {
-      open_orders,
+      open_orders: open_orders_acc,
       market,
       request_queue_acc,
       event_queue_acc,
@@ -163,8 +348,10 @@ impl<'a, 'b: 'a> SerumDexArgs<'a, 'b> {
       coin_vault_acc: TokenAccount::new(coin_vault_acc)?,
       pc_vault_acc: TokenAccount::new(pc_vault_acc)?,
       vault_signer_acc,
-      rent_sysvar_acc,
+      rent_sysvar_acc: SysRentAccount::new(rent_sysvar_acc)?.inner(),
       program_acc: serum_program_acc,
+      open_orders_pda,
+      referral_acc,
     })
   }
 
@@ -184,15 +371,140 @@ impl<'a, 'b: 'a> SerumDexArgs<'a, 'b> {
   // }
 }
 
+/// Accounts of `SettleOpenOrders`.
+#[derive(Copy, Clone)]
+pub struct SerumSettleArgs<'a, 'b: 'a> {
+  pub owner: &'a AccountInfo<'b>,
+  pub open_orders: SerumDexOpenOrders<'a, 'b>,
+  pub market: SerumDexMarket<'a, 'b>,
+  pub coin_vault_acc: TokenAccount<'a, 'b>,
+  pub pc_vault_acc: TokenAccount<'a, 'b>,
+  pub coin_wallet: TokenAccount<'a, 'b>,
+  pub pc_wallet: TokenAccount<'a, 'b>,
+  pub vault_signer_acc: &'a AccountInfo<'b>,
+  pub spl_token_program: SplTokenProgram<'a, 'b>,
+  pub program_acc: &'a AccountInfo<'b>,
+  /// set when the open orders account is the program-owned one
+  pub open_orders_authority: Option<&'a AccountInfo<'b>>,
+}
+
+impl<'a, 'b: 'a> SerumSettleArgs<'a, 'b> {
+  /// 10 accounts, followed by the optional open orders authority.
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    const MIN_ACCOUNTS: usize = 10;
+    if !(MIN_ACCOUNTS..=MIN_ACCOUNTS + 1).contains(&accounts.len()) {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts): (
+      &'a [AccountInfo<'b>; MIN_ACCOUNTS],
+      &'a [AccountInfo<'b>],
+    ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
+    let &[
+      ref owner,
+      ref open_orders_acc,
+      ref market_acc,
+      ref coin_vault_acc,
+      ref pc_vault_acc,
+      ref coin_wallet_acc,
+      ref pc_wallet_acc,
+      ref vault_signer_acc,
+      ref spl_token_program_acc,
+      ref program_acc,
+    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
+
+    if !owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount);
+    }
+    let market = SerumDexMarket::new(market_acc)?;
+    validate_dex_program(program_acc, dex_program_ids(ExchangerType::SerumDex))?;
+    if *market_acc.owner != *program_acc.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    let open_orders = SerumDexOpenOrders::new(open_orders_acc)?;
+    if *open_orders_acc.owner != *program_acc.key || open_orders.market()? != *market_acc.key {
+      return Err(ProtocolError::InvalidOpenOrdersAccount);
+    }
+    let coin_wallet = TokenAccount::new(coin_wallet_acc)?;
+    let pc_wallet = TokenAccount::new(pc_wallet_acc)?;
+    if coin_wallet.mint()? != market.coin_mint()? || pc_wallet.mint()? != market.pc_mint()? {
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+
+    Ok(Self {
+      owner,
+      open_orders,
+      market,
+      coin_vault_acc: TokenAccount::new(coin_vault_acc)?,
+      pc_vault_acc: TokenAccount::new(pc_vault_acc)?,
+      coin_wallet,
+      pc_wallet,
+      vault_signer_acc,
+      spl_token_program: SplTokenProgram::new(spl_token_program_acc)?,
+      program_acc,
+      open_orders_authority: other_accounts.first(),
+    })
+  }
+}
+
+/// Accounts of `CloseSerumOpenOrders`.
+#[derive(Copy, Clone)]
+pub struct SerumCloseArgs<'a, 'b: 'a> {
+  pub owner: &'a AccountInfo<'b>,
+  pub open_orders: SerumDexOpenOrders<'a, 'b>,
+  pub market: SerumDexMarket<'a, 'b>,
+  pub open_orders_authority: &'a AccountInfo<'b>,
+  pub program_acc: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b: 'a> SerumCloseArgs<'a, 'b> {
+  pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
+    const MIN_ACCOUNTS: usize = 5;
+    if accounts.len() != MIN_ACCOUNTS {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let &[
+      ref owner,
+      ref open_orders_acc,
+      ref market_acc,
+      ref open_orders_authority,
+      ref program_acc,
+    ]: &'a[AccountInfo<'b>; MIN_ACCOUNTS] = array_ref![accounts, 0, MIN_ACCOUNTS];
+
+    if !owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount);
+    }
+    if !owner.is_writable {
+      return Err(ProtocolError::ReadonlyAccount);
+    }
+    let market = SerumDexMarket::new(market_acc)?;
+    validate_dex_program(program_acc, dex_program_ids(ExchangerType::SerumDex))?;
+    if *market_acc.owner != *program_acc.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
+    let open_orders = SerumDexOpenOrders::new(open_orders_acc)?;
+    if *open_orders_acc.owner != *program_acc.key || open_orders.market()? != *market_acc.key {
+      return Err(ProtocolError::InvalidOpenOrdersAccount);
+    }
+
+    Ok(Self {
+      owner,
+      open_orders,
+      market,
+      open_orders_authority,
+      program_acc,
+    })
+  }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
   use super::*;
+  use crate::spl_token;
   use solana_sdk::{account_info::AccountInfo, pubkey::Pubkey};
   use std::str::FromStr;
 
-  #[test]
-  fn test_serum_dex_market() {
-    let market_data = r#"GmH4gu6PYUUKDZqX8AT2ZH7MKQkqEiK1rkgus44yrCJvP7UDfLpQzbFKzfg
+  pub(crate) const SERUM_MARKET_DATA: &str = r#"GmH4gu6PYUUKDZqX8AT2ZH7MKQkqEiK1rkgus44yrCJvP7UDfLpQzbFKzfg
 Ux1oSffopN2NGno33fnjhD37awk2MPJrXgRiQjwQWWwspgrrjXVKhP87vynWu4FzjGgx8USsnBa5
 mNEZb2rKvNmVZKekzZUpdSAiXEMbVvEpAn1tQTderQCh69t84sPfcVfseAPEKyJYcAiFLCTrKFmQ3
 SVQiartpqiySprqLqkqto5Z3LAVRGBvVvcinYuZBN49ZbBaMGxXS9wt6tXN8ZqmoZMfYvc3un68Du
@@ -200,7 +512,10 @@ J5vyRPyiYz56LqovWnbjjXY76rRPzsbXR3EqYNMyCFjoqxnsH3LLJVYXwT11ggvUery3J8bhDbdvS
 JaacCyTEuaMuWXjJMcsBxW2NQLAPzasX8vu1uTDjqnvCkZKhYcGtCpiLddLQEMXu6mTEE6ZmT73rH
 CLaoGKPSYxuVkunGb4AtkU4mSUfWw3EbKc6s6sEvgi5Ec47RYGdNDMK31jENakYtSAweGRSin1iB7
 G11FU1xhNE"#;
-    let mut data = bs58::decode(market_data.replace('\n', ""))
+
+  #[test]
+  fn test_serum_dex_market() {
+    let mut data = bs58::decode(SERUM_MARKET_DATA.replace('\n', ""))
       .into_vec()
       .unwrap();
     let pubkey = Pubkey::from_str("9wFFyRfZBsuAha4YcuxcXLKwMxJR43S7fPfQLusDBzvT").unwrap();
@@ -217,10 +532,87 @@ G11FU1xhNE"#;
       246,
     );
     let market = SerumDexMarket::new(&account_info).unwrap();
+    assert_eq!(market.own_address().unwrap(), pubkey);
     let expect_coin_mint = Pubkey::from_str("So11111111111111111111111111111111111111112").unwrap();
     let expect_pc_mint = Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap();
     assert_eq!(market.coin_mint().unwrap(), expect_coin_mint);
     assert_eq!(market.pc_mint().unwrap(), expect_pc_mint);
+    assert_eq!(market.vault_signer_nonce().unwrap(), 1);
+    assert_eq!(market.pc_fees_accrued().unwrap(), 238_200_835_077);
+    assert_eq!(market.coin_lot_size().unwrap(), 100_000_000);
+    assert_eq!(market.pc_lot_size().unwrap(), 100);
+    assert_eq!(market.fee_rate_bps().unwrap(), 0);
+    assert_eq!(market.min_amount_in(DexSide::Ask).unwrap(), 100_000_000);
+    assert_eq!(market.min_amount_in(DexSide::Bid).unwrap(), 100);
+  }
+
+  #[test]
+  fn test_serum_dex_market_zero_lot_size() {
+    let pubkey = Pubkey::new_unique();
+    let owner = Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+    // coin_lot_size, pc_lot_size
+    for offset in [5 + 344, 5 + 352] {
+      let mut data = bs58::decode(SERUM_MARKET_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap();
+      data[offset..offset + 8].copy_from_slice(&0u64.to_le_bytes());
+      let mut lamports = 1_000_000u64;
+      let account_info = AccountInfo::new(
+        &pubkey,
+        false,
+        true,
+        &mut lamports,
+        &mut data[..],
+        &owner,
+        false,
+        246,
+      );
+      assert_eq!(
+        SerumDexMarket::new(&account_info).err(),
+        Some(ProtocolError::InvalidSerumDexMarketAccount)
+      );
+    }
+  }
+
+  #[test]
+  fn test_serum_dex_market_layouts() {
+    let pubkey = Pubkey::new_unique();
+    let owner = Pubkey::from_str("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX").unwrap();
+    let check = |len: usize, flags: u64| {
+      let mut data = bs58::decode(SERUM_MARKET_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap();
+      data[5..13].copy_from_slice(&flags.to_le_bytes());
+      let tail = data.split_off(381);
+      data.resize(len - 7, 0);
+      data.extend_from_slice(&tail);
+      let mut lamports = 1_000_000u64;
+      let account_info = AccountInfo::new(
+        &pubkey,
+        false,
+        true,
+        &mut lamports,
+        &mut data[..],
+        &owner,
+        false,
+        246,
+      );
+      SerumDexMarket::new(&account_info).err()
+    };
+    // longer permissioned layout
+    assert_eq!(check(388 + 3 * 32, 3 | 1 << 9), None);
+    assert_eq!(check(388, 3 | 1 << 9 | 1 << 10), None);
+    assert_eq!(
+      check(380, 3),
+      Some(ProtocolError::InvalidSerumDexMarketAccount)
+    );
+    // disabled, closed and non market accounts
+    for flags in [3 | 1 << 7, 3 | 1 << 8, 5, 1] {
+      assert_eq!(
+        check(388, flags),
+        Some(ProtocolError::InvalidSerumDexMarketAccount)
+      );
+    }
   }
 
   #[test]
@@ -309,5 +701,136 @@ UhNFaoyqJ7aZm14QZS9c9FHesiGEqUFNiCZfkWz"#;
     let expect_owner = Pubkey::from_str("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1").unwrap();
     assert_eq!(open_orders.market().unwrap(), expect_market);
     assert_eq!(open_orders.owner().unwrap(), expect_owner);
+    assert_eq!(open_orders.native_coin_free().unwrap(), 8_196_200_000_000);
+    assert_eq!(
+      open_orders.native_coin_total().unwrap(),
+      260_368_000_000_000
+    );
+    assert_eq!(open_orders.native_pc_free().unwrap(), 61_475_074_855_900);
+    assert_eq!(open_orders.native_pc_total().unwrap(), 61_475_074_855_900);
+    assert_eq!(
+      open_orders.free_balances().unwrap(),
+      (8_196_200_000_000, 61_475_074_855_900)
+    );
+    assert_eq!(
+      open_orders.native_free_out(DexSide::Bid).unwrap(),
+      8_196_200_000_000
+    );
+    assert_eq!(
+      open_orders.native_free_out(DexSide::Ask).unwrap(),
+      61_475_074_855_900
+    );
+  }
+
+  #[test]
+  fn test_serum_dex_open_orders_resting_order() {
+    let pubkey = Pubkey::new_unique();
+    let owner = Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+    let mut data = vec![0u8; 3228];
+    data[5] = 5;
+    // every slot free
+    data[5 + 104..5 + 120].copy_from_slice(&u128::MAX.to_le_bytes());
+    let mut lamports = 23357760u64;
+    {
+      let account_info = AccountInfo::new(
+        &pubkey,
+        false,
+        true,
+        &mut lamports,
+        &mut data[..],
+        &owner,
+        false,
+        246,
+      );
+      let open_orders = SerumDexOpenOrders::new(&account_info).unwrap();
+      assert_eq!(open_orders.resting_order(0).unwrap(), None);
+    }
+
+    // a bid left resting in slot 0
+    let order_id = 0x0000_0000_0000_4e20_ffff_ffff_ffff_fffeu128;
+    data[5 + 104..5 + 120].copy_from_slice(&(u128::MAX - 1).to_le_bytes());
+    data[5 + 120..5 + 136].copy_from_slice(&1u128.to_le_bytes());
+    data[5 + 136..5 + 152].copy_from_slice(&order_id.to_le_bytes());
+    let account_info = AccountInfo::new(
+      &pubkey,
+      false,
+      true,
+      &mut lamports,
+      &mut data[..],
+      &owner,
+      false,
+      246,
+    );
+    let open_orders = SerumDexOpenOrders::new(&account_info).unwrap();
+    assert_eq!(
+      open_orders.resting_order(0).unwrap(),
+      Some((DexSide::Bid, order_id))
+    );
+    assert_eq!(open_orders.resting_order(1).unwrap(), None);
+    assert_eq!(
+      open_orders.resting_order(128),
+      Err(ProtocolError::InvalidInput)
+    );
+  }
+
+  #[test]
+  fn test_serum_dex_args_open_orders_pda() {
+    let serum_program = Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+    let market_key = Pubkey::from_str("9wFFyRfZBsuAha4YcuxcXLKwMxJR43S7fPfQLusDBzvT").unwrap();
+    let mut token_data = vec![0u8; spl_token::ACCOUNT_LEN];
+    token_data[0x6c] = 1;
+
+    let mut keys = vec![Pubkey::new_unique(); 13];
+    keys[1] = market_key;
+    keys[9] = solana_program::sysvar::rent::id();
+    keys[10] = serum_program;
+    keys[12] = system_program::id();
+    let mut owners = vec![Pubkey::new_unique(); 13];
+    owners[0] = system_program::id();
+    owners[1] = serum_program;
+    owners[6] = spl_token::ID;
+    owners[7] = spl_token::ID;
+    let mut datas = vec![vec![]; 13];
+    datas[1] = bs58::decode(SERUM_MARKET_DATA.replace('\n', ""))
+      .into_vec()
+      .unwrap();
+    datas[6] = token_data.clone();
+    datas[7] = token_data;
+    let mut lamports = vec![0u64; 13];
+
+    let accounts = keys
+      .iter()
+      .zip(owners.iter())
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .map(|(((key, owner), data), lamports)| {
+        AccountInfo::new(key, false, true, lamports, &mut data[..], owner, false, 246)
+      })
+      .collect::<Vec<_>>();
+
+    // uninitialized open orders account with the PDA accounts
+    let args = SerumDexArgs::with_parsed_args(&accounts).unwrap();
+    let pda = args.open_orders_pda.unwrap();
+    assert_eq!(*pda.authority_acc.key, keys[11]);
+    assert!(args.referral_acc.is_none());
+
+    // an uninitialized open orders account requires the PDA accounts
+    assert!(SerumDexArgs::with_parsed_args(&accounts[..11]).is_err());
+
+    // the PDA accounts must end with the system program
+    let mut wrong = accounts.clone();
+    wrong[12] = accounts[11].clone();
+    assert_eq!(
+      SerumDexArgs::with_parsed_args(&wrong).err(),
+      Some(ProtocolError::InvalidProgramAddress)
+    );
+
+    // bogus rent sysvar
+    let mut wrong = accounts.clone();
+    wrong[9] = accounts[8].clone();
+    assert_eq!(
+      SerumDexArgs::with_parsed_args(&wrong).err(),
+      Some(ProtocolError::InvalidRentAccount)
+    );
   }
 }
@@ -1,12 +1,13 @@
-use arrayref::array_refs;
+use arrayref::{array_ref, array_refs};
 use solana_program::{account_info::AccountInfo, msg, pubkey::Pubkey};
 
 use crate::{
   check_unreachable, declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  instruction::ExchangerType,
 };
 
-use super::base::{TokenAccount, TokenMint};
+use super::base::{dex_program_ids, validate_dex_program, TokenAccount, TokenMint};
 
 declare_validated_account_wrapper!(SplTokenSwapInfo, |account: &AccountInfo| {
   let data = account
@@ -37,7 +38,25 @@ declare_validated_account_wrapper!(SplTokenSwapInfo, |account: &AccountInfo| {
   Ok(())
 });
 
-impl<'a, 'b: 'a> SplTokenSwapInfo<'a, 'b> {}
+impl<'a, 'b: 'a> SplTokenSwapInfo<'a, 'b> {
+  /// 3 bytes head, 7 keys, then the trade, owner trade and owner withdraw
+  /// fee fractions before the host fee one
+  pub fn host_fee_numerator(self) -> ProtocolResult<u64> {
+    self.read_u64(275)
+  }
+
+  pub fn host_fee_denominator(self) -> ProtocolResult<u64> {
+    self.read_u64(283)
+  }
+
+  fn read_u64(self, offset: usize) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, offset, 8]))
+  }
+}
 
 #[derive(Copy, Clone)]
 pub struct SplTokenSwapArgs<'a, 'b: 'a> {
@@ -52,16 +71,32 @@ pub struct SplTokenSwapArgs<'a, 'b: 'a> {
 }
 
 impl<'a, 'b: 'a> SplTokenSwapArgs<'a, 'b> {
+  pub const MIN_ACCOUNTS: usize = 7;
+
   pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    const MIN_ACCOUNTS: usize = 7;
-    if !(accounts.len() == MIN_ACCOUNTS || accounts.len() == MIN_ACCOUNTS + 1) {
+    Self::with_parsed_exchanger_args(accounts, ExchangerType::SplTokenSwap)
+  }
+
+  /// Parses the pool of `exchanger`, `SplTokenSwap` or one of the forks
+  /// sharing its layout, against the program ids of that exchanger. Orca v1
+  /// pools take no host fee account.
+  pub fn with_parsed_exchanger_args(
+    accounts: &'a [AccountInfo<'b>],
+    exchanger: ExchangerType,
+  ) -> ProtocolResult<Self> {
+    let max_accounts = match exchanger {
+      ExchangerType::SplTokenSwap => Self::MIN_ACCOUNTS + 1,
+      ExchangerType::OrcaV1 => Self::MIN_ACCOUNTS,
+      _ => return Err(ProtocolError::InvalidInput),
+    };
+    if !(Self::MIN_ACCOUNTS..=max_accounts).contains(&accounts.len()) {
       return Err(ProtocolError::InvalidAccountsLength);
     }
     #[allow(clippy::ptr_offset_with_cast)]
     let (fixed_accounts, host_fee_account): (
-      &'a [AccountInfo<'b>; MIN_ACCOUNTS],
+      &'a [AccountInfo<'b>; Self::MIN_ACCOUNTS],
       &'a [AccountInfo<'b>],
-    ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
+    ) = array_refs![accounts, Self::MIN_ACCOUNTS; .. ;];
     let &[
       ref swap_info_acc,
       ref authority_acc,
@@ -70,22 +105,27 @@ impl<'a, 'b: 'a> SplTokenSwapArgs<'a, 'b> {
       ref pool_mint_acc,
       ref fee_acc,
       ref program_acc,
-    ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
+    ]: &'a [AccountInfo<'b>; Self::MIN_ACCOUNTS] = fixed_accounts;
     let host_fee_acc = match host_fee_account {
       [] => None,
       [ref acc] => Some(TokenAccount::new(acc)?),
       _ => check_unreachable!()?,
     };
     let swap_info = SplTokenSwapInfo::new(swap_info_acc)?;
+    validate_dex_program(program_acc, dex_program_ids(exchanger))?;
     if *swap_info.inner().owner != *program_acc.key {
       return Err(ProtocolError::InvalidProgramAddress);
     }
+    let token_a_account = TokenAccount::new(token_a_acc)?;
+    let token_b_account = TokenAccount::new(token_b_acc)?;
+    token_a_account.check_vault_owner(authority_acc.key)?;
+    token_b_account.check_vault_owner(authority_acc.key)?;
     // other checks will run in spl-token-swap
     Ok(SplTokenSwapArgs {
       swap_info,
       authority_acc_info: authority_acc,
-      token_a_account: TokenAccount::new(token_a_acc)?,
-      token_b_account: TokenAccount::new(token_b_acc)?,
+      token_a_account,
+      token_b_account,
       pool_mint: TokenMint::new(pool_mint_acc)?,
       fee_account: TokenAccount::new(fee_acc)?,
       program: program_acc,
@@ -93,6 +133,27 @@ impl<'a, 'b: 'a> SplTokenSwapArgs<'a, 'b> {
     })
   }
 
+  /// Host fee account passed to the pool when the protocol collects the host
+  /// fee: a pool token account owned by `host_fee_owner`. `None` on pools
+  /// without a host fee, the account is then left out.
+  pub fn protocol_host_fee_account(
+    &self,
+    host_fee_owner: &Pubkey,
+  ) -> ProtocolResult<Option<TokenAccount<'a, 'b>>> {
+    if self.swap_info.host_fee_numerator()? == 0 {
+      return Ok(None);
+    }
+    let host_fee_account = self
+      .host_fee_account
+      .ok_or(ProtocolError::InvalidHostFeeAccount)?;
+    if host_fee_account.mint()? != *self.pool_mint.pubkey()
+      || host_fee_account.owner()? != *host_fee_owner
+    {
+      return Err(ProtocolError::InvalidHostFeeAccount);
+    }
+    Ok(Some(host_fee_account))
+  }
+
   pub fn find_token_pair(
     &self,
     source_token_account_mint: &Pubkey,
@@ -104,3 +165,112 @@ impl<'a, 'b: 'a> SplTokenSwapArgs<'a, 'b> {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{parser::base::ORCA_V1_PROGRAM_IDS, spl_token};
+  use solana_sdk::account_info::AccountInfo;
+  use std::str::FromStr;
+
+  #[test]
+  fn test_parse_orca_v1_args() {
+    let orca_program = ORCA_V1_PROGRAM_IDS[0];
+    let spl_program = Pubkey::from_str("SwaPpA9LAaLfeLi3a68M4DjnLqgtticKg6CnyNwgAC8").unwrap();
+    let mut keys = (0..8).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    keys[6] = orca_program;
+    keys[7] = spl_program;
+    let authority = keys[1];
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    // orca v1 fee schedule: 0.25% trade fee, 0.05% owner fee, no host fee
+    let mut swap_info_data = vec![0u8; 324];
+    swap_info_data[0] = 1;
+    swap_info_data[1] = 1;
+    for (offset, value) in [(227, 25u64), (235, 10_000), (243, 5), (251, 10_000)] {
+      swap_info_data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    swap_info_data[283..291].copy_from_slice(&10_000u64.to_le_bytes());
+    let token_data = |mint: &Pubkey, owner: &Pubkey| {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[32..64].copy_from_slice(owner.as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut pool_mint_data = vec![0u8; spl_token::MINT_LEN];
+    pool_mint_data[0x2d] = 1;
+    let mut datas = vec![
+      swap_info_data,
+      vec![],
+      token_data(&mint_a, &authority),
+      token_data(&mint_b, &authority),
+      pool_mint_data,
+      token_data(&keys[4], &Pubkey::new_unique()),
+      vec![],
+      vec![],
+    ];
+    let mut owners = vec![orca_program; 8];
+    owners[1] = Pubkey::default();
+    owners[2..6].fill(spl_token::ID);
+    owners[6] = Pubkey::from_str("BPFLoader2111111111111111111111111111111111").unwrap();
+    owners[7] = owners[6];
+    let mut lamports = vec![1_000_000u64; 8];
+    let accounts = keys
+      .iter()
+      .zip(owners.iter())
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .enumerate()
+      .map(|(i, (((key, owner), data), lamports))| {
+        AccountInfo::new(
+          key,
+          false,
+          i != 1 && i < 6,
+          lamports,
+          &mut data[..],
+          owner,
+          i >= 6,
+          300,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args =
+      SplTokenSwapArgs::with_parsed_exchanger_args(&accounts[..7], ExchangerType::OrcaV1).unwrap();
+    assert_eq!(*args.swap_info.pubkey(), keys[0]);
+    assert_eq!(*args.program.key, orca_program);
+    assert!(args.host_fee_account.is_none());
+    assert_eq!(args.swap_info.host_fee_numerator(), Ok(0));
+    assert!(args
+      .protocol_host_fee_account(&Pubkey::new_unique())
+      .unwrap()
+      .is_none());
+    let (source, destination) = args.find_token_pair(&mint_b).unwrap();
+    assert_eq!(source.mint(), Ok(mint_b));
+    assert_eq!(destination.mint(), Ok(mint_a));
+
+    // routes built before OrcaV1 still reach the pool as spl-token-swap
+    assert!(SplTokenSwapArgs::with_parsed_args(&accounts[..7]).is_ok());
+
+    // no host fee account after the program
+    let mut with_host_fee = accounts[..7].to_vec();
+    with_host_fee.push(accounts[5].clone());
+    assert_eq!(
+      SplTokenSwapArgs::with_parsed_exchanger_args(&with_host_fee, ExchangerType::OrcaV1).err(),
+      Some(ProtocolError::InvalidAccountsLength)
+    );
+
+    // an spl-token-swap program is not an orca one
+    let mut spl_pool = accounts[..6].to_vec();
+    spl_pool.push(accounts[7].clone());
+    assert_eq!(
+      SplTokenSwapArgs::with_parsed_exchanger_args(&spl_pool, ExchangerType::OrcaV1).err(),
+      Some(ProtocolError::InvalidDexProgramId)
+    );
+    assert_eq!(
+      SplTokenSwapArgs::with_parsed_exchanger_args(&accounts[..7], ExchangerType::SerumDex).err(),
+      Some(ProtocolError::InvalidInput)
+    );
+  }
+}
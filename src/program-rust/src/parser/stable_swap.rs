@@ -1,11 +1,19 @@
 use crate::{
   declare_validated_account_wrapper,
   error::{ProtocolError, ProtocolResult},
+  exchanger::stable_swap::curve::compute_amp_factor,
+  instruction::ExchangerType,
 };
 use arrayref::{array_ref, array_refs};
-use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+use solana_program::{
+  account_info::AccountInfo,
+  clock::{Clock, UnixTimestamp},
+  pubkey::Pubkey,
+  sysvar::{self, Sysvar},
+};
+use std::cmp;
 
-use super::base::TokenAccount;
+use super::base::{dex_program_ids, validate_authority_pubkey, validate_dex_program, TokenAccount};
 
 declare_validated_account_wrapper!(StableSwapInfo, |account: &AccountInfo| {
   let data = account
@@ -74,6 +82,22 @@ impl<'a, 'b: 'a> StableSwapInfo<'a, 'b> {
     Ok(Pubkey::new_from_array(*array_ref![data, 299, 32]))
   }
 
+  pub fn trade_fee_numerator(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 363, 8]))
+  }
+
+  pub fn trade_fee_denominator(self) -> ProtocolResult<u64> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok(u64::from_le_bytes(*array_ref![data, 371, 8]))
+  }
+
   pub fn nonce(self) -> ProtocolResult<u8> {
     let data = self
       .inner()
@@ -81,6 +105,34 @@ impl<'a, 'b: 'a> StableSwapInfo<'a, 'b> {
       .map_err(|_| ProtocolError::BorrowAccountDataError)?;
     Ok(data[2])
   }
+
+  /// Returns `(initial_amp_factor, target_amp_factor, start_ramp_ts,
+  /// stop_ramp_ts)`.
+  pub fn amp_ramp(self) -> ProtocolResult<(u64, u64, UnixTimestamp, UnixTimestamp)> {
+    let data = self
+      .inner()
+      .try_borrow_data()
+      .map_err(|_| ProtocolError::BorrowAccountDataError)?;
+    Ok((
+      u64::from_le_bytes(*array_ref![data, 3, 8]),
+      u64::from_le_bytes(*array_ref![data, 11, 8]),
+      i64::from_le_bytes(*array_ref![data, 19, 8]),
+      i64::from_le_bytes(*array_ref![data, 27, 8]),
+    ))
+  }
+
+  /// Amplification coefficient at `current_ts`, along the ramp.
+  pub fn amp_factor(self, current_ts: UnixTimestamp) -> ProtocolResult<u64> {
+    let (initial, target, start, stop) = self.amp_ramp()?;
+    compute_amp_factor(initial, target, start, stop, current_ts)
+      .ok_or(ProtocolError::InvalidStableSwapAccountState)
+  }
+
+  /// Amplification coefficient at the Clock timestamp.
+  pub fn current_amp_factor(self) -> ProtocolResult<u64> {
+    let clock = Clock::get().map_err(|_| ProtocolError::InvalidClockAccount)?;
+    self.amp_factor(clock.unix_timestamp)
+  }
 }
 
 #[derive(Copy, Clone)]
@@ -94,14 +146,30 @@ pub struct StableSwapArgs<'a, 'b: 'a> {
 }
 
 impl<'a, 'b: 'a> StableSwapArgs<'a, 'b> {
-  const MIN_ACCOUNTS: usize = 6;
+  pub const MIN_ACCOUNTS: usize = 6;
+
+  /// Splits the pool accounts off the front of `accounts`. Saber reads the
+  /// clock through the sysvar syscall, a clock sysvar account after
+  /// `admin_fee` is still accepted from older clients and skipped.
+  pub fn split_accounts(
+    accounts: &'a [AccountInfo<'b>],
+  ) -> (&'a [AccountInfo<'b>], &'a [AccountInfo<'b>]) {
+    let len = match accounts.get(Self::MIN_ACCOUNTS - 1) {
+      Some(acc) if sysvar::clock::check_id(acc.key) => Self::MIN_ACCOUNTS + 1,
+      _ => Self::MIN_ACCOUNTS,
+    };
+    accounts.split_at(cmp::min(len, accounts.len()))
+  }
 
   pub fn with_parsed_args(accounts: &'a [AccountInfo<'b>]) -> ProtocolResult<Self> {
-    if accounts.len() < Self::MIN_ACCOUNTS {
+    let (accounts, _) = Self::split_accounts(accounts);
+    if accounts.len() < Self::MIN_ACCOUNTS
+      || sysvar::clock::check_id(accounts[accounts.len() - 1].key)
+    {
       return Err(ProtocolError::InvalidAccountsLength);
     }
     #[allow(clippy::ptr_offset_with_cast)]
-    let (fixed_accounts, other_accounts) = array_refs![accounts, 5; ..;];
+    let (fixed_accounts, _, program_acc) = array_refs![accounts, 5; ..; 1];
 
     let &[
       ref swap_info_acc,
@@ -109,17 +177,14 @@ impl<'a, 'b: 'a> StableSwapArgs<'a, 'b> {
       ref token_a_acc,
       ref token_b_acc,
       ref admin_fee_acc,
-      //ref clock_sysvar_acc,
-      //ref program_acc,
-    ]: &'a[AccountInfo<'b>; 5] = array_ref![fixed_accounts, 0, 5];
-
-    let program_acc = if other_accounts.len() == 1 {
-      other_accounts.get(0).unwrap()
-    } else {
-      other_accounts.get(1).unwrap()
-    };
+    ]: &'a[AccountInfo<'b>; 5] = fixed_accounts;
+    let program_acc = &program_acc[0];
 
     let swap_info = StableSwapInfo::new(swap_info_acc)?;
+    validate_dex_program(program_acc, dex_program_ids(ExchangerType::StableSwap))?;
+    if *swap_info_acc.owner != *program_acc.key {
+      return Err(ProtocolError::InvalidProgramAddress);
+    }
 
     if swap_info.token_a()? != *token_a_acc.key {
       return Err(ProtocolError::InvalidTokenAccount);
@@ -133,12 +198,13 @@ impl<'a, 'b: 'a> StableSwapArgs<'a, 'b> {
       return Err(ProtocolError::InvalidStableSwapAccount);
     }
 
-    // validate_authority_pubkey(
-    //   authority_acc.key,
-    //   program_acc.key,
-    //   &swap_info_acc.key.to_bytes(),
-    //   swap_info.nonce()?,
-    // )?;
+    // saber authority: create_program_address([swap_info, nonce], program)
+    validate_authority_pubkey(
+      authority_acc.key,
+      program_acc.key,
+      &swap_info_acc.key.to_bytes(),
+      swap_info.nonce()?,
+    )?;
 
     Ok(StableSwapArgs {
       swap_info,
@@ -160,4 +226,168 @@ impl<'a, 'b: 'a> StableSwapArgs<'a, 'b> {
       Ok((&self.token_b, &self.token_a))
     }
   }
+
+  /// Saber takes its admin fee in the output token, an admin fee account of
+  /// the pool in the other mint would fail the CPI deep in the swap.
+  pub fn check_admin_fee_account(&self, destination_mint: &Pubkey) -> ProtocolResult<()> {
+    if TokenAccount::new(self.admin_fee_acc)?.mint()? != *destination_mint {
+      return Err(ProtocolError::InvalidStableSwapAccount);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::spl_token;
+  use std::str::FromStr;
+
+  #[test]
+  fn test_stable_swap_amp_ramp() {
+    let mut data = vec![0u8; 395];
+    data[0] = 1;
+    data[3..11].copy_from_slice(&100u64.to_le_bytes());
+    data[11..19].copy_from_slice(&2_000u64.to_le_bytes());
+    data[19..27].copy_from_slice(&1_650_000_000i64.to_le_bytes());
+    data[27..35].copy_from_slice(&1_650_086_400i64.to_le_bytes());
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 1_000_000;
+    let account = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut lamports,
+      &mut data,
+      &owner,
+      false,
+      281,
+    );
+    let swap_info = StableSwapInfo::new(&account).unwrap();
+    assert_eq!(
+      swap_info.amp_ramp(),
+      Ok((100, 2_000, 1_650_000_000, 1_650_086_400))
+    );
+    // halfway through a one day ramp from 100 to 2_000
+    assert_eq!(swap_info.amp_factor(1_650_043_200), Ok(1_050));
+    assert_eq!(swap_info.amp_factor(1_650_086_400), Ok(2_000));
+    assert_eq!(
+      swap_info.amp_factor(1_649_999_999),
+      Err(ProtocolError::InvalidStableSwapAccountState)
+    );
+  }
+
+  #[test]
+  fn test_stable_swap_authority() {
+    // saber USDC-USDT pool
+    let program_id = Pubkey::from_str("SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ").unwrap();
+    let swap_info_key = Pubkey::from_str("YAkoNb6HKmSxQN9L8hiBE5tPJRsniSSMzND1boHmZxe").unwrap();
+    let authority = Pubkey::from_str("5C1k9yV7y4CjMnKv8eGYDgWND8P89Pdfj79Trk2qmfGo").unwrap();
+    let keys = [
+      swap_info_key,
+      authority,
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      solana_program::sysvar::clock::id(),
+      program_id,
+    ];
+
+    let mut swap_info_data = vec![0u8; 395];
+    swap_info_data[0] = 1;
+    swap_info_data[2] = 255;
+    swap_info_data[107..139].copy_from_slice(keys[2].as_ref());
+    swap_info_data[139..171].copy_from_slice(keys[3].as_ref());
+    swap_info_data[267..299].copy_from_slice(keys[4].as_ref());
+    let token_data = || {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(Pubkey::new_unique().as_ref());
+      data[0x6c] = 1;
+      data
+    };
+    let mut datas = vec![
+      swap_info_data,
+      vec![],
+      token_data(),
+      token_data(),
+      token_data(),
+      vec![],
+      vec![],
+    ];
+    let owners = [
+      program_id,
+      Pubkey::default(),
+      spl_token::ID,
+      spl_token::ID,
+      spl_token::ID,
+      Pubkey::default(),
+      Pubkey::default(),
+    ];
+    let mut lamports = vec![1_000_000u64; keys.len()];
+    let accounts = keys
+      .iter()
+      .zip(datas.iter_mut())
+      .zip(lamports.iter_mut())
+      .zip(owners.iter())
+      .map(|(((key, data), lamports), owner)| {
+        AccountInfo::new(
+          key,
+          false,
+          true,
+          lamports,
+          data.as_mut_slice(),
+          owner,
+          *key == program_id,
+          281,
+        )
+      })
+      .collect::<Vec<_>>();
+
+    let args = StableSwapArgs::with_parsed_args(&accounts).unwrap();
+    let admin_fee_mint = TokenAccount::new(&accounts[4]).unwrap().mint().unwrap();
+    assert_eq!(args.check_admin_fee_account(&admin_fee_mint), Ok(()));
+    assert_eq!(
+      args.check_admin_fee_account(&Pubkey::new_unique()),
+      Err(ProtocolError::InvalidStableSwapAccount)
+    );
+    // without the clock account
+    let short = [&accounts[..5], &accounts[6..]].concat();
+    assert!(StableSwapArgs::with_parsed_args(&short).is_ok());
+    // the accounts following the pool are left to the caller either way
+    let extra = [&accounts[..], &accounts[2..3]].concat();
+    assert_eq!(StableSwapArgs::split_accounts(&extra).0.len(), 7);
+    let extra = [&short[..], &accounts[2..3]].concat();
+    assert_eq!(StableSwapArgs::split_accounts(&extra).0.len(), 6);
+    assert!(StableSwapArgs::with_parsed_args(&extra).is_ok());
+    // only the clock sysvar may sit between admin_fee and the program
+    let not_clock = [&accounts[..5], &accounts[2..3], &accounts[6..]].concat();
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&not_clock).err(),
+      Some(ProtocolError::InvalidDexProgramId)
+    );
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&accounts[..6]).err(),
+      Some(ProtocolError::InvalidAccountsLength)
+    );
+
+    let mut not_executable = accounts.clone();
+    not_executable[6].executable = false;
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&not_executable).err(),
+      Some(ProtocolError::InvalidDexProgramId)
+    );
+
+    accounts[0].data.borrow_mut()[2] = 254;
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&accounts).err(),
+      Some(ProtocolError::InvalidProgramAddress)
+    );
+    accounts[0].data.borrow_mut()[2] = 255;
+    let wrong_authority = [&accounts[..1], &accounts[2..3], &accounts[2..]].concat();
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&wrong_authority).err(),
+      Some(ProtocolError::InvalidAuthority)
+    );
+  }
 }
@@ -1,47 +1,104 @@
 //! Program state processor
 
-use std::cmp;
+use std::{cmp, num::NonZeroU64, str::FromStr};
 
 use crate::{
   constraints::OWNER_KEY,
-  error::ProtocolError,
+  error::{ProtocolError, ProtocolResult},
   exchanger::{
-    aldrin, crema, cropper, raydium,
-    serum_dex::{self, matching::Side as DexSide},
+    aldrin, crema, cropper, meteora_damm, raydium, raydium_cpmm, saber_wrapper,
+    serum_dex::{
+      self,
+      matching::Side as DexSide,
+      order::{SettleLayout, OPEN_ORDERS_AUTHORITY_SEED, OPEN_ORDERS_LEN, OPEN_ORDERS_SEED},
+    },
     spl_token_swap, stable_swap,
   },
   instruction::{
-    ExchangerType, ProtocolInstruction, SwapInInstruction, SwapInstruction, SwapOutInstruction,
-    SwapOutSlimInstruction,
+    BlockedPoolInstruction, CheckPoolCreatorsInstruction, CollectExchangerStatsInstruction,
+    CollectHostFeeInstruction, EstimateComputeUnitsInstruction, ExchangerEnabledInstruction,
+    ExchangerSpec, ExchangerStatsInstruction, ExchangerType, ExpectOracleInstruction,
+    ExpectOracleThresholdInstruction, FeeAccrualInstruction, FeeDiscountInstruction,
+    FeeExemptOwnerInstruction, FeeGranularityInstruction, FeeReferenceMintInstruction,
+    InitializeSwapInfoInstruction, InputMinimum, MaxExpectOutRatioInstruction,
+    MaxRouteAccountsInstruction, MaxSurplusInstruction, MinNotionalInstruction,
+    MintFeeCapInstruction, MultiOutputSwapInstruction, NativeSwapInstruction, ProtocolInstruction,
+    QuoteRouteInstruction, RebateInstruction, RebateVaultInstruction, RecoverFeesInstruction,
+    ReferencePrice, RegisterPoolInstruction, StartRouteInstruction, SwapByIndexInstruction,
+    SwapInInstruction, SwapInfoTtlInstruction, SwapInstruction, SwapOutInstruction,
+    SwapOutSlimInstruction, SwapPercentInstruction, SwapStableSwapGuardInstruction,
+    SwapWithBackupInstruction, SwapWithFallbackInstruction, TransientRouteInstruction,
+    TreasuryInstruction, TrustedPoolCreatorInstruction, VerifyAuthorityInstruction,
+    SWAP_EXTRA_FLAG_NO_CLAMP, SWAP_FLAG_TREASURY,
   },
   parser::{
     aldrin::AldrinPoolArgs,
-    base::{SplTokenProgram, SwapInfoArgs, TokenAccount, UserArgs},
-    crema::CremaSwapV1Args,
+    base::{
+      validate_authority_pubkey, ProtocolConfigArgs, RecoverFeesArgs, SplTokenProgram,
+      SwapInfoArgs, TokenAccount, TokenMint, UserArgs,
+    },
+    crema::{quote_crema, CremaSwapV1Args},
     cropper::CropperArgs,
-    raydium::{RaydiumSwapArgs, RaydiumSwapArgs2},
-    serum_dex::SerumDexArgs,
+    meteora_damm::MeteoraDammArgs,
+    raydium::RaydiumSwapArgs,
+    raydium_cpmm::RaydiumCpmmArgs,
+    reserves::{self, PoolReserves},
+    saber_wrapper::SaberWrapperArgs,
+    serum_dex::{
+      SerumCloseArgs, SerumDexArgs, SerumDexOpenOrders, SerumDexOpenOrdersPda, SerumSettleArgs,
+    },
     spl_token_swap::SplTokenSwapArgs,
     stable_swap::StableSwapArgs,
   },
   spl_token,
-  state::{Status, SwapInfo},
+  state::{
+    derive_config_address, derive_exchanger_stats_address, derive_fee_accrual_address,
+    derive_pool_registry_address, derive_rebate_vault_address, derive_swap_info_address,
+    derive_trusted_pool_creators_address, ConfigView, ExchangerStats, FeeAccrual, FeeBreakdown,
+    PoolRegistry, ProtocolConfig, RebateVault, RegisteredPool, Status, SwapInfo,
+    TrustedPoolCreators, CONFIG_SEED, EXCHANGER_STATS_SEED, FEE_ACCRUAL_SEED, POOL_REGISTRY_SEED,
+    REBATE_VAULT_SEED, SWAP_INFO_SEED, TRUSTED_POOL_CREATORS_SEED,
+  },
 };
-use arrayref::array_refs;
+use arrayref::{array_ref, array_refs};
 use solana_program::{
   account_info::AccountInfo,
+  clock::Clock,
   entrypoint::ProgramResult,
+  instruction::Instruction,
   log::sol_log_compute_units,
   msg,
-  program::{invoke, invoke_signed},
+  program::{invoke, invoke_signed, set_return_data},
   program_error::ProgramError,
   program_memory::{sol_memcmp, sol_memset},
   program_option::COption,
   program_pack::Pack,
   pubkey::{Pubkey, PUBKEY_BYTES},
   rent::Rent,
+  system_instruction, system_program,
   sysvar::Sysvar,
 };
+/// Accounts of the spl-token-swap pool used to swap the fee, without host fee account
+const FEE_POOL_ACCOUNTS: usize = 7;
+pub const TRANSIENT_SEED: &[u8] = b"transient";
+
+/// Transient intermediate token account of `owner` for `mint`, see
+/// `SwapTransientRoute`.
+pub fn find_transient_address(owner: &Pubkey, mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[TRANSIENT_SEED, owner.as_ref(), mint.as_ref()], program_id)
+}
+/// Fee taken on `amount_in` with `SWAP_FLAG_FEE_ON_INPUT`, in bps
+const INPUT_FEE_BPS: u64 = 10;
+/// Output fee, a share of the surplus over `expect_amount_out`
+const OUTPUT_FEE_NUMERATOR: u64 = 25;
+const OUTPUT_FEE_DENOMINATOR: u64 = 100;
+/// Serum v3 base tier taker fee, the bound used by the settle check when the
+/// market leaves its own `fee_rate_bps` at zero
+const SERUM_BASE_TAKER_FEE_BPS: u64 = 22;
+/// Room under the swap's own price left to the reference mint fee swap, for
+/// the fee pool trading fee and spread
+const FEE_SWAP_TOLERANCE_BPS: u64 = 100;
+
 /// Program state handler.
 pub struct Processor {}
 
@@ -66,9 +123,9 @@ impl Processor {
         msg!("Instruction: Swap RaydiumSwap");
         Self::process_single_step_swap(program_id, &data, accounts, ExchangerType::RaydiumSwap)
       }
-      ProtocolInstruction::InitializeSwapInfo => {
+      ProtocolInstruction::InitializeSwapInfo(data) => {
         msg!("Instruction: InitializeSwapInfo");
-        Self::process_initialize_swap_info(program_id, accounts)
+        Self::process_initialize_swap_info(program_id, &data, accounts)
       }
       ProtocolInstruction::SetupSwapInfo => {
         msg!("Instruction: SetupSwapInfo");
@@ -161,6 +218,244 @@ impl Processor {
         accounts,
         ExchangerType::CropperFinance,
       ),
+      ProtocolInstruction::SwapMeteoraDamm(data) => {
+        Self::process_single_step_swap(program_id, &data, accounts, ExchangerType::MeteoraDamm)
+      }
+      ProtocolInstruction::SwapMeteoraDammIn(data) => {
+        Self::process_single_step_swap_in(program_id, &data, accounts, ExchangerType::MeteoraDamm)
+      }
+      ProtocolInstruction::SwapMeteoraDammOut(data) => {
+        Self::process_single_step_swap_out(program_id, &data, accounts, ExchangerType::MeteoraDamm)
+      }
+      ProtocolInstruction::SwapRaydiumCpmm(data) => {
+        Self::process_single_step_swap(program_id, &data, accounts, ExchangerType::RaydiumCpmm)
+      }
+      ProtocolInstruction::SwapRaydiumCpmmIn(data) => {
+        Self::process_single_step_swap_in(program_id, &data, accounts, ExchangerType::RaydiumCpmm)
+      }
+      ProtocolInstruction::SwapRaydiumCpmmOut(data) => {
+        Self::process_single_step_swap_out(program_id, &data, accounts, ExchangerType::RaydiumCpmm)
+      }
+      ProtocolInstruction::SwapOrcaV1(data) => {
+        Self::process_single_step_swap(program_id, &data, accounts, ExchangerType::OrcaV1)
+      }
+      ProtocolInstruction::SwapOrcaV1In(data) => {
+        Self::process_single_step_swap_in(program_id, &data, accounts, ExchangerType::OrcaV1)
+      }
+      ProtocolInstruction::SwapOrcaV1Out(data) => {
+        Self::process_single_step_swap_out(program_id, &data, accounts, ExchangerType::OrcaV1)
+      }
+      ProtocolInstruction::RecoverFees(data) => {
+        msg!("Instruction: RecoverFees");
+        Self::process_recover_fees(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SwapStableSwapGuard(data) => {
+        msg!("Instruction: Swap StableSwap Guard");
+        Self::process_stable_swap_guard(program_id, &data, accounts)
+      }
+      ProtocolInstruction::InitializeConfig => {
+        msg!("Instruction: InitializeConfig");
+        Self::process_initialize_config(program_id, accounts)
+      }
+      ProtocolInstruction::AddBlockedPool(data) => {
+        msg!("Instruction: AddBlockedPool");
+        Self::process_update_blocked_pool(program_id, &data, accounts, true)
+      }
+      ProtocolInstruction::RemoveBlockedPool(data) => {
+        msg!("Instruction: RemoveBlockedPool");
+        Self::process_update_blocked_pool(program_id, &data, accounts, false)
+      }
+      ProtocolInstruction::AddFeeExemptOwner(data) => {
+        msg!("Instruction: AddFeeExemptOwner");
+        Self::process_update_fee_exempt_owner(program_id, &data, accounts, true)
+      }
+      ProtocolInstruction::RemoveFeeExemptOwner(data) => {
+        msg!("Instruction: RemoveFeeExemptOwner");
+        Self::process_update_fee_exempt_owner(program_id, &data, accounts, false)
+      }
+      ProtocolInstruction::SetExpectOracle(data) => {
+        msg!("Instruction: SetExpectOracle");
+        Self::process_set_expect_oracle(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetExpectOracleThreshold(data) => {
+        msg!("Instruction: SetExpectOracleThreshold");
+        Self::process_set_expect_oracle_threshold(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetMaxExpectOutRatio(data) => {
+        msg!("Instruction: SetMaxExpectOutRatio");
+        Self::process_set_max_expect_out_ratio(program_id, &data, accounts)
+      }
+      ProtocolInstruction::AddTrustedPoolCreator(data) => {
+        msg!("Instruction: AddTrustedPoolCreator");
+        Self::process_add_trusted_pool_creator(program_id, &data, accounts)
+      }
+      ProtocolInstruction::RemoveTrustedPoolCreator(data) => {
+        msg!("Instruction: RemoveTrustedPoolCreator");
+        Self::process_remove_trusted_pool_creator(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetCheckPoolCreators(data) => {
+        msg!("Instruction: SetCheckPoolCreators");
+        Self::process_set_check_pool_creators(program_id, &data, accounts)
+      }
+      ProtocolInstruction::InitializeExchangerStats(data) => {
+        msg!("Instruction: InitializeExchangerStats");
+        Self::process_initialize_exchanger_stats(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetCollectExchangerStats(data) => {
+        msg!("Instruction: SetCollectExchangerStats");
+        Self::process_set_collect_exchanger_stats(program_id, &data, accounts)
+      }
+      ProtocolInstruction::GetExchangerStats => {
+        msg!("Instruction: GetExchangerStats");
+        Self::process_get_exchanger_stats(program_id, accounts)
+      }
+      ProtocolInstruction::RegisterPool(data) => {
+        msg!("Instruction: RegisterPool");
+        Self::process_register_pool(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SwapByIndex(data) => {
+        msg!("Instruction: SwapByIndex");
+        Self::process_swap_by_index(program_id, &data, accounts)
+      }
+      ProtocolInstruction::MultiOutputSwap(data) => {
+        msg!("Instruction: MultiOutputSwap");
+        Self::process_multi_output_swap(program_id, &data, accounts)
+      }
+      ProtocolInstruction::GetExchangerSpec => {
+        msg!("Instruction: GetExchangerSpec");
+        Self::process_get_exchanger_spec()
+      }
+      ProtocolInstruction::InitializeFeeAccrual(data) => {
+        msg!("Instruction: InitializeFeeAccrual");
+        Self::process_initialize_fee_accrual(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SettleAccruedFee => {
+        msg!("Instruction: SettleAccruedFee");
+        Self::process_settle_accrued_fee(program_id, accounts)
+      }
+      ProtocolInstruction::SwapPercent(data) => {
+        msg!("Instruction: SwapPercent");
+        Self::process_swap_percent(program_id, &data, accounts)
+      }
+      ProtocolInstruction::ResetSwapInfo => {
+        msg!("Instruction: ResetSwapInfo");
+        Self::process_reset_swap_info(program_id, accounts)
+      }
+      ProtocolInstruction::SetFeeReferenceMint(data) => {
+        msg!("Instruction: SetFeeReferenceMint");
+        Self::process_set_fee_reference_mint(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetComputeHint => {
+        msg!("Instruction: SetComputeHint");
+        Ok(())
+      }
+      ProtocolInstruction::SwapWithFallback(data) => {
+        msg!("Instruction: SwapWithFallback");
+        Self::process_swap_with_fallback(program_id, &data, accounts)
+      }
+      ProtocolInstruction::EstimateComputeUnits(data) => {
+        msg!("Instruction: EstimateComputeUnits");
+        let units = Self::estimate_compute_units(&data);
+        msg!("estimated compute units: {}", units);
+        set_return_data(&units.to_le_bytes());
+        Ok(())
+      }
+      ProtocolInstruction::SetMinNotional(data) => {
+        msg!("Instruction: SetMinNotional");
+        Self::process_set_min_notional(program_id, &data, accounts)
+      }
+      ProtocolInstruction::VerifyAuthority(data) => {
+        msg!("Instruction: VerifyAuthority");
+        let matched = Self::verify_authority(&data, accounts)?;
+        set_return_data(&[matched as u8]);
+        Ok(())
+      }
+      ProtocolInstruction::SettleOpenOrders => {
+        msg!("Instruction: SettleOpenOrders");
+        Self::process_settle_open_orders(program_id, accounts)
+      }
+      ProtocolInstruction::SetExchangerEnabled(data) => {
+        msg!("Instruction: SetExchangerEnabled");
+        Self::process_set_exchanger_enabled(program_id, &data, accounts)
+      }
+      ProtocolInstruction::StartRoute(data) => {
+        msg!("Instruction: StartRoute");
+        Self::process_start_route(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetFeeDiscount(data) => {
+        msg!("Instruction: SetFeeDiscount");
+        Self::process_set_fee_discount(program_id, &data, accounts)
+      }
+      ProtocolInstruction::CloseSerumOpenOrders => {
+        msg!("Instruction: CloseSerumOpenOrders");
+        Self::process_close_serum_open_orders(program_id, accounts)
+      }
+      ProtocolInstruction::SetMaxSurplus(data) => {
+        msg!("Instruction: SetMaxSurplus");
+        Self::process_set_max_surplus(program_id, &data, accounts)
+      }
+      ProtocolInstruction::CloseStaleSwapInfo => {
+        msg!("Instruction: CloseStaleSwapInfo");
+        Self::process_close_stale_swap_info(program_id, accounts)
+      }
+      ProtocolInstruction::SetSwapInfoTtl(data) => {
+        msg!("Instruction: SetSwapInfoTtl");
+        Self::process_set_swap_info_ttl(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SwapTransientRoute(data) => {
+        msg!("Instruction: SwapTransientRoute");
+        Self::process_swap_transient_route(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetFeeGranularity(data) => {
+        msg!("Instruction: SetFeeGranularity");
+        Self::process_set_fee_granularity(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetTreasury(data) => {
+        msg!("Instruction: SetTreasury");
+        Self::process_set_treasury(program_id, &data, accounts)
+      }
+      ProtocolInstruction::GetConfig => {
+        msg!("Instruction: GetConfig");
+        Self::process_get_config(program_id, accounts)
+      }
+      ProtocolInstruction::SetMintFeeCap(data) => {
+        msg!("Instruction: SetMintFeeCap");
+        Self::process_set_mint_fee_cap(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetCollectHostFee(data) => {
+        msg!("Instruction: SetCollectHostFee");
+        Self::process_set_collect_host_fee(program_id, &data, accounts)
+      }
+      ProtocolInstruction::QuoteRoute(data) => {
+        msg!("Instruction: QuoteRoute");
+        let amount_out = Self::quote_route(program_id, &data, accounts)?;
+        set_return_data(&amount_out.to_le_bytes());
+        Ok(())
+      }
+      ProtocolInstruction::SetRebate(data) => {
+        msg!("Instruction: SetRebate");
+        Self::process_set_rebate(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetRebateVault(data) => {
+        msg!("Instruction: SetRebateVault");
+        Self::process_set_rebate_vault(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SetMaxRouteAccounts(data) => {
+        msg!("Instruction: SetMaxRouteAccounts");
+        Self::process_set_max_route_accounts(program_id, &data, accounts)
+      }
+      ProtocolInstruction::SelfTest => {
+        msg!("Instruction: SelfTest");
+        Self::process_self_test()
+      }
+      ProtocolInstruction::SwapWithBackup(data) => {
+        msg!("Instruction: SwapWithBackup");
+        Self::process_swap_with_backup(program_id, &data, accounts)
+      }
+      ProtocolInstruction::NativeSwap(data) => {
+        msg!("Instruction: NativeSwap");
+        Self::process_native_swap(program_id, &data, accounts)
+      }
     }
   }
 
@@ -170,15 +465,118 @@ impl Processor {
     sol_memcmp(a.as_ref(), b.as_ref(), PUBKEY_BYTES) == 0
   }
 
+  /// Rejects a SwapInfo account passed again as one of the user accounts of
+  /// a swap in or swap out leg.
+  fn check_swap_info_not_aliased(
+    swap_info_account: &AccountInfo,
+    user_accounts: &[AccountInfo],
+  ) -> Result<(), ProtocolError> {
+    if user_accounts
+      .iter()
+      .any(|acc| Self::cmp_pubkeys(acc.key, swap_info_account.key))
+    {
+      return Err(ProtocolError::InvalidAccountData);
+    }
+    Ok(())
+  }
+
+  /// Rejects a protocol fee account, or the config treasury account, as the
+  /// intermediate account of a route: the fee transfers of the Out leg would
+  /// move the amount the In leg credited.
+  fn check_intermediate_not_fee_account(
+    token_account: TokenAccount,
+    protocol_config: Option<&ProtocolConfig>,
+  ) -> Result<(), ProtocolError> {
+    if token_account.owner()?.to_string() == *OWNER_KEY {
+      return Err(ProtocolError::InvalidTokenAccount);
+    }
+    if let Some(protocol_config) = protocol_config {
+      if protocol_config.treasury_token_account == COption::Some(*token_account.pubkey()) {
+        return Err(ProtocolError::InvalidTokenAccount);
+      }
+    }
+    Ok(())
+  }
+
+  /// Creates the program derived `account` with `space` bytes owned by
+  /// `owner`. Anyone can send lamports to the address beforehand, which makes
+  /// `create_account` fail, so a funded account only gets the rent shortfall
+  /// and is allocated and assigned instead.
+  fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    owner: &Pubkey,
+    signer_seeds: &[&[u8]],
+  ) -> ProgramResult {
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    if account.lamports() == 0 {
+      return invoke_signed(
+        &system_instruction::create_account(
+          payer.key,
+          account.key,
+          required_lamports,
+          space as u64,
+          owner,
+        ),
+        &[payer.clone(), account.clone(), system_program.clone()],
+        &[signer_seeds],
+      );
+    }
+    let shortfall = required_lamports.saturating_sub(account.lamports());
+    if shortfall > 0 {
+      invoke(
+        &system_instruction::transfer(payer.key, account.key, shortfall),
+        &[payer.clone(), account.clone(), system_program.clone()],
+      )?;
+    }
+    invoke_signed(
+      &system_instruction::allocate(account.key, space as u64),
+      &[account.clone(), system_program.clone()],
+      &[signer_seeds],
+    )?;
+    invoke_signed(
+      &system_instruction::assign(account.key, owner),
+      &[account.clone(), system_program.clone()],
+      &[signer_seeds],
+    )
+  }
+
   pub fn process_initialize_swap_info(
     program_id: &Pubkey,
+    data: &InitializeSwapInfoInstruction,
     accounts: &[AccountInfo],
   ) -> ProgramResult {
     if accounts.len() < 2 {
       return Err(ProtocolError::InvalidAccountsLength.into());
     }
     #[allow(clippy::ptr_offset_with_cast)]
-    let (&[ref swap_info_account, ref user_account], _) = array_refs![accounts, 2;..;];
+    let (&[ref swap_info_account, ref user_account], rest) = array_refs![accounts, 2;..;];
+    if let Some(nonce) = data.nonce {
+      let system_program_acc = rest.first().ok_or(ProtocolError::InvalidAccountsLength)?;
+      if *system_program_acc.key != system_program::id() {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      if !user_account.is_signer {
+        return Err(ProtocolError::InvalidSignerAccount.into());
+      }
+      let (swap_info_key, bump) = derive_swap_info_address(user_account.key, nonce, program_id);
+      if *swap_info_account.key != swap_info_key {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      // not created yet, or closed since
+      if *swap_info_account.owner == system_program::id() {
+        Self::create_pda_account(
+          user_account,
+          swap_info_account,
+          system_program_acc,
+          SwapInfo::LEN,
+          program_id,
+          &[SWAP_INFO_SEED, user_account.key.as_ref(), &[nonce], &[bump]],
+        )?;
+      }
+    }
     // check onesol_market_acc_info
     if *swap_info_account.owner != *program_id {
       return Err(ProtocolError::InvalidProgramAddress.into());
@@ -196,8 +594,8 @@ impl Processor {
     if swap_info_account.data.borrow()[0] == 1 {
       return Err(ProtocolError::InvalidAccountFlags.into());
     }
-    let swap_info = SwapInfo::new(user_account.key);
-    SwapInfo::pack(swap_info, &mut swap_info_account.data.borrow_mut())?;
+    let swap_info = SwapInfo::new(user_account.key, Clock::get()?.slot);
+    swap_info.store(&mut swap_info_account.data.borrow_mut())?;
     Ok(())
   }
 
@@ -210,18 +608,64 @@ impl Processor {
     if *swap_info_account.owner != *program_id {
       return Err(ProtocolError::InvalidProgramAddress.into());
     }
-    let mut swap_info = SwapInfo::unpack(*swap_info_account.try_borrow_data()?)?;
+    let mut swap_info = SwapInfo::load(*swap_info_account.try_borrow_data()?)?;
     if Status::from_u8(swap_info.status)? != Status::SwapInfo {
       return Err(ProtocolError::InvalidAccountFlags.into());
     }
+    // a retried setup must not discard the amount of an In leg that ran
+    if swap_info.in_progress() {
+      return Err(ProtocolError::InvalidAccountFlags.into());
+    }
     let token_account = TokenAccount::new(token_account_info)?;
+    Self::check_intermediate_not_fee_account(token_account, None)?;
     token_account.check_owner(&swap_info.owner, true)?;
+    if swap_info.token_account == COption::Some(*token_account.pubkey()) {
+      return Ok(());
+    }
     swap_info.token_account = COption::Some(*token_account.pubkey());
     swap_info.token_latest_amount = 0;
-    SwapInfo::pack(swap_info, &mut swap_info_account.data.borrow_mut())?;
+    swap_info.mid_mint = COption::None;
+    swap_info.created_slot = Clock::get()?.slot;
+    swap_info.store(&mut swap_info_account.data.borrow_mut())?;
     Ok(())
   }
 
+  /// InitializeSwapInfo, SetupSwapInfo and the In swap in one instruction,
+  /// each step keeps its own checks.
+  pub fn process_start_route(
+    program_id: &Pubkey,
+    data: &StartRouteInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    // the In swap accounts, followed by the config
+    if accounts.len() < 6 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[_, ref token_account_info, ref user_account, ref swap_info_account], _) =
+      array_refs![accounts, 4; ..;];
+    Self::process_initialize_swap_info(
+      program_id,
+      &InitializeSwapInfoInstruction { nonce: None },
+      &[swap_info_account.clone(), user_account.clone()],
+    )?;
+    Self::process_setup_swap_info(
+      program_id,
+      &[swap_info_account.clone(), token_account_info.clone()],
+    )?;
+    Self::process_single_step_swap_in(
+      program_id,
+      &SwapInInstruction {
+        amount_in: data.amount_in,
+        flags: 0,
+        rebate_nonce: 0,
+        rebate_pool_index: 0,
+      },
+      accounts,
+      data.exchanger,
+    )
+  }
+
   pub fn process_close_swap_info(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     if accounts.len() < 3 {
       return Err(ProtocolError::InvalidAccountsLength.into());
@@ -242,1434 +686,11857 @@ impl Processor {
     if !Self::cmp_pubkeys(swap_info_account.owner, program_id) {
       return Err(ProgramError::InvalidAccountData);
     }
-    let swap_info = SwapInfo::unpack(&swap_info_account.data.borrow())?;
+    let swap_info = SwapInfo::load(&swap_info_account.data.borrow())?;
+    if !Self::cmp_pubkeys(&swap_info.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    Self::close_swap_info_account(swap_info_account, destination_account)
+  }
+
+  /// Closes a SwapInfo left behind by an abandoned route, see
+  /// `SwapInfo::check_stale`.
+  pub fn process_close_stale_swap_info(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 3 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    #[rustfmt::skip]
+    let (&[
+      ref swap_info_account,
+      ref owner_account,
+      ref protocol_config_acc,
+    ], _) = array_refs![accounts, 3;..;];
+    let swap_info = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?.swap_info;
+    let protocol_config =
+      ProtocolConfigArgs::with_parsed_args(protocol_config_acc, program_id)?.config;
     if !Self::cmp_pubkeys(&swap_info.owner, owner_account.key) {
       return Err(ProtocolError::InvalidOwner.into());
     }
+    swap_info.check_stale(Clock::get()?.slot, protocol_config.swap_info_ttl_slots)?;
+    Self::close_swap_info_account(swap_info_account, owner_account)
+  }
+
+  fn close_swap_info_account(
+    swap_info_account: &AccountInfo,
+    destination_account: &AccountInfo,
+  ) -> ProgramResult {
     let dest_starting_lamports = destination_account.lamports();
     **destination_account.lamports.borrow_mut() = dest_starting_lamports
       .checked_add(swap_info_account.lamports())
       .ok_or(ProtocolError::Overflow)?;
     **swap_info_account.lamports.borrow_mut() = 0;
-    sol_memset(*swap_info_account.data.borrow_mut(), 0, SwapInfo::LEN);
+    sol_memset(
+      *swap_info_account.data.borrow_mut(),
+      0,
+      swap_info_account.data_len(),
+    );
     Ok(())
   }
 
-  pub fn process_single_step_swap(
+  pub fn process_reset_swap_info(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref swap_info_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
+    let mut swap_info = swap_info_args.swap_info;
+    if !Self::cmp_pubkeys(&swap_info.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    swap_info.reset()?;
+    swap_info.created_slot = Clock::get()?.slot;
+    swap_info.store(&mut swap_info_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_initialize_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    if accounts.len() < 3 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account, ref system_program_acc], _) =
+      array_refs![accounts, 3;..;];
+    let (config_key, bump) = derive_config_address(program_id);
+    if *config_account.key != config_key {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if owner_account.key.to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidOwnerKey.into());
+    }
+    // not created yet
+    if *config_account.owner == system_program::id() {
+      if *system_program_acc.key != system_program::id() {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      Self::create_pda_account(
+        owner_account,
+        config_account,
+        system_program_acc,
+        ProtocolConfig::LEN,
+        program_id,
+        &[CONFIG_SEED, &[bump]],
+      )?;
+    }
+    if *config_account.owner != *program_id {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    let rent = Rent::get()?;
+    if !rent.is_exempt(config_account.lamports(), config_account.data_len()) {
+      return Err(ProtocolError::NotRentExempt.into());
+    }
+    if !config_account.is_writable {
+      return Err(ProtocolError::ReadonlyAccount.into());
+    }
+    if config_account.data.borrow()[0] == 1 {
+      return Err(ProtocolError::InvalidAccountFlags.into());
+    }
+    let config = ProtocolConfig::new(owner_account.key);
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_update_blocked_pool(
     program_id: &Pubkey,
-    data: &SwapInstruction,
+    data: &BlockedPoolInstruction,
     accounts: &[AccountInfo],
-    exchanger: ExchangerType,
+    blocked: bool,
   ) -> ProgramResult {
-    if accounts.len() < 5 {
+    if accounts.len() < 2 {
       return Err(ProtocolError::InvalidAccountsLength.into());
     }
     #[allow(clippy::ptr_offset_with_cast)]
-    let (fixed_accounts, other_accounts) = array_refs![accounts, 5; ..;];
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    if blocked {
+      config.add_blocked_pool(&data.pool)?;
+    } else {
+      config.remove_blocked_pool(&data.pool)?;
+    }
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let (user_accounts, &[ref spl_token_program_acc, ref fee_token_account_acc]) =
-      array_refs![fixed_accounts, 3, 2];
+  pub fn process_update_fee_exempt_owner(
+    program_id: &Pubkey,
+    data: &FeeExemptOwnerInstruction,
+    accounts: &[AccountInfo],
+    exempt: bool,
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    if exempt {
+      config.add_fee_exempt_owner(&data.owner)?;
+    } else {
+      config.remove_fee_exempt_owner(&data.owner)?;
+    }
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let user_args = UserArgs::with_parsed_args(user_accounts)?;
-    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+  pub fn process_set_fee_reference_mint(
+    program_id: &Pubkey,
+    data: &FeeReferenceMintInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.fee_reference_mint = data.mint;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    if !user_args.source_account_owner.is_signer {
+  pub fn process_set_expect_oracle(
+    program_id: &Pubkey,
+    data: &ExpectOracleInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
       return Err(ProtocolError::InvalidSignerAccount.into());
     }
-    user_args
-      .token_source_account
-      .check_owner(user_args.source_account_owner.key, false)?;
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.expect_oracle = data.oracle;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
-    if fee_token_account.mint()? != user_args.token_destination_account.mint()? {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+  pub fn process_set_expect_oracle_threshold(
+    program_id: &Pubkey,
+    data: &ExpectOracleThresholdInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
     }
-    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
     }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.set_expect_oracle_threshold(&data.mint, data.threshold)?;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    if let Some(delegate) = fee_token_account.delegate()? {
-      if delegate == *user_args.source_account_owner.key {
-        return Err(ProtocolError::InvalidFeeTokenAccount.into());
+  /// Debugging aid for `InvalidAuthority`: a mismatch is reported, not failed.
+  fn verify_authority(
+    data: &VerifyAuthorityInstruction,
+    accounts: &[AccountInfo],
+  ) -> Result<bool, ProgramError> {
+    if accounts.len() < 3 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref pool_account, ref authority_account, ref program_account], _) =
+      array_refs![accounts, 3;..;];
+    match validate_authority_pubkey(
+      authority_account.key,
+      program_account.key,
+      &pool_account.key.to_bytes(),
+      data.nonce,
+    ) {
+      Ok(()) => Ok(true),
+      Err(e) => {
+        msg!(
+          "authority {} mismatch for pool {}, nonce {}: {}",
+          authority_account.key,
+          pool_account.key,
+          data.nonce,
+          e
+        );
+        Ok(false)
       }
     }
+  }
 
-    msg!(
-      "source_token_account amount: {}",
-      user_args.token_source_account.balance()?,
-    );
+  pub fn process_set_min_notional(
+    program_id: &Pubkey,
+    data: &MinNotionalInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.set_min_notional(&data.mint, data.amount)?;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let from_amount_before = user_args.token_source_account.balance()?;
-    let to_amount_before = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_before: {}, to_amount_before: {}, amount_in: {}",
-      from_amount_before,
-      to_amount_before,
-      data.amount_in
-    );
+  pub fn process_set_mint_fee_cap(
+    program_id: &Pubkey,
+    data: &MintFeeCapInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.set_mint_fee_cap(&data.mint, data.max_fee)?;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    match exchanger {
-      ExchangerType::SplTokenSwap => Self::process_step_tokenswap(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::StableSwap => Self::process_step_stableswap(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwap => Self::process_step_raydium(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwapSlim => Self::process_step_raydium_slim(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::SerumDex => Self::process_step_serumdex(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::CremaFinance => Self::process_step_crema_finance(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
-        program_id,
-        data.amount_in.get(),
-        data.minimum_amount_out.get(),
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-    }?;
-    let from_amount_after = user_args.token_source_account.balance()?;
-    let to_amount_after = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_after: {}, to_amount_after: {}",
-      from_amount_after,
-      to_amount_after
-    );
+  pub fn process_set_exchanger_enabled(
+    program_id: &Pubkey,
+    data: &ExchangerEnabledInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.set_exchanger_enabled(data.exchanger, data.enabled);
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let from_amount_changed = from_amount_before.checked_sub(from_amount_after).unwrap();
-    let to_amount_include_fee = to_amount_after.checked_sub(to_amount_before).unwrap();
-    msg!("from_amount changed: {}", from_amount_changed);
-    msg!(
-      "result_with_fee: {}, expect: {}, minimum: {}",
-      to_amount_include_fee,
-      data.expect_amount_out,
-      data.minimum_amount_out,
-    );
-    if to_amount_include_fee == 0 {
-      return Err(ProtocolError::DexSwapError.into());
+  pub fn process_set_fee_discount(
+    program_id: &Pubkey,
+    data: &FeeDiscountInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
     }
+    config.fee_discount_mint = data.mint;
+    config.fee_discount_threshold = data.threshold;
+    config.fee_discount_bps = data.discount_bps;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    if to_amount_include_fee < data.minimum_amount_out.get() {
-      return Err(ProtocolError::ExceededSlippage.into());
+  pub fn process_set_max_surplus(
+    program_id: &Pubkey,
+    data: &MaxSurplusInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
     }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.max_surplus_bps = data.max_surplus_bps;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let fee = to_amount_include_fee
-      .checked_sub(data.expect_amount_out.get())
-      .map(|v| v.checked_mul(25).unwrap().checked_div(100).unwrap_or(0))
-      .unwrap_or(0);
+  pub fn process_set_max_expect_out_ratio(
+    program_id: &Pubkey,
+    data: &MaxExpectOutRatioInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.max_expect_out_ratio = data.max_expect_out_ratio;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    if fee > 0 {
-      Self::token_transfer(
-        spl_token_program.inner(),
-        user_args.token_destination_account.inner(),
-        fee_token_account.inner(),
-        user_args.source_account_owner,
-        fee,
+  pub fn process_add_trusted_pool_creator(
+    program_id: &Pubkey,
+    data: &TrustedPoolCreatorInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 4 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account, ref creators_account, ref system_program_acc], _) =
+      array_refs![accounts, 4;..;];
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    // not created yet
+    if *creators_account.owner == system_program::id() {
+      if *system_program_acc.key != system_program::id() {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      let (creators_key, bump) = derive_trusted_pool_creators_address(program_id);
+      if *creators_account.key != creators_key {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      Self::create_pda_account(
+        owner_account,
+        creators_account,
+        system_program_acc,
+        TrustedPoolCreators::LEN,
+        program_id,
+        &[TRUSTED_POOL_CREATORS_SEED, &[bump]],
+      )?;
+      TrustedPoolCreators::pack(
+        TrustedPoolCreators::new(),
+        &mut creators_account.data.borrow_mut(),
       )?;
     }
+    let mut creators = Self::load_trusted_pool_creators(program_id, creators_account)?;
+    creators.add(&data.creator)?;
+    TrustedPoolCreators::pack(creators, &mut creators_account.data.borrow_mut())?;
     Ok(())
   }
 
-  pub fn process_single_step_swap_in(
+  pub fn process_remove_trusted_pool_creator(
     program_id: &Pubkey,
-    data: &SwapInInstruction,
+    data: &TrustedPoolCreatorInstruction,
     accounts: &[AccountInfo],
-    exchanger: ExchangerType,
   ) -> ProgramResult {
-    if accounts.len() < 5 {
+    if accounts.len() < 3 {
       return Err(ProtocolError::InvalidAccountsLength.into());
     }
     #[allow(clippy::ptr_offset_with_cast)]
-    let (fixed_accounts, other_accounts) = array_refs![accounts, 5; ..;];
-
-    let (user_accounts, &[ref swap_info_account, ref spl_token_program_acc]) =
-      array_refs![fixed_accounts, 3, 2];
-
-    let user_args = UserArgs::with_parsed_args(user_accounts)?;
-    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
-    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    let (&[ref config_account, ref owner_account, ref creators_account], _) =
+      array_refs![accounts, 3;..;];
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    let mut creators = Self::load_trusted_pool_creators(program_id, creators_account)?;
+    creators.remove(&data.creator)?;
+    TrustedPoolCreators::pack(creators, &mut creators_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    if !user_args.source_account_owner.is_signer {
+  pub fn process_set_check_pool_creators(
+    program_id: &Pubkey,
+    data: &CheckPoolCreatorsInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
       return Err(ProtocolError::InvalidSignerAccount.into());
     }
-    user_args
-      .token_source_account
-      .check_owner(user_args.source_account_owner.key, false)?;
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.check_pool_creators = data.enabled;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    match swap_info_args.swap_info.token_account {
-      COption::Some(k) => {
-        if k != *user_args.token_destination_account.pubkey() {
-          return Err(ProtocolError::InvalidTokenAccount.into());
-        }
-      }
-      COption::None => {
-        return Err(ProtocolError::InvalidTokenAccount.into());
+  fn load_trusted_pool_creators(
+    program_id: &Pubkey,
+    creators_account: &AccountInfo,
+  ) -> Result<TrustedPoolCreators, ProgramError> {
+    if *creators_account.owner != *program_id
+      || *creators_account.key != derive_trusted_pool_creators_address(program_id).0
+    {
+      return Err(ProtocolError::InvalidTrustedPoolCreatorsAccount.into());
+    }
+    TrustedPoolCreators::unpack(&creators_account.data.borrow())
+      .map_err(|_| ProtocolError::InvalidTrustedPoolCreatorsAccount.into())
+  }
+
+  /// Takes the optional `TrustedPoolCreators` account passed before the
+  /// config account of a swap, returns the accounts before it.
+  fn split_trusted_pool_creators<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    program_id: &Pubkey,
+  ) -> (Option<&'a AccountInfo<'b>>, &'a [AccountInfo<'b>]) {
+    match accounts.split_last() {
+      Some((last, rest))
+        if *last.owner == *program_id
+          && last.data.borrow().get(1).copied() == Some(Status::TrustedPoolCreators.to_u8()) =>
+      {
+        (Some(last), rest)
       }
+      _ => (None, accounts),
+    }
+  }
+
+  /// Fails with `UntrustedPoolCreator` when the swap passed the
+  /// `TrustedPoolCreators` list, the config checks pool creators and the
+  /// creator of the pool isn't listed. Pools that don't record their creator
+  /// can't be vouched for and are rejected as well. Swaps without the list
+  /// are not checked: the list protects the callers that pass it, it doesn't
+  /// keep a hostile caller off any pool.
+  fn check_pool_creator(
+    program_id: &Pubkey,
+    exchanger: ExchangerType,
+    pool_accounts: &[AccountInfo],
+    creators_acc: Option<&AccountInfo>,
+    protocol_config: &ProtocolConfig,
+  ) -> ProgramResult {
+    let creators_acc = match creators_acc {
+      Some(creators_acc) if protocol_config.check_pool_creators => creators_acc,
+      _ => return Ok(()),
     };
+    let creators = Self::load_trusted_pool_creators(program_id, creators_acc)?;
+    match Self::pool_creator(exchanger, pool_accounts)? {
+      Some(creator) if creators.is_trusted(&creator) => Ok(()),
+      creator => {
+        msg!("untrusted pool creator: {:?}", creator);
+        Err(ProtocolError::UntrustedPoolCreator.into())
+      }
+    }
+  }
 
-    msg!(
-      "source_token_account amount: {}",
-      user_args.token_source_account.balance()?,
-    );
+  pub fn process_set_collect_exchanger_stats(
+    program_id: &Pubkey,
+    data: &CollectExchangerStatsInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.collect_exchanger_stats = data.enabled;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
 
-    let from_amount_before = user_args.token_source_account.balance()?;
-    let to_amount_before = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_before: {}, to_amount_before: {}, amount_in: {}",
-      from_amount_before,
-      to_amount_before,
-      data.amount_in
-    );
+  pub fn process_initialize_exchanger_stats(
+    program_id: &Pubkey,
+    data: &ExchangerStatsInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 3 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref stats_account, ref payer_account, ref system_program_acc], _) =
+      array_refs![accounts, 3;..;];
+    if *system_program_acc.key != system_program::id() {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if !payer_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    let (stats_key, bump) = derive_exchanger_stats_address(data.exchanger, program_id);
+    if *stats_account.key != stats_key {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if *stats_account.owner != system_program::id() {
+      return Err(ProtocolError::InvalidAccountFlags.into());
+    }
+    Self::create_pda_account(
+      payer_account,
+      stats_account,
+      system_program_acc,
+      ExchangerStats::LEN,
+      program_id,
+      &[EXCHANGER_STATS_SEED, &[data.exchanger.to_u8()], &[bump]],
+    )?;
+    ExchangerStats::pack(
+      ExchangerStats::new(data.exchanger),
+      &mut stats_account.data.borrow_mut(),
+    )?;
+    Ok(())
+  }
 
-    match exchanger {
-      ExchangerType::SplTokenSwap => Self::process_step_tokenswap(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::StableSwap => Self::process_step_stableswap(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwap => Self::process_step_raydium(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwapSlim => Self::process_step_raydium_slim(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::SerumDex => Self::process_step_serumdex(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::CremaFinance => Self::process_step_crema_finance(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
-        program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+  pub fn process_get_exchanger_stats(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.is_empty() {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    let mut data = Vec::with_capacity(accounts.len() * ExchangerStats::VIEW_LEN);
+    for stats_account in accounts {
+      if *stats_account.owner != *program_id || stats_account.data_len() != ExchangerStats::LEN {
+        return Err(ProtocolError::InvalidExchangerStatsAccount.into());
+      }
+      let stats = ExchangerStats::unpack(&stats_account.data.borrow())?;
+      if Status::from_u8(stats.status).ok() != Some(Status::ExchangerStats) {
+        return Err(ProtocolError::InvalidExchangerStatsAccount.into());
+      }
+      data.extend_from_slice(&stats.pack_view());
+    }
+    set_return_data(&data);
+    Ok(())
+  }
+
+  pub fn process_get_exchanger_spec() -> ProgramResult {
+    let mut data = Vec::with_capacity(ExchangerType::ALL.len() * ExchangerSpec::LEN);
+    for exchanger in ExchangerType::ALL {
+      data.extend_from_slice(&exchanger.spec().pack(exchanger));
+    }
+    set_return_data(&data);
+    Ok(())
+  }
+
+  pub fn process_initialize_fee_accrual(
+    program_id: &Pubkey,
+    data: &FeeAccrualInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 4 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account, ref accrual_account, ref system_program_acc], _) =
+      array_refs![accounts, 4;..;];
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(owner_account.key, &config.owner) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    if *system_program_acc.key != system_program::id() {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    let (accrual_key, bump) = derive_fee_accrual_address(&data.owner, &data.mint, program_id);
+    if *accrual_account.key != accrual_key {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if *accrual_account.owner != system_program::id() {
+      return Err(ProtocolError::InvalidAccountFlags.into());
+    }
+    Self::create_pda_account(
+      owner_account,
+      accrual_account,
+      system_program_acc,
+      FeeAccrual::LEN,
+      program_id,
+      &[
+        FEE_ACCRUAL_SEED,
+        data.owner.as_ref(),
+        data.mint.as_ref(),
+        &[bump],
+      ],
+    )?;
+    FeeAccrual::pack(
+      FeeAccrual::new(&data.owner, &data.mint),
+      &mut accrual_account.data.borrow_mut(),
+    )?;
+    Ok(())
+  }
+
+  pub fn process_settle_accrued_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (
+      &[ref accrual_account, ref owner_account, ref source_account, ref fee_account, ref token_program_acc],
+      _,
+    ) = array_refs![accounts, 5;..;];
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    let source_token_account = TokenAccount::new(source_account)?;
+    let mint = source_token_account.mint()?;
+    let mut accrual =
+      Self::load_fee_accrual(program_id, accrual_account, owner_account.key, &mint)?;
+    source_token_account.check_owner(owner_account.key, false)?;
+    let fee_token_account = TokenAccount::new(fee_account)?;
+    if fee_token_account.mint()? != mint || fee_token_account.owner()?.to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    }
+    let spl_token_program = SplTokenProgram::new(token_program_acc)?;
+    let amount = accrual.amount;
+    if amount > 0 {
+      Self::token_transfer(
+        spl_token_program.inner(),
+        source_token_account.inner(),
+        fee_token_account.inner(),
+        owner_account,
+        amount,
+      )?;
+    }
+    accrual.amount = 0;
+    FeeAccrual::pack(accrual, &mut accrual_account.data.borrow_mut())?;
+    msg!("accrued fee settled: {}", amount);
+    set_return_data(&amount.to_le_bytes());
+    Ok(())
+  }
+
+  /// Loads the writable `FeeAccrual` of `owner` in `mint`.
+  fn load_fee_accrual(
+    program_id: &Pubkey,
+    accrual_acc: &AccountInfo,
+    owner: &Pubkey,
+    mint: &Pubkey,
+  ) -> Result<FeeAccrual, ProgramError> {
+    if *accrual_acc.owner != *program_id || accrual_acc.data_len() != FeeAccrual::LEN {
+      return Err(ProtocolError::InvalidFeeAccrualAccount.into());
+    }
+    if !accrual_acc.is_writable {
+      return Err(ProtocolError::ReadonlyAccount.into());
+    }
+    let accrual = FeeAccrual::unpack(&accrual_acc.data.borrow())?;
+    accrual.check(owner, mint)?;
+    Ok(accrual)
+  }
+
+  /// Counts a successful direct swap through `exchanger` while the config
+  /// collects exchanger stats, a counter passed while it doesn't is left as
+  /// is.
+  fn record_exchanger_stats(
+    program_id: &Pubkey,
+    exchanger: ExchangerType,
+    stats_acc: Option<&AccountInfo>,
+    protocol_config: &ProtocolConfig,
+  ) -> ProgramResult {
+    if !protocol_config.collect_exchanger_stats {
+      return Ok(());
+    }
+    let stats_acc = stats_acc.ok_or(ProtocolError::InvalidExchangerStatsAccount)?;
+    if *stats_acc.owner != *program_id || stats_acc.data_len() != ExchangerStats::LEN {
+      return Err(ProtocolError::InvalidExchangerStatsAccount.into());
+    }
+    if !stats_acc.is_writable {
+      return Err(ProtocolError::ReadonlyAccount.into());
+    }
+    let mut stats = ExchangerStats::unpack(&stats_acc.data.borrow())?;
+    stats.check_exchanger(exchanger)?;
+    stats.record_swap();
+    ExchangerStats::pack(stats, &mut stats_acc.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_collect_host_fee(
+    program_id: &Pubkey,
+    data: &CollectHostFeeInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.collect_host_fee = data.enabled;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_rebate(
+    program_id: &Pubkey,
+    data: &RebateInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.rebate_bps = data.rebate_bps;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_rebate_vault(
+    program_id: &Pubkey,
+    data: &RebateVaultInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (
+      &[ref config_account, ref owner_account, ref vault_account, ref rebate_acc, ref system_program_acc],
+      _,
+    ) = array_refs![accounts, 5;..;];
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    let token_account = TokenAccount::new(rebate_acc)?;
+    let mint = token_account.mint()?;
+    // the vault alone can move the rebates
+    if token_account.owner()? != *vault_account.key {
+      return Err(ProtocolError::InvalidRebateAccount.into());
+    }
+    // not created yet
+    if *vault_account.owner == system_program::id() {
+      if *system_program_acc.key != system_program::id() {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      let (vault_key, bump) = derive_rebate_vault_address(&mint, program_id);
+      if *vault_account.key != vault_key {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      Self::create_pda_account(
+        owner_account,
+        vault_account,
+        system_program_acc,
+        RebateVault::LEN,
         program_id,
-        data.amount_in.get(),
-        u64::MIN + 1,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-    }?;
+        &[REBATE_VAULT_SEED, mint.as_ref(), &[bump]],
+      )?;
+      RebateVault::pack(
+        RebateVault::new(&mint, rebate_acc.key, data.max_rebate),
+        &mut vault_account.data.borrow_mut(),
+      )?;
+      return Ok(());
+    }
+    if *vault_account.owner != *program_id {
+      return Err(ProtocolError::InvalidRebateAccount.into());
+    }
+    let mut vault = RebateVault::unpack(&vault_account.data.borrow())?;
+    vault.check(&mint, rebate_acc.key)?;
+    vault.max_rebate = data.max_rebate;
+    RebateVault::pack(vault, &mut vault_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_max_route_accounts(
+    program_id: &Pubkey,
+    data: &MaxRouteAccountsInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.max_route_accounts = data.max_route_accounts;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_swap_info_ttl(
+    program_id: &Pubkey,
+    data: &SwapInfoTtlInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.swap_info_ttl_slots = data.ttl_slots;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_fee_granularity(
+    program_id: &Pubkey,
+    data: &FeeGranularityInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.fee_granularity = data.granularity;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_set_treasury(
+    program_id: &Pubkey,
+    data: &TreasuryInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref config_account, ref owner_account], _) = array_refs![accounts, 2;..;];
+    let mut config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    config.treasury_token_account = data.token_account;
+    config.treasury_bps = data.treasury_bps;
+    ProtocolConfig::pack(config, &mut config_account.data.borrow_mut())?;
+    Ok(())
+  }
+
+  pub fn process_get_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let config_account = accounts
+      .first()
+      .ok_or(ProtocolError::InvalidAccountsLength)?;
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    let view = ConfigView {
+      fee_numerator: OUTPUT_FEE_NUMERATOR,
+      fee_denominator: OUTPUT_FEE_DENOMINATOR,
+      owner: config.owner,
+      paused: config.is_paused(),
+      fee_token_owner: Pubkey::from_str(OWNER_KEY).map_err(|_| ProtocolError::InvalidOwnerKey)?,
+    };
+    set_return_data(&view.pack());
+    Ok(())
+  }
+
+  pub fn process_recover_fees(
+    program_id: &Pubkey,
+    data: &RecoverFeesInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let args = RecoverFeesArgs::with_parsed_args(accounts, program_id, data.nonce)?;
+    if args.owner.pubkey().to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidOwnerKey.into());
+    }
+    let (balance, mint) = args.source_token_account.snapshot()?;
+    msg!("recover {} of {}, balance: {}", data.amount, mint, balance);
+    if balance < data.amount.get() {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    Self::token_transfer_signed(
+      program_id,
+      args.spl_token_program.inner(),
+      args.source_token_account.inner(),
+      args.destination_token_account.inner(),
+      args.authority,
+      data.nonce,
+      data.amount.get(),
+    )
+  }
+
+  pub fn process_stable_swap_guard(
+    program_id: &Pubkey,
+    data: &SwapStableSwapGuardInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    // the mints and the user accounts, followed at least by the config
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (&[ref source_mint_acc, ref destination_mint_acc, ref source_acc, ref destination_acc], _) =
+      array_refs![accounts, 4; ..;];
+    let source_mint = TokenMint::new(source_mint_acc)?;
+    let destination_mint = TokenMint::new(destination_mint_acc)?;
+    if TokenAccount::new(source_acc)?.mint()? != *source_mint.pubkey()
+      || TokenAccount::new(destination_acc)?.mint()? != *destination_mint.pubkey()
+    {
+      return Err(ProtocolError::InvalidTokenMint.into());
+    }
+    let minimum_amount_out = stable_swap::curve::minimum_out_from_bps(
+      data.amount_in.get(),
+      source_mint.decimals()?,
+      destination_mint.decimals()?,
+      data.min_out_bps_of_in,
+    )
+    .and_then(NonZeroU64::new)
+    .ok_or(ProtocolError::InvalidInput)?;
+    msg!("minimum_amount_out: {}", minimum_amount_out);
+    if data.expect_amount_out < minimum_amount_out {
+      return Err(ProtocolError::InvalidExpectAmountOut.into());
+    }
+    let (config_args, _) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    Self::process_single_step_swap(
+      program_id,
+      &SwapInstruction {
+        amount_in: data.amount_in,
+        expect_amount_out: data.expect_amount_out,
+        minimum_amount_out,
+        flags: Self::treasury_flag(&config_args.config),
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+      &accounts[2..],
+      ExchangerType::StableSwap,
+    )
+  }
+
+  pub fn process_single_step_swap(
+    program_id: &Pubkey,
+    data: &SwapInstruction,
+    accounts: &[AccountInfo],
+    exchanger: ExchangerType,
+  ) -> ProgramResult {
+    Self::single_step_swap(program_id, data, accounts, exchanger, false).map(|_| ())
+  }
+
+  /// Direct swap, with `defer_fee` the output fee is left in the destination
+  /// account and returned for the caller to charge.
+  fn single_step_swap(
+    program_id: &Pubkey,
+    data: &SwapInstruction,
+    accounts: &[AccountInfo],
+    exchanger: ExchangerType,
+    defer_fee: bool,
+  ) -> Result<u64, ProgramError> {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![accounts, 5; ..;];
+    let (other_accounts, oracle_acc) = if data.oracle_expect_amount_out() {
+      match other_accounts.split_last() {
+        Some((oracle_acc, rest)) => (rest, Some(oracle_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, ratio_mint_accs) = if data.check_expect_out_ratio() {
+      if other_accounts.len() < 2 {
+        return Err(ProtocolError::InvalidAccountsLength.into());
+      }
+      let (rest, mint_accs) = other_accounts.split_at(other_accounts.len() - 2);
+      (rest, Some((&mint_accs[0], &mint_accs[1])))
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, stats_acc) = if data.exchanger_stats() {
+      match other_accounts.split_last() {
+        Some((stats_acc, rest)) => (rest, Some(stats_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, accrual_acc) = if data.accrue_fee() {
+      match other_accounts.split_last() {
+        Some((accrual_acc, rest)) => (rest, Some(accrual_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, source_mint_acc) = if data.check_amount_in_supply() {
+      match other_accounts.split_last() {
+        Some((source_mint_acc, rest)) => (rest, Some(source_mint_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, discount_acc) = if data.fee_discount() {
+      match other_accounts.split_last() {
+        Some((discount_acc, rest)) => (rest, Some(discount_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, treasury_acc) = if data.treasury() {
+      match other_accounts.split_last() {
+        Some((treasury_acc, rest)) => (rest, Some(treasury_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    // spl-token-swap pool used to swap the fee into the reference mint
+    let (other_accounts, fee_pool_accounts) = if data.fee_in_reference_mint() {
+      if other_accounts.len() < FEE_POOL_ACCOUNTS {
+        return Err(ProtocolError::InvalidAccountsLength.into());
+      }
+      other_accounts.split_at(other_accounts.len() - FEE_POOL_ACCOUNTS)
+    } else {
+      (other_accounts, &[][..])
+    };
+
+    let (user_accounts, &[ref spl_token_program_acc, ref fee_token_account_acc]) =
+      array_refs![fixed_accounts, 3, 2];
+
+    let user_args = UserArgs::with_parsed_args(user_accounts)?;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    user_args.check_token_program(&spl_token_program)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(exchanger)?;
+    if let Some(expected_pool) = data.expected_pool {
+      Self::check_expected_pool(exchanger, other_accounts, &expected_pool)?;
+    }
+    Self::check_pool_creator(
+      program_id,
+      exchanger,
+      other_accounts,
+      creators_acc,
+      &protocol_config,
+    )?;
+
+    if !user_args.source_account_owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    user_args
+      .token_source_account
+      .check_owner(user_args.source_account_owner.key, false)?;
+    if let Some((source_mint_acc, destination_mint_acc)) = ratio_mint_accs {
+      Self::check_expect_out_ratio(
+        data.amount_in.get(),
+        data.expect_amount_out.get(),
+        (
+          &user_args.token_source_account,
+          TokenMint::new(source_mint_acc)?,
+        ),
+        (
+          &user_args.token_destination_account,
+          TokenMint::new(destination_mint_acc)?,
+        ),
+        &protocol_config,
+      )?;
+    }
+    if let Some(source_mint_acc) = source_mint_acc {
+      Self::check_amount_in_supply(
+        data.amount_in.get(),
+        &user_args.token_source_account,
+        TokenMint::new(source_mint_acc)?,
+      )?;
+    }
+    if data.require_rent_exempt_destination() {
+      user_args
+        .token_destination_account
+        .check_rent_exempt(&Rent::get()?)?;
+    }
+    Self::check_destination_owner(
+      &user_args.token_destination_account,
+      user_args.source_account_owner.key,
+      data.require_destination_owned_by_signer(),
+    )?;
+
+    let (from_amount_before, source_token_mint) = user_args.token_source_account.snapshot()?;
+    let (to_amount_before, destination_token_mint) =
+      user_args.token_destination_account.snapshot()?;
+    // the output fee is taken on the surplus over `expect_amount_out`, an
+    // oracle attested expectation can't be raised to dodge it
+    protocol_config.check_expect_oracle(
+      &destination_token_mint,
+      data.expect_amount_out.get(),
+      oracle_acc.map(|oracle_acc| (oracle_acc.key, oracle_acc.is_signer)),
+    )?;
+    let accrual = match accrual_acc {
+      Some(accrual_acc) => Some(Self::load_fee_accrual(
+        program_id,
+        accrual_acc,
+        user_args.source_account_owner.key,
+        &destination_token_mint,
+      )?),
+      None => None,
+    };
+    // every step clamps to the spendable balance, a funded `amount_in` is
+    // left as is all the way down
+    if data.no_clamp() {
+      Self::check_amount_in_funded(
+        data.amount_in.get(),
+        &user_args.token_source_account,
+        user_args.source_account_owner.key,
+      )?;
+    }
+    Self::check_min_notional(
+      data.amount_in.get(),
+      from_amount_before,
+      &source_token_mint,
+      data.min_notional_in,
+      &protocol_config,
+    )?;
+
+    let fee_mint = if data.fee_on_input() {
+      source_token_mint
+    } else if data.fee_in_reference_mint() {
+      // the fee swap is priced by the swap itself, source per destination
+      match protocol_config.fee_reference_mint {
+        COption::Some(mint) if mint == source_token_mint || mint == destination_token_mint => mint,
+        COption::Some(_) => return Err(ProtocolError::FeeReferenceMintNotSource.into()),
+        COption::None => return Err(ProtocolError::FeeReferenceMintNotSet.into()),
+      }
+    } else {
+      destination_token_mint
+    };
+
+    msg!(
+      "from_amount_before: {}, to_amount_before: {}, amount_in: {}",
+      from_amount_before,
+      to_amount_before,
+      data.amount_in
+    );
+
+    let amount_in = Self::get_amount_in(data.amount_in.get(), from_amount_before);
+    let input_fee = if data.fee_on_input() {
+      let fee = Self::discounted_fee(
+        Self::input_fee(amount_in),
+        discount_acc,
+        user_args.source_account_owner.key,
+        &protocol_config,
+      )?;
+      protocol_config.round_fee(protocol_config.cap_fee(&source_token_mint, fee))
+    } else {
+      0
+    };
+    // every check above ran, stop before the first transfer or CPI
+    if data.validate_only() {
+      Self::validate_step(
+        exchanger,
+        other_accounts,
+        &source_token_mint,
+        &destination_token_mint,
+        amount_in
+          .checked_sub(input_fee)
+          .ok_or(ProtocolError::Overflow)?,
+        data.minimum_amount_out.get(),
+        &protocol_config,
+      )?;
+      msg!("validate only, swap skipped");
+      return Ok(0);
+    }
+    if input_fee > 0 {
+      msg!("input fee: {}", input_fee);
+      let protocol_fee = Self::pay_treasury(
+        input_fee,
+        treasury_acc,
+        &user_args.token_source_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        &protocol_config,
+      )?;
+      if protocol_fee > 0 {
+        let fee_token_account =
+          Self::parse_fee_token_account(fee_token_account_acc, &fee_mint, &user_args)?;
+        Self::token_transfer(
+          spl_token_program.inner(),
+          user_args.token_source_account.inner(),
+          fee_token_account.inner(),
+          user_args.source_account_owner,
+          protocol_fee,
+        )?;
+      }
+    }
+    let amount_in = amount_in
+      .checked_sub(input_fee)
+      .ok_or(ProtocolError::Overflow)?;
+
+    let to_amount_include_fee = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => Self::process_step_tokenswap(
+        program_id,
+        exchanger,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        data.max_price_impact_bps,
+        data.reference_price,
+      ),
+      ExchangerType::StableSwap => Self::process_step_stableswap(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => Self::process_step_raydium(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::SerumDex => Self::process_step_serumdex(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        data.cancel_resting_order(),
+        data.expected_side,
+        data.check_serum_settle(),
+        data.client_route_id.unwrap_or(0),
+      ),
+      ExchangerType::CremaFinance => Self::process_step_crema_finance(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        data.max_price_impact_bps,
+        data.expected_side,
+        data.reference_price,
+      ),
+      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        data.reference_price,
+      ),
+      ExchangerType::MeteoraDamm => Self::process_step_meteora_damm(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumCpmm => Self::process_step_raydium_cpmm(
+        program_id,
+        amount_in,
+        data.minimum_amount_out.get(),
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+    }?;
+    let (from_amount_after, _) = user_args.token_source_account.snapshot()?;
+    msg!("from_amount_after: {}", from_amount_after);
+    // clamping to the spendable balance may take more than the client meant
+    if from_amount_after < data.min_source_remaining {
+      msg!(
+        "from_amount_after: {}, min_source_remaining: {}",
+        from_amount_after,
+        data.min_source_remaining
+      );
+      return Err(ProtocolError::InsufficientFunds.into());
+    }
+
+    let from_amount_changed = from_amount_before
+      .checked_sub(from_amount_after)
+      .ok_or(ProtocolError::Overflow)?;
+    msg!("from_amount changed: {}", from_amount_changed);
+    Self::log_partial_execution(data.amount_in.get(), from_amount_changed);
+    msg!(
+      "result_with_fee: {}, expect: {}, minimum: {}",
+      to_amount_include_fee,
+      data.expect_amount_out,
+      data.minimum_amount_out,
+    );
+    if to_amount_include_fee == 0 {
+      return Err(ProtocolError::DexSwapError.into());
+    }
+
+    // the output fee is a share of the surplus over `expect_amount_out`, itself
+    // at least `minimum_amount_out`, so the owner nets the minimum after fees
+    if to_amount_include_fee < data.minimum_amount_out.get() {
+      return Err(ProtocolError::ExceededSlippage.into());
+    }
+    Self::record_exchanger_stats(program_id, exchanger, stats_acc, &protocol_config)?;
+
+    if data.fee_on_input() {
+      Self::set_swap_return_data(
+        data.client_route_id,
+        &FeeBreakdown {
+          gross_output: to_amount_include_fee,
+          protocol_fee: 0,
+          referrer_fee: 0,
+          treasury_fee: 0,
+          net_output: to_amount_include_fee,
+        },
+      );
+      return Ok(0);
+    }
+    let fee = Self::discounted_fee(
+      Self::output_fee(
+        to_amount_include_fee,
+        data.expect_amount_out.get(),
+        protocol_config.max_surplus_bps,
+      ),
+      discount_acc,
+      user_args.source_account_owner.key,
+      &protocol_config,
+    )?;
+    let fee = protocol_config.round_fee(protocol_config.cap_fee(&destination_token_mint, fee));
+    let amount_out = to_amount_include_fee - fee;
+    let fee_with_treasury = fee;
+    let fee = Self::pay_treasury(
+      fee,
+      treasury_acc,
+      &user_args.token_destination_account,
+      user_args.source_account_owner,
+      &spl_token_program,
+      &protocol_config,
+    )?;
+
+    if let (Some(accrual_acc), Some(mut accrual)) = (accrual_acc, accrual) {
+      // the fee stays in the destination account, `[fee, owed]` is the
+      // return data unless a client route id is echoed
+      let owed = accrual.accrue(fee)?;
+      FeeAccrual::pack(accrual, &mut accrual_acc.data.borrow_mut())?;
+      msg!("fee accrued: {}, owed: {}", fee, owed);
+      let mut return_data = [0u8; 16];
+      return_data[..8].copy_from_slice(&fee.to_le_bytes());
+      return_data[8..].copy_from_slice(&owed.to_le_bytes());
+      set_return_data(&return_data);
+    } else if fee > 0 && !defer_fee {
+      let fee_token_account =
+        Self::parse_fee_token_account(fee_token_account_acc, &fee_mint, &user_args)?;
+      Self::charge_fee(
+        program_id,
+        fee,
+        (from_amount_changed, to_amount_include_fee),
+        &user_args.token_destination_account,
+        &fee_token_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        fee_pool_accounts,
+        &protocol_config,
+      )?;
+    }
+    if accrual_acc.is_none() || data.client_route_id.is_some() {
+      Self::set_swap_return_data(
+        data.client_route_id,
+        &FeeBreakdown {
+          gross_output: to_amount_include_fee,
+          protocol_fee: fee,
+          referrer_fee: 0,
+          treasury_fee: fee_with_treasury - fee,
+          net_output: amount_out,
+        },
+      );
+    }
+    Ok(if defer_fee { fee } else { 0 })
+  }
+
+  /// The router pinned the pool it quoted, see `pool_account`.
+  fn check_expected_pool(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+    expected_pool: &Pubkey,
+  ) -> ProtocolResult<()> {
+    match Self::pool_account(exchanger, accounts) {
+      Ok(pool) if Self::cmp_pubkeys(pool.key, expected_pool) => Ok(()),
+      _ => {
+        msg!("expected pool: {}", expected_pool);
+        Err(ProtocolError::InvalidProgramAddress)
+      }
+    }
+  }
+
+  /// Pool account of a step: the market of a serum step, the first
+  /// exchanger account otherwise.
+  fn pool_account<'a, 'b>(
+    exchanger: ExchangerType,
+    accounts: &'a [AccountInfo<'b>],
+  ) -> ProtocolResult<&'a AccountInfo<'b>> {
+    let index = match exchanger {
+      ExchangerType::SerumDex => 1,
+      _ => 0,
+    };
+    accounts
+      .get(index)
+      .ok_or(ProtocolError::InvalidAccountsLength)
+  }
+
+  /// Validates the fee token account of a direct swap, only called once a
+  /// fee is owed so fee-free swaps don't depend on it.
+  fn parse_fee_token_account<'a, 'b: 'a>(
+    fee_token_account_acc: &'a AccountInfo<'b>,
+    fee_mint: &Pubkey,
+    user_args: &UserArgs<'a, 'b>,
+  ) -> Result<TokenAccount<'a, 'b>, ProtocolError> {
+    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
+    user_args.check_fee_token_account(fee_token_account.pubkey())?;
+    if fee_token_account.mint()? != *fee_mint {
+      return Err(ProtocolError::InvalidFeeTokenAccount);
+    }
+    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidFeeTokenAccount);
+    }
+    if let Some(delegate) = fee_token_account.delegate()? {
+      if delegate == *user_args.source_account_owner.key {
+        return Err(ProtocolError::InvalidFeeTokenAccount);
+      }
+    }
+    Ok(fee_token_account)
+  }
+
+  pub fn process_swap_with_fallback(
+    program_id: &Pubkey,
+    data: &SwapWithFallbackInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, swap_accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, swap_accounts) =
+      Self::split_trusted_pool_creators(swap_accounts, program_id);
+    let protocol_config = config_args.config;
+    let (swap_accounts, treasury_acc) = Self::split_treasury(swap_accounts, &protocol_config)?;
+    if swap_accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    let (fixed_accounts, candidate_accounts) = swap_accounts.split_at(5);
+    let user_args = UserArgs::with_parsed_args(array_ref![fixed_accounts, 0, 3])?;
+    Self::check_route_accounts(program_id, accounts, &protocol_config)?;
+
+    let (index, exchanger, candidate_accounts) = Self::select_fallback_candidate(
+      data,
+      candidate_accounts,
+      &user_args.token_source_account.mint()?,
+      &user_args.token_destination_account.mint()?,
+      &protocol_config,
+    )?;
+    msg!("fallback candidate: {}, exchanger: {:?}", index, exchanger);
+    Self::process_candidate_swap(
+      program_id,
+      fixed_accounts,
+      candidate_accounts,
+      treasury_acc,
+      creators_acc,
+      config_args.config_acc,
+      index,
+      exchanger,
+      &SwapInstruction {
+        amount_in: data.amount_in,
+        expect_amount_out: data.expect_amount_out,
+        minimum_amount_out: data.minimum_amount_out,
+        flags: Self::treasury_flag(&protocol_config),
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+    )
+  }
+
+  pub fn process_swap_with_backup(
+    program_id: &Pubkey,
+    data: &SwapWithBackupInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, swap_accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, swap_accounts) =
+      Self::split_trusted_pool_creators(swap_accounts, program_id);
+    let protocol_config = config_args.config;
+    let (swap_accounts, treasury_acc) = Self::split_treasury(swap_accounts, &protocol_config)?;
+    if swap_accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    let (fixed_accounts, candidate_accounts) = swap_accounts.split_at(5);
+    let user_args = UserArgs::with_parsed_args(array_ref![fixed_accounts, 0, 3])?;
+
+    let (index, exchanger, candidate_accounts) = Self::select_backup_candidate(
+      data,
+      candidate_accounts,
+      &user_args.token_source_account.mint()?,
+      &user_args.token_destination_account.mint()?,
+      &protocol_config,
+    )?;
+    msg!("backup candidate: {}, exchanger: {:?}", index, exchanger);
+    Self::process_candidate_swap(
+      program_id,
+      fixed_accounts,
+      candidate_accounts,
+      treasury_acc,
+      creators_acc,
+      config_args.config_acc,
+      index,
+      exchanger,
+      &SwapInstruction {
+        amount_in: data.amount_in,
+        expect_amount_out: data.expect_amount_out,
+        minimum_amount_out: data.minimum_amount_out,
+        flags: Self::treasury_flag(&protocol_config),
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+    )
+  }
+
+  /// Single step swap through the candidate picked by `SwapWithFallback` or
+  /// `SwapWithBackup`, then returns `[exchanger, candidate index]`.
+  #[allow(clippy::too_many_arguments)]
+  fn process_candidate_swap(
+    program_id: &Pubkey,
+    fixed_accounts: &[AccountInfo],
+    candidate_accounts: &[AccountInfo],
+    treasury_acc: Option<&AccountInfo>,
+    creators_acc: Option<&AccountInfo>,
+    config_acc: &AccountInfo,
+    index: usize,
+    exchanger: ExchangerType,
+    data: &SwapInstruction,
+  ) -> ProgramResult {
+    let mut swap_accounts = fixed_accounts.to_vec();
+    swap_accounts.extend_from_slice(candidate_accounts);
+    swap_accounts.extend(treasury_acc.cloned());
+    swap_accounts.extend(creators_acc.cloned());
+    swap_accounts.push(config_acc.clone());
+    Self::process_single_step_swap(program_id, data, &swap_accounts, exchanger)?;
+    // set after the swap, the exchanger CPI may overwrite the return data
+    set_return_data(&[exchanger.to_u8(), index as u8]);
+    Ok(())
+  }
+
+  pub fn process_register_pool(
+    program_id: &Pubkey,
+    data: &RegisterPoolInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (
+      &[ref config_account, ref owner_account, ref registry_account, ref system_program_acc],
+      pool_accounts,
+    ) = array_refs![accounts, 4;..;];
+    let config = ProtocolConfigArgs::with_parsed_args(config_account, program_id)?.config;
+    if !owner_account.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if !Self::cmp_pubkeys(&config.owner, owner_account.key) {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+    // not created yet
+    if *registry_account.owner == system_program::id() {
+      if *system_program_acc.key != system_program::id() {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      let (registry_key, bump) = derive_pool_registry_address(program_id);
+      if *registry_account.key != registry_key {
+        return Err(ProtocolError::InvalidProgramAddress.into());
+      }
+      Self::create_pda_account(
+        owner_account,
+        registry_account,
+        system_program_acc,
+        PoolRegistry::LEN,
+        program_id,
+        &[POOL_REGISTRY_SEED, &[bump]],
+      )?;
+      PoolRegistry::init(&mut registry_account.data.borrow_mut())?;
+    }
+    if *registry_account.owner != *program_id {
+      return Err(ProtocolError::InvalidPoolRegistryAccount.into());
+    }
+    let index = PoolRegistry::register(
+      &mut registry_account.data.borrow_mut(),
+      &RegisteredPool {
+        exchanger: data.exchanger,
+        accounts: pool_accounts.iter().map(|account| *account.key).collect(),
+      },
+    )?;
+    msg!(
+      "registered pool: {}, exchanger: {:?}",
+      index,
+      data.exchanger
+    );
+    set_return_data(&[index]);
+    Ok(())
+  }
+
+  /// Pool `pool_index` of the registry, once `pool_accounts` start with its
+  /// registered accounts.
+  fn check_registered_pool(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo,
+    pool_index: u8,
+    pool_accounts: &[AccountInfo],
+  ) -> Result<RegisteredPool, ProgramError> {
+    if *registry_account.owner != *program_id {
+      return Err(ProtocolError::InvalidPoolRegistryAccount.into());
+    }
+    let pool = PoolRegistry::pool(&registry_account.data.borrow(), pool_index)?;
+    if pool_accounts.len() < pool.accounts.len()
+      || pool_accounts
+        .iter()
+        .zip(pool.accounts.iter())
+        .any(|(account, key)| !Self::cmp_pubkeys(account.key, key))
+    {
+      return Err(ProtocolError::PoolRegistryMismatch.into());
+    }
+    Ok(pool)
+  }
+
+  /// Direct swap through pool `pool_index` of the registry, the passed pool
+  /// accounts are checked against the registered ones before the exchanger
+  /// parses them.
+  pub fn process_swap_by_index(
+    program_id: &Pubkey,
+    data: &SwapByIndexInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 6 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    let (fixed_accounts, other_accounts) = accounts.split_at(5);
+    let (registry_account, pool_accounts) = other_accounts
+      .split_first()
+      .ok_or(ProtocolError::InvalidAccountsLength)?;
+    let pool =
+      Self::check_registered_pool(program_id, registry_account, data.pool_index, pool_accounts)?;
+    msg!(
+      "registered pool: {}, exchanger: {:?}",
+      data.pool_index,
+      pool.exchanger
+    );
+    let mut swap_accounts = fixed_accounts.to_vec();
+    swap_accounts.extend_from_slice(pool_accounts);
+    swap_accounts.extend(creators_acc.cloned());
+    swap_accounts.push(config_args.config_acc.clone());
+    Self::process_single_step_swap(program_id, &data.swap, &swap_accounts, pool.exchanger)
+  }
+
+  /// Direct swap of a share of the source balance.
+  pub fn process_swap_percent(
+    program_id: &Pubkey,
+    data: &SwapPercentInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let source_acc = accounts
+      .first()
+      .ok_or(ProtocolError::InvalidAccountsLength)?;
+    let (config_args, _) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let balance = TokenAccount::new(source_acc)?.balance()?;
+    let amount_in = Self::percent_of(balance, data.percent_bps);
+    msg!(
+      "balance: {}, percent_bps: {}, amount_in: {}",
+      balance,
+      data.percent_bps,
+      amount_in
+    );
+    Self::process_single_step_swap(
+      program_id,
+      &SwapInstruction {
+        amount_in: NonZeroU64::new(amount_in).ok_or(ProtocolError::InvalidInput)?,
+        expect_amount_out: data.expect_amount_out,
+        minimum_amount_out: data.minimum_amount_out,
+        flags: Self::treasury_flag(&config_args.config),
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+      accounts,
+      data.exchanger,
+    )
+  }
+
+  /// `percent_bps` of `amount`, rounded down.
+  fn percent_of(amount: u64, percent_bps: u16) -> u64 {
+    (amount as u128 * percent_bps as u128 / 10_000) as u64
+  }
+
+  /// Two direct swaps of parts of the same source into two destinations. The
+  /// legs are not clamped to the source balance, each swaps its full part.
+  pub fn process_multi_output_swap(
+    program_id: &Pubkey,
+    data: &MultiOutputSwapInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, swap_accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, swap_accounts) =
+      Self::split_trusted_pool_creators(swap_accounts, program_id);
+    let protocol_config = config_args.config;
+    let (swap_accounts, treasury_acc) = Self::split_treasury(swap_accounts, &protocol_config)?;
+    if swap_accounts.len() < 7 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![swap_accounts, 7; ..;];
+    #[rustfmt::skip]
+    let &[ref source_acc, ref owner, ref spl_token_program_acc, ref first_destination_acc, ref first_fee_acc, ref second_destination_acc, ref second_fee_acc] = fixed_accounts;
+    let destinations = [
+      (first_destination_acc, first_fee_acc),
+      (second_destination_acc, second_fee_acc),
+    ];
+    if data.legs.len() != destinations.len() {
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    // the legs together never spend more than amount_in
+    let legs_amount_in = data
+      .legs
+      .iter()
+      .try_fold(0u64, |total, leg| total.checked_add(leg.amount_in.get()))
+      .ok_or(ProtocolError::Overflow)?;
+    if legs_amount_in > data.amount_in.get() {
+      msg!(
+        "legs amount_in: {}, amount_in: {}",
+        legs_amount_in,
+        data.amount_in
+      );
+      return Err(ProtocolError::InvalidInput.into());
+    }
+    let legs_accounts_len = data
+      .legs
+      .iter()
+      .map(|leg| leg.accounts_len as usize)
+      .sum::<usize>();
+    if other_accounts.len() != legs_accounts_len {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    Self::check_route_accounts(program_id, accounts, &protocol_config)?;
+    let source_mint = TokenAccount::new(source_acc)?.mint()?;
+    let mut leg_mints = Vec::with_capacity(data.legs.len());
+    for (destination_acc, _) in destinations.iter() {
+      let destination_mint = TokenAccount::new(destination_acc)?.mint()?;
+      if destination_mint == source_mint {
+        return Err(ProtocolError::InvalidTokenMint.into());
+      }
+      leg_mints.push((source_mint, destination_mint));
+    }
+    let mut leg_pools = Vec::with_capacity(data.legs.len());
+    let mut rest = other_accounts;
+    for (index, leg) in data.legs.iter().enumerate() {
+      let (leg_accounts, next) = rest.split_at(leg.accounts_len as usize);
+      rest = next;
+      leg_pools.push(
+        Self::pool_mints(leg.exchanger, leg_accounts)
+          .map_err(|e| ProtocolError::in_leg(index as u8, e.into()))?,
+      );
+    }
+    Self::check_route_mints(&leg_mints, &leg_pools, true)?;
+
+    let mut rest = other_accounts;
+    for (index, (leg, (destination_acc, fee_acc))) in
+      data.legs.iter().zip(destinations.iter()).enumerate()
+    {
+      let (leg_accounts, next) = rest.split_at(leg.accounts_len as usize);
+      rest = next;
+      let mut swap_accounts = vec![
+        source_acc.clone(),
+        destination_acc.clone(),
+        owner.clone(),
+        spl_token_program_acc.clone(),
+        fee_acc.clone(),
+      ];
+      swap_accounts.extend_from_slice(leg_accounts);
+      swap_accounts.extend(treasury_acc.cloned());
+      swap_accounts.extend(creators_acc.cloned());
+      swap_accounts.push(config_args.config_acc.clone());
+      Self::process_single_step_swap(
+        program_id,
+        &SwapInstruction {
+          amount_in: leg.amount_in,
+          expect_amount_out: leg.expect_amount_out,
+          minimum_amount_out: leg.minimum_amount_out,
+          flags: Self::treasury_flag(&protocol_config),
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: SWAP_EXTRA_FLAG_NO_CLAMP,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &swap_accounts,
+        leg.exchanger,
+      )
+      .map_err(|e| ProtocolError::in_leg(index as u8, e))?;
+    }
+    Ok(())
+  }
+
+  /// Direct swap out of or into a transient WSOL account, closed afterwards so
+  /// the owner deals in lamports only.
+  pub fn process_native_swap(
+    program_id: &Pubkey,
+    data: &NativeSwapInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 7 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![accounts, 7; ..;];
+    let (other_accounts, fee_collector_acc) = if data.lamport_fee {
+      match other_accounts.split_last() {
+        Some((fee_collector_acc, rest)) => (rest, Some(fee_collector_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    #[rustfmt::skip]
+    let &[ref token_account_acc, ref owner, ref transient_acc, ref native_mint_acc, ref spl_token_program_acc, ref system_program_acc, ref fee_token_account_acc] = fixed_accounts;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(data.exchanger)?;
+    if !owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    if *system_program_acc.key != system_program::id() {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if *native_mint_acc.key != spl_token::native_mint::id() {
+      return Err(ProtocolError::InvalidTokenMint.into());
+    }
+    let (transient_key, transient_bump) =
+      find_transient_address(owner.key, native_mint_acc.key, program_id);
+    if transient_key != *transient_acc.key {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    if let Some(fee_collector_acc) = fee_collector_acc {
+      if fee_collector_acc.key.to_string() != *OWNER_KEY
+        || *fee_collector_acc.owner != system_program::id()
+        || !fee_collector_acc.is_writable
+      {
+        return Err(ProtocolError::InvalidFeeCollector.into());
+      }
+    }
+
+    let wrapped = if data.unwrap { 0 } else { data.amount_in.get() };
+    let lamports = Rent::get()?
+      .minimum_balance(spl_token::ACCOUNT_LEN)
+      .checked_add(wrapped)
+      .ok_or(ProtocolError::Overflow)?;
+    if owner.lamports() < lamports {
+      msg!("lamports: {}, required: {}", owner.lamports(), lamports);
+      return Err(ProtocolError::InsufficientLamports.into());
+    }
+    msg!("spl_token.create_transient_account");
+    Self::create_pda_account(
+      owner,
+      transient_acc,
+      system_program_acc,
+      spl_token::ACCOUNT_LEN,
+      spl_token_program.pubkey(),
+      &[
+        TRANSIENT_SEED,
+        owner.key.as_ref(),
+        native_mint_acc.key.as_ref(),
+        &[transient_bump],
+      ],
+    )?;
+    if wrapped > 0 {
+      invoke(
+        &system_instruction::transfer(owner.key, transient_acc.key, wrapped),
+        &[
+          owner.clone(),
+          transient_acc.clone(),
+          system_program_acc.clone(),
+        ],
+      )?;
+    }
+    // the lamports over the rent are the amount of a native account
+    invoke(
+      &spl_token::instruction::initialize_account3(
+        spl_token_program.pubkey(),
+        transient_acc.key,
+        native_mint_acc.key,
+        owner.key,
+      )?,
+      &[
+        transient_acc.clone(),
+        native_mint_acc.clone(),
+        spl_token_program.inner().clone(),
+      ],
+    )?;
+
+    let (source_acc, destination_acc) = if data.unwrap {
+      (token_account_acc, transient_acc)
+    } else {
+      (transient_acc, token_account_acc)
+    };
+    let mut swap_accounts = vec![
+      source_acc.clone(),
+      destination_acc.clone(),
+      owner.clone(),
+      spl_token_program_acc.clone(),
+      fee_token_account_acc.clone(),
+    ];
+    swap_accounts.extend_from_slice(other_accounts);
+    swap_accounts.extend(creators_acc.cloned());
+    swap_accounts.push(config_args.config_acc.clone());
+    let lamport_fee = Self::single_step_swap(
+      program_id,
+      &SwapInstruction {
+        amount_in: data.amount_in,
+        expect_amount_out: data.expect_amount_out,
+        minimum_amount_out: data.minimum_amount_out,
+        flags: Self::treasury_flag(&protocol_config),
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+      &swap_accounts,
+      data.exchanger,
+      fee_collector_acc.is_some(),
+    )?;
+
+    // a native account closes with its amount, which goes back as lamports
+    invoke(
+      &spl_token::instruction::close_account(
+        spl_token_program.pubkey(),
+        transient_acc.key,
+        owner.key,
+        owner.key,
+        &[],
+      )?,
+      &[
+        transient_acc.clone(),
+        owner.clone(),
+        spl_token_program.inner().clone(),
+      ],
+    )?;
+    // the fee was closed to the owner with the rest of the output
+    if let Some(fee_collector_acc) = fee_collector_acc {
+      if lamport_fee > 0 {
+        if owner.lamports() < lamport_fee {
+          msg!("lamports: {}, fee: {}", owner.lamports(), lamport_fee);
+          return Err(ProtocolError::InsufficientLamports.into());
+        }
+        msg!("system.transfer fee: {}", lamport_fee);
+        invoke(
+          &system_instruction::transfer(owner.key, fee_collector_acc.key, lamport_fee),
+          &[
+            owner.clone(),
+            fee_collector_acc.clone(),
+            system_program_acc.clone(),
+          ],
+        )?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Fails a multi-leg swap whose distinct accounts, this program included,
+  /// are over the config limit, before any leg runs. Routers get a specific
+  /// error to split the route on rather than a runtime rejection.
+  fn check_route_accounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    protocol_config: &ProtocolConfig,
+  ) -> ProtocolResult<()> {
+    let mut keys = accounts.iter().map(|a| a.key).collect::<Vec<_>>();
+    keys.push(program_id);
+    keys.sort_unstable();
+    keys.dedup();
+    let limit = protocol_config.route_accounts_limit();
+    if keys.len() > limit {
+      msg!(
+        "route takes {} distinct accounts, over the limit of {}",
+        keys.len(),
+        limit
+      );
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    Ok(())
+  }
+
+  /// Fails a multi-leg swap whose legs don't chain, before any leg runs.
+  /// `legs` are the source and destination mints of each leg and `pools` the
+  /// mints its pool trades, see `pool_mints`. Sequential legs start from the
+  /// mint the previous one ended on, split legs all start from the mint of
+  /// the first one. The broken leg is reported with its index, see
+  /// `ProtocolError::in_leg`.
+  fn check_route_mints(
+    legs: &[(Pubkey, Pubkey)],
+    pools: &[Option<(Pubkey, Pubkey)>],
+    split: bool,
+  ) -> ProgramResult {
+    for (index, (&(source, destination), pool)) in legs.iter().zip(pools).enumerate() {
+      let chained = match index.checked_sub(1) {
+        None => true,
+        Some(_) if split => source == legs[0].0,
+        Some(previous) => source == legs[previous].1,
+      };
+      let traded = pool.map_or(true, |(mint_a, mint_b)| {
+        (mint_a, mint_b) == (source, destination) || (mint_b, mint_a) == (source, destination)
+      });
+      if !chained || !traded {
+        msg!("leg {}: {} -> {}", index, source, destination);
+        return Err(ProtocolError::in_leg(
+          index as u8,
+          ProtocolError::MintRoutingMismatch.into(),
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  /// Parses the pool accounts of a swap step without running it. The pool
+  /// must trade the swap mints and, when its reserves can be read, quote at
+  /// least `minimum_amount_out`.
+  fn validate_step(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<(), ProtocolError> {
+    // blocked pools fail as in the swap, serum and crema pools aren't quoted
+    protocol_config.check_pool(Self::pool_account(exchanger, accounts)?.key)?;
+    let (mint_a, mint_b) = match Self::pool_mints(exchanger, accounts)? {
+      Some(mints) => mints,
+      // the wrapped mints are only known to the step
+      None => return Ok(()),
+    };
+    if (mint_a, mint_b) != (*source_mint, *destination_mint)
+      && (mint_b, mint_a) != (*source_mint, *destination_mint)
+    {
+      return Err(ProtocolError::MintRoutingMismatch);
+    }
+    match exchanger {
+      // no pool reserves to quote from
+      ExchangerType::SerumDex | ExchangerType::CremaFinance => Ok(()),
+      _ => {
+        let quote = Self::quote_candidate(
+          exchanger,
+          accounts,
+          source_mint,
+          destination_mint,
+          amount_in,
+          protocol_config,
+        )?;
+        msg!("quote: {}, minimum: {}", quote, minimum_amount_out);
+        if quote < minimum_amount_out {
+          return Err(ProtocolError::ExceededSlippage);
+        }
+        Ok(())
+      }
+    }
+  }
+
+  /// Mints traded by the pool of a route leg, read before any leg runs.
+  /// `None` when the pool accounts alone don't tell: stable swap pools
+  /// behind saber wrappers trade the wrapped mints.
+  fn pool_mints(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+  ) -> Result<Option<(Pubkey, Pubkey)>, ProtocolError> {
+    let mints = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => {
+        let args = SplTokenSwapArgs::with_parsed_exchanger_args(accounts, exchanger)?;
+        (args.token_a_account.mint()?, args.token_b_account.mint()?)
+      }
+      ExchangerType::SerumDex => {
+        let args = SerumDexArgs::with_parsed_args(accounts)?;
+        (args.market.coin_mint()?, args.market.pc_mint()?)
+      }
+      ExchangerType::StableSwap => {
+        if !StableSwapArgs::split_accounts(accounts).1.is_empty() {
+          return Ok(None);
+        }
+        let args = StableSwapArgs::with_parsed_args(accounts)?;
+        (args.token_a.mint()?, args.token_b.mint()?)
+      }
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => {
+        let args = RaydiumSwapArgs::with_parsed_args(accounts)?;
+        (args.amm_info.coin_mint()?, args.amm_info.pc_mint()?)
+      }
+      ExchangerType::CremaFinance => {
+        let args = CremaSwapV1Args::with_parsed_args(accounts)?;
+        (
+          args.swap_info.token_a_mint()?,
+          args.swap_info.token_b_mint()?,
+        )
+      }
+      ExchangerType::AldrinExchange => {
+        let args = AldrinPoolArgs::with_parsed_args(accounts)?;
+        (args.pool_info.coin_mint()?, args.pool_info.pc_mint()?)
+      }
+      ExchangerType::CropperFinance => {
+        let args = CropperArgs::with_parsed_args(accounts)?;
+        (
+          args.swap_info.token_a_mint()?,
+          args.swap_info.token_b_mint()?,
+        )
+      }
+      ExchangerType::MeteoraDamm => {
+        let args = MeteoraDammArgs::with_parsed_args(accounts)?;
+        (*args.token_a_mint.pubkey(), *args.token_b_mint.pubkey())
+      }
+      ExchangerType::RaydiumCpmm => {
+        let args = RaydiumCpmmArgs::with_parsed_args(accounts)?;
+        (*args.token_0_mint.pubkey(), *args.token_1_mint.pubkey())
+      }
+    };
+    Ok(Some(mints))
+  }
+
+  /// Creator, or owner, recorded by the pool of a swap step. `None` for
+  /// exchangers whose pools don't record one.
+  fn pool_creator(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+  ) -> Result<Option<Pubkey>, ProtocolError> {
+    let creator = match exchanger {
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => {
+        RaydiumSwapArgs::with_parsed_args(accounts)?
+          .amm_info
+          .amm_owner()?
+      }
+      ExchangerType::RaydiumCpmm => RaydiumCpmmArgs::with_parsed_args(accounts)?
+        .pool
+        .pool_creator()?,
+      ExchangerType::MeteoraDamm => MeteoraDammArgs::with_parsed_args(accounts)?
+        .pool
+        .creator()?,
+      // the stable swap admin and the Aldrin authority run the pool, they
+      // aren't who created it
+      ExchangerType::StableSwap
+      | ExchangerType::AldrinExchange
+      | ExchangerType::SplTokenSwap
+      | ExchangerType::OrcaV1
+      | ExchangerType::SerumDex
+      | ExchangerType::CremaFinance
+      | ExchangerType::CropperFinance => return Ok(None),
+    };
+    Ok(Some(creator))
+  }
+
+  /// Quotes every candidate from its pool reserves, skipping the ones that
+  /// can't be used, and returns the best one able to fill `minimum_amount_out`.
+  fn select_fallback_candidate<'a, 'b: 'a>(
+    data: &SwapWithFallbackInstruction,
+    accounts: &'a [AccountInfo<'b>],
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<(usize, ExchangerType, &'a [AccountInfo<'b>]), ProtocolError> {
+    let accounts_len = data
+      .candidates
+      .iter()
+      .map(|(_, len)| *len as usize)
+      .sum::<usize>();
+    if accounts_len != accounts.len() {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let mut best: Option<(usize, ExchangerType, &'a [AccountInfo<'b>], u64)> = None;
+    let mut rest = accounts;
+    for (index, (exchanger, len)) in data.candidates.iter().enumerate() {
+      let (candidate_accounts, next) = rest.split_at(*len as usize);
+      rest = next;
+      let quote = match Self::quote_candidate(
+        *exchanger,
+        candidate_accounts,
+        source_mint,
+        destination_mint,
+        data.amount_in.get(),
+        protocol_config,
+      ) {
+        Ok(quote) => quote,
+        Err(e) => {
+          msg!("skip candidate {}: {}", index, e);
+          continue;
+        }
+      };
+      msg!("candidate {} quote: {}", index, quote);
+      if quote < data.minimum_amount_out.get() {
+        continue;
+      }
+      if best.map(|(_, _, _, q)| quote > q).unwrap_or(true) {
+        best = Some((index, *exchanger, candidate_accounts, quote));
+      }
+    }
+    best
+      .map(|(index, exchanger, accounts, _)| (index, exchanger, accounts))
+      .ok_or(ProtocolError::NoValidFallbackCandidate)
+  }
+
+  /// Picks the primary pool unless it fails to parse, is blocked or quotes
+  /// below `minimum_amount_out`, then the backup spl-token-swap pool. A CPI
+  /// failure aborts the transaction, so nothing is left to retry after it.
+  fn select_backup_candidate<'a, 'b: 'a>(
+    data: &SwapWithBackupInstruction,
+    accounts: &'a [AccountInfo<'b>],
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<(usize, ExchangerType, &'a [AccountInfo<'b>]), ProtocolError> {
+    let (exchanger, len) = data.primary;
+    if accounts.len() <= len as usize {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let (primary_accounts, backup_accounts) = accounts.split_at(len as usize);
+    let minimum_amount_out = data.minimum_amount_out.get();
+    let amount_in = data.amount_in.get();
+    match Self::quote_candidate(
+      exchanger,
+      primary_accounts,
+      source_mint,
+      destination_mint,
+      amount_in,
+      protocol_config,
+    ) {
+      Ok(quote) if quote >= minimum_amount_out => return Ok((0, exchanger, primary_accounts)),
+      Ok(quote) => msg!("skip primary, quote: {}", quote),
+      Err(e) => msg!("skip primary: {}", e),
+    }
+    let quote = Self::quote_candidate(
+      ExchangerType::SplTokenSwap,
+      backup_accounts,
+      source_mint,
+      destination_mint,
+      amount_in,
+      protocol_config,
+    )?;
+    msg!("backup quote: {}", quote);
+    if quote < minimum_amount_out {
+      return Err(ProtocolError::NoValidFallbackCandidate);
+    }
+    Ok((1, ExchangerType::SplTokenSwap, backup_accounts))
+  }
+
+  fn quote_candidate(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProtocolError> {
+    protocol_config.check_exchanger(exchanger)?;
+    let (pool, quote) = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => {
+        let args = SplTokenSwapArgs::with_parsed_exchanger_args(accounts, exchanger)?;
+        (
+          *args.swap_info.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      ExchangerType::StableSwap => {
+        let args = StableSwapArgs::with_parsed_args(accounts)?;
+        (
+          *args.swap_info.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => {
+        let args = RaydiumSwapArgs::with_parsed_args(accounts)?;
+        let quote = args.quote(source_mint, destination_mint, amount_in)?;
+        (
+          *args.amm_info.pubkey(),
+          args.round_to_lot(destination_mint, quote)?,
+        )
+      }
+      ExchangerType::AldrinExchange => {
+        let args = AldrinPoolArgs::with_parsed_args(accounts)?;
+        (
+          *args.pool_info.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      ExchangerType::CropperFinance => {
+        let args = CropperArgs::with_parsed_args(accounts)?;
+        (
+          *args.swap_info.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      ExchangerType::MeteoraDamm => {
+        let args = MeteoraDammArgs::with_parsed_args(accounts)?;
+        (
+          *args.pool.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      ExchangerType::RaydiumCpmm => {
+        let args = RaydiumCpmmArgs::with_parsed_args(accounts)?;
+        (
+          *args.pool.pubkey(),
+          args.quote(source_mint, destination_mint, amount_in)?,
+        )
+      }
+      // no pool reserves to quote from
+      ExchangerType::SerumDex | ExchangerType::CremaFinance => {
+        return Err(ProtocolError::InvalidInput)
+      }
+    };
+    protocol_config.check_pool(&pool)?;
+    Ok(quote)
+  }
+
+  /// Net output of a route: every leg is quoted from the previous leg quote,
+  /// then the output fee of a swap expecting `expect_amount_out` is taken.
+  /// The fee discount and treasury split are left out, the first needs the
+  /// user accounts and the second doesn't change what the user gets. A leg
+  /// failing to quote is reported with its index, see `ProtocolError::in_leg`.
+  pub fn quote_route(
+    program_id: &Pubkey,
+    data: &QuoteRouteInstruction,
+    accounts: &[AccountInfo],
+  ) -> Result<u64, ProgramError> {
+    let legs = data.legs.len();
+    if accounts.len() < legs + 2 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    let (fixed_accounts, pool_accounts) = accounts.split_at(legs + 2);
+    let protocol_config =
+      ProtocolConfigArgs::with_parsed_args(&fixed_accounts[0], program_id)?.config;
+    let mints = &fixed_accounts[1..];
+    let accounts_len = data
+      .legs
+      .iter()
+      .map(|(_, len)| *len as usize)
+      .sum::<usize>();
+    if accounts_len != pool_accounts.len() {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+
+    let mut amount = data.amount_in.get();
+    let mut rest = pool_accounts;
+    for (index, (exchanger, len)) in data.legs.iter().enumerate() {
+      let (leg_accounts, next) = rest.split_at(*len as usize);
+      rest = next;
+      amount = Self::quote_route_leg(
+        *exchanger,
+        leg_accounts,
+        mints[index].key,
+        mints[index + 1].key,
+        amount,
+        &protocol_config,
+      )
+      .map_err(|e| ProtocolError::in_leg(index as u8, e.into()))?;
+      msg!("leg {} quote: {}", index, amount);
+    }
+    let fee = Self::output_fee(
+      amount,
+      data.expect_amount_out.get(),
+      protocol_config.max_surplus_bps,
+    );
+    let fee = protocol_config.round_fee(protocol_config.cap_fee(mints[legs].key, fee));
+    msg!("route quote: {}, fee: {}", amount, fee);
+    Ok(amount - fee)
+  }
+
+  /// Like `quote_candidate`, but stable swap and crema pools are quoted on
+  /// their own curve rather than ranked by constant product.
+  fn quote_route_leg(
+    exchanger: ExchangerType,
+    accounts: &[AccountInfo],
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProtocolError> {
+    match exchanger {
+      ExchangerType::StableSwap => {
+        protocol_config.check_exchanger(exchanger)?;
+        let args = StableSwapArgs::with_parsed_args(accounts)?;
+        protocol_config.check_pool(args.swap_info.pubkey())?;
+        reserves::quote_stableswap(
+          &args,
+          source_mint,
+          destination_mint,
+          amount_in,
+          args.swap_info.current_amp_factor()?,
+        )
+      }
+      ExchangerType::CremaFinance => {
+        protocol_config.check_exchanger(exchanger)?;
+        let args = CremaSwapV1Args::with_parsed_args(accounts)?;
+        protocol_config.check_pool(args.swap_info.pubkey())?;
+        args.find_token_pair(source_mint, destination_mint)?;
+        let a_to_b = *source_mint == args.swap_info.token_a_mint()?;
+        quote_crema(args.swap_info, amount_in, a_to_b)
+      }
+      _ => Self::quote_candidate(
+        exchanger,
+        accounts,
+        source_mint,
+        destination_mint,
+        amount_in,
+        protocol_config,
+      ),
+    }
+  }
+
+  pub fn process_single_step_swap_in(
+    program_id: &Pubkey,
+    data: &SwapInInstruction,
+    accounts: &[AccountInfo],
+    exchanger: ExchangerType,
+  ) -> ProgramResult {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 5 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![accounts, 5; ..;];
+    let (other_accounts, rebate_accounts) = if data.rebate() {
+      if other_accounts.len() < 3 {
+        return Err(ProtocolError::InvalidAccountsLength.into());
+      }
+      let (rest, rebate_accounts) = other_accounts.split_at(other_accounts.len() - 3);
+      (rest, Some(rebate_accounts))
+    } else {
+      (other_accounts, None)
+    };
+
+    let (user_accounts, &[ref swap_info_account, ref spl_token_program_acc]) =
+      array_refs![fixed_accounts, 3, 2];
+
+    Self::check_swap_info_not_aliased(swap_info_account, user_accounts)?;
+    let user_args = UserArgs::with_parsed_args(user_accounts)?;
+    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    user_args.check_token_program(&spl_token_program)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(exchanger)?;
+
+    if !user_args.source_account_owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    user_args
+      .token_source_account
+      .check_owner(user_args.source_account_owner.key, false)?;
+
+    match swap_info_args.swap_info.token_account {
+      COption::Some(k) => {
+        if k != *user_args.token_destination_account.pubkey() {
+          return Err(ProtocolError::InvalidTokenAccount.into());
+        }
+      }
+      COption::None => {
+        return Err(ProtocolError::InvalidTokenAccount.into());
+      }
+    };
+    swap_info_args.swap_info.check_swap_in()?;
+
+    let (from_amount_before, _) = user_args.token_source_account.snapshot()?;
+    let (to_amount_before, mid_mint) = user_args.token_destination_account.snapshot()?;
+    msg!(
+      "from_amount_before: {}, to_amount_before: {}, amount_in: {}",
+      from_amount_before,
+      to_amount_before,
+      data.amount_in
+    );
+
+    Self::check_pool_creator(
+      program_id,
+      exchanger,
+      other_accounts,
+      creators_acc,
+      &protocol_config,
+    )?;
+    if let Some([registry_account, _, _]) = rebate_accounts {
+      let pool = Self::check_registered_pool(
+        program_id,
+        registry_account,
+        data.rebate_pool_index,
+        other_accounts,
+      )?;
+      if pool.exchanger != exchanger {
+        return Err(ProtocolError::PoolRegistryMismatch.into());
+      }
+    }
+    let to_amount_include_fee = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => Self::process_step_tokenswap(
+        program_id,
+        exchanger,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+      ),
+      ExchangerType::StableSwap => Self::process_step_stableswap(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => Self::process_step_raydium(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::SerumDex => Self::process_step_serumdex(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        false,
+        None,
+        false,
+        0,
+      ),
+      ExchangerType::CremaFinance => Self::process_step_crema_finance(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+        None,
+      ),
+      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        None,
+      ),
+      ExchangerType::MeteoraDamm => Self::process_step_meteora_damm(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumCpmm => Self::process_step_raydium_cpmm(
+        program_id,
+        data.amount_in.get(),
+        u64::MIN + 1,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+    }?;
+
+    let (from_amount_after, _) = user_args.token_source_account.snapshot()?;
+    let (to_amount_after, _) = user_args.token_destination_account.snapshot()?;
+    msg!(
+      "from_amount_after: {}, to_amount_after: {}",
+      from_amount_after,
+      to_amount_after
+    );
+
+    let from_amount_changed = from_amount_before
+      .checked_sub(from_amount_after)
+      .ok_or(ProtocolError::Overflow)?;
+    msg!("from_amount changed: {}", from_amount_changed);
+    Self::log_partial_execution(data.amount_in.get(), from_amount_changed);
+    msg!("result_with_fee: {}", to_amount_include_fee);
+    let mut swap_info = swap_info_args.swap_info;
+    let rebate = match rebate_accounts {
+      Some([_, vault_account, rebate_acc]) => Self::pay_rebate(
+        program_id,
+        to_amount_include_fee,
+        vault_account,
+        rebate_acc,
+        data.rebate_nonce,
+        user_args.source_account_owner.key,
+        &user_args.token_destination_account,
+        &spl_token_program,
+        &protocol_config,
+      )?,
+      _ => 0,
+    };
+
+    swap_info.token_latest_amount = to_amount_include_fee + rebate;
+    swap_info.mid_mint = COption::Some(mid_mint);
+    swap_info.store(&mut swap_info_args.swap_info_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn process_single_step_swap_out(
+    program_id: &Pubkey,
+    data: &SwapOutInstruction,
+    accounts: &[AccountInfo],
+    exchanger: ExchangerType,
+  ) -> ProgramResult {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 6 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![accounts, 6; ..;];
+    let (other_accounts, refund_acc) = if data.refund_residual() {
+      match other_accounts.split_last() {
+        Some((refund_acc, rest)) => (rest, Some(refund_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, discount_acc) = if data.fee_discount() {
+      match other_accounts.split_last() {
+        Some((discount_acc, rest)) => (rest, Some(discount_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, treasury_acc) = if data.treasury() {
+      match other_accounts.split_last() {
+        Some((treasury_acc, rest)) => (rest, Some(treasury_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (other_accounts, None)
+    };
+    let (other_accounts, minimum_mint_accs) = if data.input_minimum.is_some() {
+      if other_accounts.len() < 2 {
+        return Err(ProtocolError::InvalidAccountsLength.into());
+      }
+      let (rest, mint_accs) = other_accounts.split_at(other_accounts.len() - 2);
+      (rest, Some((&mint_accs[0], &mint_accs[1])))
+    } else {
+      (other_accounts, None)
+    };
+
+    let (
+      user_accounts,
+      &[ref swap_info_account, ref spl_token_program_acc, ref fee_token_account_acc],
+    ) = array_refs![fixed_accounts, 3, 3];
+
+    Self::check_swap_info_not_aliased(swap_info_account, user_accounts)?;
+    let user_args = UserArgs::with_parsed_args(user_accounts)?;
+    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    user_args.check_token_program(&spl_token_program)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(exchanger)?;
+
+    if !user_args.source_account_owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    user_args
+      .token_source_account
+      .check_owner(user_args.source_account_owner.key, false)?;
+
+    if !swap_info_args.swap_info_acc.is_writable {
+      return Err(ProtocolError::ReadonlyAccount.into());
+    }
+    match swap_info_args.swap_info.token_account {
+      COption::Some(k) => {
+        if k != *user_args.token_source_account.pubkey() {
+          return Err(ProtocolError::InvalidTokenAccount.into());
+        }
+      }
+      COption::None => {
+        return Err(ProtocolError::InvalidTokenAccount.into());
+      }
+    };
+    Self::check_intermediate_not_fee_account(
+      user_args.token_source_account,
+      Some(&protocol_config),
+    )?;
+    // the lamports of a closed SwapInfo only ever go back to its owner
+    if data.close_on_complete()
+      && !Self::cmp_pubkeys(
+        &swap_info_args.swap_info.owner,
+        user_args.source_account_owner.key,
+      )
+    {
+      return Err(ProtocolError::InvalidOwner.into());
+    }
+
+    let (from_amount_before, mid_mint) = user_args.token_source_account.snapshot()?;
+    swap_info_args.swap_info.check_mid_mint(&mid_mint)?;
+    let (to_amount_before, destination_token_mint) =
+      user_args.token_destination_account.snapshot()?;
+    // no oracle account on an Out leg, over the threshold the route fails
+    protocol_config.check_expect_oracle(
+      &destination_token_mint,
+      data.expect_amount_out.get(),
+      None,
+    )?;
+
+    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
+    user_args.check_fee_token_account(fee_token_account.pubkey())?;
+    if fee_token_account.mint()? != destination_token_mint {
+      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    }
+    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    }
+
+    if let Some(delegate) = fee_token_account.delegate()? {
+      if delegate == *user_args.source_account_owner.key {
+        return Err(ProtocolError::InvalidFeeTokenAccount.into());
+      }
+    }
+
+    let amount_in = swap_info_args.swap_info.token_latest_amount;
+    let minimum_amount_out = match (data.input_minimum, minimum_mint_accs) {
+      (Some(input_minimum), Some((input_mint_acc, destination_mint_acc))) => {
+        Self::minimum_from_input(
+          data.minimum_amount_out.get(),
+          data.expect_amount_out.get(),
+          &input_minimum,
+          TokenMint::new(input_mint_acc)?,
+          TokenMint::new(destination_mint_acc)?,
+          &destination_token_mint,
+        )?
+      }
+      _ => data.minimum_amount_out.get(),
+    };
+    let amount_out = minimum_amount_out;
+    msg!(
+      "from_amount_before: {}, to_amount_before: {}, amount_in: {}, expect_amount_out: {}, minimum_amount_out: {}",
+      from_amount_before,
+      to_amount_before,
+      amount_in,
+      data.expect_amount_out,
+      minimum_amount_out,
+    );
+
+    Self::check_pool_creator(
+      program_id,
+      exchanger,
+      other_accounts,
+      creators_acc,
+      &protocol_config,
+    )?;
+    let to_amount_include_fee = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => Self::process_step_tokenswap(
+        program_id,
+        exchanger,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+      ),
+      ExchangerType::StableSwap => Self::process_step_stableswap(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => Self::process_step_raydium(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::SerumDex => Self::process_step_serumdex(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        false,
+        None,
+        false,
+        data.client_route_id.unwrap_or(0),
+      ),
+      ExchangerType::CremaFinance => Self::process_step_crema_finance(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+        None,
+      ),
+      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        None,
+      ),
+      ExchangerType::MeteoraDamm => Self::process_step_meteora_damm(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumCpmm => Self::process_step_raydium_cpmm(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+    }?;
+
+    let (from_amount_after, _) = user_args.token_source_account.snapshot()?;
+    let (to_amount_after, _) = user_args.token_destination_account.snapshot()?;
+    msg!(
+      "from_amount_after: {}, to_amount_after: {}",
+      from_amount_after,
+      to_amount_after
+    );
+
+    let from_amount_changed = from_amount_before
+      .checked_sub(from_amount_after)
+      .ok_or(ProtocolError::Overflow)?;
+    msg!("from_amount changed: {}", from_amount_changed);
+    Self::log_partial_execution(amount_in, from_amount_changed);
+    msg!(
+      "result_with_fee: {}, expect: {}, minimum: {}",
+      to_amount_include_fee,
+      data.expect_amount_out,
+      minimum_amount_out,
+    );
+    if to_amount_include_fee == 0 {
+      return Err(ProtocolError::DexSwapError.into());
+    }
+
+    if to_amount_include_fee < minimum_amount_out {
+      return Err(ProtocolError::ExceededSlippage.into());
+    }
+
+    let fee = Self::discounted_fee(
+      Self::output_fee(
+        to_amount_include_fee,
+        data.expect_amount_out.get(),
+        protocol_config.max_surplus_bps,
+      ),
+      discount_acc,
+      user_args.source_account_owner.key,
+      &protocol_config,
+    )?;
+    let fee = protocol_config
+      .round_fee(protocol_config.cap_fee(&user_args.token_destination_account.mint()?, fee));
+    let amount_out = to_amount_include_fee - fee;
+    let fee_with_treasury = fee;
+    let fee = Self::pay_treasury(
+      fee,
+      treasury_acc,
+      &user_args.token_destination_account,
+      user_args.source_account_owner,
+      &spl_token_program,
+      &protocol_config,
+    )?;
+
+    if fee > 0 {
+      Self::token_transfer(
+        spl_token_program.inner(),
+        user_args.token_destination_account.inner(),
+        fee_token_account.inner(),
+        user_args.source_account_owner,
+        fee,
+      )?;
+    }
+    if let Some(refund_acc) = refund_acc {
+      Self::refund_residual(
+        amount_in,
+        from_amount_changed,
+        &user_args.token_source_account,
+        refund_acc,
+        user_args.source_account_owner,
+        &spl_token_program,
+      )?;
+    }
+    if data.close_on_complete() {
+      Self::close_swap_info_account(swap_info_args.swap_info_acc, user_args.source_account_owner)?;
+    } else {
+      let mut swap_info = swap_info_args.swap_info;
+      swap_info.token_latest_amount = to_amount_include_fee;
+      swap_info.token_account = COption::None;
+      swap_info.mid_mint = COption::None;
+
+      swap_info.store(&mut swap_info_args.swap_info_acc.data.borrow_mut())?;
+    }
+    Self::set_swap_return_data(
+      data.client_route_id,
+      &FeeBreakdown {
+        gross_output: to_amount_include_fee,
+        protocol_fee: fee,
+        referrer_fee: 0,
+        treasury_fee: fee_with_treasury - fee,
+        net_output: amount_out,
+      },
+    );
+    Ok(())
+  }
+
+  pub fn process_single_step_swap_out_slim(
+    program_id: &Pubkey,
+    data: &SwapOutSlimInstruction,
+    accounts: &[AccountInfo],
+    exchanger: ExchangerType,
+  ) -> ProgramResult {
+    let (config_args, accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, accounts) = Self::split_trusted_pool_creators(accounts, program_id);
+    if accounts.len() < 6 {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![accounts, 6; ..;];
+
+    let (
+      user_accounts,
+      &[ref swap_info_account, ref spl_token_program_acc, ref fee_token_account_acc],
+    ) = array_refs![fixed_accounts, 3, 3];
+
+    Self::check_swap_info_not_aliased(swap_info_account, user_accounts)?;
+    let user_args = UserArgs::with_parsed_args(user_accounts)?;
+    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    user_args.check_token_program(&spl_token_program)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(exchanger)?;
+
+    if !user_args.source_account_owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    user_args
+      .token_source_account
+      .check_owner(user_args.source_account_owner.key, false)?;
+
+    if !swap_info_args.swap_info_acc.is_writable {
+      return Err(ProtocolError::ReadonlyAccount.into());
+    }
+    match swap_info_args.swap_info.token_account {
+      COption::Some(k) => {
+        if k != *user_args.token_source_account.pubkey() {
+          return Err(ProtocolError::InvalidTokenAccount.into());
+        }
+      }
+      COption::None => {
+        return Err(ProtocolError::InvalidTokenAccount.into());
+      }
+    };
+
+    let (from_amount_before, mid_mint) = user_args.token_source_account.snapshot()?;
+    swap_info_args.swap_info.check_mid_mint(&mid_mint)?;
+    let (to_amount_before, destination_token_mint) =
+      user_args.token_destination_account.snapshot()?;
+    // the slim fee is taken over `minimum_amount_out`, held to the same
+    // threshold as an expectation
+    protocol_config.check_expect_oracle(
+      &destination_token_mint,
+      data.minimum_amount_out.get(),
+      None,
+    )?;
+
+    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
+    user_args.check_fee_token_account(fee_token_account.pubkey())?;
+    if fee_token_account.mint()? != destination_token_mint {
+      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    }
+    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
+      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    }
+
+    if let Some(delegate) = fee_token_account.delegate()? {
+      if delegate == *user_args.source_account_owner.key {
+        return Err(ProtocolError::InvalidFeeTokenAccount.into());
+      }
+    }
+
+    let amount_in = swap_info_args.swap_info.token_latest_amount;
+    let amount_out = data.minimum_amount_out.get();
+    msg!(
+      "from_amount_before: {}, to_amount_before: {}, amount_in: {}, minimum_amount_out: {}",
+      from_amount_before,
+      to_amount_before,
+      amount_in,
+      data.minimum_amount_out,
+    );
+
+    Self::check_pool_creator(
+      program_id,
+      exchanger,
+      other_accounts,
+      creators_acc,
+      &protocol_config,
+    )?;
+    let to_amount_include_fee = match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => Self::process_step_tokenswap(
+        program_id,
+        exchanger,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+      ),
+      ExchangerType::StableSwap => Self::process_step_stableswap(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => Self::process_step_raydium(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::SerumDex => Self::process_step_serumdex(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        false,
+        None,
+        false,
+        0,
+      ),
+      ExchangerType::CremaFinance => Self::process_step_crema_finance(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        0,
+        None,
+        None,
+      ),
+      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+        None,
+      ),
+      ExchangerType::MeteoraDamm => Self::process_step_meteora_damm(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+      ExchangerType::RaydiumCpmm => Self::process_step_raydium_cpmm(
+        program_id,
+        amount_in,
+        amount_out,
+        &user_args.token_source_account,
+        &user_args.token_destination_account,
+        user_args.source_account_owner,
+        &spl_token_program,
+        other_accounts,
+        &protocol_config,
+      ),
+    }?;
+
+    let (from_amount_after, _) = user_args.token_source_account.snapshot()?;
+    let (to_amount_after, _) = user_args.token_destination_account.snapshot()?;
+    msg!(
+      "from_amount_after: {}, to_amount_after: {}",
+      from_amount_after,
+      to_amount_after
+    );
+
+    let from_amount_changed = from_amount_before
+      .checked_sub(from_amount_after)
+      .ok_or(ProtocolError::Overflow)?;
+    msg!("from_amount changed: {}", from_amount_changed);
+    Self::log_partial_execution(amount_in, from_amount_changed);
+    msg!(
+      "result_with_fee: {}, minimum: {}",
+      to_amount_include_fee,
+      data.minimum_amount_out,
+    );
+    if to_amount_include_fee == 0 {
+      return Err(ProtocolError::DexSwapError.into());
+    }
+
+    if to_amount_include_fee < data.minimum_amount_out.get() {
+      return Err(ProtocolError::ExceededSlippage.into());
+    }
+
+    let fee = Self::slim_fee(to_amount_include_fee, data.minimum_amount_out.get())?;
+
+    if fee > 0 {
+      Self::token_transfer(
+        spl_token_program.inner(),
+        user_args.token_destination_account.inner(),
+        fee_token_account.inner(),
+        user_args.source_account_owner,
+        fee,
+      )?;
+    }
+    let mut swap_info = swap_info_args.swap_info;
+    swap_info.token_latest_amount = to_amount_include_fee;
+    swap_info.token_account = COption::None;
+    swap_info.mid_mint = COption::None;
+
+    swap_info.store(&mut swap_info_args.swap_info_acc.data.borrow_mut())?;
+    Ok(())
+  }
+
+  /// Logs when the exchanger took less than requested, either because
+  /// `get_amount_in` clamped it to the source balance or the DEX left a rest.
+  fn log_partial_execution(requested: u64, executed: u64) {
+    if executed < requested {
+      msg!(
+        "partial execution, requested: {}, executed: {}",
+        requested,
+        executed
+      );
+    }
+  }
+
+  /// Swaps both legs of a route through a transient intermediate token
+  /// account created and closed within the instruction.
+  pub fn process_swap_transient_route(
+    program_id: &Pubkey,
+    data: &TransientRouteInstruction,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let (config_args, swap_accounts) = ProtocolConfigArgs::split_trailing(accounts, program_id)?;
+    let (creators_acc, swap_accounts) =
+      Self::split_trusted_pool_creators(swap_accounts, program_id);
+    if swap_accounts.len() < 8 + data.in_accounts_len as usize {
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (fixed_accounts, other_accounts) = array_refs![swap_accounts, 8; ..;];
+    let (in_accounts, out_accounts) = other_accounts.split_at(data.in_accounts_len as usize);
+    #[rustfmt::skip]
+    let (
+      user_accounts,
+      &[ref transient_acc, ref mid_mint_acc, ref spl_token_program_acc, ref system_program_acc, ref fee_token_account_acc],
+    ) = array_refs![fixed_accounts, 3, 5];
+
+    let user_args = UserArgs::with_parsed_args(user_accounts)?;
+    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+    user_args.check_token_program(&spl_token_program)?;
+    let protocol_config = config_args.config;
+    protocol_config.check_exchanger(data.exchanger_in)?;
+    protocol_config.check_exchanger(data.exchanger_out)?;
+    Self::check_route_accounts(program_id, accounts, &protocol_config)?;
+    let (out_accounts, treasury_acc) = Self::split_treasury(out_accounts, &protocol_config)?;
+    let (out_accounts, discount_acc) = if data.fee_discount() {
+      match out_accounts.split_last() {
+        Some((discount_acc, rest)) => (rest, Some(discount_acc)),
+        None => return Err(ProtocolError::InvalidAccountsLength.into()),
+      }
+    } else {
+      (out_accounts, None)
+    };
+    let owner = user_args.source_account_owner;
+    if !owner.is_signer {
+      return Err(ProtocolError::InvalidSignerAccount.into());
+    }
+    user_args
+      .token_source_account
+      .check_owner(owner.key, false)?;
+    if *system_program_acc.key != system_program::id() {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+
+    let (from_amount_before, source_token_mint) = user_args.token_source_account.snapshot()?;
+    let destination_token_mint = user_args.token_destination_account.mint()?;
+    // no oracle account on a transient route, over the threshold it fails
+    protocol_config.check_expect_oracle(
+      &destination_token_mint,
+      data.expect_amount_out.get(),
+      None,
+    )?;
+    let mid_mint = TokenMint::new(mid_mint_acc)?;
+    if *mid_mint.pubkey() == source_token_mint || *mid_mint.pubkey() == destination_token_mint {
+      return Err(ProtocolError::InvalidTokenMint.into());
+    }
+    let (transient_key, transient_bump) =
+      find_transient_address(owner.key, mid_mint.pubkey(), program_id);
+    if transient_key != *transient_acc.key {
+      return Err(ProtocolError::InvalidProgramAddress.into());
+    }
+    let leg_pools = [
+      Self::pool_mints(data.exchanger_in, in_accounts)
+        .map_err(|e| ProtocolError::in_leg(0, e.into()))?,
+      Self::pool_mints(data.exchanger_out, out_accounts)
+        .map_err(|e| ProtocolError::in_leg(1, e.into()))?,
+    ];
+    Self::check_route_mints(
+      &[
+        (source_token_mint, *mid_mint.pubkey()),
+        (*mid_mint.pubkey(), destination_token_mint),
+      ],
+      &leg_pools,
+      false,
+    )?;
+
+    msg!("spl_token.create_transient_account");
+    Self::create_pda_account(
+      owner,
+      transient_acc,
+      system_program_acc,
+      spl_token::ACCOUNT_LEN,
+      spl_token_program.pubkey(),
+      &[
+        TRANSIENT_SEED,
+        owner.key.as_ref(),
+        mid_mint.pubkey().as_ref(),
+        &[transient_bump],
+      ],
+    )?;
+    invoke(
+      &spl_token::instruction::initialize_account3(
+        spl_token_program.pubkey(),
+        transient_acc.key,
+        mid_mint.pubkey(),
+        owner.key,
+      )?,
+      &[
+        transient_acc.clone(),
+        mid_mint.inner().clone(),
+        spl_token_program.inner().clone(),
+      ],
+    )?;
+    let transient = TokenAccount::new(transient_acc)?;
+
+    let amount_in = Self::get_amount_in(data.amount_in.get(), from_amount_before);
+    let mid_amount = Self::process_step(
+      program_id,
+      data.exchanger_in,
+      amount_in,
+      1,
+      &user_args.token_source_account,
+      &transient,
+      owner,
+      &spl_token_program,
+      in_accounts,
+      &protocol_config,
+      creators_acc,
+    )
+    .map_err(|e| ProtocolError::in_leg(0, e))?;
+    msg!("transient amount: {}", mid_amount);
+    let to_amount_include_fee = Self::process_step(
+      program_id,
+      data.exchanger_out,
+      mid_amount,
+      data.minimum_amount_out.get(),
+      &transient,
+      &user_args.token_destination_account,
+      owner,
+      &spl_token_program,
+      out_accounts,
+      &protocol_config,
+      creators_acc,
+    )
+    .map_err(|e| ProtocolError::in_leg(1, e))?;
+    if to_amount_include_fee == 0 {
+      return Err(ProtocolError::DexSwapError.into());
+    }
+    if to_amount_include_fee < data.minimum_amount_out.get() {
+      return Err(ProtocolError::ExceededSlippage.into());
+    }
+
+    let fee = Self::discounted_fee(
+      Self::output_fee(
+        to_amount_include_fee,
+        data.expect_amount_out.get(),
+        protocol_config.max_surplus_bps,
+      ),
+      discount_acc,
+      owner.key,
+      &protocol_config,
+    )?;
+    let fee = protocol_config.round_fee(protocol_config.cap_fee(&destination_token_mint, fee));
+    let fee = Self::pay_treasury(
+      fee,
+      treasury_acc,
+      &user_args.token_destination_account,
+      owner,
+      &spl_token_program,
+      &protocol_config,
+    )?;
+    if fee > 0 {
+      let fee_token_account =
+        Self::parse_fee_token_account(fee_token_account_acc, &destination_token_mint, &user_args)?;
+      Self::charge_fee(
+        program_id,
+        fee,
+        (amount_in, to_amount_include_fee),
+        &user_args.token_destination_account,
+        &fee_token_account,
+        owner,
+        &spl_token_program,
+        &[],
+        &protocol_config,
+      )?;
+    }
+
+    // a residual would be lost with the account, fail the whole route instead
+    if transient.balance()? != 0 {
+      return Err(ProtocolError::TransientAccountNotEmpty.into());
+    }
+    invoke(
+      &spl_token::instruction::close_account(
+        spl_token_program.pubkey(),
+        transient_acc.key,
+        owner.key,
+        owner.key,
+        &[],
+      )?,
+      &[
+        transient_acc.clone(),
+        owner.clone(),
+        spl_token_program.inner().clone(),
+      ],
+    )?;
+    Ok(())
+  }
+
+  /// Swaps through `exchanger` without the per-swap options of the direct
+  /// swap, returns the output amount. The pool creator is checked against
+  /// `creators_acc` when passed, see `check_pool_creator`.
+  #[allow(clippy::too_many_arguments)]
+  fn process_step<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    exchanger: ExchangerType,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+    creators_acc: Option<&AccountInfo>,
+  ) -> Result<u64, ProgramError> {
+    Self::check_pool_creator(
+      program_id,
+      exchanger,
+      accounts,
+      creators_acc,
+      protocol_config,
+    )?;
+    match exchanger {
+      ExchangerType::SplTokenSwap | ExchangerType::OrcaV1 => Self::process_step_tokenswap(
+        program_id,
+        exchanger,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+        0,
+        None,
+      ),
+      ExchangerType::StableSwap => Self::process_step_stableswap(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+      ),
+      ExchangerType::RaydiumSwap | ExchangerType::RaydiumSwapSlim => Self::process_step_raydium(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+      ),
+      ExchangerType::SerumDex => Self::process_step_serumdex(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+        false,
+        None,
+        false,
+        0,
+      ),
+      ExchangerType::CremaFinance => Self::process_step_crema_finance(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+      ),
+      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+        0,
+        None,
+        None,
+      ),
+      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+        None,
+      ),
+      ExchangerType::MeteoraDamm => Self::process_step_meteora_damm(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+      ),
+      ExchangerType::RaydiumCpmm => Self::process_step_raydium_cpmm(
+        program_id,
+        amount_in,
+        minimum_amount_out,
+        source_token_account,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        accounts,
+        protocol_config,
+      ),
+    }
+  }
+
+  /// Sends what is left of `amount_in` in the intermediate account to
+  /// `refund_acc`, returns the refunded amount.
+  fn refund_residual<'a, 'b: 'a>(
+    amount_in: u64,
+    from_amount_changed: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    refund_acc: &'a AccountInfo<'b>,
+    authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+  ) -> Result<u64, ProgramError> {
+    let refund_account = TokenAccount::new(refund_acc)?;
+    if refund_account.pubkey() == source_token_account.pubkey()
+      || refund_account.mint()? != source_token_account.mint()?
+    {
+      return Err(ProtocolError::InvalidTokenAccount.into());
+    }
+    let residual = amount_in
+      .saturating_sub(from_amount_changed)
+      .min(source_token_account.balance()?);
+    if residual > 0 {
+      msg!("refund residual: {}", residual);
+      Self::token_transfer(
+        spl_token_program.inner(),
+        source_token_account.inner(),
+        refund_acc,
+        authority,
+        residual,
+      )?;
+    }
+    Ok(residual)
+  }
+
+  /// Compute units of one `process_step_*` call, CPI included, as logged by
+  /// `sol_log_compute_units` around the step. Approximate, clients should
+  /// keep some margin on top.
+  fn estimate_compute_units(data: &EstimateComputeUnitsInstruction) -> u32 {
+    match (data.exchanger, data.reverse) {
+      (ExchangerType::SplTokenSwap, _) => 45_000,
+      (ExchangerType::SerumDex, false) => 95_000,
+      (ExchangerType::SerumDex, true) => 100_000,
+      (ExchangerType::StableSwap, _) => 55_000,
+      (ExchangerType::RaydiumSwap, _) => 70_000,
+      (ExchangerType::RaydiumSwapSlim, _) => 65_000,
+      (ExchangerType::CremaFinance, _) => 60_000,
+      (ExchangerType::AldrinExchange, _) => 60_000,
+      (ExchangerType::CropperFinance, _) => 50_000,
+      (ExchangerType::MeteoraDamm, _) => 70_000,
+      (ExchangerType::RaydiumCpmm, _) => 70_000,
+      (ExchangerType::OrcaV1, _) => 45_000,
+    }
+  }
+
+  /// Rejects swaps whose clamped `amount_in` is below the minimum of the
+  /// config for the source mint, or else the instruction minimum.
+  fn check_min_notional(
+    amount_in: u64,
+    source_token_balance: u64,
+    source_token_mint: &Pubkey,
+    min_notional_in: u64,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<(), ProtocolError> {
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+    let min_notional_in = protocol_config
+      .min_notional_in(source_token_mint)
+      .unwrap_or(min_notional_in);
+    if amount_in < min_notional_in {
+      msg!(
+        "amount_in {} below min notional {}",
+        amount_in,
+        min_notional_in
+      );
+      return Err(ProtocolError::InvalidInput);
+    }
+    Ok(())
+  }
+
+  /// Rejects a destination token account not owned by the signer when the
+  /// swap opted in, a delegate of the destination is not its owner.
+  fn check_destination_owner(
+    destination_token_account: &TokenAccount,
+    signer: &Pubkey,
+    required: bool,
+  ) -> ProtocolResult<()> {
+    if !required {
+      return Ok(());
+    }
+    destination_token_account.check_owner(signer, true)
+  }
+
+  /// Rejects a serum or aldrin step whose side, derived from the source mint,
+  /// is not the one the router expects (0 bid, 1 ask).
+  fn check_expected_side(expected_side: Option<u8>, side: u8) -> Result<(), ProtocolError> {
+    match expected_side {
+      Some(expected) if expected != side => {
+        msg!("side {}, expected: {}", side, expected);
+        Err(ProtocolError::MintRoutingMismatch)
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Rejects a serum fill on which the market accrued more than its fee rate.
+  /// `settled_pc` is the quote the owner received (ask) or spent (bid), fees
+  /// included, and `fees_accrued` the market `pc_fees_accrued` delta.
+  fn check_serum_settle(
+    side: DexSide,
+    settled_pc: u64,
+    fees_accrued: u64,
+    fee_rate_bps: u64,
+  ) -> Result<(), ProtocolError> {
+    let fill_pc = match side {
+      DexSide::Bid => settled_pc.checked_sub(fees_accrued),
+      DexSide::Ask => settled_pc.checked_add(fees_accrued),
+    }
+    .ok_or(ProtocolError::DexSwapError)?;
+    let fee_rate_bps = if fee_rate_bps == 0 {
+      SERUM_BASE_TAKER_FEE_BPS
+    } else {
+      fee_rate_bps
+    };
+    // round up, the dex charges at least one native unit per fill
+    let max_fee = (fill_pc as u128 * fee_rate_bps as u128 + 9_999) / 10_000;
+    if fees_accrued as u128 > max_fee {
+      msg!(
+        "serum fees: {}, fill: {}, max: {}",
+        fees_accrued,
+        fill_pc,
+        max_fee
+      );
+      return Err(ProtocolError::DexSwapError);
+    }
+    Ok(())
+  }
+
+  /// Rejects a step whose output falls more than `max_price_impact_bps` below
+  /// the spot price of `pool_reserves`, read before the swap.
+  fn check_price_impact(
+    pool_reserves: Option<(u64, u64)>,
+    amount_in: u64,
+    amount_out: u64,
+    max_price_impact_bps: u16,
+  ) -> Result<(), ProtocolError> {
+    let (source_reserve, destination_reserve) = match pool_reserves {
+      Some(r) if max_price_impact_bps != 0 => r,
+      _ => return Ok(()),
+    };
+    let impact =
+      reserves::price_impact_bps(source_reserve, destination_reserve, amount_in, amount_out)?;
+    if impact > max_price_impact_bps as u64 {
+      msg!("price impact {} bps, max: {}", impact, max_price_impact_bps);
+      return Err(ProtocolError::ExceededSlippage);
+    }
+    Ok(())
+  }
+
+  /// Rejects a step whose pool spot price, read before the swap, drifted more
+  /// than `max_drift_bps` from the price the client quoted against.
+  fn check_reference_price(
+    pool_reserves: Option<(u64, u64)>,
+    reference_price: Option<ReferencePrice>,
+  ) -> Result<(), ProtocolError> {
+    let ((source_reserve, destination_reserve), reference) = match (pool_reserves, reference_price)
+    {
+      (Some(r), Some(reference)) => (r, reference),
+      _ => return Ok(()),
+    };
+    // spot `destination_reserve / source_reserve` against `num / den`
+    let spot = destination_reserve as u128 * reference.den as u128;
+    let expected = source_reserve as u128 * reference.num as u128;
+    let drift = if spot > expected {
+      spot - expected
+    } else {
+      expected - spot
+    };
+    // an empty pool or products too large to compare are refused
+    let drift_bps = drift
+      .checked_mul(10_000)
+      .and_then(|drift| drift.checked_div(expected))
+      .unwrap_or(u128::MAX);
+    if drift_bps > reference.max_drift_bps as u128 {
+      msg!(
+        "pool price {}/{}, reference: {}/{}",
+        destination_reserve,
+        source_reserve,
+        reference.num,
+        reference.den
+      );
+      return Err(ProtocolError::PriceDeviationTooHigh);
+    }
+    Ok(())
+  }
+
+  /// Rejects an `amount_in` larger than the whole supply of the source mint,
+  /// usually an amount scaled with the wrong decimals.
+  fn check_amount_in_supply(
+    amount_in: u64,
+    source_token_account: &TokenAccount,
+    source_mint: TokenMint,
+  ) -> Result<(), ProtocolError> {
+    if source_token_account.mint()? != *source_mint.pubkey() {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+    if amount_in > source_mint.supply()? {
+      msg!(
+        "amount_in: {}, source mint supply: {}",
+        amount_in,
+        source_mint.supply()?
+      );
+      return Err(ProtocolError::InvalidInput);
+    }
+    Ok(())
+  }
+
+  /// Rejects an `expect_amount_out` over the config `max_expect_out_ratio`
+  /// times `amount_in`, each token account passed with its mint.
+  fn check_expect_out_ratio(
+    amount_in: u64,
+    expect_amount_out: u64,
+    (source_token_account, source_mint): (&TokenAccount, TokenMint),
+    (destination_token_account, destination_mint): (&TokenAccount, TokenMint),
+    protocol_config: &ProtocolConfig,
+  ) -> Result<(), ProtocolError> {
+    if source_token_account.mint()? != *source_mint.pubkey()
+      || destination_token_account.mint()? != *destination_mint.pubkey()
+    {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+    protocol_config.check_expect_out_ratio(
+      amount_in,
+      source_mint.decimals()?,
+      expect_amount_out,
+      destination_mint.decimals()?,
+    )
+  }
+
+  /// Minimum output of a swap out leg, the larger of `minimum_amount_out` and
+  /// `input_minimum` rescaled from the route input mint to the destination
+  /// mint. Like the minimum field, it must stay within `expect_amount_out`.
+  fn minimum_from_input(
+    minimum_amount_out: u64,
+    expect_amount_out: u64,
+    input_minimum: &InputMinimum,
+    input_mint: TokenMint,
+    destination_mint: TokenMint,
+    destination_token_mint: &Pubkey,
+  ) -> Result<u64, ProtocolError> {
+    if destination_mint.pubkey() != destination_token_mint {
+      return Err(ProtocolError::InvalidTokenMint);
+    }
+    let minimum_from_input = stable_swap::curve::minimum_out_from_bps(
+      input_minimum.amount_in.get(),
+      input_mint.decimals()?,
+      destination_mint.decimals()?,
+      input_minimum.min_out_bps_of_in,
+    )
+    .ok_or(ProtocolError::InvalidInput)?;
+    msg!(
+      "input: {}, minimum from input: {}",
+      input_minimum.amount_in,
+      minimum_from_input
+    );
+    if minimum_from_input > expect_amount_out {
+      return Err(ProtocolError::InvalidExpectAmountOut);
+    }
+    Ok(minimum_amount_out.max(minimum_from_input))
+  }
+
+  /// Fee on the source amount with `SWAP_FLAG_FEE_ON_INPUT`.
+  fn input_fee(amount_in: u64) -> u64 {
+    (amount_in as u128 * INPUT_FEE_BPS as u128 / 10_000) as u64
+  }
+
+  /// Default fee, a quarter of the output above `expect_amount_out`. A
+  /// non-zero `max_surplus_bps` caps the surplus at that share of
+  /// `expect_amount_out`, so a too low expectation can't inflate the fee.
+  fn output_fee(amount_out: u64, expect_amount_out: u64, max_surplus_bps: u16) -> u64 {
+    let amount_out = if max_surplus_bps == 0 {
+      amount_out
+    } else {
+      let bound = expect_amount_out as u128 * (10_000 + max_surplus_bps as u128) / 10_000;
+      cmp::min(amount_out as u128, bound) as u64
+    };
+    // the fee is at most the surplus, the u128 product can't overflow and the
+    // quotient always fits back in a u64
+    amount_out
+      .checked_sub(expect_amount_out)
+      .map(|v| (v as u128 * OUTPUT_FEE_NUMERATOR as u128 / OUTPUT_FEE_DENOMINATOR as u128) as u64)
+      .unwrap_or(0)
+  }
+
+  /// Applies the config holder discount to `fee` when the owner passed a
+  /// token account of the discount mint. Fee-exempt owners pay nothing.
+  fn discounted_fee(
+    fee: u64,
+    discount_acc: Option<&AccountInfo>,
+    owner: &Pubkey,
+    protocol_config: &ProtocolConfig,
+  ) -> ProtocolResult<u64> {
+    if protocol_config.is_fee_exempt(owner) {
+      return Ok(0);
+    }
+    let discount_acc = match discount_acc {
+      Some(discount_acc) => TokenAccount::new(discount_acc)?,
+      None => return Ok(fee),
+    };
+    discount_acc.check_owner(owner, true)?;
+    let (balance, mint) = discount_acc.snapshot()?;
+    protocol_config.discounted_fee(fee, &mint, balance)
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  /// Credits the config rebate of an In leg `amount_out` to `destination` out
+  /// of `rebate_acc`, the token account of the DESTINATION mint rebate vault.
+  /// Limited to the vault cap left to `owner` in this slot and to the
+  /// rebate account balance, a drained account doesn't fail the swap.
+  fn pay_rebate<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_out: u64,
+    vault_account: &'a AccountInfo<'b>,
+    rebate_acc: &'a AccountInfo<'b>,
+    nonce: u8,
+    owner: &Pubkey,
+    destination: &TokenAccount<'a, 'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    if *vault_account.owner != *program_id {
+      return Err(ProtocolError::InvalidRebateAccount.into());
+    }
+    let mint = destination.mint()?;
+    let mut vault = RebateVault::unpack(&vault_account.data.borrow())
+      .map_err(|_| ProtocolError::InvalidRebateAccount)?;
+    vault.check(&mint, rebate_acc.key)?;
+    let vault_key =
+      Pubkey::create_program_address(&[REBATE_VAULT_SEED, mint.as_ref(), &[nonce]], program_id)
+        .map_err(|_| ProtocolError::InvalidRebateAccount)?;
+    let rebate_account = TokenAccount::new(rebate_acc)?;
+    if vault_key != *vault_account.key || rebate_account.owner()? != vault_key {
+      return Err(ProtocolError::InvalidRebateAccount.into());
+    }
+    let rebate = cmp::min(
+      protocol_config.rebate(amount_out),
+      rebate_account.balance()?,
+    );
+    let rebate = vault.take_rebate(owner, rebate, Clock::get()?.slot);
+    RebateVault::pack(vault, &mut vault_account.data.borrow_mut())?;
+    if rebate > 0 {
+      msg!("rebate: {}", rebate);
+      let ix = spl_token::instruction::transfer(
+        spl_token_program.inner().key,
+        rebate_acc.key,
+        destination.inner().key,
+        vault_account.key,
+        &[],
+        rebate,
+      )?;
+      invoke_signed(
+        &ix,
+        &[
+          rebate_acc.clone(),
+          destination.inner().clone(),
+          vault_account.clone(),
+          spl_token_program.inner().clone(),
+        ],
+        &[&[REBATE_VAULT_SEED, mint.as_ref(), &[nonce]]],
+      )?;
+    }
+    Ok(rebate)
+  }
+
+  /// `SWAP_FLAG_TREASURY` once the config has a treasury, for the swaps
+  /// that forward the treasury token account to a direct swap.
+  fn treasury_flag(protocol_config: &ProtocolConfig) -> u8 {
+    if protocol_config.treasury_token_account.is_some() {
+      SWAP_FLAG_TREASURY
+    } else {
+      0
+    }
+  }
+
+  /// Splits the treasury token account off the end of `accounts` once the
+  /// config has a treasury.
+  fn split_treasury<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> ProtocolResult<(&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>)> {
+    if protocol_config.treasury_token_account.is_none() {
+      return Ok((accounts, None));
+    }
+    match accounts.split_last() {
+      Some((treasury_acc, rest)) => Ok((rest, Some(treasury_acc))),
+      None => Err(ProtocolError::InvalidAccountsLength),
+    }
+  }
+
+  /// Sends the config `treasury_bps` share of `fee` from `fee_source` to the
+  /// treasury token account and returns the rest, all of `fee` when the
+  /// config has no treasury. A zero fee skips the treasury mint check.
+  fn pay_treasury<'a, 'b: 'a>(
+    fee: u64,
+    treasury_acc: Option<&'a AccountInfo<'b>>,
+    fee_source: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let treasury_acc = match (protocol_config.treasury_token_account, treasury_acc) {
+      (COption::None, None) => return Ok(fee),
+      (COption::Some(ref key), Some(treasury_acc)) if key == treasury_acc.key => treasury_acc,
+      _ => return Err(ProtocolError::InvalidTreasuryAccount.into()),
+    };
+    if fee == 0 {
+      return Ok(0);
+    }
+    let treasury = TokenAccount::new(treasury_acc)?;
+    if treasury.mint()? != fee_source.mint()? {
+      return Err(ProtocolError::InvalidTokenMint.into());
+    }
+    let share = protocol_config.treasury_share(fee);
+    if share > 0 {
+      msg!("treasury fee: {}", share);
+      Self::token_transfer(
+        spl_token_program.inner(),
+        fee_source.inner(),
+        treasury.inner(),
+        source_account_authority,
+        share,
+      )?;
+    }
+    Ok(fee - share)
+  }
+
+  /// Sets `[client_route_id, net_output]` as the return data, little endian,
+  /// when the client passed a route id, the packed `FeeBreakdown` otherwise.
+  /// Called last, an exchanger CPI would overwrite it.
+  fn set_swap_return_data(client_route_id: Option<u64>, breakdown: &FeeBreakdown) {
+    match client_route_id {
+      Some(client_route_id) => {
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&client_route_id.to_le_bytes());
+        data[8..].copy_from_slice(&breakdown.net_output.to_le_bytes());
+        set_return_data(&data);
+      }
+      None => set_return_data(&breakdown.pack()),
+    }
+  }
+
+  /// Moves `fee` from the user's destination account to `fee_token_account`,
+  /// swapping it through the spl-token-swap `fee_pool_accounts` when the fee
+  /// account holds another mint. The fee pool is the caller's pick, the fee
+  /// must swap at no worse than `FEE_SWAP_TOLERANCE_BPS` under the
+  /// `(amount_in, amount_out)` price the swap itself got.
+  #[allow(clippy::too_many_arguments)]
+  fn charge_fee<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    fee: u64,
+    realized_price: (u64, u64),
+    destination_token_account: &TokenAccount<'a, 'b>,
+    fee_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    fee_pool_accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> ProgramResult {
+    if fee_token_account.mint()? == destination_token_account.mint()? {
+      return Self::token_transfer(
+        spl_token_program.inner(),
+        destination_token_account.inner(),
+        fee_token_account.inner(),
+        source_account_authority,
+        fee,
+      );
+    }
+    let minimum_fee_out = Self::fee_swap_minimum_out(fee, realized_price)?;
+    let fee_out = Self::process_step_tokenswap(
+      program_id,
+      ExchangerType::SplTokenSwap,
+      fee,
+      minimum_fee_out,
+      destination_token_account,
+      fee_token_account,
+      source_account_authority,
+      spl_token_program,
+      fee_pool_accounts,
+      protocol_config,
+      0,
+      None,
+    )?;
+    msg!("fee: {}, swapped to reference mint: {}", fee, fee_out);
+    if fee_out < minimum_fee_out {
+      return Err(ProtocolError::ExceededSlippage.into());
+    }
+    Ok(())
+  }
+
+  /// Smallest reference mint amount `fee` may swap into: the fee priced at
+  /// the `(amount_in, amount_out)` of the swap, source mint per destination
+  /// mint, less `FEE_SWAP_TOLERANCE_BPS`. At least 1.
+  fn fee_swap_minimum_out(fee: u64, (amount_in, amount_out): (u64, u64)) -> ProtocolResult<u64> {
+    let minimum = (fee as u128)
+      .checked_mul(amount_in as u128)
+      .and_then(|v| v.checked_mul((10_000 - FEE_SWAP_TOLERANCE_BPS) as u128))
+      .and_then(|v| v.checked_div(amount_out as u128 * 10_000))
+      .filter(|v| *v <= u64::MAX as u128)
+      .ok_or(ProtocolError::Overflow)?;
+    Ok(cmp::max(minimum as u64, 1))
+  }
+
+  /// Step swap in spl-token-swap, or in an Orca v1 pool for `OrcaV1`
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_tokenswap<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    exchanger: ExchangerType,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+    max_price_impact_bps: u16,
+    reference_price: Option<ReferencePrice>,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    msg!(
+      "swap using token-swap, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let spl_token_swap_args = SplTokenSwapArgs::with_parsed_exchanger_args(accounts, exchanger)?;
+    protocol_config.check_pool(spl_token_swap_args.swap_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let token_swap_amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    let destination_token_mint = destination_token_account.mint()?;
+
+    let (pool_source_token_acc, pool_destination_token_acc) =
+      spl_token_swap_args.find_token_pair(&source_token_mint)?;
+    let pool_reserves = if max_price_impact_bps != 0 || reference_price.is_some() {
+      Some(spl_token_swap_args.reserves(&source_token_mint, &destination_token_mint)?)
+    } else {
+      None
+    };
+    Self::check_reference_price(pool_reserves, reference_price)?;
+
+    if pool_source_token_acc.mint()? != source_token_mint {
+      return Err(ProtocolError::MintRoutingMismatch.into());
+    }
+    if pool_destination_token_acc.mint()? != destination_token_mint {
+      return Err(ProtocolError::MintRoutingMismatch.into());
+    }
+
+    let host_fee_account = match exchanger {
+      // orca pools have no host fee to collect
+      ExchangerType::OrcaV1 => None,
+      _ if protocol_config.collect_host_fee => {
+        let host_fee_owner =
+          Pubkey::from_str(OWNER_KEY).map_err(|_| ProtocolError::InvalidOwnerKey)?;
+        spl_token_swap_args.protocol_host_fee_account(&host_fee_owner)?
+      }
+      _ => spl_token_swap_args.host_fee_account,
+    };
+
+    let (instruction, swap_accounts) = Self::spl_token_swap_cpi(
+      spl_token_swap_args.program,
+      spl_token_program.inner(),
+      spl_token_swap_args.swap_info.inner(),
+      spl_token_swap_args.authority_acc_info,
+      source_account_authority,
+      source_token_account.inner(),
+      pool_source_token_acc.inner(),
+      pool_destination_token_acc.inner(),
+      destination_token_account.inner(),
+      spl_token_swap_args.pool_mint.inner(),
+      spl_token_swap_args.fee_account.inner(),
+      host_fee_account.map(|acc| acc.inner()),
+      token_swap_amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke spl-token-swap swap");
+    invoke(&instruction, &swap_accounts)?;
+    let amount_out = destination_token_account.amount_delta(destination_amount_before)?;
+    Self::check_price_impact(
+      pool_reserves,
+      token_swap_amount_in,
+      amount_out,
+      max_price_impact_bps,
+    )?;
+    Ok(amount_out)
+  }
+
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_serumdex<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+    cancel_resting_order: bool,
+    expected_side: Option<u8>,
+    check_settle: bool,
+    client_order_id: u64,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    let source_amount_before = source_token_account.balance()?;
+    let dex_args = SerumDexArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(dex_args.market.pubkey())?;
+    let pc_fees_before = dex_args.market.pc_fees_accrued()?;
+
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    let side = dex_args.find_side(&source_token_mint)?;
+    Self::check_expected_side(expected_side, side as u8)?;
+    // under one lot the order would be empty
+    let min_amount_in = dex_args.market.min_amount_in(side)?;
+    if amount_in < min_amount_in {
+      msg!("amount_in: {}, one lot: {}", amount_in, min_amount_in);
+      return Err(ProtocolError::InvalidInput.into());
+    }
+
+    let (pc_wallet_account, coin_wallet_account) = match side {
+      DexSide::Bid => (source_token_account, destination_token_account),
+      DexSide::Ask => (destination_token_account, source_token_account),
+    };
+
+    let authority_bump = match dex_args.open_orders_pda {
+      Some(pda) => Some(Self::prepare_serum_open_orders_pda(
+        program_id,
+        &dex_args,
+        &pda,
+        source_account_authority,
+      )?),
+      None => None,
+    };
+    let authority_bump_seed = [authority_bump.unwrap_or_default()];
+    let authority_seeds = [
+      OPEN_ORDERS_AUTHORITY_SEED,
+      dex_args.market.pubkey().as_ref(),
+      source_account_authority.key.as_ref(),
+      &authority_bump_seed,
+    ];
+    let open_order_authority = match dex_args.open_orders_pda {
+      Some(pda) => {
+        // the PDA pays for the order from the user's source account
+        invoke(
+          &spl_token::instruction::approve(
+            spl_token_program.pubkey(),
+            source_token_account.pubkey(),
+            pda.authority_acc.key,
+            source_account_authority.key,
+            amount_in,
+          )?,
+          &[
+            source_token_account.inner().clone(),
+            pda.authority_acc.clone(),
+            source_account_authority.clone(),
+            spl_token_program.inner().clone(),
+          ],
+        )?;
+        pda.authority_acc
+      }
+      None => source_account_authority,
+    };
+
+    let orderbook = serum_dex::order::OrderbookClient {
+      market: serum_dex::order::MarketAccounts {
+        market: dex_args.market.inner(),
+        open_orders: dex_args.open_orders,
+        request_queue: dex_args.request_queue_acc,
+        event_queue: dex_args.event_queue_acc,
+        bids: dex_args.bids_acc,
+        asks: dex_args.asks_acc,
+        order_payer_authority: source_token_account.inner(),
+        coin_vault: dex_args.coin_vault_acc.inner(),
+        pc_vault: dex_args.pc_vault_acc.inner(),
+        vault_signer: dex_args.vault_signer_acc,
+        coin_wallet: coin_wallet_account.inner(),
+      },
+      open_order_authority,
+      open_order_authority_seeds: authority_bump.map(|_| &authority_seeds[..]),
+      pc_wallet: pc_wallet_account.inner(),
+      dex_program: dex_args.program_acc,
+      token_program: spl_token_program.inner(),
+      rent: dex_args.rent_sysvar_acc,
+    };
+    let open_orders = SerumDexOpenOrders::new(dex_args.open_orders)?;
+    if cancel_resting_order {
+      if let Some((side, order_id)) = open_orders.resting_order(0)? {
+        msg!("serum.cancel_order");
+        orderbook.cancel_order(side, order_id)?;
+      }
+    }
+    let free_out_before = open_orders.native_free_out(side)?;
+    match side {
+      DexSide::Bid => orderbook.buy(amount_in, minimum_amount_out, client_order_id, None)?,
+      DexSide::Ask => orderbook.sell(amount_in, minimum_amount_out, client_order_id, None)?,
+    }
+    let free_out_after = open_orders.native_free_out(side)?;
+    if dex_args.open_orders_pda.is_some() {
+      invoke(
+        &spl_token::instruction::revoke(
+          spl_token_program.pubkey(),
+          source_token_account.pubkey(),
+          source_account_authority.key,
+        )?,
+        &[
+          source_token_account.inner().clone(),
+          source_account_authority.clone(),
+          spl_token_program.inner().clone(),
+        ],
+      )?;
+    }
+    msg!("serum.settle");
+    let referral_mint = match dex_args.referral_acc {
+      Some(referral) => Some(referral.mint()?),
+      None => None,
+    };
+    let referral = match orderbook.settle_layout(referral_mint)? {
+      SettleLayout::WithReferral => dex_args.referral_acc.map(|acc| acc.inner().clone()),
+      SettleLayout::Minimal => None,
+    };
+    orderbook.settle(referral)?;
+    let amount_out = Self::serum_filled_out(
+      free_out_before,
+      free_out_after,
+      destination_token_account.amount_delta(destination_amount_before)?,
+    )?;
+    if check_settle {
+      let settled_pc = match side {
+        DexSide::Bid => source_amount_before
+          .checked_sub(source_token_account.balance()?)
+          .ok_or(ProtocolError::DexSwapError)?,
+        DexSide::Ask => amount_out,
+      };
+      let fees_accrued = dex_args
+        .market
+        .pc_fees_accrued()?
+        .saturating_sub(pc_fees_before);
+      Self::check_serum_settle(
+        side,
+        settled_pc,
+        fees_accrued,
+        dex_args.market.fee_rate_bps()?,
+      )?;
+    }
+    Ok(amount_out)
+  }
+
+  /// Output filled by the order, read from the open orders free balance
+  /// before settle rather than the wallet delta, which also holds whatever
+  /// was already free and anything else moving the wallet. The settle must
+  /// have paid at least the fill.
+  fn serum_filled_out(
+    free_out_before: u64,
+    free_out_after: u64,
+    settled: u64,
+  ) -> Result<u64, ProtocolError> {
+    let filled = free_out_after
+      .checked_sub(free_out_before)
+      .ok_or(ProtocolError::DexSwapError)?;
+    if settled < filled {
+      return Err(ProtocolError::DexSwapError);
+    }
+    Ok(filled)
+  }
+
+  /// Recovers the free balances left in an open orders account, e.g. by
+  /// partial fills, by settling them without placing an order.
+  pub fn process_settle_open_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let args = SerumSettleArgs::with_parsed_args(accounts)?;
+    let market_key = args.market.pubkey();
+    let open_orders_owner = args.open_orders.owner()?;
+    let (open_order_authority, authority_bump) = match args.open_orders_authority {
+      Some(authority_acc) => {
+        let (authority_key, authority_bump) =
+          serum_dex::order::find_open_orders_authority(market_key, args.owner.key, program_id);
+        if authority_key != *authority_acc.key || open_orders_owner != authority_key {
+          return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+        }
+        (authority_acc, Some(authority_bump))
+      }
+      None => {
+        if open_orders_owner != *args.owner.key {
+          return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+        }
+        (args.owner, None)
+      }
+    };
+    let (coin_free, pc_free) = args.open_orders.free_balances()?;
+    msg!("coin_free: {}, pc_free: {}", coin_free, pc_free);
+
+    let authority_bump_seed = [authority_bump.unwrap_or_default()];
+    let authority_seeds = [
+      OPEN_ORDERS_AUTHORITY_SEED,
+      market_key.as_ref(),
+      args.owner.key.as_ref(),
+      &authority_bump_seed,
+    ];
+    // queues, order payer and rent are only used to place orders
+    let orderbook = serum_dex::order::OrderbookClient {
+      market: serum_dex::order::MarketAccounts {
+        market: args.market.inner(),
+        open_orders: args.open_orders.inner(),
+        request_queue: args.market.inner(),
+        event_queue: args.market.inner(),
+        bids: args.market.inner(),
+        asks: args.market.inner(),
+        order_payer_authority: args.coin_wallet.inner(),
+        coin_vault: args.coin_vault_acc.inner(),
+        pc_vault: args.pc_vault_acc.inner(),
+        vault_signer: args.vault_signer_acc,
+        coin_wallet: args.coin_wallet.inner(),
+      },
+      open_order_authority,
+      open_order_authority_seeds: authority_bump.map(|_| &authority_seeds[..]),
+      pc_wallet: args.pc_wallet.inner(),
+      dex_program: args.program_acc,
+      token_program: args.spl_token_program.inner(),
+      rent: args.program_acc,
+    };
+    orderbook.settle_layout(None)?;
+    orderbook.settle(None)
+  }
+
+  /// Closes the program-owned open orders account of the owner once it is
+  /// settled, the rent goes back to the owner.
+  pub fn process_close_serum_open_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let args = SerumCloseArgs::with_parsed_args(accounts)?;
+    let market_key = args.market.pubkey();
+    let (open_orders_key, _) =
+      serum_dex::order::find_open_orders_address(market_key, args.owner.key, program_id);
+    if open_orders_key != *args.open_orders.pubkey() {
+      return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+    }
+    let (authority_key, authority_bump) =
+      serum_dex::order::find_open_orders_authority(market_key, args.owner.key, program_id);
+    if authority_key != *args.open_orders_authority.key {
+      return Err(ProtocolError::InvalidAuthority.into());
+    }
+    if args.open_orders.owner()? != authority_key {
+      return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+    }
+    if args.open_orders.total_balances()? != (0, 0) {
+      return Err(ProtocolError::OpenOrdersNotEmpty.into());
+    }
+
+    msg!("serum.close_open_orders");
+    serum_dex::order::invoke_close_open_orders(
+      &[
+        OPEN_ORDERS_AUTHORITY_SEED,
+        market_key.as_ref(),
+        args.owner.key.as_ref(),
+        &[authority_bump],
+      ],
+      args.program_acc.key,
+      args.open_orders.inner(),
+      args.open_orders_authority,
+      args.owner,
+      args.market.inner(),
+    )?;
+    Ok(())
+  }
+
+  /// Checks the program-owned open orders account of `owner`, creating and
+  /// initializing it on first use. Returns the open orders authority bump seed.
+  fn prepare_serum_open_orders_pda<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    dex_args: &SerumDexArgs<'a, 'b>,
+    pda: &SerumDexOpenOrdersPda<'a, 'b>,
+    owner: &'a AccountInfo<'b>,
+  ) -> Result<u8, ProgramError> {
+    let market_key = dex_args.market.pubkey();
+    let (open_orders_key, open_orders_bump) =
+      serum_dex::order::find_open_orders_address(market_key, owner.key, program_id);
+    if open_orders_key != *dex_args.open_orders.key {
+      return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+    }
+    let (authority_key, authority_bump) =
+      serum_dex::order::find_open_orders_authority(market_key, owner.key, program_id);
+    if authority_key != *pda.authority_acc.key {
+      return Err(ProtocolError::InvalidAuthority.into());
+    }
+    if *dex_args.open_orders.owner == *dex_args.program_acc.key {
+      let open_orders = SerumDexOpenOrders::new(dex_args.open_orders)?;
+      if open_orders.owner()? != authority_key || open_orders.market()? != *market_key {
+        return Err(ProtocolError::InvalidOpenOrdersAccount.into());
+      }
+      return Ok(authority_bump);
+    }
+
+    msg!("serum.init_open_orders");
+    Self::create_pda_account(
+      owner,
+      dex_args.open_orders,
+      pda.system_program_acc,
+      OPEN_ORDERS_LEN,
+      dex_args.program_acc.key,
+      &[
+        OPEN_ORDERS_SEED,
+        market_key.as_ref(),
+        owner.key.as_ref(),
+        &[open_orders_bump],
+      ],
+    )?;
+    serum_dex::order::invoke_init_open_orders(
+      &[
+        OPEN_ORDERS_AUTHORITY_SEED,
+        market_key.as_ref(),
+        owner.key.as_ref(),
+        &[authority_bump],
+      ],
+      dex_args.program_acc.key,
+      dex_args.open_orders,
+      pda.authority_acc,
+      dex_args.market.inner(),
+      dex_args.rent_sysvar_acc,
+    )?;
+    Ok(authority_bump)
+  }
+
+  /// Step swap in spl-token-swap
+  #[allow(clippy::too_many_arguments)]
+  fn process_step_stableswap<'a, 'b: 'a>(
+    _program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+
+    let (accounts, wrapper_accounts) = StableSwapArgs::split_accounts(accounts);
+    let swap_args = StableSwapArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.swap_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using stable-swap, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+    let (source_wrapper, destination_wrapper) = Self::find_saber_wrappers(
+      wrapper_accounts,
+      &source_token_mint,
+      &destination_token_mint,
+    )?;
+
+    // trade the wrapped token in the pool, wrapping the input and unwrapping
+    // the output around the swap
+    let (source_token_account, amount_in) = match source_wrapper {
+      Some(wrapper) => {
+        let wrapped_before = wrapper.user_wrapped_tokens.balance()?;
+        Self::invoke_saber_wrapper(
+          &wrapper,
+          source_token_account,
+          source_account_authority,
+          spl_token_program,
+          amount_in,
+          true,
+        )?;
+        (
+          wrapper.user_wrapped_tokens,
+          wrapper.user_wrapped_tokens.amount_delta(wrapped_before)?,
+        )
+      }
+      None => (*source_token_account, amount_in),
+    };
+    let (swap_destination_account, minimum_amount_out) = match destination_wrapper {
+      Some(wrapper) => (
+        wrapper.user_wrapped_tokens,
+        minimum_amount_out
+          .checked_mul(wrapper.wrapper.multiplier()?)
+          .ok_or(ProtocolError::Overflow)?,
+      ),
+      None => (*destination_token_account, minimum_amount_out),
+    };
+    let swap_destination_before = swap_destination_account.balance()?;
+
+    let (swap_source_token_acc, swap_destination_token_acc) =
+      swap_args.find_token_pair(&source_token_account.mint()?)?;
+
+    if swap_source_token_acc.mint()? != source_token_account.mint()? {
+      return Err(ProtocolError::MintRoutingMismatch.into());
+    }
+    if swap_destination_token_acc.mint()? != swap_destination_account.mint()? {
+      return Err(ProtocolError::MintRoutingMismatch.into());
+    }
+    swap_args.check_admin_fee_account(&swap_destination_token_acc.mint()?)?;
+
+    let (instruction, swap_accounts) = Self::stable_swap_cpi(
+      swap_args.program_acc,
+      spl_token_program.inner(),
+      swap_args.swap_info.inner(),
+      swap_args.authority_acc,
+      source_account_authority,
+      source_token_account.inner(),
+      swap_source_token_acc.inner(),
+      swap_destination_token_acc.inner(),
+      swap_destination_account.inner(),
+      swap_args.admin_fee_acc,
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke saber-stableswap swap");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+
+    if let Some(wrapper) = destination_wrapper {
+      let wrapped_out = swap_destination_account.amount_delta(swap_destination_before)?;
+      Self::invoke_saber_wrapper(
+        &wrapper,
+        destination_token_account,
+        source_account_authority,
+        spl_token_program,
+        wrapped_out,
+        false,
+      )?;
+    }
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// Splits the add-decimals wrapper accounts following the stable swap
+  /// accounts into the wrapper of the source mint and the one of the
+  /// destination mint. Each wrapper underlying mint must be a user mint.
+  #[allow(clippy::type_complexity)]
+  fn find_saber_wrappers<'a, 'b: 'a>(
+    accounts: &'a [AccountInfo<'b>],
+    source_token_mint: &Pubkey,
+    destination_token_mint: &Pubkey,
+  ) -> ProtocolResult<(
+    Option<SaberWrapperArgs<'a, 'b>>,
+    Option<SaberWrapperArgs<'a, 'b>>,
+  )> {
+    if accounts.len() % SaberWrapperArgs::ACCOUNTS_LEN != 0
+      || accounts.len() > 2 * SaberWrapperArgs::ACCOUNTS_LEN
+    {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let (mut source_wrapper, mut destination_wrapper) = (None, None);
+    for wrapper_accounts in accounts.chunks(SaberWrapperArgs::ACCOUNTS_LEN) {
+      let wrapper = SaberWrapperArgs::with_parsed_args(wrapper_accounts)?;
+      let underlying_mint = wrapper.wrapper.underlying_mint()?;
+      if underlying_mint == *source_token_mint && source_wrapper.is_none() {
+        source_wrapper = Some(wrapper);
+      } else if underlying_mint == *destination_token_mint && destination_wrapper.is_none() {
+        destination_wrapper = Some(wrapper);
+      } else {
+        msg!(
+          "wrapper underlying mint {} is not a user mint",
+          underlying_mint
+        );
+        return Err(ProtocolError::MintRoutingMismatch);
+      }
+    }
+    Ok((source_wrapper, destination_wrapper))
+  }
+
+  /// Deposits `amount` underlying tokens into the wrapper, or withdraws
+  /// `amount` wrapped tokens when `deposit` is false.
+  fn invoke_saber_wrapper<'a, 'b: 'a>(
+    wrapper: &SaberWrapperArgs<'a, 'b>,
+    user_underlying_tokens: &TokenAccount<'a, 'b>,
+    authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    amount: u64,
+    deposit: bool,
+  ) -> ProgramResult {
+    let (instruction, wrapper_accounts) = Self::saber_wrapper_cpi(
+      wrapper.program_id,
+      spl_token_program.inner(),
+      wrapper.wrapper.inner(),
+      wrapper.wrapper_mint,
+      wrapper.wrapper_underlying_tokens.inner(),
+      authority,
+      user_underlying_tokens.inner(),
+      wrapper.user_wrapped_tokens.inner(),
+      amount,
+      deposit,
+    )?;
+    msg!(
+      "invoke saber add-decimals {}, amount: {}",
+      if deposit { "deposit" } else { "withdraw" },
+      amount
+    );
+    invoke(&instruction, &wrapper_accounts)
+  }
+
+  /// Step swap in spl-token-swap
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_raydium<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    let swap_args = RaydiumSwapArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.amm_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using raydium, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+
+    let (instruction, swap_accounts) = Self::raydium_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      swap_args.amm_info.inner(),
+      swap_args.authority,
+      swap_args.open_orders.inner(),
+      swap_args.target_orders,
+      swap_args.pool_token_coin.inner(),
+      swap_args.pool_token_pc.inner(),
+      swap_args.serum_dex_program_id,
+      swap_args.serum_market.inner(),
+      swap_args.bids,
+      swap_args.asks,
+      swap_args.event_q,
+      swap_args.coin_vault.inner(),
+      swap_args.pc_vault.inner(),
+      swap_args.vault_signer,
+      source_token_account.inner(),
+      destination_token_account.inner(),
+      source_account_authority,
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke raydium swap_base_in");
+    invoke(&instruction, &swap_accounts)?;
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// Step swap in spl-token-swap
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_crema_finance<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+    msg!("process_step crema-finance");
+
+    let swap_args = CremaSwapV1Args::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.swap_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using crema-finance, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+
+    let (pool_source_token_acc, pool_destination_token_acc) =
+      swap_args.find_token_pair(&source_token_mint, &destination_token_mint)?;
+
+    let (instruction, swap_accounts) = Self::crema_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      swap_args.swap_info.inner(),
+      swap_args.authority,
+      source_account_authority,
+      source_token_account.inner(),
+      destination_token_account.inner(),
+      pool_source_token_acc.inner(),
+      pool_destination_token_acc.inner(),
+      swap_args.tick_dst,
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke crema-finance swap");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// Step swap in spl-token-swap
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_aldrin_exchange<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+    max_price_impact_bps: u16,
+    expected_side: Option<u8>,
+    reference_price: Option<ReferencePrice>,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+
+    let swap_args = AldrinPoolArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.pool_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using aldrin-exchanger, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+    let pool_coin_mint = swap_args.pool_coin_vault.mint()?;
+    let pool_pc_mint = swap_args.pool_pc_vault.mint()?;
+
+    let side = swap_args.find_side(&source_token_mint)?;
+    Self::check_expected_side(expected_side, side as u8)?;
+    let pool_reserves = if max_price_impact_bps != 0 || reference_price.is_some() {
+      Some(swap_args.reserves(&source_token_mint, &destination_token_mint)?)
+    } else {
+      None
+    };
+    Self::check_reference_price(pool_reserves, reference_price)?;
+
+    let (user_coin_token_acc, user_pc_token_acc) =
+      if source_token_mint == pool_coin_mint && destination_token_mint == pool_pc_mint {
+        (source_token_account, destination_token_account)
+      } else if source_token_mint == pool_pc_mint && destination_token_mint == pool_coin_mint {
+        (destination_token_account, source_token_account)
+      } else {
+        return Err(ProtocolError::MintRoutingMismatch.into());
+      };
+
+    let (instruction, swap_accounts) = Self::aldrin_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      swap_args.pool_info.inner(),
+      swap_args.authority,
+      swap_args.pool_mint.inner(),
+      swap_args.pool_coin_vault.inner(),
+      swap_args.pool_pc_vault.inner(),
+      swap_args.fee_account,
+      swap_args.curve_key,
+      user_coin_token_acc.inner(),
+      user_pc_token_acc.inner(),
+      source_account_authority,
+      amount_in,
+      minimum_amount_out,
+      side,
+    )?;
+
+    msg!("invoke aldrin-exchanger swap");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+    let amount_out = destination_token_account.amount_delta(destination_amount_before)?;
+    Self::check_price_impact(pool_reserves, amount_in, amount_out, max_price_impact_bps)?;
+    Ok(amount_out)
+  }
+
+  /// Step swap in spl-token-swap
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_cropper_finance<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+    reference_price: Option<ReferencePrice>,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+
+    let swap_args = CropperArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.swap_info.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using cropper-finance, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+    let pool_token_a_mint = swap_args.swap_info.token_a_mint()?;
+    let pool_token_b_mint = swap_args.swap_info.token_b_mint()?;
+    let destination_token_mint = destination_token_account.mint()?;
+
+    if swap_args.fee_account.mint()? != source_token_mint {
+      msg!(
+        "cropper-finance.fee_account.mint is {}, expect {}",
+        swap_args.fee_account.pubkey(),
+        destination_token_mint
+      );
+    }
+
+    let (pool_source_token_account, pool_destination_token_account) =
+      if source_token_mint == pool_token_a_mint && destination_token_mint == pool_token_b_mint {
+        (swap_args.token_a_account, swap_args.token_b_account)
+      } else if source_token_mint == pool_token_b_mint
+        && destination_token_mint == pool_token_a_mint
+      {
+        (swap_args.token_b_account, swap_args.token_a_account)
+      } else {
+        return Err(ProtocolError::MintRoutingMismatch.into());
+      };
+    if reference_price.is_some() {
+      Self::check_reference_price(
+        Some(swap_args.reserves(&source_token_mint, &destination_token_mint)?),
+        reference_price,
+      )?;
+    }
+
+    let (instruction, swap_accounts) = Self::cropper_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      swap_args.swap_info.inner(),
+      swap_args.authority,
+      source_account_authority,
+      swap_args.program_state.inner(),
+      source_token_account.inner(),
+      pool_source_token_account.inner(),
+      pool_destination_token_account.inner(),
+      destination_token_account.inner(),
+      swap_args.pool_mint.inner(),
+      swap_args.fee_account.inner(),
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke cropper-finance swap");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// Fee of a slim swap out: a quarter of the surplus over `minimum_amount_out`,
+  /// capped at 1bp of the output. An output below the minimum is an error,
+  /// not a zero fee.
+  fn slim_fee(to_amount_include_fee: u64, minimum_amount_out: u64) -> Result<u64, ProtocolError> {
+    let surplus = to_amount_include_fee
+      .checked_sub(minimum_amount_out)
+      .ok_or(ProtocolError::ExceededSlippage)?;
+    Ok(cmp::min(surplus / 4, to_amount_include_fee / 10_000))
+  }
+
+  /// Step swap in meteora damm v2
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_meteora_damm<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+
+    let swap_args = MeteoraDammArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.pool.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using meteora-damm, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+    let token_a_mint = *swap_args.token_a_mint.pubkey();
+    let token_b_mint = *swap_args.token_b_mint.pubkey();
+    if !((source_token_mint == token_a_mint && destination_token_mint == token_b_mint)
+      || (source_token_mint == token_b_mint && destination_token_mint == token_a_mint))
+    {
+      return Err(ProtocolError::MintRoutingMismatch.into());
+    }
+
+    let (instruction, swap_accounts) = Self::meteora_damm_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      swap_args.pool_authority,
+      swap_args.pool.inner(),
+      source_token_account.inner(),
+      destination_token_account.inner(),
+      swap_args.token_a_vault.inner(),
+      swap_args.token_b_vault.inner(),
+      swap_args.token_a_mint.inner(),
+      swap_args.token_b_mint.inner(),
+      source_account_authority,
+      swap_args.event_authority,
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke meteora-damm swap");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// Step swap in raydium cp-swap
+  #[allow(clippy::too_many_arguments, unused_variables)]
+  fn process_step_raydium_cpmm<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    source_token_account: &TokenAccount<'a, 'b>,
+    destination_token_account: &TokenAccount<'a, 'b>,
+    source_account_authority: &'a AccountInfo<'b>,
+    spl_token_program: &SplTokenProgram<'a, 'b>,
+    accounts: &'a [AccountInfo<'b>],
+    protocol_config: &ProtocolConfig,
+  ) -> Result<u64, ProgramError> {
+    let destination_amount_before = destination_token_account.balance()?;
+    sol_log_compute_units();
+
+    let swap_args = RaydiumCpmmArgs::with_parsed_args(accounts)?;
+    protocol_config.check_pool(swap_args.pool.pubkey())?;
+    let (source_token_balance, source_token_mint) =
+      source_token_account.spendable_snapshot(source_account_authority.key)?;
+    let amount_in = Self::get_amount_in(amount_in, source_token_balance);
+
+    msg!(
+      "swap using raydium-cpmm, amount_in: {}, minimum_amount_out: {}",
+      amount_in,
+      minimum_amount_out,
+    );
+
+    let destination_token_mint = destination_token_account.mint()?;
+    let (input_vault, output_vault, input_mint, output_mint) =
+      swap_args.find_token_pair(&source_token_mint, &destination_token_mint)?;
+
+    let (instruction, swap_accounts) = Self::raydium_cpmm_swap_cpi(
+      swap_args.program_id,
+      spl_token_program.inner(),
+      source_account_authority,
+      swap_args.authority,
+      swap_args.amm_config.inner(),
+      swap_args.pool.inner(),
+      source_token_account.inner(),
+      destination_token_account.inner(),
+      input_vault.inner(),
+      output_vault.inner(),
+      input_mint.inner(),
+      output_mint.inner(),
+      swap_args.observation_state,
+      amount_in,
+      minimum_amount_out,
+    )?;
+
+    msg!("invoke raydium-cpmm swap_base_input");
+
+    sol_log_compute_units();
+    invoke(&instruction, &swap_accounts)?;
+    sol_log_compute_units();
+    Ok(destination_token_account.amount_delta(destination_amount_before)?)
+  }
+
+  /// spl-token-swap `swap` instruction and the accounts handed to its invoke
+  #[allow(clippy::too_many_arguments)]
+  fn spl_token_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    swap_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    swap_source: &AccountInfo<'b>,
+    swap_destination: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    pool_mint: &AccountInfo<'b>,
+    fee_account: &AccountInfo<'b>,
+    host_fee_account: Option<&AccountInfo<'b>>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let mut swap_accounts = vec![
+      swap_info.clone(),
+      authority.clone(),
+      user_authority.clone(),
+      source.clone(),
+      swap_source.clone(),
+      swap_destination.clone(),
+      destination.clone(),
+      pool_mint.clone(),
+      fee_account.clone(),
+    ];
+    if let Some(host_fee_account) = host_fee_account {
+      swap_accounts.push(host_fee_account.clone());
+    }
+    swap_accounts.push(program.clone());
+
+    let instruction = spl_token_swap::instruction::swap(
+      program.key,
+      token_program.key,
+      swap_info.key,
+      authority.key,
+      user_authority.key,
+      source.key,
+      swap_source.key,
+      swap_destination.key,
+      destination.key,
+      pool_mint.key,
+      fee_account.key,
+      host_fee_account.map(|acc| acc.key),
+      spl_token_swap::instruction::Swap {
+        amount_in,
+        minimum_amount_out,
+      },
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// saber stable swap `swap` instruction and the accounts handed to its
+  /// invoke
+  #[allow(clippy::too_many_arguments)]
+  fn stable_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    swap_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    swap_source: &AccountInfo<'b>,
+    swap_destination: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    admin_fee_account: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      swap_info.clone(),
+      authority.clone(),
+      user_authority.clone(),
+      source.clone(),
+      swap_source.clone(),
+      swap_destination.clone(),
+      destination.clone(),
+      admin_fee_account.clone(),
+      token_program.clone(),
+      program.clone(),
+    ];
+
+    let instruction = stable_swap::instruction::swap(
+      program.key,
+      token_program.key,
+      swap_info.key,
+      authority.key,
+      user_authority.key,
+      source.key,
+      swap_source.key,
+      swap_destination.key,
+      destination.key,
+      admin_fee_account.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// saber add-decimals `deposit` instruction, or `withdraw` when `deposit`
+  /// is false, and the accounts handed to its invoke
+  #[allow(clippy::too_many_arguments)]
+  fn saber_wrapper_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    wrapper: &AccountInfo<'b>,
+    wrapper_mint: &AccountInfo<'b>,
+    wrapper_underlying_tokens: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    user_underlying_tokens: &AccountInfo<'b>,
+    user_wrapped_tokens: &AccountInfo<'b>,
+    amount: u64,
+    deposit: bool,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let build = if deposit {
+      saber_wrapper::instruction::deposit
+    } else {
+      saber_wrapper::instruction::withdraw
+    };
+    let instruction = build(
+      program.key,
+      wrapper.key,
+      wrapper_mint.key,
+      wrapper_underlying_tokens.key,
+      authority.key,
+      user_underlying_tokens.key,
+      user_wrapped_tokens.key,
+      token_program.key,
+      amount,
+    )?;
+    let wrapper_accounts = vec![
+      wrapper.clone(),
+      wrapper_mint.clone(),
+      wrapper_underlying_tokens.clone(),
+      authority.clone(),
+      user_underlying_tokens.clone(),
+      user_wrapped_tokens.clone(),
+      token_program.clone(),
+      program.clone(),
+    ];
+    Ok((instruction, wrapper_accounts))
+  }
+
+  /// raydium `swap_base_in` instruction and the accounts handed to its
+  /// invoke
+  #[allow(clippy::too_many_arguments)]
+  fn raydium_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    amm_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    open_orders: &AccountInfo<'b>,
+    target_orders: Option<&AccountInfo<'b>>,
+    pool_token_coin: &AccountInfo<'b>,
+    pool_token_pc: &AccountInfo<'b>,
+    serum_dex_program: &AccountInfo<'b>,
+    serum_market: &AccountInfo<'b>,
+    bids: &AccountInfo<'b>,
+    asks: &AccountInfo<'b>,
+    event_q: &AccountInfo<'b>,
+    coin_vault: &AccountInfo<'b>,
+    pc_vault: &AccountInfo<'b>,
+    vault_signer: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let mut swap_accounts = vec![
+      program.clone(),
+      token_program.clone(),
+      amm_info.clone(),
+      authority.clone(),
+      open_orders.clone(),
+    ];
+    if let Some(target_orders) = target_orders {
+      swap_accounts.push(target_orders.clone());
+    }
+    swap_accounts.extend_from_slice(&[
+      pool_token_coin.clone(),
+      pool_token_pc.clone(),
+      serum_dex_program.clone(),
+      serum_market.clone(),
+      bids.clone(),
+      asks.clone(),
+      event_q.clone(),
+      coin_vault.clone(),
+      pc_vault.clone(),
+      vault_signer.clone(),
+      source.clone(),
+      destination.clone(),
+      user_authority.clone(),
+    ]);
+
+    let instruction = raydium::instruction::swap(
+      program.key,
+      amm_info.key,
+      authority.key,
+      open_orders.key,
+      target_orders.map(|acc| acc.key),
+      pool_token_coin.key,
+      pool_token_pc.key,
+      serum_dex_program.key,
+      serum_market.key,
+      bids.key,
+      asks.key,
+      event_q.key,
+      coin_vault.key,
+      pc_vault.key,
+      vault_signer.key,
+      source.key,
+      destination.key,
+      user_authority.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// crema `swap` instruction and the accounts handed to its invoke
+  #[allow(clippy::too_many_arguments)]
+  fn crema_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    swap_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    pool_source: &AccountInfo<'b>,
+    pool_destination: &AccountInfo<'b>,
+    tick_dst: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      program.clone(),
+      swap_info.clone(),
+      authority.clone(),
+      user_authority.clone(),
+      source.clone(),
+      destination.clone(),
+      pool_source.clone(),
+      pool_destination.clone(),
+      tick_dst.clone(),
+      token_program.clone(),
+    ];
+
+    let instruction = crema::instruction::swap_instruction(
+      program.key,
+      swap_info.key,
+      authority.key,
+      user_authority.key,
+      source.key,
+      destination.key,
+      pool_source.key,
+      pool_destination.key,
+      tick_dst.key,
+      token_program.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// aldrin `swap` instruction and the accounts handed to its invoke
+  #[allow(clippy::too_many_arguments)]
+  fn aldrin_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    pool_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    pool_mint: &AccountInfo<'b>,
+    pool_coin_vault: &AccountInfo<'b>,
+    pool_pc_vault: &AccountInfo<'b>,
+    fee_account: &AccountInfo<'b>,
+    curve_key: &AccountInfo<'b>,
+    user_coin: &AccountInfo<'b>,
+    user_pc: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    side: aldrin::instruction::Side,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      program.clone(),
+      pool_info.clone(),
+      authority.clone(),
+      pool_mint.clone(),
+      pool_coin_vault.clone(),
+      pool_pc_vault.clone(),
+      fee_account.clone(),
+      curve_key.clone(),
+      user_coin.clone(),
+      user_pc.clone(),
+      user_authority.clone(),
+      token_program.clone(),
+    ];
+
+    let instruction = aldrin::instruction::swap_instruction(
+      program.key,
+      pool_info.key,
+      authority.key,
+      pool_mint.key,
+      pool_coin_vault.key,
+      pool_pc_vault.key,
+      fee_account.key,
+      curve_key.key,
+      user_coin.key,
+      user_pc.key,
+      user_authority.key,
+      token_program.key,
+      amount_in,
+      minimum_amount_out,
+      side,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// cropper `swap` instruction and the accounts handed to its invoke
+  #[allow(clippy::too_many_arguments)]
+  fn cropper_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    swap_info: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    program_state: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    pool_source: &AccountInfo<'b>,
+    pool_destination: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    pool_mint: &AccountInfo<'b>,
+    fee_account: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      program.clone(),
+      swap_info.clone(),
+      authority.clone(),
+      user_authority.clone(),
+      program_state.clone(),
+      source.clone(),
+      pool_source.clone(),
+      pool_destination.clone(),
+      destination.clone(),
+      pool_mint.clone(),
+      fee_account.clone(),
+      token_program.clone(),
+    ];
+
+    let instruction = cropper::instruction::swap_instruction(
+      program.key,
+      token_program.key,
+      swap_info.key,
+      authority.key,
+      user_authority.key,
+      program_state.key,
+      source.key,
+      pool_source.key,
+      pool_destination.key,
+      destination.key,
+      pool_mint.key,
+      fee_account.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// meteora damm v2 `swap` instruction and the accounts handed to its
+  /// invoke
+  #[allow(clippy::too_many_arguments)]
+  fn meteora_damm_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    pool_authority: &AccountInfo<'b>,
+    pool: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    token_a_vault: &AccountInfo<'b>,
+    token_b_vault: &AccountInfo<'b>,
+    token_a_mint: &AccountInfo<'b>,
+    token_b_mint: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    event_authority: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      pool_authority.clone(),
+      pool.clone(),
+      source.clone(),
+      destination.clone(),
+      token_a_vault.clone(),
+      token_b_vault.clone(),
+      token_a_mint.clone(),
+      token_b_mint.clone(),
+      user_authority.clone(),
+      token_program.clone(),
+      event_authority.clone(),
+      program.clone(),
+    ];
+
+    let instruction = meteora_damm::instruction::swap_instruction(
+      program.key,
+      pool_authority.key,
+      pool.key,
+      source.key,
+      destination.key,
+      token_a_vault.key,
+      token_b_vault.key,
+      token_a_mint.key,
+      token_b_mint.key,
+      user_authority.key,
+      token_program.key,
+      event_authority.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// raydium cp-swap `swap_base_input` instruction and the accounts handed to
+  /// its invoke. Vaults and mints are classic spl-token accounts, both sides
+  /// go through the same token program.
+  #[allow(clippy::too_many_arguments)]
+  fn raydium_cpmm_swap_cpi<'b>(
+    program: &AccountInfo<'b>,
+    token_program: &AccountInfo<'b>,
+    user_authority: &AccountInfo<'b>,
+    authority: &AccountInfo<'b>,
+    amm_config: &AccountInfo<'b>,
+    pool: &AccountInfo<'b>,
+    source: &AccountInfo<'b>,
+    destination: &AccountInfo<'b>,
+    input_vault: &AccountInfo<'b>,
+    output_vault: &AccountInfo<'b>,
+    input_mint: &AccountInfo<'b>,
+    output_mint: &AccountInfo<'b>,
+    observation_state: &AccountInfo<'b>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+  ) -> Result<(Instruction, Vec<AccountInfo<'b>>), ProgramError> {
+    let swap_accounts = vec![
+      user_authority.clone(),
+      authority.clone(),
+      amm_config.clone(),
+      pool.clone(),
+      source.clone(),
+      destination.clone(),
+      input_vault.clone(),
+      output_vault.clone(),
+      token_program.clone(),
+      input_mint.clone(),
+      output_mint.clone(),
+      observation_state.clone(),
+      program.clone(),
+    ];
+
+    let instruction = raydium_cpmm::instruction::swap_base_input(
+      program.key,
+      user_authority.key,
+      authority.key,
+      amm_config.key,
+      pool.key,
+      source.key,
+      destination.key,
+      input_vault.key,
+      output_vault.key,
+      token_program.key,
+      token_program.key,
+      input_mint.key,
+      output_mint.key,
+      observation_state.key,
+      amount_in,
+      minimum_amount_out,
+    )?;
+    Ok((instruction, swap_accounts))
+  }
+
+  /// Runs the exchanger CPI self-test in debug builds, see `SelfTest`. A
+  /// no-op in release builds.
+  pub fn process_self_test() -> ProgramResult {
+    #[cfg(debug_assertions)]
+    Self::self_test()?;
+    Ok(())
+  }
+
+  /// Builds the CPI of every exchanger with distinct dummy accounts and
+  /// checks the accounts handed to each invoke against its instruction.
+  #[cfg(debug_assertions)]
+  fn self_test() -> ProgramResult {
+    const DUMMY_ACCOUNTS: usize = 24;
+
+    // the token program is the real one, its meta may have no account
+    let keys = (0..DUMMY_ACCOUNTS)
+      .map(|i| match i {
+        0 => spl_token::ID,
+        i => Pubkey::new_from_array([i as u8; 32]),
+      })
+      .collect::<Vec<_>>();
+    let mut lamports = vec![0u64; DUMMY_ACCOUNTS];
+    let mut datas = vec![Vec::<u8>::new(); DUMMY_ACCOUNTS];
+    let a = keys
+      .iter()
+      .zip(lamports.iter_mut())
+      .zip(datas.iter_mut())
+      .map(|((key, lamports), data)| {
+        AccountInfo::new(key, false, false, lamports, &mut data[..], key, false, 0)
+      })
+      .collect::<Vec<_>>();
+    let token_program = &a[0];
+
+    for host_fee_account in [None, Some(&a[12])] {
+      let (instruction, accounts) = Self::spl_token_swap_cpi(
+        &a[1],
+        token_program,
+        &a[2],
+        &a[3],
+        &a[4],
+        &a[5],
+        &a[6],
+        &a[7],
+        &a[8],
+        &a[9],
+        &a[10],
+        host_fee_account,
+        1,
+        1,
+      )?;
+      Self::check_cpi_accounts("spl-token-swap", &instruction, &accounts)?;
+    }
+
+    let (instruction, accounts) = Self::stable_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      1,
+      1,
+    )?;
+    Self::check_cpi_accounts("stable-swap", &instruction, &accounts)?;
+
+    for deposit in [true, false] {
+      let (instruction, accounts) = Self::saber_wrapper_cpi(
+        &a[1],
+        token_program,
+        &a[2],
+        &a[3],
+        &a[4],
+        &a[5],
+        &a[6],
+        &a[7],
+        1,
+        deposit,
+      )?;
+      Self::check_cpi_accounts("saber-wrapper", &instruction, &accounts)?;
+    }
+
+    for target_orders in [None, Some(&a[20])] {
+      let (instruction, accounts) = Self::raydium_swap_cpi(
+        &a[1],
+        token_program,
+        &a[2],
+        &a[3],
+        &a[4],
+        target_orders,
+        &a[5],
+        &a[6],
+        &a[7],
+        &a[8],
+        &a[9],
+        &a[10],
+        &a[11],
+        &a[12],
+        &a[13],
+        &a[14],
+        &a[15],
+        &a[16],
+        &a[17],
+        1,
+        1,
+      )?;
+      Self::check_cpi_accounts("raydium", &instruction, &accounts)?;
+    }
+
+    let (instruction, accounts) = Self::crema_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      1,
+      1,
+    )?;
+    Self::check_cpi_accounts("crema-finance", &instruction, &accounts)?;
+
+    let (instruction, accounts) = Self::aldrin_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      &a[10],
+      &a[11],
+      1,
+      1,
+      aldrin::instruction::Side::Bid,
+    )?;
+    Self::check_cpi_accounts("aldrin-exchanger", &instruction, &accounts)?;
+
+    let (instruction, accounts) = Self::cropper_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      &a[10],
+      &a[11],
+      1,
+      1,
+    )?;
+    Self::check_cpi_accounts("cropper-finance", &instruction, &accounts)?;
+
+    let (instruction, accounts) = Self::meteora_damm_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      &a[10],
+      &a[11],
+      1,
+      1,
+    )?;
+    Self::check_cpi_accounts("meteora-damm", &instruction, &accounts)?;
+
+    let (instruction, accounts) = Self::raydium_cpmm_swap_cpi(
+      &a[1],
+      token_program,
+      &a[2],
+      &a[3],
+      &a[4],
+      &a[5],
+      &a[6],
+      &a[7],
+      &a[8],
+      &a[9],
+      &a[10],
+      &a[11],
+      &a[12],
+      1,
+      1,
+    )?;
+    Self::check_cpi_accounts("raydium-cpmm", &instruction, &accounts)?;
+
+    let orderbook = serum_dex::order::OrderbookClient {
+      market: serum_dex::order::MarketAccounts {
+        market: &a[2],
+        open_orders: &a[3],
+        request_queue: &a[4],
+        event_queue: &a[5],
+        bids: &a[6],
+        asks: &a[7],
+        order_payer_authority: &a[8],
+        coin_vault: &a[9],
+        pc_vault: &a[10],
+        vault_signer: &a[11],
+        coin_wallet: &a[12],
+      },
+      open_order_authority: &a[13],
+      open_order_authority_seeds: None,
+      pc_wallet: &a[14],
+      dex_program: &a[1],
+      token_program,
+      rent: &a[15],
+    };
+    for discount in [None, Some(a[16].clone())] {
+      let (instruction, accounts) =
+        orderbook.new_order_instruction(1, 1, 1, DexSide::Bid, 0, discount)?;
+      Self::check_cpi_accounts("serum-dex new_order", &instruction, &accounts)?;
+    }
+    let (instruction, accounts) = orderbook.cancel_order_instruction(DexSide::Ask, 1)?;
+    Self::check_cpi_accounts("serum-dex cancel_order", &instruction, &accounts)?;
+    for referral in [None, Some(a[16].clone())] {
+      let (instruction, accounts) = orderbook.settle_instruction(referral)?;
+      Self::check_cpi_accounts("serum-dex settle", &instruction, &accounts)?;
+    }
+    msg!("self test passed");
+    Ok(())
+  }
+
+  /// Checks `account_infos` holds exactly one account per distinct meta of
+  /// `instruction` plus the callee program. The token program may be left
+  /// out, the runtime resolves executable accounts of the transaction.
+  #[cfg(debug_assertions)]
+  fn check_cpi_accounts(
+    name: &str,
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+  ) -> ProgramResult {
+    let mut info_keys = account_infos
+      .iter()
+      .map(|info| *info.key)
+      .collect::<Vec<_>>();
+    info_keys.sort();
+    let mut expected = instruction
+      .accounts
+      .iter()
+      .map(|meta| meta.pubkey)
+      .chain([instruction.program_id])
+      .filter(|key| *key != spl_token::ID || info_keys.contains(key))
+      .collect::<Vec<_>>();
+    expected.sort();
+    expected.dedup();
+    if info_keys != expected {
+      msg!(
+        "{} cpi expects {} accounts, got {}",
+        name,
+        expected.len(),
+        info_keys.len()
+      );
+      return Err(ProtocolError::InvalidAccountsLength.into());
+    }
+    Ok(())
+  }
+
+  /// `SWAP_EXTRA_FLAG_NO_CLAMP`: the swap fails rather than partially
+  /// executing on a source that can't spend `amount_in`.
+  fn check_amount_in_funded(
+    amount_in: u64,
+    source_token_account: &TokenAccount,
+    authority: &Pubkey,
+  ) -> Result<(), ProtocolError> {
+    let (spendable, _) = source_token_account.spendable_snapshot(authority)?;
+    if spendable < amount_in {
+      msg!("amount_in: {}, spendable: {}", amount_in, spendable);
+      return Err(ProtocolError::InvalidSourceBalance);
+    }
+    Ok(())
+  }
+
+  fn get_amount_in(amount_in: u64, source_token_balance: u64) -> u64 {
+    if source_token_balance < amount_in {
+      source_token_balance
+    } else {
+      amount_in
+    }
+  }
+
+  // /// check token account authority
+  // pub fn check_token_account_authority(
+  //   token_account: &spl_token::state::Account,
+  //   authority_info: &Pubkey,
+  // ) -> Result<(), ProtocolError> {
+  //   if !token_account
+  //     .delegate
+  //     .map(|d| d == *authority_info)
+  //     .unwrap_or(false)
+  //     || token_account.owner == *authority_info
+  //   {
+  //     return Err(ProtocolError::InvalidDelegate);
+  //   }
+  //   Ok(())
+  // }
+
+  /// Issue a spl_token `Transfer` instruction.
+  pub fn token_transfer_signed<'a>(
+    base: &Pubkey,
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    nonce: u8,
+    amount: u64,
+  ) -> Result<(), ProgramError> {
+    let base_bytes = base.to_bytes();
+    let authority_signature_seeds = [&base_bytes[..32], &[nonce]];
+    let signers = &[&authority_signature_seeds[..]];
+    let ix = spl_token::instruction::transfer(
+      token_program.key,
+      source.key,
+      destination.key,
+      authority.key,
+      &[],
+      amount,
+    )?;
+    // invoke(&ix, &[source, destination, authority, token_program])
+    invoke_signed(
+      &ix,
+      &[
+        source.clone(),
+        destination.clone(),
+        authority.clone(),
+        token_program.clone(),
+      ],
+      signers,
+    )
+  }
+
+  /// Issue a spl_token `Transfer` instruction.
+  pub fn token_transfer<'a>(
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    amount: u64,
+  ) -> Result<(), ProgramError> {
+    let ix = spl_token::instruction::transfer(
+      token_program.key,
+      source.key,
+      destination.key,
+      authority.key,
+      &[],
+      amount,
+    )?;
+    // invoke(&ix, &[source, destination, authority, token_program])
+    invoke(
+      &ix,
+      &[
+        source.clone(),
+        destination.clone(),
+        authority.clone(),
+        token_program.clone(),
+      ],
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::instruction::{
+    OutputLeg, SWAP_EXTRA_FLAG_ACCRUE_FEE, SWAP_EXTRA_FLAG_EXCHANGER_STATS,
+    SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT, SWAP_EXTRA_FLAG_VALIDATE_ONLY,
+    SWAP_FLAG_FEE_IN_REFERENCE_MINT, SWAP_IN_FLAG_REBATE, SWAP_OUT_FLAG_CLOSE_ON_COMPLETE,
+    SWAP_OUT_FLAG_MINIMUM_FROM_INPUT,
+  };
+  use solana_program::{program::get_return_data, program_stubs};
+  use solana_sdk::account::Account;
+  use std::{cell::RefCell, ops::Range, str::FromStr};
+
+  thread_local! {
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = RefCell::new(None);
+    static SETTLE_REFERRAL: RefCell<Option<Pubkey>> = RefCell::new(None);
+    static LAMPORT_TOP_UP: RefCell<u64> = RefCell::new(0);
+  }
 
-    let from_amount_after = user_args.token_source_account.balance()?;
-    let to_amount_after = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_after: {}, to_amount_after: {}",
-      from_amount_after,
-      to_amount_after
-    );
+  /// Simulates spl-token `transfer`, `initialize_account3` and
+  /// `close_account`, system `transfer`, `allocate`, `assign` and
+  /// `create_account` into a preallocated account, a
+  /// spl-token-swap `swap` paying out twice the amount in, serum `new_order`
+  /// bids filled at twice the quote amount, `settle_funds` and
+  /// `close_open_orders`. Return data, the referral of the last settle and
+  /// the lamports a swap adds on top of its output, as a rent top-up in the
+  /// same transaction would, are kept per test thread.
+  struct SwapStubs;
+
+  impl program_stubs::SyscallStubs for SwapStubs {
+    fn sol_invoke_signed(
+      &self,
+      instruction: &Instruction,
+      account_infos: &[AccountInfo],
+      _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+      let find = |i: usize| {
+        account_infos
+          .iter()
+          .find(|a| *a.key == instruction.accounts[i].pubkey)
+          .unwrap()
+      };
+      if instruction.program_id == serum_program_id()
+        && instruction.data[1..5] == 14u32.to_le_bytes()
+      {
+        // close_open_orders: moves the rent to the destination
+        let (open_orders, destination) = (find(0), find(2));
+        let lamports = open_orders.lamports();
+        **open_orders.lamports.borrow_mut() = 0;
+        **destination.lamports.borrow_mut() += lamports;
+        open_orders.data.borrow_mut().fill(0);
+        return Ok(());
+      }
+      if instruction.program_id == serum_program_id()
+        && instruction.data[1..5] == 10u32.to_le_bytes()
+      {
+        // new_order, bids only: the quote amount buys twice as much coin,
+        // left free in the open orders until settled
+        let pc = u64::from_le_bytes(*array_ref![instruction.data, 25, 8]);
+        add_amount(find(6), pc, false);
+        add_amount(find(9), pc, true);
+        let mut data = find(1).data.borrow_mut();
+        for offset in [5 + 72, 5 + 80] {
+          let amount = u64::from_le_bytes(*array_ref![data, offset, 8]) + 2 * pc;
+          data[offset..offset + 8].copy_from_slice(&amount.to_le_bytes());
+        }
+        return Ok(());
+      }
+      if instruction.program_id == serum_program_id() {
+        // settle_funds: pays the free balances out of the vaults
+        SETTLE_REFERRAL.with(|r| *r.borrow_mut() = instruction.accounts.get(9).map(|m| m.pubkey));
+        let open_orders = find(1);
+        let (coin_free, pc_free) = {
+          let data = open_orders.data.borrow();
+          (
+            u64::from_le_bytes(*array_ref![data, 5 + 72, 8]),
+            u64::from_le_bytes(*array_ref![data, 5 + 88, 8]),
+          )
+        };
+        add_amount(find(3), coin_free, false);
+        add_amount(find(5), coin_free, true);
+        add_amount(find(4), pc_free, false);
+        add_amount(find(6), pc_free, true);
+        let mut data = open_orders.data.borrow_mut();
+        let coin_total = u64::from_le_bytes(*array_ref![data, 5 + 80, 8]).saturating_sub(coin_free);
+        let pc_total = u64::from_le_bytes(*array_ref![data, 5 + 96, 8]).saturating_sub(pc_free);
+        data[5 + 72..5 + 80].copy_from_slice(&0u64.to_le_bytes());
+        data[5 + 80..5 + 88].copy_from_slice(&coin_total.to_le_bytes());
+        data[5 + 88..5 + 96].copy_from_slice(&0u64.to_le_bytes());
+        data[5 + 96..5 + 104].copy_from_slice(&pc_total.to_le_bytes());
+        return Ok(());
+      }
+      if instruction.program_id == system_program::id() && instruction.data[0] == 2 {
+        // transfer
+        let (from, to) = (find(0), find(1));
+        let lamports = u64::from_le_bytes(*array_ref![instruction.data, 4, 8]);
+        **from.lamports.borrow_mut() -= lamports;
+        **to.lamports.borrow_mut() += lamports;
+        return Ok(());
+      }
+      if instruction.program_id == system_program::id() && instruction.data[0] == 8 {
+        // allocate: the account data is preallocated
+        return Ok(());
+      }
+      if instruction.program_id == system_program::id() && instruction.data[0] == 1 {
+        // assign
+        find(0).assign(&Pubkey::new_from_array(*array_ref![
+          instruction.data,
+          4,
+          32
+        ]));
+        return Ok(());
+      }
+      if instruction.program_id == system_program::id() {
+        // create_account: funds and assigns the account, fails with
+        // `AccountAlreadyInUse` as the system program does once it has lamports
+        let (payer, account) = (find(0), find(1));
+        if account.lamports() > 0 {
+          return Err(ProgramError::Custom(0));
+        }
+        let lamports = u64::from_le_bytes(*array_ref![instruction.data, 4, 8]);
+        **payer.lamports.borrow_mut() -= lamports;
+        **account.lamports.borrow_mut() += lamports;
+        account.assign(&Pubkey::new_from_array(*array_ref![
+          instruction.data,
+          20,
+          32
+        ]));
+        return Ok(());
+      }
+      if instruction.program_id == spl_token::ID && instruction.data[0] == 18 {
+        // initialize_account3
+        let lamports = find(0).lamports();
+        let mut data = find(0).data.borrow_mut();
+        data[0..32].copy_from_slice(instruction.accounts[1].pubkey.as_ref());
+        data[32..64].copy_from_slice(&instruction.data[1..33]);
+        data[0x6c] = 1;
+        if instruction.accounts[1].pubkey == spl_token::native_mint::id() {
+          // native account: the lamports over the rent are the amount
+          let reserve = Rent::default().minimum_balance(spl_token::ACCOUNT_LEN);
+          data[64..72].copy_from_slice(&(lamports - reserve).to_le_bytes());
+          data[109] = 1;
+          data[113..121].copy_from_slice(&reserve.to_le_bytes());
+        }
+        return Ok(());
+      }
+      if instruction.program_id == spl_token::ID && instruction.data[0] == 9 {
+        // close_account
+        let (account, destination) = (find(0), find(1));
+        let lamports = account.lamports();
+        **account.lamports.borrow_mut() = 0;
+        **destination.lamports.borrow_mut() += lamports;
+        account.data.borrow_mut().fill(0);
+        return Ok(());
+      }
+      let amount_in = u64::from_le_bytes(*array_ref![instruction.data, 1, 8]);
+      let (source, destination, amount_out) = if instruction.program_id == spl_token::ID {
+        (find(0), find(1), amount_in)
+      } else {
+        (find(3), find(6), amount_in * 2)
+      };
+      if instruction.program_id != spl_token::ID && instruction.accounts.len() == 11 {
+        // token-swap with a host fee account: mints it the host fee share of
+        // amount_in in pool tokens
+        let host_fee = {
+          let data = find(0).data.borrow();
+          let numerator = u64::from_le_bytes(*array_ref![data, 275, 8]);
+          let denominator = u64::from_le_bytes(*array_ref![data, 283, 8]);
+          amount_in * numerator / denominator
+        };
+        add_amount(find(10), host_fee, true);
+      }
+      add_amount(source, amount_in, false);
+      add_amount(destination, amount_out, true);
+      if instruction.program_id != spl_token::ID {
+        **destination.lamports.borrow_mut() += LAMPORT_TOP_UP.with(|t| *t.borrow());
+      }
+      Ok(())
+    }
 
-    let from_amount_changed = from_amount_before.checked_sub(from_amount_after).unwrap();
-    let to_amount_include_fee = to_amount_after.checked_sub(to_amount_before).unwrap();
-    msg!("from_amount changed: {}", from_amount_changed);
-    msg!("result_with_fee: {}", to_amount_include_fee);
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+      unsafe {
+        *(var_addr as *mut Rent) = Rent::default();
+      }
+      solana_program::entrypoint::SUCCESS
+    }
 
-    let mut swap_info = swap_info_args.swap_info;
-    swap_info.token_latest_amount = to_amount_include_fee;
-    SwapInfo::pack(
-      swap_info,
-      &mut swap_info_args.swap_info_acc.data.borrow_mut(),
-    )?;
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+      unsafe {
+        *(var_addr as *mut Clock) = Clock {
+          slot: STUB_SLOT,
+          ..Clock::default()
+        };
+      }
+      solana_program::entrypoint::SUCCESS
+    }
 
-    Ok(())
+    fn sol_set_return_data(&self, data: &[u8]) {
+      RETURN_DATA.with(|r| *r.borrow_mut() = Some((Pubkey::default(), data.to_vec())));
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+      RETURN_DATA.with(|r| r.borrow().clone())
+    }
   }
 
-  pub fn process_single_step_swap_out(
-    program_id: &Pubkey,
-    data: &SwapOutInstruction,
-    accounts: &[AccountInfo],
-    exchanger: ExchangerType,
-  ) -> ProgramResult {
-    if accounts.len() < 6 {
-      return Err(ProtocolError::InvalidAccountsLength.into());
+  const STUB_SLOT: u64 = 1_000;
+
+  fn serum_program_id() -> Pubkey {
+    Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap()
+  }
+
+  fn token_swap_program_id() -> Pubkey {
+    crate::parser::base::SPL_TOKEN_SWAP_PROGRAM_IDS[0]
+  }
+
+  fn add_amount(account: &AccountInfo, amount: u64, credit: bool) {
+    let mut data = account.data.borrow_mut();
+    let balance = u64::from_le_bytes(*array_ref![data, 64, 8]);
+    let balance = if credit {
+      balance + amount
+    } else {
+      balance - amount
+    };
+    data[64..72].copy_from_slice(&balance.to_le_bytes());
+    // native accounts move the lamports with the amount
+    if data[109] == 1 {
+      let mut lamports = account.lamports.borrow_mut();
+      **lamports = if credit {
+        **lamports + amount
+      } else {
+        **lamports - amount
+      };
     }
-    #[allow(clippy::ptr_offset_with_cast)]
-    let (fixed_accounts, other_accounts) = array_refs![accounts, 6; ..;];
+  }
 
-    let (
-      user_accounts,
-      &[ref swap_info_account, ref spl_token_program_acc, ref fee_token_account_acc],
-    ) = array_refs![fixed_accounts, 3, 3];
+  struct Fixture {
+    keys: Vec<Pubkey>,
+    accounts: Vec<Account>,
+  }
 
-    let user_args = UserArgs::with_parsed_args(user_accounts)?;
-    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
-    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+  impl Fixture {
+    fn new() -> Self {
+      Fixture {
+        keys: vec![],
+        accounts: vec![],
+      }
+    }
 
-    if !user_args.source_account_owner.is_signer {
-      return Err(ProtocolError::InvalidSignerAccount.into());
+    fn add_with_key(&mut self, key: Pubkey, owner: Pubkey, data: Vec<u8>) -> usize {
+      self.keys.push(key);
+      self.accounts.push(Account {
+        lamports: 1_000_000,
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 281,
+      });
+      self.accounts.len() - 1
     }
-    user_args
-      .token_source_account
-      .check_owner(user_args.source_account_owner.key, false)?;
 
-    if !swap_info_args.swap_info_acc.is_writable {
-      return Err(ProtocolError::ReadonlyAccount.into());
+    fn add_program(&mut self, key: Pubkey) -> usize {
+      let program = self.add_with_key(key, Pubkey::default(), vec![]);
+      self.accounts[program].executable = true;
+      program
     }
-    match swap_info_args.swap_info.token_account {
-      COption::Some(k) => {
-        if k != *user_args.token_source_account.pubkey() {
-          return Err(ProtocolError::InvalidTokenAccount.into());
-        }
+
+    fn add(&mut self, owner: Pubkey, data: Vec<u8>) -> usize {
+      self.add_with_key(Pubkey::new_unique(), owner, data)
+    }
+
+    /// The protocol config at its program derived address
+    fn add_config(&mut self, program_id: &Pubkey, data: Vec<u8>) -> usize {
+      self.add_with_key(derive_config_address(program_id).0, *program_id, data)
+    }
+
+    fn add_token_account(&mut self, mint: &Pubkey, amount: u64) -> usize {
+      let mut data = vec![0u8; spl_token::ACCOUNT_LEN];
+      data[0..32].copy_from_slice(mint.as_ref());
+      data[64..72].copy_from_slice(&amount.to_le_bytes());
+      data[0x6c] = 1;
+      self.add(spl_token::ID, data)
+    }
+
+    /// swap_info, authority, vault_a, vault_b, pool_mint, fee, program
+    fn add_token_swap(&mut self, program: &Pubkey, mint_a: &Pubkey, mint_b: &Pubkey) -> usize {
+      let mut swap_info_data = vec![0u8; 324];
+      swap_info_data[0] = 1;
+      swap_info_data[1] = 1;
+      let mut pool_mint_data = vec![0u8; spl_token::MINT_LEN];
+      pool_mint_data[0x2d] = 1;
+      let swap_info = self.add(*program, swap_info_data);
+      let authority = self.add(Pubkey::default(), vec![]);
+      for mint in [mint_a, mint_b] {
+        let vault = self.add_token_account(mint, 1_000_000);
+        let authority_key = self.keys[authority];
+        self.accounts[vault].data[32..64].copy_from_slice(authority_key.as_ref());
       }
-      COption::None => {
-        return Err(ProtocolError::InvalidTokenAccount.into());
+      self.add(spl_token::ID, pool_mint_data);
+      self.add_token_account(mint_a, 0);
+      self.add_program(*program);
+      swap_info
+    }
+
+    /// swap_info, authority, token_a, token_b, admin_fee, the clock sysvar
+    /// when `with_clock`, program
+    fn add_stable_swap(
+      &mut self,
+      program: &Pubkey,
+      mint_a: &Pubkey,
+      mint_b: &Pubkey,
+      with_clock: bool,
+    ) -> usize {
+      let swap_info_key = Pubkey::new_unique();
+      let (authority_key, nonce) = Pubkey::find_program_address(&[swap_info_key.as_ref()], program);
+      let swap_info = self.add_with_key(swap_info_key, *program, vec![0u8; 395]);
+      self.add_with_key(authority_key, Pubkey::default(), vec![]);
+      let token_a = self.add_token_account(mint_a, 1_000_000);
+      let token_b = self.add_token_account(mint_b, 1_000_000);
+      let admin_fee = self.add_token_account(mint_b, 0);
+      let keys = [self.keys[token_a], self.keys[token_b], self.keys[admin_fee]];
+      let data = &mut self.accounts[swap_info].data;
+      data[0] = 1;
+      data[2] = nonce;
+      data[107..139].copy_from_slice(keys[0].as_ref());
+      data[139..171].copy_from_slice(keys[1].as_ref());
+      data[267..299].copy_from_slice(keys[2].as_ref());
+      if with_clock {
+        self.add_with_key(
+          solana_program::sysvar::clock::id(),
+          solana_program::sysvar::id(),
+          vec![],
+        );
       }
+      self.add_program(*program);
+      swap_info
+    }
+
+    /// pool, amm_config, authority, vault_0, vault_1, mint_0, mint_1,
+    /// observation_state, program of a Raydium CP-Swap pool created by
+    /// `creator`
+    fn add_raydium_cpmm(&mut self, mint_0: &Pubkey, mint_1: &Pubkey, creator: &Pubkey) -> usize {
+      use crate::parser::raydium_cpmm::{test::amm_config_data, AUTH_SEED};
+      let program = crate::parser::base::RAYDIUM_CPMM_PROGRAM_IDS[0];
+      let (authority_key, auth_bump) = Pubkey::find_program_address(&[AUTH_SEED], &program);
+      let pool = self.add(program, vec![]);
+      let amm_config = self.add(program, amm_config_data());
+      self.add_with_key(authority_key, Pubkey::default(), vec![]);
+      let vault_0 = self.add_token_account(mint_0, 1_000_000);
+      let vault_1 = self.add_token_account(mint_1, 1_000_000);
+      let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+      mint_data[0x2d] = 1;
+      self.add_with_key(*mint_0, spl_token::ID, mint_data.clone());
+      self.add_with_key(*mint_1, spl_token::ID, mint_data);
+      let observation_state = self.add(program, vec![0u8; 64]);
+      self.add_program(program);
+      let keys = [amm_config, vault_0, vault_1, observation_state].map(|i| self.keys[i]);
+      let mut data = crate::parser::raydium_cpmm::test::pool_data(
+        [&keys[0], &keys[1], &keys[2], mint_0, mint_1, &keys[3]],
+        auth_bump,
+      );
+      data[40..72].copy_from_slice(creator.as_ref());
+      self.accounts[pool].data = data;
+      pool
+    }
+
+    fn infos(&mut self) -> Vec<AccountInfo> {
+      self
+        .keys
+        .iter()
+        .zip(self.accounts.iter_mut())
+        .map(|(key, account)| {
+          AccountInfo::new(
+            key,
+            false,
+            true,
+            &mut account.lamports,
+            &mut account.data[..],
+            &account.owner,
+            account.executable,
+            account.rent_epoch,
+          )
+        })
+        .collect()
+    }
+  }
+
+  #[test]
+  fn test_step_returns_destination_delta() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let authority = fixture.add(Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let infos = fixture.infos();
+    let protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+
+    let step = || {
+      Processor::process_step_tokenswap(
+        &Pubkey::new_unique(),
+        ExchangerType::SplTokenSwap,
+        500,
+        1,
+        &TokenAccount::new(&infos[source]).unwrap(),
+        &TokenAccount::new(&infos[destination]).unwrap(),
+        &infos[authority],
+        &SplTokenProgram::new(&infos[token_program]).unwrap(),
+        &infos[pool..pool + FEE_POOL_ACCOUNTS],
+        &protocol_config,
+        0,
+        None,
+      )
     };
+    assert_eq!(step(), Ok(1_000));
+    // source is empty, nothing is swapped
+    assert_eq!(step(), Ok(0));
+    assert_eq!(
+      TokenAccount::new(&infos[destination]).unwrap().balance(),
+      Ok(1_000)
+    );
 
-    msg!(
-      "source_token_account amount: {}",
-      user_args.token_source_account.balance()?,
+    // a vault with the pool mint that the pool authority does not own
+    infos[pool + 3].data.borrow_mut()[32..64].copy_from_slice(Pubkey::new_unique().as_ref());
+    assert_eq!(step(), Err(ProtocolError::InvalidTokenAccount.into()));
+  }
+
+  #[test]
+  fn test_swap_mint_routing_mismatch() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
     );
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_b, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let other_source = fixture.add_token_account(&mint_c, 1_000);
+    let other_destination = fixture.add_token_account(&mint_c, 0);
+    let token_swap = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let stable_swap = fixture.add_stable_swap(
+      &crate::parser::base::STABLE_SWAP_PROGRAM_IDS[0],
+      &mint_a,
+      &mint_b,
+      false,
+    );
+    for i in [source, destination, other_source, other_destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |source: usize, destination: usize, pool: Range<usize>, exchanger| {
+      let user_accounts = [infos[source].clone(), infos[destination].clone()];
+      let accounts = [
+        &user_accounts[..],
+        &infos[owner..config],
+        &infos[pool],
+        &infos[config..=config],
+      ]
+      .concat();
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        exchanger,
+      )
+    };
 
-    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
-    if fee_token_account.mint()? != user_args.token_destination_account.mint()? {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    // both pools trade a and b, in either orientation a c leg can't be routed
+    for (pool, exchanger) in [
+      (token_swap..stable_swap, ExchangerType::SplTokenSwap),
+      (stable_swap..infos.len(), ExchangerType::StableSwap),
+    ] {
+      for (source, destination) in [(source, other_destination), (other_source, destination)] {
+        assert_eq!(
+          swap(source, destination, pool.clone(), exchanger),
+          Err(ProtocolError::MintRoutingMismatch.into())
+        );
+      }
     }
-    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    assert_eq!((balance(source), balance(other_source)), (1_000, 1_000));
+    swap(
+      source,
+      destination,
+      token_swap..stable_swap,
+      ExchangerType::SplTokenSwap,
+    )
+    .unwrap();
+    assert_eq!((balance(source), balance(destination)), (500, 1_000));
+  }
+
+  #[test]
+  fn test_settle_open_orders() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let serum_program = serum_program_id();
+    let market_data =
+      bs58::decode(crate::parser::serum_dex::tests::SERUM_MARKET_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap();
+    let read_key = |offset: usize| Pubkey::new_from_array(*array_ref![market_data, 5 + offset, 32]);
+    let (market_key, coin_mint, pc_mint) = (read_key(8), read_key(48), read_key(80));
+    let vault_signer = Pubkey::create_program_address(
+      &[market_key.as_ref(), array_ref![market_data, 5 + 40, 8]],
+      &serum_program,
+    )
+    .unwrap();
+    let user = Pubkey::new_unique();
+    let (authority_key, _) =
+      serum_dex::order::find_open_orders_authority(&market_key, &user, &program_id);
+
+    // residual free balances left by partial IOC fills
+    let open_orders_data = |owner: &Pubkey| {
+      let mut data = vec![0u8; OPEN_ORDERS_LEN];
+      data[5] = 5;
+      data[5 + 8..5 + 40].copy_from_slice(market_key.as_ref());
+      data[5 + 40..5 + 72].copy_from_slice(owner.as_ref());
+      data[5 + 72..5 + 80].copy_from_slice(&30u64.to_le_bytes());
+      data[5 + 88..5 + 96].copy_from_slice(&70u64.to_le_bytes());
+      data
+    };
+    let mut fixture = Fixture::new();
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let open_orders = fixture.add(serum_program, open_orders_data(&user));
+    fixture.add_with_key(market_key, serum_program, market_data.clone());
+    fixture.add_token_account(&coin_mint, 1_000);
+    fixture.add_token_account(&pc_mint, 1_000);
+    let coin_wallet = fixture.add_token_account(&coin_mint, 0);
+    let pc_wallet = fixture.add_token_account(&pc_mint, 0);
+    fixture.add_with_key(vault_signer, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_program(serum_program);
+    fixture.add_with_key(authority_key, Pubkey::default(), vec![]);
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..10]),
+      Ok(())
+    );
+    assert_eq!((balance(coin_wallet), balance(pc_wallet)), (30, 70));
+    assert_eq!(
+      SerumDexOpenOrders::new(&infos[open_orders])
+        .unwrap()
+        .free_balances(),
+      Ok((0, 0))
+    );
+
+    // open orders of another owner
+    infos[open_orders]
+      .data
+      .borrow_mut()
+      .copy_from_slice(&open_orders_data(&Pubkey::new_unique()));
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..10]),
+      Err(ProtocolError::InvalidOpenOrdersAccount.into())
+    );
+
+    // program-owned open orders, settled by the authority PDA
+    infos[open_orders]
+      .data
+      .borrow_mut()
+      .copy_from_slice(&open_orders_data(&authority_key));
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..10]),
+      Err(ProtocolError::InvalidOpenOrdersAccount.into())
+    );
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..11]),
+      Ok(())
+    );
+    assert_eq!((balance(coin_wallet), balance(pc_wallet)), (60, 140));
+    assert_eq!(
+      Processor::process_settle_open_orders(&Pubkey::new_unique(), &infos[..11]),
+      Err(ProtocolError::InvalidOpenOrdersAccount.into())
+    );
+
+    infos[owner].is_signer = false;
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..11]),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+  }
+
+  #[test]
+  fn test_serum_step_settle_layouts() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let serum_program = serum_program_id();
+    let market_data =
+      bs58::decode(crate::parser::serum_dex::tests::SERUM_MARKET_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap();
+    let read_key = |offset: usize| Pubkey::new_from_array(*array_ref![market_data, 5 + offset, 32]);
+    let (market_key, coin_mint, pc_mint) = (read_key(8), read_key(48), read_key(80));
+    let vault_signer = Pubkey::create_program_address(
+      &[market_key.as_ref(), array_ref![market_data, 5 + 40, 8]],
+      &serum_program,
+    )
+    .unwrap();
+    let user = Pubkey::new_unique();
+    let mut open_orders_data = vec![0u8; OPEN_ORDERS_LEN];
+    open_orders_data[5] = 5;
+    open_orders_data[5 + 8..5 + 40].copy_from_slice(market_key.as_ref());
+    open_orders_data[5 + 40..5 + 72].copy_from_slice(user.as_ref());
+
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&pc_mint, 3_000);
+    let destination = fixture.add_token_account(&coin_mint, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    // open orders, market, queues and book sides, vaults, vault signer, rent
+    // and program, followed by the optional referral
+    let pool = fixture.add(serum_program, open_orders_data);
+    fixture.add_with_key(market_key, serum_program, market_data.clone());
+    for _ in 0..4 {
+      fixture.add(serum_program, vec![]);
+    }
+    fixture.add_token_account(&coin_mint, 1_000_000);
+    fixture.add_token_account(&pc_mint, 1_000_000);
+    fixture.add_with_key(vault_signer, Pubkey::default(), vec![]);
+    fixture.add_with_key(
+      solana_program::sysvar::rent::id(),
+      solana_program::sysvar::id(),
+      vec![],
+    );
+    fixture.add_program(serum_program);
+    let pc_referral = fixture.add_token_account(&pc_mint, 0);
+    let coin_referral = fixture.add_token_account(&coin_mint, 0);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+    let step = |referral: Option<usize>| {
+      let referral = referral.map(|i| infos[i].clone());
+      let accounts = [&infos[pool..pc_referral], referral.as_slice()].concat();
+      let out = Processor::process_step_serumdex(
+        &Pubkey::new_unique(),
+        1_000,
+        1,
+        &TokenAccount::new(&infos[source]).unwrap(),
+        &TokenAccount::new(&infos[destination]).unwrap(),
+        &infos[owner],
+        &SplTokenProgram::new(&infos[token_program]).unwrap(),
+        &accounts,
+        &protocol_config,
+        false,
+        None,
+        false,
+        0,
+      );
+      (out, SETTLE_REFERRAL.with(|r| r.borrow_mut().take()))
+    };
 
-    if let Some(delegate) = fee_token_account.delegate()? {
-      if delegate == *user_args.source_account_owner.key {
-        return Err(ProtocolError::InvalidFeeTokenAccount.into());
-      }
+    // minimal layout without a referral
+    assert_eq!(step(None), (Ok(2_000), None));
+    // a referral wallet of the quote mint is passed to settle
+    assert_eq!(
+      step(Some(pc_referral)),
+      (Ok(2_000), Some(*infos[pc_referral].key))
+    );
+    // the dex would reject a referral in another mint, settle without it
+    assert_eq!(step(Some(coin_referral)), (Ok(2_000), None));
+    assert_eq!(
+      TokenAccount::new(&infos[destination]).unwrap().balance(),
+      Ok(6_000)
+    );
+  }
+
+  #[test]
+  fn test_close_serum_open_orders() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let serum_program = serum_program_id();
+    let market_data =
+      bs58::decode(crate::parser::serum_dex::tests::SERUM_MARKET_DATA.replace('\n', ""))
+        .into_vec()
+        .unwrap();
+    let read_key = |offset: usize| Pubkey::new_from_array(*array_ref![market_data, 5 + offset, 32]);
+    let (market_key, coin_mint, pc_mint) = (read_key(8), read_key(48), read_key(80));
+    let vault_signer = Pubkey::create_program_address(
+      &[market_key.as_ref(), array_ref![market_data, 5 + 40, 8]],
+      &serum_program,
+    )
+    .unwrap();
+    let user = Pubkey::new_unique();
+    let (open_orders_key, _) =
+      serum_dex::order::find_open_orders_address(&market_key, &user, &program_id);
+    let (authority_key, _) =
+      serum_dex::order::find_open_orders_authority(&market_key, &user, &program_id);
+
+    // managed open orders left with the free balance of a filled order
+    let mut open_orders_data = vec![0u8; OPEN_ORDERS_LEN];
+    open_orders_data[5] = 5;
+    open_orders_data[5 + 8..5 + 40].copy_from_slice(market_key.as_ref());
+    open_orders_data[5 + 40..5 + 72].copy_from_slice(authority_key.as_ref());
+    for offset in [72, 80] {
+      open_orders_data[5 + offset..5 + offset + 8].copy_from_slice(&30u64.to_le_bytes());
     }
-    let from_amount_before = user_args.token_source_account.balance()?;
-    let to_amount_before = user_args.token_destination_account.balance()?;
+    let mut fixture = Fixture::new();
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let open_orders = fixture.add_with_key(open_orders_key, serum_program, open_orders_data);
+    let market = fixture.add_with_key(market_key, serum_program, market_data.clone());
+    fixture.add_token_account(&coin_mint, 1_000);
+    fixture.add_token_account(&pc_mint, 1_000);
+    let coin_wallet = fixture.add_token_account(&coin_mint, 0);
+    fixture.add_token_account(&pc_mint, 0);
+    fixture.add_with_key(vault_signer, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let program = fixture.add_program(serum_program);
+    let authority = fixture.add_with_key(authority_key, Pubkey::default(), vec![]);
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let close_accounts = [owner, open_orders, market, authority, program]
+      .iter()
+      .map(|i| infos[*i].clone())
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      Processor::process_close_serum_open_orders(&program_id, &close_accounts),
+      Err(ProtocolError::OpenOrdersNotEmpty.into())
+    );
+    assert_eq!(
+      Processor::process_settle_open_orders(&program_id, &infos[..11]),
+      Ok(())
+    );
+    assert_eq!(
+      TokenAccount::new(&infos[coin_wallet]).unwrap().balance(),
+      Ok(30)
+    );
 
-    let amount_in = swap_info_args.swap_info.token_latest_amount;
-    let amount_out = data.minimum_amount_out.get();
-    msg!(
-      "from_amount_before: {}, to_amount_before: {}, amount_in: {}, expect_amount_out: {}, minimum_amount_out: {}",
-      from_amount_before,
-      to_amount_before,
-      amount_in,
-      data.expect_amount_out,
-      data.minimum_amount_out,
+    // open orders PDA of another owner
+    assert_eq!(
+      Processor::process_close_serum_open_orders(&Pubkey::new_unique(), &close_accounts),
+      Err(ProtocolError::InvalidOpenOrdersAccount.into())
+    );
+    let mut wrong_authority = close_accounts.clone();
+    wrong_authority[3] = infos[coin_wallet].clone();
+    assert_eq!(
+      Processor::process_close_serum_open_orders(&program_id, &wrong_authority),
+      Err(ProtocolError::InvalidAuthority.into())
     );
 
-    match exchanger {
-      ExchangerType::SplTokenSwap => Self::process_step_tokenswap(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::StableSwap => Self::process_step_stableswap(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwap => Self::process_step_raydium(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::RaydiumSwapSlim => Self::process_step_raydium_slim(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::SerumDex => Self::process_step_serumdex(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
+    assert_eq!(
+      Processor::process_close_serum_open_orders(&program_id, &close_accounts),
+      Ok(())
+    );
+    assert_eq!(infos[open_orders].lamports(), 0);
+    assert_eq!(infos[owner].lamports(), 2_000_000);
+
+    infos[owner].is_signer = false;
+    let close_accounts = [owner, open_orders, market, authority, program]
+      .iter()
+      .map(|i| infos[*i].clone())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      Processor::process_close_serum_open_orders(&program_id, &close_accounts),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+  }
+
+  #[test]
+  fn test_charge_fee_in_reference_mint() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let reference_mint = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let authority = fixture.add(Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let pool = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let fee_pool = fixture.add_token_swap(&swap_program, &mint_b, &reference_mint);
+    let reference_fee_account = fixture.add_token_account(&reference_mint, 0);
+    let destination_fee_account = fixture.add_token_account(&mint_b, 0);
+    let infos = fixture.infos();
+    let mut protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+    protocol_config.fee_reference_mint = COption::Some(reference_mint);
+
+    let destination_account = TokenAccount::new(&infos[destination]).unwrap();
+    let spl_token_program = SplTokenProgram::new(&infos[token_program]).unwrap();
+    let out = Processor::process_step_tokenswap(
+      &swap_program,
+      ExchangerType::SplTokenSwap,
+      500,
+      1,
+      &TokenAccount::new(&infos[source]).unwrap(),
+      &destination_account,
+      &infos[authority],
+      &spl_token_program,
+      &infos[pool..pool + FEE_POOL_ACCOUNTS],
+      &protocol_config,
+      0,
+      None,
+    )
+    .unwrap();
+    assert_eq!(out, 1_000);
+
+    // destination is not the reference mint: the fee goes through the fee pool
+    let fee = 25;
+    Processor::charge_fee(
+      &swap_program,
+      fee,
+      (500, 1_000),
+      &destination_account,
+      &TokenAccount::new(&infos[reference_fee_account]).unwrap(),
+      &infos[authority],
+      &spl_token_program,
+      &infos[fee_pool..fee_pool + FEE_POOL_ACCOUNTS],
+      &protocol_config,
+    )
+    .unwrap();
+    assert_eq!(destination_account.balance(), Ok(975));
+    assert_eq!(
+      TokenAccount::new(&infos[reference_fee_account])
+        .unwrap()
+        .balance(),
+      Ok(50)
+    );
+
+    // fee account in the destination mint: plain transfer, no pool needed
+    Processor::charge_fee(
+      &swap_program,
+      fee,
+      (500, 1_000),
+      &destination_account,
+      &TokenAccount::new(&infos[destination_fee_account]).unwrap(),
+      &infos[authority],
+      &spl_token_program,
+      &[],
+      &protocol_config,
+    )
+    .unwrap();
+    assert_eq!(destination_account.balance(), Ok(950));
+    assert_eq!(
+      TokenAccount::new(&infos[destination_fee_account])
+        .unwrap()
+        .balance(),
+      Ok(fee)
+    );
+
+    // a blocked fee pool is refused like any other pool
+    protocol_config
+      .add_blocked_pool(infos[fee_pool].key)
+      .unwrap();
+    assert_eq!(
+      Processor::charge_fee(
+        &swap_program,
+        fee,
+        (500, 1_000),
+        &destination_account,
+        &TokenAccount::new(&infos[reference_fee_account]).unwrap(),
+        &infos[authority],
         &spl_token_program,
-        other_accounts,
+        &infos[fee_pool..fee_pool + FEE_POOL_ACCOUNTS],
+        &protocol_config,
       ),
-      ExchangerType::CremaFinance => Self::process_step_crema_finance(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
+      Err(ProtocolError::PoolBlocked.into())
+    );
+
+    // the fee pool pays 2 per fee token, under the 5 the swap itself got
+    protocol_config
+      .remove_blocked_pool(infos[fee_pool].key)
+      .unwrap();
+    assert_eq!(
+      Processor::charge_fee(
+        &swap_program,
+        fee,
+        (5_000, 1_000),
+        &destination_account,
+        &TokenAccount::new(&infos[reference_fee_account]).unwrap(),
+        &infos[authority],
         &spl_token_program,
-        other_accounts,
+        &infos[fee_pool..fee_pool + FEE_POOL_ACCOUNTS],
+        &protocol_config,
       ),
-      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
+      Err(ProtocolError::ExceededSlippage.into())
+    );
+  }
+
+  #[test]
+  fn test_swap_fee_in_reference_mint() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    // the fee is taken in the source mint, swapped back through the fee pool
+    let fee_account = fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.add_token_swap(&swap_program, &mint_b, &mint_a);
+    let config = fixture.add_config(&program_id, vec![0u8; ProtocolConfig::LEN]);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |reference_mint: &Pubkey| {
+      let mut protocol_config = ProtocolConfig::new(&user);
+      protocol_config.fee_reference_mint = COption::Some(*reference_mint);
+      ProtocolConfig::pack(protocol_config, &mut infos[config].data.borrow_mut()).unwrap();
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(100).unwrap(),
+          minimum_amount_out: NonZeroU64::new(100).unwrap(),
+          flags: SWAP_FLAG_FEE_IN_REFERENCE_MINT,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &infos,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+
+    // 100 in, 200 out: the 25 fee needs at least 12 back, the pool pays 50
+    swap(&mint_a).unwrap();
+    assert_eq!(
+      (balance(source), balance(destination), balance(fee_account)),
+      (900, 175, 50)
+    );
+    // a reference mint the swap does not price
+    assert_eq!(
+      swap(&Pubkey::new_unique()),
+      Err(ProtocolError::FeeReferenceMintNotSource.into())
+    );
+  }
+
+  #[test]
+  fn test_pay_treasury() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let destination = fixture.add_token_account(&mint_b, 1_000);
+    let authority = fixture.add(Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let treasury = fixture.add_token_account(&mint_b, 0);
+    let other_mint_treasury = fixture.add_token_account(&mint_a, 0);
+    let infos = fixture.infos();
+    let destination_account = TokenAccount::new(&infos[destination]).unwrap();
+    let spl_token_program = SplTokenProgram::new(&infos[token_program]).unwrap();
+    let mut protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+    let pay = |treasury_acc: Option<&AccountInfo<'_>>, config: &ProtocolConfig| {
+      Processor::pay_treasury(
+        100,
+        treasury_acc,
+        &destination_account,
+        &infos[authority],
         &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
+        config,
+      )
+    };
+
+    // no treasury configured: the whole fee is left for the fee account
+    assert_eq!(pay(None, &protocol_config), Ok(100));
+    assert_eq!(
+      pay(Some(&infos[treasury]), &protocol_config),
+      Err(ProtocolError::InvalidTreasuryAccount.into())
+    );
+
+    protocol_config.treasury_token_account = COption::Some(*infos[treasury].key);
+    protocol_config.treasury_bps = 2_000;
+    assert_eq!(
+      pay(None, &protocol_config),
+      Err(ProtocolError::InvalidTreasuryAccount.into())
+    );
+    assert_eq!(
+      pay(Some(&infos[other_mint_treasury]), &protocol_config),
+      Err(ProtocolError::InvalidTreasuryAccount.into())
+    );
+    assert_eq!(pay(Some(&infos[treasury]), &protocol_config), Ok(80));
+    assert_eq!(destination_account.balance(), Ok(980));
+    assert_eq!(
+      TokenAccount::new(&infos[treasury]).unwrap().balance(),
+      Ok(20)
+    );
+
+    // the treasury must hold the fee mint, unless there is no fee to share
+    protocol_config.treasury_token_account = COption::Some(*infos[other_mint_treasury].key);
+    assert_eq!(
+      pay(Some(&infos[other_mint_treasury]), &protocol_config),
+      Err(ProtocolError::InvalidTokenMint.into())
+    );
+    assert_eq!(
+      Processor::pay_treasury(
+        0,
+        Some(&infos[other_mint_treasury]),
+        &destination_account,
+        &infos[authority],
         &spl_token_program,
-        other_accounts,
+        &protocol_config,
       ),
-    }?;
+      Ok(0)
+    );
+  }
 
-    let from_amount_after = user_args.token_source_account.balance()?;
-    let to_amount_after = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_after: {}, to_amount_after: {}",
-      from_amount_after,
-      to_amount_after
+  #[test]
+  fn test_set_exchanger_enabled() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (user, admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&admin), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    fixture.accounts[source].data[32..64].copy_from_slice(user.as_ref());
+    fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_b, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let config = fixture.add_config(&program_id, config_data);
+    let admin = fixture.add_with_key(admin, Pubkey::default(), vec![]);
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+
+    let set_enabled = |infos: &[AccountInfo], exchanger: ExchangerType, enabled: bool| {
+      Processor::process_set_exchanger_enabled(
+        &program_id,
+        &ExchangerEnabledInstruction { exchanger, enabled },
+        &[infos[config].clone(), infos[admin].clone()],
+      )
+    };
+    let swap = |infos: &[AccountInfo]| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(900).unwrap(),
+          minimum_amount_out: NonZeroU64::new(890).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &infos[..admin],
+        ExchangerType::SplTokenSwap,
+      )
+    };
+
+    assert_eq!(
+      set_enabled(&infos, ExchangerType::AldrinExchange, false),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+    infos[admin].is_signer = true;
+    set_enabled(&infos, ExchangerType::AldrinExchange, false).unwrap();
+    // other exchangers go past the check, up to the fee account owner once a
+    // fee is owed
+    assert_eq!(
+      swap(&infos),
+      Err(ProtocolError::InvalidFeeTokenAccount.into())
+    );
+    set_enabled(&infos, ExchangerType::SplTokenSwap, false).unwrap();
+    assert_eq!(swap(&infos), Err(ProtocolError::ExchangerDisabled.into()));
+    set_enabled(&infos, ExchangerType::SplTokenSwap, true).unwrap();
+    assert_eq!(
+      swap(&infos),
+      Err(ProtocolError::InvalidFeeTokenAccount.into())
+    );
+    let protocol_config = ProtocolConfig::unpack(&infos[config].data.borrow()).unwrap();
+    assert_eq!(
+      protocol_config.check_exchanger(ExchangerType::AldrinExchange),
+      Err(ProtocolError::ExchangerDisabled)
     );
 
-    let from_amount_changed = from_amount_before.checked_sub(from_amount_after).unwrap();
-    let to_amount_include_fee = to_amount_after.checked_sub(to_amount_before).unwrap();
-    msg!("from_amount changed: {}", from_amount_changed);
-    msg!(
-      "result_with_fee: {}, expect: {}, minimum: {}",
-      to_amount_include_fee,
-      data.expect_amount_out,
-      data.minimum_amount_out,
+    // signed, but not by the config owner
+    assert_eq!(
+      Processor::process_set_exchanger_enabled(
+        &program_id,
+        &ExchangerEnabledInstruction {
+          exchanger: ExchangerType::SplTokenSwap,
+          enabled: false,
+        },
+        &[infos[config].clone(), infos[owner].clone()],
+      ),
+      Err(ProtocolError::InvalidOwner.into())
     );
-    if to_amount_include_fee == 0 {
-      return Err(ProtocolError::DexSwapError.into());
+  }
+
+  #[test]
+  fn test_initialize_config() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let owner_key = Pubkey::from_str(OWNER_KEY).unwrap();
+    let rent = Rent::default().minimum_balance(ProtocolConfig::LEN);
+    let initialize = |config_key: Pubkey, config_lamports: u64| {
+      let mut fixture = Fixture::new();
+      let config = fixture.add_with_key(
+        config_key,
+        system_program::id(),
+        vec![0u8; ProtocolConfig::LEN],
+      );
+      fixture.accounts[config].lamports = config_lamports;
+      let owner = fixture.add_with_key(owner_key, system_program::id(), vec![]);
+      fixture.accounts[owner].lamports = 100_000_000;
+      fixture.add_program(system_program::id());
+      let mut infos = fixture.infos();
+      infos[owner].is_signer = true;
+      Processor::process_initialize_config(&program_id, &infos)?;
+      assert_eq!(*infos[config].owner, program_id);
+      assert_eq!(infos[config].lamports(), rent);
+      Ok::<_, ProgramError>(ProtocolConfig::unpack(&infos[config].data.borrow()).unwrap())
+    };
+    let config_key = derive_config_address(&program_id).0;
+
+    assert_eq!(
+      initialize(config_key, 0),
+      Ok(ProtocolConfig::new(&owner_key))
+    );
+    // lamports sent to the address beforehand don't block the creation
+    assert_eq!(
+      initialize(config_key, 1_000),
+      Ok(ProtocolConfig::new(&owner_key))
+    );
+    assert_eq!(
+      initialize(Pubkey::new_unique(), 0),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+  }
+
+  #[test]
+  fn test_get_config() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&admin);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let config_index = fixture.add_config(&program_id, config_data);
+    let infos = fixture.infos();
+
+    Processor::process_get_config(&program_id, &infos).unwrap();
+    let (_, return_data) = get_return_data().unwrap();
+    assert_eq!(
+      ConfigView::unpack(&return_data),
+      Ok(ConfigView {
+        fee_numerator: 25,
+        fee_denominator: 100,
+        owner: admin,
+        paused: false,
+        fee_token_owner: Pubkey::from_str(OWNER_KEY).unwrap(),
+      })
+    );
+
+    config.disabled_exchangers = u16::MAX;
+    ProtocolConfig::pack(config, &mut infos[config_index].data.borrow_mut()).unwrap();
+    Processor::process_get_config(&program_id, &infos).unwrap();
+    let (_, return_data) = get_return_data().unwrap();
+    assert!(ConfigView::unpack(&return_data).unwrap().paused);
+
+    assert_eq!(
+      Processor::process_get_config(&program_id, &[]),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+  }
+
+  #[test]
+  fn test_zero_fee_swap_skips_fee_account() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    // wrong mint and not owned by the fee owner
+    fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |expect_amount_out: u64, accounts: &[AccountInfo]| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(expect_amount_out).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    if to_amount_include_fee < data.minimum_amount_out.get() {
-      return Err(ProtocolError::ExceededSlippage.into());
+    // 1_000 out, no surplus over the expectation, no fee owed
+    swap(1_000, &infos).unwrap();
+    assert_eq!((balance(source), balance(destination)), (500, 1_000));
+    // a surplus owes a fee, the fee account is checked then
+    assert_eq!(
+      swap(900, &infos),
+      Err(ProtocolError::InvalidFeeTokenAccount.into())
+    );
+  }
+
+  #[test]
+  fn test_swap_requires_config() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_b, 0);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config
+      .add_blocked_pool(&fixture.keys[pool])
+      .unwrap();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(protocol_config, &mut config_data).unwrap();
+    let config = fixture.add_config(&program_id, config_data);
+    // a config that blocks nothing, away from the config address
+    let mut open_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut open_data).unwrap();
+    let look_alike = fixture.add(program_id, open_data.clone());
+    // at the config address, but owned by another program
+    let foreign = fixture.add_with_key(
+      derive_config_address(&program_id).0,
+      Pubkey::new_unique(),
+      open_data,
+    );
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let swap = |config: Option<usize>| {
+      let mut accounts = infos[..config].to_vec();
+      accounts.extend(config.map(|i| infos[i].clone()));
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    let fee = to_amount_include_fee
-      .checked_sub(data.expect_amount_out.get())
-      .map(|v| v.checked_mul(25).unwrap().checked_div(100).unwrap_or(0))
-      .unwrap_or(0);
+    assert_eq!(swap(Some(config)), Err(ProtocolError::PoolBlocked.into()));
+    // the blocklist can't be skipped by leaving the config out or passing
+    // another one
+    assert_eq!(swap(None), Err(ProtocolError::InvalidOwner.into()));
+    assert_eq!(
+      swap(Some(look_alike)),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    assert_eq!(swap(Some(foreign)), Err(ProtocolError::InvalidOwner.into()));
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    assert_eq!((balance(source), balance(destination)), (1_000, 0));
+  }
 
-    if fee > 0 {
-      Self::token_transfer(
-        spl_token_program.inner(),
-        user_args.token_destination_account.inner(),
-        fee_token_account.inner(),
-        user_args.source_account_owner,
-        fee,
-      )?;
+  #[test]
+  fn test_fee_exempt_owner() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (user, admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&admin), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_500);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    // wrong mint and not owned by the fee owner
+    fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let config = fixture.add_config(&program_id, config_data);
+    let admin = fixture.add_with_key(admin, Pubkey::default(), vec![]);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    let mut swap_info = swap_info_args.swap_info;
-    swap_info.token_latest_amount = to_amount_include_fee;
-    swap_info.token_account = COption::None;
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let update = |infos: &[AccountInfo], signer: usize, exempt: bool| {
+      Processor::process_update_fee_exempt_owner(
+        &program_id,
+        &FeeExemptOwnerInstruction { owner: user },
+        &[infos[config].clone(), infos[signer].clone()],
+        exempt,
+      )
+    };
+    let swap = |infos: &[AccountInfo]| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(900).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &infos[..admin],
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    SwapInfo::pack(
-      swap_info,
-      &mut swap_info_args.swap_info_acc.data.borrow_mut(),
-    )?;
-    Ok(())
+    // the surplus owes a fee, the invalid fee account is checked
+    assert_eq!(
+      swap(&infos),
+      Err(ProtocolError::InvalidFeeTokenAccount.into())
+    );
+    // only the config owner can exempt a wallet
+    assert_eq!(
+      update(&infos, owner, true),
+      Err(ProtocolError::InvalidOwner.into())
+    );
+    assert_eq!(
+      update(&infos, admin, true),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+    infos[admin].is_signer = true;
+    update(&infos, admin, true).unwrap();
+
+    // exempt, the whole 1_000 out is kept and the fee account left alone
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let destination_before = balance(destination);
+    swap(&infos).unwrap();
+    assert_eq!(balance(destination) - destination_before, 1_000);
+
+    update(&infos, admin, false).unwrap();
+    assert_eq!(
+      swap(&infos),
+      Err(ProtocolError::InvalidFeeTokenAccount.into())
+    );
   }
 
-  pub fn process_single_step_swap_out_slim(
-    program_id: &Pubkey,
-    data: &SwapOutSlimInstruction,
-    accounts: &[AccountInfo],
-    exchanger: ExchangerType,
-  ) -> ProgramResult {
-    if accounts.len() < 6 {
-      return Err(ProtocolError::InvalidAccountsLength.into());
+  #[test]
+  fn test_oracle_expect_amount_out() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (user, oracle) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let oracle_acc = fixture.add_with_key(oracle, Pubkey::default(), vec![]);
+    let forged = fixture.add(Pubkey::default(), vec![]);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    #[allow(clippy::ptr_offset_with_cast)]
-    let (fixed_accounts, other_accounts) = array_refs![accounts, 6; ..;];
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    infos[forged].is_signer = true;
+    let swap_expecting = |infos: &[AccountInfo], signer: Option<usize>, expect_amount_out: u64| {
+      let mut accounts = [&infos[..config], &infos[config + 1..oracle_acc]].concat();
+      accounts.extend(signer.map(|signer| infos[signer].clone()));
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(250).unwrap(),
+          expect_amount_out: NonZeroU64::new(expect_amount_out).unwrap(),
+          minimum_amount_out: NonZeroU64::new(450).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: if signer.is_some() {
+            SWAP_EXTRA_FLAG_ORACLE_EXPECT_AMOUNT_OUT
+          } else {
+            0
+          },
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+    let swap = |infos: &[AccountInfo], signer: usize| swap_expecting(infos, Some(signer), 500);
 
-    let (
-      user_accounts,
-      &[ref swap_info_account, ref spl_token_program_acc, ref fee_token_account_acc],
-    ) = array_refs![fixed_accounts, 3, 3];
+    // no oracle configured
+    assert_eq!(
+      swap(&infos, oracle_acc),
+      Err(ProtocolError::InvalidExpectOracle.into())
+    );
+    Processor::process_set_expect_oracle(
+      &program_id,
+      &ExpectOracleInstruction {
+        oracle: COption::Some(oracle),
+      },
+      &[infos[config].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    // the configured oracle didn't sign
+    assert_eq!(
+      swap(&infos, oracle_acc),
+      Err(ProtocolError::InvalidExpectOracle.into())
+    );
+    // a signer that isn't the configured oracle
+    assert_eq!(
+      swap(&infos, forged),
+      Err(ProtocolError::InvalidExpectOracle.into())
+    );
+    infos[oracle_acc].is_signer = true;
+    swap(&infos, oracle_acc).unwrap();
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    assert_eq!((balance(source), balance(destination)), (750, 500));
+
+    // from the threshold of the destination mint the oracle is mandatory,
+    // leaving the flag off doesn't skip it
+    Processor::process_set_expect_oracle_threshold(
+      &program_id,
+      &ExpectOracleThresholdInstruction {
+        mint: mint_b,
+        threshold: 500,
+      },
+      &[infos[config].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    assert_eq!(
+      swap_expecting(&infos, None, 500),
+      Err(ProtocolError::InvalidExpectOracle.into())
+    );
+    assert_eq!(balance(source), 750);
+    swap_expecting(&infos, None, 499).unwrap();
+    swap_expecting(&infos, Some(oracle_acc), 500).unwrap();
+    assert_eq!((balance(source), balance(destination)), (250, 1_500));
+  }
 
-    let user_args = UserArgs::with_parsed_args(user_accounts)?;
-    let swap_info_args = SwapInfoArgs::with_parsed_args(swap_info_account, program_id)?;
-    let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+  #[test]
+  fn test_expected_pool() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    // same mints, another pool
+    let look_alike = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    let expected_pool = fixture.keys[pool];
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let swap = |infos: &[AccountInfo], pool: usize| {
+      let mut accounts = infos[..config].to_vec();
+      accounts.extend_from_slice(&infos[pool..pool + 7]);
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(200).unwrap(),
+          minimum_amount_out: NonZeroU64::new(200).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: Some(expected_pool),
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    if !user_args.source_account_owner.is_signer {
-      return Err(ProtocolError::InvalidSignerAccount.into());
+    assert_eq!(
+      swap(&infos, look_alike),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    assert_eq!((balance(source), balance(destination)), (1_000, 0));
+    swap(&infos, pool).unwrap();
+    assert_eq!((balance(source), balance(destination)), (900, 200));
+  }
+
+  #[test]
+  fn test_min_source_remaining() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 400);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    user_args
-      .token_source_account
-      .check_owner(user_args.source_account_owner.key, false)?;
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |amount_in: u64, min_source_remaining: u64| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(amount_in).unwrap(),
+          expect_amount_out: NonZeroU64::new(200).unwrap(),
+          minimum_amount_out: NonZeroU64::new(200).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining,
+        },
+        &infos,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    if !swap_info_args.swap_info_acc.is_writable {
-      return Err(ProtocolError::ReadonlyAccount.into());
+    // exactly the floor is left
+    swap(100, 300).unwrap();
+    assert_eq!((balance(source), balance(destination)), (300, 200));
+    // clamped to the 300 left, the source would be drained
+    assert_eq!(swap(1_000, 1), Err(ProtocolError::InsufficientFunds.into()));
+  }
+
+  #[test]
+  fn test_validate_only() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 400);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee = fixture.add_token_account(&mint_b, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let other_pool =
+      fixture.add_token_swap(&token_swap_program_id(), &mint_a, &Pubkey::new_unique());
+    let blocked_pool =
+      fixture.add_token_swap(&token_swap_program_id(), &mint_a, &Pubkey::new_unique());
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config
+      .add_blocked_pool(&fixture.keys[blocked_pool])
+      .unwrap();
+    ProtocolConfig::pack(protocol_config, &mut fixture.accounts[config].data).unwrap();
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    match swap_info_args.swap_info.token_account {
-      COption::Some(k) => {
-        if k != *user_args.token_source_account.pubkey() {
-          return Err(ProtocolError::InvalidTokenAccount.into());
-        }
-      }
-      COption::None => {
-        return Err(ProtocolError::InvalidTokenAccount.into());
-      }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balances = |infos: &[AccountInfo]| {
+      [source, destination, fee, pool + 2, pool + 3]
+        .map(|i| TokenAccount::new(&infos[i]).unwrap().balance().unwrap())
+    };
+    let before = balances(&infos);
+    let swap = |pool_accounts: Range<usize>, minimum_amount_out: u64| {
+      let accounts = [
+        &infos[..config],
+        &infos[pool_accounts],
+        &infos[config..=config],
+      ]
+      .concat();
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(minimum_amount_out).unwrap(),
+          minimum_amount_out: NonZeroU64::new(minimum_amount_out).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: SWAP_EXTRA_FLAG_VALIDATE_ONLY,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
     };
 
-    msg!(
-      "source_token_account amount: {}",
-      user_args.token_source_account.balance()?,
+    // 1_000_000 / 1_000_000 reserves quote 99 for 100
+    assert_eq!(swap(pool..other_pool, 99), Ok(()));
+    assert_eq!(balances(&infos), before);
+    assert_eq!(
+      swap(pool..other_pool, 100),
+      Err(ProtocolError::ExceededSlippage.into())
+    );
+    assert_eq!(
+      swap(other_pool..blocked_pool, 1),
+      Err(ProtocolError::MintRoutingMismatch.into())
     );
+    // a blocked pool fails before its mints are read, quoted or not
+    assert_eq!(
+      swap(blocked_pool..infos.len(), 1),
+      Err(ProtocolError::PoolBlocked.into())
+    );
+    assert_eq!(balances(&infos), before);
+  }
 
-    let fee_token_account = TokenAccount::new(fee_token_account_acc)?;
-    if fee_token_account.mint()? != user_args.token_destination_account.mint()? {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+  #[test]
+  fn test_no_clamp() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 400);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    if fee_token_account.owner()?.to_string() != *OWNER_KEY {
-      return Err(ProtocolError::InvalidFeeTokenAccount.into());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let swap = |extra_flags: u8| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(800).unwrap(),
+          minimum_amount_out: NonZeroU64::new(800).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &infos,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    assert_eq!(
+      swap(SWAP_EXTRA_FLAG_NO_CLAMP),
+      Err(ProtocolError::InvalidSourceBalance.into())
+    );
+    assert_eq!((balance(source), balance(destination)), (400, 0));
+    // clamped to the 400 held
+    swap(0).unwrap();
+    assert_eq!((balance(source), balance(destination)), (0, 800));
+  }
+
+  #[test]
+  fn test_swap_by_index() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let (registry_key, _) = derive_pool_registry_address(&program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 100_000_000;
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let registry = fixture.add_with_key(
+      registry_key,
+      system_program::id(),
+      vec![0u8; PoolRegistry::LEN],
+    );
+    // anyone may fund the address before the registry is created
+    fixture.accounts[registry].lamports = 1_000;
+    let mut forged_data = vec![0u8; PoolRegistry::LEN];
+    PoolRegistry::init(&mut forged_data).unwrap();
+    let forged = fixture.add(Pubkey::new_unique(), forged_data);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let other_pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let system_program_acc = fixture.add_program(system_program::id());
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    let mut infos = fixture.infos();
+    let register = |infos: &[AccountInfo], pool: usize| {
+      let mut accounts = vec![
+        infos[config].clone(),
+        infos[owner].clone(),
+        infos[registry].clone(),
+        infos[system_program_acc].clone(),
+      ];
+      accounts.extend_from_slice(&infos[pool..pool + 7]);
+      Processor::process_register_pool(
+        &program_id,
+        &RegisterPoolInstruction {
+          exchanger: ExchangerType::SplTokenSwap,
+        },
+        &accounts,
+      )
+    };
+    let swap_with = |infos: &[AccountInfo], registry: usize, pool_index: u8, pool: usize| {
+      let mut accounts = infos[..config].to_vec();
+      accounts.push(infos[registry].clone());
+      accounts.extend_from_slice(&infos[pool..pool + 7]);
+      accounts.push(infos[config].clone());
+      Processor::process_swap_by_index(
+        &program_id,
+        &SwapByIndexInstruction {
+          pool_index,
+          swap: SwapInstruction {
+            amount_in: NonZeroU64::new(100).unwrap(),
+            expect_amount_out: NonZeroU64::new(200).unwrap(),
+            minimum_amount_out: NonZeroU64::new(200).unwrap(),
+            flags: 0,
+            min_notional_in: 0,
+            max_price_impact_bps: 0,
+            expected_side: None,
+            client_route_id: None,
+            reference_price: None,
+            extra_flags: 0,
+            expected_pool: None,
+            min_source_remaining: 0,
+          },
+        },
+        &accounts,
+      )
+    };
+    let swap = |infos: &[AccountInfo], pool_index: u8, pool: usize| {
+      swap_with(infos, registry, pool_index, pool)
+    };
 
-    if let Some(delegate) = fee_token_account.delegate()? {
-      if delegate == *user_args.source_account_owner.key {
-        return Err(ProtocolError::InvalidFeeTokenAccount.into());
-      }
+    assert_eq!(
+      register(&infos, pool),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+    infos[owner].is_signer = true;
+    // no registry yet
+    assert_eq!(
+      swap(&infos, 0, pool),
+      Err(ProtocolError::InvalidPoolRegistryAccount.into())
+    );
+    register(&infos, pool).unwrap();
+    assert_eq!(get_return_data().unwrap().1, vec![0]);
+    assert_eq!(*infos[registry].owner, program_id);
+    assert_eq!(
+      infos[registry].lamports(),
+      Rent::default().minimum_balance(PoolRegistry::LEN)
+    );
+    register(&infos, other_pool).unwrap();
+    assert_eq!(get_return_data().unwrap().1, vec![1]);
+
+    swap(&infos, 0, pool).unwrap();
+    swap(&infos, 1, other_pool).unwrap();
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    assert_eq!((balance(source), balance(destination)), (800, 400));
+    assert_eq!(
+      swap(&infos, 0, other_pool),
+      Err(ProtocolError::PoolRegistryMismatch.into())
+    );
+    assert_eq!(
+      swap(&infos, 2, pool),
+      Err(ProtocolError::InvalidInput.into())
+    );
+    // a registry look-alike owned by another program
+    assert_eq!(
+      swap_with(&infos, forged, 0, pool),
+      Err(ProtocolError::InvalidPoolRegistryAccount.into())
+    );
+  }
+
+  #[test]
+  fn test_get_exchanger_spec() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    Processor::process(&Pubkey::new_unique(), &[], &[80]).unwrap();
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(data.len(), 10 * ExchangerSpec::LEN);
+    // spl-token-swap, 7 accounts or 8 with the host fee, direct, in and out
+    assert_eq!(data[..4], [0, 7, 8, 0b111]);
+    assert_eq!(data[36..], [9, 9, 9, 0b111]);
+  }
+
+  #[test]
+  fn test_exchanger_stats() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let exchanger = ExchangerType::SplTokenSwap;
+    let (stats_key, _) = derive_exchanger_stats_address(exchanger, &program_id);
+    let (serum_stats_key, _) = derive_exchanger_stats_address(ExchangerType::SerumDex, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let stats = fixture.add_with_key(
+      stats_key,
+      system_program::id(),
+      vec![0u8; ExchangerStats::LEN],
+    );
+    let serum_stats = fixture.add_with_key(
+      serum_stats_key,
+      system_program::id(),
+      vec![0u8; ExchangerStats::LEN],
+    );
+    let system_program_acc = fixture.add_program(system_program::id());
+    fixture.accounts[stats].lamports = 0;
+    // anyone may fund the address before the counter is created
+    fixture.accounts[serum_stats].lamports = 1_000;
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let initialize = |infos: &[AccountInfo], stats: usize, exchanger: ExchangerType| {
+      Processor::process_initialize_exchanger_stats(
+        &program_id,
+        &ExchangerStatsInstruction { exchanger },
+        &[
+          infos[stats].clone(),
+          infos[owner].clone(),
+          infos[system_program_acc].clone(),
+        ],
+      )
+    };
+    let swap = |infos: &[AccountInfo], stats_acc: Option<usize>| {
+      let mut accounts = [&infos[..config], &infos[config + 1..stats]].concat();
+      accounts.extend(stats_acc.map(|i| infos[i].clone()));
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(200).unwrap(),
+          minimum_amount_out: NonZeroU64::new(200).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: if stats_acc.is_some() {
+            SWAP_EXTRA_FLAG_EXCHANGER_STATS
+          } else {
+            0
+          },
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        exchanger,
+      )
+    };
+    let get = |infos: &[AccountInfo], accounts: &[usize]| {
+      let accounts: Vec<_> = accounts.iter().map(|i| infos[*i].clone()).collect();
+      Processor::process_get_exchanger_stats(&program_id, &accounts)?;
+      Ok::<_, ProgramError>(get_return_data().unwrap().1)
+    };
+
+    assert_eq!(
+      initialize(&infos, stats, ExchangerType::SerumDex),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    initialize(&infos, stats, exchanger).unwrap();
+    initialize(&infos, serum_stats, ExchangerType::SerumDex).unwrap();
+    assert_eq!(*infos[stats].owner, program_id);
+    assert_eq!(*infos[serum_stats].owner, program_id);
+    assert_eq!(
+      infos[serum_stats].lamports(),
+      Rent::default().minimum_balance(ExchangerStats::LEN)
+    );
+    assert_eq!(
+      initialize(&infos, stats, exchanger),
+      Err(ProtocolError::InvalidAccountFlags.into())
+    );
+
+    // off by default, a passed counter is left as is
+    swap(&infos, None).unwrap();
+    swap(&infos, Some(stats)).unwrap();
+    assert_eq!(
+      get(&infos, &[stats]).unwrap(),
+      vec![0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    Processor::process_set_collect_exchanger_stats(
+      &program_id,
+      &CollectExchangerStatsInstruction { enabled: true },
+      &[infos[config].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    assert_eq!(
+      swap(&infos, None),
+      Err(ProtocolError::InvalidExchangerStatsAccount.into())
+    );
+    assert_eq!(
+      swap(&infos, Some(serum_stats)),
+      Err(ProtocolError::InvalidExchangerStatsAccount.into())
+    );
+    swap(&infos, Some(stats)).unwrap();
+    swap(&infos, Some(stats)).unwrap();
+    assert_eq!(
+      get(&infos, &[stats, serum_stats]).unwrap(),
+      vec![0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+      get(&infos, &[config]),
+      Err(ProtocolError::InvalidExchangerStatsAccount.into())
+    );
+    assert_eq!(
+      get(&infos, &[]),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+  }
+
+  #[test]
+  fn test_fee_accrual() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let (accrual_key, _) = derive_fee_accrual_address(&user, &mint_b, &program_id);
+    let (other_key, _) = derive_fee_accrual_address(&user, &mint_a, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_b, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let accrual = fixture.add_with_key(
+      accrual_key,
+      system_program::id(),
+      vec![0u8; FeeAccrual::LEN],
+    );
+    let other_accrual =
+      fixture.add_with_key(other_key, system_program::id(), vec![0u8; FeeAccrual::LEN]);
+    let system_program_acc = fixture.add_program(system_program::id());
+    fixture.accounts[accrual].lamports = 0;
+    // someone sent lamports to the other address before it was created
+    fixture.accounts[other_accrual].lamports = 1_000;
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    let from_amount_before = user_args.token_source_account.balance()?;
-    let to_amount_before = user_args.token_destination_account.balance()?;
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let owed = |i: usize| FeeAccrual::unpack(&infos[i].data.borrow()).unwrap().amount;
+    let initialize = |accrual: usize, mint: Pubkey| {
+      Processor::process_initialize_fee_accrual(
+        &program_id,
+        &FeeAccrualInstruction { owner: user, mint },
+        &[
+          infos[config].clone(),
+          infos[owner].clone(),
+          infos[accrual].clone(),
+          infos[system_program_acc].clone(),
+        ],
+      )
+    };
+    let swap = |accrual: usize| {
+      let mut accounts = [&infos[..config], &infos[config + 1..accrual]].concat();
+      accounts.push(infos[accrual].clone());
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(150).unwrap(),
+          minimum_amount_out: NonZeroU64::new(150).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: SWAP_EXTRA_FLAG_ACCRUE_FEE,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+    let settle = |accrual: usize| {
+      Processor::process_settle_accrued_fee(
+        &program_id,
+        &[
+          infos[accrual].clone(),
+          infos[owner].clone(),
+          infos[destination].clone(),
+          infos[fee_account].clone(),
+          infos[token_program].clone(),
+        ],
+      )
+    };
 
-    let amount_in = swap_info_args.swap_info.token_latest_amount;
-    let amount_out = data.minimum_amount_out.get();
-    msg!(
-      "from_amount_before: {}, to_amount_before: {}, amount_in: {}, minimum_amount_out: {}",
-      from_amount_before,
-      to_amount_before,
-      amount_in,
-      data.minimum_amount_out,
+    assert_eq!(
+      initialize(accrual, mint_a),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    // no accrual without one created by the config owner
+    assert_eq!(
+      swap(accrual),
+      Err(ProtocolError::InvalidFeeAccrualAccount.into())
+    );
+    initialize(accrual, mint_b).unwrap();
+    initialize(other_accrual, mint_a).unwrap();
+    assert_eq!(*infos[accrual].owner, program_id);
+    assert_eq!(*infos[other_accrual].owner, program_id);
+    assert_eq!(
+      infos[other_accrual].lamports(),
+      Rent::default().minimum_balance(FeeAccrual::LEN)
+    );
+    assert_eq!(
+      initialize(accrual, mint_b),
+      Err(ProtocolError::InvalidAccountFlags.into())
+    );
+    // the accrual must be in the destination mint
+    assert_eq!(
+      swap(other_accrual),
+      Err(ProtocolError::InvalidFeeAccrualAccount.into())
     );
 
-    match exchanger {
-      ExchangerType::SplTokenSwap => Self::process_step_tokenswap(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
-      ),
-      ExchangerType::StableSwap => Self::process_step_stableswap(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+    // a quarter of the 50 surplus is owed, the destination keeps it for now
+    swap(accrual).unwrap();
+    assert_eq!((balance(destination), balance(fee_account)), (200, 0));
+    assert_eq!(owed(accrual), 12);
+    let return_data = get_return_data().unwrap().1;
+    assert_eq!(return_data[..8], 12u64.to_le_bytes());
+    assert_eq!(return_data[8..], 12u64.to_le_bytes());
+    swap(accrual).unwrap();
+    assert_eq!(owed(accrual), 24);
+
+    assert_eq!(
+      settle(other_accrual),
+      Err(ProtocolError::InvalidFeeAccrualAccount.into())
+    );
+    settle(accrual).unwrap();
+    assert_eq!((balance(destination), balance(fee_account)), (376, 24));
+    assert_eq!(owed(accrual), 0);
+    assert_eq!(get_return_data().unwrap().1, 24u64.to_le_bytes());
+  }
+
+  #[test]
+  fn test_swap_percent() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_b, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |percent_bps: u16| {
+      Processor::process_swap_percent(
+        &program_id,
+        &SwapPercentInstruction {
+          exchanger: ExchangerType::SplTokenSwap,
+          percent_bps,
+          expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+        },
+        &infos,
+      )
+    };
+
+    assert_eq!(Processor::percent_of(1_000, 5_000), 500);
+    assert_eq!(Processor::percent_of(999, 5_000), 499);
+    assert_eq!(Processor::percent_of(u64::MAX, 10_000), u64::MAX);
+    // half of the 1_000, then all of the 500 left
+    swap(5_000).unwrap();
+    assert_eq!((balance(source), balance(destination)), (500, 1_000));
+    swap(10_000).unwrap();
+    assert_eq!((balance(source), balance(destination)), (0, 2_000));
+    // nothing left to swap
+    assert_eq!(swap(10_000), Err(ProtocolError::InvalidInput.into()));
+  }
+
+  #[test]
+  fn test_fee_breakdown() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_b, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let treasury = fixture.add_token_account(&mint_b, 0);
+    let config = fixture.add_config(&program_id, vec![0u8; ProtocolConfig::LEN]);
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config.treasury_token_account = COption::Some(fixture.keys[treasury]);
+    protocol_config.treasury_bps = 2_000;
+    ProtocolConfig::pack(protocol_config, &mut fixture.accounts[config].data).unwrap();
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    Processor::process_single_step_swap(
+      &program_id,
+      &SwapInstruction {
+        amount_in: NonZeroU64::new(100).unwrap(),
+        expect_amount_out: NonZeroU64::new(100).unwrap(),
+        minimum_amount_out: NonZeroU64::new(100).unwrap(),
+        flags: SWAP_FLAG_TREASURY,
+        min_notional_in: 0,
+        max_price_impact_bps: 0,
+        expected_side: None,
+        client_route_id: None,
+        reference_price: None,
+        extra_flags: 0,
+        expected_pool: None,
+        min_source_remaining: 0,
+      },
+      &infos[..=config],
+      ExchangerType::SplTokenSwap,
+    )
+    .unwrap();
+    let (_, return_data) = get_return_data().unwrap();
+    let breakdown = FeeBreakdown::unpack(&return_data).unwrap();
+    // a quarter of the 100 surplus, a fifth of it to the treasury
+    assert_eq!(
+      breakdown,
+      FeeBreakdown {
+        gross_output: 200,
+        protocol_fee: 20,
+        referrer_fee: 0,
+        treasury_fee: 5,
+        net_output: 175,
+      }
+    );
+    assert_eq!(
+      (
+        balance(destination),
+        balance(fee_account),
+        balance(treasury)
       ),
-      ExchangerType::RaydiumSwap => Self::process_step_raydium(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        breakdown.net_output,
+        breakdown.protocol_fee,
+        breakdown.treasury_fee
+      )
+    );
+    assert_eq!(
+      breakdown.net_output + breakdown.protocol_fee + breakdown.treasury_fee,
+      breakdown.gross_output
+    );
+    assert_eq!(
+      FeeBreakdown::unpack(&return_data[..FeeBreakdown::LEN - 1]),
+      Err(ProtocolError::InvalidInput)
+    );
+  }
+
+  #[test]
+  fn test_short_account_lists() {
+    let program_id = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&Pubkey::new_unique()), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    for _ in 0..9 {
+      fixture.add(Pubkey::default(), vec![]);
+    }
+    let config = fixture.add_config(&program_id, config_data);
+    let infos = fixture.infos();
+    let amount = NonZeroU64::new(1_000).unwrap();
+    let exchanger = ExchangerType::SplTokenSwap;
+    let entry_points: Vec<(usize, Box<dyn Fn(&[AccountInfo]) -> ProgramResult>)> = vec![
+      (
+        5,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_single_step_swap(
+            &program_id,
+            &SwapInstruction {
+              amount_in: amount,
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              flags: 0,
+              min_notional_in: 0,
+              max_price_impact_bps: 0,
+              expected_side: None,
+              client_route_id: None,
+              reference_price: None,
+              extra_flags: 0,
+              expected_pool: None,
+              min_source_remaining: 0,
+            },
+            accounts,
+            exchanger,
+          )
+        }),
       ),
-      ExchangerType::RaydiumSwapSlim => Self::process_step_raydium_slim(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        5,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_single_step_swap_in(
+            &program_id,
+            &SwapInInstruction {
+              amount_in: amount,
+              flags: 0,
+              rebate_nonce: 0,
+              rebate_pool_index: 0,
+            },
+            accounts,
+            exchanger,
+          )
+        }),
       ),
-      ExchangerType::SerumDex => Self::process_step_serumdex(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        6,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_single_step_swap_out(
+            &program_id,
+            &SwapOutInstruction {
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              flags: 0,
+              client_route_id: None,
+              input_minimum: None,
+            },
+            accounts,
+            exchanger,
+          )
+        }),
       ),
-      ExchangerType::CremaFinance => Self::process_step_crema_finance(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        6,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_single_step_swap_out_slim(
+            &program_id,
+            &SwapOutSlimInstruction {
+              minimum_amount_out: amount,
+            },
+            accounts,
+            exchanger,
+          )
+        }),
       ),
-      ExchangerType::AldrinExchange => Self::process_step_aldrin_exchange(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        4,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_stable_swap_guard(
+            &program_id,
+            &SwapStableSwapGuardInstruction {
+              amount_in: amount,
+              expect_amount_out: amount,
+              min_out_bps_of_in: 0,
+            },
+            accounts,
+          )
+        }),
       ),
-      ExchangerType::CropperFinance => Self::process_step_cropper_finance(
-        program_id,
-        amount_in,
-        amount_out,
-        &user_args.token_source_account,
-        &user_args.token_destination_account,
-        user_args.source_account_owner,
-        &spl_token_program,
-        other_accounts,
+      (
+        5,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_swap_with_fallback(
+            &program_id,
+            &SwapWithFallbackInstruction {
+              amount_in: amount,
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              candidates: vec![(exchanger, 7)],
+            },
+            accounts,
+          )
+        }),
       ),
-    }?;
+      (
+        5,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_swap_with_backup(
+            &program_id,
+            &SwapWithBackupInstruction {
+              amount_in: amount,
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              primary: (exchanger, 7),
+            },
+            accounts,
+          )
+        }),
+      ),
+      (
+        7,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_native_swap(
+            &program_id,
+            &NativeSwapInstruction {
+              exchanger,
+              unwrap: false,
+              amount_in: amount,
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              lamport_fee: false,
+            },
+            accounts,
+          )
+        }),
+      ),
+      (
+        5,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_start_route(
+            &program_id,
+            &StartRouteInstruction {
+              exchanger,
+              amount_in: amount,
+            },
+            accounts,
+          )
+        }),
+      ),
+      (
+        8,
+        Box::new(|accounts: &[AccountInfo]| {
+          Processor::process_swap_transient_route(
+            &program_id,
+            &TransientRouteInstruction {
+              exchanger_in: exchanger,
+              exchanger_out: exchanger,
+              in_accounts_len: 0,
+              amount_in: amount,
+              expect_amount_out: amount,
+              minimum_amount_out: amount,
+              flags: 0,
+            },
+            accounts,
+          )
+        }),
+      ),
+    ];
 
-    let from_amount_after = user_args.token_source_account.balance()?;
-    let to_amount_after = user_args.token_destination_account.balance()?;
-    msg!(
-      "from_amount_after: {}, to_amount_after: {}",
-      from_amount_after,
-      to_amount_after
-    );
+    // the minimum of every entry point doesn't count the config passed last
+    for (min_accounts, entry_point) in entry_points.iter() {
+      for len in 0..*min_accounts {
+        let accounts = [&infos[..len], &infos[config..=config]].concat();
+        assert_eq!(
+          entry_point(&accounts),
+          Err(ProtocolError::InvalidAccountsLength.into()),
+          "{} accounts",
+          len
+        );
+      }
+    }
+  }
 
-    let from_amount_changed = from_amount_before.checked_sub(from_amount_after).unwrap();
-    let to_amount_include_fee = to_amount_after.checked_sub(to_amount_before).unwrap();
-    msg!("from_amount changed: {}", from_amount_changed);
-    msg!(
-      "result_with_fee: {}, minimum: {}",
-      to_amount_include_fee,
-      data.minimum_amount_out,
+  #[test]
+  fn test_stable_swap_ramping_pool() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = crate::parser::base::STABLE_SWAP_PROGRAM_IDS[0];
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool = fixture.add_stable_swap(&swap_program, &mint_a, &mint_b, false);
+    let legacy_pool = fixture.add_stable_swap(&swap_program, &mint_a, &mint_b, true);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    // amp ramping from 100 to 2_000 around the stub clock
+    for i in [pool, legacy_pool] {
+      let data = &mut fixture.accounts[i].data;
+      data[3..11].copy_from_slice(&100u64.to_le_bytes());
+      data[11..19].copy_from_slice(&2_000u64.to_le_bytes());
+      data[19..27].copy_from_slice(&(-3_600i64).to_le_bytes());
+      data[27..35].copy_from_slice(&3_600i64.to_le_bytes());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |pool_accounts: Range<usize>| {
+      let accounts = [
+        &infos[..config],
+        &infos[pool_accounts],
+        &infos[config..=config],
+      ]
+      .concat();
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::StableSwap,
+      )
+    };
+
+    assert_eq!(
+      StableSwapArgs::with_parsed_args(&infos[pool..legacy_pool])
+        .unwrap()
+        .swap_info
+        .current_amp_factor(),
+      Ok(1_050)
     );
-    if to_amount_include_fee == 0 {
-      return Err(ProtocolError::DexSwapError.into());
+    // the swap CPI doesn't take the clock, saber reads it itself
+    swap(pool..legacy_pool).unwrap();
+    assert_eq!((balance(source), balance(destination)), (500, 1_000));
+    swap(legacy_pool..infos.len()).unwrap();
+    assert_eq!((balance(source), balance(destination)), (0, 2_000));
+  }
+
+  #[test]
+  fn test_delegated_swap() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (owner_key, delegate_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&owner_key), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let delegate = fixture.add_with_key(delegate_key, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(owner_key.as_ref());
     }
+    // the delegate may move 300 of the 500
+    let data = &mut fixture.accounts[source].data;
+    data[72..76].copy_from_slice(&[1, 0, 0, 0]);
+    data[76..108].copy_from_slice(delegate_key.as_ref());
+    data[121..129].copy_from_slice(&300u64.to_le_bytes());
+    let mut infos = fixture.infos();
+    infos[delegate].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = || {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(600).unwrap(),
+          minimum_amount_out: NonZeroU64::new(500).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &infos,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    if to_amount_include_fee < data.minimum_amount_out.get() {
-      return Err(ProtocolError::ExceededSlippage.into());
+    // clamped to the delegated amount rather than the balance
+    swap().unwrap();
+    assert_eq!((balance(source), balance(destination)), (200, 600));
+    infos[source].data.borrow_mut()[121..129].copy_from_slice(&0u64.to_le_bytes());
+    assert_eq!(swap(), Err(ProtocolError::InvalidDelegate.into()));
+  }
+
+  #[test]
+  fn test_collect_host_fee() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config.collect_host_fee = true;
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(protocol_config, &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_a, 0);
+    let swap_info = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let pool_mint = fixture.keys[swap_info + 4];
+    let host_fee_account = fixture.add_token_account(&pool_mint, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    // 1% host fee
+    fixture.accounts[swap_info].data[275..283].copy_from_slice(&1u64.to_le_bytes());
+    fixture.accounts[swap_info].data[283..291].copy_from_slice(&100u64.to_le_bytes());
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |infos: &[AccountInfo], host_fee: bool| {
+      let mut accounts = infos[..host_fee_account].to_vec();
+      if host_fee {
+        accounts.push(infos[host_fee_account].clone());
+      }
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    let fee1 = to_amount_include_fee
-      .checked_sub(data.minimum_amount_out.get())
-      .map(|v| (v as u128).checked_div(4).unwrap_or(0) as u64)
-      .unwrap_or(0);
+    // not owned by the fee owner
+    assert_eq!(
+      swap(&infos, true),
+      Err(ProtocolError::InvalidHostFeeAccount.into())
+    );
+    // no host fee account passed
+    assert_eq!(
+      swap(&infos, false),
+      Err(ProtocolError::InvalidHostFeeAccount.into())
+    );
+    infos[host_fee_account].data.borrow_mut()[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    swap(&infos, true).unwrap();
+    assert_eq!((balance(source), balance(destination)), (500, 1_000));
+    assert_eq!(balance(host_fee_account), 5);
+
+    // a pool without a host fee leaves the account out
+    infos[swap_info].data.borrow_mut()[275..283].copy_from_slice(&0u64.to_le_bytes());
+    swap(&infos, false).unwrap();
+    assert_eq!((balance(source), balance(destination)), (0, 2_000));
+  }
 
-    let fee2 = to_amount_include_fee.checked_div(10_000).unwrap_or(0);
+  #[test]
+  fn test_set_collect_host_fee() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&admin), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let config = fixture.add_config(&program_id, config_data);
+    let admin = fixture.add_with_key(admin, Pubkey::default(), vec![]);
+    let other = fixture.add(Pubkey::default(), vec![]);
+    let mut infos = fixture.infos();
+    let set = |infos: &[AccountInfo], signer: usize, enabled: bool| {
+      Processor::process_set_collect_host_fee(
+        &program_id,
+        &CollectHostFeeInstruction { enabled },
+        &[infos[config].clone(), infos[signer].clone()],
+      )
+    };
 
-    let fee = cmp::min(fee1, fee2);
+    assert_eq!(
+      set(&infos, admin, true),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+    infos[admin].is_signer = true;
+    infos[other].is_signer = true;
+    assert_eq!(
+      set(&infos, other, true),
+      Err(ProtocolError::InvalidOwner.into())
+    );
+    set(&infos, admin, true).unwrap();
+    assert!(
+      ProtocolConfig::unpack(&infos[config].data.borrow())
+        .unwrap()
+        .collect_host_fee
+    );
+    set(&infos, admin, false).unwrap();
+    assert!(
+      !ProtocolConfig::unpack(&infos[config].data.borrow())
+        .unwrap()
+        .collect_host_fee
+    );
+  }
 
-    if fee > 0 {
-      Self::token_transfer(
-        spl_token_program.inner(),
-        user_args.token_destination_account.inner(),
-        fee_token_account.inner(),
-        user_args.source_account_owner,
-        fee,
-      )?;
-    }
-    let mut swap_info = swap_info_args.swap_info;
-    swap_info.token_latest_amount = to_amount_include_fee;
-    swap_info.token_account = COption::None;
+  #[test]
+  fn test_initialize_derived_swap_info() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let (swap_info_key, _) = derive_swap_info_address(&user, 7, &program_id);
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add_with_key(
+      swap_info_key,
+      system_program::id(),
+      vec![0u8; SwapInfo::LEN],
+    );
+    fixture.accounts[swap_info].lamports = 0;
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    fixture.add_program(system_program::id());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let initialize = |nonce: u8| {
+      Processor::process_initialize_swap_info(
+        &program_id,
+        &InitializeSwapInfoInstruction { nonce: Some(nonce) },
+        &infos,
+      )
+    };
 
-    SwapInfo::pack(
-      swap_info,
-      &mut swap_info_args.swap_info_acc.data.borrow_mut(),
-    )?;
-    Ok(())
+    assert_eq!(
+      initialize(8),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    assert_eq!(
+      Processor::process_initialize_swap_info(
+        &program_id,
+        &InitializeSwapInfoInstruction { nonce: Some(7) },
+        &infos[..2],
+      ),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    initialize(7).unwrap();
+    assert_eq!(*infos[swap_info].owner, program_id);
+    assert_eq!(
+      infos[swap_info].lamports(),
+      Rent::default().minimum_balance(SwapInfo::LEN)
+    );
+    let swap_info_data = SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
+    assert_eq!(swap_info_data.owner, user);
+    assert_eq!(
+      initialize(7),
+      Err(ProtocolError::InvalidAccountFlags.into())
+    );
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_tokenswap<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    msg!(
-      "swap using token-swap, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+  #[test]
+  fn test_initialize_prefunded_swap_info() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let (swap_info_key, _) = derive_swap_info_address(&user, 7, &program_id);
+    let mut fixture = Fixture::new();
+    // anyone may fund the address before it is initialized
+    let swap_info = fixture.add_with_key(
+      swap_info_key,
+      system_program::id(),
+      vec![0u8; SwapInfo::LEN],
     );
+    fixture.accounts[swap_info].lamports = 1_000;
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    fixture.add_program(system_program::id());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+
+    Processor::process_initialize_swap_info(
+      &program_id,
+      &InitializeSwapInfoInstruction { nonce: Some(7) },
+      &infos,
+    )
+    .unwrap();
+    assert_eq!(*infos[swap_info].owner, program_id);
+    let rent = Rent::default().minimum_balance(SwapInfo::LEN);
+    assert_eq!(infos[swap_info].lamports(), rent);
+    assert_eq!(infos[owner].lamports(), 10_000_000 - (rent - 1_000));
+    let swap_info_data = SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
+    assert_eq!(swap_info_data.owner, user);
+  }
 
-    let spl_token_swap_args = SplTokenSwapArgs::with_parsed_args(accounts)?;
-    let token_swap_amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_setup_swap_info_again() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    let intermediate = fixture.add_token_account(&Pubkey::new_unique(), 0);
+    let other = fixture.add_token_account(&Pubkey::new_unique(), 0);
+    for i in [intermediate, other] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    SwapInfo::new(&user, 0)
+      .store(&mut fixture.accounts[swap_info].data)
+      .unwrap();
+    let infos = fixture.infos();
+    let setup = |token_account: usize| {
+      Processor::process_setup_swap_info(
+        &program_id,
+        &[infos[swap_info].clone(), infos[token_account].clone()],
+      )
+    };
+    let state = || SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+    assert_eq!(setup(intermediate), Ok(()));
+    let set_up = state();
+    assert_eq!(
+      set_up.token_account,
+      COption::Some(*infos[intermediate].key)
+    );
+    assert_eq!(set_up.created_slot, STUB_SLOT);
+    // retried before the In leg, nothing changes
+    assert_eq!(setup(intermediate), Ok(()));
+    assert_eq!(state(), set_up);
+    // or bound to another account
+    assert_eq!(setup(other), Ok(()));
+    assert_eq!(state().token_account, COption::Some(*infos[other].key));
+
+    // once the In leg recorded its output, the amount is kept
+    let mut in_progress = state();
+    in_progress.token_latest_amount = 1_000;
+    in_progress
+      .store(&mut infos[swap_info].data.borrow_mut())
+      .unwrap();
+    assert_eq!(setup(other), Err(ProtocolError::InvalidAccountFlags.into()));
+    assert_eq!(
+      setup(intermediate),
+      Err(ProtocolError::InvalidAccountFlags.into())
+    );
+    assert_eq!(state(), in_progress);
+
+    // the Out leg unbound the account, the next route sets up again
+    let mut completed = in_progress;
+    completed.token_account = COption::None;
+    completed
+      .store(&mut infos[swap_info].data.borrow_mut())
+      .unwrap();
+    assert_eq!(setup(intermediate), Ok(()));
+    assert_eq!(state().token_latest_amount, 0);
+  }
 
-    let (pool_source_token_acc, pool_destination_token_acc) =
-      spl_token_swap_args.find_token_pair(&source_token_mint)?;
+  #[test]
+  fn test_intermediate_not_fee_account() {
+    let program_id = Pubkey::new_unique();
+    let (mint_b, mint_c) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let fee_owner = Pubkey::from_str(OWNER_KEY).unwrap();
+    let user = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&user);
+    let mut fixture = Fixture::new();
+    let fee_intermediate = fixture.add_token_account(&mint_b, 1_000);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let owner = fixture.add_with_key(fee_owner, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_c, 0);
+    let config_acc = fixture.add_config(&program_id, vec![0u8; ProtocolConfig::LEN]);
+    let intermediate = fixture.add_token_account(&mint_b, 1_000);
+    let user_owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    for i in [fee_intermediate, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(fee_owner.as_ref());
+    }
+    fixture.accounts[intermediate].data[32..64].copy_from_slice(user.as_ref());
+    config.treasury_token_account = COption::Some(fixture.keys[intermediate]);
+    ProtocolConfig::pack(config, &mut fixture.accounts[config_acc].data).unwrap();
+    let mut infos = fixture.infos();
+    for i in [owner, user_owner] {
+      infos[i].is_signer = true;
+    }
+    let bind = |token_account: usize, owner: &Pubkey| {
+      let mut state = SwapInfo::new(owner, 0);
+      state.token_account = COption::Some(*infos[token_account].key);
+      state.token_latest_amount = 1_000;
+      state
+        .store(&mut infos[swap_info].data.borrow_mut())
+        .unwrap();
+    };
+    let swap_out = |token_account: usize, owner: usize| {
+      let mut accounts = infos[..config_acc + 1].to_vec();
+      accounts[0] = infos[token_account].clone();
+      accounts[2] = infos[owner].clone();
+      Processor::process_single_step_swap_out(
+        &program_id,
+        &SwapOutInstruction {
+          expect_amount_out: NonZeroU64::new(2_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(2_000).unwrap(),
+          flags: 0,
+          client_route_id: None,
+          input_minimum: None,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    if pool_source_token_acc.mint()? != source_token_mint {
-      return Err(ProtocolError::InvalidTokenMint.into());
+    SwapInfo::new(&fee_owner, 0)
+      .store(&mut infos[swap_info].data.borrow_mut())
+      .unwrap();
+    assert_eq!(
+      Processor::process_setup_swap_info(
+        &program_id,
+        &[infos[swap_info].clone(), infos[fee_intermediate].clone()],
+      ),
+      Err(ProtocolError::InvalidTokenAccount.into())
+    );
+    // bound before the check, the Out leg refuses it as well
+    bind(fee_intermediate, &fee_owner);
+    assert_eq!(
+      swap_out(fee_intermediate, owner),
+      Err(ProtocolError::InvalidTokenAccount.into())
+    );
+    // nor the treasury account of the config
+    bind(intermediate, &user);
+    assert_eq!(
+      swap_out(intermediate, user_owner),
+      Err(ProtocolError::InvalidTokenAccount.into())
+    );
+  }
+
+  #[test]
+  fn test_swap_in_rebate() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let (vault_key, nonce) = derive_rebate_vault_address(&mint_b, &program_id);
+    let (registry_key, _) = derive_pool_registry_address(&program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 5_000);
+    let intermediate = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 100_000_000;
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    let other_swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    for i in [swap_info, other_swap_info] {
+      fixture.accounts[i].lamports = Rent::default().minimum_balance(SwapInfo::LEN);
     }
-    if pool_destination_token_acc.mint()? != destination_token_mint {
-      return Err(ProtocolError::InvalidTokenMint.into());
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let other_pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let registry = fixture.add_with_key(
+      registry_key,
+      system_program::id(),
+      vec![0u8; PoolRegistry::LEN],
+    );
+    fixture.accounts[registry].lamports = 0;
+    let vault = fixture.add_with_key(vault_key, system_program::id(), vec![0u8; RebateVault::LEN]);
+    fixture.accounts[vault].lamports = 0;
+    let rebate_account = fixture.add_token_account(&mint_b, 1_000);
+    let wrong_mint = fixture.add_token_account(&mint_a, 1_000);
+    let config = fixture.add_config(&program_id, config_data);
+    let system_program_acc = fixture.add_program(system_program::id());
+    for i in [source, intermediate] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
+    for i in [rebate_account, wrong_mint] {
+      fixture.accounts[i].data[32..64].copy_from_slice(vault_key.as_ref());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    for i in [swap_info, other_swap_info] {
+      Processor::process_initialize_swap_info(
+        &program_id,
+        &InitializeSwapInfoInstruction { nonce: None },
+        &[infos[i].clone(), infos[owner].clone()],
+      )
+      .unwrap();
+    }
+    Processor::process_set_rebate(
+      &program_id,
+      &RebateInstruction { rebate_bps: 100 },
+      &[infos[config].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    let set_rebate_vault = |max_rebate: u64, token_account: usize| {
+      Processor::process_set_rebate_vault(
+        &program_id,
+        &RebateVaultInstruction { max_rebate },
+        &[
+          infos[config].clone(),
+          infos[owner].clone(),
+          infos[vault].clone(),
+          infos[token_account].clone(),
+          infos[system_program_acc].clone(),
+        ],
+      )
+    };
+    // the first call creates the vault of the token account mint
+    set_rebate_vault(15, rebate_account).unwrap();
+    assert_eq!(*infos[vault].owner, program_id);
+    assert_eq!(
+      RebateVault::unpack(&infos[vault].data.borrow()).unwrap(),
+      RebateVault::new(&mint_b, infos[rebate_account].key, 15)
+    );
+    let register = |pool: usize, exchanger: ExchangerType| {
+      let mut accounts = vec![
+        infos[config].clone(),
+        infos[owner].clone(),
+        infos[registry].clone(),
+        infos[system_program_acc].clone(),
+      ];
+      accounts.extend_from_slice(&infos[pool..pool + FEE_POOL_ACCOUNTS]);
+      Processor::process_register_pool(
+        &program_id,
+        &RegisterPoolInstruction { exchanger },
+        &accounts,
+      )
+    };
+    register(pool, ExchangerType::SplTokenSwap).unwrap();
+    // returns the intermediate amount the Out leg will swap, `rebate` is the
+    // registered pool index and the vault token account
+    let swap_in = |swap_info: usize, amount_in: u64, pool: usize, rebate: Option<(u8, usize)>| {
+      // unbound as by the Out leg of the previous route
+      let mut state = SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
+      state.token_account = COption::None;
+      state
+        .store(&mut infos[swap_info].data.borrow_mut())
+        .unwrap();
+      Processor::process_setup_swap_info(
+        &program_id,
+        &[infos[swap_info].clone(), infos[intermediate].clone()],
+      )?;
+      let mut accounts = vec![
+        infos[source].clone(),
+        infos[intermediate].clone(),
+        infos[owner].clone(),
+        infos[swap_info].clone(),
+        infos[token_program].clone(),
+      ];
+      accounts.extend_from_slice(&infos[pool..pool + FEE_POOL_ACCOUNTS]);
+      let (flags, rebate_pool_index) = match rebate {
+        Some((pool_index, token_account)) => {
+          accounts.extend([
+            infos[registry].clone(),
+            infos[vault].clone(),
+            infos[token_account].clone(),
+          ]);
+          (SWAP_IN_FLAG_REBATE, pool_index)
+        }
+        None => (0, 0),
+      };
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap_in(
+        &program_id,
+        &SwapInInstruction {
+          amount_in: NonZeroU64::new(amount_in).unwrap(),
+          flags,
+          rebate_nonce: nonce,
+          rebate_pool_index,
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )?;
+      Ok::<_, ProgramError>(
+        SwapInfo::unpack(&infos[swap_info].data.borrow())
+          .unwrap()
+          .token_latest_amount,
+      )
+    };
+    let with_rebate = Some((0, rebate_account));
 
-    let mut swap_accounts = vec![
-      spl_token_swap_args.swap_info.inner().clone(),
-      spl_token_swap_args.authority_acc_info.clone(),
-      source_account_authority.clone(),
-      source_token_account.inner().clone(),
-      pool_source_token_acc.inner().clone(),
-      pool_destination_token_acc.inner().clone(),
-      destination_token_account.inner().clone(),
-      spl_token_swap_args.pool_mint.inner().clone(),
-      spl_token_swap_args.fee_account.inner().clone(),
-    ];
+    // 1% of the 1_000 out
+    assert_eq!(swap_in(swap_info, 500, pool, with_rebate), Ok(1_010));
+    assert_eq!(
+      (balance(intermediate), balance(rebate_account)),
+      (1_010, 990)
+    );
+    // 1% of 2_000 is over what the cap leaves the owner in this slot, another
+    // SwapInfo of theirs doesn't get a cap of its own
+    assert_eq!(
+      swap_in(other_swap_info, 1_000, pool, with_rebate),
+      Ok(2_005)
+    );
+    assert_eq!(
+      (balance(intermediate), balance(rebate_account)),
+      (3_015, 985)
+    );
+    assert_eq!(swap_in(swap_info, 500, pool, with_rebate), Ok(1_000));
+    // the cap is per slot
+    let mut state = RebateVault::unpack(&infos[vault].data.borrow()).unwrap();
+    assert_eq!(state.slot, STUB_SLOT);
+    assert_eq!(state.credited[..state.credited_len as usize], [(user, 15)]);
+    state.slot = STUB_SLOT - 1;
+    RebateVault::pack(state, &mut infos[vault].data.borrow_mut()).unwrap();
+    assert_eq!(swap_in(swap_info, 1_000, pool, with_rebate), Ok(2_015));
+    // raising the vault cap
+    set_rebate_vault(1_000, rebate_account).unwrap();
+    assert_eq!(swap_in(swap_info, 1_000, pool, with_rebate), Ok(2_020));
+    assert_eq!(balance(rebate_account), 950);
+    // no rebate without the flag, the rebate accounts are not read
+    assert_eq!(swap_in(swap_info, 500, pool, None), Ok(1_000));
+    assert_eq!(balance(rebate_account), 950);
+
+    // only swaps through the registered pool are rebated
+    assert_eq!(swap_in(swap_info, 500, other_pool, None), Ok(1_000));
+    assert_eq!(
+      swap_in(swap_info, 500, other_pool, with_rebate),
+      Err(ProtocolError::PoolRegistryMismatch.into())
+    );
+    assert_eq!(
+      swap_in(swap_info, 500, other_pool, Some((1, rebate_account))),
+      Err(ProtocolError::InvalidInput.into())
+    );
+    // registered for another exchanger
+    register(other_pool, ExchangerType::OrcaV1).unwrap();
+    assert_eq!(
+      swap_in(swap_info, 500, other_pool, Some((1, rebate_account))),
+      Err(ProtocolError::PoolRegistryMismatch.into())
+    );
+    assert_eq!(balance(rebate_account), 950);
 
-    let host_fee_account_key = spl_token_swap_args.host_fee_account.map(|v| v.inner().key);
+    // the vault only pays from its own token account
+    assert_eq!(
+      set_rebate_vault(1_000, wrong_mint),
+      Err(ProtocolError::InvalidRebateAccount.into())
+    );
+    assert_eq!(
+      swap_in(swap_info, 500, pool, Some((0, wrong_mint))),
+      Err(ProtocolError::InvalidRebateAccount.into())
+    );
+    // which the vault owns
+    infos[rebate_account].data.borrow_mut()[32..64].copy_from_slice(user.as_ref());
+    assert_eq!(
+      swap_in(swap_info, 500, pool, with_rebate),
+      Err(ProtocolError::InvalidRebateAccount.into())
+    );
+    assert_eq!(
+      set_rebate_vault(1_000, rebate_account),
+      Err(ProtocolError::InvalidRebateAccount.into())
+    );
+  }
 
-    if host_fee_account_key.is_some() {
-      swap_accounts.push(
-        spl_token_swap_args
-          .host_fee_account
-          .unwrap()
-          .inner()
-          .clone(),
-      );
+  #[test]
+  fn test_swap_in_native_destination() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, native_mint) = (Pubkey::new_unique(), spl_token::native_mint::id());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 5_000);
+    let intermediate = fixture.add_token_account(&native_mint, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    fixture.accounts[swap_info].lamports = Rent::default().minimum_balance(SwapInfo::LEN);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let pool = fixture.add_token_swap(&token_swap_program_id(), &mint_a, &native_mint);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, intermediate] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    swap_accounts.push(spl_token_swap_args.program.clone());
+    let reserve = Rent::default().minimum_balance(spl_token::ACCOUNT_LEN);
+    fixture.accounts[intermediate].lamports = reserve;
+    fixture.accounts[intermediate].data[109] = 1;
+    fixture.accounts[intermediate].data[113..121].copy_from_slice(&reserve.to_le_bytes());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    Processor::process_initialize_swap_info(
+      &program_id,
+      &InitializeSwapInfoInstruction { nonce: None },
+      &[infos[swap_info].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    Processor::process_setup_swap_info(
+      &program_id,
+      &[infos[swap_info].clone(), infos[intermediate].clone()],
+    )
+    .unwrap();
+
+    // the swap lands 1_000 tokens and another 5_000 lamports in the account
+    LAMPORT_TOP_UP.with(|t| *t.borrow_mut() = 5_000);
+    let result = Processor::process_single_step_swap_in(
+      &program_id,
+      &SwapInInstruction {
+        amount_in: NonZeroU64::new(500).unwrap(),
+        flags: 0,
+        rebate_nonce: 0,
+        rebate_pool_index: 0,
+      },
+      &infos,
+      ExchangerType::SplTokenSwap,
+    );
+    LAMPORT_TOP_UP.with(|t| *t.borrow_mut() = 0);
+    assert_eq!(result, Ok(()));
+    assert_eq!(infos[intermediate].lamports(), reserve + 6_000);
+    // the Out leg is handed the token amount only
+    assert_eq!(
+      SwapInfo::unpack(&infos[swap_info].data.borrow())
+        .unwrap()
+        .token_latest_amount,
+      1_000
+    );
+  }
 
-    let instruction_data = spl_token_swap::instruction::Swap {
-      amount_in: token_swap_amount_in,
-      minimum_amount_out,
-    };
-    let instruction = spl_token_swap::instruction::swap(
-      spl_token_swap_args.program.key,
-      spl_token_program.inner().key,
-      spl_token_swap_args.swap_info.inner().key,
-      spl_token_swap_args.authority_acc_info.key,
-      source_account_authority.key,
-      source_token_account.inner().key,
-      pool_source_token_acc.inner().key,
-      pool_destination_token_acc.inner().key,
-      destination_token_account.inner().key,
-      spl_token_swap_args.pool_mint.inner().key,
-      spl_token_swap_args.fee_account.inner().key,
-      host_fee_account_key,
-      instruction_data,
-    )?;
+  #[test]
+  fn test_minimum_from_input() {
+    let mut fixture = Fixture::new();
+    let mut mints = vec![];
+    for decimals in [0u8, 6, 9] {
+      let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+      mint_data[44] = decimals;
+      mint_data[0x2d] = 1;
+      mints.push(fixture.add(spl_token::ID, mint_data));
+    }
+    let infos = fixture.infos();
+    let minimum =
+      |input: usize, destination: usize, amount_in: u64, bps: u16, minimum_amount_out: u64| {
+        Processor::minimum_from_input(
+          minimum_amount_out,
+          u64::MAX,
+          &InputMinimum {
+            amount_in: NonZeroU64::new(amount_in).unwrap(),
+            min_out_bps_of_in: bps,
+          },
+          TokenMint::new(&infos[mints[input]]).unwrap(),
+          TokenMint::new(&infos[mints[destination]]).unwrap(),
+          infos[mints[destination]].key,
+        )
+      };
+    // input decimals, destination decimals
+    // 6, 6
+    assert_eq!(minimum(1, 1, 1_000_000, 9_950, 1), Ok(995_000));
+    // 6, 9
+    assert_eq!(minimum(1, 2, 1_000_000, 9_950, 1), Ok(995_000_000));
+    // 9, 6, rounded down
+    assert_eq!(minimum(2, 1, 1_000_000_999, 9_950, 1), Ok(995_000));
+    // 0, 9
+    assert_eq!(minimum(0, 2, 3, 10_000, 1), Ok(3_000_000_000));
+    // 9, 0, the minimum field wins over the rounded down input
+    assert_eq!(minimum(2, 0, 999_999_999, 10_000, 1), Ok(1));
+    assert_eq!(minimum(1, 1, 1_000_000, 9_950, 996_000), Ok(996_000));
+    assert_eq!(
+      minimum(1, 2, u64::MAX, 10_000, 1),
+      Err(ProtocolError::InvalidInput)
+    );
 
-    msg!("invoke spl-token-swap swap");
-    invoke(&instruction, &swap_accounts)?;
-    Ok(())
+    // the destination mint must be the one of the destination account
+    assert_eq!(
+      Processor::minimum_from_input(
+        1,
+        u64::MAX,
+        &InputMinimum {
+          amount_in: NonZeroU64::new(1_000).unwrap(),
+          min_out_bps_of_in: 10_000,
+        },
+        TokenMint::new(&infos[mints[1]]).unwrap(),
+        TokenMint::new(&infos[mints[2]]).unwrap(),
+        infos[mints[1]].key,
+      ),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+    // like the minimum field, it must stay within the expected amount
+    assert_eq!(
+      Processor::minimum_from_input(
+        1,
+        994_999,
+        &InputMinimum {
+          amount_in: NonZeroU64::new(1_000_000).unwrap(),
+          min_out_bps_of_in: 9_950,
+        },
+        TokenMint::new(&infos[mints[1]]).unwrap(),
+        TokenMint::new(&infos[mints[1]]).unwrap(),
+        infos[mints[1]].key,
+      ),
+      Err(ProtocolError::InvalidExpectAmountOut)
+    );
   }
 
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_serumdex<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    let dex_args = SerumDexArgs::with_parsed_args(accounts)?;
+  #[test]
+  fn test_swap_out_minimum_from_input() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let intermediate = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    fixture.accounts[swap_info].lamports = Rent::default().minimum_balance(SwapInfo::LEN);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool_ab = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    let pool_bc = fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    // A with 6 decimals, C with 7
+    let mut mint_accounts = vec![];
+    for (mint, decimals) in [(mint_a, 6u8), (mint_c, 7)] {
+      let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+      mint_data[44] = decimals;
+      mint_data[0x2d] = 1;
+      mint_accounts.push(fixture.add_with_key(mint, spl_token::ID, mint_data));
+    }
+    for i in [source, intermediate, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    Processor::process_start_route(
+      &program_id,
+      &StartRouteInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        amount_in: NonZeroU64::new(500).unwrap(),
+      },
+      &[
+        &infos[..config],
+        &infos[pool_ab..pool_ab + FEE_POOL_ACCOUNTS],
+        &infos[config..=config],
+      ]
+      .concat(),
+    )
+    .unwrap();
+    assert_eq!(balance(intermediate), 1_000);
+
+    let mut out_accounts = vec![
+      infos[intermediate].clone(),
+      infos[destination].clone(),
+      infos[owner].clone(),
+      infos[swap_info].clone(),
+      infos[token_program].clone(),
+      infos[fee_account].clone(),
+    ];
+    out_accounts.extend_from_slice(&infos[pool_bc..pool_bc + FEE_POOL_ACCOUNTS]);
+    let swap_out = |mints: [usize; 2], min_out_bps_of_in: u16| {
+      let mut accounts = out_accounts.clone();
+      accounts.extend(mints.iter().map(|i| infos[mint_accounts[*i]].clone()));
+      accounts.push(infos[config].clone());
+      Processor::process_single_step_swap_out(
+        &program_id,
+        &SwapOutInstruction {
+          expect_amount_out: NonZeroU64::new(1_950).unwrap(),
+          minimum_amount_out: NonZeroU64::new(1_000).unwrap(),
+          flags: SWAP_OUT_FLAG_MINIMUM_FROM_INPUT,
+          client_route_id: None,
+          input_minimum: Some(InputMinimum {
+            amount_in: NonZeroU64::new(500).unwrap(),
+            min_out_bps_of_in,
+          }),
+        },
+        &accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+    assert_eq!(
+      swap_out([0, 0], 3_800),
+      Err(ProtocolError::InvalidTokenMint.into())
+    );
+    // 500 of A is 5_000 of C, 41% of it is over the expected 1_950
+    assert_eq!(
+      swap_out([0, 1], 4_100),
+      Err(ProtocolError::InvalidExpectAmountOut.into())
+    );
+    assert_eq!(balance(destination), 0);
+    // 38% of it raises the minimum to 1_900, the 2_000 out passes
+    swap_out([0, 1], 3_800).unwrap();
+    // a quarter of the 50 surplus goes to the fee account
+    assert_eq!((balance(destination), balance(fee_account)), (1_988, 12));
+  }
 
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_swap_out_close_on_complete() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let intermediate = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    let rent = Rent::default().minimum_balance(SwapInfo::LEN);
+    fixture.accounts[swap_info].lamports = rent;
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool_ab = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    let pool_bc = fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    for i in [source, intermediate, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let owner_lamports = infos[owner].lamports();
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    Processor::process_start_route(
+      &program_id,
+      &StartRouteInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        amount_in: NonZeroU64::new(500).unwrap(),
+      },
+      &[
+        &infos[..config],
+        &infos[pool_ab..pool_ab + FEE_POOL_ACCOUNTS],
+        &infos[config..=config],
+      ]
+      .concat(),
+    )
+    .unwrap();
+
+    let mut out_accounts = vec![
+      infos[intermediate].clone(),
+      infos[destination].clone(),
+      infos[owner].clone(),
+      infos[swap_info].clone(),
+      infos[token_program].clone(),
+      infos[fee_account].clone(),
+    ];
+    out_accounts.extend_from_slice(&infos[pool_bc..pool_bc + FEE_POOL_ACCOUNTS]);
+    out_accounts.push(infos[config].clone());
+    let swap_out = || {
+      Processor::process_single_step_swap_out(
+        &program_id,
+        &SwapOutInstruction {
+          expect_amount_out: NonZeroU64::new(2_000).unwrap(),
+          minimum_amount_out: NonZeroU64::new(1_000).unwrap(),
+          flags: SWAP_OUT_FLAG_CLOSE_ON_COMPLETE,
+          client_route_id: None,
+          input_minimum: None,
+        },
+        &out_accounts,
+        ExchangerType::SplTokenSwap,
+      )
+    };
+    let set_owner = |key: &Pubkey| {
+      let mut data = infos[swap_info].data.borrow_mut();
+      let mut state = SwapInfo::load(&data).unwrap();
+      state.owner = *key;
+      state.store(&mut data).unwrap();
+    };
+    // only the SwapInfo owner gets its lamports
+    set_owner(&Pubkey::new_unique());
+    assert_eq!(swap_out(), Err(ProtocolError::InvalidOwner.into()));
+    assert_eq!(infos[swap_info].lamports(), rent);
+    assert_eq!(balance(intermediate), 1_000);
+
+    // the 1_000 intermediate tokens swapped, then the SwapInfo closed
+    set_owner(&user);
+    swap_out().unwrap();
+    assert_eq!(balance(destination), 2_000);
+    assert_eq!(infos[swap_info].lamports(), 0);
+    assert!(infos[swap_info].data.borrow().iter().all(|b| *b == 0));
+    assert_eq!(infos[owner].lamports(), owner_lamports + rent);
+  }
 
-    let side = dex_args.find_side(&source_token_account.mint()?)?;
+  #[test]
+  fn test_swap_info_aliasing_user_account() {
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&Pubkey::new_unique(), 500);
+    let destination = fixture.add_token_account(&Pubkey::new_unique(), 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&Pubkey::new_unique(), 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+
+    for aliased in [source, destination, owner] {
+      let mut user_accounts = vec![
+        infos[source].clone(),
+        infos[destination].clone(),
+        infos[owner].clone(),
+      ];
+      user_accounts[aliased - source] = infos[swap_info].clone();
+      let mut in_accounts = user_accounts.clone();
+      in_accounts.extend_from_slice(&[
+        infos[swap_info].clone(),
+        infos[token_program].clone(),
+        infos[config].clone(),
+      ]);
+      assert_eq!(
+        Processor::process_single_step_swap_in(
+          &program_id,
+          &SwapInInstruction {
+            amount_in: NonZeroU64::new(500).unwrap(),
+            flags: 0,
+            rebate_nonce: 0,
+            rebate_pool_index: 0,
+          },
+          &in_accounts,
+          ExchangerType::SplTokenSwap,
+        ),
+        Err(ProtocolError::InvalidAccountData.into())
+      );
+      let mut out_accounts = user_accounts;
+      out_accounts.extend_from_slice(&[
+        infos[swap_info].clone(),
+        infos[token_program].clone(),
+        infos[fee_account].clone(),
+        infos[config].clone(),
+      ]);
+      assert_eq!(
+        Processor::process_single_step_swap_out(
+          &program_id,
+          &SwapOutInstruction {
+            expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+            minimum_amount_out: NonZeroU64::new(900).unwrap(),
+            flags: 0,
+            client_route_id: None,
+            input_minimum: None,
+          },
+          &out_accounts,
+          ExchangerType::SplTokenSwap,
+        ),
+        Err(ProtocolError::InvalidAccountData.into())
+      );
+    }
+  }
 
-    let (pc_wallet_account, coin_wallet_account) = match side {
-      DexSide::Bid => (source_token_account, destination_token_account),
-      DexSide::Ask => (destination_token_account, source_token_account),
+  #[test]
+  fn test_start_route_then_swap_out() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let intermediate = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let swap_info = fixture.add(program_id, vec![0u8; SwapInfo::LEN]);
+    fixture.accounts[swap_info].lamports = Rent::default().minimum_balance(SwapInfo::LEN);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool_ab = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    let pool_bc = fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    for i in [source, intermediate, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+
+    let start_route = StartRouteInstruction {
+      exchanger: ExchangerType::SplTokenSwap,
+      amount_in: NonZeroU64::new(500).unwrap(),
     };
+    let start_accounts = &[
+      &infos[..config],
+      &infos[pool_ab..pool_ab + FEE_POOL_ACCOUNTS],
+      &infos[config..=config],
+    ]
+    .concat();
+    assert_eq!(
+      Processor::process_start_route(&program_id, &start_route, start_accounts),
+      Ok(())
+    );
+    assert_eq!((balance(source), balance(intermediate)), (0, 1_000));
+    let state = SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
+    assert_eq!(state.owner, user);
+    assert_eq!(state.token_account, COption::Some(*infos[intermediate].key));
+    assert_eq!(state.token_latest_amount, 1_000);
+    assert_eq!(state.created_slot, STUB_SLOT);
+    // the SwapInfo is initialized once, a second start needs a new one
+    assert_eq!(
+      Processor::process_start_route(&program_id, &start_route, start_accounts),
+      Err(ProtocolError::InvalidAccountFlags.into())
+    );
 
-    let orderbook = serum_dex::order::OrderbookClient {
-      market: serum_dex::order::MarketAccounts {
-        market: dex_args.market.inner(),
-        open_orders: dex_args.open_orders.inner(),
-        request_queue: dex_args.request_queue_acc,
-        event_queue: dex_args.event_queue_acc,
-        bids: dex_args.bids_acc,
-        asks: dex_args.asks_acc,
-        order_payer_authority: source_token_account.inner(),
-        coin_vault: dex_args.coin_vault_acc.inner(),
-        pc_vault: dex_args.pc_vault_acc.inner(),
-        vault_signer: dex_args.vault_signer_acc,
-        coin_wallet: coin_wallet_account.inner(),
-      },
-      open_order_authority: source_account_authority,
-      pc_wallet: pc_wallet_account.inner(),
-      dex_program: dex_args.program_acc,
-      token_program: spl_token_program.inner(),
-      rent: dex_args.rent_sysvar_acc,
+    let mut out_accounts = vec![
+      infos[intermediate].clone(),
+      infos[destination].clone(),
+      infos[owner].clone(),
+      infos[swap_info].clone(),
+      infos[token_program].clone(),
+      infos[fee_account].clone(),
+    ];
+    out_accounts.extend_from_slice(&infos[pool_bc..pool_bc + FEE_POOL_ACCOUNTS]);
+    out_accounts.push(infos[config].clone());
+    let swap_out = || {
+      Processor::process_single_step_swap_out(
+        &program_id,
+        &SwapOutInstruction {
+          expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+          minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+          flags: 0,
+          client_route_id: Some(7),
+          input_minimum: None,
+        },
+        &out_accounts,
+        ExchangerType::SplTokenSwap,
+      )
     };
-    // orderbook.cancel_order(side)?;
-    match side {
-      DexSide::Bid => orderbook.buy(amount_in, None)?,
-      DexSide::Ask => orderbook.sell(amount_in, None)?,
+    assert_eq!(state.mid_mint, COption::Some(mint_b));
+    // intermediate account closed and recreated at the same address
+    infos[intermediate].data.borrow_mut()[0..32].copy_from_slice(Pubkey::new_unique().as_ref());
+    assert_eq!(swap_out(), Err(ProtocolError::InvalidTokenAccount.into()));
+    infos[intermediate].data.borrow_mut()[0..32].copy_from_slice(mint_b.as_ref());
+    swap_out().unwrap();
+    // 2_000 out, a quarter of the 400 surplus goes to the fee account
+    assert_eq!(balance(intermediate), 0);
+    assert_eq!((balance(destination), balance(fee_account)), (1_900, 100));
+    // the route id comes back with the amount the destination received
+    let (_, return_data) = get_return_data().unwrap();
+    assert_eq!(return_data[..8], 7u64.to_le_bytes());
+    assert_eq!(return_data[8..], 1_900u64.to_le_bytes());
+    let state = SwapInfo::unpack(&infos[swap_info].data.borrow()).unwrap();
+    assert_eq!(state.token_account, COption::None);
+    assert_eq!(state.mid_mint, COption::None);
+
+    // abandoned SwapInfo, closed by anyone once older than the config TTL
+    let close_stale_accounts = [
+      infos[swap_info].clone(),
+      infos[owner].clone(),
+      infos[config].clone(),
+    ];
+    assert_eq!(
+      Processor::process_close_stale_swap_info(&program_id, &close_stale_accounts),
+      Err(ProtocolError::SwapInfoNotStale.into())
+    );
+    Processor::process_set_swap_info_ttl(
+      &program_id,
+      &SwapInfoTtlInstruction { ttl_slots: 500 },
+      &[infos[config].clone(), infos[owner].clone()],
+    )
+    .unwrap();
+    assert_eq!(
+      Processor::process_close_stale_swap_info(&program_id, &close_stale_accounts),
+      Err(ProtocolError::SwapInfoNotStale.into())
+    );
+    let mut state = state;
+    state.created_slot = STUB_SLOT - 500;
+    state
+      .store(&mut infos[swap_info].data.borrow_mut())
+      .unwrap();
+    assert_eq!(
+      Processor::process_close_stale_swap_info(
+        &program_id,
+        &[
+          infos[swap_info].clone(),
+          infos[destination].clone(),
+          infos[config].clone(),
+        ]
+      ),
+      Err(ProtocolError::InvalidOwner.into())
+    );
+    let (rent, owner_lamports) = (infos[swap_info].lamports(), infos[owner].lamports());
+    assert_eq!(
+      Processor::process_close_stale_swap_info(&program_id, &close_stale_accounts),
+      Ok(())
+    );
+    assert_eq!(infos[owner].lamports(), owner_lamports + rent);
+    assert_eq!(infos[swap_info].lamports(), 0);
+  }
+
+  #[test]
+  fn test_swap_transient_route() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &mint_b, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let transient = fixture.add_with_key(
+      transient_key,
+      system_program::id(),
+      vec![0u8; spl_token::ACCOUNT_LEN],
+    );
+    fixture.accounts[transient].lamports = 0;
+    fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    let config = fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let data = TransientRouteInstruction {
+      exchanger_in: ExchangerType::SplTokenSwap,
+      exchanger_out: ExchangerType::SplTokenSwap,
+      in_accounts_len: FEE_POOL_ACCOUNTS as u8,
+      amount_in: NonZeroU64::new(500).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+      minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+      flags: 0,
+    };
+
+    // the transient account must be the PDA of the owner and the mint
+    let mut wrong_transient = infos.clone();
+    wrong_transient[transient] = infos[fee_account].clone();
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &wrong_transient),
+      Err(ProtocolError::InvalidProgramAddress.into())
+    );
+    let short = [&infos[..8 + FEE_POOL_ACCOUNTS - 1], &infos[config..]].concat();
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &short),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    // the source must belong to the signer
+    let mut foreign_source = infos.clone();
+    foreign_source[source] = infos[fee_account].clone();
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &foreign_source),
+      Err(ProtocolError::InvalidOwner.into())
+    );
+
+    // second leg through the a/b pool, the route never reaches c
+    let mut broken = infos.clone();
+    // the config comes last
+    let pools = broken.len() - 1 - 2 * FEE_POOL_ACCOUNTS;
+    for i in 0..FEE_POOL_ACCOUNTS {
+      broken[pools + FEE_POOL_ACCOUNTS + i] = infos[pools + i].clone();
     }
-    msg!("serum.settle");
-    orderbook.settle(None)?;
-    Ok(())
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &broken),
+      Err(ProtocolError::in_leg(
+        1,
+        ProtocolError::MintRoutingMismatch.into()
+      ))
+    );
+
+    let owner_lamports = infos[owner].lamports();
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &infos),
+      Ok(())
+    );
+    // 500 a -> 1_000 b -> 2_000 c, a quarter of the 400 surplus is the fee
+    assert_eq!(balance(source), 0);
+    assert_eq!((balance(destination), balance(fee_account)), (1_900, 100));
+    // closed, the rent is back with the owner
+    assert_eq!(infos[transient].lamports(), 0);
+    assert!(infos[transient].data.borrow().iter().all(|b| *b == 0));
+    assert_eq!(infos[owner].lamports(), owner_lamports);
+
+    // anyone may fund the address before the route, it is still created
+    infos[transient].assign(&system_program::id());
+    **infos[transient].lamports.borrow_mut() = 1_000;
+    add_amount(&infos[source], 500, true);
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &infos),
+      Ok(())
+    );
+    assert_eq!((balance(destination), balance(fee_account)), (3_800, 200));
+    assert_eq!(infos[transient].lamports(), 0);
+    assert_eq!(infos[owner].lamports(), owner_lamports + 1_000);
+
+    // an exempt owner keeps the whole output, as on the direct swap
+    let mut protocol_config = ProtocolConfig::unpack(&infos[config].data.borrow()).unwrap();
+    protocol_config.add_fee_exempt_owner(&user).unwrap();
+    ProtocolConfig::pack(protocol_config, &mut infos[config].data.borrow_mut()).unwrap();
+    infos[transient].assign(&system_program::id());
+    add_amount(&infos[source], 500, true);
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &infos),
+      Ok(())
+    );
+    assert_eq!((balance(destination), balance(fee_account)), (5_800, 200));
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments)]
-  fn process_step_stableswap<'a, 'b: 'a>(
-    _program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    sol_log_compute_units();
+  #[test]
+  fn test_native_swap_sol_to_usdc() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let native_mint = spl_token::native_mint::id();
+    let usdc = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &native_mint, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let destination = fixture.add_token_account(&usdc, 0);
+    fixture.accounts[destination].data[32..64].copy_from_slice(user.as_ref());
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    let transient = fixture.add_with_key(
+      transient_key,
+      system_program::id(),
+      vec![0u8; spl_token::ACCOUNT_LEN],
+    );
+    fixture.accounts[transient].lamports = 0;
+    fixture.add_with_key(native_mint, spl_token::ID, mint_data);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&usdc, 0);
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    fixture.add_token_swap(&token_swap_program_id(), &native_mint, &usdc);
+    fixture.add_config(&program_id, config_data);
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let mut data = NativeSwapInstruction {
+      exchanger: ExchangerType::SplTokenSwap,
+      unwrap: false,
+      amount_in: NonZeroU64::new(10_000_000).unwrap(),
+      expect_amount_out: NonZeroU64::new(900_000).unwrap(),
+      minimum_amount_out: NonZeroU64::new(800_000).unwrap(),
+      lamport_fee: false,
+    };
 
-    let swap_args = StableSwapArgs::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+    // 10_000_000 lamports can't also pay the WSOL account rent
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &infos),
+      Err(ProtocolError::InsufficientLamports.into())
+    );
+    data.amount_in = NonZeroU64::new(500_000).unwrap();
+    let mut wrong_mint = infos.clone();
+    wrong_mint[transient + 1] = infos[destination].clone();
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &wrong_mint),
+      Err(ProtocolError::InvalidTokenMint.into())
+    );
 
-    msg!(
-      "swap using stable-swap, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &infos),
+      Ok(())
     );
+    // 500_000 lamports -> 1_000_000 usdc, a quarter of the 100_000 surplus is
+    // the fee
+    assert_eq!(
+      (balance(destination), balance(fee_account)),
+      (975_000, 25_000)
+    );
+    // closed, only the swapped lamports are gone
+    assert_eq!(infos[transient].lamports(), 0);
+    assert!(infos[transient].data.borrow().iter().all(|b| *b == 0));
+    assert_eq!(infos[owner].lamports(), 10_000_000 - 500_000);
+
+    // anyone may fund the address before the swap, it is still created and
+    // the funded lamports go to the owner on close
+    infos[transient].assign(&system_program::id());
+    **infos[transient].lamports.borrow_mut() = 1_000;
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &infos),
+      Ok(())
+    );
+    assert_eq!(
+      (balance(destination), balance(fee_account)),
+      (1_950_000, 50_000)
+    );
+    assert_eq!(infos[transient].lamports(), 0);
+    assert_eq!(infos[owner].lamports(), 10_000_000 - 1_000_000 + 1_000);
+  }
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+  #[test]
+  fn test_native_swap_lamport_fee() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let native_mint = spl_token::native_mint::id();
+    let usdc = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &native_mint, &program_id);
+    let rent = Rent::default().minimum_balance(spl_token::ACCOUNT_LEN);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&usdc, 10_000_000);
+    fixture.accounts[source].data[32..64].copy_from_slice(user.as_ref());
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    let transient = fixture.add_with_key(
+      transient_key,
+      system_program::id(),
+      vec![0u8; spl_token::ACCOUNT_LEN],
+    );
+    fixture.accounts[transient].lamports = 0;
+    fixture.add_with_key(native_mint, spl_token::ID, mint_data);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+    // a usdc account, never used as the fee is in lamports
+    let fee_account = fixture.add_token_account(&usdc, 0);
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    fixture.add_token_swap(&token_swap_program_id(), &usdc, &native_mint);
+    let collector = fixture.add_with_key(
+      Pubkey::from_str(OWNER_KEY).unwrap(),
+      system_program::id(),
+      vec![],
+    );
+    fixture.add_config(&program_id, config_data);
+    fixture.accounts[collector].lamports = 0;
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let data = NativeSwapInstruction {
+      exchanger: ExchangerType::SplTokenSwap,
+      unwrap: true,
+      amount_in: NonZeroU64::new(5_000_000).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_000_000).unwrap(),
+      minimum_amount_out: NonZeroU64::new(900_000).unwrap(),
+      lamport_fee: true,
+    };
 
-    let (swap_source_token_acc, swap_destination_token_acc) =
-      swap_args.find_token_pair(&source_token_mint)?;
+    let mut wrong_collector = infos.clone();
+    wrong_collector[collector] = infos[fee_account].clone();
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &wrong_collector),
+      Err(ProtocolError::InvalidFeeCollector.into())
+    );
+    assert_eq!(balance(source), 10_000_000);
 
-    if swap_source_token_acc.mint()? != source_token_mint {
-      return Err(ProtocolError::InvalidTokenMint.into());
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &infos),
+      Ok(())
+    );
+    // 5_000_000 usdc -> 10_000_000 lamports, a quarter of the 9_000_000
+    // surplus is the fee, paid in lamports rather than out of the WSOL. The
+    // stub swap only credits the token amount, so the owner gets back the
+    // rent alone when the transient account is closed
+    assert_eq!(balance(source), 5_000_000);
+    assert_eq!(balance(fee_account), 0);
+    assert_eq!(infos[transient].lamports(), 0);
+    assert_eq!(infos[collector].lamports(), 2_250_000);
+    assert_eq!(infos[owner].lamports(), 10_000_000 - 2_250_000);
+
+    // the owner can't cover the fee
+    **infos[owner].lamports.borrow_mut() = rent;
+    assert_eq!(
+      Processor::process_native_swap(&program_id, &data, &infos),
+      Err(ProtocolError::InsufficientLamports.into())
+    );
+  }
+
+  #[test]
+  fn test_multi_output_swap() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let destination_b = fixture.add_token_account(&mint_b, 0);
+    let fee_b = fixture.add_token_account(&mint_b, 0);
+    let destination_c = fixture.add_token_account(&mint_c, 0);
+    let fee_c = fixture.add_token_account(&mint_c, 0);
+    fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.add_token_swap(&swap_program, &mint_a, &mint_c);
+    let config = fixture.add_config(&program_id, config_data);
+    for i in [source, destination_b, destination_c] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-    if swap_destination_token_acc.mint()? != destination_token_mint {
-      return Err(ProtocolError::InvalidTokenMint.into());
+    for i in [fee_b, fee_c] {
+      fixture.accounts[i].data[32..64]
+        .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
     }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let leg = |amount_in: u64, expect_amount_out: u64, minimum_amount_out: u64| OutputLeg {
+      exchanger: ExchangerType::SplTokenSwap,
+      accounts_len: FEE_POOL_ACCOUNTS as u8,
+      amount_in: NonZeroU64::new(amount_in).unwrap(),
+      expect_amount_out: NonZeroU64::new(expect_amount_out).unwrap(),
+      minimum_amount_out: NonZeroU64::new(minimum_amount_out).unwrap(),
+    };
+    let data = MultiOutputSwapInstruction {
+      amount_in: NonZeroU64::new(1_000).unwrap(),
+      legs: [leg(600, 1_100, 1_000), leg(400, 800, 700)],
+    };
 
-    let swap_accounts = vec![
-      swap_args.swap_info.inner().clone(),
-      swap_args.authority_acc.clone(),
-      source_account_authority.clone(),
-      source_token_account.inner().clone(),
-      swap_source_token_acc.inner().clone(),
-      swap_destination_token_acc.inner().clone(),
-      destination_token_account.inner().clone(),
-      swap_args.admin_fee_acc.clone(),
-      spl_token_program.inner().clone(),
-      swap_args.program_acc.clone(),
-    ];
+    // each output must be another token than the source
+    let mut same_mint = infos.clone();
+    same_mint[destination_c] = infos[source].clone();
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &data, &same_mint),
+      Err(ProtocolError::InvalidTokenMint.into())
+    );
+    let short = [&infos[..config - 1], &infos[config..]].concat();
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &data, &short),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    // the legs spend more than amount_in, or more than a u64
+    let over = MultiOutputSwapInstruction {
+      amount_in: NonZeroU64::new(999).unwrap(),
+      legs: data.legs.clone(),
+    };
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &over, &infos),
+      Err(ProtocolError::InvalidInput.into())
+    );
+    let overflow = MultiOutputSwapInstruction {
+      amount_in: NonZeroU64::new(u64::MAX).unwrap(),
+      legs: [leg(u64::MAX, u64::MAX, 1), leg(1, 2, 1)],
+    };
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &overflow, &infos),
+      Err(ProtocolError::Overflow.into())
+    );
+    // the c output through the a/b pool, caught before the first leg swaps
+    let mut broken = infos.clone();
+    // the config comes last
+    let pools = broken.len() - 1 - 2 * FEE_POOL_ACCOUNTS;
+    for i in 0..FEE_POOL_ACCOUNTS {
+      broken[pools + FEE_POOL_ACCOUNTS + i] = infos[pools + i].clone();
+    }
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &data, &broken),
+      Err(ProtocolError::in_leg(
+        1,
+        ProtocolError::MintRoutingMismatch.into()
+      ))
+    );
+    assert_eq!(balance(source), 1_000);
 
-    let instruction = stable_swap::instruction::swap(
-      swap_args.program_acc.key,
-      spl_token_program.inner().key,
-      swap_args.swap_info.inner().key,
-      swap_args.authority_acc.key,
-      source_account_authority.key,
-      source_token_account.inner().key,
-      swap_source_token_acc.inner().key,
-      swap_destination_token_acc.inner().key,
-      destination_token_account.inner().key,
-      swap_args.admin_fee_acc.key,
-      amount_in,
-      minimum_amount_out,
-    )?;
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &data, &infos),
+      Ok(())
+    );
+    // 600 a -> 1_200 b, a quarter of the 100 surplus is the fee
+    assert_eq!((balance(destination_b), balance(fee_b)), (1_175, 25));
+    // 400 a -> 800 c, no surplus
+    assert_eq!((balance(destination_c), balance(fee_c)), (800, 0));
+    assert_eq!(balance(source), 0);
+
+    // the first leg can't swap its part of the now empty source
+    assert_eq!(
+      Processor::process_multi_output_swap(&program_id, &data, &infos),
+      Err(ProtocolError::in_leg(
+        0,
+        ProtocolError::InvalidSourceBalance.into()
+      ))
+    );
+  }
 
-    msg!("invoke saber-stableswap swap");
+  #[test]
+  fn test_wrapped_swaps_pay_treasury() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 10_000);
+    let destination_b = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee_b = fixture.add_token_account(&mint_b, 0);
+    let pool = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let backup_pool = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let stable_pool = fixture.add_stable_swap(
+      &crate::parser::base::STABLE_SWAP_PROGRAM_IDS[0],
+      &mint_a,
+      &mint_b,
+      false,
+    );
+    let pool_c = fixture.add_token_swap(&swap_program, &mint_a, &mint_c);
+    let mints = fixture.add_with_key(mint_a, spl_token::ID, mint_data.clone());
+    fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+    let destination_c = fixture.add_token_account(&mint_c, 0);
+    let fee_c = fixture.add_token_account(&mint_c, 0);
+    let treasury = fixture.add_token_account(&mint_b, 0);
+    let config = fixture.add_config(&program_id, vec![0u8; ProtocolConfig::LEN]);
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config.treasury_token_account = COption::Some(fixture.keys[treasury]);
+    protocol_config.treasury_bps = 2_000;
+    ProtocolConfig::pack(protocol_config, &mut fixture.accounts[config].data).unwrap();
+    for i in [source, destination_b, destination_c] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    for i in [fee_b, fee_c] {
+      fixture.accounts[i].data[32..64]
+        .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let nonzero = |value: u64| NonZeroU64::new(value).unwrap();
+    // the user accounts, then the pool accounts, then the treasury and the
+    // config
+    let with_pools = |pools: &[Range<usize>]| {
+      let mut accounts = infos[..=fee_b].to_vec();
+      for pool in pools {
+        accounts.extend_from_slice(&infos[pool.clone()]);
+      }
+      accounts.extend_from_slice(&infos[treasury..=config]);
+      accounts
+    };
+    // each swap is 500 a -> 1_000 b, a quarter of the 400 surplus is the fee
+    // and a fifth of it goes to the treasury
+    let paid = |swaps: u64| {
+      assert_eq!(
+        (balance(destination_b), balance(fee_b), balance(treasury)),
+        (900 * swaps, 80 * swaps, 20 * swaps)
+      );
+    };
 
-    sol_log_compute_units();
-    invoke(&instruction, &swap_accounts)?;
-    sol_log_compute_units();
-    Ok(())
+    Processor::process_swap_percent(
+      &program_id,
+      &SwapPercentInstruction {
+        exchanger: ExchangerType::SplTokenSwap,
+        percent_bps: 500,
+        expect_amount_out: nonzero(600),
+        minimum_amount_out: nonzero(900),
+      },
+      &with_pools(&[pool..pool + FEE_POOL_ACCOUNTS]),
+    )
+    .unwrap();
+    paid(1);
+
+    let guard_accounts = [
+      &infos[mints..mints + 2],
+      &with_pools(&[stable_pool..stable_pool + 6])[..],
+    ]
+    .concat();
+    Processor::process_stable_swap_guard(
+      &program_id,
+      &SwapStableSwapGuardInstruction {
+        amount_in: nonzero(500),
+        expect_amount_out: nonzero(600),
+        min_out_bps_of_in: 10_000,
+      },
+      &guard_accounts,
+    )
+    .unwrap();
+    paid(2);
+
+    Processor::process_swap_with_fallback(
+      &program_id,
+      &SwapWithFallbackInstruction {
+        amount_in: nonzero(500),
+        expect_amount_out: nonzero(600),
+        minimum_amount_out: nonzero(400),
+        candidates: vec![(ExchangerType::SplTokenSwap, FEE_POOL_ACCOUNTS as u8)],
+      },
+      &with_pools(&[pool..pool + FEE_POOL_ACCOUNTS]),
+    )
+    .unwrap();
+    paid(3);
+
+    Processor::process_swap_with_backup(
+      &program_id,
+      &SwapWithBackupInstruction {
+        amount_in: nonzero(500),
+        expect_amount_out: nonzero(600),
+        minimum_amount_out: nonzero(400),
+        primary: (ExchangerType::SplTokenSwap, FEE_POOL_ACCOUNTS as u8),
+      },
+      &with_pools(&[pool..pool + FEE_POOL_ACCOUNTS, backup_pool..stable_pool]),
+    )
+    .unwrap();
+    paid(4);
+
+    // the c leg has no surplus, so no fee to share with the b treasury
+    let leg = |expect_amount_out: u64| OutputLeg {
+      exchanger: ExchangerType::SplTokenSwap,
+      accounts_len: FEE_POOL_ACCOUNTS as u8,
+      amount_in: nonzero(500),
+      expect_amount_out: nonzero(expect_amount_out),
+      minimum_amount_out: nonzero(900),
+    };
+    let multi_output_accounts = [
+      &infos[source..=source],
+      &infos[owner..=token_program],
+      &infos[destination_b..=destination_b],
+      &infos[fee_b..=fee_b],
+      &infos[destination_c..=fee_c],
+      &infos[pool..pool + FEE_POOL_ACCOUNTS],
+      &infos[pool_c..pool_c + FEE_POOL_ACCOUNTS],
+      &infos[treasury..=config],
+    ]
+    .concat();
+    Processor::process_multi_output_swap(
+      &program_id,
+      &MultiOutputSwapInstruction {
+        amount_in: nonzero(1_000),
+        legs: [leg(600), leg(1_000)],
+      },
+      &multi_output_accounts,
+    )
+    .unwrap();
+    paid(5);
+    assert_eq!((balance(destination_c), balance(fee_c)), (1_000, 0));
+    assert_eq!(balance(source), 10_000 - 6 * 500);
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_raydium<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    let swap_args = RaydiumSwapArgs::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_check_route_mints() {
+    let (a, b, c, d) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let broken = |leg: u8| -> ProgramResult {
+      Err(ProtocolError::in_leg(
+        leg,
+        ProtocolError::MintRoutingMismatch.into(),
+      ))
+    };
+    // a -> b -> c, pools in either order, unknown pools are not checked
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (b, c)], &[Some((b, a)), Some((b, c))], false),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (b, c)], &[None, None], false),
+      Ok(())
+    );
+    // second leg starts from another mint than b
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (d, c)], &[None, None], false),
+      broken(1)
+    );
+    // the pool of the first leg trades a/d
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (b, c)], &[Some((a, d)), None], false),
+      broken(0)
+    );
 
-    msg!(
-      "swap using raydium, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+    // a split into b and c
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (a, c)], &[Some((a, b)), Some((c, a))], true),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (b, c)], &[None, None], true),
+      broken(1)
     );
+    assert_eq!(
+      Processor::check_route_mints(&[(a, b), (a, c)], &[Some((a, b)), Some((a, b))], true),
+      broken(1)
+    );
+  }
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+  #[test]
+  fn test_check_pool_creator() {
+    let program_id = Pubkey::new_unique();
+    let (creator, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut creators = TrustedPoolCreators::new();
+    creators.add(&creator).unwrap();
+    let mut creators_data = vec![0u8; TrustedPoolCreators::LEN];
+    TrustedPoolCreators::pack(creators, &mut creators_data).unwrap();
+    let mut fixture = Fixture::new();
+    let pool = fixture.add_raydium_cpmm(&mint_a, &mint_b, &creator);
+    let stable_pool = fixture.add_stable_swap(
+      &crate::parser::base::STABLE_SWAP_PROGRAM_IDS[0],
+      &mint_a,
+      &mint_b,
+      false,
+    );
+    fixture.accounts[stable_pool].data[75..107].copy_from_slice(creator.as_ref());
+    let trusted = fixture.add_with_key(
+      derive_trusted_pool_creators_address(&program_id).0,
+      program_id,
+      creators_data.clone(),
+    );
+    let untrusted = fixture.add(program_id, creators_data);
+    let infos = fixture.infos();
+    let (cpmm_accounts, stable_accounts) =
+      (&infos[pool..pool + 9], &infos[stable_pool..stable_pool + 6]);
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    let check = |exchanger: ExchangerType,
+                 pool_accounts: &[AccountInfo],
+                 creators_acc: Option<&AccountInfo>,
+                 config: &ProtocolConfig| {
+      Processor::check_pool_creator(&program_id, exchanger, pool_accounts, creators_acc, config)
+    };
 
-    let swap_accounts = vec![
-      swap_args.program_id.clone(),
-      spl_token_program.inner().clone(),
-      swap_args.amm_info.inner().clone(),
-      swap_args.authority.clone(),
-      swap_args.open_orders.inner().clone(),
-      swap_args.target_orders.clone(),
-      swap_args.pool_token_coin.inner().clone(),
-      swap_args.pool_token_pc.inner().clone(),
-      swap_args.serum_dex_program_id.clone(),
-      swap_args.serum_market.inner().clone(),
-      swap_args.bids.clone(),
-      swap_args.asks.clone(),
-      swap_args.event_q.clone(),
-      swap_args.coin_vault.inner().clone(),
-      swap_args.pc_vault.inner().clone(),
-      swap_args.vault_signer.clone(),
-      source_token_account.inner().clone(),
-      destination_token_account.inner().clone(),
-      source_account_authority.clone(),
+    // off by default, nothing is read
+    assert_eq!(
+      check(
+        ExchangerType::StableSwap,
+        stable_accounts,
+        Some(&infos[trusted]),
+        &config
+      ),
+      Ok(())
+    );
+    config.check_pool_creators = true;
+    assert_eq!(
+      check(
+        ExchangerType::RaydiumCpmm,
+        cpmm_accounts,
+        Some(&infos[trusted]),
+        &config
+      ),
+      Ok(())
+    );
+    // swaps without the list aren't checked, whatever the pool
+    assert_eq!(
+      check(ExchangerType::StableSwap, stable_accounts, None, &config),
+      Ok(())
+    );
+    // a list at another address than the program's
+    assert_eq!(
+      check(
+        ExchangerType::RaydiumCpmm,
+        cpmm_accounts,
+        Some(&infos[untrusted]),
+        &config
+      ),
+      Err(ProtocolError::InvalidTrustedPoolCreatorsAccount.into())
+    );
+    // the stable swap admin runs the pool, it isn't recorded as its creator
+    assert_eq!(
+      check(
+        ExchangerType::StableSwap,
+        stable_accounts,
+        Some(&infos[trusted]),
+        &config
+      ),
+      Err(ProtocolError::UntrustedPoolCreator.into())
+    );
+    infos[pool].data.borrow_mut()[40..72].copy_from_slice(other.as_ref());
+    assert_eq!(
+      check(
+        ExchangerType::RaydiumCpmm,
+        cpmm_accounts,
+        Some(&infos[trusted]),
+        &config
+      ),
+      Err(ProtocolError::UntrustedPoolCreator.into())
+    );
+  }
+
+  #[test]
+  fn test_trusted_pool_creators() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (user, creator) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut protocol_config = ProtocolConfig::new(&user);
+    protocol_config.check_pool_creators = true;
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(protocol_config, &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let owner = fixture.add_with_key(user, system_program::id(), vec![]);
+    fixture.accounts[owner].lamports = 10_000_000;
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_token_account(&mint_b, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    let creators = fixture.add_with_key(
+      derive_trusted_pool_creators_address(&program_id).0,
+      system_program::id(),
+      vec![0u8; TrustedPoolCreators::LEN],
+    );
+    // anyone may fund the address before the list is created
+    fixture.accounts[creators].lamports = 1_000;
+    let config = fixture.add_config(&program_id, config_data);
+    let system_program_acc = fixture.add_program(system_program::id());
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let trusted = || {
+      TrustedPoolCreators::unpack(&infos[creators].data.borrow())
+        .unwrap()
+        .is_trusted(&creator)
+    };
+    let admin_accounts = [
+      infos[config].clone(),
+      infos[owner].clone(),
+      infos[creators].clone(),
+      infos[system_program_acc].clone(),
     ];
+    let swap_accounts = |with_list: bool| {
+      let mut accounts = infos[..creators].to_vec();
+      if with_list {
+        accounts.push(infos[creators].clone());
+      }
+      accounts.push(infos[config].clone());
+      accounts
+    };
+    let swap = |with_list: bool| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(100).unwrap(),
+          expect_amount_out: NonZeroU64::new(200).unwrap(),
+          minimum_amount_out: NonZeroU64::new(200).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining: 0,
+        },
+        &swap_accounts(with_list),
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-    let instruction = raydium::instruction::swap(
-      swap_args.program_id.key,
-      swap_args.amm_info.pubkey(),
-      swap_args.authority.key,
-      swap_args.open_orders.pubkey(),
-      swap_args.target_orders.key,
-      swap_args.pool_token_coin.pubkey(),
-      swap_args.pool_token_pc.pubkey(),
-      swap_args.serum_dex_program_id.key,
-      swap_args.serum_market.pubkey(),
-      swap_args.bids.key,
-      swap_args.asks.key,
-      swap_args.event_q.key,
-      swap_args.coin_vault.pubkey(),
-      swap_args.pc_vault.pubkey(),
-      swap_args.vault_signer.key,
-      source_token_account.pubkey(),
-      destination_token_account.pubkey(),
-      source_account_authority.key,
-      amount_in,
-      minimum_amount_out,
-    )?;
+    Processor::process_add_trusted_pool_creator(
+      &program_id,
+      &TrustedPoolCreatorInstruction { creator },
+      &admin_accounts,
+    )
+    .unwrap();
+    assert_eq!(*infos[creators].owner, program_id);
+    assert_eq!(
+      infos[creators].lamports(),
+      Rent::default().minimum_balance(TrustedPoolCreators::LEN)
+    );
+    assert!(trusted());
+
+    // swaps without the list go through any pool while the config checks
+    // pool creators
+    swap(false).unwrap();
+    assert_eq!((balance(source), balance(destination)), (900, 200));
+    // spl token swap pools don't record their creator, swaps passing the
+    // list can't go through them, however they are made
+    assert_eq!(swap(true), Err(ProtocolError::UntrustedPoolCreator.into()));
+    assert_eq!(
+      Processor::process_swap_percent(
+        &program_id,
+        &SwapPercentInstruction {
+          exchanger: ExchangerType::SplTokenSwap,
+          percent_bps: 5_000,
+          expect_amount_out: NonZeroU64::new(900).unwrap(),
+          minimum_amount_out: NonZeroU64::new(900).unwrap(),
+        },
+        &swap_accounts(true),
+      ),
+      Err(ProtocolError::UntrustedPoolCreator.into())
+    );
+    assert_eq!((balance(source), balance(destination)), (900, 200));
 
-    msg!("invoke raydium swap_base_in");
-    invoke(&instruction, &swap_accounts)?;
-    Ok(())
+    Processor::process_remove_trusted_pool_creator(
+      &program_id,
+      &TrustedPoolCreatorInstruction { creator },
+      &admin_accounts[..3],
+    )
+    .unwrap();
+    assert!(!trusted());
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_raydium_slim<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    let swap_args = RaydiumSwapArgs2::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_route_accounts_limit() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &mint_b, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let transient = fixture.add_with_key(
+      transient_key,
+      system_program::id(),
+      vec![0u8; spl_token::ACCOUNT_LEN],
+    );
+    fixture.accounts[transient].lamports = 0;
+    fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    let config = fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let data = TransientRouteInstruction {
+      exchanger_in: ExchangerType::SplTokenSwap,
+      exchanger_out: ExchangerType::SplTokenSwap,
+      in_accounts_len: FEE_POOL_ACCOUNTS as u8,
+      amount_in: NonZeroU64::new(500).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+      minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+      flags: 0,
+    };
+    let set_limit = |max_route_accounts: u8| {
+      Processor::process_set_max_route_accounts(
+        &program_id,
+        &MaxRouteAccountsInstruction { max_route_accounts },
+        &[infos[config].clone(), infos[owner].clone()],
+      )
+      .unwrap()
+    };
+    // 22 distinct accounts as the swap program is passed for both legs, and
+    // this program
+    let distinct = 23;
+
+    set_limit(distinct - 1);
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &infos),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    assert_eq!(
+      TokenAccount::new(&infos[source]).unwrap().balance(),
+      Ok(500)
+    );
+    set_limit(distinct);
+    assert_eq!(
+      Processor::process_swap_transient_route(&program_id, &data, &infos),
+      Ok(())
+    );
+  }
 
-    msg!("swap using raydium, amount_in: {}", amount_in,);
+  #[test]
+  fn test_mint_fee_cap() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &mint_b, &program_id);
+    // returns the destination and fee account balances after the route
+    let route_with_cap = |max_fee: u64| {
+      let mut config_data = vec![0u8; ProtocolConfig::LEN];
+      ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+      let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+      mint_data[0x2d] = 1;
+      let mut fixture = Fixture::new();
+      let source = fixture.add_token_account(&mint_a, 500);
+      let destination = fixture.add_token_account(&mint_c, 0);
+      let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+      let transient = fixture.add_with_key(
+        transient_key,
+        system_program::id(),
+        vec![0u8; spl_token::ACCOUNT_LEN],
+      );
+      fixture.accounts[transient].lamports = 0;
+      fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+      fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+      fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+      let fee_account = fixture.add_token_account(&mint_c, 0);
+      fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+      fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+      let config = fixture.add_config(&program_id, config_data);
+      for i in [source, destination] {
+        fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+      }
+      fixture.accounts[fee_account].data[32..64]
+        .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+      let mut infos = fixture.infos();
+      infos[owner].is_signer = true;
+      if max_fee > 0 {
+        Processor::process_set_mint_fee_cap(
+          &program_id,
+          &MintFeeCapInstruction {
+            mint: mint_c,
+            max_fee,
+          },
+          &[infos[config].clone(), infos[owner].clone()],
+        )
+        .unwrap();
+      }
+      let data = TransientRouteInstruction {
+        exchanger_in: ExchangerType::SplTokenSwap,
+        exchanger_out: ExchangerType::SplTokenSwap,
+        in_accounts_len: FEE_POOL_ACCOUNTS as u8,
+        amount_in: NonZeroU64::new(500).unwrap(),
+        expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+        minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+        flags: 0,
+      };
+      Processor::process_swap_transient_route(&program_id, &data, &infos).unwrap();
+      let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+      (balance(destination), balance(fee_account))
+    };
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+    // 2_000 c out, the uncapped fee is a quarter of the 400 surplus
+    assert_eq!(route_with_cap(0), (1_900, 100));
+    assert_eq!(route_with_cap(101), (1_900, 100));
+    assert_eq!(route_with_cap(100), (1_900, 100));
+    assert_eq!(route_with_cap(99), (1_901, 99));
+    assert_eq!(route_with_cap(1), (1_999, 1));
+  }
 
-    let swap_accounts = vec![
-      swap_args.program_id.clone(),
-      spl_token_program.inner().clone(),
-      swap_args.amm_info.inner().clone(),
-      swap_args.authority.clone(),
-      swap_args.open_orders.inner().clone(),
-      swap_args.pool_token_coin.inner().clone(),
-      swap_args.pool_token_pc.inner().clone(),
-      swap_args.serum_dex_program_id.clone(),
-      swap_args.serum_market.inner().clone(),
-      swap_args.bids.clone(),
-      swap_args.asks.clone(),
-      swap_args.event_q.clone(),
-      swap_args.coin_vault.inner().clone(),
-      swap_args.pc_vault.inner().clone(),
-      swap_args.vault_signer.clone(),
-      source_token_account.inner().clone(),
-      destination_token_account.inner().clone(),
-      source_account_authority.clone(),
-    ];
+  #[test]
+  fn test_quote_route() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let swap_program = token_swap_program_id();
+    let (mint_a, mint_b, mint_c) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let user = Pubkey::new_unique();
+    let (transient_key, _) = find_transient_address(&user, &mint_b, &program_id);
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_c, 0);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    let transient = fixture.add_with_key(
+      transient_key,
+      system_program::id(),
+      vec![0u8; spl_token::ACCOUNT_LEN],
+    );
+    fixture.accounts[transient].lamports = 0;
+    let mint_b_acc = fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    fixture.add_with_key(system_program::id(), Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_c, 0);
+    let config = fixture.add_config(&program_id, config_data);
+    let pool_ab = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let pool_bc = fixture.add_token_swap(&swap_program, &mint_b, &mint_c);
+    let mint_a_acc = fixture.add_with_key(mint_a, spl_token::ID, vec![]);
+    let mint_c_acc = fixture.add_with_key(mint_c, spl_token::ID, vec![]);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    // deep pools at a 1:2 price, like the swap stub
+    for pool in [pool_ab, pool_bc] {
+      fixture.accounts[pool + 2].data[64..72].copy_from_slice(&1_000_000_000u64.to_le_bytes());
+      fixture.accounts[pool + 3].data[64..72].copy_from_slice(&2_000_000_000u64.to_le_bytes());
+    }
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+
+    let quote_accounts = [config, mint_a_acc, mint_b_acc, mint_c_acc]
+      .iter()
+      .map(|i| infos[*i].clone())
+      .chain(infos[pool_ab..pool_ab + FEE_POOL_ACCOUNTS].iter().cloned())
+      .chain(infos[pool_bc..pool_bc + FEE_POOL_ACCOUNTS].iter().cloned())
+      .collect::<Vec<_>>();
+    let mut input = vec![61u8];
+    input.extend_from_slice(&500u64.to_le_bytes());
+    input.extend_from_slice(&1_600u64.to_le_bytes());
+    input.extend_from_slice(&[2, 0, FEE_POOL_ACCOUNTS as u8, 0, FEE_POOL_ACCOUNTS as u8]);
+    Processor::process(&program_id, &quote_accounts, &input).unwrap();
+    let (_, return_data) = get_return_data().unwrap();
+    let quote = u64::from_le_bytes(*array_ref![return_data, 0, 8]);
+    // 500 a -> 999 b -> 1_997 c, less a quarter of the 397 surplus
+    assert_eq!(quote, 1_898);
+
+    let data = TransientRouteInstruction {
+      exchanger_in: ExchangerType::SplTokenSwap,
+      exchanger_out: ExchangerType::SplTokenSwap,
+      in_accounts_len: FEE_POOL_ACCOUNTS as u8,
+      amount_in: NonZeroU64::new(500).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+      minimum_amount_out: NonZeroU64::new(1_500).unwrap(),
+      flags: 0,
+    };
+    let route_accounts = [
+      &infos[..config],
+      &infos[pool_ab..pool_bc + 7],
+      &infos[config..=config],
+    ]
+    .concat();
+    Processor::process_swap_transient_route(&program_id, &data, &route_accounts).unwrap();
+    let amount_out = TokenAccount::new(&infos[destination])
+      .unwrap()
+      .balance()
+      .unwrap();
+    // each leg quote rounds down, within 0.5% of the execution
+    assert!(quote <= amount_out && amount_out - quote <= amount_out * 50 / 10_000);
+
+    // the legs and their accounts must line up
+    assert_eq!(
+      Processor::quote_route(
+        &program_id,
+        &QuoteRouteInstruction {
+          amount_in: NonZeroU64::new(500).unwrap(),
+          expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+          legs: vec![(ExchangerType::SplTokenSwap, FEE_POOL_ACCOUNTS as u8)],
+        },
+        &quote_accounts,
+      ),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    // the failing leg is reported
+    let mut bad_quote_accounts = quote_accounts.clone();
+    bad_quote_accounts[4 + FEE_POOL_ACCOUNTS] = infos[fee_account].clone();
+    let error = Processor::quote_route(
+      &program_id,
+      &QuoteRouteInstruction {
+        amount_in: NonZeroU64::new(500).unwrap(),
+        expect_amount_out: NonZeroU64::new(1_600).unwrap(),
+        legs: vec![
+          (ExchangerType::SplTokenSwap, FEE_POOL_ACCOUNTS as u8),
+          (ExchangerType::SplTokenSwap, FEE_POOL_ACCOUNTS as u8),
+        ],
+      },
+      &bad_quote_accounts,
+    )
+    .unwrap_err();
+    match error {
+      ProgramError::Custom(code) => assert_eq!(ProtocolError::from_leg_code(code).unwrap().0, 1),
+      e => panic!("not a leg error: {:?}", e),
+    }
+  }
 
-    let instruction = raydium::instruction::swap_slim(
-      swap_args.program_id.key,
-      swap_args.amm_info.pubkey(),
-      swap_args.authority.key,
-      swap_args.open_orders.pubkey(),
-      swap_args.pool_token_coin.pubkey(),
-      swap_args.pool_token_pc.pubkey(),
-      swap_args.serum_dex_program_id.key,
-      swap_args.serum_market.pubkey(),
-      swap_args.bids.key,
-      swap_args.asks.key,
-      swap_args.event_q.key,
-      swap_args.coin_vault.pubkey(),
-      swap_args.pc_vault.pubkey(),
-      swap_args.vault_signer.key,
-      source_token_account.pubkey(),
-      destination_token_account.pubkey(),
-      source_account_authority.key,
-      amount_in,
-      minimum_amount_out,
-    )?;
+  #[test]
+  fn test_set_mint_fee_cap() {
+    let program_id = Pubkey::new_unique();
+    let (admin, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&admin), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let config = fixture.add_config(&program_id, config_data);
+    let admin = fixture.add_with_key(admin, Pubkey::default(), vec![]);
+    let other = fixture.add(Pubkey::default(), vec![]);
+    let mut infos = fixture.infos();
+    let set_cap = |infos: &[AccountInfo], signer: usize, max_fee: u64| {
+      Processor::process_set_mint_fee_cap(
+        &program_id,
+        &MintFeeCapInstruction { mint, max_fee },
+        &[infos[config].clone(), infos[signer].clone()],
+      )
+    };
 
-    msg!("invoke raydium swap_base_in");
-    invoke(&instruction, &swap_accounts)?;
-    Ok(())
+    assert_eq!(
+      set_cap(&infos, admin, 1_000),
+      Err(ProtocolError::InvalidSignerAccount.into())
+    );
+    infos[admin].is_signer = true;
+    infos[other].is_signer = true;
+    assert_eq!(
+      set_cap(&infos, other, 1_000),
+      Err(ProtocolError::InvalidOwner.into())
+    );
+    set_cap(&infos, admin, 1_000).unwrap();
+    let protocol_config = ProtocolConfig::unpack(&infos[config].data.borrow()).unwrap();
+    assert_eq!(protocol_config.cap_fee(&mint, 5_000), 1_000);
+    set_cap(&infos, admin, 0).unwrap();
+    let protocol_config = ProtocolConfig::unpack(&infos[config].data.borrow()).unwrap();
+    assert_eq!(protocol_config.cap_fee(&mint, 5_000), 5_000);
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_crema_finance<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    sol_log_compute_units();
-    msg!("process_step crema-finance");
+  #[test]
+  fn test_check_amount_in_supply() {
+    let mint = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint, 500);
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[36..44].copy_from_slice(&1_000_000u64.to_le_bytes());
+    mint_data[44] = 6;
+    mint_data[0x2d] = 1;
+    let source_mint = fixture.add_with_key(mint, spl_token::ID, mint_data.clone());
+    let other_mint = fixture.add(spl_token::ID, mint_data);
+    let infos = fixture.infos();
+    let source_account = TokenAccount::new(&infos[source]).unwrap();
+    let source_mint = TokenMint::new(&infos[source_mint]).unwrap();
+
+    assert_eq!(
+      Processor::check_amount_in_supply(1_000_000, &source_account, source_mint),
+      Ok(())
+    );
+    // 1.5 scaled by 1e18 instead of 1e6
+    assert_eq!(
+      Processor::check_amount_in_supply(1_500_000_000_000_000_000, &source_account, source_mint),
+      Err(ProtocolError::InvalidInput)
+    );
+    assert_eq!(
+      Processor::check_amount_in_supply(1_000_001, &source_account, source_mint),
+      Err(ProtocolError::InvalidInput)
+    );
+    assert_eq!(
+      Processor::check_amount_in_supply(
+        1,
+        &source_account,
+        TokenMint::new(&infos[other_mint]).unwrap()
+      ),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+  }
 
-    let swap_args = CremaSwapV1Args::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_check_expect_out_ratio() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 500);
+    let destination = fixture.add_token_account(&mint_b, 0);
+    let mut mint_data = vec![0u8; spl_token::MINT_LEN];
+    mint_data[0x2d] = 1;
+    mint_data[44] = 6;
+    let source_mint = fixture.add_with_key(mint_a, spl_token::ID, mint_data.clone());
+    mint_data[44] = 9;
+    let destination_mint = fixture.add_with_key(mint_b, spl_token::ID, mint_data);
+    let infos = fixture.infos();
+    let source_account = TokenAccount::new(&infos[source]).unwrap();
+    let destination_account = TokenAccount::new(&infos[destination]).unwrap();
+    let mut protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+    let check = |expect_amount_out: u64, source_mint: usize, config: &ProtocolConfig| {
+      Processor::check_expect_out_ratio(
+        1_000_000,
+        expect_amount_out,
+        (
+          &source_account,
+          TokenMint::new(&infos[source_mint]).unwrap(),
+        ),
+        (
+          &destination_account,
+          TokenMint::new(&infos[destination_mint]).unwrap(),
+        ),
+        config,
+      )
+    };
 
-    msg!(
-      "swap using crema-finance, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+    // no ratio configured
+    assert_eq!(check(u64::MAX, source_mint, &protocol_config), Ok(()));
+    protocol_config.max_expect_out_ratio = 1_000;
+    // 1 token in, 1_000 tokens out at most
+    assert_eq!(
+      check(1_000_000_000_000, source_mint, &protocol_config),
+      Ok(())
+    );
+    assert_eq!(
+      check(1_000_000_000_001, source_mint, &protocol_config),
+      Err(ProtocolError::InvalidExpectAmountOut)
+    );
+    assert_eq!(
+      check(1, destination_mint, &protocol_config),
+      Err(ProtocolError::InvalidTokenMint)
     );
+  }
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+  #[test]
+  fn test_discounted_fee() {
+    let (mint, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let holder = fixture.add_token_account(&mint, 1_000);
+    let small_holder = fixture.add_token_account(&mint, 999);
+    let other_owner = fixture.add_token_account(&mint, 1_000);
+    let other_mint = fixture.add_token_account(&Pubkey::new_unique(), 1_000);
+    for i in [holder, small_holder, other_mint] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
+    }
+    let infos = fixture.infos();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    config.fee_discount_mint = COption::Some(mint);
+    config.fee_discount_threshold = 1_000;
+    config.fee_discount_bps = 5_000;
+
+    let discounted_fee =
+      |idx: Option<usize>| Processor::discounted_fee(100, idx.map(|i| &infos[i]), &user, &config);
+    assert_eq!(discounted_fee(None), Ok(100));
+    assert_eq!(discounted_fee(Some(holder)), Ok(50));
+    assert_eq!(discounted_fee(Some(small_holder)), Ok(100));
+    assert_eq!(
+      discounted_fee(Some(other_owner)),
+      Err(ProtocolError::InvalidOwner)
+    );
+    assert_eq!(
+      discounted_fee(Some(other_mint)),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+    config.add_fee_exempt_owner(&user).unwrap();
+    let exempt_fee =
+      |idx: Option<usize>| Processor::discounted_fee(100, idx.map(|i| &infos[i]), &user, &config);
+    assert_eq!(exempt_fee(None), Ok(0));
+    assert_eq!(exempt_fee(Some(other_owner)), Ok(0));
+  }
 
-    let (pool_source_token_acc, pool_destination_token_acc) =
-      swap_args.find_token_pair(&source_token_mint, &destination_token_mint)?;
+  #[test]
+  fn test_select_fallback_candidate() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let swap_program = token_swap_program_id();
+    let mut fixture = Fixture::new();
+    let stale = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.keys[stale + 6] = Pubkey::new_unique();
+    let fair = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let best = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    fixture.accounts[best + 3].data[64..72].copy_from_slice(&2_000_000u64.to_le_bytes());
+    // serum market, never quoted
+    fixture.add(Pubkey::default(), vec![]);
+    let infos = fixture.infos();
+    let mut protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+
+    let mut data = SwapWithFallbackInstruction {
+      amount_in: NonZeroU64::new(1_000).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+      minimum_amount_out: NonZeroU64::new(990).unwrap(),
+      candidates: vec![
+        (ExchangerType::SplTokenSwap, 7),
+        (ExchangerType::SplTokenSwap, 7),
+        (ExchangerType::SplTokenSwap, 7),
+        (ExchangerType::SerumDex, 1),
+      ],
+    };
+    let select = |data: &SwapWithFallbackInstruction, protocol_config: &ProtocolConfig| {
+      Processor::select_fallback_candidate(data, &infos, &mint_a, &mint_b, protocol_config)
+        .map(|(index, exchanger, accounts)| (index, exchanger, *accounts[0].key))
+    };
 
-    let swap_accounts = vec![
-      swap_args.program_id.clone(),
-      swap_args.swap_info.inner().clone(),
-      swap_args.authority.clone(),
-      source_account_authority.clone(),
-      source_token_account.inner().clone(),
-      destination_token_account.inner().clone(),
-      pool_source_token_acc.inner().clone(),
-      pool_destination_token_acc.inner().clone(),
-      swap_args.tick_dst.clone(),
-      spl_token_program.inner().clone(),
-    ];
+    // stale pool and serum market are skipped, the deepest pool wins
+    assert_eq!(
+      select(&data, &protocol_config),
+      Ok((2, ExchangerType::SplTokenSwap, *infos[best].key))
+    );
+    protocol_config.add_blocked_pool(infos[best].key).unwrap();
+    assert_eq!(
+      select(&data, &protocol_config),
+      Ok((1, ExchangerType::SplTokenSwap, *infos[fair].key))
+    );
+    // 1_000 in quotes 999 out of the remaining pool
+    data.minimum_amount_out = NonZeroU64::new(1_000).unwrap();
+    assert_eq!(
+      select(&data, &protocol_config),
+      Err(ProtocolError::NoValidFallbackCandidate)
+    );
+    data.candidates.pop();
+    assert_eq!(
+      select(&data, &protocol_config),
+      Err(ProtocolError::InvalidAccountsLength)
+    );
+  }
 
-    let instruction = crema::instruction::swap_instruction(
-      swap_args.program_id.key,
-      swap_args.swap_info.inner().key,
-      swap_args.authority.key,
-      source_account_authority.key,
-      source_token_account.inner().key,
-      destination_token_account.inner().key,
-      pool_source_token_acc.inner().key,
-      pool_destination_token_acc.inner().key,
-      swap_args.tick_dst.key,
-      spl_token_program.inner().key,
-      amount_in,
-      minimum_amount_out,
-    )?;
+  #[test]
+  fn test_select_backup_candidate() {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let swap_program = token_swap_program_id();
+    let mut fixture = Fixture::new();
+    let primary = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let backup = fixture.add_token_swap(&swap_program, &mint_a, &mint_b);
+    let infos = fixture.infos();
+    let mut protocol_config = ProtocolConfig::new(&Pubkey::new_unique());
+
+    let mut data = SwapWithBackupInstruction {
+      amount_in: NonZeroU64::new(1_000).unwrap(),
+      expect_amount_out: NonZeroU64::new(1_000).unwrap(),
+      minimum_amount_out: NonZeroU64::new(990).unwrap(),
+      primary: (ExchangerType::SplTokenSwap, 7),
+    };
+    let select = |data: &SwapWithBackupInstruction,
+                  infos: &[AccountInfo],
+                  protocol_config: &ProtocolConfig| {
+      Processor::select_backup_candidate(data, infos, &mint_a, &mint_b, protocol_config)
+        .map(|(index, exchanger, accounts)| (index, exchanger, *accounts[0].key))
+    };
 
-    msg!("invoke crema-finance swap");
+    assert_eq!(
+      select(&data, &infos, &protocol_config),
+      Ok((0, ExchangerType::SplTokenSwap, *infos[primary].key))
+    );
+    // primary accounts read as a raydium pool no longer parse
+    data.primary = (ExchangerType::RaydiumSwap, 7);
+    assert_eq!(
+      select(&data, &infos, &protocol_config),
+      Ok((1, ExchangerType::SplTokenSwap, *infos[backup].key))
+    );
+    data.primary = (ExchangerType::SplTokenSwap, 7);
+    protocol_config
+      .add_blocked_pool(infos[primary].key)
+      .unwrap();
+    assert_eq!(
+      select(&data, &infos, &protocol_config),
+      Ok((1, ExchangerType::SplTokenSwap, *infos[backup].key))
+    );
+    // 1_000 in quotes 999 out of the backup pool
+    data.minimum_amount_out = NonZeroU64::new(1_000).unwrap();
+    assert_eq!(
+      select(&data, &infos, &protocol_config),
+      Err(ProtocolError::NoValidFallbackCandidate)
+    );
+    // no backup pool
+    assert_eq!(
+      select(&data, &infos[..7], &protocol_config),
+      Err(ProtocolError::InvalidAccountsLength)
+    );
+  }
 
-    sol_log_compute_units();
-    invoke(&instruction, &swap_accounts)?;
-    sol_log_compute_units();
-    Ok(())
+  #[test]
+  fn test_find_saber_wrappers() {
+    let wrapper_program = crate::parser::base::SABER_WRAPPER_PROGRAM_IDS[0];
+    let (usdc, wrapped_usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let other_mint = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let underlying_tokens = Pubkey::new_unique();
+    let wrapper = fixture.add(
+      wrapper_program,
+      crate::parser::saber_wrapper::test::wrapped_token_data(
+        1_000,
+        &usdc,
+        &underlying_tokens,
+        &wrapped_usdc,
+      ),
+    );
+    fixture.add_with_key(wrapped_usdc, spl_token::ID, vec![0u8; spl_token::MINT_LEN]);
+    let mut vault_data = vec![0u8; spl_token::ACCOUNT_LEN];
+    vault_data[0..32].copy_from_slice(usdc.as_ref());
+    vault_data[0x6c] = 1;
+    fixture.add_with_key(underlying_tokens, spl_token::ID, vault_data);
+    fixture.add_token_account(&wrapped_usdc, 0);
+    fixture.add_program(wrapper_program);
+    let infos = fixture.infos();
+    let accounts = &infos[wrapper..wrapper + SaberWrapperArgs::ACCOUNTS_LEN];
+
+    let (source, destination) =
+      Processor::find_saber_wrappers(accounts, &usdc, &other_mint).unwrap();
+    assert!(source.is_some() && destination.is_none());
+    let (source, destination) =
+      Processor::find_saber_wrappers(accounts, &other_mint, &usdc).unwrap();
+    assert!(source.is_none() && destination.is_some());
+    assert_eq!(
+      Processor::find_saber_wrappers(accounts, &other_mint, &other_mint).err(),
+      Some(ProtocolError::MintRoutingMismatch)
+    );
+    assert_eq!(
+      Processor::find_saber_wrappers(&accounts[..4], &usdc, &other_mint).err(),
+      Some(ProtocolError::InvalidAccountsLength)
+    );
+    let (source, destination) = Processor::find_saber_wrappers(&[], &usdc, &other_mint).unwrap();
+    assert!(source.is_none() && destination.is_none());
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_aldrin_exchange<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    sol_log_compute_units();
+  #[test]
+  fn test_refund_residual() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let intermediate = fixture.add_token_account(&mint_a, 30);
+    let refund = fixture.add_token_account(&mint_a, 0);
+    let wrong_mint = fixture.add_token_account(&mint_b, 0);
+    let authority = fixture.add(Pubkey::default(), vec![]);
+    let token_program = fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let infos = fixture.infos();
+    let source = TokenAccount::new(&infos[intermediate]).unwrap();
+    let spl_token_program = SplTokenProgram::new(&infos[token_program]).unwrap();
+
+    let refund_residual = |amount_in, from_amount_changed, refund_idx: usize| {
+      Processor::refund_residual(
+        amount_in,
+        from_amount_changed,
+        &source,
+        &infos[refund_idx],
+        &infos[authority],
+        &spl_token_program,
+      )
+    };
+    assert_eq!(
+      refund_residual(100, 90, wrong_mint),
+      Err(ProtocolError::InvalidTokenAccount.into())
+    );
+    assert_eq!(
+      refund_residual(100, 90, intermediate),
+      Err(ProtocolError::InvalidTokenAccount.into())
+    );
+    // fully executed, nothing to refund
+    assert_eq!(refund_residual(100, 100, refund), Ok(0));
+    // 10 of 100 left by the exchanger, the rest of the balance is not touched
+    assert_eq!(refund_residual(100, 90, refund), Ok(10));
+    assert_eq!(source.balance(), Ok(20));
+    // clamped: never more than the balance
+    assert_eq!(refund_residual(100, 0, refund), Ok(20));
+    assert_eq!(source.balance(), Ok(0));
+    assert_eq!(TokenAccount::new(&infos[refund]).unwrap().balance(), Ok(30));
+  }
 
-    let swap_args = AldrinPoolArgs::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_check_min_notional() {
+    let (mint, other_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    let check = |amount_in, balance, mint: &Pubkey, min, config: &ProtocolConfig| {
+      Processor::check_min_notional(amount_in, balance, mint, min, config)
+    };
+    assert_eq!(check(1, 1, &mint, 0, &config), Ok(()));
+    assert_eq!(check(100, 1_000, &mint, 100, &config), Ok(()));
+    assert_eq!(
+      check(99, 1_000, &mint, 100, &config),
+      Err(ProtocolError::InvalidInput)
+    );
+    // clamped to the source balance
+    assert_eq!(
+      check(1_000, 99, &mint, 100, &config),
+      Err(ProtocolError::InvalidInput)
+    );
 
-    msg!(
-      "swap using aldrin-exchanger, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+    // the config overrides the instruction, both ways
+    config.set_min_notional(&mint, 500).unwrap();
+    assert_eq!(
+      check(499, 1_000, &mint, 100, &config),
+      Err(ProtocolError::InvalidInput)
     );
+    assert_eq!(check(500, 1_000, &mint, 100, &config), Ok(()));
+    assert_eq!(check(500, 1_000, &mint, 1_000, &config), Ok(()));
+    assert!(check(99, 1_000, &other_mint, 100, &config).is_err());
+  }
 
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
-    let pool_coin_mint = swap_args.pool_coin_vault.mint()?;
-    let pool_pc_mint = swap_args.pool_pc_vault.mint()?;
+  #[test]
+  fn test_serum_filled_out() {
+    assert_eq!(Processor::serum_filled_out(0, 1_000, 1_000), Ok(1_000));
+    // free before the order is settled too but isn't part of the output
+    assert_eq!(Processor::serum_filled_out(300, 1_300, 1_300), Ok(1_000));
+    assert_eq!(
+      Processor::serum_filled_out(0, 1_000, 999),
+      Err(ProtocolError::DexSwapError)
+    );
+    assert_eq!(
+      Processor::serum_filled_out(1_000, 0, 1_000),
+      Err(ProtocolError::DexSwapError)
+    );
+  }
 
-    let side = swap_args.find_side(&source_token_mint)?;
+  #[test]
+  fn test_check_serum_settle() {
+    // 1_000_000 pc filled at the 22 bps taker fee
+    assert_eq!(
+      Processor::check_serum_settle(DexSide::Ask, 997_800, 2_200, 0),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_serum_settle(DexSide::Bid, 1_002_200, 2_200, 22),
+      Ok(())
+    );
+    // market credited a tenth of the fill and kept the rest as fees
+    assert_eq!(
+      Processor::check_serum_settle(DexSide::Ask, 100_000, 900_000, 22),
+      Err(ProtocolError::DexSwapError)
+    );
+    assert_eq!(
+      Processor::check_serum_settle(DexSide::Bid, 1_002_200, 2_200, 10),
+      Err(ProtocolError::DexSwapError)
+    );
+  }
 
-    let (user_coin_token_acc, user_pc_token_acc) =
-      if source_token_mint == pool_coin_mint && destination_token_mint == pool_pc_mint {
-        (source_token_account, destination_token_account)
-      } else if source_token_mint == pool_pc_mint && destination_token_mint == pool_coin_mint {
-        (destination_token_account, source_token_account)
-      } else {
-        return Err(ProtocolError::InvalidTokenMint.into());
-      };
+  #[test]
+  fn test_check_destination_owner() {
+    let (mint, signer) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut fixture = Fixture::new();
+    let own = fixture.add_token_account(&mint, 0);
+    fixture.accounts[own].data[32..64].copy_from_slice(signer.as_ref());
+    let third_party = fixture.add_token_account(&mint, 0);
+    // delegated to the signer, still someone else's account
+    fixture.accounts[third_party].data[72] = 1;
+    fixture.accounts[third_party].data[76..108].copy_from_slice(signer.as_ref());
+    let infos = fixture.infos();
+    let check = |index: usize, required: bool| {
+      Processor::check_destination_owner(
+        &TokenAccount::new(&infos[index]).unwrap(),
+        &signer,
+        required,
+      )
+    };
 
-    let swap_accounts = vec![
-      swap_args.program_id.clone(),
-      swap_args.pool_info.inner().clone(),
-      swap_args.authority.clone(),
-      swap_args.pool_mint.inner().clone(),
-      swap_args.pool_coin_vault.inner().clone(),
-      swap_args.pool_pc_vault.inner().clone(),
-      swap_args.fee_account.clone(),
-      swap_args.curve_key.clone(),
-      user_coin_token_acc.inner().clone(),
-      user_pc_token_acc.inner().clone(),
-      source_account_authority.clone(),
-      spl_token_program.inner().clone(),
-    ];
+    assert_eq!(check(own, false), Ok(()));
+    assert_eq!(check(third_party, false), Ok(()));
+    assert_eq!(check(own, true), Ok(()));
+    assert_eq!(check(third_party, true), Err(ProtocolError::InvalidOwner));
+  }
 
-    let instruction = aldrin::instruction::swap_instruction(
-      swap_args.program_id.key,
-      swap_args.pool_info.inner().key,
-      swap_args.authority.key,
-      swap_args.pool_mint.inner().key,
-      swap_args.pool_coin_vault.inner().key,
-      swap_args.pool_pc_vault.inner().key,
-      swap_args.fee_account.key,
-      swap_args.curve_key.key,
-      user_coin_token_acc.inner().key,
-      user_pc_token_acc.inner().key,
-      source_account_authority.key,
-      spl_token_program.inner().key,
-      amount_in,
-      minimum_amount_out,
-      side,
-    )?;
+  #[test]
+  fn test_check_expected_side() {
+    assert_eq!(
+      Processor::check_expected_side(None, DexSide::Ask as u8),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_expected_side(Some(1), DexSide::Ask as u8),
+      Ok(())
+    );
+    // router thinks it is buying the coin while the source mint is the coin
+    assert_eq!(
+      Processor::check_expected_side(Some(0), DexSide::Ask as u8),
+      Err(ProtocolError::MintRoutingMismatch)
+    );
+    assert_eq!(
+      Processor::check_expected_side(Some(1), aldrin::instruction::Side::Bid as u8),
+      Err(ProtocolError::MintRoutingMismatch)
+    );
+  }
 
-    msg!("invoke aldrin-exchanger swap");
+  #[test]
+  fn test_output_fee_max_surplus() {
+    // expect set far too low, only 5% over the expectation is charged
+    assert_eq!(Processor::output_fee(2_000_000, 1_000_000, 500), 12_500);
+    assert_eq!(Processor::output_fee(2_000_000, 1_000_000, 0), 250_000);
+    // surplus within the bound is untouched
+    assert_eq!(Processor::output_fee(1_040_000, 1_000_000, 500), 10_000);
+    assert_eq!(Processor::output_fee(900_000, 1_000_000, 500), 0);
+    // the bound itself may exceed u64
+    assert_eq!(
+      Processor::output_fee(u64::MAX, u64::MAX - 100, u16::MAX),
+      25
+    );
+  }
 
-    sol_log_compute_units();
-    invoke(&instruction, &swap_accounts)?;
-    sol_log_compute_units();
-    Ok(())
+  #[test]
+  fn test_output_fee_net_of_minimum() {
+    // gross output anywhere from the minimum up, the output fee never takes the
+    // owner below `minimum_amount_out`
+    let (expect, minimum) = (1_000_000u64, 990_000u64);
+    for amount_out in [
+      minimum,
+      expect - 1,
+      expect,
+      expect + 3,
+      2 * expect,
+      u64::MAX / 25,
+    ] {
+      for max_surplus_bps in [0, 500] {
+        let fee = Processor::output_fee(amount_out, expect, max_surplus_bps);
+        assert!(amount_out - fee >= minimum);
+        assert!(amount_out - fee >= cmp::min(amount_out, expect));
+      }
+    }
+    // the slim legs share the surplus over the minimum itself
+    for amount_out in [minimum, expect, 2 * expect] {
+      let fee = Processor::slim_fee(amount_out, minimum).unwrap();
+      assert!(amount_out - fee >= minimum);
+    }
+    assert_eq!(
+      Processor::slim_fee(minimum - 1, minimum),
+      Err(ProtocolError::ExceededSlippage)
+    );
   }
 
-  /// Step swap in spl-token-swap
-  #[allow(clippy::too_many_arguments, unused_variables)]
-  fn process_step_cropper_finance<'a, 'b: 'a>(
-    program_id: &Pubkey,
-    amount_in: u64,
-    minimum_amount_out: u64,
-    source_token_account: &TokenAccount<'a, 'b>,
-    destination_token_account: &TokenAccount<'a, 'b>,
-    source_account_authority: &'a AccountInfo<'b>,
-    spl_token_program: &SplTokenProgram<'a, 'b>,
-    accounts: &'a [AccountInfo<'b>],
-  ) -> ProgramResult {
-    sol_log_compute_units();
+  #[test]
+  fn test_input_fee_vs_output_fee() {
+    // output beats the expectation: the output fee takes a quarter of the surplus
+    assert_eq!(Processor::output_fee(2_000_000, 1_900_000, 0), 25_000);
+    assert_eq!(Processor::input_fee(1_000_000), 1_000);
+    // no surplus, no output fee, the input fee does not depend on the output
+    assert_eq!(Processor::output_fee(1_900_000, 1_900_000, 0), 0);
+    assert_eq!(Processor::output_fee(1_800_000, 1_900_000, 0), 0);
+    assert_eq!(Processor::input_fee(1_000_000), 1_000);
+    // rounds down, dust amounts are free
+    assert_eq!(Processor::input_fee(999), 0);
+    assert_eq!(Processor::input_fee(u64::MAX), u64::MAX / 1_000);
+  }
 
-    let swap_args = CropperArgs::with_parsed_args(accounts)?;
-    let amount_in = Self::get_amount_in(amount_in, source_token_account.balance()?);
+  #[test]
+  fn test_check_price_impact() {
+    // 1_000 in against a 1_000 / 2_000 pool: constant product gives 1_000 out,
+    // half of the 2_000 spot output
+    let shallow = Some((1_000, 2_000));
+    assert_eq!(
+      Processor::check_price_impact(shallow, 1_000, 1_000, 100),
+      Err(ProtocolError::ExceededSlippage)
+    );
+    assert_eq!(
+      Processor::check_price_impact(shallow, 1_000, 1_000, 5_000),
+      Ok(())
+    );
+    // opt-in
+    assert_eq!(
+      Processor::check_price_impact(shallow, 1_000, 1_000, 0),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_price_impact(None, 1_000, 1_000, 100),
+      Ok(())
+    );
+    // the same trade into a deep pool
+    assert_eq!(
+      Processor::check_price_impact(Some((1_000_000_000, 2_000_000_000)), 1_000, 1_999, 100),
+      Ok(())
+    );
+  }
 
-    msg!(
-      "swap using cropper-finance, amount_in: {}, minimum_amount_out: {}",
-      amount_in,
-      minimum_amount_out,
+  #[test]
+  fn test_check_reference_price() {
+    // 1_000 / 2_000 pool, spot 2 destination per source
+    let pool = Some((1_000, 2_000));
+    let reference = |num, den, max_drift_bps| {
+      Some(ReferencePrice {
+        num,
+        den,
+        max_drift_bps,
+      })
+    };
+    assert_eq!(
+      Processor::check_reference_price(pool, reference(2, 1, 0)),
+      Ok(())
     );
-    let pool_token_a_mint = swap_args.swap_info.token_a_mint()?;
-    let pool_token_b_mint = swap_args.swap_info.token_b_mint()?;
-    let source_token_mint = source_token_account.mint()?;
-    let destination_token_mint = destination_token_account.mint()?;
+    // quoted at 2.02, the pool is 99 bps below it
+    assert_eq!(
+      Processor::check_reference_price(pool, reference(202, 100, 100)),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_reference_price(pool, reference(202, 100, 98)),
+      Err(ProtocolError::PriceDeviationTooHigh)
+    );
+    // quoted at 1.98, the pool moved up by 101 bps
+    assert_eq!(
+      Processor::check_reference_price(pool, reference(198, 100, 101)),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_reference_price(pool, reference(198, 100, 100)),
+      Err(ProtocolError::PriceDeviationTooHigh)
+    );
+    // opt-in
+    assert_eq!(Processor::check_reference_price(pool, None), Ok(()));
+    assert_eq!(
+      Processor::check_reference_price(None, reference(1, 1, 0)),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_reference_price(Some((0, 2_000)), reference(2, 1, u16::MAX)),
+      Err(ProtocolError::PriceDeviationTooHigh)
+    );
+    assert_eq!(
+      Processor::check_reference_price(Some((u64::MAX, 1)), reference(u64::MAX, 1, u16::MAX)),
+      Err(ProtocolError::PriceDeviationTooHigh)
+    );
+  }
 
-    if swap_args.fee_account.mint()? != source_token_mint {
-      msg!(
-        "cropper-finance.fee_account.mint is {}, expect {}",
-        swap_args.fee_account.pubkey(),
-        destination_token_mint
-      );
+  #[test]
+  fn test_verify_authority() {
+    // crema USDT-USDC pool
+    let pool = Pubkey::from_str("8J3avAjuRfL2CYFKKDwhhceiRoajhrHv9kN5nUiEnuBG").unwrap();
+    let program = Pubkey::from_str("6MLxLqiXaaSUpkgMnWDTuejNZEz3kE7k2woyHGVFw319").unwrap();
+    let authority = Pubkey::from_str("HsYb453638e4ZwykZj4PHNwFiXHauV9UuNL3mCmFkZzh").unwrap();
+    let mut fixture = Fixture::new();
+    fixture.add_with_key(pool, program, vec![]);
+    fixture.add_with_key(authority, Pubkey::default(), vec![]);
+    fixture.add_with_key(program, Pubkey::default(), vec![]);
+    let infos = fixture.infos();
+
+    let verify = |nonce, accounts: &[AccountInfo]| {
+      Processor::verify_authority(&VerifyAuthorityInstruction { nonce }, accounts)
+    };
+    assert_eq!(verify(254, &infos), Ok(true));
+    assert_eq!(verify(253, &infos), Ok(false));
+    // 255 is on the curve for this pool
+    assert_eq!(verify(255, &infos), Ok(false));
+    assert_eq!(
+      verify(254, &[infos[1].clone(), infos[0].clone(), infos[2].clone()]),
+      Ok(false)
+    );
+    assert_eq!(
+      verify(254, &infos[..2]),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+  }
+
+  #[test]
+  fn test_estimate_compute_units_covers_exchangers() {
+    let exchangers = (0..=u8::MAX)
+      .filter_map(ExchangerType::from)
+      .collect::<Vec<_>>();
+    assert_eq!(exchangers.len(), 9);
+    for exchanger in exchangers {
+      for reverse in [false, true] {
+        let units = Processor::estimate_compute_units(&EstimateComputeUnitsInstruction {
+          exchanger,
+          reverse,
+        });
+        assert!(units > 0 && units < 200_000, "{:?}", exchanger);
+      }
     }
+  }
 
-    let (pool_source_token_account, pool_destination_token_account) =
-      if source_token_mint == pool_token_a_mint && destination_token_mint == pool_token_b_mint {
-        (swap_args.token_a_account, swap_args.token_b_account)
-      } else if source_token_mint == pool_token_b_mint
-        && destination_token_mint == pool_token_a_mint
-      {
-        (swap_args.token_b_account, swap_args.token_a_account)
-      } else {
-        return Err(ProtocolError::InvalidTokenAccount.into());
-      };
+  #[test]
+  fn test_set_compute_hint_is_noop() {
+    let program_id = Pubkey::new_unique();
+    let mut fixture = Fixture::new();
+    let account = fixture.add_token_account(&Pubkey::new_unique(), 100);
+    let data = fixture.accounts[account].data.clone();
+    let infos = fixture.infos();
+    let mut input = vec![42u8];
+    input.extend_from_slice(&10_000u64.to_le_bytes());
+
+    assert_eq!(Processor::process(&program_id, &[], &input), Ok(()));
+    assert_eq!(Processor::process(&program_id, &infos, &input), Ok(()));
+    assert_eq!(infos[account].data.borrow().to_vec(), data);
+    assert_eq!(infos[account].lamports(), 1_000_000);
+  }
 
-    let swap_accounts = vec![
-      swap_args.program_id.clone(),
-      swap_args.swap_info.inner().clone(),
-      swap_args.authority.clone(),
-      source_account_authority.clone(),
-      swap_args.program_state.inner().clone(),
-      source_token_account.inner().clone(),
-      pool_source_token_account.inner().clone(),
-      pool_destination_token_account.inner().clone(),
-      destination_token_account.inner().clone(),
-      swap_args.pool_mint.inner().clone(),
-      swap_args.fee_account.inner().clone(),
-      spl_token_program.inner().clone(),
-    ];
+  #[test]
+  fn test_slim_fee_boundary() {
+    // output exactly at the minimum leaves no surplus to charge
+    assert_eq!(Processor::slim_fee(1_000_000, 1_000_000), Ok(0));
+    assert_eq!(Processor::slim_fee(u64::MAX, u64::MAX), Ok(0));
+    // one unit below the minimum is rejected instead of charging nothing
+    assert_eq!(
+      Processor::slim_fee(999_999, 1_000_000),
+      Err(ProtocolError::ExceededSlippage)
+    );
+    assert_eq!(
+      Processor::slim_fee(0, 1),
+      Err(ProtocolError::ExceededSlippage)
+    );
+  }
 
-    let instruction = cropper::instruction::swap_instruction(
-      swap_args.program_id.key,
-      spl_token_program.inner().key,
-      swap_args.swap_info.inner().key,
-      swap_args.authority.key,
-      source_account_authority.key,
-      swap_args.program_state.inner().key,
-      source_token_account.inner().key,
-      pool_source_token_account.inner().key,
-      pool_destination_token_account.inner().key,
-      destination_token_account.inner().key,
-      swap_args.pool_mint.inner().key,
-      swap_args.fee_account.inner().key,
-      amount_in,
-      minimum_amount_out,
-    )?;
+  #[test]
+  fn test_slim_fee_capped() {
+    // quarter of the surplus
+    assert_eq!(Processor::slim_fee(1_000_004, 1_000_000), Ok(1));
+    assert_eq!(Processor::slim_fee(1_000_003, 1_000_000), Ok(0));
+    // 1bp of the output
+    assert_eq!(Processor::slim_fee(2_000_000, 1_000_000), Ok(200));
+    // 9 decimals, large amounts
+    let out = 18_000_000_000_000_000_000u64;
+    assert_eq!(Processor::slim_fee(out, 0), Ok(out / 10_000));
+    assert_eq!(Processor::slim_fee(out, out - 400), Ok(100));
+  }
 
-    msg!("invoke cropper-finance swap");
+  #[test]
+  fn test_fees_over_u64_range() {
+    let mut amounts = vec![0, 1, 3, 4, 9_999, 10_000, 10_001];
+    for shift in (0..64).step_by(3) {
+      let v = 1u64 << shift;
+      amounts.extend_from_slice(&[v - 1, v, v + 1, u64::MAX / v, u64::MAX - v]);
+    }
+    amounts.push(u64::MAX);
+    for &out in &amounts {
+      for &minimum in &amounts {
+        match Processor::slim_fee(out, minimum) {
+          Ok(fee) => {
+            assert!(fee <= (out - minimum) / 4);
+            assert!(fee <= out / 10_000);
+            assert!(out - fee >= minimum);
+          }
+          Err(e) => {
+            assert_eq!(e, ProtocolError::ExceededSlippage);
+            assert!(out < minimum);
+          }
+        }
+        let fee = Processor::output_fee(out, minimum, 0);
+        assert_eq!(fee, out.saturating_sub(minimum) / 4);
+        assert!(out - fee >= cmp::min(out, minimum));
+      }
+    }
+    // the surplus times the numerator overflows a u64
+    assert_eq!(Processor::output_fee(u64::MAX, 1, 0), (u64::MAX - 1) / 4);
+  }
 
-    sol_log_compute_units();
-    invoke(&instruction, &swap_accounts)?;
-    sol_log_compute_units();
-    Ok(())
+  #[test]
+  fn test_self_test() {
+    let program_id = Pubkey::new_unique();
+    assert_eq!(Processor::process(&program_id, &[], &[67]), Ok(()));
+
+    // an account missing from the invoke, or one the instruction has no meta for
+    let mut fixture = Fixture::new();
+    let token_program = fixture.add_program(spl_token::ID);
+    let pool = (0..10)
+      .map(|_| fixture.add(Pubkey::default(), vec![]))
+      .collect::<Vec<_>>();
+    let infos = fixture.infos();
+    let (instruction, accounts) = Processor::stable_swap_cpi(
+      &infos[pool[0]],
+      &infos[token_program],
+      &infos[pool[1]],
+      &infos[pool[2]],
+      &infos[pool[3]],
+      &infos[pool[4]],
+      &infos[pool[5]],
+      &infos[pool[6]],
+      &infos[pool[7]],
+      &infos[pool[8]],
+      1,
+      1,
+    )
+    .unwrap();
+    assert_eq!(
+      Processor::check_cpi_accounts("stable-swap", &instruction, &accounts),
+      Ok(())
+    );
+    assert_eq!(
+      Processor::check_cpi_accounts("stable-swap", &instruction, &accounts[1..]),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
+    let mut extra = accounts.clone();
+    extra.push(infos[pool[9]].clone());
+    assert_eq!(
+      Processor::check_cpi_accounts("stable-swap", &instruction, &extra),
+      Err(ProtocolError::InvalidAccountsLength.into())
+    );
   }
 
-  fn get_amount_in(amount_in: u64, source_token_balance: u64) -> u64 {
-    if source_token_balance < amount_in {
-      source_token_balance
-    } else {
-      amount_in
+  #[test]
+  fn test_single_step_swap_snapshots_user_accounts() {
+    program_stubs::set_syscall_stubs(Box::new(SwapStubs));
+    let program_id = Pubkey::new_unique();
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let user = Pubkey::new_unique();
+    let mut config_data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(ProtocolConfig::new(&user), &mut config_data).unwrap();
+    let mut fixture = Fixture::new();
+    let source = fixture.add_token_account(&mint_a, 1_000);
+    let destination = fixture.add_token_account(&mint_b, 300);
+    let owner = fixture.add_with_key(user, Pubkey::default(), vec![]);
+    fixture.add_with_key(spl_token::ID, Pubkey::default(), vec![]);
+    let fee_account = fixture.add_token_account(&mint_b, 0);
+    fixture.add_token_swap(&token_swap_program_id(), &mint_a, &mint_b);
+    fixture.add_config(&program_id, config_data);
+    for i in [source, destination] {
+      fixture.accounts[i].data[32..64].copy_from_slice(user.as_ref());
     }
-  }
+    fixture.accounts[fee_account].data[32..64]
+      .copy_from_slice(Pubkey::from_str(OWNER_KEY).unwrap().as_ref());
+    let mut infos = fixture.infos();
+    infos[owner].is_signer = true;
+    let balance = |i: usize| TokenAccount::new(&infos[i]).unwrap().balance().unwrap();
+    let swap = |min_source_remaining: u64| {
+      Processor::process_single_step_swap(
+        &program_id,
+        &SwapInstruction {
+          amount_in: NonZeroU64::new(400).unwrap(),
+          expect_amount_out: NonZeroU64::new(700).unwrap(),
+          minimum_amount_out: NonZeroU64::new(700).unwrap(),
+          flags: 0,
+          min_notional_in: 0,
+          max_price_impact_bps: 0,
+          expected_side: None,
+          client_route_id: None,
+          reference_price: None,
+          extra_flags: 0,
+          expected_pool: None,
+          min_source_remaining,
+        },
+        &infos,
+        ExchangerType::SplTokenSwap,
+      )
+    };
 
-  // /// check token account authority
-  // pub fn check_token_account_authority(
-  //   token_account: &spl_token::state::Account,
-  //   authority_info: &Pubkey,
-  // ) -> Result<(), ProtocolError> {
-  //   if !token_account
-  //     .delegate
-  //     .map(|d| d == *authority_info)
-  //     .unwrap_or(false)
-  //     || token_account.owner == *authority_info
-  //   {
-  //     return Err(ProtocolError::InvalidDelegate);
-  //   }
-  //   Ok(())
-  // }
+    // the output is the destination delta from its snapshot, not its balance
+    assert_eq!(
+      (balance(source), balance(destination), balance(fee_account)),
+      (1_000, 300, 0)
+    );
+    swap(0).unwrap();
+    // 400 a -> 800 b, a quarter of the 100 surplus is the fee
+    assert_eq!(
+      (balance(source), balance(destination), balance(fee_account)),
+      (600, 1_075, 25)
+    );
 
-  /// Issue a spl_token `Transfer` instruction.
-  pub fn token_transfer_signed<'a>(
-    base: &Pubkey,
-    token_program: &AccountInfo<'a>,
-    source: &AccountInfo<'a>,
-    destination: &AccountInfo<'a>,
-    authority: &AccountInfo<'a>,
-    nonce: u8,
-    amount: u64,
-  ) -> Result<(), ProgramError> {
-    let base_bytes = base.to_bytes();
-    let authority_signature_seeds = [&base_bytes[..32], &[nonce]];
-    let signers = &[&authority_signature_seeds[..]];
-    let ix = spl_token::instruction::transfer(
-      token_program.key,
-      source.key,
-      destination.key,
-      authority.key,
-      &[],
-      amount,
-    )?;
-    // invoke(&ix, &[source, destination, authority, token_program])
-    invoke_signed(
-      &ix,
-      &[
-        source.clone(),
-        destination.clone(),
-        authority.clone(),
-        token_program.clone(),
-      ],
-      signers,
-    )
+    // the source snapshot after the swap is below the floor the client asked
+    assert_eq!(swap(300), Err(ProtocolError::InsufficientFunds.into()));
+    // the user accounts can't be read while another borrow holds them
+    let held = infos[destination].data.borrow_mut();
+    assert_eq!(swap(0), Err(ProtocolError::BorrowAccountDataError.into()));
+    drop(held);
   }
 
-  /// Issue a spl_token `Transfer` instruction.
-  pub fn token_transfer<'a>(
-    token_program: &AccountInfo<'a>,
-    source: &AccountInfo<'a>,
-    destination: &AccountInfo<'a>,
-    authority: &AccountInfo<'a>,
-    amount: u64,
-  ) -> Result<(), ProgramError> {
-    let ix = spl_token::instruction::transfer(
-      token_program.key,
-      source.key,
-      destination.key,
-      authority.key,
-      &[],
-      amount,
-    )?;
-    // invoke(&ix, &[source, destination, authority, token_program])
-    invoke(
-      &ix,
-      &[
-        source.clone(),
-        destination.clone(),
-        authority.clone(),
-        token_program.clone(),
-      ],
-    )
+  #[test]
+  fn test_no_panicking_unwrap() {
+    // a panic aborts the program without telling which check failed
+    for source in [
+      include_str!("processor.rs"),
+      include_str!("exchanger/serum_dex/order.rs"),
+    ] {
+      let program = source.split("#[cfg(test)]").next().unwrap();
+      assert!(!program.contains(".unwrap()"));
+      assert!(!program.contains(".expect("));
+    }
   }
 }
@@ -35,6 +35,29 @@ pub enum TokenInstruction {
     amount: u64,
   },
 
+  /// Approves a delegate.  A delegate is given the authority over tokens on
+  /// behalf of the source account's owner.
+  ///
+  /// Accounts expected by this instruction:
+  ///
+  ///   * Single owner
+  ///   0. `[writable]` The source account.
+  ///   1. `[]` The delegate.
+  ///   2. `[signer]` The source account owner.
+  Approve {
+    /// The amount of tokens the delegate is approved for.
+    amount: u64,
+  },
+
+  /// Revokes the delegate's authority.
+  ///
+  /// Accounts expected by this instruction:
+  ///
+  ///   * Single owner
+  ///   0. `[writable]` The source account.
+  ///   1. `[signer]` The source account owner.
+  Revoke,
+
   /// Close an account by transferring all its SOL to the destination account.
   /// Non-native accounts may only be closed if its token amount is zero.
   ///
@@ -51,6 +74,18 @@ pub enum TokenInstruction {
   ///   2. `[]` The account's multisignature owner.
   ///   3. ..3+M `[signer]` M signer accounts.
   CloseAccount,
+
+  /// Like InitializeAccount, but the owner pubkey is passed via instruction
+  /// data rather than the accounts list and the rent sysvar is not required.
+  ///
+  /// Accounts expected by this instruction:
+  ///
+  ///   0. `[writable]`  The account to initialize.
+  ///   1. `[]` The mint this account will be associated with.
+  InitializeAccount3 {
+    /// The new account's owner/multisignature.
+    owner: Pubkey,
+  },
 }
 
 impl TokenInstruction {
@@ -62,7 +97,16 @@ impl TokenInstruction {
         buf.push(3);
         buf.extend_from_slice(&amount.to_le_bytes());
       }
+      &Self::Approve { amount } => {
+        buf.push(4);
+        buf.extend_from_slice(&amount.to_le_bytes());
+      }
+      Self::Revoke => buf.push(5),
       Self::CloseAccount => buf.push(9),
+      Self::InitializeAccount3 { owner } => {
+        buf.push(18);
+        buf.extend_from_slice(owner.as_ref());
+      }
     };
     buf
   }
@@ -98,7 +142,51 @@ pub fn transfer(
   })
 }
 
-#[allow(dead_code)]
+/// Creates an `Approve` instruction.
+pub fn approve(
+  token_program_id: &Pubkey,
+  source_pubkey: &Pubkey,
+  delegate_pubkey: &Pubkey,
+  owner_pubkey: &Pubkey,
+  amount: u64,
+) -> Result<Instruction, ProgramError> {
+  check_program_account(token_program_id)?;
+  let data = TokenInstruction::Approve { amount }.pack();
+
+  let accounts = vec![
+    AccountMeta::new(*source_pubkey, false),
+    AccountMeta::new_readonly(*delegate_pubkey, false),
+    AccountMeta::new_readonly(*owner_pubkey, true),
+  ];
+
+  Ok(Instruction {
+    program_id: *token_program_id,
+    accounts,
+    data,
+  })
+}
+
+/// Creates a `Revoke` instruction.
+pub fn revoke(
+  token_program_id: &Pubkey,
+  source_pubkey: &Pubkey,
+  owner_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+  check_program_account(token_program_id)?;
+  let data = TokenInstruction::Revoke.pack();
+
+  let accounts = vec![
+    AccountMeta::new(*source_pubkey, false),
+    AccountMeta::new_readonly(*owner_pubkey, true),
+  ];
+
+  Ok(Instruction {
+    program_id: *token_program_id,
+    accounts,
+    data,
+  })
+}
+
 /// Creates a `CloseAccount` instruction.
 pub fn close_account(
   token_program_id: &Pubkey,
@@ -127,3 +215,28 @@ pub fn close_account(
     data,
   })
 }
+
+/// Creates an `InitializeAccount3` instruction.
+pub fn initialize_account3(
+  token_program_id: &Pubkey,
+  account_pubkey: &Pubkey,
+  mint_pubkey: &Pubkey,
+  owner_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+  check_program_account(token_program_id)?;
+  let data = TokenInstruction::InitializeAccount3 {
+    owner: *owner_pubkey,
+  }
+  .pack();
+
+  let accounts = vec![
+    AccountMeta::new(*account_pubkey, false),
+    AccountMeta::new_readonly(*mint_pubkey, false),
+  ];
+
+  Ok(Instruction {
+    program_id: *token_program_id,
+    accounts,
+    data,
+  })
+}
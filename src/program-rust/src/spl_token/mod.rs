@@ -6,6 +6,10 @@ use std::str::FromStr;
 
 solana_program::declare_id!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
 
+pub mod native_mint {
+  solana_program::declare_id!("So11111111111111111111111111111111111111112");
+}
+
 /// Checks that the supplied program ID is the correct one for SPL-token
 pub fn check_program_account(spl_token_program_id: &Pubkey) -> ProgramResult {
   if spl_token_program_id != &id() {
@@ -1,4 +1,5 @@
 //! State transition types
+use crate::{error::ProtocolError, instruction::ExchangerType};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
   program_error::ProgramError,
@@ -6,18 +7,31 @@ use solana_program::{
   program_pack::{IsInitialized, Pack, Sealed},
   pubkey::Pubkey,
 };
+use std::cmp;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Status {
   SwapInfo,
+  ProtocolConfig,
   Closed,
+  ExchangerStats,
+  PoolRegistry,
+  FeeAccrual,
+  TrustedPoolCreators,
+  RebateVault,
 }
 
 impl Status {
   pub fn from_u8(status: u8) -> Result<Self, ProgramError> {
     match status {
       1 => Ok(Status::SwapInfo),
+      2 => Ok(Status::ProtocolConfig),
       3 => Ok(Status::Closed),
+      4 => Ok(Status::ExchangerStats),
+      5 => Ok(Status::PoolRegistry),
+      6 => Ok(Status::FeeAccrual),
+      7 => Ok(Status::TrustedPoolCreators),
+      8 => Ok(Status::RebateVault),
       _ => Err(ProgramError::InvalidArgument),
     }
   }
@@ -25,7 +39,13 @@ impl Status {
   pub fn to_u8(&self) -> u8 {
     match self {
       Status::SwapInfo => 1,
+      Status::ProtocolConfig => 2,
       Status::Closed => 3,
+      Status::ExchangerStats => 4,
+      Status::PoolRegistry => 5,
+      Status::FeeAccrual => 6,
+      Status::TrustedPoolCreators => 7,
+      Status::RebateVault => 8,
     }
   }
 }
@@ -43,64 +63,1472 @@ pub struct SwapInfo {
   pub owner: Pubkey,
   /// token account
   pub token_account: COption<Pubkey>,
+  /// mint of `token_account` recorded by the In leg, always `None` in legacy
+  /// accounts
+  pub mid_mint: COption<Pubkey>,
+  /// slot of the last `InitializeSwapInfo`, `SetupSwapInfo` or
+  /// `ResetSwapInfo`, 0 in accounts created before it was recorded
+  pub created_slot: u64,
+}
+
+pub const SWAP_INFO_SEED: &[u8] = b"swap_info";
+
+/// Swap info account of `owner` created by `InitializeSwapInfo` with `nonce`,
+/// so that clients find it without keeping track of a keypair.
+pub fn derive_swap_info_address(owner: &Pubkey, nonce: u8, program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[SWAP_INFO_SEED, owner.as_ref(), &[nonce]], program_id)
+}
+
+/// Length of the SwapInfo accounts created before `mid_mint` was added.
+pub const LEGACY_SWAP_INFO_LEN: usize = 78;
+/// Length of the SwapInfo accounts created before `created_slot` was added.
+pub const MID_MINT_SWAP_INFO_LEN: usize = 114;
+
+fn is_legacy_swap_info_len(len: usize) -> bool {
+  len == LEGACY_SWAP_INFO_LEN || len == MID_MINT_SWAP_INFO_LEN
 }
 
 impl SwapInfo {
+  pub fn new(owner: &Pubkey, created_slot: u64) -> Self {
+    Self {
+      is_initialized: 1,
+      status: Status::SwapInfo.to_u8(),
+      token_latest_amount: 0,
+      owner: *owner,
+      token_account: COption::None,
+      mid_mint: COption::None,
+      created_slot,
+    }
+  }
+
+  /// Unpacks a SwapInfo account of any length, the fields a legacy account
+  /// lacks are zero.
+  pub fn load(src: &[u8]) -> Result<Self, ProgramError> {
+    if is_legacy_swap_info_len(src.len()) {
+      let mut data = [0u8; SwapInfo::LEN];
+      data[..src.len()].copy_from_slice(src);
+      return Self::unpack(&data);
+    }
+    Self::unpack(src)
+  }
+
+  /// Packs into a SwapInfo account of any length, the fields a legacy account
+  /// lacks are dropped.
+  pub fn store(self, dst: &mut [u8]) -> Result<(), ProgramError> {
+    if is_legacy_swap_info_len(dst.len()) {
+      let mut data = [0u8; SwapInfo::LEN];
+      Self::pack(self, &mut data)?;
+      let len = dst.len();
+      dst.copy_from_slice(&data[..len]);
+      return Ok(());
+    }
+    Self::pack(self, dst)
+  }
+
+  /// Returns a SwapInfo whose route completed (the Out leg unbound its token
+  /// account) to its initialized state, ready for `SetupSwapInfo`.
+  pub fn reset(&mut self) -> Result<(), ProtocolError> {
+    if Status::from_u8(self.status).ok() != Some(Status::SwapInfo) || self.token_account.is_some() {
+      return Err(ProtocolError::InvalidAccountFlags);
+    }
+    self.token_latest_amount = 0;
+    self.mid_mint = COption::None;
+    Ok(())
+  }
+
+  /// Only one In leg may run per setup, a second one would overwrite the
+  /// amount recorded by the first.
+  pub fn check_swap_in(&self) -> Result<(), ProtocolError> {
+    if self.token_latest_amount != 0 {
+      return Err(ProtocolError::InvalidAccountFlags);
+    }
+    Ok(())
+  }
+
+  /// The intermediate account may have been closed and recreated with another
+  /// mint since the In leg.
+  pub fn check_mid_mint(&self, mint: &Pubkey) -> Result<(), ProtocolError> {
+    match self.mid_mint {
+      COption::Some(mid_mint) if mid_mint != *mint => Err(ProtocolError::InvalidTokenAccount),
+      _ => Ok(()),
+    }
+  }
+
+  /// The In leg ran and the Out leg did not yet.
+  pub fn in_progress(&self) -> bool {
+    self.token_account.is_some() && self.token_latest_amount != 0
+  }
+
+  /// Anyone may close a SwapInfo created more than `ttl_slots` before `slot`
+  /// that is not in the middle of a route. A zero `ttl_slots` disables it, a
+  /// legacy account without `created_slot` is never stale.
+  pub fn check_stale(&self, slot: u64, ttl_slots: u64) -> Result<(), ProtocolError> {
+    if Status::from_u8(self.status).ok() != Some(Status::SwapInfo) {
+      return Err(ProtocolError::InvalidAccountFlags);
+    }
+    if ttl_slots == 0
+      || self.created_slot == 0
+      || self.in_progress()
+      || self.created_slot.saturating_add(ttl_slots) > slot
+    {
+      return Err(ProtocolError::SwapInfoNotStale);
+    }
+    Ok(())
+  }
+}
+
+impl Sealed for SwapInfo {}
+
+impl IsInitialized for SwapInfo {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized == 1
+  }
+}
+
+impl Pack for SwapInfo {
+  const LEN: usize = 122;
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    let output = array_mut_ref![dst, 0, 122];
+    #[rustfmt::skip]
+    let (
+      is_initialized,
+      status,
+      token_latest_amount,
+      owner,
+      token_account,
+      mid_mint,
+      created_slot,
+    ) = mut_array_refs![output, 1, 1, 8, 32, 36, 36, 8];
+    is_initialized.copy_from_slice(&[self.is_initialized]);
+    status.copy_from_slice(&[self.status]);
+    token_latest_amount.copy_from_slice(&self.token_latest_amount.to_le_bytes()[..]);
+    owner.copy_from_slice(self.owner.as_ref());
+    pack_coption_key(&self.token_account, token_account);
+    pack_coption_key(&self.mid_mint, mid_mint);
+    *created_slot = self.created_slot.to_le_bytes();
+  }
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let input = array_ref![src, 0, 122];
+    #[rustfmt::skip]
+    let (
+      &[is_initialized],
+      &[status],
+      &token_latest_amount,
+      owner,
+      token_account,
+      mid_mint,
+      &created_slot,
+    ) = array_refs![input, 1, 1, 8, 32, 36, 36, 8];
+    Ok(Self {
+      is_initialized,
+      status,
+      token_latest_amount: u64::from_le_bytes(token_latest_amount),
+      owner: Pubkey::new(owner),
+      token_account: unpack_coption_key(token_account)?,
+      mid_mint: unpack_coption_key(mid_mint)?,
+      created_slot: u64::from_le_bytes(created_slot),
+    })
+  }
+}
+
+pub const EXCHANGER_STATS_SEED: &[u8] = b"exchanger_stats";
+
+/// Swap counter of `exchanger` created by `InitializeExchangerStats`, one per
+/// exchanger type.
+pub fn derive_exchanger_stats_address(
+  exchanger: ExchangerType,
+  program_id: &Pubkey,
+) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[EXCHANGER_STATS_SEED, &[exchanger.to_u8()]], program_id)
+}
+
+/// Successful direct swaps through an exchanger, counted while the config
+/// `collect_exchanger_stats` is on.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ExchangerStats {
+  /// Initialized state.
+  pub is_initialized: u8,
+  /// account type, always `Status::ExchangerStats`
+  pub status: u8,
+  /// `ExchangerType::to_u8()` of the counted exchanger
+  pub exchanger: u8,
+  /// number of successful swaps
+  pub swap_count: u64,
+}
+
+impl ExchangerStats {
+  /// `GetExchangerStats` return data entry length
+  pub const VIEW_LEN: usize = 9;
+
+  pub fn new(exchanger: ExchangerType) -> Self {
+    Self {
+      is_initialized: 1,
+      status: Status::ExchangerStats.to_u8(),
+      exchanger: exchanger.to_u8(),
+      swap_count: 0,
+    }
+  }
+
+  /// Fails unless this is the counter of `exchanger`.
+  pub fn check_exchanger(&self, exchanger: ExchangerType) -> Result<(), ProtocolError> {
+    if Status::from_u8(self.status).ok() != Some(Status::ExchangerStats)
+      || self.exchanger != exchanger.to_u8()
+    {
+      return Err(ProtocolError::InvalidExchangerStatsAccount);
+    }
+    Ok(())
+  }
+
+  pub fn record_swap(&mut self) {
+    self.swap_count = self.swap_count.saturating_add(1);
+  }
+
+  // [exchanger], [swap_count]
+  pub fn pack_view(&self) -> [u8; ExchangerStats::VIEW_LEN] {
+    let mut dst = [0u8; ExchangerStats::VIEW_LEN];
+    let (exchanger, swap_count) = mut_array_refs![&mut dst, 1, 8];
+    exchanger[0] = self.exchanger;
+    *swap_count = self.swap_count.to_le_bytes();
+    dst
+  }
+}
+
+impl Sealed for ExchangerStats {}
+
+impl IsInitialized for ExchangerStats {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized == 1
+  }
+}
+
+impl Pack for ExchangerStats {
+  const LEN: usize = 11;
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    let output = array_mut_ref![dst, 0, 11];
+    let (is_initialized, status, exchanger, swap_count) = mut_array_refs![output, 1, 1, 1, 8];
+    is_initialized.copy_from_slice(&[self.is_initialized]);
+    status.copy_from_slice(&[self.status]);
+    exchanger.copy_from_slice(&[self.exchanger]);
+    *swap_count = self.swap_count.to_le_bytes();
+  }
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let input = array_ref![src, 0, 11];
+    let (&[is_initialized], &[status], &[exchanger], &swap_count) = array_refs![input, 1, 1, 1, 8];
+    Ok(Self {
+      is_initialized,
+      status,
+      exchanger,
+      swap_count: u64::from_le_bytes(swap_count),
+    })
+  }
+}
+
+pub const POOL_REGISTRY_SEED: &[u8] = b"pool_registry";
+pub const MAX_REGISTERED_POOLS: usize = 16;
+/// Largest exchanger account set, raydium takes 15
+pub const MAX_POOL_ACCOUNTS: usize = 16;
+const POOL_ENTRY_LEN: usize = 2 + 32 * MAX_POOL_ACCOUNTS;
+
+/// The pool registry created by the first `RegisterPool`.
+pub fn derive_pool_registry_address(program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[POOL_REGISTRY_SEED], program_id)
+}
+
+/// Exchanger accounts of a pool, swapped through by index with `SwapByIndex`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RegisteredPool {
+  pub exchanger: ExchangerType,
+  pub accounts: Vec<Pubkey>,
+}
+
+/// Pools registered by the config owner. The account is read and written in
+/// place, it is too large to unpack on the stack.
+///
+/// [is_initialized], [status], [pools_len],
+/// MAX_REGISTERED_POOLS * ([exchanger], [accounts_len], MAX_POOL_ACCOUNTS * [pubkey])
+pub struct PoolRegistry;
+
+impl PoolRegistry {
+  pub const LEN: usize = 3 + POOL_ENTRY_LEN * MAX_REGISTERED_POOLS;
+
+  pub fn init(dst: &mut [u8]) -> Result<(), ProtocolError> {
+    if dst.len() != PoolRegistry::LEN || dst[0] == 1 {
+      return Err(ProtocolError::InvalidPoolRegistryAccount);
+    }
+    dst[..3].copy_from_slice(&[1, Status::PoolRegistry.to_u8(), 0]);
+    Ok(())
+  }
+
+  fn pools_len(src: &[u8]) -> Result<usize, ProtocolError> {
+    if src.len() != PoolRegistry::LEN
+      || src[0] != 1
+      || Status::from_u8(src[1]).ok() != Some(Status::PoolRegistry)
+    {
+      return Err(ProtocolError::InvalidPoolRegistryAccount);
+    }
+    Ok(src[2] as usize)
+  }
+
+  pub fn pool(src: &[u8], index: u8) -> Result<RegisteredPool, ProtocolError> {
+    if index as usize >= Self::pools_len(src)? {
+      return Err(ProtocolError::InvalidInput);
+    }
+    let entry = array_ref![src, 3 + POOL_ENTRY_LEN * index as usize, POOL_ENTRY_LEN];
+    let (&[exchanger, accounts_len], accounts) = array_refs![entry, 2, 32 * MAX_POOL_ACCOUNTS];
+    Ok(RegisteredPool {
+      exchanger: ExchangerType::from(exchanger).ok_or(ProtocolError::InvalidPoolRegistryAccount)?,
+      accounts: accounts
+        .chunks_exact(32)
+        .take(accounts_len as usize)
+        .map(Pubkey::new)
+        .collect(),
+    })
+  }
+
+  /// Appends a pool, returning its index.
+  pub fn register(dst: &mut [u8], pool: &RegisteredPool) -> Result<u8, ProtocolError> {
+    let index = Self::pools_len(dst)?;
+    if index >= MAX_REGISTERED_POOLS {
+      return Err(ProtocolError::PoolRegistryFull);
+    }
+    if pool.accounts.is_empty() || pool.accounts.len() > MAX_POOL_ACCOUNTS {
+      return Err(ProtocolError::InvalidAccountsLength);
+    }
+    let entry = array_mut_ref![dst, 3 + POOL_ENTRY_LEN * index, POOL_ENTRY_LEN];
+    let (header, accounts) = mut_array_refs![entry, 2, 32 * MAX_POOL_ACCOUNTS];
+    *header = [pool.exchanger.to_u8(), pool.accounts.len() as u8];
+    for (dst, key) in accounts.chunks_exact_mut(32).zip(pool.accounts.iter()) {
+      dst.copy_from_slice(key.as_ref());
+    }
+    dst[2] = index as u8 + 1;
+    Ok(index as u8)
+  }
+}
+
+pub const FEE_ACCRUAL_SEED: &[u8] = b"fee_accrual";
+
+/// Fee accrual of `owner` in `mint`, created by `InitializeFeeAccrual`.
+pub fn derive_fee_accrual_address(
+  owner: &Pubkey,
+  mint: &Pubkey,
+  program_id: &Pubkey,
+) -> (Pubkey, u8) {
+  Pubkey::find_program_address(
+    &[FEE_ACCRUAL_SEED, owner.as_ref(), mint.as_ref()],
+    program_id,
+  )
+}
+
+/// Output fees owed by an owner in one mint, recorded by swaps with
+/// `SWAP_EXTRA_FLAG_ACCRUE_FEE` and paid by `SettleAccruedFee`.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FeeAccrual {
+  /// Initialized state.
+  pub is_initialized: u8,
+  /// account type, always `Status::FeeAccrual`
+  pub status: u8,
+  /// swap owner the fees are owed by
+  pub owner: Pubkey,
+  /// mint of the owed fees
+  pub mint: Pubkey,
+  /// fees owed since the last settle
+  pub amount: u64,
+}
+
+impl FeeAccrual {
+  pub fn new(owner: &Pubkey, mint: &Pubkey) -> Self {
+    Self {
+      is_initialized: 1,
+      status: Status::FeeAccrual.to_u8(),
+      owner: *owner,
+      mint: *mint,
+      amount: 0,
+    }
+  }
+
+  /// Fails unless this is the accrual of `owner` in `mint`.
+  pub fn check(&self, owner: &Pubkey, mint: &Pubkey) -> Result<(), ProtocolError> {
+    if Status::from_u8(self.status).ok() != Some(Status::FeeAccrual)
+      || self.owner != *owner
+      || self.mint != *mint
+    {
+      return Err(ProtocolError::InvalidFeeAccrualAccount);
+    }
+    Ok(())
+  }
+
+  /// Adds `fee` to the owed amount and returns it.
+  pub fn accrue(&mut self, fee: u64) -> Result<u64, ProtocolError> {
+    self.amount = self
+      .amount
+      .checked_add(fee)
+      .ok_or(ProtocolError::Overflow)?;
+    Ok(self.amount)
+  }
+}
+
+impl Sealed for FeeAccrual {}
+
+impl IsInitialized for FeeAccrual {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized == 1
+  }
+}
+
+impl Pack for FeeAccrual {
+  const LEN: usize = 74;
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    let output = array_mut_ref![dst, 0, 74];
+    let (is_initialized, status, owner, mint, amount) = mut_array_refs![output, 1, 1, 32, 32, 8];
+    is_initialized.copy_from_slice(&[self.is_initialized]);
+    status.copy_from_slice(&[self.status]);
+    owner.copy_from_slice(self.owner.as_ref());
+    mint.copy_from_slice(self.mint.as_ref());
+    *amount = self.amount.to_le_bytes();
+  }
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let input = array_ref![src, 0, 74];
+    let (&[is_initialized], &[status], owner, mint, &amount) = array_refs![input, 1, 1, 32, 32, 8];
+    Ok(Self {
+      is_initialized,
+      status,
+      owner: Pubkey::new_from_array(*owner),
+      mint: Pubkey::new_from_array(*mint),
+      amount: u64::from_le_bytes(amount),
+    })
+  }
+}
+
+pub const REBATE_VAULT_SEED: &[u8] = b"rebate_vault";
+pub const MAX_REBATE_OWNERS_PER_SLOT: usize = 16;
+
+/// Rebate vault of `mint`, created by the first `SetRebateVault`. It is the
+/// authority of its token account, out of reach of `RecoverFees`.
+pub fn derive_rebate_vault_address(mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[REBATE_VAULT_SEED, mint.as_ref()], program_id)
+}
+
+/// Token account the In leg rebates of one mint are paid from, and their cap.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct RebateVault {
+  /// Initialized state.
+  pub is_initialized: u8,
+  /// account type, always `Status::RebateVault`
+  pub status: u8,
+  /// mint of the rebates
+  pub mint: Pubkey,
+  /// token account owned by the vault the rebates are paid from
+  pub token_account: Pubkey,
+  /// largest rebate an owner gets in one slot, in raw units of `mint`
+  pub max_rebate: u64,
+  /// slot the `credited` entries are for
+  pub slot: u64,
+  /// number of used entries in `credited`
+  pub credited_len: u8,
+  /// owners rebated in `slot` and the rebates credited to them
+  pub credited: [(Pubkey, u64); MAX_REBATE_OWNERS_PER_SLOT],
+}
+
+impl RebateVault {
+  pub fn new(mint: &Pubkey, token_account: &Pubkey, max_rebate: u64) -> Self {
+    Self {
+      is_initialized: 1,
+      status: Status::RebateVault.to_u8(),
+      mint: *mint,
+      token_account: *token_account,
+      max_rebate,
+      slot: 0,
+      credited_len: 0,
+      credited: [(Pubkey::default(), 0); MAX_REBATE_OWNERS_PER_SLOT],
+    }
+  }
+
+  /// Part of `rebate` still under the `max_rebate` of `owner` in `slot`, the
+  /// closest a program gets to a transaction, recorded as credited. The cap
+  /// is kept here rather than on a SwapInfo so that the SwapInfos of one
+  /// owner share it. Once `MAX_REBATE_OWNERS_PER_SLOT` owners were rebated in
+  /// a slot, the others get nothing until the next one.
+  pub fn take_rebate(&mut self, owner: &Pubkey, rebate: u64, slot: u64) -> u64 {
+    if self.slot != slot {
+      self.slot = slot;
+      self.credited_len = 0;
+      self.credited = [(Pubkey::default(), 0); MAX_REBATE_OWNERS_PER_SLOT];
+    }
+    if rebate == 0 {
+      return 0;
+    }
+    let len = self.credited_len as usize;
+    let index = match self.credited[..len].iter().position(|(k, _)| k == owner) {
+      Some(index) => index,
+      None if len < MAX_REBATE_OWNERS_PER_SLOT => {
+        self.credited[len] = (*owner, 0);
+        self.credited_len += 1;
+        len
+      }
+      None => return 0,
+    };
+    let credited = &mut self.credited[index].1;
+    let rebate = cmp::min(rebate, self.max_rebate.saturating_sub(*credited));
+    *credited += rebate;
+    rebate
+  }
+
+  /// Fails unless this is the vault of `mint` paying from `token_account`.
+  pub fn check(&self, mint: &Pubkey, token_account: &Pubkey) -> Result<(), ProtocolError> {
+    if Status::from_u8(self.status).ok() != Some(Status::RebateVault)
+      || self.mint != *mint
+      || self.token_account != *token_account
+    {
+      return Err(ProtocolError::InvalidRebateAccount);
+    }
+    Ok(())
+  }
+}
+
+impl Sealed for RebateVault {}
+
+impl IsInitialized for RebateVault {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized == 1
+  }
+}
+
+impl Pack for RebateVault {
+  const LEN: usize = 83 + 40 * MAX_REBATE_OWNERS_PER_SLOT;
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    let output = array_mut_ref![dst, 0, RebateVault::LEN];
+    #[rustfmt::skip]
+    let (
+      is_initialized,
+      status,
+      mint,
+      token_account,
+      max_rebate,
+      slot,
+      credited_len,
+      credited,
+    ) = mut_array_refs![output, 1, 1, 32, 32, 8, 8, 1, 40 * MAX_REBATE_OWNERS_PER_SLOT];
+    is_initialized.copy_from_slice(&[self.is_initialized]);
+    status.copy_from_slice(&[self.status]);
+    mint.copy_from_slice(self.mint.as_ref());
+    token_account.copy_from_slice(self.token_account.as_ref());
+    *max_rebate = self.max_rebate.to_le_bytes();
+    *slot = self.slot.to_le_bytes();
+    credited_len.copy_from_slice(&[self.credited_len]);
+    for (dst, (owner, amount)) in credited.chunks_exact_mut(40).zip(self.credited.iter()) {
+      dst[..32].copy_from_slice(owner.as_ref());
+      dst[32..].copy_from_slice(&amount.to_le_bytes());
+    }
+  }
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let input = array_ref![src, 0, RebateVault::LEN];
+    #[rustfmt::skip]
+    let (
+      &[is_initialized],
+      &[status],
+      mint,
+      token_account,
+      &max_rebate,
+      &slot,
+      &[credited_len],
+      credited_data,
+    ) = array_refs![input, 1, 1, 32, 32, 8, 8, 1, 40 * MAX_REBATE_OWNERS_PER_SLOT];
+    if credited_len as usize > MAX_REBATE_OWNERS_PER_SLOT {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    let mut credited = [(Pubkey::default(), 0); MAX_REBATE_OWNERS_PER_SLOT];
+    for (entry, src) in credited.iter_mut().zip(credited_data.chunks_exact(40)) {
+      *entry = (
+        Pubkey::new(&src[..32]),
+        u64::from_le_bytes(*array_ref![src, 32, 8]),
+      );
+    }
+    Ok(Self {
+      is_initialized,
+      status,
+      mint: Pubkey::new_from_array(*mint),
+      token_account: Pubkey::new_from_array(*token_account),
+      max_rebate: u64::from_le_bytes(max_rebate),
+      slot: u64::from_le_bytes(slot),
+      credited_len,
+      credited,
+    })
+  }
+}
+
+pub const TRUSTED_POOL_CREATORS_SEED: &[u8] = b"trusted_pool_creators";
+pub const MAX_TRUSTED_POOL_CREATORS: usize = 16;
+
+/// The trusted pool creators list created by the first
+/// `AddTrustedPoolCreator`.
+pub fn derive_trusted_pool_creators_address(program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[TRUSTED_POOL_CREATORS_SEED], program_id)
+}
+
+/// Pool creators the swaps passing this list may go through while the config
+/// `check_pool_creators` is on. Only the swaps of callers that pass it are
+/// filtered.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TrustedPoolCreators {
+  /// Initialized state.
+  pub is_initialized: u8,
+  /// account type, always `Status::TrustedPoolCreators`
+  pub status: u8,
+  /// number of used entries in `creators`
+  pub creators_len: u8,
+  /// creators, or owners, of the trusted pools
+  pub creators: [Pubkey; MAX_TRUSTED_POOL_CREATORS],
+}
+
+impl TrustedPoolCreators {
+  pub fn new() -> Self {
+    Self {
+      is_initialized: 1,
+      status: Status::TrustedPoolCreators.to_u8(),
+      creators_len: 0,
+      creators: [Pubkey::default(); MAX_TRUSTED_POOL_CREATORS],
+    }
+  }
+
+  pub fn is_trusted(&self, creator: &Pubkey) -> bool {
+    self.creators[..self.creators_len as usize].contains(creator)
+  }
+
+  pub fn add(&mut self, creator: &Pubkey) -> Result<(), ProtocolError> {
+    if self.is_trusted(creator) {
+      return Ok(());
+    }
+    let len = self.creators_len as usize;
+    if len >= MAX_TRUSTED_POOL_CREATORS {
+      return Err(ProtocolError::TrustedPoolCreatorListFull);
+    }
+    self.creators[len] = *creator;
+    self.creators_len += 1;
+    Ok(())
+  }
+
+  pub fn remove(&mut self, creator: &Pubkey) -> Result<(), ProtocolError> {
+    let len = self.creators_len as usize;
+    let index = self.creators[..len]
+      .iter()
+      .position(|k| k == creator)
+      .ok_or(ProtocolError::InvalidInput)?;
+    self.creators[index] = self.creators[len - 1];
+    self.creators[len - 1] = Pubkey::default();
+    self.creators_len -= 1;
+    Ok(())
+  }
+}
+
+impl Default for TrustedPoolCreators {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Sealed for TrustedPoolCreators {}
+
+impl IsInitialized for TrustedPoolCreators {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized == 1
+  }
+}
+
+impl Pack for TrustedPoolCreators {
+  const LEN: usize = 3 + 32 * MAX_TRUSTED_POOL_CREATORS;
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    let output = array_mut_ref![dst, 0, TrustedPoolCreators::LEN];
+    let (is_initialized, status, creators_len, creators) =
+      mut_array_refs![output, 1, 1, 1, 32 * MAX_TRUSTED_POOL_CREATORS];
+    is_initialized.copy_from_slice(&[self.is_initialized]);
+    status.copy_from_slice(&[self.status]);
+    creators_len.copy_from_slice(&[self.creators_len]);
+    for (dst, creator) in creators.chunks_exact_mut(32).zip(self.creators.iter()) {
+      dst.copy_from_slice(creator.as_ref());
+    }
+  }
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let input = array_ref![src, 0, TrustedPoolCreators::LEN];
+    let (&[is_initialized], &[status], &[creators_len], creators_data) =
+      array_refs![input, 1, 1, 1, 32 * MAX_TRUSTED_POOL_CREATORS];
+    if Status::from_u8(status).ok() != Some(Status::TrustedPoolCreators)
+      || creators_len as usize > MAX_TRUSTED_POOL_CREATORS
+    {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    let mut creators = [Pubkey::default(); MAX_TRUSTED_POOL_CREATORS];
+    for (creator, src) in creators.iter_mut().zip(creators_data.chunks_exact(32)) {
+      *creator = Pubkey::new(src);
+    }
+    Ok(Self {
+      is_initialized,
+      status,
+      creators_len,
+      creators,
+    })
+  }
+}
+
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// The protocol config created by `InitializeConfig`, swaps take it as their
+/// last account.
+pub fn derive_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+  Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+pub const MAX_BLOCKED_POOLS: usize = 16;
+pub const MAX_FEE_EXEMPT_OWNERS: usize = 4;
+pub const MAX_MIN_NOTIONAL_MINTS: usize = 4;
+pub const MAX_MINT_FEE_CAPS: usize = 4;
+pub const MAX_EXPECT_ORACLE_MINTS: usize = 2;
+/// Transaction account lock limit of the runtime
+pub const DEFAULT_MAX_ROUTE_ACCOUNTS: u8 = 64;
+
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ProtocolConfig {
+  /// Initialized state.
+  pub is_initialized: u8,
+  /// account type, always `Status::ProtocolConfig`
+  pub status: u8,
+  /// Owner address, allowed to update the config
+  pub owner: Pubkey,
+  /// number of used entries in `blocked_pools`
+  pub blocked_pools_len: u8,
+  /// pools that can not be swapped through
+  pub blocked_pools: [Pubkey; MAX_BLOCKED_POOLS],
+  /// mint the fee may be swapped into when the destination is another token
+  pub fee_reference_mint: COption<Pubkey>,
+  /// number of used entries in `min_notional`
+  pub min_notional_len: u8,
+  /// per source mint minimum `amount_in`, overrides the instruction value
+  pub min_notional: [(Pubkey, u64); MAX_MIN_NOTIONAL_MINTS],
+  /// bit `ExchangerType::to_u8()` set when swaps through the exchanger are
+  /// disabled
+  pub disabled_exchangers: u16,
+  /// holders of this mint get `fee_discount_bps` off the fee
+  pub fee_discount_mint: COption<Pubkey>,
+  /// minimum `fee_discount_mint` balance to get the discount
+  pub fee_discount_threshold: u64,
+  /// discount on the fee, in bps of the fee
+  pub fee_discount_bps: u16,
+  /// largest surplus over `expect_amount_out` the output fee is taken on, in
+  /// bps of `expect_amount_out`. 0 leaves the surplus unbounded.
+  pub max_surplus_bps: u16,
+  /// slots after which anyone may close a SwapInfo that is not in the middle
+  /// of a route, 0 disables `CloseStaleSwapInfo`
+  pub swap_info_ttl_slots: u64,
+  /// the fee is rounded down to a multiple of this many raw units, the
+  /// remainder stays with the user. 0 and 1 leave the fee as is.
+  pub fee_granularity: u64,
+  /// DAO treasury token account getting `treasury_bps` of every fee
+  pub treasury_token_account: COption<Pubkey>,
+  /// share of the fee sent to `treasury_token_account`, in bps of the fee
+  pub treasury_bps: u16,
+  /// number of used entries in `mint_fee_caps`
+  pub mint_fee_caps_len: u8,
+  /// per mint largest fee in raw units, mints not listed have no absolute cap
+  pub mint_fee_caps: [(Pubkey, u64); MAX_MINT_FEE_CAPS],
+  /// spl-token-swap steps pass a protocol owned pool token account as the
+  /// host fee account, on pools with a host fee
+  pub collect_host_fee: bool,
+  /// share of an In leg output credited back to the user from the rebate
+  /// vault of its mint, in bps. 0 turns the rebate off
+  pub rebate_bps: u16,
+  /// most distinct accounts a multi-leg swap may take, 0 uses
+  /// `DEFAULT_MAX_ROUTE_ACCOUNTS`
+  pub max_route_accounts: u8,
+  /// number of used entries in `fee_exempt_owners`
+  pub fee_exempt_owners_len: u8,
+  /// protocol owned wallets whose swaps are never charged a fee
+  pub fee_exempt_owners: [Pubkey; MAX_FEE_EXEMPT_OWNERS],
+  /// signer attesting `expect_amount_out` of the swaps over the
+  /// `expect_oracle_mints` threshold of their DESTINATION mint
+  pub expect_oracle: COption<Pubkey>,
+  /// largest `expect_amount_out` of a swap with
+  /// `SWAP_EXTRA_FLAG_CHECK_EXPECT_OUT_RATIO`, as a multiple of `amount_in`
+  /// in whole tokens. 0 leaves it unbounded
+  pub max_expect_out_ratio: u16,
+  /// direct swaps increment the `ExchangerStats` counter of their exchanger
+  pub collect_exchanger_stats: bool,
+  /// swaps passing the `TrustedPoolCreators` list only go through pools
+  /// whose creator is in it, swaps without the list are not checked
+  pub check_pool_creators: bool,
+  /// number of used entries in `expect_oracle_mints`
+  pub expect_oracle_mints_len: u8,
+  /// per DESTINATION mint `expect_amount_out`, in raw units, from which a
+  /// swap must be attested by `expect_oracle`. Mints not listed never are
+  pub expect_oracle_mints: [(Pubkey, u64); MAX_EXPECT_ORACLE_MINTS],
+}
+
+impl ProtocolConfig {
   pub fn new(owner: &Pubkey) -> Self {
     Self {
       is_initialized: 1,
-      status: Status::SwapInfo.to_u8(),
-      token_latest_amount: 0,
+      status: Status::ProtocolConfig.to_u8(),
       owner: *owner,
-      token_account: COption::None,
+      blocked_pools_len: 0,
+      blocked_pools: [Pubkey::default(); MAX_BLOCKED_POOLS],
+      fee_reference_mint: COption::None,
+      min_notional_len: 0,
+      min_notional: [(Pubkey::default(), 0); MAX_MIN_NOTIONAL_MINTS],
+      disabled_exchangers: 0,
+      fee_discount_mint: COption::None,
+      fee_discount_threshold: 0,
+      fee_discount_bps: 0,
+      max_surplus_bps: 0,
+      swap_info_ttl_slots: 0,
+      fee_granularity: 0,
+      treasury_token_account: COption::None,
+      treasury_bps: 0,
+      mint_fee_caps_len: 0,
+      mint_fee_caps: [(Pubkey::default(), 0); MAX_MINT_FEE_CAPS],
+      collect_host_fee: false,
+      rebate_bps: 0,
+      max_route_accounts: 0,
+      fee_exempt_owners_len: 0,
+      fee_exempt_owners: [Pubkey::default(); MAX_FEE_EXEMPT_OWNERS],
+      expect_oracle: COption::None,
+      max_expect_out_ratio: 0,
+      collect_exchanger_stats: false,
+      check_pool_creators: false,
+      expect_oracle_mints_len: 0,
+      expect_oracle_mints: [(Pubkey::default(), 0); MAX_EXPECT_ORACLE_MINTS],
+    }
+  }
+
+  fn blocked_pools(&self) -> &[Pubkey] {
+    &self.blocked_pools[..self.blocked_pools_len as usize]
+  }
+
+  pub fn check_pool(&self, pool: &Pubkey) -> Result<(), ProtocolError> {
+    if self.blocked_pools().contains(pool) {
+      return Err(ProtocolError::PoolBlocked);
+    }
+    Ok(())
+  }
+
+  pub fn add_blocked_pool(&mut self, pool: &Pubkey) -> Result<(), ProtocolError> {
+    if self.blocked_pools().contains(pool) {
+      return Ok(());
+    }
+    let len = self.blocked_pools_len as usize;
+    if len >= MAX_BLOCKED_POOLS {
+      return Err(ProtocolError::BlockedPoolListFull);
+    }
+    self.blocked_pools[len] = *pool;
+    self.blocked_pools_len += 1;
+    Ok(())
+  }
+
+  pub fn remove_blocked_pool(&mut self, pool: &Pubkey) -> Result<(), ProtocolError> {
+    let len = self.blocked_pools_len as usize;
+    let index = self
+      .blocked_pools()
+      .iter()
+      .position(|k| k == pool)
+      .ok_or(ProtocolError::InvalidInput)?;
+    self.blocked_pools[index] = self.blocked_pools[len - 1];
+    self.blocked_pools[len - 1] = Pubkey::default();
+    self.blocked_pools_len -= 1;
+    Ok(())
+  }
+
+  pub fn is_fee_exempt(&self, owner: &Pubkey) -> bool {
+    self.fee_exempt_owners[..self.fee_exempt_owners_len as usize].contains(owner)
+  }
+
+  pub fn add_fee_exempt_owner(&mut self, owner: &Pubkey) -> Result<(), ProtocolError> {
+    if self.is_fee_exempt(owner) {
+      return Ok(());
+    }
+    let len = self.fee_exempt_owners_len as usize;
+    if len >= MAX_FEE_EXEMPT_OWNERS {
+      return Err(ProtocolError::FeeExemptOwnerListFull);
+    }
+    self.fee_exempt_owners[len] = *owner;
+    self.fee_exempt_owners_len += 1;
+    Ok(())
+  }
+
+  pub fn remove_fee_exempt_owner(&mut self, owner: &Pubkey) -> Result<(), ProtocolError> {
+    let len = self.fee_exempt_owners_len as usize;
+    let index = self.fee_exempt_owners[..len]
+      .iter()
+      .position(|k| k == owner)
+      .ok_or(ProtocolError::InvalidInput)?;
+    self.fee_exempt_owners[index] = self.fee_exempt_owners[len - 1];
+    self.fee_exempt_owners[len - 1] = Pubkey::default();
+    self.fee_exempt_owners_len -= 1;
+    Ok(())
+  }
+
+  pub fn min_notional_in(&self, mint: &Pubkey) -> Option<u64> {
+    self.min_notional[..self.min_notional_len as usize]
+      .iter()
+      .find(|(k, _)| k == mint)
+      .map(|(_, amount)| *amount)
+  }
+
+  /// Sets the minimum `amount_in` of `mint`, zero removes the entry.
+  pub fn set_min_notional(&mut self, mint: &Pubkey, amount: u64) -> Result<(), ProtocolError> {
+    let len = self.min_notional_len as usize;
+    let index = self.min_notional[..len].iter().position(|(k, _)| k == mint);
+    match (index, amount) {
+      (Some(index), 0) => {
+        self.min_notional[index] = self.min_notional[len - 1];
+        self.min_notional[len - 1] = (Pubkey::default(), 0);
+        self.min_notional_len -= 1;
+      }
+      (Some(index), _) => self.min_notional[index].1 = amount,
+      (None, 0) => return Err(ProtocolError::InvalidInput),
+      (None, _) => {
+        if len >= MAX_MIN_NOTIONAL_MINTS {
+          return Err(ProtocolError::MinNotionalListFull);
+        }
+        self.min_notional[len] = (*mint, amount);
+        self.min_notional_len += 1;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn check_exchanger(&self, exchanger: ExchangerType) -> Result<(), ProtocolError> {
+    if self.disabled_exchangers & (1u16 << exchanger.to_u8()) != 0 {
+      return Err(ProtocolError::ExchangerDisabled);
+    }
+    Ok(())
+  }
+
+  pub fn set_exchanger_enabled(&mut self, exchanger: ExchangerType, enabled: bool) {
+    let bit = 1u16 << exchanger.to_u8();
+    if enabled {
+      self.disabled_exchangers &= !bit;
+    } else {
+      self.disabled_exchangers |= bit;
+    }
+  }
+
+  /// Returns `fee` after the holder discount of a `balance` of `mint`.
+  pub fn discounted_fee(
+    &self,
+    fee: u64,
+    mint: &Pubkey,
+    balance: u64,
+  ) -> Result<u64, ProtocolError> {
+    match self.fee_discount_mint {
+      COption::Some(ref discount_mint) if discount_mint == mint => {}
+      _ => return Err(ProtocolError::InvalidTokenMint),
+    }
+    if balance < self.fee_discount_threshold {
+      return Ok(fee);
+    }
+    let discount = (fee as u128)
+      .checked_mul(self.fee_discount_bps as u128)
+      .map(|x| x / 10_000)
+      .ok_or(ProtocolError::Overflow)?;
+    Ok(fee.saturating_sub(discount as u64))
+  }
+
+  /// Rounds `fee` down to a multiple of `fee_granularity`.
+  pub fn round_fee(&self, fee: u64) -> u64 {
+    if self.fee_granularity <= 1 {
+      return fee;
+    }
+    fee - fee % self.fee_granularity
+  }
+
+  /// Part of `fee` owed to the treasury, rounded down.
+  pub fn treasury_share(&self, fee: u64) -> u64 {
+    (fee as u128 * self.treasury_bps as u128 / 10_000) as u64
+  }
+
+  /// Rebate owed on an In leg output of `amount_out`, before the cap of the
+  /// rebate vault.
+  pub fn rebate(&self, amount_out: u64) -> u64 {
+    (amount_out as u128 * self.rebate_bps as u128 / 10_000) as u64
+  }
+
+  /// Most distinct accounts a multi-leg swap may take.
+  pub fn route_accounts_limit(&self) -> usize {
+    match self.max_route_accounts {
+      0 => DEFAULT_MAX_ROUTE_ACCOUNTS as usize,
+      limit => limit as usize,
+    }
+  }
+
+  /// Caps `fee` taken in `mint` at the mint's absolute cap, if it has one.
+  pub fn cap_fee(&self, mint: &Pubkey, fee: u64) -> u64 {
+    self.mint_fee_caps[..self.mint_fee_caps_len as usize]
+      .iter()
+      .find(|(k, _)| k == mint)
+      .map_or(fee, |(_, max_fee)| cmp::min(fee, *max_fee))
+  }
+
+  /// Sets the absolute fee cap of `mint`, zero removes the entry.
+  pub fn set_mint_fee_cap(&mut self, mint: &Pubkey, max_fee: u64) -> Result<(), ProtocolError> {
+    let len = self.mint_fee_caps_len as usize;
+    let index = self.mint_fee_caps[..len]
+      .iter()
+      .position(|(k, _)| k == mint);
+    match (index, max_fee) {
+      (Some(index), 0) => {
+        self.mint_fee_caps[index] = self.mint_fee_caps[len - 1];
+        self.mint_fee_caps[len - 1] = (Pubkey::default(), 0);
+        self.mint_fee_caps_len -= 1;
+      }
+      (Some(index), _) => self.mint_fee_caps[index].1 = max_fee,
+      (None, 0) => return Err(ProtocolError::InvalidInput),
+      (None, _) => {
+        if len >= MAX_MINT_FEE_CAPS {
+          return Err(ProtocolError::MintFeeCapListFull);
+        }
+        self.mint_fee_caps[len] = (*mint, max_fee);
+        self.mint_fee_caps_len += 1;
+      }
+    }
+    Ok(())
+  }
+
+  /// Checks a swap into `mint` expecting `expect_amount_out` is attested
+  /// once it reaches the mint threshold. A passed `oracle`, with whether it
+  /// signed, must be the configured `expect_oracle` whatever the amount.
+  pub fn check_expect_oracle(
+    &self,
+    mint: &Pubkey,
+    expect_amount_out: u64,
+    oracle: Option<(&Pubkey, bool)>,
+  ) -> Result<(), ProtocolError> {
+    if let Some((oracle, is_signer)) = oracle {
+      return match self.expect_oracle {
+        COption::Some(ref expect_oracle) if expect_oracle == oracle && is_signer => Ok(()),
+        _ => Err(ProtocolError::InvalidExpectOracle),
+      };
+    }
+    match self.expect_oracle_mints[..self.expect_oracle_mints_len as usize]
+      .iter()
+      .find(|(k, _)| k == mint)
+    {
+      Some((_, threshold)) if expect_amount_out >= *threshold => {
+        Err(ProtocolError::InvalidExpectOracle)
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Sets the `expect_amount_out` of `mint` from which swaps must be
+  /// attested by the oracle, zero removes the entry.
+  pub fn set_expect_oracle_threshold(
+    &mut self,
+    mint: &Pubkey,
+    threshold: u64,
+  ) -> Result<(), ProtocolError> {
+    let len = self.expect_oracle_mints_len as usize;
+    let index = self.expect_oracle_mints[..len]
+      .iter()
+      .position(|(k, _)| k == mint);
+    match (index, threshold) {
+      (Some(index), 0) => {
+        self.expect_oracle_mints[index] = self.expect_oracle_mints[len - 1];
+        self.expect_oracle_mints[len - 1] = (Pubkey::default(), 0);
+        self.expect_oracle_mints_len -= 1;
+      }
+      (Some(index), _) => self.expect_oracle_mints[index].1 = threshold,
+      (None, 0) => return Err(ProtocolError::InvalidInput),
+      (None, _) => {
+        if len >= MAX_EXPECT_ORACLE_MINTS {
+          return Err(ProtocolError::ExpectOracleMintListFull);
+        }
+        self.expect_oracle_mints[len] = (*mint, threshold);
+        self.expect_oracle_mints_len += 1;
+      }
+    }
+    Ok(())
+  }
+
+  /// Fails with `InvalidExpectAmountOut` when `expect_amount_out` is over
+  /// `max_expect_out_ratio` times `amount_in`, both in whole tokens.
+  pub fn check_expect_out_ratio(
+    &self,
+    amount_in: u64,
+    source_decimals: u8,
+    expect_amount_out: u64,
+    destination_decimals: u8,
+  ) -> Result<(), ProtocolError> {
+    if self.max_expect_out_ratio == 0 {
+      return Ok(());
+    }
+    let scale = |decimals: u8| 10u128.checked_pow(decimals as u32);
+    let expect = scale(source_decimals)
+      .and_then(|factor| (expect_amount_out as u128).checked_mul(factor))
+      .ok_or(ProtocolError::Overflow)?;
+    // a limit too large for u128 bounds nothing
+    let limit = scale(destination_decimals)
+      .and_then(|factor| (amount_in as u128).checked_mul(factor))
+      .and_then(|x| x.checked_mul(self.max_expect_out_ratio as u128));
+    match limit {
+      Some(limit) if expect > limit => Err(ProtocolError::InvalidExpectAmountOut),
+      _ => Ok(()),
     }
   }
+
+  /// Every exchanger is disabled, nothing can be swapped.
+  pub fn is_paused(&self) -> bool {
+    (0..=u8::MAX)
+      .filter_map(ExchangerType::from)
+      .all(|exchanger| self.check_exchanger(exchanger).is_err())
+  }
 }
 
-impl Sealed for SwapInfo {}
+/// `GetConfig` return data. New fields are only ever appended, older
+/// decoders ignore the trailing bytes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ConfigView {
+  /// output fee, `fee_numerator / fee_denominator` of the surplus
+  pub fee_numerator: u64,
+  pub fee_denominator: u64,
+  /// config owner
+  pub owner: Pubkey,
+  /// every exchanger is disabled
+  pub paused: bool,
+  /// owner the fee token accounts must have
+  pub fee_token_owner: Pubkey,
+}
 
-impl IsInitialized for SwapInfo {
+impl ConfigView {
+  pub const VERSION: u8 = 1;
+  pub const LEN: usize = 82;
+
+  // [version], [fee_numerator], [fee_denominator], [owner], [paused], [fee_token_owner]
+  pub fn pack(&self) -> [u8; ConfigView::LEN] {
+    let mut dst = [0u8; ConfigView::LEN];
+    let (version, fee_numerator, fee_denominator, owner, paused, fee_token_owner) =
+      mut_array_refs![&mut dst, 1, 8, 8, 32, 1, 32];
+    version[0] = Self::VERSION;
+    *fee_numerator = self.fee_numerator.to_le_bytes();
+    *fee_denominator = self.fee_denominator.to_le_bytes();
+    owner.copy_from_slice(self.owner.as_ref());
+    paused[0] = self.paused as u8;
+    fee_token_owner.copy_from_slice(self.fee_token_owner.as_ref());
+    dst
+  }
+
+  /// Decodes `GetConfig` return data, safe to use from `no-entrypoint` clients.
+  pub fn unpack(src: &[u8]) -> Result<Self, ProtocolError> {
+    if src.len() < ConfigView::LEN {
+      return Err(ProtocolError::InvalidInput);
+    }
+    let src = array_ref![src, 0, ConfigView::LEN];
+    let (&[version], &fee_numerator, &fee_denominator, owner, &[paused], fee_token_owner) =
+      array_refs![src, 1, 8, 8, 32, 1, 32];
+    if version < Self::VERSION || paused > 1 {
+      return Err(ProtocolError::InvalidInput);
+    }
+    Ok(Self {
+      fee_numerator: u64::from_le_bytes(fee_numerator),
+      fee_denominator: u64::from_le_bytes(fee_denominator),
+      owner: Pubkey::new_from_array(*owner),
+      paused: paused == 1,
+      fee_token_owner: Pubkey::new_from_array(*fee_token_owner),
+    })
+  }
+}
+
+/// Return data of a direct swap or an Out leg without a client route id:
+/// where the DESTINATION tokens of the swap went. Fees are in DESTINATION
+/// units, also when swapped into the reference mint.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FeeBreakdown {
+  /// output of the exchanger, before any fee
+  pub gross_output: u64,
+  /// fee sent to the fee token account, or owed by the caller
+  pub protocol_fee: u64,
+  /// share of a referrer, the output fee isn't split with one and it is
+  /// always 0
+  pub referrer_fee: u64,
+  /// share of the fee sent to the config treasury
+  pub treasury_fee: u64,
+  /// left in the DESTINATION account
+  pub net_output: u64,
+}
+
+impl FeeBreakdown {
+  pub const VERSION: u8 = 1;
+  pub const LEN: usize = 41;
+
+  // [version], [gross_output], [protocol_fee], [referrer_fee], [treasury_fee], [net_output]
+  pub fn pack(&self) -> [u8; FeeBreakdown::LEN] {
+    let mut dst = [0u8; FeeBreakdown::LEN];
+    let (version, gross_output, protocol_fee, referrer_fee, treasury_fee, net_output) =
+      mut_array_refs![&mut dst, 1, 8, 8, 8, 8, 8];
+    version[0] = Self::VERSION;
+    *gross_output = self.gross_output.to_le_bytes();
+    *protocol_fee = self.protocol_fee.to_le_bytes();
+    *referrer_fee = self.referrer_fee.to_le_bytes();
+    *treasury_fee = self.treasury_fee.to_le_bytes();
+    *net_output = self.net_output.to_le_bytes();
+    dst
+  }
+
+  /// Decodes swap return data, safe to use from `no-entrypoint` clients.
+  pub fn unpack(src: &[u8]) -> Result<Self, ProtocolError> {
+    if src.len() < FeeBreakdown::LEN {
+      return Err(ProtocolError::InvalidInput);
+    }
+    let src = array_ref![src, 0, FeeBreakdown::LEN];
+    let (&[version], &gross_output, &protocol_fee, &referrer_fee, &treasury_fee, &net_output) =
+      array_refs![src, 1, 8, 8, 8, 8, 8];
+    if version < Self::VERSION {
+      return Err(ProtocolError::InvalidInput);
+    }
+    Ok(Self {
+      gross_output: u64::from_le_bytes(gross_output),
+      protocol_fee: u64::from_le_bytes(protocol_fee),
+      referrer_fee: u64::from_le_bytes(referrer_fee),
+      treasury_fee: u64::from_le_bytes(treasury_fee),
+      net_output: u64::from_le_bytes(net_output),
+    })
+  }
+}
+
+impl Sealed for ProtocolConfig {}
+
+impl IsInitialized for ProtocolConfig {
   fn is_initialized(&self) -> bool {
     self.is_initialized == 1
   }
 }
 
-impl Pack for SwapInfo {
-  const LEN: usize = 78;
+impl Pack for ProtocolConfig {
+  const LEN: usize = 1280;
 
   fn pack_into_slice(&self, dst: &mut [u8]) {
-    let output = array_mut_ref![dst, 0, 78];
+    let output = array_mut_ref![dst, 0, 1280];
     #[rustfmt::skip]
     let (
       is_initialized,
       status,
-      token_latest_amount,
       owner,
-      token_account,
-    ) = mut_array_refs![output, 1, 1, 8, 32, 36];
+      blocked_pools_len,
+      blocked_pools,
+      fee_reference_mint,
+      min_notional_len,
+      min_notional,
+      disabled_exchangers,
+      fee_discount_mint,
+      fee_discount_threshold,
+      fee_discount_bps,
+      max_surplus_bps,
+      swap_info_ttl_slots,
+      fee_granularity,
+      treasury_token_account,
+      treasury_bps,
+      mint_fee_caps_len,
+      mint_fee_caps,
+      collect_host_fee,
+      rebate_bps,
+      max_route_accounts,
+      fee_exempt_owners_len,
+      fee_exempt_owners,
+      expect_oracle,
+      max_expect_out_ratio,
+      collect_exchanger_stats,
+      check_pool_creators,
+      expect_oracle_mints_len,
+      expect_oracle_mints,
+      _reserved,
+    ) = mut_array_refs![output, 1, 1, 32, 1, 32 * MAX_BLOCKED_POOLS, 36, 1, 40 * MAX_MIN_NOTIONAL_MINTS, 2, 36, 8, 2, 2, 8, 8, 36, 2, 1, 40 * MAX_MINT_FEE_CAPS, 1, 2, 1, 1, 32 * MAX_FEE_EXEMPT_OWNERS, 36, 2, 1, 1, 1, 40 * MAX_EXPECT_ORACLE_MINTS, 17];
     is_initialized.copy_from_slice(&[self.is_initialized]);
     status.copy_from_slice(&[self.status]);
-    token_latest_amount.copy_from_slice(&self.token_latest_amount.to_le_bytes()[..]);
     owner.copy_from_slice(self.owner.as_ref());
-    pack_coption_key(&self.token_account, token_account);
+    blocked_pools_len.copy_from_slice(&[self.blocked_pools_len]);
+    for (dst, pool) in blocked_pools
+      .chunks_exact_mut(32)
+      .zip(self.blocked_pools.iter())
+    {
+      dst.copy_from_slice(pool.as_ref());
+    }
+    for (dst, owner) in fee_exempt_owners
+      .chunks_exact_mut(32)
+      .zip(self.fee_exempt_owners.iter())
+    {
+      dst.copy_from_slice(owner.as_ref());
+    }
+    pack_coption_key(&self.fee_reference_mint, fee_reference_mint);
+    min_notional_len.copy_from_slice(&[self.min_notional_len]);
+    for (dst, (mint, amount)) in min_notional
+      .chunks_exact_mut(40)
+      .zip(self.min_notional.iter())
+    {
+      dst[..32].copy_from_slice(mint.as_ref());
+      dst[32..].copy_from_slice(&amount.to_le_bytes());
+    }
+    *disabled_exchangers = self.disabled_exchangers.to_le_bytes();
+    pack_coption_key(&self.fee_discount_mint, fee_discount_mint);
+    *fee_discount_threshold = self.fee_discount_threshold.to_le_bytes();
+    *fee_discount_bps = self.fee_discount_bps.to_le_bytes();
+    *max_surplus_bps = self.max_surplus_bps.to_le_bytes();
+    *swap_info_ttl_slots = self.swap_info_ttl_slots.to_le_bytes();
+    *fee_granularity = self.fee_granularity.to_le_bytes();
+    pack_coption_key(&self.treasury_token_account, treasury_token_account);
+    *treasury_bps = self.treasury_bps.to_le_bytes();
+    mint_fee_caps_len.copy_from_slice(&[self.mint_fee_caps_len]);
+    for (dst, (mint, max_fee)) in mint_fee_caps
+      .chunks_exact_mut(40)
+      .zip(self.mint_fee_caps.iter())
+    {
+      dst[..32].copy_from_slice(mint.as_ref());
+      dst[32..].copy_from_slice(&max_fee.to_le_bytes());
+    }
+    collect_host_fee.copy_from_slice(&[self.collect_host_fee as u8]);
+    *rebate_bps = self.rebate_bps.to_le_bytes();
+    max_route_accounts.copy_from_slice(&[self.max_route_accounts]);
+    fee_exempt_owners_len.copy_from_slice(&[self.fee_exempt_owners_len]);
+    pack_coption_key(&self.expect_oracle, expect_oracle);
+    *max_expect_out_ratio = self.max_expect_out_ratio.to_le_bytes();
+    collect_exchanger_stats.copy_from_slice(&[self.collect_exchanger_stats as u8]);
+    check_pool_creators.copy_from_slice(&[self.check_pool_creators as u8]);
+    expect_oracle_mints_len.copy_from_slice(&[self.expect_oracle_mints_len]);
+    for (dst, (mint, threshold)) in expect_oracle_mints
+      .chunks_exact_mut(40)
+      .zip(self.expect_oracle_mints.iter())
+    {
+      dst[..32].copy_from_slice(mint.as_ref());
+      dst[32..].copy_from_slice(&threshold.to_le_bytes());
+    }
   }
 
   fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-    let input = array_ref![src, 0, 78];
+    let input = array_ref![src, 0, 1280];
     #[rustfmt::skip]
     let (
       &[is_initialized],
       &[status],
-      &token_latest_amount,
       owner,
-      token_account,
-    ) = array_refs![input, 1, 1, 8, 32, 36];
+      &[blocked_pools_len],
+      blocked_pools_data,
+      fee_reference_mint,
+      &[min_notional_len],
+      min_notional_data,
+      &disabled_exchangers,
+      fee_discount_mint,
+      &fee_discount_threshold,
+      &fee_discount_bps,
+      &max_surplus_bps,
+      &swap_info_ttl_slots,
+      &fee_granularity,
+      treasury_token_account,
+      &treasury_bps,
+      &[mint_fee_caps_len],
+      mint_fee_caps_data,
+      &[collect_host_fee],
+      &rebate_bps,
+      &[max_route_accounts],
+      &[fee_exempt_owners_len],
+      fee_exempt_owners_data,
+      expect_oracle,
+      &max_expect_out_ratio,
+      &[collect_exchanger_stats],
+      &[check_pool_creators],
+      &[expect_oracle_mints_len],
+      expect_oracle_mints_data,
+      _reserved,
+    ) = array_refs![input, 1, 1, 32, 1, 32 * MAX_BLOCKED_POOLS, 36, 1, 40 * MAX_MIN_NOTIONAL_MINTS, 2, 36, 8, 2, 2, 8, 8, 36, 2, 1, 40 * MAX_MINT_FEE_CAPS, 1, 2, 1, 1, 32 * MAX_FEE_EXEMPT_OWNERS, 36, 2, 1, 1, 1, 40 * MAX_EXPECT_ORACLE_MINTS, 17];
+    if blocked_pools_len as usize > MAX_BLOCKED_POOLS
+      || min_notional_len as usize > MAX_MIN_NOTIONAL_MINTS
+      || mint_fee_caps_len as usize > MAX_MINT_FEE_CAPS
+      || fee_exempt_owners_len as usize > MAX_FEE_EXEMPT_OWNERS
+      || expect_oracle_mints_len as usize > MAX_EXPECT_ORACLE_MINTS
+      || collect_host_fee > 1
+      || collect_exchanger_stats > 1
+      || check_pool_creators > 1
+    {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    let mut blocked_pools = [Pubkey::default(); MAX_BLOCKED_POOLS];
+    for (pool, src) in blocked_pools
+      .iter_mut()
+      .zip(blocked_pools_data.chunks_exact(32))
+    {
+      *pool = Pubkey::new(src);
+    }
+    let mut fee_exempt_owners = [Pubkey::default(); MAX_FEE_EXEMPT_OWNERS];
+    for (owner, src) in fee_exempt_owners
+      .iter_mut()
+      .zip(fee_exempt_owners_data.chunks_exact(32))
+    {
+      *owner = Pubkey::new(src);
+    }
+    let mut min_notional = [(Pubkey::default(), 0); MAX_MIN_NOTIONAL_MINTS];
+    for ((mint, amount), src) in min_notional
+      .iter_mut()
+      .zip(min_notional_data.chunks_exact(40))
+    {
+      *mint = Pubkey::new(&src[..32]);
+      *amount = u64::from_le_bytes(*array_ref![src, 32, 8]);
+    }
+    let mut mint_fee_caps = [(Pubkey::default(), 0); MAX_MINT_FEE_CAPS];
+    for ((mint, max_fee), src) in mint_fee_caps
+      .iter_mut()
+      .zip(mint_fee_caps_data.chunks_exact(40))
+    {
+      *mint = Pubkey::new(&src[..32]);
+      *max_fee = u64::from_le_bytes(*array_ref![src, 32, 8]);
+    }
+    let mut expect_oracle_mints = [(Pubkey::default(), 0); MAX_EXPECT_ORACLE_MINTS];
+    for ((mint, threshold), src) in expect_oracle_mints
+      .iter_mut()
+      .zip(expect_oracle_mints_data.chunks_exact(40))
+    {
+      *mint = Pubkey::new(&src[..32]);
+      *threshold = u64::from_le_bytes(*array_ref![src, 32, 8]);
+    }
     Ok(Self {
       is_initialized,
       status,
-      token_latest_amount: u64::from_le_bytes(token_latest_amount),
       owner: Pubkey::new(owner),
-      token_account: unpack_coption_key(token_account)?,
+      blocked_pools_len,
+      blocked_pools,
+      fee_reference_mint: unpack_coption_key(fee_reference_mint)?,
+      min_notional_len,
+      min_notional,
+      disabled_exchangers: u16::from_le_bytes(disabled_exchangers),
+      fee_discount_mint: unpack_coption_key(fee_discount_mint)?,
+      fee_discount_threshold: u64::from_le_bytes(fee_discount_threshold),
+      fee_discount_bps: u16::from_le_bytes(fee_discount_bps),
+      max_surplus_bps: u16::from_le_bytes(max_surplus_bps),
+      swap_info_ttl_slots: u64::from_le_bytes(swap_info_ttl_slots),
+      fee_granularity: u64::from_le_bytes(fee_granularity),
+      treasury_token_account: unpack_coption_key(treasury_token_account)?,
+      treasury_bps: u16::from_le_bytes(treasury_bps),
+      mint_fee_caps_len,
+      mint_fee_caps,
+      collect_host_fee: collect_host_fee == 1,
+      rebate_bps: u16::from_le_bytes(rebate_bps),
+      max_route_accounts,
+      fee_exempt_owners_len,
+      fee_exempt_owners,
+      expect_oracle: unpack_coption_key(expect_oracle)?,
+      max_expect_out_ratio: u16::from_le_bytes(max_expect_out_ratio),
+      collect_exchanger_stats: collect_exchanger_stats == 1,
+      check_pool_creators: check_pool_creators == 1,
+      expect_oracle_mints_len,
+      expect_oracle_mints,
     })
   }
 }
@@ -129,7 +1557,7 @@ fn unpack_coption_key(src: &[u8; 36]) -> Result<COption<Pubkey>, ProgramError> {
 
 #[cfg(test)]
 mod test {
-  // use super::*;
+  use super::*;
 
   // const TEST_VERSION: u8 = 1;
   // const TEST_NONCE: u8 = 255;
@@ -141,4 +1569,828 @@ mod test {
   pub fn test_onesol_amm_info() {
     assert_eq!(1, 1);
   }
+
+  #[test]
+  fn test_derive_swap_info_address() {
+    let (owner, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (address, bump) = derive_swap_info_address(&owner, 0, &program_id);
+    assert_eq!(
+      Pubkey::create_program_address(
+        &[SWAP_INFO_SEED, owner.as_ref(), &[0], &[bump]],
+        &program_id
+      ),
+      Ok(address)
+    );
+    assert_eq!(derive_swap_info_address(&owner, 0, &program_id).0, address);
+    assert_ne!(derive_swap_info_address(&owner, 1, &program_id).0, address);
+    assert_ne!(
+      derive_swap_info_address(&Pubkey::new_unique(), 0, &program_id).0,
+      address
+    );
+  }
+
+  #[test]
+  pub fn test_swap_info_single_swap_in() {
+    let mut swap_info = SwapInfo::new(&Pubkey::new_unique(), 0);
+    swap_info.token_account = COption::Some(Pubkey::new_unique());
+    assert_eq!(swap_info.check_swap_in(), Ok(()));
+
+    // first In leg records its output
+    swap_info.token_latest_amount = 1_000;
+    let mut data = vec![0u8; SwapInfo::LEN];
+    SwapInfo::pack(swap_info, &mut data).unwrap();
+
+    // second In leg against the same SwapInfo
+    let mut swap_info = SwapInfo::unpack(&data).unwrap();
+    assert_eq!(
+      swap_info.check_swap_in(),
+      Err(ProtocolError::InvalidAccountFlags)
+    );
+
+    // setting up the swap info again starts a new lifecycle
+    swap_info.token_latest_amount = 0;
+    assert_eq!(swap_info.check_swap_in(), Ok(()));
+  }
+
+  #[test]
+  pub fn test_swap_info_reset_between_routes() {
+    let mut swap_info = SwapInfo::new(&Pubkey::new_unique(), 0);
+    for _ in 0..2 {
+      // setup, In, Out
+      swap_info.token_account = COption::Some(Pubkey::new_unique());
+      swap_info.token_latest_amount = 0;
+      swap_info.check_swap_in().unwrap();
+      swap_info.token_latest_amount = 1_000;
+      assert_eq!(swap_info.reset(), Err(ProtocolError::InvalidAccountFlags));
+      swap_info.token_latest_amount = 990;
+      swap_info.token_account = COption::None;
+
+      let mut data = vec![0u8; SwapInfo::LEN];
+      SwapInfo::pack(swap_info, &mut data).unwrap();
+      swap_info = SwapInfo::unpack(&data).unwrap();
+      swap_info.reset().unwrap();
+      assert_eq!(swap_info.token_latest_amount, 0);
+      assert_eq!(swap_info.token_account, COption::None);
+      assert_eq!(Status::from_u8(swap_info.status), Ok(Status::SwapInfo));
+    }
+
+    swap_info.status = Status::Closed.to_u8();
+    assert_eq!(swap_info.reset(), Err(ProtocolError::InvalidAccountFlags));
+  }
+
+  /// Golden layout of `SwapInfo`, existing on-chain accounts depend on it. A
+  /// change here needs a migration, not just a test update. The first
+  /// `LEGACY_SWAP_INFO_LEN` bytes are shared with the legacy accounts.
+  #[test]
+  pub fn test_swap_info_layout() {
+    let owner = Pubkey::new_from_array([7u8; 32]);
+    let token_account = Pubkey::new_from_array([9u8; 32]);
+    let mid_mint = Pubkey::new_from_array([11u8; 32]);
+    let swap_info = SwapInfo {
+      is_initialized: 1,
+      status: Status::SwapInfo.to_u8(),
+      token_latest_amount: 0x0102_0304_0506_0708,
+      owner,
+      token_account: COption::Some(token_account),
+      mid_mint: COption::Some(mid_mint),
+      created_slot: 0x1112_1314_1516_1718,
+    };
+    assert_eq!(SwapInfo::LEN, 122);
+    assert_eq!(LEGACY_SWAP_INFO_LEN, 78);
+    assert_eq!(MID_MINT_SWAP_INFO_LEN, 114);
+    let mut data = vec![0u8; SwapInfo::LEN];
+    SwapInfo::pack(swap_info, &mut data).unwrap();
+
+    assert_eq!(data[0], 1);
+    assert_eq!(data[1], 1);
+    assert_eq!(data[2..10], [8, 7, 6, 5, 4, 3, 2, 1]);
+    assert_eq!(data[10..42], owner.to_bytes());
+    assert_eq!(data[42..46], [1, 0, 0, 0]);
+    assert_eq!(data[46..78], token_account.to_bytes());
+    assert_eq!(data[78..82], [1, 0, 0, 0]);
+    assert_eq!(data[82..114], mid_mint.to_bytes());
+    assert_eq!(
+      data[114..122],
+      [0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11]
+    );
+    assert_eq!(SwapInfo::unpack(&data), Ok(swap_info));
+
+    let mut swap_info = swap_info;
+    swap_info.token_account = COption::None;
+    SwapInfo::pack(swap_info, &mut data).unwrap();
+    // only the tag is cleared, the stale key is left in place
+    assert_eq!(data[42..46], [0u8; 4]);
+    assert_eq!(data[46..78], token_account.to_bytes());
+  }
+
+  #[test]
+  pub fn test_swap_info_legacy_account() {
+    let mut swap_info = SwapInfo::new(&Pubkey::new_unique(), 42);
+    swap_info.token_account = COption::Some(Pubkey::new_unique());
+    swap_info.token_latest_amount = 1_000;
+    let mut data = vec![0u8; SwapInfo::LEN];
+    swap_info.store(&mut data).unwrap();
+    assert_eq!(SwapInfo::load(&data), Ok(swap_info));
+
+    // the mint recorded by the In leg can't be kept in a legacy account
+    let mut legacy_data = vec![0u8; LEGACY_SWAP_INFO_LEN];
+    swap_info.mid_mint = COption::Some(Pubkey::new_unique());
+    swap_info.store(&mut legacy_data).unwrap();
+    assert_eq!(legacy_data[..], data[..LEGACY_SWAP_INFO_LEN]);
+    let legacy = SwapInfo::load(&legacy_data).unwrap();
+    assert_eq!(legacy.mid_mint, COption::None);
+    assert_eq!(legacy.token_latest_amount, 1_000);
+    assert_eq!(legacy.check_mid_mint(&Pubkey::new_unique()), Ok(()));
+
+    let mint = Pubkey::new_unique();
+    swap_info.mid_mint = COption::Some(mint);
+    assert_eq!(swap_info.check_mid_mint(&mint), Ok(()));
+    assert_eq!(
+      swap_info.check_mid_mint(&Pubkey::new_unique()),
+      Err(ProtocolError::InvalidTokenAccount)
+    );
+    assert!(SwapInfo::load(&data[..LEGACY_SWAP_INFO_LEN + 1]).is_err());
+
+    // accounts created before the slot was recorded keep the mint
+    let mut mid_mint_data = vec![0u8; MID_MINT_SWAP_INFO_LEN];
+    swap_info.store(&mut mid_mint_data).unwrap();
+    let mid_mint_swap_info = SwapInfo::load(&mid_mint_data).unwrap();
+    assert_eq!(mid_mint_swap_info.mid_mint, COption::Some(mint));
+    assert_eq!(mid_mint_swap_info.created_slot, 0);
+  }
+
+  #[test]
+  pub fn test_swap_info_check_stale() {
+    let mut swap_info = SwapInfo::new(&Pubkey::new_unique(), 100);
+    assert_eq!(
+      swap_info.check_stale(1_000, 0),
+      Err(ProtocolError::SwapInfoNotStale)
+    );
+    assert_eq!(
+      swap_info.check_stale(199, 100),
+      Err(ProtocolError::SwapInfoNotStale)
+    );
+    assert_eq!(swap_info.check_stale(200, 100), Ok(()));
+
+    // set up, the In leg did not run
+    swap_info.token_account = COption::Some(Pubkey::new_unique());
+    assert_eq!(swap_info.check_stale(200, 100), Ok(()));
+    // between the In and the Out leg
+    swap_info.token_latest_amount = 1_000;
+    assert!(swap_info.in_progress());
+    assert_eq!(
+      swap_info.check_stale(u64::MAX, 100),
+      Err(ProtocolError::SwapInfoNotStale)
+    );
+    // the Out leg unbound the token account
+    swap_info.token_account = COption::None;
+    assert_eq!(swap_info.check_stale(200, 100), Ok(()));
+
+    swap_info.created_slot = u64::MAX;
+    assert_eq!(
+      swap_info.check_stale(u64::MAX, 100),
+      Err(ProtocolError::SwapInfoNotStale)
+    );
+    // no slot recorded
+    swap_info.created_slot = 0;
+    assert_eq!(
+      swap_info.check_stale(u64::MAX, 1),
+      Err(ProtocolError::SwapInfoNotStale)
+    );
+    swap_info.status = Status::ProtocolConfig.to_u8();
+    assert_eq!(
+      swap_info.check_stale(u64::MAX, 1),
+      Err(ProtocolError::InvalidAccountFlags)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_blocked_pools() {
+    let blocked = Pubkey::new_unique();
+    let unblocked = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    config.add_blocked_pool(&blocked).unwrap();
+    assert_eq!(config.check_pool(&blocked), Err(ProtocolError::PoolBlocked));
+    assert_eq!(config.check_pool(&unblocked), Ok(()));
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.check_pool(&blocked), Err(ProtocolError::PoolBlocked));
+    assert_eq!(config.fee_reference_mint, COption::None);
+
+    let mint = Pubkey::new_unique();
+    config.fee_reference_mint = COption::Some(mint);
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.fee_reference_mint, COption::Some(mint));
+
+    config.remove_blocked_pool(&blocked).unwrap();
+    assert_eq!(config.check_pool(&blocked), Ok(()));
+    assert_eq!(
+      config.remove_blocked_pool(&blocked),
+      Err(ProtocolError::InvalidInput)
+    );
+
+    for _ in 0..MAX_BLOCKED_POOLS {
+      config.add_blocked_pool(&Pubkey::new_unique()).unwrap();
+    }
+    assert_eq!(
+      config.add_blocked_pool(&blocked),
+      Err(ProtocolError::BlockedPoolListFull)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_fee_exempt_owners() {
+    let exempt = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert!(!config.is_fee_exempt(&exempt));
+    config.add_fee_exempt_owner(&exempt).unwrap();
+    config.add_fee_exempt_owner(&exempt).unwrap();
+    config.add_blocked_pool(&Pubkey::new_unique()).unwrap();
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert!(config.is_fee_exempt(&exempt));
+    assert!(!config.is_fee_exempt(&config.blocked_pools[0]));
+    assert_eq!(config.fee_exempt_owners_len, 1);
+
+    config.remove_fee_exempt_owner(&exempt).unwrap();
+    assert!(!config.is_fee_exempt(&exempt));
+    assert_eq!(
+      config.remove_fee_exempt_owner(&exempt),
+      Err(ProtocolError::InvalidInput)
+    );
+    for _ in 0..MAX_FEE_EXEMPT_OWNERS {
+      config.add_fee_exempt_owner(&Pubkey::new_unique()).unwrap();
+    }
+    assert_eq!(
+      config.add_fee_exempt_owner(&exempt),
+      Err(ProtocolError::FeeExemptOwnerListFull)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_min_notional() {
+    let mint = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.min_notional_in(&mint), None);
+    config.set_min_notional(&mint, 1_000).unwrap();
+    config.set_min_notional(&mint, 2_000).unwrap();
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.min_notional_in(&mint), Some(2_000));
+    assert_eq!(config.min_notional_len, 1);
+
+    config.set_min_notional(&mint, 0).unwrap();
+    assert_eq!(config.min_notional_in(&mint), None);
+    assert_eq!(
+      config.set_min_notional(&mint, 0),
+      Err(ProtocolError::InvalidInput)
+    );
+    for _ in 0..MAX_MIN_NOTIONAL_MINTS {
+      config.set_min_notional(&Pubkey::new_unique(), 1).unwrap();
+    }
+    assert_eq!(
+      config.set_min_notional(&mint, 1),
+      Err(ProtocolError::MinNotionalListFull)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_disabled_exchangers() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    config.set_exchanger_enabled(ExchangerType::AldrinExchange, false);
+    config.set_exchanger_enabled(ExchangerType::MeteoraDamm, false);
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(
+      config.check_exchanger(ExchangerType::AldrinExchange),
+      Err(ProtocolError::ExchangerDisabled)
+    );
+    assert_eq!(
+      config.check_exchanger(ExchangerType::MeteoraDamm),
+      Err(ProtocolError::ExchangerDisabled)
+    );
+    assert_eq!(config.check_exchanger(ExchangerType::SerumDex), Ok(()));
+
+    config.set_exchanger_enabled(ExchangerType::AldrinExchange, true);
+    assert_eq!(
+      config.check_exchanger(ExchangerType::AldrinExchange),
+      Ok(())
+    );
+    assert_eq!(
+      config.check_exchanger(ExchangerType::MeteoraDamm),
+      Err(ProtocolError::ExchangerDisabled)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_fee_discount() {
+    let mint = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(
+      config.discounted_fee(1_000, &mint, u64::MAX),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+    config.fee_discount_mint = COption::Some(mint);
+    config.fee_discount_threshold = 500;
+    config.fee_discount_bps = 2_500;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.fee_discount_mint, COption::Some(mint));
+    assert_eq!(config.discounted_fee(1_000, &mint, 500), Ok(750));
+    assert_eq!(config.discounted_fee(1_000, &mint, 499), Ok(1_000));
+    assert_eq!(
+      config.discounted_fee(u64::MAX, &mint, 500),
+      Ok(u64::MAX - u64::MAX / 4)
+    );
+    assert_eq!(
+      config.discounted_fee(1_000, &Pubkey::new_unique(), 500),
+      Err(ProtocolError::InvalidTokenMint)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_round_fee() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.round_fee(1_234), 1_234);
+    config.fee_granularity = 1;
+    assert_eq!(config.round_fee(1_234), 1_234);
+    config.fee_granularity = 1_000;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.fee_granularity, 1_000);
+    assert_eq!(config.round_fee(0), 0);
+    assert_eq!(config.round_fee(999), 0);
+    assert_eq!(config.round_fee(1_000), 1_000);
+    assert_eq!(config.round_fee(1_999), 1_000);
+    assert_eq!(config.round_fee(2_000), 2_000);
+    assert_eq!(config.round_fee(u64::MAX), u64::MAX - u64::MAX % 1_000);
+  }
+
+  #[test]
+  pub fn test_protocol_config_treasury_share() {
+    let treasury = Pubkey::new_unique();
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.treasury_share(1_000), 0);
+    config.treasury_token_account = COption::Some(treasury);
+    config.treasury_bps = 2_000;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.treasury_token_account, COption::Some(treasury));
+    assert_eq!(config.treasury_share(1_000), 200);
+    assert_eq!(config.treasury_share(9), 1);
+    assert_eq!(config.treasury_share(4), 0);
+    assert_eq!(config.treasury_share(u64::MAX), u64::MAX / 5);
+  }
+
+  #[test]
+  pub fn test_protocol_config_mint_fee_cap() {
+    let (mint, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.cap_fee(&mint, u64::MAX), u64::MAX);
+    assert_eq!(
+      config.set_mint_fee_cap(&mint, 0),
+      Err(ProtocolError::InvalidInput)
+    );
+    config.set_mint_fee_cap(&mint, 1_000).unwrap();
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.mint_fee_caps_len, 1);
+    assert_eq!(config.cap_fee(&mint, 999), 999);
+    assert_eq!(config.cap_fee(&mint, 1_000), 1_000);
+    assert_eq!(config.cap_fee(&mint, 1_001), 1_000);
+    assert_eq!(config.cap_fee(&other, 1_001), 1_001);
+
+    for _ in 1..MAX_MINT_FEE_CAPS {
+      config.set_mint_fee_cap(&Pubkey::new_unique(), 1).unwrap();
+    }
+    assert_eq!(
+      config.set_mint_fee_cap(&other, 1),
+      Err(ProtocolError::MintFeeCapListFull)
+    );
+    // updating a listed mint still works on a full list
+    config.set_mint_fee_cap(&mint, 2_000).unwrap();
+    assert_eq!(config.cap_fee(&mint, 2_001), 2_000);
+    config.set_mint_fee_cap(&mint, 0).unwrap();
+    assert_eq!(config.cap_fee(&mint, 2_001), 2_001);
+    assert_eq!(config.mint_fee_caps_len as usize, MAX_MINT_FEE_CAPS - 1);
+  }
+
+  #[test]
+  pub fn test_protocol_config_expect_out_ratio() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.check_expect_out_ratio(1, 6, u64::MAX, 0), Ok(()));
+    config.max_expect_out_ratio = 100;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.max_expect_out_ratio, 100);
+    // 1 token of 6 decimals for 100 tokens of 9 decimals
+    assert_eq!(
+      config.check_expect_out_ratio(1_000_000, 6, 100_000_000_000, 9),
+      Ok(())
+    );
+    assert_eq!(
+      config.check_expect_out_ratio(1_000_000, 6, 100_000_000_001, 9),
+      Err(ProtocolError::InvalidExpectAmountOut)
+    );
+    // 1 token of 9 decimals for 100 tokens of 6 decimals
+    assert_eq!(
+      config.check_expect_out_ratio(1_000_000_000, 9, 100_000_000, 6),
+      Ok(())
+    );
+    assert_eq!(
+      config.check_expect_out_ratio(1_000_000_000, 9, 100_000_001, 6),
+      Err(ProtocolError::InvalidExpectAmountOut)
+    );
+    assert_eq!(
+      config.check_expect_out_ratio(1, 40, 1, 0),
+      Err(ProtocolError::Overflow)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_expect_oracle() {
+    let (oracle, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (mint, other_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(
+      config.check_expect_oracle(&mint, 1_000, Some((&oracle, true))),
+      Err(ProtocolError::InvalidExpectOracle)
+    );
+    // no threshold, nothing to attest
+    assert_eq!(config.check_expect_oracle(&mint, u64::MAX, None), Ok(()));
+    config.expect_oracle = COption::Some(oracle);
+    assert_eq!(
+      config.set_expect_oracle_threshold(&mint, 0),
+      Err(ProtocolError::InvalidInput)
+    );
+    config.set_expect_oracle_threshold(&mint, 1_000).unwrap();
+    for _ in 0..MAX_MINT_FEE_CAPS {
+      config.set_mint_fee_cap(&Pubkey::new_unique(), 1).unwrap();
+    }
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let mut config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.expect_oracle, COption::Some(oracle));
+    assert_eq!(config.expect_oracle_mints_len, 1);
+    assert_eq!(config.mint_fee_caps_len as usize, MAX_MINT_FEE_CAPS);
+    assert_eq!(config.check_expect_oracle(&mint, 999, None), Ok(()));
+    assert_eq!(
+      config.check_expect_oracle(&mint, 1_000, None),
+      Err(ProtocolError::InvalidExpectOracle)
+    );
+    assert_eq!(config.check_expect_oracle(&other_mint, 1_000, None), Ok(()));
+    assert_eq!(
+      config.check_expect_oracle(&mint, 1_000, Some((&oracle, true))),
+      Ok(())
+    );
+    // a passed oracle is checked under the threshold too
+    assert_eq!(
+      config.check_expect_oracle(&mint, 1, Some((&oracle, false))),
+      Err(ProtocolError::InvalidExpectOracle)
+    );
+    assert_eq!(
+      config.check_expect_oracle(&mint, 1_000, Some((&other, true))),
+      Err(ProtocolError::InvalidExpectOracle)
+    );
+
+    for _ in 1..MAX_EXPECT_ORACLE_MINTS {
+      config
+        .set_expect_oracle_threshold(&Pubkey::new_unique(), 1)
+        .unwrap();
+    }
+    assert_eq!(
+      config.set_expect_oracle_threshold(&other_mint, 1),
+      Err(ProtocolError::ExpectOracleMintListFull)
+    );
+    config.set_expect_oracle_threshold(&mint, 0).unwrap();
+    assert_eq!(config.check_expect_oracle(&mint, u64::MAX, None), Ok(()));
+    assert_eq!(
+      config.expect_oracle_mints_len as usize,
+      MAX_EXPECT_ORACLE_MINTS - 1
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_rebate() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(config.rebate(1_000_000), 0);
+    config.rebate_bps = 10;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.rebate_bps, 10);
+    assert_eq!(config.rebate(9_999), 9);
+    assert_eq!(config.rebate(100_000), 100);
+    assert_eq!(config.rebate(u64::MAX), u64::MAX / 1_000);
+  }
+
+  #[test]
+  pub fn test_rebate_vault_take_rebate() {
+    let mut vault = RebateVault::new(&Pubkey::new_unique(), &Pubkey::new_unique(), 15);
+    let (owner, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    assert_eq!(vault.take_rebate(&owner, 10, 100), 10);
+    // the cap is shared by the legs of the same slot, whatever their SwapInfo
+    assert_eq!(vault.take_rebate(&owner, 10, 100), 5);
+    assert_eq!(vault.take_rebate(&owner, 10, 100), 0);
+    // each owner has its own
+    assert_eq!(vault.take_rebate(&other, 20, 100), 15);
+    assert_eq!(vault.credited_len, 2);
+
+    let mut data = vec![0u8; RebateVault::LEN];
+    RebateVault::pack(vault, &mut data).unwrap();
+    let mut vault = RebateVault::unpack(&data).unwrap();
+    assert_eq!(vault.take_rebate(&owner, 10, 100), 0);
+    // and starts over in the next one
+    assert_eq!(vault.take_rebate(&owner, 10, 101), 10);
+    assert_eq!((vault.slot, vault.credited_len), (101, 1));
+    vault.max_rebate = u64::MAX;
+    assert_eq!(vault.take_rebate(&owner, u64::MAX, 101), u64::MAX - 10);
+
+    // a full slot leaves the owners it doesn't list without a rebate
+    for _ in 1..MAX_REBATE_OWNERS_PER_SLOT {
+      assert_eq!(vault.take_rebate(&Pubkey::new_unique(), 1, 101), 1);
+    }
+    assert_eq!(vault.take_rebate(&Pubkey::new_unique(), 1, 101), 0);
+    assert_eq!(vault.take_rebate(&Pubkey::new_unique(), 1, 102), 1);
+
+    data[82] = MAX_REBATE_OWNERS_PER_SLOT as u8 + 1;
+    assert_eq!(
+      RebateVault::unpack(&data),
+      Err(ProgramError::InvalidAccountData)
+    );
+  }
+
+  #[test]
+  pub fn test_rebate_vault() {
+    let program_id = Pubkey::new_unique();
+    let (mint, token_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (address, bump) = derive_rebate_vault_address(&mint, &program_id);
+    assert_eq!(
+      Pubkey::create_program_address(&[REBATE_VAULT_SEED, mint.as_ref(), &[bump]], &program_id),
+      Ok(address)
+    );
+
+    let vault = RebateVault::new(&mint, &token_account, 500);
+    let mut data = vec![0u8; RebateVault::LEN];
+    RebateVault::pack(vault, &mut data).unwrap();
+    let vault = RebateVault::unpack(&data).unwrap();
+    assert_eq!(vault.max_rebate, 500);
+    assert_eq!(vault.check(&mint, &token_account), Ok(()));
+    assert_eq!(
+      vault.check(&token_account, &mint),
+      Err(ProtocolError::InvalidRebateAccount)
+    );
+    assert_eq!(
+      RebateVault::unpack(&[0u8; RebateVault::LEN]),
+      Err(ProgramError::UninitializedAccount)
+    );
+  }
+
+  #[test]
+  pub fn test_protocol_config_route_accounts_limit() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert_eq!(
+      config.route_accounts_limit(),
+      DEFAULT_MAX_ROUTE_ACCOUNTS as usize
+    );
+    config.max_route_accounts = 32;
+
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert_eq!(config.max_route_accounts, 32);
+    assert_eq!(config.route_accounts_limit(), 32);
+  }
+
+  #[test]
+  pub fn test_trusted_pool_creators() {
+    let program_id = Pubkey::new_unique();
+    let (address, bump) = derive_trusted_pool_creators_address(&program_id);
+    assert_eq!(
+      Pubkey::create_program_address(&[TRUSTED_POOL_CREATORS_SEED, &[bump]], &program_id),
+      Ok(address)
+    );
+
+    let (creator, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut creators = TrustedPoolCreators::new();
+    assert!(!creators.is_trusted(&creator));
+    creators.add(&creator).unwrap();
+    creators.add(&creator).unwrap();
+    assert_eq!(creators.creators_len, 1);
+    let mut data = vec![0u8; TrustedPoolCreators::LEN];
+    TrustedPoolCreators::pack(creators, &mut data).unwrap();
+    let mut creators = TrustedPoolCreators::unpack(&data).unwrap();
+    assert!(creators.is_trusted(&creator));
+    assert!(!creators.is_trusted(&other));
+    assert_eq!(creators.remove(&other), Err(ProtocolError::InvalidInput));
+    creators.remove(&creator).unwrap();
+    assert!(!creators.is_trusted(&creator));
+
+    for _ in 0..MAX_TRUSTED_POOL_CREATORS {
+      creators.add(&Pubkey::new_unique()).unwrap();
+    }
+    assert_eq!(
+      creators.add(&other),
+      Err(ProtocolError::TrustedPoolCreatorListFull)
+    );
+    assert!(TrustedPoolCreators::unpack(&[0u8; TrustedPoolCreators::LEN]).is_err());
+
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    config.check_pool_creators = true;
+    config.collect_host_fee = true;
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    let config = ProtocolConfig::unpack(&data).unwrap();
+    assert!(config.check_pool_creators);
+    assert!(config.collect_host_fee);
+  }
+
+  #[test]
+  pub fn test_exchanger_stats() {
+    let program_id = Pubkey::new_unique();
+    let (address, bump) = derive_exchanger_stats_address(ExchangerType::SerumDex, &program_id);
+    assert_eq!(
+      Pubkey::create_program_address(&[EXCHANGER_STATS_SEED, &[1], &[bump]], &program_id),
+      Ok(address)
+    );
+    assert_ne!(
+      derive_exchanger_stats_address(ExchangerType::SplTokenSwap, &program_id).0,
+      address
+    );
+
+    let mut stats = ExchangerStats::new(ExchangerType::SerumDex);
+    stats.record_swap();
+    stats.record_swap();
+    let mut data = vec![0u8; ExchangerStats::LEN];
+    ExchangerStats::pack(stats, &mut data).unwrap();
+    let mut stats = ExchangerStats::unpack(&data).unwrap();
+    assert_eq!(stats.swap_count, 2);
+    assert_eq!(stats.check_exchanger(ExchangerType::SerumDex), Ok(()));
+    assert_eq!(
+      stats.check_exchanger(ExchangerType::SplTokenSwap),
+      Err(ProtocolError::InvalidExchangerStatsAccount)
+    );
+    assert_eq!(stats.pack_view(), [1, 2, 0, 0, 0, 0, 0, 0, 0]);
+    stats.swap_count = u64::MAX;
+    stats.record_swap();
+    assert_eq!(stats.swap_count, u64::MAX);
+    assert_eq!(
+      ExchangerStats::unpack(&[0u8; ExchangerStats::LEN]),
+      Err(ProgramError::UninitializedAccount)
+    );
+
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    config.expect_oracle = COption::Some(Pubkey::new_unique());
+    config.collect_exchanger_stats = true;
+    let mut data = vec![0u8; ProtocolConfig::LEN];
+    ProtocolConfig::pack(config, &mut data).unwrap();
+    assert_eq!(ProtocolConfig::unpack(&data), Ok(config));
+  }
+
+  #[test]
+  pub fn test_pool_registry() {
+    let mut data = vec![0u8; PoolRegistry::LEN];
+    assert_eq!(
+      PoolRegistry::pool(&data, 0),
+      Err(ProtocolError::InvalidPoolRegistryAccount)
+    );
+    PoolRegistry::init(&mut data).unwrap();
+    assert_eq!(
+      PoolRegistry::init(&mut data),
+      Err(ProtocolError::InvalidPoolRegistryAccount)
+    );
+    assert_eq!(
+      PoolRegistry::pool(&data, 0),
+      Err(ProtocolError::InvalidInput)
+    );
+
+    let pool = |exchanger, len| RegisteredPool {
+      exchanger,
+      accounts: (0..len).map(|_| Pubkey::new_unique()).collect(),
+    };
+    let spl = pool(ExchangerType::SplTokenSwap, 7);
+    let raydium = pool(ExchangerType::RaydiumSwap, MAX_POOL_ACCOUNTS);
+    assert_eq!(PoolRegistry::register(&mut data, &spl), Ok(0));
+    assert_eq!(PoolRegistry::register(&mut data, &raydium), Ok(1));
+    assert_eq!(PoolRegistry::pool(&data, 0), Ok(spl.clone()));
+    assert_eq!(PoolRegistry::pool(&data, 1), Ok(raydium));
+    assert_eq!(
+      PoolRegistry::pool(&data, 2),
+      Err(ProtocolError::InvalidInput)
+    );
+    assert_eq!(
+      PoolRegistry::register(&mut data, &pool(ExchangerType::SerumDex, 0)),
+      Err(ProtocolError::InvalidAccountsLength)
+    );
+    assert_eq!(
+      PoolRegistry::register(
+        &mut data,
+        &pool(ExchangerType::SerumDex, MAX_POOL_ACCOUNTS + 1)
+      ),
+      Err(ProtocolError::InvalidAccountsLength)
+    );
+    for index in 2..MAX_REGISTERED_POOLS {
+      assert_eq!(PoolRegistry::register(&mut data, &spl), Ok(index as u8));
+    }
+    assert_eq!(
+      PoolRegistry::register(&mut data, &spl),
+      Err(ProtocolError::PoolRegistryFull)
+    );
+    assert_eq!(
+      PoolRegistry::pool(&data, MAX_REGISTERED_POOLS as u8 - 1),
+      Ok(spl)
+    );
+  }
+
+  #[test]
+  pub fn test_config_view_round_trip() {
+    let mut config = ProtocolConfig::new(&Pubkey::new_unique());
+    assert!(!config.is_paused());
+    config.disabled_exchangers = u16::MAX;
+    assert!(config.is_paused());
+    config.set_exchanger_enabled(ExchangerType::MeteoraDamm, true);
+    assert!(!config.is_paused());
+
+    let view = ConfigView {
+      fee_numerator: 25,
+      fee_denominator: 100,
+      owner: config.owner,
+      paused: true,
+      fee_token_owner: Pubkey::new_unique(),
+    };
+    let data = view.pack();
+    assert_eq!(data[0], ConfigView::VERSION);
+    assert_eq!(ConfigView::unpack(&data), Ok(view));
+    // fields appended by a later version are ignored
+    let mut longer = data.to_vec();
+    longer.extend_from_slice(&[2; 8]);
+    assert_eq!(ConfigView::unpack(&longer), Ok(view));
+    assert_eq!(
+      ConfigView::unpack(&data[..ConfigView::LEN - 1]),
+      Err(ProtocolError::InvalidInput)
+    );
+    let mut bad_flag = data;
+    bad_flag[49] = 2;
+    assert_eq!(
+      ConfigView::unpack(&bad_flag),
+      Err(ProtocolError::InvalidInput)
+    );
+  }
+
+  #[test]
+  pub fn test_fee_accrual() {
+    let program_id = Pubkey::new_unique();
+    let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (address, bump) = derive_fee_accrual_address(&owner, &mint, &program_id);
+    assert_eq!(
+      Pubkey::create_program_address(
+        &[FEE_ACCRUAL_SEED, owner.as_ref(), mint.as_ref(), &[bump]],
+        &program_id
+      ),
+      Ok(address)
+    );
+    assert_ne!(
+      derive_fee_accrual_address(&mint, &owner, &program_id).0,
+      address
+    );
+
+    let mut accrual = FeeAccrual::new(&owner, &mint);
+    assert_eq!(accrual.accrue(12), Ok(12));
+    assert_eq!(accrual.accrue(30), Ok(42));
+    let mut data = vec![0u8; FeeAccrual::LEN];
+    FeeAccrual::pack(accrual, &mut data).unwrap();
+    let mut accrual = FeeAccrual::unpack(&data).unwrap();
+    assert_eq!(accrual.amount, 42);
+    assert_eq!(accrual.check(&owner, &mint), Ok(()));
+    assert_eq!(
+      accrual.check(&mint, &owner),
+      Err(ProtocolError::InvalidFeeAccrualAccount)
+    );
+    assert_eq!(accrual.accrue(u64::MAX), Err(ProtocolError::Overflow));
+    assert_eq!(
+      FeeAccrual::unpack(&[0u8; FeeAccrual::LEN]),
+      Err(ProgramError::UninitializedAccount)
+    );
+  }
 }